@@ -0,0 +1,191 @@
+// Procedure Throughput
+//
+// Drives NULL, GETATTR, LOOKUP and READ through the in-process RPC
+// dispatcher, the same entry point the TCP and UDP servers use once a
+// record is read, so the numbers are dispatch and backend cost without any
+// transport. The export is a small fixture tree on the local backend in a
+// temporary directory; after the first iteration it is served from the page
+// cache.
+//
+// Criterion reports calls per second; latency percentiles over a separate
+// fixed run are printed before each benchmark.
+//
+// Run with `cargo bench --bench dispatch`
+
+use std::hint::black_box;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use tempfile::TempDir;
+use xdr_codec::Pack;
+
+use arcticwolf::config::ExportConfig;
+use arcticwolf::exports::{Export, ExportTable};
+use arcticwolf::fsal::{BackendConfig, FileHandle};
+use arcticwolf::nfs::{NfsState, NFS_PROGRAM, NFS_V3};
+use arcticwolf::portmap::Registry;
+use arcticwolf::protocol::v3::nfs::{fhandle3, filename3, GETATTR3args, LOOKUP3args, READ3args};
+use arcticwolf::rpc::dispatch::RpcDispatcher;
+
+/// Calls timed for the percentile report
+const LATENCY_SAMPLES: usize = 20_000;
+
+/// Directories in the fixture tree, and files in each
+const FIXTURE_DIRS: usize = 8;
+const FIXTURE_FILES: usize = 32;
+
+/// Size of the file READ is benchmarked on
+const DATA_FILE_SIZE: usize = 1024 * 1024;
+
+/// The dispatcher, with the fixture export and the handles calls use
+struct Fixture {
+    _dir: TempDir,
+    dispatcher: RpcDispatcher,
+    root: FileHandle,
+    file: FileHandle,
+    peer: SocketAddr,
+}
+
+impl Fixture {
+    /// A root with `FIXTURE_DIRS` directories of small files plus
+    /// `data.bin`, exported alone
+    fn new() -> Self {
+        let dir = TempDir::new().expect("Failed to create fixture directory");
+        for d in 0..FIXTURE_DIRS {
+            let sub = dir.path().join(format!("dir{}", d));
+            std::fs::create_dir(&sub).unwrap();
+            for f in 0..FIXTURE_FILES {
+                std::fs::write(sub.join(format!("file{}.txt", f)), b"fixture").unwrap();
+            }
+        }
+        let contents: Vec<u8> = (0..DATA_FILE_SIZE).map(|i| (i % 251) as u8).collect();
+        std::fs::write(dir.path().join("data.bin"), contents).unwrap();
+
+        let fs: Arc<dyn arcticwolf::Filesystem> = Arc::from(
+            BackendConfig::local(dir.path())
+                .create_filesystem()
+                .expect("Failed to open fixture export"),
+        );
+        let root = fs.root_handle();
+        let file = fs.lookup(&root, "data.bin").unwrap();
+        let dispatcher = RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(NfsState::default()),
+            ExportTable::new(vec![Export::new(ExportConfig::default(), fs)]),
+        );
+
+        Self {
+            _dir: dir,
+            dispatcher,
+            root,
+            file,
+            peer: "127.0.0.1:900".parse().unwrap(),
+        }
+    }
+
+    fn dispatch(&self, call: &[u8]) {
+        let reply = self.dispatcher.dispatch(call, self.peer);
+        black_box(reply.expect("Every benchmarked call is answered"));
+    }
+}
+
+/// An AUTH_NONE NFSv3 call to `procedure` with `args`
+fn nfs_call(procedure: u32, args: &[u8]) -> Vec<u8> {
+    let mut call = Vec::new();
+    for word in [1, 0, 2, NFS_PROGRAM, NFS_V3, procedure] {
+        call.extend_from_slice(&word.to_be_bytes());
+    }
+    call.extend_from_slice(&[0; 16]);
+    call.extend_from_slice(args);
+    call
+}
+
+/// XDR encoding of procedure arguments
+fn packed(args: impl Pack<Vec<u8>>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    args.pack(&mut buf).unwrap();
+    buf
+}
+
+/// Print p50/p90/p99/max of `LATENCY_SAMPLES` dispatches of `call`
+fn report_latency(fixture: &Fixture, name: &str, call: &[u8]) {
+    let mut samples: Vec<Duration> = (0..LATENCY_SAMPLES)
+        .map(|_| {
+            let started = Instant::now();
+            fixture.dispatch(call);
+            started.elapsed()
+        })
+        .collect();
+    samples.sort();
+    let percentile = |p: usize| samples[(samples.len() * p / 100).min(samples.len() - 1)];
+    println!(
+        "{:<12} p50 {:>9.2?}  p90 {:>9.2?}  p99 {:>9.2?}  max {:>9.2?}",
+        name,
+        percentile(50),
+        percentile(90),
+        percentile(99),
+        samples[samples.len() - 1]
+    );
+}
+
+fn bench_procedures(c: &mut Criterion) {
+    let fixture = Fixture::new();
+
+    let calls = [
+        ("null", nfs_call(0, &[])),
+        (
+            "getattr",
+            nfs_call(
+                1,
+                &packed(GETATTR3args {
+                    object: fhandle3(fixture.file.clone()),
+                }),
+            ),
+        ),
+        (
+            "lookup",
+            nfs_call(
+                3,
+                &packed(LOOKUP3args {
+                    what_dir: fhandle3(fixture.root.clone()),
+                    name: filename3("dir3".to_string()),
+                }),
+            ),
+        ),
+        (
+            "read_4k",
+            nfs_call(
+                6,
+                &packed(READ3args {
+                    file: fhandle3(fixture.file.clone()),
+                    offset: 0,
+                    count: 4096,
+                }),
+            ),
+        ),
+        (
+            "read_64k",
+            nfs_call(
+                6,
+                &packed(READ3args {
+                    file: fhandle3(fixture.file.clone()),
+                    offset: 65536,
+                    count: 65536,
+                }),
+            ),
+        ),
+    ];
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(1));
+    for (name, call) in &calls {
+        report_latency(&fixture, name, call);
+        group.bench_function(*name, |b| b.iter(|| fixture.dispatch(call)));
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_procedures);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+// Backend Throughput
+//
+// Streams a file through the local backend the way NFS clients do, to show
+// what readahead and write coalescing buy: sequential 64 KiB READs with
+// several readahead windows, and 4 KiB sequential WRITEs followed by a
+// COMMIT with several coalescing thresholds. The file lives in a temporary
+// directory, so after the first iteration reads come from the page cache
+// and the numbers are the backend's own cost.
+//
+// Criterion reports bytes per second.
+//
+// Run with `cargo bench --bench fsal`
+
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use tempfile::TempDir;
+
+use arcticwolf::fsal::{CoalescingFilesystem, Filesystem, LocalFilesystem, MAX_READAHEAD};
+
+/// Bytes streamed per iteration
+const FILE_SIZE: usize = 64 * 1024 * 1024;
+
+/// Size of each READ, as a client with rsize=64k sends them
+const READ_CHUNK: u32 = 64 * 1024;
+
+/// Size of each WRITE, as small unstable writes from an application
+const WRITE_CHUNK: usize = 4096;
+
+fn bench_sequential_read(c: &mut Criterion) {
+    let dir = TempDir::new().expect("Failed to create fixture directory");
+    let contents: Vec<u8> = (0..FILE_SIZE).map(|i| (i % 251) as u8).collect();
+    std::fs::write(dir.path().join("large.bin"), contents).unwrap();
+
+    let mut group = c.benchmark_group("sequential_read");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(10);
+    for window in [0, 256 * 1024, 1024 * 1024, MAX_READAHEAD] {
+        let fs = LocalFilesystem::new(dir.path())
+            .expect("Failed to open fixture export")
+            .with_readahead(window);
+        let file = fs.lookup(&fs.root_handle(), "large.bin").unwrap();
+        group.bench_with_input(BenchmarkId::new("readahead", window), &window, |b, _| {
+            b.iter(|| {
+                let mut offset = 0;
+                while offset < FILE_SIZE as u64 {
+                    offset += fs.read(&file, offset, READ_CHUNK).unwrap().len() as u64;
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn bench_small_sequential_writes(c: &mut Criterion) {
+    let dir = TempDir::new().expect("Failed to create fixture directory");
+    let chunk = vec![0x5au8; WRITE_CHUNK];
+
+    let mut group = c.benchmark_group("small_sequential_writes");
+    group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+    group.sample_size(10);
+    for threshold in [0, 64 * 1024, 1024 * 1024] {
+        let local = Box::new(LocalFilesystem::new(dir.path()).expect("Failed to open fixture export"));
+        let fs: Box<dyn Filesystem> = if threshold == 0 {
+            local
+        } else {
+            Box::new(CoalescingFilesystem::new(local, threshold))
+        };
+        std::fs::write(dir.path().join("out.bin"), b"").unwrap();
+        let file = fs.lookup(&fs.root_handle(), "out.bin").unwrap();
+        group.bench_with_input(BenchmarkId::new("coalescing", threshold), &threshold, |b, _| {
+            b.iter(|| {
+                for offset in (0..FILE_SIZE).step_by(WRITE_CHUNK) {
+                    fs.write(&file, offset as u64, &chunk).unwrap();
+                }
+                fs.commit(&file, 0, 0).unwrap();
+            })
+        });
+    }
+    group.finish();
+}
+
+criterion_group!(benches, bench_sequential_read, bench_small_sequential_writes);
+criterion_main!(benches);
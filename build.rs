@@ -27,6 +27,8 @@ fn main() {
         ("portmap.x", "portmap_generated.rs"),
         ("mount.x", "mount_generated.rs"),
         ("nfs.x", "nfs_generated.rs"),
+        ("nlm.x", "nlm_generated.rs"),
+        ("nsm.x", "nsm_generated.rs"),
     ];
 
     for (spec_file, output_file) in xdr_specs {
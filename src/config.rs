@@ -0,0 +1,1447 @@
+// Server Configuration
+//
+// TOML configuration file support. Every field has a default, so an empty
+// (or missing) configuration file yields a working server.
+
+use anyhow::{anyhow, Context, Result};
+use serde::Deserialize;
+use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::path::Path;
+
+use crate::fsal::{handle::MIN_SUBTREE_CHECK_HANDLE_LEN, registry, AtimePolicy, MAX_READAHEAD};
+
+/// Top-level server configuration
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// RPC server (transport) options
+    pub server: ServerConfig,
+
+    /// NFS protocol options
+    pub nfs: NfsConfig,
+
+    /// Log output options
+    pub logging: LoggingConfig,
+
+    /// Trace export options
+    pub telemetry: TelemetryConfig,
+
+    /// Prometheus metrics endpoint options
+    pub metrics: MetricsConfig,
+
+    /// Health check endpoint options
+    pub health: HealthConfig,
+
+    /// Lock recovery (NSM) options
+    pub nsm: NsmConfig,
+
+    /// Filesystem backend options
+    pub fsal: FsalConfig,
+
+    /// Exported directories (`[[export]]` tables); when none are given, the
+    /// legacy `fsal.export_path` or the default export is used
+    #[serde(rename = "export", default)]
+    pub exports: Vec<ExportConfig>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            server: ServerConfig::default(),
+            nfs: NfsConfig::default(),
+            logging: LoggingConfig::default(),
+            telemetry: TelemetryConfig::default(),
+            metrics: MetricsConfig::default(),
+            health: HealthConfig::default(),
+            nsm: NsmConfig::default(),
+            fsal: FsalConfig::default(),
+            exports: vec![ExportConfig::default()],
+        }
+    }
+}
+
+/// Filesystem backend options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct FsalConfig {
+    /// Name of the backend serving the exports: "local", or one an
+    /// embedding crate added with `fsal::register_backend`
+    pub backend: String,
+
+    /// Deprecated single export directory, kept for old configuration
+    /// files; equivalent to one `[[export]]` with this path
+    pub export_path: Option<String>,
+
+    /// How long fetched attributes are reused (milliseconds); 0 disables
+    /// the attribute cache
+    pub attr_cache_ttl_ms: u64,
+
+    /// Most file handles whose attributes are cached, per export
+    pub attr_cache_entries: usize,
+
+    /// Bytes read ahead of sequential READs and kept for the next ones
+    /// (local backend); 0 disables readahead. At most 4 MiB, kept for up
+    /// to 64 files at once per export
+    pub readahead: u32,
+
+    /// Whether LOOKUP resolves symlinks to their targets (local backend).
+    /// Off, LOOKUP returns the symlink itself and no operation acts through
+    /// one; on, targets outside the export are refused with NFS3ERR_ACCES
+    pub follow_symlinks: bool,
+
+    /// Drop the page cache of files not read for this long (seconds, local
+    /// backend); 0 leaves the page cache to the kernel. Saves memory when
+    /// exporting more data than fits in RAM, at the cost of re-reading from
+    /// disk any file read again after going idle
+    pub drop_cache_idle_secs: u64,
+
+    /// How often idle files are looked for (seconds)
+    pub drop_cache_interval_secs: u64,
+
+    /// Bytes of contiguous UNSTABLE writes held per file and passed to the
+    /// backend as one write; 0 writes each WRITE through. Held data is lost
+    /// if the server dies before flushing it, as UNSTABLE permits: clients
+    /// resend it when the restart changes the write verifier
+    pub write_coalesce_bytes: usize,
+}
+
+impl Default for FsalConfig {
+    fn default() -> Self {
+        Self {
+            backend: "local".to_string(),
+            export_path: None,
+            attr_cache_ttl_ms: 1000,
+            attr_cache_entries: 8192,
+            readahead: 0,
+            follow_symlinks: false,
+            drop_cache_idle_secs: 0,
+            drop_cache_interval_secs: 60,
+            write_coalesce_bytes: 0,
+        }
+    }
+}
+
+/// One exported directory
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExportConfig {
+    /// Directory to export
+    pub path: String,
+
+    /// Clients allowed to mount and access it: addresses or CIDR ranges
+    /// ("10.0.0.0/24"), host names or patterns ("*.corp.example.com"),
+    /// or "*"; also reported by MOUNT EXPORT (empty = everyone)
+    pub clients: Vec<String>,
+
+    /// Reject every modifying NFS operation with NFS3ERR_ROFS
+    pub read_only: bool,
+
+    /// Which AUTH_SYS identities are mapped to the anonymous one
+    pub squash: SquashPolicy,
+
+    /// uid that squashed and AUTH_NONE callers act as
+    #[serde(alias = "anon_uid")]
+    pub anonuid: u32,
+
+    /// gid that squashed and AUTH_NONE callers act as
+    #[serde(alias = "anon_gid")]
+    pub anongid: u32,
+
+    /// When READ updates access times: "strict" (as the host mount says)
+    /// or "noatime"
+    /// (changing it on a running export takes a restart)
+    pub atime: AtimePolicy,
+
+    /// fsid every object of the export reports, instead of one derived
+    /// from the export root's device and inode; give an export the same value on every
+    /// server it can fail over to (changing it takes a restart)
+    pub fsid: Option<u64>,
+
+    /// Tie each handle to the path of its object, so it goes stale once the
+    /// object is renamed (subtree_check); off by default (no_subtree_check),
+    /// where handles follow their objects, as it needs nfs.file_handle_len
+    /// of at least 24. Handles never lead out of the export either way.
+    pub subtree_check: bool,
+}
+
+impl Default for ExportConfig {
+    fn default() -> Self {
+        Self {
+            path: "/tmp/nfs_exports".to_string(),
+            clients: Vec::new(),
+            read_only: false,
+            squash: SquashPolicy::RootSquash,
+            anonuid: 65534,
+            anongid: 65534,
+            atime: AtimePolicy::Strict,
+            fsid: None,
+            subtree_check: false,
+        }
+    }
+}
+
+impl ExportConfig {
+    /// The export's modes as /etc/exports options, e.g.
+    /// "rw,root_squash,no_subtree_check"
+    pub fn options(&self) -> String {
+        let squash = match self.squash {
+            SquashPolicy::RootSquash => "root_squash",
+            SquashPolicy::NoRootSquash => "no_root_squash",
+            SquashPolicy::AllSquash => "all_squash",
+        };
+        [
+            if self.read_only { "ro" } else { "rw" },
+            squash,
+            if self.subtree_check { "subtree_check" } else { "no_subtree_check" },
+        ]
+        .join(",")
+    }
+
+    /// Whether `other` decides differently who may do what: which clients
+    /// are admitted, how callers are squashed, or whether writes are allowed
+    pub fn access_differs(&self, other: &ExportConfig) -> bool {
+        self.clients != other.clients
+            || self.squash != other.squash
+            || self.anonuid != other.anonuid
+            || self.anongid != other.anongid
+            || self.read_only != other.read_only
+    }
+}
+
+/// Identity squashing for an export, as in /etc/exports
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SquashPolicy {
+    /// Map uid/gid 0 to anonuid/anongid
+    RootSquash,
+    /// Trust every caller's identity, root included
+    NoRootSquash,
+    /// Map every caller to anonuid/anongid
+    AllSquash,
+}
+
+/// Log output options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LoggingConfig {
+    /// Most verbose level logged ("trace", "debug", "info", "warn", "error"
+    /// or "off"), or comma-separated per-module directives in RUST_LOG
+    /// syntax, e.g. "info,arcticwolf::nfs=debug,xdr_codec=warn". RUST_LOG,
+    /// when set, is applied on top
+    pub level: String,
+
+    /// Hex-dump every call and reply, with the decoded call header and NFS
+    /// arguments; verbose, and only shown when debug is enabled for
+    /// arcticwolf::rpc::trace (changing it takes a restart)
+    pub trace_xdr: bool,
+}
+
+impl Default for LoggingConfig {
+    fn default() -> Self {
+        Self {
+            level: "info".to_string(),
+            trace_xdr: false,
+        }
+    }
+}
+
+impl LoggingConfig {
+    /// The level to log at, "info" when none is configured
+    pub fn effective_level(&self) -> &str {
+        match self.level.trim() {
+            "" => "info",
+            level => level,
+        }
+    }
+}
+
+/// Trace export options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TelemetryConfig {
+    /// OTLP (gRPC) collector endpoint, e.g. "http://localhost:4317";
+    /// OpenTelemetry export is disabled when unset
+    pub otlp_endpoint: Option<String>,
+
+    /// `service.name` reported with exported spans
+    pub service_name: String,
+}
+
+impl Default for TelemetryConfig {
+    fn default() -> Self {
+        Self {
+            otlp_endpoint: None,
+            service_name: "arcticwolf".to_string(),
+        }
+    }
+}
+
+/// Prometheus metrics endpoint options
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address serving `/metrics` over HTTP, e.g. "0.0.0.0:9100"; the
+    /// endpoint is disabled when unset
+    pub listen: Option<String>,
+}
+
+/// Health check endpoint options
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Address serving `/healthz` over HTTP, e.g. "0.0.0.0:8080"; the
+    /// endpoint is disabled when unset
+    pub listen: Option<String>,
+}
+
+/// Lock recovery (NSM) options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NsmConfig {
+    /// Directory keeping the NSM state number and monitored hosts across
+    /// restarts, e.g. "/var/lib/arcticwolf/nsm"; when unset they only live
+    /// in memory and clients are not told to reclaim locks after a restart
+    pub state_dir: Option<String>,
+
+    /// After a restart with hosts to notify, how long only reclaimed locks
+    /// are granted, so those hosts get their locks back before anyone else
+    /// takes them (seconds)
+    pub grace_period_secs: u64,
+}
+
+impl Default for NsmConfig {
+    fn default() -> Self {
+        Self {
+            state_dir: None,
+            grace_period_secs: 90,
+        }
+    }
+}
+
+/// RPC server (transport) options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    /// Address the RPC listeners bind to (TCP and UDP); 0.0.0.0 when unset
+    pub bind_address: Option<IpAddr>,
+
+    /// Port shared by every RPC program (PORTMAP, MOUNT, NFS, NLM, NSM);
+    /// 4000 when unset
+    pub port: Option<u16>,
+
+    /// Addresses to listen on instead of `bind_address` and `port`, each
+    /// served alike over TCP and UDP, e.g. ["10.0.0.5:2049",
+    /// "192.168.7.5:2049"] for a multi-homed server or ["0.0.0.0:2049",
+    /// "0.0.0.0:4000"] to keep a legacy port. Cannot be combined with
+    /// `bind_address` or `port`. The portmapper advertises the first one's
+    /// port
+    pub listen: Vec<SocketAddr>,
+
+    /// Maximum simultaneous TCP connections across all clients; further
+    /// connections wait until one closes. Also caps the UDP calls being
+    /// answered at once
+    pub max_connections: usize,
+
+    /// Maximum simultaneous TCP connections from a single source IP, and
+    /// UDP calls being answered for it (datagrams past it are dropped)
+    pub max_connections_per_ip: u32,
+
+    /// Close TCP connections that send nothing for this long (seconds,
+    /// 0 = never)
+    pub idle_timeout_secs: u64,
+
+    /// Send replies without waiting to coalesce them (TCP_NODELAY)
+    pub tcp_nodelay: bool,
+
+    /// Probe silent TCP connections after this long, and again at this
+    /// interval, so dead clients are detected (seconds, 0 = no keepalive)
+    pub keepalive_secs: u64,
+
+    /// How long shutdown waits for in-flight requests to finish before
+    /// dropping their connections (seconds)
+    pub shutdown_grace_period_secs: u64,
+
+    /// How long shutdown may spend flushing uncommitted writes (seconds)
+    pub shutdown_flush_timeout_secs: u64,
+
+    /// Largest RPC message accepted over TCP, all record fragments together
+    /// (bytes); a client sending more is disconnected. Must leave room for a
+    /// `wtmax`-sized WRITE plus its headers
+    pub max_message_size: usize,
+
+    /// Largest record fragment in a reply (bytes); bigger replies are split
+    /// into several fragments
+    pub max_fragment_size: usize,
+
+    /// SO_SNDBUF of each TCP connection (bytes, 0 = kernel default and
+    /// autotuning). Setting it turns autotuning off for the connection, so
+    /// it only helps where autotuning tops out below the link's
+    /// bandwidth-delay product; it should hold several `max_fragment_size`
+    /// fragments so a large READ reply streams out without stalls. Linux
+    /// doubles the value and caps it at net.core.wmem_max
+    pub send_buffer_size: usize,
+
+    /// SO_RCVBUF of each TCP connection (bytes, 0 = kernel default and
+    /// autotuning); bounds how much of a large WRITE a client can have in
+    /// flight. Set after the handshake, so the window scale was already
+    /// negotiated from the kernel's net.ipv4.tcp_rmem maximum; capped at
+    /// net.core.rmem_max
+    pub recv_buffer_size: usize,
+}
+
+/// Room `max_message_size` needs beyond a WRITE's data, for the RPC header,
+/// credentials, file handle and WRITE arguments
+pub const MESSAGE_HEADER_ROOM: usize = 4096;
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            bind_address: None,
+            port: None,
+            listen: Vec::new(),
+            max_connections: 1024,
+            max_connections_per_ip: 32,
+            idle_timeout_secs: 60,
+            tcp_nodelay: true,
+            keepalive_secs: 60,
+            shutdown_grace_period_secs: 10,
+            shutdown_flush_timeout_secs: 30,
+            max_message_size: 1024 * 1024 + MESSAGE_HEADER_ROOM,
+            max_fragment_size: 32 * 1024,
+            send_buffer_size: 0,
+            recv_buffer_size: 0,
+        }
+    }
+}
+
+/// NFS protocol options
+#[derive(Debug, Clone, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NfsConfig {
+    /// Maximum number of directory entries a single client may enumerate via
+    /// READDIR/READDIRPLUS within `readdir_window_secs` (unset = unlimited)
+    pub readdir_max_entries_per_client: Option<u64>,
+
+    /// Length of the READDIR throttling window in seconds
+    pub readdir_window_secs: u64,
+
+    /// Maximum READ size advertised to clients in FSINFO (bytes)
+    ///
+    /// Larger READ requests are answered with a short read of this size.
+    pub rtmax: u32,
+
+    /// Preferred READ size advertised in FSINFO (bytes, capped at `rtmax`)
+    pub rtpref: u32,
+
+    /// Maximum WRITE size advertised to clients in FSINFO (bytes)
+    pub wtmax: u32,
+
+    /// Preferred WRITE size advertised in FSINFO (bytes, capped at `wtmax`)
+    pub wtpref: u32,
+
+    /// Preferred READDIR reply size advertised in FSINFO (bytes)
+    pub dtpref: u32,
+
+    /// How to handle WRITE requests larger than `wtmax`
+    pub oversized_writes: OversizedWritePolicy,
+
+    /// Hard upper bound for WRITE requests accepted under
+    /// `oversized_writes = "accept"` (bytes)
+    pub write_hard_limit: u32,
+
+    /// Report every file as owned by this uid in returned attributes
+    /// (display only, on-disk ownership is untouched)
+    pub override_uid: Option<u32>,
+
+    /// Report every file as owned by this gid in returned attributes
+    /// (display only, on-disk ownership is untouched)
+    pub override_gid: Option<u32>,
+
+    /// Serialize WRITE/COMMIT/SETATTR(size) per file handle, for backends
+    /// where concurrent modification of one file is unsafe
+    pub serialize_file_writes: bool,
+
+    /// Maximum total call and reply bytes in flight; beyond it connections
+    /// wait before reading their next call and READs get NFS3ERR_JUKEBOX
+    /// (at least the larger of rtmax and wtmax plus headers; unset =
+    /// unlimited)
+    pub max_inflight_bytes: Option<u64>,
+
+    /// Length of every file handle sent to clients (16 to 64 bytes); handles
+    /// are zero-padded to it for clients that expect fixed-size handles
+    pub file_handle_len: usize,
+
+    /// Number of replies to non-idempotent calls kept for replay to
+    /// retransmits (0 disables the duplicate request cache)
+    pub reply_cache_size: usize,
+
+    /// How long a cached reply may be replayed (seconds)
+    pub reply_cache_ttl_secs: u64,
+}
+
+impl Default for NfsConfig {
+    fn default() -> Self {
+        Self {
+            readdir_max_entries_per_client: None,
+            readdir_window_secs: 10,
+            rtmax: 1024 * 1024,
+            rtpref: 64 * 1024,
+            wtmax: 1024 * 1024,
+            wtpref: 64 * 1024,
+            dtpref: 8192,
+            oversized_writes: OversizedWritePolicy::Reject,
+            write_hard_limit: 4 * 1024 * 1024,
+            override_uid: None,
+            override_gid: None,
+            serialize_file_writes: false,
+            max_inflight_bytes: None,
+            file_handle_len: 32,
+            reply_cache_size: 1024,
+            reply_cache_ttl_secs: 120,
+        }
+    }
+}
+
+/// Policy for WRITE requests whose count exceeds the advertised wtmax
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum OversizedWritePolicy {
+    /// Fail the WRITE with NFS3ERR_INVAL
+    Reject,
+    /// Perform the WRITE as long as it stays within `write_hard_limit`
+    Accept,
+}
+
+impl Config {
+    /// Socket addresses the RPC listeners bind to, the first being the one
+    /// the portmapper advertises
+    pub fn bind_addrs(&self) -> Vec<SocketAddr> {
+        if self.server.listen.is_empty() {
+            vec![SocketAddr::new(
+                self.server.bind_address.unwrap_or(IpAddr::V4(Ipv4Addr::UNSPECIFIED)),
+                self.server.port.unwrap_or(4000),
+            )]
+        } else {
+            self.server.listen.clone()
+        }
+    }
+
+    /// Load configuration from a TOML file
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file: {}", path.display()))?;
+        Self::from_toml_str(&contents)
+            .with_context(|| format!("Failed to parse config file: {}", path.display()))
+    }
+
+    /// Parse configuration from a TOML string
+    pub fn from_toml_str(contents: &str) -> Result<Self> {
+        let mut config: Config = toml::from_str(contents)?;
+
+        if config.exports.is_empty() {
+            let export = match config.fsal.export_path.take() {
+                Some(path) => ExportConfig {
+                    path,
+                    ..ExportConfig::default()
+                },
+                None => ExportConfig::default(),
+            };
+            config.exports.push(export);
+        } else if config.fsal.export_path.is_some() {
+            return Err(anyhow!(
+                "fsal.export_path cannot be combined with [[export]] tables"
+            ));
+        }
+
+        if !registry::is_registered(&config.fsal.backend) {
+            return Err(anyhow!(
+                "Unknown fsal.backend {:?} (registered: {})",
+                config.fsal.backend,
+                registry::registered_backends().join(", ")
+            ));
+        }
+
+        if !config.server.listen.is_empty()
+            && (config.server.bind_address.is_some() || config.server.port.is_some())
+        {
+            return Err(anyhow!(
+                "server.listen cannot be combined with server.bind_address or server.port"
+            ));
+        }
+
+        for (i, addr) in config.server.listen.iter().enumerate() {
+            if config.server.listen[..i].contains(addr) {
+                return Err(anyhow!("server.listen has {} more than once", addr));
+            }
+        }
+
+        // A WRITE of the advertised size must fit in one message; READ
+        // replies are not bounded by it
+        let largest_write = config.nfs.wtmax as usize + MESSAGE_HEADER_ROOM;
+        if config.server.max_message_size < largest_write {
+            return Err(anyhow!(
+                "server.max_message_size ({}) must be at least nfs.wtmax plus {} bytes of headers ({})",
+                config.server.max_message_size,
+                MESSAGE_HEADER_ROOM,
+                largest_write
+            ));
+        }
+
+        // Any single transfer of the advertised sizes, with its headers, has
+        // to fit the in-flight budget
+        let largest_transfer = config.nfs.rtmax.max(config.nfs.wtmax) as u64
+            + MESSAGE_HEADER_ROOM as u64;
+        if let Some(limit) = config.nfs.max_inflight_bytes.filter(|&l| l < largest_transfer) {
+            return Err(anyhow!(
+                "nfs.max_inflight_bytes ({}) must be at least the larger of nfs.rtmax and \
+                 nfs.wtmax plus {} bytes of headers ({})",
+                limit,
+                MESSAGE_HEADER_ROOM,
+                largest_transfer
+            ));
+        }
+
+        if config.fsal.readahead > MAX_READAHEAD {
+            return Err(anyhow!(
+                "fsal.readahead ({}) must be at most {} bytes",
+                config.fsal.readahead,
+                MAX_READAHEAD
+            ));
+        }
+
+        if config.server.max_connections == 0 {
+            return Err(anyhow!("server.max_connections must be at least 1"));
+        }
+
+        let level = config.logging.effective_level();
+        if let Err(e) = tracing_subscriber::EnvFilter::builder().parse(level) {
+            return Err(anyhow!("Invalid logging.level {:?}: {}", level, e));
+        }
+
+        for (i, export) in config.exports.iter().enumerate() {
+            if config.exports[..i].iter().any(|other| other.path == export.path) {
+                return Err(anyhow!("Export {} is configured more than once", export.path));
+            }
+            if let Some(fsid) = export.fsid {
+                if let Some(other) = config.exports[..i].iter().find(|other| other.fsid == Some(fsid)) {
+                    return Err(anyhow!(
+                        "Exports {} and {} are both configured with fsid {}",
+                        other.path,
+                        export.path,
+                        fsid
+                    ));
+                }
+            }
+            if export.subtree_check && config.nfs.file_handle_len < MIN_SUBTREE_CHECK_HANDLE_LEN {
+                return Err(anyhow!(
+                    "Export {} uses subtree_check, which needs nfs.file_handle_len of at least {}",
+                    export.path,
+                    MIN_SUBTREE_CHECK_HANDLE_LEN
+                ));
+            }
+            // Host names are allowed, but anything written as a range must be one
+            if let Some(bad) = export
+                .clients
+                .iter()
+                .find(|client| client.contains('/') && crate::exports::parse_cidr(client).is_none())
+            {
+                return Err(anyhow!(
+                    "Invalid client range {} for export {}",
+                    bad,
+                    export.path
+                ));
+            }
+        }
+
+        Ok(config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_empty_config_uses_defaults() {
+        let config = Config::from_toml_str("").unwrap();
+        assert_eq!(config.server.max_connections_per_ip, 32);
+        assert_eq!(config.nfs.readdir_max_entries_per_client, None);
+        assert_eq!(config.nfs.readdir_window_secs, 10);
+        assert_eq!(config.nfs.wtmax, 1024 * 1024);
+        assert_eq!(config.nfs.oversized_writes, OversizedWritePolicy::Reject);
+        assert_eq!(config.telemetry.otlp_endpoint, None);
+    }
+
+    #[test]
+    fn test_exports() {
+        let default = Config::default();
+        assert_eq!(default.exports.len(), 1);
+        assert_eq!(default.exports[0].path, "/tmp/nfs_exports");
+        assert!(default.exports[0].clients.is_empty());
+        assert!(!default.exports[0].read_only);
+        assert_eq!(default.exports[0].squash, SquashPolicy::RootSquash);
+        assert_eq!(default.exports[0].anonuid, 65534);
+        assert_eq!(default.exports[0].anongid, 65534);
+
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            clients = ["10.0.0.0/24", "backup.example.com"]
+            read_only = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports.len(), 1);
+        assert_eq!(config.exports[0].path, "/srv/nfs");
+        assert_eq!(config.exports[0].clients, ["10.0.0.0/24", "backup.example.com"]);
+        assert!(config.exports[0].read_only);
+    }
+
+    #[test]
+    fn test_multiple_exports() {
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+
+            [[export]]
+            path = "/srv/public"
+            read_only = true
+            squash = "all_squash"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports.len(), 2);
+        assert_eq!(config.exports[0].path, "/srv/nfs");
+        assert!(!config.exports[0].read_only);
+        assert_eq!(config.exports[1].path, "/srv/public");
+        assert!(config.exports[1].read_only);
+        assert_eq!(config.exports[1].squash, SquashPolicy::AllSquash);
+
+        let duplicate = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+
+            [[export]]
+            path = "/srv/nfs"
+            "#,
+        );
+        assert!(duplicate.is_err());
+    }
+
+    #[test]
+    fn test_invalid_client_range() {
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            clients = ["10.0.0.0/33"]
+            "#,
+        );
+        assert!(config.is_err());
+    }
+
+    #[test]
+    fn test_legacy_export_path() {
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            export_path = "/srv/legacy"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports.len(), 1);
+        assert_eq!(config.exports[0].path, "/srv/legacy");
+        assert_eq!(config.exports[0].squash, SquashPolicy::RootSquash);
+        assert_eq!(config.fsal.export_path, None);
+
+        let both = Config::from_toml_str(
+            r#"
+            [fsal]
+            export_path = "/srv/legacy"
+
+            [[export]]
+            path = "/srv/nfs"
+            "#,
+        );
+        assert!(both.is_err());
+    }
+
+    #[test]
+    fn test_export_squash() {
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            squash = "all_squash"
+            anonuid = 2000
+            anongid = 2000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports[0].squash, SquashPolicy::AllSquash);
+        assert_eq!(config.exports[0].anonuid, 2000);
+        assert_eq!(config.exports[0].anongid, 2000);
+
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            anon_uid = 3000
+            anon_gid = 4000
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports[0].anonuid, 3000);
+        assert_eq!(config.exports[0].anongid, 4000);
+    }
+
+    #[test]
+    fn test_export_atime() {
+        assert_eq!(Config::default().exports[0].atime, AtimePolicy::Strict);
+
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            atime = "noatime"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports[0].atime, AtimePolicy::Noatime);
+
+        // The old name of the kernel's policy still parses
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            atime = "relatime"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports[0].atime, AtimePolicy::Strict);
+    }
+
+    #[test]
+    fn test_export_fsid() {
+        assert_eq!(Config::default().exports[0].fsid, None);
+
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/a"
+            fsid = 101
+
+            [[export]]
+            path = "/srv/b"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports[0].fsid, Some(101));
+        assert_eq!(config.exports[1].fsid, None);
+
+        let shared = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/a"
+            fsid = 7
+
+            [[export]]
+            path = "/srv/b"
+            fsid = 7
+            "#,
+        );
+        assert!(shared.is_err(), "Exports must not share an fsid");
+    }
+
+    #[test]
+    fn test_export_options() {
+        assert_eq!(Config::default().exports[0].options(), "rw,root_squash,no_subtree_check");
+
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/nfs"
+            read_only = true
+            squash = "all_squash"
+            subtree_check = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.exports[0].options(), "ro,all_squash,subtree_check");
+    }
+
+    #[test]
+    fn test_export_subtree_check() {
+        assert!(!Config::default().exports[0].subtree_check);
+
+        let config = Config::from_toml_str(
+            r#"
+            [[export]]
+            path = "/srv/a"
+            subtree_check = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.exports[0].subtree_check);
+
+        let short_handles = Config::from_toml_str(
+            r#"
+            [nfs]
+            file_handle_len = 16
+
+            [[export]]
+            path = "/srv/a"
+            subtree_check = true
+            "#,
+        );
+        assert!(short_handles.is_err(), "16-byte handles have no room for a fileid");
+    }
+
+    #[test]
+    fn test_fsal_backend() {
+        assert_eq!(Config::default().fsal.backend, "local");
+
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            backend = "local"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fsal.backend, "local");
+
+        for name in ["nfs", "memory"] {
+            let unknown = Config::from_toml_str(&format!("[fsal]\nbackend = {:?}\n", name));
+            assert!(unknown.is_err(), "Unknown backend {} should be reported", name);
+        }
+    }
+
+    #[test]
+    fn test_attr_cache() {
+        let default = Config::default();
+        assert_eq!(default.fsal.attr_cache_ttl_ms, 1000);
+        assert_eq!(default.fsal.attr_cache_entries, 8192);
+
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            attr_cache_ttl_ms = 0
+            attr_cache_entries = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fsal.attr_cache_ttl_ms, 0);
+        assert_eq!(config.fsal.attr_cache_entries, 100);
+    }
+
+    #[test]
+    fn test_readahead() {
+        assert_eq!(Config::default().fsal.readahead, 0);
+
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            readahead = 1048576
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fsal.readahead, 1048576);
+
+        let too_large = Config::from_toml_str(
+            r#"
+            [fsal]
+            readahead = 16777216
+            "#,
+        );
+        assert!(too_large.is_err());
+    }
+
+    #[test]
+    fn test_follow_symlinks() {
+        assert!(!Config::default().fsal.follow_symlinks);
+
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            follow_symlinks = true
+            "#,
+        )
+        .unwrap();
+        assert!(config.fsal.follow_symlinks);
+    }
+
+    #[test]
+    fn test_drop_cache() {
+        let default = Config::default();
+        assert_eq!(default.fsal.drop_cache_idle_secs, 0);
+        assert_eq!(default.fsal.drop_cache_interval_secs, 60);
+
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            drop_cache_idle_secs = 600
+            drop_cache_interval_secs = 30
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fsal.drop_cache_idle_secs, 600);
+        assert_eq!(config.fsal.drop_cache_interval_secs, 30);
+    }
+
+    #[test]
+    fn test_write_coalesce_bytes() {
+        assert_eq!(Config::default().fsal.write_coalesce_bytes, 0);
+
+        let config = Config::from_toml_str(
+            r#"
+            [fsal]
+            write_coalesce_bytes = 1048576
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.fsal.write_coalesce_bytes, 1048576);
+    }
+
+    #[test]
+    fn test_bind_addr() {
+        assert_eq!(Config::default().bind_addrs(), vec!["0.0.0.0:4000".parse().unwrap()]);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            bind_address = "::1"
+            port = 2049
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.bind_addrs(), vec!["[::1]:2049".parse().unwrap()]);
+
+        let invalid = Config::from_toml_str(
+            r#"
+            [server]
+            bind_address = "not-an-address"
+            "#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_listen_addresses() {
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            listen = ["10.0.0.5:2049", "[fd00::5]:2049", "0.0.0.0:4000"]
+            "#,
+        )
+        .unwrap();
+        let expected: Vec<SocketAddr> = ["10.0.0.5:2049", "[fd00::5]:2049", "0.0.0.0:4000"]
+            .iter()
+            .map(|addr| addr.parse().unwrap())
+            .collect();
+        assert_eq!(config.bind_addrs(), expected);
+
+        let repeated = Config::from_toml_str(
+            r#"
+            [server]
+            listen = ["10.0.0.5:2049", "10.0.0.5:2049"]
+            "#,
+        );
+        assert!(repeated.is_err());
+
+        // Either listen or bind_address/port, not both
+        for conflicting in ["port = 2049", "bind_address = \"10.0.0.5\""] {
+            let both = Config::from_toml_str(&format!(
+                r#"
+                [server]
+                {}
+                listen = ["10.0.0.5:2049"]
+                "#,
+                conflicting
+            ));
+            assert!(both.is_err(), "{}", conflicting);
+        }
+    }
+
+    #[test]
+    fn test_logging_level() {
+        assert_eq!(Config::default().logging.effective_level(), "info");
+        assert!(!Config::default().logging.trace_xdr);
+
+        let config = Config::from_toml_str(
+            r#"
+            [logging]
+            level = "debug"
+            trace_xdr = true
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.logging.effective_level(), "debug");
+        assert!(config.logging.trace_xdr);
+
+        let blank = Config::from_toml_str(
+            r#"
+            [logging]
+            level = ""
+            "#,
+        )
+        .unwrap();
+        assert_eq!(blank.logging.effective_level(), "info");
+
+        let directives = Config::from_toml_str(
+            r#"
+            [logging]
+            level = "arcticwolf=debug,xdr_codec=warn"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(directives.logging.effective_level(), "arcticwolf=debug,xdr_codec=warn");
+
+        let invalid = Config::from_toml_str(
+            r#"
+            [logging]
+            level = "arcticwolf=loud"
+            "#,
+        );
+        assert!(invalid.is_err());
+    }
+
+    #[test]
+    fn test_telemetry() {
+        let config = Config::from_toml_str(
+            r#"
+            [telemetry]
+            otlp_endpoint = "http://collector:4317"
+            service_name = "nfs-edge"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(
+            config.telemetry.otlp_endpoint.as_deref(),
+            Some("http://collector:4317")
+        );
+        assert_eq!(config.telemetry.service_name, "nfs-edge");
+    }
+
+    #[test]
+    fn test_metrics() {
+        assert_eq!(Config::default().metrics.listen, None);
+
+        let config = Config::from_toml_str(
+            r#"
+            [metrics]
+            listen = "127.0.0.1:9100"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.metrics.listen.as_deref(), Some("127.0.0.1:9100"));
+    }
+
+    #[test]
+    fn test_health() {
+        assert_eq!(Config::default().health.listen, None);
+
+        let config = Config::from_toml_str(
+            r#"
+            [health]
+            listen = "0.0.0.0:8080"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.health.listen.as_deref(), Some("0.0.0.0:8080"));
+    }
+
+    #[test]
+    fn test_nsm() {
+        assert_eq!(Config::default().nsm.state_dir, None);
+
+        let config = Config::from_toml_str(
+            r#"
+            [nsm]
+            state_dir = "/var/lib/arcticwolf/nsm"
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nsm.state_dir.as_deref(), Some("/var/lib/arcticwolf/nsm"));
+    }
+
+    #[test]
+    fn test_connection_limit() {
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            max_connections_per_ip = 4
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.max_connections_per_ip, 4);
+    }
+
+    #[test]
+    fn test_max_connections() {
+        assert_eq!(Config::default().server.max_connections, 1024);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            max_connections = 8
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.max_connections, 8);
+
+        assert!(Config::from_toml_str("[server]\nmax_connections = 0").is_err());
+    }
+
+    #[test]
+    fn test_shutdown_flush_timeout() {
+        assert_eq!(Config::default().server.shutdown_flush_timeout_secs, 30);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            shutdown_flush_timeout_secs = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.shutdown_flush_timeout_secs, 5);
+    }
+
+    #[test]
+    fn test_idle_timeout() {
+        assert_eq!(Config::default().server.idle_timeout_secs, 60);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            idle_timeout_secs = 0
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.idle_timeout_secs, 0);
+    }
+
+    #[test]
+    fn test_socket_options() {
+        let server = Config::default().server;
+        assert!(server.tcp_nodelay);
+        assert_eq!(server.keepalive_secs, 60);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            tcp_nodelay = false
+            keepalive_secs = 0
+            "#,
+        )
+        .unwrap();
+        assert!(!config.server.tcp_nodelay);
+        assert_eq!(config.server.keepalive_secs, 0);
+        assert_eq!(config.server.send_buffer_size, 0);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            send_buffer_size = 4194304
+            recv_buffer_size = 1048576
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.send_buffer_size, 4 * 1024 * 1024);
+        assert_eq!(config.server.recv_buffer_size, 1024 * 1024);
+    }
+
+    #[test]
+    fn test_shutdown_grace_period() {
+        assert_eq!(Config::default().server.shutdown_grace_period_secs, 10);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            shutdown_grace_period_secs = 2
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.shutdown_grace_period_secs, 2);
+    }
+
+    #[test]
+    fn test_max_message_size() {
+        assert_eq!(Config::default().server.max_message_size, 1024 * 1024 + 4096);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            max_message_size = 69632
+
+            [nfs]
+            wtmax = 65536
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.max_message_size, 69632);
+
+        // Smaller than the largest WRITE clients are told they may send
+        assert!(Config::from_toml_str(
+            r#"
+            [server]
+            max_message_size = 65536
+            "#,
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_max_fragment_size() {
+        assert_eq!(Config::default().server.max_fragment_size, 32 * 1024);
+
+        let config = Config::from_toml_str(
+            r#"
+            [server]
+            max_fragment_size = 8192
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.server.max_fragment_size, 8192);
+    }
+
+    #[test]
+    fn test_transfer_sizes() {
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            rtmax = 262144
+            rtpref = 131072
+            wtpref = 32768
+            dtpref = 16384
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.rtmax, 262144);
+        assert_eq!(config.nfs.rtpref, 131072);
+        assert_eq!(config.nfs.wtmax, 1024 * 1024);
+        assert_eq!(config.nfs.wtpref, 32768);
+        assert_eq!(config.nfs.dtpref, 16384);
+    }
+
+    #[test]
+    fn test_oversized_write_policy() {
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            wtmax = 65536
+            oversized_writes = "accept"
+            write_hard_limit = 131072
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.wtmax, 65536);
+        assert_eq!(config.nfs.oversized_writes, OversizedWritePolicy::Accept);
+        assert_eq!(config.nfs.write_hard_limit, 131072);
+    }
+
+    #[test]
+    fn test_file_handle_len() {
+        assert_eq!(Config::default().nfs.file_handle_len, 32);
+
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            file_handle_len = 64
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.file_handle_len, 64);
+    }
+
+    #[test]
+    fn test_inflight_limit() {
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            max_inflight_bytes = 67108864
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.max_inflight_bytes, Some(64 * 1024 * 1024));
+
+        // A budget no transfer of the advertised size fits is refused
+        let result = Config::from_toml_str(
+            r#"
+            [nfs]
+            max_inflight_bytes = 1048576
+            "#,
+        );
+        assert!(result.is_err());
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            rtmax = 524288
+            wtmax = 524288
+            max_inflight_bytes = 1048576
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.max_inflight_bytes, Some(1024 * 1024));
+    }
+
+    #[test]
+    fn test_reply_cache() {
+        let default = Config::default();
+        assert_eq!(default.nfs.reply_cache_size, 1024);
+        assert_eq!(default.nfs.reply_cache_ttl_secs, 120);
+
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            reply_cache_size = 0
+            reply_cache_ttl_secs = 30
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.reply_cache_size, 0);
+        assert_eq!(config.nfs.reply_cache_ttl_secs, 30);
+    }
+
+    #[test]
+    fn test_owner_override() {
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            override_uid = 1000
+            override_gid = 100
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.override_uid, Some(1000));
+        assert_eq!(config.nfs.override_gid, Some(100));
+    }
+
+    #[test]
+    fn test_readdir_limits() {
+        let config = Config::from_toml_str(
+            r#"
+            [nfs]
+            readdir_max_entries_per_client = 50000
+            readdir_window_secs = 5
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.nfs.readdir_max_entries_per_client, Some(50000));
+        assert_eq!(config.nfs.readdir_window_secs, 5);
+    }
+
+    #[test]
+    fn test_unknown_field_rejected() {
+        let result = Config::from_toml_str(
+            r#"
+            [nfs]
+            no_such_option = true
+            "#,
+        );
+        assert!(result.is_err(), "Typos in config should be reported");
+    }
+}
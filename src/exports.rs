@@ -0,0 +1,470 @@
+// Export Table
+//
+// The exports served by this server, each with the FSAL backend rooted at its
+// directory. MOUNT picks an export by path (longest match); NFS calls are
+// routed to the export whose backend issued the file handle they carry.
+//
+// A configuration reload swaps in a whole new table; each call works on the
+// table that was current when it arrived.
+
+use anyhow::Result;
+use std::net::IpAddr;
+use std::sync::{Arc, RwLock};
+
+use crate::config::ExportConfig;
+use crate::fsal::Filesystem;
+use crate::hostnames;
+use crate::nfs::drc::DuplicateRequestCache;
+
+/// A configured export and the backend serving it
+#[derive(Clone)]
+pub struct Export {
+    pub config: ExportConfig,
+    pub filesystem: Arc<dyn Filesystem>,
+}
+
+impl Export {
+    pub fn new(config: ExportConfig, filesystem: Arc<dyn Filesystem>) -> Self {
+        Self { config, filesystem }
+    }
+}
+
+/// All exports, in configuration order; cheap to clone
+#[derive(Clone)]
+pub struct ExportTable {
+    exports: Arc<[Export]>,
+}
+
+impl ExportTable {
+    pub fn new(exports: Vec<Export>) -> Self {
+        Self {
+            exports: Arc::from(exports),
+        }
+    }
+
+    /// Every export, in configuration order
+    pub fn iter(&self) -> std::slice::Iter<'_, Export> {
+        self.exports.iter()
+    }
+
+    /// Configuration of every export, in configuration order
+    pub fn configs(&self) -> impl Iterator<Item = &ExportConfig> {
+        self.exports.iter().map(|export| &export.config)
+    }
+
+    /// The first configured export
+    pub fn first(&self) -> Option<&Export> {
+        self.exports.first()
+    }
+
+    /// The export containing `dirpath`, preferring the longest export path
+    ///
+    /// With "/srv" and "/srv/projects" both exported, "/srv/projects/a"
+    /// belongs to the latter.
+    pub fn for_path(&self, dirpath: &str) -> Option<&Export> {
+        self.exports
+            .iter()
+            .filter(|export| relative_path(dirpath, &export.config.path).is_some())
+            .max_by_key(|export| export.config.path.trim_end_matches('/').len())
+    }
+
+    /// The export whose backend issued `handle`, preferring the longest
+    /// export path
+    ///
+    /// With "/srv" and "/srv/projects" both exported, a handle both backends
+    /// accept belongs to the latter, as its MOUNT did.
+    pub fn for_handle(&self, handle: &[u8]) -> Option<&Export> {
+        let handle = handle.to_vec();
+        self.exports
+            .iter()
+            .filter(|export| export.filesystem.owns_handle(&handle))
+            .max_by_key(|export| export.config.path.trim_end_matches('/').len())
+    }
+
+    /// A table serving `configs`
+    ///
+    /// Paths exported already keep their backend, so the handles clients
+    /// hold stay valid, and only pick up the new options; new paths get a
+    /// backend from `open`. Fails without side effects if any `open` fails.
+    pub fn reconfigure(
+        &self,
+        configs: &[ExportConfig],
+        open: impl Fn(&ExportConfig) -> Result<Arc<dyn Filesystem>>,
+    ) -> Result<ExportTable> {
+        let mut exports = Vec::with_capacity(configs.len());
+        for config in configs {
+            let filesystem = match self.iter().find(|export| export.config.path == config.path) {
+                Some(export) => export.filesystem.clone(),
+                None => open(config)?,
+            };
+            exports.push(Export::new(config.clone(), filesystem));
+        }
+        Ok(ExportTable::new(exports))
+    }
+}
+
+/// The export table in effect, shared by everything serving calls and
+/// replaced as a whole on reload
+#[derive(Clone)]
+pub struct SharedExports {
+    current: Arc<RwLock<ExportTable>>,
+}
+
+impl SharedExports {
+    pub fn new(table: ExportTable) -> Self {
+        Self {
+            current: Arc::new(RwLock::new(table)),
+        }
+    }
+
+    /// The table in effect now; later replacements don't affect it
+    pub fn current(&self) -> ExportTable {
+        self.current.read().unwrap().clone()
+    }
+
+    /// Serve `table` from now on
+    pub fn replace(&self, table: ExportTable) {
+        *self.current.write().unwrap() = table;
+    }
+
+    /// Serve `table`, reloaded from the table in effect, from now on
+    ///
+    /// Only what the new options may have made wrong is dropped: the cached
+    /// attributes of exports whose options changed and, once any export
+    /// decides differently who may do what, the replies in `reply_cache`,
+    /// answered under the old rules. Otherwise a retransmitted call still
+    /// gets its original reply.
+    pub fn reload(&self, table: ExportTable, reply_cache: &DuplicateRequestCache) {
+        let previous = std::mem::replace(&mut *self.current.write().unwrap(), table.clone());
+
+        let mut access_changed = false;
+        for export in table.iter() {
+            let Some(old) = previous.iter().find(|old| old.config.path == export.config.path)
+            else {
+                continue;
+            };
+            if old.config != export.config {
+                export.filesystem.drop_caches();
+            }
+            access_changed |= old.config.access_differs(&export.config);
+        }
+        if access_changed {
+            reply_cache.clear();
+        }
+    }
+}
+
+/// The part of `dirpath` below `export_path`, or None if it is not inside
+///
+/// Matching is by whole components: "/exportfoo" is not below "/export".
+pub fn relative_path<'a>(dirpath: &'a str, export_path: &str) -> Option<&'a str> {
+    let rest = dirpath.strip_prefix(export_path.trim_end_matches('/'))?;
+    (rest.is_empty() || rest.starts_with('/')).then_some(rest)
+}
+
+/// Whether `client` may mount and access `export`
+///
+/// An empty client list admits everyone. Entries are "*", single addresses
+/// ("192.0.2.10"), CIDR ranges ("10.0.0.0/24", "2001:db8::/32"), or host
+/// names and wildcard patterns ("*.corp.example.com") matched against the
+/// client's verified reverse-DNS name. A client without one can still match
+/// by address.
+pub fn client_allowed(client: IpAddr, export: &ExportConfig) -> bool {
+    client_matches(client, export, || hostnames::cache().hostname(client))
+}
+
+/// `client_allowed`, with the client's host name looked up by `hostname`
+/// only when an address entry doesn't already admit it
+fn client_matches(
+    client: IpAddr,
+    export: &ExportConfig,
+    hostname: impl FnOnce() -> Option<String>,
+) -> bool {
+    let address_match = export.clients.is_empty()
+        || export.clients.iter().any(|entry| {
+            entry == "*"
+                || parse_cidr(entry)
+                    .is_some_and(|(network, prefix)| cidr_contains(network, prefix, client))
+        });
+    if address_match {
+        return true;
+    }
+
+    // Only exports naming hosts pay for a reverse lookup
+    let mut patterns = export
+        .clients
+        .iter()
+        .filter(|entry| parse_cidr(entry).is_none())
+        .peekable();
+    if patterns.peek().is_none() {
+        return false;
+    }
+    let Some(name) = hostname() else {
+        return false;
+    };
+    patterns.any(|pattern| hostnames::matches(pattern, &name))
+}
+
+/// Parse an address ("192.0.2.10") or CIDR range ("10.0.0.0/24") into the
+/// network address and prefix length
+pub fn parse_cidr(entry: &str) -> Option<(IpAddr, u8)> {
+    let (addr, prefix) = match entry.split_once('/') {
+        Some((addr, prefix)) => (addr, Some(prefix)),
+        None => (entry, None),
+    };
+    let addr: IpAddr = addr.parse().ok()?;
+    let max_prefix = if addr.is_ipv4() { 32 } else { 128 };
+    let prefix = match prefix {
+        Some(prefix) => prefix.parse::<u8>().ok().filter(|&p| p <= max_prefix)?,
+        None => max_prefix,
+    };
+    Some((addr, prefix))
+}
+
+/// Whether `client` lies within `network/prefix`
+///
+/// IPv4-mapped IPv6 clients (::ffff:a.b.c.d) match IPv4 ranges.
+fn cidr_contains(network: IpAddr, prefix: u8, client: IpAddr) -> bool {
+    match (network, client.to_canonical()) {
+        (IpAddr::V4(network), IpAddr::V4(client)) => {
+            let mask = u32::MAX.checked_shl(32 - prefix as u32).unwrap_or(0);
+            u32::from(network) & mask == u32::from(client) & mask
+        }
+        (IpAddr::V6(network), IpAddr::V6(client)) => {
+            let mask = u128::MAX.checked_shl(128 - prefix as u32).unwrap_or(0);
+            u128::from(network) & mask == u128::from(client) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use tempfile::TempDir;
+
+    fn export(path: &str, root: &TempDir) -> Export {
+        let filesystem = BackendConfig::local(root.path())
+            .create_filesystem()
+            .unwrap();
+        Export::new(
+            ExportConfig {
+                path: path.to_string(),
+                ..ExportConfig::default()
+            },
+            Arc::from(filesystem),
+        )
+    }
+
+    #[test]
+    fn test_relative_path() {
+        assert_eq!(relative_path("/srv/export", "/srv/export"), Some(""));
+        assert_eq!(relative_path("/srv/export/a/b", "/srv/export/"), Some("/a/b"));
+        assert_eq!(relative_path("/srv/exportfoo", "/srv/export"), None);
+        assert_eq!(relative_path("/etc", "/srv/export"), None);
+        assert_eq!(relative_path("/etc", "/"), Some("/etc"));
+    }
+
+    #[test]
+    fn test_longest_match() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let table = ExportTable::new(vec![export("/srv", &a), export("/srv/projects", &b)]);
+
+        let path_of = |dirpath| table.for_path(dirpath).map(|e| e.config.path.as_str());
+        assert_eq!(path_of("/srv"), Some("/srv"));
+        assert_eq!(path_of("/srv/home"), Some("/srv"));
+        assert_eq!(path_of("/srv/projects"), Some("/srv/projects"));
+        assert_eq!(path_of("/srv/projects/a"), Some("/srv/projects"));
+        assert_eq!(path_of("/srv/projectsx"), Some("/srv"));
+        assert_eq!(path_of("/var"), None);
+    }
+
+    #[test]
+    fn test_route_by_handle() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(b.path().join("file.txt"), b"data").unwrap();
+        let table = ExportTable::new(vec![export("/a", &a), export("/b", &b)]);
+
+        let b_export = table.iter().nth(1).unwrap();
+        let file = b_export
+            .filesystem
+            .lookup(&b_export.filesystem.root_handle(), "file.txt")
+            .unwrap();
+
+        assert_eq!(table.for_handle(&file).unwrap().config.path, "/b");
+        let root_a = table.first().unwrap().filesystem.root_handle();
+        assert_eq!(table.for_handle(&root_a).unwrap().config.path, "/a");
+        assert!(table.for_handle(&[0xDE, 0xAD, 0xBE, 0xEF]).is_none());
+    }
+
+    #[test]
+    fn test_route_nested_export_by_handle() {
+        let root = TempDir::new().unwrap();
+        std::fs::create_dir(root.path().join("projects")).unwrap();
+        std::fs::write(root.path().join("projects/file.txt"), b"data").unwrap();
+        let nested = |path: &str, dir: &std::path::Path| {
+            let filesystem = BackendConfig::local(dir).create_filesystem().unwrap();
+            Export::new(
+                ExportConfig {
+                    path: path.to_string(),
+                    ..ExportConfig::default()
+                },
+                Arc::from(filesystem),
+            )
+        };
+        let table = ExportTable::new(vec![
+            nested("/srv", root.path()),
+            nested("/srv/projects", &root.path().join("projects")),
+            nested("/srv/alias", root.path()),
+        ]);
+
+        // Handles the nested export issued go to it, not its parent
+        let projects = &table.iter().nth(1).unwrap().filesystem;
+        let file = projects.lookup(&projects.root_handle(), "file.txt").unwrap();
+        assert_eq!(table.for_handle(&file).unwrap().config.path, "/srv/projects");
+        assert_eq!(
+            table.for_handle(&projects.root_handle()).unwrap().config.path,
+            "/srv/projects"
+        );
+
+        // Of two exports of one directory, the longer path wins either way
+        let srv = &table.first().unwrap().filesystem;
+        assert_eq!(table.for_handle(&srv.root_handle()).unwrap().config.path, "/srv/alias");
+    }
+
+    #[test]
+    fn test_reconfigure() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let table = ExportTable::new(vec![export("/a", &a), export("/b", &b)]);
+        let old_a = table.first().unwrap().filesystem.clone();
+
+        let c = TempDir::new().unwrap();
+        let configs = vec![
+            ExportConfig {
+                path: "/a".to_string(),
+                read_only: true,
+                ..ExportConfig::default()
+            },
+            ExportConfig {
+                path: "/c".to_string(),
+                ..ExportConfig::default()
+            },
+        ];
+        let open = |config: &ExportConfig| -> Result<Arc<dyn Filesystem>> {
+            assert_eq!(config.path, "/c", "only new paths get a backend");
+            let filesystem = BackendConfig::local(c.path()).create_filesystem()?;
+            Ok(Arc::from(filesystem))
+        };
+        let reloaded = table.reconfigure(&configs, open).unwrap();
+
+        // /a keeps its backend (and handles) with the new options; /b is gone
+        let paths: Vec<_> = reloaded.configs().map(|config| config.path.as_str()).collect();
+        assert_eq!(paths, ["/a", "/c"]);
+        let new_a = reloaded.first().unwrap();
+        assert!(Arc::ptr_eq(&new_a.filesystem, &old_a));
+        assert!(new_a.config.read_only);
+        assert!(reloaded.for_handle(&old_a.root_handle()).is_some());
+
+        // A backend that can't be opened fails the whole reload
+        let failed = table.reconfigure(&configs, |_| Err(anyhow::anyhow!("no such directory")));
+        assert!(failed.is_err());
+    }
+
+    #[test]
+    fn test_shared_exports_swap() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let shared = SharedExports::new(ExportTable::new(vec![export("/a", &a)]));
+
+        // A table taken before the swap is unaffected by it
+        let in_flight = shared.current();
+        shared.replace(ExportTable::new(vec![export("/b", &b)]));
+        assert_eq!(in_flight.first().unwrap().config.path, "/a");
+        assert_eq!(shared.current().first().unwrap().config.path, "/b");
+    }
+
+    #[test]
+    fn test_reload_flushes_what_changed() {
+        use crate::config::SquashPolicy;
+        use crate::nfs::drc::{DrcKey, DrcLookup};
+        use bytes::BytesMut;
+        use std::time::Duration;
+
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        let table = ExportTable::new(vec![export("/a", &a), export("/b", &b)]);
+        let shared = SharedExports::new(table.clone());
+        let reply_cache = DuplicateRequestCache::new(16, Duration::from_secs(60));
+        let key = DrcKey::new("127.0.0.1:700".parse().unwrap(), 1, 100003, 12, b"args");
+        reply_cache.insert(key, &BytesMut::from(&b"removed"[..]));
+
+        // Options that don't change who may do what keep the cached replies
+        let mut configs: Vec<_> = table.configs().cloned().collect();
+        configs[1].atime = crate::fsal::AtimePolicy::Noatime;
+        shared.reload(table.reconfigure(&configs, |_| unreachable!()).unwrap(), &reply_cache);
+        assert_eq!(reply_cache.begin(&key), DrcLookup::Replay(BytesMut::from(&b"removed"[..])));
+
+        // Squashing differently drops them
+        configs[0].squash = SquashPolicy::NoRootSquash;
+        shared.reload(table.reconfigure(&configs, |_| unreachable!()).unwrap(), &reply_cache);
+        assert_eq!(reply_cache.begin(&key), DrcLookup::New);
+        assert_eq!(shared.current().first().unwrap().config.squash, SquashPolicy::NoRootSquash);
+    }
+
+    #[test]
+    fn test_parse_cidr() {
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        assert_eq!(parse_cidr("10.0.0.0/24"), Some((ip("10.0.0.0"), 24)));
+        assert_eq!(parse_cidr("192.0.2.10"), Some((ip("192.0.2.10"), 32)));
+        assert_eq!(parse_cidr("2001:db8::/32"), Some((ip("2001:db8::"), 32)));
+        assert_eq!(parse_cidr("10.0.0.0/33"), None);
+        assert_eq!(parse_cidr("10.0.0.0/x"), None);
+        assert_eq!(parse_cidr("backup.example.com"), None);
+    }
+
+    #[test]
+    fn test_client_allowed() {
+        let export = |clients: &[&str]| ExportConfig {
+            clients: clients.iter().map(|c| c.to_string()).collect(),
+            ..ExportConfig::default()
+        };
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+
+        assert!(client_allowed(ip("203.0.113.5"), &export(&[])));
+        assert!(client_allowed(ip("203.0.113.5"), &export(&["*"])));
+
+        // Clients without a host name only match by address
+        let lan = export(&["10.0.0.0/24", "192.0.2.10", "2001:db8::/32", "backup"]);
+        let allowed = |client: &str| client_matches(ip(client), &lan, || None);
+        assert!(allowed("10.0.0.7"));
+        assert!(allowed("::ffff:10.0.0.7"));
+        assert!(!allowed("10.0.1.7"));
+        assert!(allowed("192.0.2.10"));
+        assert!(!allowed("192.0.2.11"));
+        assert!(allowed("2001:db8::1"));
+        assert!(!allowed("2001:db9::1"));
+
+        assert!(client_allowed(ip("198.51.100.1"), &export(&["0.0.0.0/0"])));
+    }
+
+    #[test]
+    fn test_client_allowed_by_hostname() {
+        let corp = ExportConfig {
+            clients: vec!["10.0.0.0/24".to_string(), "*.corp.example.com".to_string()],
+            ..ExportConfig::default()
+        };
+        let ip = |s: &str| s.parse::<IpAddr>().unwrap();
+        let named = |name: &'static str| move || Some(name.to_string());
+
+        assert!(client_matches(ip("192.0.2.5"), &corp, named("ws1.corp.example.com")));
+        assert!(!client_matches(ip("192.0.2.5"), &corp, named("ws1.example.com")));
+        assert!(!client_matches(ip("192.0.2.5"), &corp, || None));
+
+        // Address matches never need a lookup, nor do exports without names
+        assert!(client_matches(ip("10.0.0.5"), &corp, || unreachable!()));
+        let by_address = ExportConfig {
+            clients: vec!["10.0.0.0/24".to_string()],
+            ..ExportConfig::default()
+        };
+        assert!(!client_matches(ip("192.0.2.5"), &by_address, || unreachable!()));
+    }
+}
@@ -0,0 +1,516 @@
+// Attribute Cache
+//
+// GETATTR, LOOKUP and the post-op attributes of nearly every reply stat the
+// backend. CachingFilesystem wraps a backend and keeps recently fetched
+// attributes for a short time, dropping a handle's entry whenever an
+// operation through it could change them. Hard links have handles of their
+// own, so entries are dropped by file id, covering every cached name of the
+// file. Changes made behind the server's back show up once entries expire.
+
+use anyhow::Result;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use super::{
+    Capabilities, CreateMode, DirEntry, FileAttributes, FileHandle, FileType, Filesystem,
+    FsStat, PathConf, SetTime,
+};
+
+/// Hit and miss counters, shared by every export's cache
+#[derive(Default)]
+pub struct CacheStats {
+    hits: AtomicU64,
+    misses: AtomicU64,
+}
+
+impl CacheStats {
+    pub fn hits(&self) -> u64 {
+        self.hits.load(Ordering::Relaxed)
+    }
+
+    pub fn misses(&self) -> u64 {
+        self.misses.load(Ordering::Relaxed)
+    }
+}
+
+struct Entry {
+    attrs: FileAttributes,
+    fetched: Instant,
+    /// Position in the recency order
+    tick: u64,
+}
+
+/// A `getattr` miss on its way to the backend
+struct Fetch {
+    generation: u64,
+    /// Files invalidated since it started; its result may predate them
+    dropped: Vec<u64>,
+}
+
+/// Least recently used entries, bounded in number and age
+struct AttrCache {
+    ttl: Duration,
+    capacity: usize,
+    entries: HashMap<FileHandle, Entry>,
+    /// Handles by last use, oldest first
+    recency: BTreeMap<u64, FileHandle>,
+    next_tick: u64,
+    /// Cached handles by file id, so every name of a file drops together
+    by_fileid: HashMap<u64, HashSet<FileHandle>>,
+    /// Misses in progress; an invalidation that lands during one keeps its
+    /// stale result out of the cache
+    fetching: HashMap<FileHandle, Fetch>,
+    next_generation: u64,
+}
+
+impl AttrCache {
+    fn new(ttl: Duration, capacity: usize) -> Self {
+        Self {
+            ttl,
+            capacity,
+            entries: HashMap::new(),
+            recency: BTreeMap::new(),
+            next_tick: 0,
+            by_fileid: HashMap::new(),
+            fetching: HashMap::new(),
+            next_generation: 0,
+        }
+    }
+
+    fn get(&mut self, handle: &FileHandle) -> Option<FileAttributes> {
+        let entry = self.entries.get_mut(handle)?;
+        if entry.fetched.elapsed() >= self.ttl {
+            self.remove(handle);
+            return None;
+        }
+        self.recency.remove(&entry.tick);
+        entry.tick = self.next_tick;
+        self.recency.insert(entry.tick, handle.clone());
+        self.next_tick += 1;
+        Some(entry.attrs.clone())
+    }
+
+    /// Note a miss for `handle` going to the backend
+    fn start_fetch(&mut self, handle: &FileHandle) -> u64 {
+        let generation = self.next_generation;
+        self.next_generation += 1;
+        self.fetching.insert(
+            handle.clone(),
+            Fetch {
+                generation,
+                dropped: Vec::new(),
+            },
+        );
+        generation
+    }
+
+    /// Cache what the miss fetched, unless the file was invalidated since
+    fn finish_fetch(&mut self, handle: &FileHandle, generation: u64, attrs: Option<&FileAttributes>) {
+        if self.fetching.get(handle).map(|fetch| fetch.generation) != Some(generation) {
+            return;
+        }
+        let fetch = self.fetching.remove(handle).unwrap();
+        if let Some(attrs) = attrs.filter(|attrs| !fetch.dropped.contains(&attrs.fileid)) {
+            self.insert(handle, attrs.clone());
+        }
+    }
+
+    fn insert(&mut self, handle: &FileHandle, attrs: FileAttributes) {
+        self.remove(handle);
+        while self.entries.len() >= self.capacity {
+            let Some((_, oldest)) = self.recency.first_key_value() else {
+                break;
+            };
+            let oldest = oldest.clone();
+            self.remove(&oldest);
+        }
+        let tick = self.next_tick;
+        self.next_tick += 1;
+        self.recency.insert(tick, handle.clone());
+        self.by_fileid
+            .entry(attrs.fileid)
+            .or_default()
+            .insert(handle.clone());
+        self.entries.insert(
+            handle.clone(),
+            Entry {
+                attrs,
+                fetched: Instant::now(),
+                tick,
+            },
+        );
+    }
+
+    fn remove(&mut self, handle: &FileHandle) {
+        if let Some(entry) = self.entries.remove(handle) {
+            self.recency.remove(&entry.tick);
+            if let Some(handles) = self.by_fileid.get_mut(&entry.attrs.fileid) {
+                handles.remove(handle);
+                if handles.is_empty() {
+                    self.by_fileid.remove(&entry.attrs.fileid);
+                }
+            }
+        }
+    }
+
+    /// Drop `handle`'s entry and any fetch of it in progress
+    fn invalidate(&mut self, handle: &FileHandle) {
+        self.remove(handle);
+        self.fetching.remove(handle);
+    }
+
+    /// Drop `handle` and every other name cached for the same file
+    fn remove_file(&mut self, handle: &FileHandle) {
+        match self.entries.get(handle).map(|entry| entry.attrs.fileid) {
+            Some(fileid) => self.remove_fileid(fileid),
+            // Not cached, so any fetch in progress could be another name of it
+            None => self.fetching.clear(),
+        }
+    }
+
+    /// Drop every entry, and keep the results of fetches in progress out
+    fn clear(&mut self) {
+        self.entries.clear();
+        self.recency.clear();
+        self.by_fileid.clear();
+        self.fetching.clear();
+    }
+
+    fn remove_fileid(&mut self, fileid: u64) {
+        for handle in self.by_fileid.remove(&fileid).unwrap_or_default() {
+            self.remove(&handle);
+        }
+        // A fetch in progress may be for another name of the same file
+        for fetch in self.fetching.values_mut() {
+            fetch.dropped.push(fileid);
+        }
+    }
+}
+
+/// A backend with an attribute cache in front of `getattr`
+pub struct CachingFilesystem {
+    inner: Box<dyn Filesystem>,
+    cache: Mutex<AttrCache>,
+    stats: Arc<CacheStats>,
+}
+
+impl CachingFilesystem {
+    /// Cache up to `capacity` handles' attributes for `ttl` each
+    pub fn new(
+        inner: Box<dyn Filesystem>,
+        ttl: Duration,
+        capacity: usize,
+        stats: Arc<CacheStats>,
+    ) -> Self {
+        Self {
+            inner,
+            cache: Mutex::new(AttrCache::new(ttl, capacity.max(1))),
+            stats,
+        }
+    }
+
+    /// Drop the entries of the file `name` in `dir` refers to, before an
+    /// operation replaces or unlinks it
+    ///
+    /// Its file id comes from the backend, since the name itself may not be
+    /// cached while another link to the file is.
+    fn invalidate_child(&self, dir: &FileHandle, name: &str) {
+        let Ok(child) = self.inner.lookup(dir, name) else {
+            return;
+        };
+        let fileid = self.inner.getattr(&child).ok().map(|attrs| attrs.fileid);
+        let mut cache = self.cache.lock().unwrap();
+        cache.invalidate(&child);
+        if let Some(fileid) = fileid {
+            cache.remove_fileid(fileid);
+        }
+    }
+
+    /// Drop the entries of the files behind `handles`, passing `result` on
+    fn invalidating<T>(&self, handles: &[&FileHandle], result: Result<T>) -> Result<T> {
+        let mut cache = self.cache.lock().unwrap();
+        for handle in handles {
+            cache.remove_file(handle);
+            cache.invalidate(handle);
+        }
+        result
+    }
+}
+
+impl Filesystem for CachingFilesystem {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_handle(&self) -> FileHandle {
+        self.inner.root_handle()
+    }
+
+    fn owns_handle(&self, handle: &FileHandle) -> bool {
+        self.inner.owns_handle(handle)
+    }
+
+    fn drop_caches(&self) {
+        self.cache.lock().unwrap().clear();
+        self.inner.drop_caches();
+    }
+
+    fn lookup(&self, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
+        self.inner.lookup(dir_handle, name)
+    }
+
+    fn getattr(&self, handle: &FileHandle) -> Result<FileAttributes> {
+        if let Some(attrs) = self.cache.lock().unwrap().get(handle) {
+            self.stats.hits.fetch_add(1, Ordering::Relaxed);
+            return Ok(attrs);
+        }
+        self.stats.misses.fetch_add(1, Ordering::Relaxed);
+
+        // The lock is not held across the backend call, so the result is
+        // only cached if nothing invalidated the file in the meantime
+        let generation = self.cache.lock().unwrap().start_fetch(handle);
+        let result = self.inner.getattr(handle);
+        self.cache
+            .lock()
+            .unwrap()
+            .finish_fetch(handle, generation, result.as_ref().ok());
+        result
+    }
+
+    fn statfs(&self, handle: &FileHandle) -> Result<FsStat> {
+        self.inner.statfs(handle)
+    }
+
+    fn pathconf(&self, handle: &FileHandle) -> Result<PathConf> {
+        self.inner.pathconf(handle)
+    }
+
+    fn read(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<Vec<u8>> {
+        self.inner.read(handle, offset, count)
+    }
+
+    fn readdir(&self, dir_handle: &FileHandle, cookie: u64, count: u32) -> Result<(Vec<DirEntry>, bool)> {
+        self.inner.readdir(dir_handle, cookie, count)
+    }
+
+    fn write(&self, handle: &FileHandle, offset: u64, data: &[u8]) -> Result<u32> {
+        self.invalidating(&[handle], self.inner.write(handle, offset, data))
+    }
+
+    fn setattr_size(&self, handle: &FileHandle, size: u64) -> Result<()> {
+        self.invalidating(&[handle], self.inner.setattr_size(handle, size))
+    }
+
+    fn setattr_mode(&self, handle: &FileHandle, mode: u32) -> Result<()> {
+        self.invalidating(&[handle], self.inner.setattr_mode(handle, mode))
+    }
+
+    fn setattr_owner(&self, handle: &FileHandle, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.invalidating(&[handle], self.inner.setattr_owner(handle, uid, gid))
+    }
+
+    fn setattr_times(
+        &self,
+        handle: &FileHandle,
+        atime: Option<SetTime>,
+        mtime: Option<SetTime>,
+    ) -> Result<()> {
+        self.invalidating(&[handle], self.inner.setattr_times(handle, atime, mtime))
+    }
+
+    fn create(
+        &self,
+        dir_handle: &FileHandle,
+        name: &str,
+        mode: u32,
+        how: CreateMode,
+    ) -> Result<FileHandle> {
+        // UNCHECKED truncates an existing file
+        if how == CreateMode::Unchecked {
+            self.invalidate_child(dir_handle, name);
+        }
+        self.invalidating(&[dir_handle], self.inner.create(dir_handle, name, mode, how))
+    }
+
+    fn remove(&self, dir_handle: &FileHandle, name: &str) -> Result<()> {
+        // Other links to the file see its link count drop
+        self.invalidate_child(dir_handle, name);
+        self.invalidating(&[dir_handle], self.inner.remove(dir_handle, name))
+    }
+
+    fn mkdir(&self, dir_handle: &FileHandle, name: &str, mode: u32) -> Result<FileHandle> {
+        self.invalidating(&[dir_handle], self.inner.mkdir(dir_handle, name, mode))
+    }
+
+    fn rmdir(&self, dir_handle: &FileHandle, name: &str) -> Result<()> {
+        self.invalidate_child(dir_handle, name);
+        self.invalidating(&[dir_handle], self.inner.rmdir(dir_handle, name))
+    }
+
+    fn rename(
+        &self,
+        from_dir_handle: &FileHandle,
+        from_name: &str,
+        to_dir_handle: &FileHandle,
+        to_name: &str,
+    ) -> Result<()> {
+        // Both the renamed object and any target it replaces change
+        self.invalidate_child(from_dir_handle, from_name);
+        self.invalidate_child(to_dir_handle, to_name);
+        let result = self.inner.rename(from_dir_handle, from_name, to_dir_handle, to_name);
+        self.invalidating(&[from_dir_handle, to_dir_handle], result)
+    }
+
+    fn symlink(&self, dir_handle: &FileHandle, name: &str, target: &str) -> Result<FileHandle> {
+        self.invalidating(&[dir_handle], self.inner.symlink(dir_handle, name, target))
+    }
+
+    fn readlink(&self, handle: &FileHandle) -> Result<String> {
+        self.inner.readlink(handle)
+    }
+
+    fn link(&self, file_handle: &FileHandle, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
+        let result = self.inner.link(file_handle, dir_handle, name);
+        self.invalidating(&[file_handle, dir_handle], result)
+    }
+
+    fn commit(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<()> {
+        self.inner.commit(handle, offset, count)
+    }
+
+    fn mknod(
+        &self,
+        dir_handle: &FileHandle,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+        rdev: (u32, u32),
+    ) -> Result<FileHandle> {
+        let result = self.inner.mknod(dir_handle, name, file_type, mode, rdev);
+        self.invalidating(&[dir_handle], result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use tempfile::TempDir;
+
+    fn caching_fs(temp_dir: &TempDir, ttl: Duration, capacity: usize) -> (CachingFilesystem, Arc<CacheStats>) {
+        let inner = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let stats = Arc::new(CacheStats::default());
+        (CachingFilesystem::new(inner, ttl, capacity, stats.clone()), stats)
+    }
+
+    #[test]
+    fn test_getattr_cached_until_mutation() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let (fs, stats) = caching_fs(&temp_dir, Duration::from_secs(60), 16);
+        let file = fs.lookup(&fs.root_handle(), "file.txt").unwrap();
+
+        assert_eq!(fs.getattr(&file).unwrap().size, 4);
+        assert_eq!(fs.getattr(&file).unwrap().size, 4);
+        assert_eq!((stats.hits(), stats.misses()), (1, 1));
+
+        // Changes behind the server's back are not seen while cached...
+        std::fs::write(temp_dir.path().join("file.txt"), b"longer data").unwrap();
+        assert_eq!(fs.getattr(&file).unwrap().size, 4);
+
+        // ...but writes through it are
+        fs.write(&file, 11, b"!").unwrap();
+        assert_eq!(fs.getattr(&file).unwrap().size, 12);
+        assert_eq!((stats.hits(), stats.misses()), (2, 2));
+    }
+
+    #[test]
+    fn test_directory_changes_invalidate() {
+        let temp_dir = TempDir::new().unwrap();
+        let (fs, _stats) = caching_fs(&temp_dir, Duration::from_secs(60), 16);
+        let root = fs.root_handle();
+
+        let nlink = fs.getattr(&root).unwrap().nlink;
+        fs.mkdir(&root, "sub", 0o755).unwrap();
+        assert_eq!(fs.getattr(&root).unwrap().nlink, nlink + 1);
+
+        let file = fs.create(&root, "a", 0o644, CreateMode::Unchecked).unwrap();
+        fs.link(&file, &root, "b").unwrap();
+        assert_eq!(fs.getattr(&file).unwrap().nlink, 2);
+        fs.remove(&root, "b").unwrap();
+        assert_eq!(fs.getattr(&file).unwrap().nlink, 1);
+    }
+
+    #[test]
+    fn test_invalidation_during_miss() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("a"), b"x").unwrap();
+        std::fs::hard_link(temp_dir.path().join("a"), temp_dir.path().join("b")).unwrap();
+        let (fs, _stats) = caching_fs(&temp_dir, Duration::from_secs(60), 16);
+        let a = fs.lookup(&fs.root_handle(), "a").unwrap();
+        let b = fs.lookup(&fs.root_handle(), "b").unwrap();
+        let attrs = fs.inner.getattr(&a).unwrap();
+        let mut cache = fs.cache.lock().unwrap();
+
+        // The handle itself changes while its attributes are fetched
+        let generation = cache.start_fetch(&a);
+        cache.invalidate(&a);
+        cache.finish_fetch(&a, generation, Some(&attrs));
+        assert!(cache.get(&a).is_none());
+
+        // Another name of the same file changes
+        cache.insert(&b, attrs.clone());
+        let generation = cache.start_fetch(&a);
+        cache.remove_file(&b);
+        cache.finish_fetch(&a, generation, Some(&attrs));
+        assert!(cache.get(&a).is_none());
+        assert!(cache.get(&b).is_none());
+        assert!(cache.by_fileid.is_empty() && cache.fetching.is_empty());
+    }
+
+    #[test]
+    fn test_drop_caches() {
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let (fs, stats) = caching_fs(&temp_dir, Duration::from_secs(60), 16);
+        let file = fs.lookup(&fs.root_handle(), "file.txt").unwrap();
+        fs.getattr(&file).unwrap();
+
+        std::fs::write(temp_dir.path().join("file.txt"), b"longer data").unwrap();
+        fs.drop_caches();
+        assert_eq!(fs.getattr(&file).unwrap().size, 11);
+        assert_eq!((stats.hits(), stats.misses()), (0, 2));
+    }
+
+    #[test]
+    fn test_expiry_and_eviction() {
+        let temp_dir = TempDir::new().unwrap();
+        for name in ["a", "b", "c"] {
+            std::fs::write(temp_dir.path().join(name), b"x").unwrap();
+        }
+
+        // A zero TTL never serves from the cache
+        let (fs, stats) = caching_fs(&temp_dir, Duration::ZERO, 16);
+        let a = fs.lookup(&fs.root_handle(), "a").unwrap();
+        fs.getattr(&a).unwrap();
+        fs.getattr(&a).unwrap();
+        assert_eq!((stats.hits(), stats.misses()), (0, 2));
+
+        // Two entries: using "a" again keeps it over "b" when "c" arrives
+        let (fs, stats) = caching_fs(&temp_dir, Duration::from_secs(60), 2);
+        let handle = |name| fs.lookup(&fs.root_handle(), name).unwrap();
+        let (a, b, c) = (handle("a"), handle("b"), handle("c"));
+        fs.getattr(&a).unwrap();
+        fs.getattr(&b).unwrap();
+        fs.getattr(&a).unwrap();
+        fs.getattr(&c).unwrap();
+        assert_eq!((stats.hits(), stats.misses()), (1, 3));
+        fs.getattr(&a).unwrap();
+        fs.getattr(&b).unwrap();
+        assert_eq!((stats.hits(), stats.misses()), (2, 4));
+    }
+}
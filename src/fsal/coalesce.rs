@@ -0,0 +1,430 @@
+// Write Coalescing
+//
+// Clients with small wsize, or applications writing in small chunks, send
+// long runs of small sequential UNSTABLE WRITEs, each of which costs the
+// backend a separate write. CoalescingFilesystem wraps a backend and holds
+// contiguous writes to a handle in memory, passing them down as one write
+// when the run breaks (a write elsewhere in the file), reaches the size
+// threshold, or something needs the data on the backend: COMMIT, READ,
+// SETATTR, LINK, or a CREATE, REMOVE or RENAME that could replace or unlink
+// the file.
+//
+// Crash consistency: buffered data lives only in this process until flushed,
+// which is what UNSTABLE allows. If the server dies first, the write verifier
+// changes with the restart and clients resend everything not yet committed.
+// A flush that fails is reported by the next COMMIT of that handle, so the
+// client learns about it the same way. Stable writes are committed by the
+// WRITE itself and never stay buffered. Until flushed, GETATTR reports the
+// buffered size but the backend's mtime, so clients may see the mtime move
+// once more when the data lands.
+
+use anyhow::{anyhow, Result};
+use std::collections::HashMap;
+use std::sync::Mutex;
+use tracing::{debug, warn};
+
+use crate::nfs::write_serializer::WriteSerializer;
+
+use super::{
+    Capabilities, CreateMode, DirEntry, FileAttributes, FileHandle, FileType, Filesystem,
+    FsStat, PathConf, SetTime,
+};
+
+/// Most handles with buffered writes at once; the least recently written
+/// is flushed to make room
+const MAX_BUFFERS: usize = 64;
+
+/// A run of contiguous writes not yet passed to the backend
+struct Pending {
+    offset: u64,
+    data: Vec<u8>,
+    /// Position in the write order, for choosing what to flush
+    tick: u64,
+}
+
+impl Pending {
+    fn end(&self) -> u64 {
+        self.offset + self.data.len() as u64
+    }
+}
+
+#[derive(Default)]
+struct Buffers {
+    pending: HashMap<FileHandle, Pending>,
+    /// Flushes that failed, reported by the handle's next COMMIT
+    failed: HashMap<FileHandle, String>,
+    next_tick: u64,
+}
+
+/// A backend that merges contiguous writes before passing them down
+pub struct CoalescingFilesystem {
+    inner: Box<dyn Filesystem>,
+    /// Buffered bytes per handle that trigger a flush
+    threshold: usize,
+    buffers: Mutex<Buffers>,
+    /// Orders writes and flushes per handle while the backend is written
+    serializer: WriteSerializer,
+}
+
+impl CoalescingFilesystem {
+    /// Buffer up to `threshold` bytes of contiguous writes per handle
+    pub fn new(inner: Box<dyn Filesystem>, threshold: usize) -> Self {
+        Self {
+            inner,
+            threshold: threshold.max(1),
+            buffers: Mutex::new(Buffers::default()),
+            serializer: WriteSerializer::new(true),
+        }
+    }
+
+    /// Write all of `data` at `offset`, however the backend splits it
+    fn write_all(&self, handle: &FileHandle, mut offset: u64, mut data: &[u8]) -> Result<()> {
+        while !data.is_empty() {
+            let written = self.inner.write(handle, offset, data)? as usize;
+            if written == 0 {
+                return Err(anyhow!("Backend accepted no data at offset {}", offset));
+            }
+            offset += written as u64;
+            data = &data[written.min(data.len())..];
+        }
+        Ok(())
+    }
+
+    /// Pass a taken-out run down, remembering a failure for the next COMMIT
+    ///
+    /// Called without the buffers lock held, so other handles keep writing
+    /// while the backend works; the caller holds the handle's serializer.
+    fn flush_pending(&self, handle: &FileHandle, pending: Pending) -> Result<()> {
+        debug!(
+            "Flushing {} coalesced bytes at offset {}",
+            pending.data.len(),
+            pending.offset
+        );
+        let result = self.write_all(handle, pending.offset, &pending.data);
+        if let Err(e) = &result {
+            warn!("Flushing coalesced writes failed: {}", e);
+            self.buffers
+                .lock()
+                .unwrap()
+                .failed
+                .insert(handle.clone(), e.to_string());
+        }
+        result
+    }
+
+    /// Put the writes buffered for `handle` on the backend
+    fn flush(&self, handle: &FileHandle) -> Result<()> {
+        self.serializer.run(handle, || {
+            let pending = self.buffers.lock().unwrap().pending.remove(handle);
+            match pending {
+                Some(pending) => self.flush_pending(handle, pending),
+                None => Ok(()),
+            }
+        })
+    }
+
+    /// Put every buffered write on the backend before the namespace changes
+    ///
+    /// Failures are left for each handle's COMMIT to report, not blamed on
+    /// the unrelated operation.
+    fn flush_all(&self) {
+        let handles: Vec<FileHandle> =
+            self.buffers.lock().unwrap().pending.keys().cloned().collect();
+        for handle in handles {
+            let _ = self.flush(&handle);
+        }
+    }
+}
+
+impl Drop for CoalescingFilesystem {
+    fn drop(&mut self) {
+        self.flush_all();
+    }
+}
+
+impl Filesystem for CoalescingFilesystem {
+    fn capabilities(&self) -> Capabilities {
+        self.inner.capabilities()
+    }
+
+    fn root_handle(&self) -> FileHandle {
+        self.inner.root_handle()
+    }
+
+    fn owns_handle(&self, handle: &FileHandle) -> bool {
+        self.inner.owns_handle(handle)
+    }
+
+    fn drop_caches(&self) {
+        self.inner.drop_caches();
+    }
+
+    fn lookup(&self, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
+        self.inner.lookup(dir_handle, name)
+    }
+
+    fn getattr(&self, handle: &FileHandle) -> Result<FileAttributes> {
+        let mut attrs = self.inner.getattr(handle)?;
+        if let Some(pending) = self.buffers.lock().unwrap().pending.get(handle) {
+            attrs.size = attrs.size.max(pending.end());
+        }
+        Ok(attrs)
+    }
+
+    fn statfs(&self, handle: &FileHandle) -> Result<FsStat> {
+        self.inner.statfs(handle)
+    }
+
+    fn pathconf(&self, handle: &FileHandle) -> Result<PathConf> {
+        self.inner.pathconf(handle)
+    }
+
+    fn read(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<Vec<u8>> {
+        self.flush(handle)?;
+        self.inner.read(handle, offset, count)
+    }
+
+    fn readdir(&self, dir_handle: &FileHandle, cookie: u64, count: u32) -> Result<(Vec<DirEntry>, bool)> {
+        self.inner.readdir(dir_handle, cookie, count)
+    }
+
+    fn write(&self, handle: &FileHandle, offset: u64, data: &[u8]) -> Result<u32> {
+        // Writes and flushes of one handle stay in order; the buffers lock
+        // is only held to update the map, never across a backend write
+        let (written, evict) = self.serializer.run(handle, || -> Result<(u32, Option<FileHandle>)> {
+            let mut buffers = self.buffers.lock().unwrap();
+            let tick = buffers.next_tick;
+            buffers.next_tick += 1;
+
+            // Continue the handle's run while it stays under the threshold
+            if let Some(pending) = buffers.pending.get_mut(handle) {
+                if pending.end() == offset && pending.data.len() + data.len() <= self.threshold {
+                    pending.data.extend_from_slice(data);
+                    pending.tick = tick;
+                    if pending.data.len() >= self.threshold {
+                        let pending = buffers.pending.remove(handle).unwrap();
+                        drop(buffers);
+                        self.flush_pending(handle, pending)?;
+                    }
+                    return Ok((data.len() as u32, None));
+                }
+            }
+
+            // Otherwise the run ends here; a write as large as a full buffer
+            // gains nothing from waiting
+            if let Some(pending) = buffers.pending.remove(handle) {
+                drop(buffers);
+                self.flush_pending(handle, pending)?;
+                buffers = self.buffers.lock().unwrap();
+            }
+            if data.len() >= self.threshold {
+                drop(buffers);
+                return Ok((self.inner.write(handle, offset, data)?, None));
+            }
+
+            // The oldest run is flushed once this handle is released, so no
+            // two handles' serializers are ever held together
+            let evict = if buffers.pending.len() >= MAX_BUFFERS {
+                buffers
+                    .pending
+                    .iter()
+                    .min_by_key(|(_, pending)| pending.tick)
+                    .map(|(handle, _)| handle.clone())
+            } else {
+                None
+            };
+            buffers.pending.insert(
+                handle.clone(),
+                Pending {
+                    offset,
+                    data: data.to_vec(),
+                    tick,
+                },
+            );
+            Ok((data.len() as u32, evict))
+        })?;
+
+        if let Some(oldest) = evict {
+            let _ = self.flush(&oldest);
+        }
+        Ok(written)
+    }
+
+    fn setattr_size(&self, handle: &FileHandle, size: u64) -> Result<()> {
+        self.flush(handle)?;
+        self.inner.setattr_size(handle, size)
+    }
+
+    fn setattr_mode(&self, handle: &FileHandle, mode: u32) -> Result<()> {
+        self.flush(handle)?;
+        self.inner.setattr_mode(handle, mode)
+    }
+
+    fn setattr_owner(&self, handle: &FileHandle, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
+        self.flush(handle)?;
+        self.inner.setattr_owner(handle, uid, gid)
+    }
+
+    fn setattr_times(
+        &self,
+        handle: &FileHandle,
+        atime: Option<SetTime>,
+        mtime: Option<SetTime>,
+    ) -> Result<()> {
+        // Flushed first, or the data landing later would move mtime again
+        self.flush(handle)?;
+        self.inner.setattr_times(handle, atime, mtime)
+    }
+
+    fn create(
+        &self,
+        dir_handle: &FileHandle,
+        name: &str,
+        mode: u32,
+        how: CreateMode,
+    ) -> Result<FileHandle> {
+        self.flush_all();
+        self.inner.create(dir_handle, name, mode, how)
+    }
+
+    fn remove(&self, dir_handle: &FileHandle, name: &str) -> Result<()> {
+        self.flush_all();
+        self.inner.remove(dir_handle, name)
+    }
+
+    fn mkdir(&self, dir_handle: &FileHandle, name: &str, mode: u32) -> Result<FileHandle> {
+        self.inner.mkdir(dir_handle, name, mode)
+    }
+
+    fn rmdir(&self, dir_handle: &FileHandle, name: &str) -> Result<()> {
+        self.inner.rmdir(dir_handle, name)
+    }
+
+    fn rename(
+        &self,
+        from_dir_handle: &FileHandle,
+        from_name: &str,
+        to_dir_handle: &FileHandle,
+        to_name: &str,
+    ) -> Result<()> {
+        // Buffered handles may name the renamed file or the one it replaces
+        self.flush_all();
+        self.inner.rename(from_dir_handle, from_name, to_dir_handle, to_name)
+    }
+
+    fn symlink(&self, dir_handle: &FileHandle, name: &str, target: &str) -> Result<FileHandle> {
+        self.inner.symlink(dir_handle, name, target)
+    }
+
+    fn readlink(&self, handle: &FileHandle) -> Result<String> {
+        self.inner.readlink(handle)
+    }
+
+    fn link(&self, file_handle: &FileHandle, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
+        self.flush(file_handle)?;
+        self.inner.link(file_handle, dir_handle, name)
+    }
+
+    fn commit(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<()> {
+        let flushed = self.flush(handle);
+        if let Some(error) = self.buffers.lock().unwrap().failed.remove(handle) {
+            // Data already acknowledged is gone, whatever the cause: EIO
+            return Err(anyhow::Error::new(std::io::Error::from_raw_os_error(libc::EIO))
+                .context(format!("Buffered write failed: {}", error)));
+        }
+        flushed?;
+        self.inner.commit(handle, offset, count)
+    }
+
+    fn mknod(
+        &self,
+        dir_handle: &FileHandle,
+        name: &str,
+        file_type: FileType,
+        mode: u32,
+        rdev: (u32, u32),
+    ) -> Result<FileHandle> {
+        self.inner.mknod(dir_handle, name, file_type, mode, rdev)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use std::fs;
+    use tempfile::TempDir;
+
+    fn coalescing_fs(temp_dir: &TempDir, threshold: usize) -> CoalescingFilesystem {
+        let inner = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        CoalescingFilesystem::new(inner, threshold)
+    }
+
+    #[test]
+    fn test_contiguous_writes_held_until_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file");
+        fs::write(&path, b"").unwrap();
+        let fs = coalescing_fs(&temp_dir, 1024);
+        let file = fs.lookup(&fs.root_handle(), "file").unwrap();
+
+        for offset in (0..40).step_by(4) {
+            assert_eq!(fs.write(&file, offset, b"abcd").unwrap(), 4);
+        }
+        // Nothing on disk yet, but GETATTR already shows the new size
+        assert_eq!(fs::read(&path).unwrap().len(), 0);
+        assert_eq!(fs.getattr(&file).unwrap().size, 40);
+
+        fs.commit(&file, 0, 0).unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"abcd".repeat(10));
+    }
+
+    #[test]
+    fn test_flush_triggers() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file");
+        fs::write(&path, b"").unwrap();
+        let fs = coalescing_fs(&temp_dir, 8);
+        let file = fs.lookup(&fs.root_handle(), "file").unwrap();
+
+        // Reaching the threshold
+        fs.write(&file, 0, b"abcd").unwrap();
+        fs.write(&file, 4, b"efgh").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"abcdefgh");
+
+        // A write elsewhere flushes the run before it
+        fs.write(&file, 8, b"ij").unwrap();
+        fs.write(&file, 20, b"kl").unwrap();
+        assert_eq!(fs::read(&path).unwrap().len(), 10);
+
+        // READ sees everything written
+        let data = fs.read(&file, 0, 100).unwrap();
+        assert_eq!(data.len(), 22);
+        assert_eq!(&data[20..], b"kl");
+
+        // Namespace changes flush too
+        fs.write(&file, 22, b"mn").unwrap();
+        fs.rename(&fs.root_handle(), "file", &fs.root_handle(), "renamed").unwrap();
+        assert_eq!(fs::read(temp_dir.path().join("renamed")).unwrap().len(), 24);
+    }
+
+    #[test]
+    fn test_failed_flush_reported_by_commit() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file"), b"").unwrap();
+        let fs = coalescing_fs(&temp_dir, 1024);
+        let file = fs.lookup(&fs.root_handle(), "file").unwrap();
+
+        // The file is replaced behind the server's back while data is held
+        fs.write(&file, 0, b"data").unwrap();
+        fs::remove_file(temp_dir.path().join("file")).unwrap();
+        fs::create_dir(temp_dir.path().join("file")).unwrap();
+        let error = fs.commit(&file, 0, 0).unwrap_err();
+        assert_eq!(
+            crate::nfs::io_error_to_nfsstat(&error),
+            Some(crate::protocol::v3::nfs::nfsstat3::NFS3ERR_IO)
+        );
+    }
+}
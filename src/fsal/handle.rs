@@ -1,116 +1,284 @@
 // File Handle Management
 //
 // File handles are opaque identifiers used by NFS to reference files/directories.
-// This module manages the bidirectional mapping between file handles and paths.
+// This module derives handles from the objects they name and maps them back
+// to paths.
+//
+// Handle layout (big-endian):
+//   [0..4]   export id, derived from the device and inode of the export root
+//            and the generation of the server instance
+//   [4..8]   generation of the object (its birth time, folded), so a reused
+//            inode number does not revive the handles of a removed object
+//   [8..16]  inode number of the object
+//   [16..24] hash of the object's path below the export root, when handles
+//            are tied to their path (subtree checking); otherwise padding
+//   [..]     zero padding up to the configured length
+//
+// A handle is only good with the server instance that issued it: one not in
+// the map from handle to path is stale, without searching the export for its
+// object, so a client can't make the server walk the export by sending made
+// up handles. Clients get STALE for the handles of a previous instance and
+// look the paths up again.
 
+use anyhow::{anyhow, Result};
 use std::collections::HashMap;
-use std::path::PathBuf;
-use std::sync::{Arc, RwLock};
+use std::fs::Metadata;
+use std::os::unix::ffi::OsStrExt;
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, OnceLock, RwLock};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use super::os_error;
 
 /// File handle type (opaque bytes)
 pub type FileHandle = Vec<u8>;
 
+/// Shortest handle length: export id, generation and inode number
+pub const MIN_HANDLE_LEN: usize = 16;
+
+/// Longest handle length allowed by NFSv3 (NFS3_FHSIZE)
+pub const MAX_HANDLE_LEN: usize = 64;
+
+/// Handle length used unless configured otherwise
+pub const DEFAULT_HANDLE_LEN: usize = 32;
+
+/// Version of the handle layout above, bumped whenever it changes
+pub const HANDLE_FORMAT_VERSION: u32 = 3;
+
+/// Shortest handle length with room for the path hash of subtree checking
+pub const MIN_SUBTREE_CHECK_HANDLE_LEN: usize = 24;
+
 /// File handle manager
 ///
-/// Maintains the mapping between file handles and filesystem paths.
+/// Issues the handle of an object and caches the path it was found at.
+/// Every handle it issues has the same length, zero-padded after the fields
+/// above, since some clients mishandle variable-length handles.
 /// Thread-safe for concurrent access.
 #[derive(Clone)]
 pub struct HandleManager {
-    /// Map from file handle to path
+    /// Map from file handle to the path its object was last seen at
     handle_to_path: Arc<RwLock<HashMap<FileHandle, PathBuf>>>,
-    /// Map from path to file handle (for quick lookups)
-    path_to_handle: Arc<RwLock<HashMap<PathBuf, FileHandle>>>,
-    /// Counter for generating unique handles
-    next_id: Arc<RwLock<u64>>,
+    /// Export root, which path hashes are relative to
+    root: Option<PathBuf>,
+    /// Generation of the server instance, mixed into the export id
+    instance: u32,
+    /// Export id embedded in every issued handle
+    export_id: u32,
+    /// Length of every issued handle in bytes
+    handle_len: usize,
+    /// Whether handles carry the hash of their object's path
+    path_hashes: bool,
 }
 
 impl HandleManager {
-    /// Create a new handle manager
+    /// Create a new handle manager, with no export root
     pub fn new() -> Self {
         Self {
             handle_to_path: Arc::new(RwLock::new(HashMap::new())),
-            path_to_handle: Arc::new(RwLock::new(HashMap::new())),
-            next_id: Arc::new(RwLock::new(1)), // Start from 1 (0 could be reserved)
+            root: None,
+            instance: instance_generation(),
+            export_id: 1,
+            handle_len: DEFAULT_HANDLE_LEN,
+            path_hashes: false,
         }
     }
 
-    /// Generate a new file handle for a path
+    /// Create a handle manager issuing handles of `handle_len` bytes
     ///
-    /// If the path already has a handle, return the existing one.
-    /// Otherwise, create a new handle.
-    pub fn create_handle(&self, path: PathBuf) -> FileHandle {
-        // Check if path already has a handle
-        {
-            let path_map = self.path_to_handle.read().unwrap();
-            if let Some(handle) = path_map.get(&path) {
-                return handle.clone();
-            }
+    /// The length must lie within `MIN_HANDLE_LEN..=MAX_HANDLE_LEN`.
+    pub fn with_handle_len(handle_len: usize) -> Result<Self> {
+        if !(MIN_HANDLE_LEN..=MAX_HANDLE_LEN).contains(&handle_len) {
+            return Err(anyhow!(
+                "File handle length {} out of range ({}..={} bytes)",
+                handle_len,
+                MIN_HANDLE_LEN,
+                MAX_HANDLE_LEN
+            ));
         }
+        Ok(Self {
+            handle_len,
+            ..Self::new()
+        })
+    }
 
-        // Generate new handle
-        let id = {
-            let mut next_id = self.next_id.write().unwrap();
-            let current = *next_id;
-            *next_id += 1;
-            current
-        };
+    /// Issue handles for the export rooted at `root`
+    ///
+    /// The export id comes from the root's device and inode, so it differs
+    /// between exports, and from the server instance, so it differs between
+    /// restarts.
+    pub fn with_root(mut self, root: &Path) -> Result<Self> {
+        let metadata = std::fs::metadata(root)?;
+        let id = metadata.dev() ^ metadata.ino().rotate_left(32);
+        self.export_id = (((id ^ (id >> 32)) as u32) ^ self.instance).max(1);
+        self.root = Some(root.to_path_buf());
+        Ok(self)
+    }
+
+    /// Tie each handle to the path of its object, so a handle goes stale
+    /// once its object is moved elsewhere
+    ///
+    /// Needs handles of at least `MIN_SUBTREE_CHECK_HANDLE_LEN` bytes.
+    pub fn with_path_hashes(mut self) -> Result<Self> {
+        if self.handle_len < MIN_SUBTREE_CHECK_HANDLE_LEN {
+            return Err(anyhow!(
+                "File handle length {} leaves no room for a path hash (at least {} bytes)",
+                self.handle_len,
+                MIN_SUBTREE_CHECK_HANDLE_LEN
+            ));
+        }
+        self.path_hashes = true;
+        Ok(self)
+    }
+
+    /// The inode number embedded in `handle`
+    pub fn fileid(&self, handle: &[u8]) -> Option<u64> {
+        Some(u64::from_be_bytes(handle.get(8..16)?.try_into().ok()?))
+    }
+
+    /// Length of every handle issued by this manager
+    pub fn handle_len(&self) -> usize {
+        self.handle_len
+    }
+
+    /// Export id embedded in the handles issued by this manager
+    pub fn export_id(&self) -> u32 {
+        self.export_id
+    }
+
+    /// Get the file handle for a path
+    ///
+    /// The handle is derived from the object at `path`, so the same object
+    /// always gets the same handle, and another object at the same path
+    /// another handle. An object with several hard links keeps the path it
+    /// was first seen at while that still leads to it.
+    pub fn create_handle(&self, path: PathBuf) -> FileHandle {
+        let metadata = std::fs::symlink_metadata(&path).ok();
+        let handle = self.encode(&path, metadata.as_ref());
 
-        // Create handle from ID (32 bytes with ID in first 8 bytes)
-        let mut handle = vec![0u8; 32];
-        handle[0..8].copy_from_slice(&id.to_be_bytes());
-
-        // Store path hash in bytes 8-16 for verification
-        use std::collections::hash_map::DefaultHasher;
-        use std::hash::{Hash, Hasher};
-        let mut hasher = DefaultHasher::new();
-        path.hash(&mut hasher);
-        let path_hash = hasher.finish();
-        handle[8..16].copy_from_slice(&path_hash.to_be_bytes());
-
-        // Store mappings
-        {
-            let mut handle_map = self.handle_to_path.write().unwrap();
-            let mut path_map = self.path_to_handle.write().unwrap();
-
-            handle_map.insert(handle.clone(), path.clone());
-            path_map.insert(path.clone(), handle.clone());
+        let known = self.lookup_path(&handle).filter(|known| *known != path);
+        let still_there = |known: PathBuf| {
+            std::fs::symlink_metadata(&known)
+                .is_ok_and(|metadata| self.names(&handle, &known, &metadata))
+        };
+        if known.is_some_and(still_there) {
+            return handle;
         }
+        self.handle_to_path
+            .write()
+            .unwrap()
+            .insert(handle.clone(), path.clone());
 
         tracing::debug!("Created file handle for path: {:?}", path);
         handle
     }
 
-    /// Look up the path for a file handle
+    /// Whether `handle` names the object at `path`, whose metadata is given
+    pub fn names(&self, handle: &[u8], path: &Path, metadata: &Metadata) -> bool {
+        handle == self.encode(path, Some(metadata)).as_slice()
+    }
+
+    /// Look up the cached path for a file handle
+    ///
+    /// Handles of any other length than the configured one never match.
     pub fn lookup_path(&self, handle: &FileHandle) -> Option<PathBuf> {
         let handle_map = self.handle_to_path.read().unwrap();
         handle_map.get(handle).cloned()
     }
 
-    /// Check if a file handle exists
+    /// Decode a handle received from a client into a path
+    ///
+    /// That is the path its object was last seen at. Handles of the wrong
+    /// length, of another export or server instance, and handles this
+    /// manager never issued are rejected as stale, all without touching the
+    /// filesystem. The caller checks that the path still holds the object.
+    pub fn decode(&self, handle: &[u8]) -> Result<PathBuf> {
+        if handle.len() != self.handle_len {
+            return Err(os_error(
+                libc::ESTALE,
+                format!(
+                    "Stale file handle: length {} (expected {})",
+                    handle.len(),
+                    self.handle_len
+                ),
+            ));
+        }
+
+        let export_id = u32::from_be_bytes(handle[0..4].try_into().unwrap());
+        if export_id != self.export_id {
+            return Err(os_error(
+                libc::ESTALE,
+                format!(
+                    "Stale file handle: export {:#010x} (this export is {:#010x})",
+                    export_id, self.export_id
+                ),
+            ));
+        }
+
+        self.handle_to_path
+            .read()
+            .unwrap()
+            .get(handle)
+            .cloned()
+            .ok_or_else(|| {
+                os_error(
+                    libc::ESTALE,
+                    "Stale file handle: not issued by this server instance".to_string(),
+                )
+            })
+    }
+
+    /// Follow a rename of `from` to `to`: the handles of the object at `from`
+    /// and, for a directory, of everything below it now lead to the new path
+    pub fn rename_path(&self, from: &Path, to: &Path) {
+        let mut handle_map = self.handle_to_path.write().unwrap();
+        for path in handle_map.values_mut() {
+            if let Ok(rest) = path.strip_prefix(from) {
+                *path = if rest.as_os_str().is_empty() {
+                    to.to_path_buf()
+                } else {
+                    to.join(rest)
+                };
+            }
+        }
+    }
+
+    /// Check if a file handle is cached
     pub fn is_valid(&self, handle: &FileHandle) -> bool {
         let handle_map = self.handle_to_path.read().unwrap();
         handle_map.contains_key(handle)
     }
 
-    /// Remove a file handle (e.g., when file is deleted)
+    /// Forget the cached path of a file handle (e.g., when file is deleted)
     pub fn remove_handle(&self, handle: &FileHandle) -> Option<PathBuf> {
-        let mut handle_map = self.handle_to_path.write().unwrap();
-        let mut path_map = self.path_to_handle.write().unwrap();
-
-        if let Some(path) = handle_map.remove(handle) {
-            path_map.remove(&path);
+        let path = self.handle_to_path.write().unwrap().remove(handle);
+        if let Some(path) = &path {
             tracing::debug!("Removed file handle for path: {:?}", path);
-            Some(path)
-        } else {
-            None
         }
+        path
     }
 
-    /// Get total number of handles
+    /// Get total number of cached handles
     pub fn count(&self) -> usize {
         let handle_map = self.handle_to_path.read().unwrap();
         handle_map.len()
     }
+
+    /// The handle of the object at `path`; an object that cannot be stat'ed
+    /// gets inode number 0
+    fn encode(&self, path: &Path, metadata: Option<&Metadata>) -> FileHandle {
+        let mut handle = vec![0u8; self.handle_len];
+        handle[0..4].copy_from_slice(&self.export_id.to_be_bytes());
+        if let Some(metadata) = metadata {
+            handle[4..8].copy_from_slice(&object_generation(metadata).to_be_bytes());
+            handle[8..16].copy_from_slice(&metadata.ino().to_be_bytes());
+        }
+        if self.path_hashes {
+            let relative = self.root.as_deref().and_then(|root| path.strip_prefix(root).ok());
+            handle[16..24].copy_from_slice(&path_hash(relative.unwrap_or(path)).to_be_bytes());
+        }
+        handle
+    }
 }
 
 impl Default for HandleManager {
@@ -119,14 +287,62 @@ impl Default for HandleManager {
     }
 }
 
+/// Generation of this server instance: its start time folded into 32 bits,
+/// shared by every manager of the instance
+fn instance_generation() -> u32 {
+    static GENERATION: OnceLock<u32> = OnceLock::new();
+    *GENERATION.get_or_init(|| {
+        let nanos = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|d| d.as_nanos() as u64)
+            .unwrap_or(0);
+        (nanos ^ (nanos >> 32)) as u32
+    })
+}
+
+/// Generation of an object: its birth time folded into 32 bits, or 0 where
+/// the filesystem doesn't record one
+fn object_generation(metadata: &Metadata) -> u32 {
+    metadata
+        .created()
+        .ok()
+        .and_then(|created| created.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs() as u32 ^ d.subsec_nanos().rotate_left(16))
+        .unwrap_or(0)
+}
+
+/// Hash of a path, embedded in its handle
+///
+/// FNV-1a rather than the std hasher, whose output may change between
+/// releases and would turn every handle stale after an upgrade.
+fn path_hash(path: &Path) -> u64 {
+    path.as_os_str()
+        .as_bytes()
+        .iter()
+        .fold(0xcbf2_9ce4_8422_2325, |hash, &byte| {
+            (hash ^ byte as u64).wrapping_mul(0x0000_0100_0000_01b3)
+        })
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use tempfile::TempDir;
+
+    /// A manager for an export holding `names` as files
+    fn export(names: &[&str]) -> (HandleManager, TempDir) {
+        let temp_dir = TempDir::new().unwrap();
+        for name in names {
+            std::fs::write(temp_dir.path().join(name), name.as_bytes()).unwrap();
+        }
+        let manager = HandleManager::new().with_root(temp_dir.path()).unwrap();
+        (manager, temp_dir)
+    }
 
     #[test]
     fn test_create_and_lookup() {
-        let manager = HandleManager::new();
-        let path = PathBuf::from("/test/file.txt");
+        let (manager, temp_dir) = export(&["file.txt"]);
+        let path = temp_dir.path().join("file.txt");
 
         let handle = manager.create_handle(path.clone());
         assert_eq!(manager.lookup_path(&handle), Some(path));
@@ -134,8 +350,8 @@ mod tests {
 
     #[test]
     fn test_idempotent_create() {
-        let manager = HandleManager::new();
-        let path = PathBuf::from("/test/file.txt");
+        let (manager, temp_dir) = export(&["file.txt"]);
+        let path = temp_dir.path().join("file.txt");
 
         let handle1 = manager.create_handle(path.clone());
         let handle2 = manager.create_handle(path.clone());
@@ -145,8 +361,8 @@ mod tests {
 
     #[test]
     fn test_remove_handle() {
-        let manager = HandleManager::new();
-        let path = PathBuf::from("/test/file.txt");
+        let (manager, temp_dir) = export(&["file.txt"]);
+        let path = temp_dir.path().join("file.txt");
 
         let handle = manager.create_handle(path.clone());
         assert!(manager.is_valid(&handle));
@@ -155,4 +371,165 @@ mod tests {
         assert_eq!(removed_path, Some(path));
         assert!(!manager.is_valid(&handle));
     }
+
+    #[test]
+    fn test_fixed_handle_length() {
+        let names: Vec<String> = (0..10).map(|i| format!("f{}", "x".repeat(i * 20))).collect();
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let (_, temp_dir) = export(&names);
+        for len in [MIN_HANDLE_LEN, DEFAULT_HANDLE_LEN, MAX_HANDLE_LEN] {
+            let manager = HandleManager::with_handle_len(len).unwrap();
+            for name in &names {
+                let path = temp_dir.path().join(name);
+                let handle = manager.create_handle(path.clone());
+                assert_eq!(handle.len(), len);
+                assert_eq!(manager.lookup_path(&handle), Some(path));
+
+                // Truncated or further padded handles are not accepted
+                assert_eq!(manager.lookup_path(&handle[..len - 1].to_vec()), None);
+                let mut padded = handle.clone();
+                padded.push(0);
+                assert_eq!(manager.lookup_path(&padded), None);
+            }
+        }
+    }
+
+    #[test]
+    fn test_embedded_fileids() {
+        let (manager, temp_dir) = export(&["file"]);
+        let path = temp_dir.path().join("file");
+        let ino = std::fs::metadata(&path).unwrap().ino();
+
+        let handle = manager.create_handle(path.clone());
+        assert_eq!(manager.fileid(&handle), Some(ino));
+
+        // Another file at the same path gets a handle of its own; the old
+        // one no longer decodes once its file is gone
+        let other = temp_dir.path().join("other");
+        std::fs::write(&other, b"two").unwrap();
+        std::fs::rename(&other, &path).unwrap();
+        let new_handle = manager.create_handle(path.clone());
+        assert_ne!(new_handle, handle);
+        assert_ne!(manager.fileid(&new_handle), Some(ino));
+        assert_eq!(manager.decode(&new_handle).unwrap(), path);
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(!manager.names(&handle, &path, &metadata));
+    }
+
+    #[test]
+    fn test_path_hashes() {
+        let (_, temp_dir) = export(&["file"]);
+        let path = temp_dir.path().join("file");
+
+        let short = HandleManager::with_handle_len(16).unwrap();
+        assert!(short.with_root(temp_dir.path()).unwrap().with_path_hashes().is_err());
+        let manager = HandleManager::new()
+            .with_root(temp_dir.path())
+            .unwrap()
+            .with_path_hashes()
+            .unwrap();
+        let handle = manager.create_handle(path.clone());
+        let metadata = std::fs::symlink_metadata(&path).unwrap();
+        assert!(manager.names(&handle, &path, &metadata));
+
+        // The same file under another name is not what the handle names
+        let moved = temp_dir.path().join("moved");
+        std::fs::rename(&path, &moved).unwrap();
+        assert!(!manager.names(&handle, &moved, &metadata));
+    }
+
+    #[test]
+    fn test_decode() {
+        let (manager, temp_dir) = export(&["file.txt"]);
+        let path = temp_dir.path().join("file.txt");
+        let handle = manager.create_handle(path.clone());
+
+        assert_eq!(manager.decode(&handle).unwrap(), path);
+        assert_eq!(
+            u32::from_be_bytes(handle[0..4].try_into().unwrap()),
+            manager.export_id()
+        );
+    }
+
+    #[test]
+    fn test_decode_rejects_previous_instance() {
+        let (old, temp_dir) = export(&["file.txt"]);
+        let path = temp_dir.path().join("file.txt");
+        let handle = old.create_handle(path.clone());
+
+        // A restarted server has another generation, so another export id
+        let new = HandleManager {
+            instance: old.instance.wrapping_add(1),
+            ..HandleManager::new()
+        }
+        .with_root(temp_dir.path())
+        .unwrap();
+        assert_ne!(new.export_id(), old.export_id());
+        new.create_handle(path);
+        let err = new.decode(&handle).unwrap_err();
+        assert!(err.to_string().contains("Stale file handle"));
+
+        // Another export's manager doesn't take it either
+        let (other, _other_dir) = export(&[]);
+        let err = other.decode(&handle).unwrap_err();
+        assert!(err.to_string().contains("Stale file handle"));
+    }
+
+    #[test]
+    fn test_decode_unknown_handle_stale() {
+        let (issuer, temp_dir) = export(&["file.txt"]);
+        let handle = issuer.create_handle(temp_dir.path().join("file.txt"));
+
+        // Same export and instance, but never issued by this manager: stale,
+        // rather than searched for
+        let manager = HandleManager::new().with_root(temp_dir.path()).unwrap();
+        assert_eq!(manager.export_id(), issuer.export_id());
+        let err = manager.decode(&handle).unwrap_err();
+        assert!(err.to_string().contains("Stale file handle"));
+        assert_eq!(manager.count(), 0);
+    }
+
+    #[test]
+    fn test_rename_path() {
+        let (manager, temp_dir) = export(&["file.txt"]);
+        std::fs::create_dir(temp_dir.path().join("dir")).unwrap();
+        std::fs::write(temp_dir.path().join("dir/inner"), b"inner").unwrap();
+        let file = manager.create_handle(temp_dir.path().join("file.txt"));
+        let dir = manager.create_handle(temp_dir.path().join("dir"));
+        let inner = manager.create_handle(temp_dir.path().join("dir/inner"));
+
+        manager.rename_path(&temp_dir.path().join("dir"), &temp_dir.path().join("moved"));
+        assert_eq!(manager.decode(&dir).unwrap(), temp_dir.path().join("moved"));
+        assert_eq!(manager.decode(&inner).unwrap(), temp_dir.path().join("moved/inner"));
+        assert_eq!(manager.decode(&file).unwrap(), temp_dir.path().join("file.txt"));
+    }
+
+    #[test]
+    fn test_decode_rejects_forged_handles() {
+        let (manager, temp_dir) = export(&["file.txt"]);
+        let handle = manager.create_handle(temp_dir.path().join("file.txt"));
+
+        // Wrong length
+        assert!(manager.decode(&[0xDE, 0xAD, 0xBE, 0xEF]).is_err());
+        assert!(manager.decode(&handle[..MIN_HANDLE_LEN]).is_err());
+
+        // Right export, but no such inode
+        let mut forged = handle.clone();
+        forged[8..16].copy_from_slice(&u64::MAX.to_be_bytes());
+        assert!(manager.decode(&forged).is_err());
+
+        // Right inode, but padding set
+        let mut forged = handle.clone();
+        forged[DEFAULT_HANDLE_LEN - 1] = 1;
+        assert!(manager.decode(&forged).is_err());
+
+        // All zeroes
+        assert!(manager.decode(&vec![0u8; DEFAULT_HANDLE_LEN]).is_err());
+    }
+
+    #[test]
+    fn test_handle_length_out_of_range() {
+        assert!(HandleManager::with_handle_len(MIN_HANDLE_LEN - 1).is_err());
+        assert!(HandleManager::with_handle_len(MAX_HANDLE_LEN + 1).is_err());
+    }
 }
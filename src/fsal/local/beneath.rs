@@ -0,0 +1,397 @@
+// Path Resolution Beneath the Export Root
+//
+// Handles name paths, and a path is only as good as the directories along
+// it: a client can RENAME a directory away and SYMLINK its old name to
+// /etc, after which the path of every handle issued below it leads out of
+// the export. So paths are never handed to the kernel whole. Each is walked
+// from a descriptor of the export root, one component at a time with
+// O_NOFOLLOW, and the object is then acted on through a descriptor or an
+// *at syscall relative to the directory holding it.
+
+use std::ffi::{CStr, CString, OsStr, OsString};
+use std::fs;
+use std::io;
+use std::os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, IntoRawFd, OwnedFd};
+use std::os::unix::ffi::{OsStrExt, OsStringExt};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// The export root, held open
+pub(super) struct Root {
+    dir: OwnedFd,
+    path: PathBuf,
+}
+
+/// An object of the export, as the directory holding it and its name there
+///
+/// The export root is "." in itself.
+pub(super) struct Entry {
+    pub dir: OwnedFd,
+    pub name: CString,
+}
+
+impl Root {
+    /// Hold the directory at `path`, which must be canonical
+    pub fn new(path: &Path) -> io::Result<Self> {
+        let c_path = CString::new(path.as_os_str().as_bytes())?;
+        let fd = unsafe {
+            libc::open(
+                c_path.as_ptr(),
+                libc::O_PATH | libc::O_DIRECTORY | libc::O_CLOEXEC,
+            )
+        };
+        Ok(Self {
+            dir: owned(fd)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    /// Walk to the directory holding the object at `path`
+    ///
+    /// Fails if `path` is not below the root, has "." or ".." components, or
+    /// passes through anything but a directory, symlinks included.
+    pub fn entry(&self, path: &Path) -> io::Result<Entry> {
+        let relative = path.strip_prefix(&self.path).map_err(|_| outside(path))?;
+        let mut names = Vec::new();
+        for component in relative.components() {
+            match component {
+                Component::Normal(name) => names.push(CString::new(name.as_bytes())?),
+                _ => return Err(outside(path)),
+            }
+        }
+
+        let mut dir = self.dir.try_clone()?;
+        let Some(name) = names.pop() else {
+            // The descriptor outlives a removed root, which would otherwise
+            // go on answering for an export that is gone
+            if fs::File::from(dir.try_clone()?).metadata()?.nlink() == 0 {
+                return Err(io::Error::from_raw_os_error(libc::ENOENT));
+            }
+            return Ok(Entry {
+                dir,
+                name: c".".to_owned(),
+            });
+        };
+        for component in &names {
+            dir = openat(dir.as_fd(), component, libc::O_PATH | libc::O_DIRECTORY, 0)?;
+        }
+        Ok(Entry { dir, name })
+    }
+
+    /// Walk to the object at `path` as `entry` does, and lstat it
+    pub fn reach(&self, path: &Path) -> io::Result<(Entry, fs::Metadata)> {
+        let entry = self.entry(path)?;
+        let metadata = entry.symlink_metadata()?;
+        Ok((entry, metadata))
+    }
+}
+
+impl Entry {
+    /// Open the object with `flags`; a symlink is opened itself with O_PATH
+    /// and fails with ELOOP otherwise
+    pub fn open(&self, flags: libc::c_int) -> io::Result<fs::File> {
+        openat(self.dir.as_fd(), &self.name, flags, 0).map(fs::File::from)
+    }
+
+    /// The object's own attributes, a symlink's included
+    pub fn symlink_metadata(&self) -> io::Result<fs::Metadata> {
+        self.open(libc::O_PATH)?.metadata()
+    }
+
+    /// The entry `name` of this directory
+    pub fn child(&self, name: &str) -> io::Result<Entry> {
+        Ok(Entry {
+            dir: self.open(libc::O_PATH | libc::O_DIRECTORY)?.into(),
+            name: CString::new(name)?,
+        })
+    }
+
+    /// The entry `name` of the directory holding this object
+    pub fn sibling(&self, name: &str) -> io::Result<Entry> {
+        Ok(Entry {
+            dir: self.dir.try_clone()?,
+            name: CString::new(name)?,
+        })
+    }
+
+    /// The entries of this directory, "." and ".." left out, in the order
+    /// the directory lists them
+    pub fn read_dir(&self) -> io::Result<Vec<Entry>> {
+        let dir: OwnedFd = self.open(libc::O_RDONLY | libc::O_DIRECTORY)?.into();
+        let listed = dir.try_clone()?.into_raw_fd();
+        let stream = unsafe { libc::fdopendir(listed) };
+        if stream.is_null() {
+            let err = io::Error::last_os_error();
+            unsafe { libc::close(listed) };
+            return Err(err);
+        }
+
+        let mut entries = Vec::new();
+        let result = loop {
+            // readdir reports errors only through errno
+            unsafe { *libc::__errno_location() = 0 };
+            let dirent = unsafe { libc::readdir64(stream) };
+            if dirent.is_null() {
+                let err = io::Error::last_os_error();
+                break if err.raw_os_error() == Some(0) { Ok(()) } else { Err(err) };
+            }
+            let name = unsafe { CStr::from_ptr((*dirent).d_name.as_ptr()) };
+            if name != c"." && name != c".." {
+                match dir.try_clone() {
+                    Ok(dir) => entries.push(Entry {
+                        dir,
+                        name: name.to_owned(),
+                    }),
+                    Err(e) => break Err(e),
+                }
+            }
+        };
+        unsafe { libc::closedir(stream) };
+        result.map(|()| entries)
+    }
+
+    /// Create a regular file, opened with `flags`
+    pub fn create(&self, flags: libc::c_int, mode: u32) -> io::Result<fs::File> {
+        openat(self.dir.as_fd(), &self.name, flags | libc::O_CREAT, mode).map(fs::File::from)
+    }
+
+    /// Create a directory
+    pub fn mkdir(&self, mode: u32) -> io::Result<()> {
+        check(unsafe { libc::mkdirat(self.dir.as_raw_fd(), self.name.as_ptr(), mode as libc::mode_t) })
+    }
+
+    /// Create a special file: FIFO, device or socket, by the type in `mode`
+    pub fn mknod(&self, mode: u32, dev: libc::dev_t) -> io::Result<()> {
+        check(unsafe {
+            libc::mknodat(self.dir.as_raw_fd(), self.name.as_ptr(), mode as libc::mode_t, dev)
+        })
+    }
+
+    /// Create a symlink to `target`
+    pub fn symlink(&self, target: &str) -> io::Result<()> {
+        let target = CString::new(target)?;
+        check(unsafe { libc::symlinkat(target.as_ptr(), self.dir.as_raw_fd(), self.name.as_ptr()) })
+    }
+
+    /// The target of the symlink
+    pub fn read_link(&self) -> io::Result<OsString> {
+        let mut buffer = vec![0u8; libc::PATH_MAX as usize];
+        let len = unsafe {
+            libc::readlinkat(
+                self.dir.as_raw_fd(),
+                self.name.as_ptr(),
+                buffer.as_mut_ptr().cast(),
+                buffer.len(),
+            )
+        };
+        if len < 0 {
+            return Err(io::Error::last_os_error());
+        }
+        buffer.truncate(len as usize);
+        Ok(OsString::from_vec(buffer))
+    }
+
+    /// Remove a non-directory
+    pub fn remove_file(&self) -> io::Result<()> {
+        check(unsafe { libc::unlinkat(self.dir.as_raw_fd(), self.name.as_ptr(), 0) })
+    }
+
+    /// Remove an empty directory
+    pub fn remove_dir(&self) -> io::Result<()> {
+        check(unsafe { libc::unlinkat(self.dir.as_raw_fd(), self.name.as_ptr(), libc::AT_REMOVEDIR) })
+    }
+
+    /// Rename the object to `to`, replacing what is there
+    pub fn rename(&self, to: &Entry) -> io::Result<()> {
+        check(unsafe {
+            libc::renameat(
+                self.dir.as_raw_fd(),
+                self.name.as_ptr(),
+                to.dir.as_raw_fd(),
+                to.name.as_ptr(),
+            )
+        })
+    }
+
+    /// Give the object, a symlink itself, the further name `to`
+    pub fn hard_link(&self, to: &Entry) -> io::Result<()> {
+        check(unsafe {
+            libc::linkat(
+                self.dir.as_raw_fd(),
+                self.name.as_ptr(),
+                to.dir.as_raw_fd(),
+                to.name.as_ptr(),
+                0,
+            )
+        })
+    }
+
+    /// chmod the object; fails with EOPNOTSUPP for a symlink, whose mode
+    /// Linux does not keep
+    pub fn set_mode(&self, mode: u32) -> io::Result<()> {
+        check(unsafe {
+            libc::fchmodat(
+                self.dir.as_raw_fd(),
+                self.name.as_ptr(),
+                mode as libc::mode_t,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+
+    /// chown the object, a symlink itself; None leaves that id alone
+    pub fn set_owner(&self, uid: Option<u32>, gid: Option<u32>) -> io::Result<()> {
+        // -1 is "unchanged" to chown(2)
+        let uid = uid.unwrap_or(u32::MAX);
+        let gid = gid.unwrap_or(u32::MAX);
+        check(unsafe {
+            libc::fchownat(
+                self.dir.as_raw_fd(),
+                self.name.as_ptr(),
+                uid,
+                gid,
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+
+    /// Set the access and modification times of the object, a symlink
+    /// itself; None leaves that time alone
+    pub fn set_times(&self, atime: Option<SystemTime>, mtime: Option<SystemTime>) -> io::Result<()> {
+        let timespec = |time: Option<SystemTime>| -> io::Result<libc::timespec> {
+            let Some(time) = time else {
+                return Ok(libc::timespec {
+                    tv_sec: 0,
+                    tv_nsec: libc::UTIME_OMIT,
+                });
+            };
+            let since_epoch = time
+                .duration_since(UNIX_EPOCH)
+                .map_err(|_| io::Error::from_raw_os_error(libc::EINVAL))?;
+            Ok(libc::timespec {
+                tv_sec: since_epoch.as_secs() as libc::time_t,
+                tv_nsec: since_epoch.subsec_nanos() as libc::c_long,
+            })
+        };
+        let times = [timespec(atime)?, timespec(mtime)?];
+        check(unsafe {
+            libc::utimensat(
+                self.dir.as_raw_fd(),
+                self.name.as_ptr(),
+                times.as_ptr(),
+                libc::AT_SYMLINK_NOFOLLOW,
+            )
+        })
+    }
+
+    /// The object's name in its directory
+    pub fn name(&self) -> &OsStr {
+        OsStr::from_bytes(self.name.as_bytes())
+    }
+}
+
+/// openat(2) `name` in `dir`, never following a symlink; `mode` is used
+/// only with O_CREAT
+fn openat(dir: BorrowedFd<'_>, name: &CStr, flags: libc::c_int, mode: u32) -> io::Result<OwnedFd> {
+    let fd = unsafe {
+        libc::openat(
+            dir.as_raw_fd(),
+            name.as_ptr(),
+            flags | libc::O_NOFOLLOW | libc::O_CLOEXEC,
+            mode as libc::c_uint,
+        )
+    };
+    owned(fd)
+}
+
+/// The result of a syscall returning 0 or -1 and errno
+fn check(rc: libc::c_int) -> io::Result<()> {
+    if rc != 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(())
+}
+
+/// Take ownership of the descriptor a syscall returned, or its error
+fn owned(fd: libc::c_int) -> io::Result<OwnedFd> {
+    if fd < 0 {
+        return Err(io::Error::last_os_error());
+    }
+    Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+}
+
+fn outside(path: &Path) -> io::Error {
+    io::Error::new(
+        io::ErrorKind::InvalidInput,
+        format!("Path is outside export root: {:?}", path),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_walk_refuses_symlinked_directory() {
+        let temp_dir = TempDir::new().unwrap();
+        let outside_dir = TempDir::new().unwrap();
+        let root_path = temp_dir.path().canonicalize().unwrap();
+        fs::create_dir(root_path.join("dir")).unwrap();
+        fs::write(root_path.join("dir/file"), b"inside").unwrap();
+        fs::write(outside_dir.path().join("file"), b"outside").unwrap();
+        std::os::unix::fs::symlink(outside_dir.path(), root_path.join("link")).unwrap();
+
+        let root = Root::new(&root_path).unwrap();
+        let metadata = |path: &Path| root.reach(path).map(|(_, metadata)| metadata);
+        assert!(metadata(&root_path).unwrap().is_dir());
+        assert!(metadata(&root_path.join("dir/file")).unwrap().is_file());
+
+        // The symlink itself is fine; going through it is not
+        assert!(metadata(&root_path.join("link")).unwrap().is_symlink());
+        let err = metadata(&root_path.join("link/file")).unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ENOTDIR));
+        let err = root
+            .entry(&root_path.join("link"))
+            .unwrap()
+            .open(libc::O_RDONLY)
+            .unwrap_err();
+        assert_eq!(err.raw_os_error(), Some(libc::ELOOP));
+
+        assert!(root.entry(&root_path.join("dir/../dir/file")).is_err());
+        assert!(root.entry(outside_dir.path()).is_err());
+    }
+
+    #[test]
+    fn test_entry_operations() {
+        let temp_dir = TempDir::new().unwrap();
+        let root_path = temp_dir.path().canonicalize().unwrap();
+        let root = Root::new(&root_path).unwrap();
+        let (top, _) = root.reach(&root_path).unwrap();
+
+        top.child("dir").unwrap().mkdir(0o755).unwrap();
+        let (dir, _) = root.reach(&root_path.join("dir")).unwrap();
+        let file = dir.child("file").unwrap();
+        file.create(libc::O_WRONLY | libc::O_EXCL, 0o644).unwrap();
+        file.sibling("link").unwrap().symlink("file").unwrap();
+        let names: Vec<_> = dir.read_dir().unwrap().iter().map(|e| e.name().to_owned()).collect();
+        assert_eq!(names.len(), 2);
+
+        // A symlink's own times are set, not its target's
+        let link = file.sibling("link").unwrap();
+        let epoch = UNIX_EPOCH + std::time::Duration::from_secs(1000);
+        link.set_times(None, Some(epoch)).unwrap();
+        assert_eq!(link.read_link().unwrap(), "file");
+        assert_eq!(link.symlink_metadata().unwrap().modified().unwrap(), epoch);
+        assert_ne!(file.symlink_metadata().unwrap().modified().unwrap(), epoch);
+        assert!(link.set_mode(0o600).is_err());
+
+        link.rename(&file.sibling("renamed").unwrap()).unwrap();
+        assert!(file.sibling("link").unwrap().symlink_metadata().is_err());
+        file.remove_file().unwrap();
+        assert!(dir.remove_dir().is_err(), "dir still holds \"renamed\"");
+        file.sibling("renamed").unwrap().remove_file().unwrap();
+        top.child("dir").unwrap().remove_dir().unwrap();
+    }
+}
@@ -2,24 +2,61 @@
 //
 // Implements the Filesystem trait for local filesystem access.
 
+mod beneath;
+mod page_cache;
+mod readahead;
+
 use anyhow::{anyhow, Context, Result};
 use std::fs;
 use std::io::{Read, Seek, SeekFrom, Write};
 use std::os::unix::fs::{MetadataExt, PermissionsExt};
 use std::path::{Path, PathBuf};
-use tracing::{debug, warn};
-
-use super::handle::{FileHandle, HandleManager};
-use super::{DirEntry, FileAttributes, FileTime, FileType, Filesystem};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tracing::{debug, info, warn};
+
+use self::beneath::{Entry, Root};
+use self::page_cache::IdlePages;
+use self::readahead::Readahead;
+pub use self::readahead::MAX_READAHEAD;
+use super::handle::{FileHandle, HandleManager, DEFAULT_HANDLE_LEN};
+use super::{
+    AtimePolicy, Capabilities, CreateMode, DirEntry, FileAttributes, FileTime, FileType, Filesystem, FsStat,
+    os_error, PathConf, SetTime,
+};
 
 /// Local filesystem implementation
 pub struct LocalFilesystem {
     /// Root directory for exports
     root_path: PathBuf,
+    /// The root directory held open, which every path is walked from
+    root: Root,
     /// File handle manager
     handle_manager: HandleManager,
     /// Root file handle
     root_handle: FileHandle,
+    /// Optional operations offered to clients
+    capabilities: Capabilities,
+    /// Prefetching for sequential reads, if enabled
+    readahead: Option<Readahead>,
+    /// Files read recently, whose cached pages are dropped once idle
+    idle_pages: Option<Arc<IdlePages>>,
+    /// Whether LOOKUP resolves symlinks to their targets
+    follow_symlinks: bool,
+    /// When READ updates access times
+    atime: AtimePolicy,
+    /// fsid reported for every object: derived from the root's device and
+    /// inode unless configured, so a filesystem mounted inside the export
+    /// doesn't look like a different export, while two exports of one
+    /// device don't look like the same one
+    fsid: u64,
+    /// Device of the export root, whose objects report their inode number
+    /// as fileid unchanged
+    root_dev: u64,
+    /// Whether each handle is checked to still name the object it was
+    /// issued for, inside the export
+    subtree_check: bool,
 }
 
 impl LocalFilesystem {
@@ -28,304 +65,657 @@ impl LocalFilesystem {
     /// # Arguments
     /// * `root_path` - Root directory to export (e.g., "/export")
     pub fn new<P: AsRef<Path>>(root_path: P) -> Result<Self> {
+        Self::with_handle_len(root_path, DEFAULT_HANDLE_LEN)
+    }
+
+    /// Create a new local filesystem issuing file handles of `handle_len` bytes
+    ///
+    /// Fails unless `root_path` is an existing, readable directory, so a
+    /// misconfigured export stops the server at startup rather than failing
+    /// every call with NFS3ERR_IO.
+    pub fn with_handle_len<P: AsRef<Path>>(root_path: P, handle_len: usize) -> Result<Self> {
         let root_path = root_path.as_ref().canonicalize().context(format!(
-            "Failed to canonicalize root path: {:?}",
+            "Export path {:?} does not exist or cannot be resolved",
             root_path.as_ref()
         ))?;
 
-        // Verify root path exists and is a directory
         let metadata = fs::metadata(&root_path)
-            .context(format!("Failed to stat root path: {:?}", root_path))?;
-
+            .context(format!("Failed to stat export path {:?}", root_path))?;
         if !metadata.is_dir() {
-            return Err(anyhow!("Root path is not a directory: {:?}", root_path));
+            return Err(anyhow!("Export path {:?} is not a directory", root_path));
         }
+        fs::read_dir(&root_path)
+            .context(format!("Export path {:?} is not readable", root_path))?;
 
-        let handle_manager = HandleManager::new();
+        let root = Root::new(&root_path)
+            .context(format!("Failed to open export path {:?}", root_path))?;
+        let handle_manager = HandleManager::with_handle_len(handle_len)?.with_root(&root_path)?;
 
         // Create root handle
         let root_handle = handle_manager.create_handle(root_path.clone());
 
-        debug!("LocalFilesystem created with root: {:?}", root_path);
+        info!("Local filesystem rooted at {}", root_path.display());
 
         Ok(Self {
             root_path,
+            root,
             handle_manager,
             root_handle,
+            capabilities: Capabilities::all(),
+            readahead: None,
+            idle_pages: None,
+            follow_symlinks: false,
+            atime: AtimePolicy::default(),
+            fsid: metadata.dev() ^ metadata.ino().rotate_left(32),
+            root_dev: metadata.dev(),
+            subtree_check: false,
         })
     }
 
-    /// Resolve a file handle to a full path
-    fn resolve_handle(&self, handle: &FileHandle) -> Result<PathBuf> {
-        self.handle_manager
-            .lookup_path(handle)
-            .ok_or_else(|| anyhow!("Invalid file handle"))
+    /// Restrict the optional operations offered to clients
+    ///
+    /// Useful when the export lives on a filesystem that cannot hold
+    /// symlinks, hard links or special files (e.g. FAT).
+    pub fn with_capabilities(mut self, capabilities: Capabilities) -> Self {
+        self.capabilities = capabilities;
+        self
     }
 
-    /// Validate that a path is within the export root
-    ///
-    /// This prevents path traversal attacks (e.g., "../../../etc/passwd")
-    fn validate_path(&self, path: &Path) -> Result<()> {
-        // For paths that don't exist yet, we need to validate the parent directory
-        // and then check if the final component would be safe
-        let absolute_path = if path.is_absolute() {
-            path.to_path_buf()
-        } else {
-            // This shouldn't happen in our code, but handle it defensively
-            self.root_path.join(path)
-        };
+    /// Prefetch `window` bytes (at most MAX_READAHEAD) past reads that
+    /// continue the previous one through the same handle; 0 disables
+    /// readahead
+    pub fn with_readahead(mut self, window: u32) -> Self {
+        self.readahead = (window > 0).then(|| Readahead::new(window));
+        self
+    }
 
-        // Check if path exists - if yes, canonicalize it
-        // If not, canonicalize the parent and validate the final component
-        let canonical = if absolute_path.exists() {
-            absolute_path
-                .canonicalize()
-                .context(format!("Failed to canonicalize existing path: {:?}", path))?
-        } else {
-            // Get parent and canonicalize that
-            let parent = absolute_path
-                .parent()
-                .ok_or_else(|| anyhow!("Path has no parent: {:?}", absolute_path))?;
+    /// Drop the cached pages of files not read for `idle`, checking every
+    /// `interval`; a zero `idle` leaves the page cache alone
+    pub fn with_idle_page_drop(mut self, idle: Duration, interval: Duration) -> Self {
+        self.idle_pages = (!idle.is_zero()).then(|| IdlePages::start(idle, interval));
+        self
+    }
 
-            let canonical_parent = parent
-                .canonicalize()
-                .context(format!("Failed to canonicalize parent path: {:?}", parent))?;
+    /// Have LOOKUP resolve symlinks to their targets, as long as those are
+    /// inside the export, instead of returning the symlinks themselves
+    pub fn with_follow_symlinks(mut self, follow_symlinks: bool) -> Self {
+        self.follow_symlinks = follow_symlinks;
+        self
+    }
 
-            // Check if parent is within root
-            if !canonical_parent.starts_with(&self.root_path) {
-                warn!(
-                    "Path traversal attempt: parent {:?} is outside root {:?}",
-                    canonical_parent, self.root_path
-                );
-                return Err(anyhow!("Path is outside export root"));
-            }
+    /// Choose when READ updates access times
+    pub fn with_atime(mut self, atime: AtimePolicy) -> Self {
+        self.atime = atime;
+        self
+    }
 
-            // Get final component and validate it doesn't contain traversal attempts
-            if let Some(file_name) = absolute_path.file_name() {
-                let file_name_str = file_name
-                    .to_str()
-                    .ok_or_else(|| anyhow!("Invalid filename encoding"))?;
+    /// Report `fsid` for every object instead of the root's device
+    pub fn with_fsid(mut self, fsid: u64) -> Self {
+        self.fsid = fsid;
+        self
+    }
 
-                if file_name_str.contains("..") || file_name_str.contains('/') {
-                    return Err(anyhow!("Invalid filename: {}", file_name_str));
-                }
+    /// Tie each handle to the path its object had when the handle was
+    /// issued, so the handle goes stale once the object is moved
+    ///
+    /// Otherwise a handle follows its object through renames. Needs handles
+    /// of at least `MIN_SUBTREE_CHECK_HANDLE_LEN` bytes to carry the path
+    /// hashes. Staying inside the export is checked in either mode.
+    pub fn with_subtree_check(mut self, enabled: bool) -> Result<Self> {
+        if enabled && !self.subtree_check {
+            let handle_len = self.handle_manager.handle_len();
+            self.handle_manager = HandleManager::with_handle_len(handle_len)?
+                .with_root(&self.root_path)?
+                .with_path_hashes()?;
+            self.root_handle = self.handle_manager.create_handle(self.root_path.clone());
+        }
+        self.subtree_check = enabled;
+        Ok(self)
+    }
 
-                // Return the would-be canonical path
-                canonical_parent.join(file_name)
-            } else {
-                return Err(anyhow!("Path has no filename component"));
+    /// Resolve a file handle to its object
+    ///
+    /// The path is walked from the export root without following symlinks,
+    /// whether or not subtree checking is on: once a directory on the path
+    /// is renamed away behind the server's back and a symlink takes its
+    /// name, the handle is stale rather than a way out of the export.
+    fn resolve_handle(&self, handle: &FileHandle) -> Result<Resolved> {
+        let path = self.handle_manager.decode(handle)?;
+        self.reach_handle(handle, path.clone())?
+            .ok_or_else(|| {
+                os_error(
+                    libc::ESTALE,
+                    format!("Stale file handle: {:?} is no longer the object it named", path),
+                )
+            })
+    }
+
+    /// Reach `path` from the export root, if it still holds the object of
+    /// `handle`
+    fn reach_handle(&self, handle: &FileHandle, path: PathBuf) -> Result<Option<Resolved>> {
+        let (entry, metadata) = match self.root.reach(&path) {
+            Ok(reached) => reached,
+            Err(e) if matches!(e.raw_os_error(), None | Some(libc::ENOENT | libc::ENOTDIR)) => {
+                return Ok(None);
             }
+            Err(e) => return Err(e).context(format!("Failed to stat: {:?}", path)),
         };
+        if !self.handle_manager.names(handle, &path, &metadata) {
+            return Ok(None);
+        }
+        Ok(Some(Resolved { path, entry, metadata }))
+    }
+
+    /// Resolve a file handle to the object an operation reads, modifies or
+    /// looks inside
+    ///
+    /// Such operations would act on whatever a symlink points to, possibly
+    /// outside the export, so a handle naming a symlink is refused unless
+    /// symlinks are followed, and then only resolves to a target within the
+    /// export.
+    fn resolve_object(&self, handle: &FileHandle) -> Result<Resolved> {
+        let object = self.resolve_handle(handle)?;
+        if !object.metadata.file_type().is_symlink() {
+            return Ok(object);
+        }
+        if !self.follow_symlinks {
+            return Err(os_error(libc::EINVAL, format!("Is a symbolic link: {:?}", object.path)));
+        }
+        self.resolve_symlink(&object.path)
+    }
 
-        if !canonical.starts_with(&self.root_path) {
+    /// The target of the symlink at `path`, which must be within the export
+    fn resolve_symlink(&self, path: &Path) -> Result<Resolved> {
+        let target = path
+            .canonicalize()
+            .context(format!("File not found: dangling symlink {:?}", path))?;
+        if !target.starts_with(&self.root_path) {
+            warn!(
+                "Symlink {:?} points to {:?}, outside root {:?}",
+                path, target, self.root_path
+            );
+            return Err(os_error(libc::EACCES, "Path is outside export root".to_string()));
+        }
+        // Walked again like any path, in case it changed since
+        let (entry, metadata) = self
+            .root
+            .reach(&target)
+            .context(format!("File not found: symlink target {:?}", target))?;
+        Ok(Resolved {
+            path: target,
+            entry,
+            metadata,
+        })
+    }
+
+    /// Path of the entry `name` in the directory at `dir_path`
+    ///
+    /// "." names the directory itself and ".." its parent, except at the
+    /// export root; any other name must pass `validate_name`. Nothing is
+    /// resolved here; walking the path from the root later keeps it inside
+    /// the export, and leaves a symlink a symlink.
+    fn resolve_child(&self, dir_path: &Path, name: &str) -> Result<PathBuf> {
+        if !dir_path.starts_with(&self.root_path) {
             warn!(
                 "Path traversal attempt: {:?} is outside root {:?}",
-                canonical, self.root_path
+                dir_path, self.root_path
             );
-            return Err(anyhow!("Path is outside export root"));
+            return Err(os_error(libc::EACCES, "Path is outside export root".to_string()));
         }
 
-        Ok(())
+        match name {
+            "." => Ok(dir_path.to_path_buf()),
+            ".." if dir_path == self.root_path => {
+                warn!("Path traversal attempt: \"..\" of export root {:?}", self.root_path);
+                Err(os_error(libc::EACCES, "Path is outside export root".to_string()))
+            }
+            ".." => Ok(dir_path
+                .parent()
+                .ok_or_else(|| anyhow!("Path has no parent: {:?}", dir_path))?
+                .to_path_buf()),
+            _ => {
+                validate_name(name)?;
+                Ok(dir_path.join(name))
+            }
+        }
     }
 
-    /// Convert std::fs::Metadata to FileAttributes
-    fn metadata_to_attr(&self, metadata: &fs::Metadata, path: &Path) -> FileAttributes {
-        #[cfg(unix)]
-        let ftype = {
-            use std::os::unix::fs::FileTypeExt;
-            let file_type = metadata.file_type();
-
-            if file_type.is_dir() {
-                FileType::Directory
-            } else if file_type.is_file() {
-                FileType::RegularFile
-            } else if file_type.is_symlink() {
-                FileType::SymbolicLink
-            } else if file_type.is_fifo() {
-                FileType::NamedPipe
-            } else if file_type.is_char_device() {
-                FileType::CharDevice
-            } else if file_type.is_block_device() {
-                FileType::BlockDevice
-            } else if file_type.is_socket() {
-                FileType::Socket
-            } else {
-                FileType::RegularFile // Default
+    /// The entry `name` of the directory `dir`, to create, remove or rename
+    fn dir_entry(&self, dir: &Resolved, name: &str) -> Result<Entry> {
+        validate_name(name)?;
+        if !dir.metadata.is_dir() {
+            return Err(anyhow!("Not a directory: {:?}", dir.path));
+        }
+        dir.entry
+            .child(name)
+            .context(format!("Failed to open directory: {:?}", dir.path))
+    }
+
+    /// Exclusively create `entry` at `path`, with `verf` in place the moment
+    /// the file can be seen
+    ///
+    /// The file is prepared, verifier included, under a staging name and
+    /// hard-linked to `path`, which fails if anything is there. Of racing
+    /// creates exactly one links its file; the others find it with its
+    /// verifier already stored, so only a retransmission of the winner's
+    /// call matches it.
+    fn create_exclusive(&self, entry: &Entry, path: &Path, mode: u32, verf: [u8; 8]) -> Result<FileHandle> {
+        let staging = entry.sibling(&staging_name())?;
+        let prepared = (|| {
+            let file = staging.create(libc::O_WRONLY | libc::O_EXCL, mode)?;
+            file.set_permissions(fs::Permissions::from_mode(mode))?;
+            let (atime, mtime) = verifier_to_times(verf);
+            file.set_times(fs::FileTimes::new().set_accessed(atime).set_modified(mtime))
+        })();
+        let linked = prepared.and_then(|()| staging.hard_link(entry));
+        let _ = staging.remove_file();
+
+        match linked {
+            Ok(()) => {
+                debug!("CREATE: {:?} mode={:o} exclusive -> handle", path, mode);
+                Ok(self.handle_manager.create_handle(path.to_path_buf()))
             }
-        };
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                self.create_existing(entry, path.to_path_buf(), CreateMode::Exclusive(verf))
+            }
+            Err(e) => Err(e).context(format!("Failed to create file: {:?}", path)),
+        }
+    }
+
+    /// Answer a guarded or exclusive create of `entry` at `path`, which
+    /// already exists
+    ///
+    /// A retransmitted exclusive create finds its own file.
+    fn create_existing(&self, entry: &Entry, path: PathBuf, how: CreateMode) -> Result<FileHandle> {
+        if let CreateMode::Exclusive(verf) = how {
+            let metadata = entry
+                .symlink_metadata()
+                .context(format!("Failed to stat file: {:?}", path))?;
+            if metadata.is_file() && stored_create_verifier(&metadata) == verf {
+                debug!("CREATE: {:?} exists with matching verifier", path);
+                return Ok(self.handle_manager.create_handle(path));
+            }
+        }
+        Err(anyhow!("File already exists: {:?}", path))
+    }
+}
+
+/// An object a handle names, reached from the export root
+struct Resolved {
+    /// Its path, which handles for the objects below it are made from
+    path: PathBuf,
+    /// Where it is, to act on it through
+    entry: Entry,
+    /// Its own attributes (lstat) as it was reached
+    metadata: fs::Metadata,
+}
+
+/// Check `name` can be an entry of a directory: a single component, and
+/// not "." or "..", which only LOOKUP resolves
+///
+/// Every procedure taking a name checks it here, so a name one of them
+/// accepts ("a..b") is accepted by all.
+fn validate_name(name: &str) -> Result<()> {
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') || name.contains('\0') {
+        return Err(std::io::Error::from_raw_os_error(libc::EINVAL))
+            .context(format!("Invalid filename: {:?}", name));
+    }
+    Ok(())
+}
+
+/// NFS file id of inode `ino` on device `dev`, in an export on `root_dev`
+///
+/// All objects share the export's fsid, but a filesystem mounted inside the
+/// export numbers its inodes independently of the export's own. Its device
+/// goes into the id, with the top bit set, so its ids stay clear of the
+/// root device's inode numbers and of each other's.
+fn fileid(dev: u64, ino: u64, root_dev: u64) -> u64 {
+    if dev == root_dev {
+        ino
+    } else {
+        (ino ^ dev.rotate_left(40)) | 1 << 63
+    }
+}
+
+/// Attributes of the object `metadata` describes, in the export `fsid`
+/// on `root_dev`
+///
+/// Every attribute this backend reports is built here, so fsid, fileid,
+/// type and rdev come out the same whichever procedure asked.
+fn stat_to_attributes(metadata: &fs::Metadata, fsid: u64, root_dev: u64) -> FileAttributes {
+    FileAttributes {
+        ftype: file_type(metadata),
+        mode: metadata.permissions().mode(),
+        nlink: metadata.nlink() as u32,
+        uid: metadata.uid(),
+        gid: metadata.gid(),
+        size: metadata.len(),
+        used: metadata.blocks() * 512, // blocks are typically 512 bytes
+        rdev: (libc::major(metadata.rdev()), libc::minor(metadata.rdev())),
+        fsid,
+        fileid: fileid(metadata.dev(), metadata.ino(), root_dev),
+        atime: FileTime {
+            seconds: metadata.atime() as u64,
+            nseconds: metadata.atime_nsec() as u32,
+        },
+        mtime: FileTime {
+            seconds: metadata.mtime() as u64,
+            nseconds: metadata.mtime_nsec() as u32,
+        },
+        ctime: FileTime {
+            seconds: metadata.ctime() as u64,
+            nseconds: metadata.ctime_nsec() as u32,
+        },
+    }
+}
+
+/// NFS type of the object `metadata` describes
+fn file_type(metadata: &fs::Metadata) -> FileType {
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::FileTypeExt;
+        let file_type = metadata.file_type();
 
-        #[cfg(not(unix))]
-        let ftype = if metadata.is_dir() {
+        if file_type.is_dir() {
             FileType::Directory
-        } else if metadata.is_file() {
+        } else if file_type.is_file() {
             FileType::RegularFile
-        } else if metadata.is_symlink() {
+        } else if file_type.is_symlink() {
             FileType::SymbolicLink
+        } else if file_type.is_fifo() {
+            FileType::NamedPipe
+        } else if file_type.is_char_device() {
+            FileType::CharDevice
+        } else if file_type.is_block_device() {
+            FileType::BlockDevice
+        } else if file_type.is_socket() {
+            FileType::Socket
         } else {
             FileType::RegularFile // Default
-        };
+        }
+    }
 
-        FileAttributes {
-            ftype,
-            mode: metadata.permissions().mode(),
-            nlink: metadata.nlink() as u32,
-            uid: metadata.uid(),
-            gid: metadata.gid(),
-            size: metadata.len(),
-            used: metadata.blocks() * 512, // blocks are typically 512 bytes
-            rdev: (metadata.rdev() as u32, 0),
-            fsid: metadata.dev(),
-            fileid: metadata.ino(),
-            atime: FileTime {
-                seconds: metadata.atime() as u64,
-                nseconds: metadata.atime_nsec() as u32,
-            },
-            mtime: FileTime {
-                seconds: metadata.mtime() as u64,
-                nseconds: metadata.mtime_nsec() as u32,
-            },
-            ctime: FileTime {
-                seconds: metadata.ctime() as u64,
-                nseconds: metadata.ctime_nsec() as u32,
-            },
+    #[cfg(not(unix))]
+    {
+        if metadata.is_dir() {
+            FileType::Directory
+        } else if metadata.is_symlink() {
+            FileType::SymbolicLink
+        } else {
+            FileType::RegularFile
         }
     }
 }
 
-impl Filesystem for LocalFilesystem {
-    fn root_handle(&self) -> FileHandle {
-        self.root_handle.clone()
-    }
+/// Query an fpathconf(3) variable of `file`, returning None when it
+/// reports -1
+///
+/// For limits -1 means there is none; for options (`_PC_NO_TRUNC`,
+/// `_PC_CHOWN_RESTRICTED`) it means the option is not in effect.
+fn query_pathconf(file: &fs::File, name: libc::c_int) -> Option<libc::c_long> {
+    use std::os::unix::io::AsRawFd;
 
-    fn lookup(&self, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
-        let dir_path = self.resolve_handle(dir_handle)?;
+    let value = unsafe { libc::fpathconf(file.as_raw_fd(), name) };
+    (value != -1).then_some(value)
+}
 
-        // Security: prevent path traversal
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid filename: {}", name));
+/// Read up to `count` bytes at `offset` of `file`, stopping short only at
+/// end of file
+fn read_at(file: &fs::File, offset: u64, count: u32) -> Result<Vec<u8>> {
+    let mut file = file;
+
+    // Seek to offset
+    file.seek(SeekFrom::Start(offset))
+        .context("Failed to seek")?;
+
+    let mut buffer = Vec::with_capacity(count as usize);
+    file.take(count as u64)
+        .read_to_end(&mut buffer)
+        .context("Failed to read file")?;
+    Ok(buffer)
+}
+
+/// Open `entry` for reading, without access time updates if `noatime`
+///
+/// O_NOATIME is limited to the file's owner; for anyone else the read
+/// updates the access time after all.
+fn open_for_read(entry: &Entry, noatime: bool) -> std::io::Result<fs::File> {
+    if noatime {
+        match entry.open(libc::O_RDONLY | libc::O_NOATIME) {
+            Err(e) if e.raw_os_error() == Some(libc::EPERM) => {}
+            result => return result,
         }
+    }
+    entry.open(libc::O_RDONLY)
+}
 
-        let full_path = dir_path.join(name);
+/// Encode an exclusive create verifier as (atime, mtime)
+///
+/// The high half becomes the atime seconds and the low half the mtime seconds.
+fn verifier_to_times(verf: [u8; 8]) -> (SystemTime, SystemTime) {
+    let high = u32::from_be_bytes([verf[0], verf[1], verf[2], verf[3]]);
+    let low = u32::from_be_bytes([verf[4], verf[5], verf[6], verf[7]]);
+    (
+        UNIX_EPOCH + Duration::from_secs(high as u64),
+        UNIX_EPOCH + Duration::from_secs(low as u64),
+    )
+}
+
+/// Decode the verifier stored by an exclusive create (see `verifier_to_times`)
+fn stored_create_verifier(metadata: &fs::Metadata) -> [u8; 8] {
+    let mut verf = [0u8; 8];
+    verf[..4].copy_from_slice(&(metadata.atime() as u32).to_be_bytes());
+    verf[4..].copy_from_slice(&(metadata.mtime() as u32).to_be_bytes());
+    verf
+}
 
-        // Validate path is within export root
-        self.validate_path(&full_path)?;
+/// Hidden name, unique to this call, under which an exclusively created
+/// file is prepared in the directory it is created in
+fn staging_name() -> String {
+    static STAGED: AtomicU64 = AtomicU64::new(0);
+    format!(
+        ".excl-{}-{}",
+        std::process::id(),
+        STAGED.fetch_add(1, Ordering::Relaxed)
+    )
+}
 
-        // Check if file exists
-        if !full_path.exists() {
-            return Err(anyhow!("File not found: {}", name));
+impl Filesystem for LocalFilesystem {
+    fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    fn root_handle(&self) -> FileHandle {
+        self.root_handle.clone()
+    }
+
+    fn owns_handle(&self, handle: &FileHandle) -> bool {
+        self.handle_manager.decode(handle).is_ok()
+    }
+
+    fn lookup(&self, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
+        let dir = self.resolve_object(dir_handle)?;
+        if !dir.metadata.is_dir() {
+            return Err(anyhow!("Not a directory: {:?}", dir.path));
         }
+        let full_path = self.resolve_child(&dir.path, name)?;
+
+        // lstat, so a symlink is found even when its target is missing
+        let (_, metadata) = self
+            .root
+            .reach(&full_path)
+            .map_err(|_| anyhow!("File not found: {}", name))?;
+
+        // A symlink is either handed back as itself, for the client to
+        // READLINK, or resolved here to a target that must be in the export
+        let full_path = if !metadata.file_type().is_symlink() {
+            full_path
+        } else if self.follow_symlinks {
+            self.resolve_symlink(&full_path)?.path
+        } else {
+            full_path
+        };
 
         // Create or get existing handle
         let handle = self.handle_manager.create_handle(full_path);
 
-        debug!("LOOKUP: {:?}/{} -> handle", dir_path, name);
+        debug!("LOOKUP: {:?}/{} -> handle", dir.path, name);
 
         Ok(handle)
     }
 
     fn getattr(&self, handle: &FileHandle) -> Result<FileAttributes> {
-        let path = self.resolve_handle(handle)?;
+        // lstat: a symlink reports its own attributes, as NFS expects
+        let object = self.resolve_handle(handle)?;
 
-        let metadata = fs::metadata(&path).context(format!("Failed to stat: {:?}", path))?;
+        Ok(stat_to_attributes(&object.metadata, self.fsid, self.root_dev))
+    }
 
-        Ok(self.metadata_to_attr(&metadata, &path))
+    fn statfs(&self, handle: &FileHandle) -> Result<FsStat> {
+        use std::os::unix::io::AsRawFd;
+
+        let object = self.resolve_handle(handle)?;
+        let file = object
+            .entry
+            .open(libc::O_PATH)
+            .context(format!("Failed to open: {:?}", object.path))?;
+
+        let mut stat = std::mem::MaybeUninit::<libc::statvfs>::uninit();
+        let result = unsafe { libc::fstatvfs(file.as_raw_fd(), stat.as_mut_ptr()) };
+        if result != 0 {
+            return Err(anyhow!(
+                "Failed to statvfs {:?}: {}",
+                object.path,
+                std::io::Error::last_os_error()
+            ));
+        }
+        let stat = unsafe { stat.assume_init() };
+
+        // Block counts are in units of the fragment size
+        let block_size = u64::from(stat.f_frsize);
+        let fs_stat = FsStat {
+            tbytes: u64::from(stat.f_blocks) * block_size,
+            fbytes: u64::from(stat.f_bfree) * block_size,
+            abytes: u64::from(stat.f_bavail) * block_size,
+            tfiles: u64::from(stat.f_files),
+            ffiles: u64::from(stat.f_ffree),
+            afiles: u64::from(stat.f_favail),
+        };
+
+        debug!("STATFS: {:?} -> {:?}", object.path, fs_stat);
+        Ok(fs_stat)
     }
 
-    fn read(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<Vec<u8>> {
-        let path = self.resolve_handle(handle)?;
+    fn pathconf(&self, handle: &FileHandle) -> Result<PathConf> {
+        let object = self.resolve_handle(handle)?;
 
-        let mut file =
-            fs::File::open(&path).context(format!("Failed to open file: {:?}", path))?;
+        // Held open, so a -1 below means "no limit" or "option not in
+        // effect" rather than a lookup failure
+        let file = object
+            .entry
+            .open(libc::O_PATH)
+            .context(format!("Failed to open: {:?}", object.path))?;
 
-        // Seek to offset
-        file.seek(SeekFrom::Start(offset))
-            .context("Failed to seek")?;
+        let defaults = PathConf::default();
+        let limit = |name, default: u32| {
+            query_pathconf(&file, name)
+                .map_or(default, |value| u32::try_from(value).unwrap_or(u32::MAX))
+        };
 
-        // Read up to count bytes
-        let mut buffer = vec![0u8; count as usize];
-        let bytes_read = file.read(&mut buffer).context("Failed to read file")?;
+        let conf = PathConf {
+            linkmax: limit(libc::_PC_LINK_MAX, u32::MAX),
+            name_max: limit(libc::_PC_NAME_MAX, defaults.name_max),
+            no_trunc: query_pathconf(&file, libc::_PC_NO_TRUNC).is_some(),
+            chown_restricted: query_pathconf(&file, libc::_PC_CHOWN_RESTRICTED).is_some(),
+            // POSIX has no query for these; Unix filesystems are case-sensitive
+            case_insensitive: defaults.case_insensitive,
+            case_preserving: defaults.case_preserving,
+        };
+
+        debug!("PATHCONF: {:?} -> {:?}", object.path, conf);
+        Ok(conf)
+    }
 
-        // Truncate buffer to actual bytes read
-        buffer.truncate(bytes_read);
+    fn read(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<Vec<u8>> {
+        let object = self.resolve_object(handle)?;
+
+        // Access times are left to the kernel and the host's mount options
+        let noatime = self.atime == AtimePolicy::Noatime;
+        let file = open_for_read(&object.entry, noatime)
+            .context(format!("Failed to open file: {:?}", object.path))?;
+        let buffer = match &self.readahead {
+            Some(readahead) => {
+                let metadata = &object.metadata;
+                let version = (
+                    metadata.ino(),
+                    metadata.len(),
+                    metadata.mtime(),
+                    metadata.mtime_nsec(),
+                );
+                readahead.read(handle, offset, count, version, |offset, count| {
+                    read_at(&file, offset, count)
+                })?
+            }
+            None => read_at(&file, offset, count)?,
+        };
+        if let Some(idle_pages) = &self.idle_pages {
+            idle_pages.touch(handle, &object.path);
+        }
 
         debug!(
             "READ: {:?} offset={} count={} -> {} bytes",
-            path, offset, count, bytes_read
+            object.path,
+            offset,
+            count,
+            buffer.len()
         );
 
         Ok(buffer)
     }
 
     fn readdir(&self, dir_handle: &FileHandle, cookie: u64, count: u32) -> Result<(Vec<DirEntry>, bool)> {
-        let dir_path = self.resolve_handle(dir_handle)?;
+        let dir = self.resolve_object(dir_handle)?;
 
         // Verify it's a directory
-        let metadata = fs::metadata(&dir_path)
-            .context(format!("Failed to stat directory: {:?}", dir_path))?;
-
-        if !metadata.is_dir() {
-            return Err(anyhow!("Not a directory: {:?}", dir_path));
+        if !dir.metadata.is_dir() {
+            return Err(anyhow!("Not a directory: {:?}", dir.path));
         }
 
         // Read directory entries
-        let read_dir = fs::read_dir(&dir_path)
-            .context(format!("Failed to read directory: {:?}", dir_path))?;
+        let children = dir
+            .entry
+            .read_dir()
+            .context(format!("Failed to read directory: {:?}", dir.path))?;
 
         // Collect all entries
         let mut entries: Vec<DirEntry> = Vec::new();
 
-        for (index, entry_result) in read_dir.enumerate() {
-            let entry = entry_result.context("Failed to read directory entry")?;
-            let entry_path = entry.path();
-            let entry_metadata = entry.metadata()
+        for (index, child) in children.iter().enumerate() {
+            // Skip entries before cookie (cookie is 0-based index + 1)
+            if cookie > 0 && (index as u64) < cookie {
+                continue;
+            }
+
+            let entry_path = dir.path.join(child.name());
+            let mut entry_metadata = child.symlink_metadata()
                 .context(format!("Failed to get metadata for: {:?}", entry_path))?;
 
-            #[cfg(unix)]
-            let file_type = {
-                use std::os::unix::fs::FileTypeExt;
-                let ft = entry_metadata.file_type();
-
-                if ft.is_dir() {
-                    FileType::Directory
-                } else if ft.is_file() {
-                    FileType::RegularFile
-                } else if ft.is_symlink() {
-                    FileType::SymbolicLink
-                } else if ft.is_fifo() {
-                    FileType::NamedPipe
-                } else if ft.is_char_device() {
-                    FileType::CharDevice
-                } else if ft.is_block_device() {
-                    FileType::BlockDevice
-                } else if ft.is_socket() {
-                    FileType::Socket
-                } else {
-                    FileType::RegularFile // Default
+            // LOOKUP hands out the target of a followed symlink, so its
+            // entry has to describe the target too for the fileids to agree
+            if self.follow_symlinks && entry_metadata.file_type().is_symlink() {
+                if let Ok(target) = self.resolve_symlink(&entry_path) {
+                    entry_metadata = target.metadata;
                 }
-            };
-
-            #[cfg(not(unix))]
-            let file_type = if entry_metadata.is_dir() {
-                FileType::Directory
-            } else if entry_metadata.is_file() {
-                FileType::RegularFile
-            } else if entry_metadata.is_symlink() {
-                FileType::SymbolicLink
-            } else {
-                FileType::RegularFile // Default
-            };
+            }
+
+            let file_type = file_type(&entry_metadata);
 
-            let name = entry.file_name()
+            let name = child.name()
                 .to_string_lossy()
                 .to_string();
 
-            // Skip entries before cookie (cookie is 0-based index + 1)
-            if cookie > 0 && (index as u64) < cookie {
-                continue;
-            }
-
             entries.push(DirEntry {
-                fileid: entry_metadata.ino(),
+                fileid: fileid(entry_metadata.dev(), entry_metadata.ino(), self.root_dev),
                 name,
                 file_type,
             });
@@ -334,7 +724,7 @@ impl Filesystem for LocalFilesystem {
             if entries.len() >= count as usize {
                 debug!(
                     "READDIR: {:?} cookie={} count={} -> {} entries (more available)",
-                    dir_path, cookie, count, entries.len()
+                    dir.path, cookie, count, entries.len()
                 );
                 return Ok((entries, false)); // Not EOF, more entries available
             }
@@ -342,34 +732,34 @@ impl Filesystem for LocalFilesystem {
 
         debug!(
             "READDIR: {:?} cookie={} count={} -> {} entries (EOF)",
-            dir_path, cookie, count, entries.len()
+            dir.path, cookie, count, entries.len()
         );
 
         Ok((entries, true)) // EOF reached
     }
 
     fn write(&self, handle: &FileHandle, offset: u64, data: &[u8]) -> Result<u32> {
-        let path = self.resolve_handle(handle)?;
+        let object = self.resolve_object(handle)?;
 
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            .create(true)
-            .open(&path)
-            .context(format!("Failed to open file for writing: {:?}", path))?;
+        let mut file = object
+            .entry
+            .open(libc::O_WRONLY)
+            .context(format!("Failed to open file for writing: {:?}", object.path))?;
 
         // Seek to offset
         file.seek(SeekFrom::Start(offset))
             .context("Failed to seek")?;
 
         // Write data
+        // Durability is left to commit(), so UNSTABLE writes stay cheap
         let bytes_written = file.write(data).context("Failed to write file")?;
-
-        // Flush to disk
-        file.sync_all().context("Failed to sync file")?;
+        if let Some(readahead) = &self.readahead {
+            readahead.invalidate(handle);
+        }
 
         debug!(
             "WRITE: {:?} offset={} count={} -> {} bytes",
-            path,
+            object.path,
             offset,
             data.len(),
             bytes_written
@@ -379,89 +769,165 @@ impl Filesystem for LocalFilesystem {
     }
 
     fn setattr_size(&self, handle: &FileHandle, size: u64) -> Result<()> {
-        let path = self.resolve_handle(handle)?;
+        let object = self.resolve_object(handle)?;
 
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .open(&path)
-            .context(format!("Failed to open file for setattr: {:?}", path))?;
+        let file = object
+            .entry
+            .open(libc::O_WRONLY)
+            .context(format!("Failed to open file for setattr: {:?}", object.path))?;
 
+        // ftruncate drops the tail when shrinking and leaves a hole when
+        // growing, so extending a file allocates no blocks. It runs even
+        // when the size is unchanged: a truncate still updates mtime and
+        // ctime (`> file` on an empty file)
         file.set_len(size)
             .context("Failed to set file size")?;
+        if let Some(readahead) = &self.readahead {
+            readahead.invalidate(handle);
+        }
 
-        debug!("SETATTR: {:?} size={}", path, size);
+        debug!("SETATTR: {:?} size={}", object.path, size);
 
         Ok(())
     }
 
     fn setattr_mode(&self, handle: &FileHandle, mode: u32) -> Result<()> {
-        let path = self.resolve_handle(handle)?;
+        let object = self.resolve_object(handle)?;
 
-        let permissions = fs::Permissions::from_mode(mode);
-        fs::set_permissions(&path, permissions)
-            .context(format!("Failed to set permissions: {:?}", path))?;
+        object
+            .entry
+            .set_mode(mode)
+            .context(format!("Failed to set permissions: {:?}", object.path))?;
 
-        debug!("SETATTR: {:?} mode={:o}", path, mode);
+        debug!("SETATTR: {:?} mode={:o}", object.path, mode);
 
         Ok(())
     }
 
     fn setattr_owner(&self, handle: &FileHandle, uid: Option<u32>, gid: Option<u32>) -> Result<()> {
-        let path = self.resolve_handle(handle)?;
+        // A symlink's own owner is changed, as lchown does
+        let object = self.resolve_handle(handle)?;
 
-        // Note: chown requires root privileges on Unix systems
-        // For now, we'll just log this and return success
-        // In production, you might want to use nix::unistd::chown
-        debug!("SETATTR: {:?} uid={:?} gid={:?} (not implemented)", path, uid, gid);
+        debug!("SETATTR: {:?} uid={:?} gid={:?}", object.path, uid, gid);
+
+        // Changing the owner requires root (or CAP_CHOWN); an unprivileged
+        // server gets "Operation not permitted" for anything but a no-op
+        object
+            .entry
+            .set_owner(uid, gid)
+            .context(format!("Failed to change owner of {:?}", object.path))?;
 
         Ok(())
     }
 
-    fn create(&self, dir_handle: &FileHandle, name: &str, mode: u32) -> Result<FileHandle> {
-        let dir_path = self.resolve_handle(dir_handle)?;
+    fn setattr_times(
+        &self,
+        handle: &FileHandle,
+        atime: Option<SetTime>,
+        mtime: Option<SetTime>,
+    ) -> Result<()> {
+        // A symlink's own times are set, as utimensat(AT_SYMLINK_NOFOLLOW)
+        // does
+        let object = self.resolve_handle(handle)?;
+
+        // Both SET_TO_SERVER_TIME timestamps get the same instant
+        let now = SystemTime::now();
+        let resolve = |time: SetTime| match time {
+            SetTime::ServerTime => now,
+            SetTime::ClientTime(t) => UNIX_EPOCH + Duration::new(t.seconds, t.nseconds),
+        };
 
-        // Security: prevent path traversal
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid filename: {}", name));
-        }
+        object
+            .entry
+            .set_times(atime.map(resolve), mtime.map(resolve))
+            .context(format!("Failed to set times: {:?}", object.path))?;
 
-        let full_path = dir_path.join(name);
+        debug!("SETATTR: {:?} atime={:?} mtime={:?}", object.path, atime, mtime);
 
-        // Validate path is within export root
-        self.validate_path(&full_path)?;
+        Ok(())
+    }
 
-        // Create file
-        let file = fs::File::create(&full_path)
-            .context(format!("Failed to create file: {:?}", full_path))?;
+    fn create(
+        &self,
+        dir_handle: &FileHandle,
+        name: &str,
+        mode: u32,
+        how: CreateMode,
+    ) -> Result<FileHandle> {
+        let dir = self.resolve_object(dir_handle)?;
+
+        let full_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
+
+        if let CreateMode::Exclusive(verf) = how {
+            if self.capabilities.contains(Capabilities::HARD_LINK) {
+                return self.create_exclusive(&entry, &full_path, mode, verf);
+            }
+        }
+
+        // Create file; an existing symlink is never followed
+        let file = match how {
+            CreateMode::Unchecked => entry
+                .create(libc::O_WRONLY | libc::O_TRUNC, mode)
+                .context(format!("Failed to create file: {:?}", full_path))?,
+            CreateMode::Guarded | CreateMode::Exclusive(_) => {
+                match entry.create(libc::O_WRONLY | libc::O_EXCL, mode) {
+                    Ok(file) => file,
+                    Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                        return self.create_existing(&entry, full_path, how);
+                    }
+                    Err(e) => {
+                        return Err(e).context(format!("Failed to create file: {:?}", full_path));
+                    }
+                }
+            }
+        };
 
         // Set permissions
         let permissions = fs::Permissions::from_mode(mode);
         file.set_permissions(permissions)
             .context("Failed to set permissions")?;
 
+        // Exclusive creates keep the verifier in atime/mtime until the client
+        // sets real attributes (RFC 1813 section 3.3.8). Without hard links
+        // it is stored just after the file appears, so a retransmission
+        // landing in between is answered NFS3ERR_EXIST.
+        if let CreateMode::Exclusive(verf) = how {
+            let (atime, mtime) = verifier_to_times(verf);
+            file.set_times(fs::FileTimes::new().set_accessed(atime).set_modified(mtime))
+                .context("Failed to store create verifier")?;
+        }
+
         // Create handle
         let handle = self.handle_manager.create_handle(full_path.clone());
 
-        debug!("CREATE: {:?} mode={:o} -> handle", full_path, mode);
+        debug!("CREATE: {:?} mode={:o} how={:?} -> handle", full_path, mode, how);
 
         Ok(handle)
     }
 
     fn remove(&self, dir_handle: &FileHandle, name: &str) -> Result<()> {
-        let dir_path = self.resolve_handle(dir_handle)?;
-
-        // Security: prevent path traversal
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid filename: {}", name));
-        }
+        let dir = self.resolve_object(dir_handle)?;
 
-        let full_path = dir_path.join(name);
+        let full_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
 
-        // Validate path is within export root
-        self.validate_path(&full_path)?;
+        // Directories must go through rmdir
+        let metadata = match entry.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(anyhow!("File not found: {:?}", full_path));
+            }
+            Err(e) => {
+                return Err(e).context(format!("Failed to stat file: {:?}", full_path));
+            }
+        };
+        if metadata.is_dir() {
+            return Err(anyhow!("Is a directory: {:?}", full_path));
+        }
 
         // Remove file
-        fs::remove_file(&full_path).context(format!("Failed to remove file: {:?}", full_path))?;
+        entry.remove_file().context(format!("Failed to remove file: {:?}", full_path))?;
 
         debug!("REMOVE: {:?}", full_path);
 
@@ -469,24 +935,16 @@ impl Filesystem for LocalFilesystem {
     }
 
     fn mkdir(&self, dir_handle: &FileHandle, name: &str, mode: u32) -> Result<FileHandle> {
-        let dir_path = self.resolve_handle(dir_handle)?;
-
-        // Security: prevent path traversal
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid directory name: {}", name));
-        }
-
-        let full_path = dir_path.join(name);
+        let dir = self.resolve_object(dir_handle)?;
 
-        // Validate path is within export root
-        self.validate_path(&full_path)?;
+        let full_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
 
         // Create directory
-        fs::create_dir(&full_path).context(format!("Failed to create directory: {:?}", full_path))?;
+        entry.mkdir(mode).context(format!("Failed to create directory: {:?}", full_path))?;
 
-        // Set permissions
-        let permissions = fs::Permissions::from_mode(mode);
-        fs::set_permissions(&full_path, permissions).context("Failed to set permissions")?;
+        // Set permissions, which the umask may have cut
+        entry.set_mode(mode).context("Failed to set permissions")?;
 
         // Create handle
         let handle = self.handle_manager.create_handle(full_path.clone());
@@ -497,20 +955,36 @@ impl Filesystem for LocalFilesystem {
     }
 
     fn rmdir(&self, dir_handle: &FileHandle, name: &str) -> Result<()> {
-        let dir_path = self.resolve_handle(dir_handle)?;
-
-        // Security: prevent path traversal
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid directory name: {}", name));
-        }
+        let dir = self.resolve_object(dir_handle)?;
 
-        let full_path = dir_path.join(name);
+        let full_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
 
-        // Validate path is within export root
-        self.validate_path(&full_path)?;
+        // Only empty directories can be removed
+        let metadata = match entry.symlink_metadata() {
+            Ok(metadata) => metadata,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                return Err(anyhow!("Directory not found: {:?}", full_path));
+            }
+            Err(e) => {
+                return Err(e).context(format!("Failed to stat directory: {:?}", full_path));
+            }
+        };
+        if !metadata.is_dir() {
+            return Err(anyhow!("Not a directory: {:?}", full_path));
+        }
+        // read_dir never yields "." and "..", so any entry means not empty
+        let has_entries = !entry
+            .read_dir()
+            .context(format!("Failed to read directory: {:?}", full_path))?
+            .is_empty();
+        if has_entries {
+            return Err(anyhow!("Directory not empty: {:?}", full_path));
+        }
 
         // Remove directory
-        fs::remove_dir(&full_path)
+        entry
+            .remove_dir()
             .context(format!("Failed to remove directory: {:?}", full_path))?;
 
         debug!("RMDIR: {:?}", full_path);
@@ -525,59 +999,74 @@ impl Filesystem for LocalFilesystem {
         to_dir_handle: &FileHandle,
         to_name: &str,
     ) -> Result<()> {
-        let from_dir_path = self.resolve_handle(from_dir_handle)?;
-        let to_dir_path = self.resolve_handle(to_dir_handle)?;
-
-        // Security: prevent path traversal
-        if from_name.contains('/') || from_name.contains("..") {
-            return Err(anyhow!("Invalid source name: {}", from_name));
-        }
-        if to_name.contains('/') || to_name.contains("..") {
-            return Err(anyhow!("Invalid target name: {}", to_name));
+        let from_dir = self.resolve_object(from_dir_handle)?;
+        let to_dir = self.resolve_object(to_dir_handle)?;
+
+        let from_full_path = from_dir.path.join(from_name);
+        let to_full_path = to_dir.path.join(to_name);
+        let from_entry = self.dir_entry(&from_dir, from_name)?;
+        let to_entry = self.dir_entry(&to_dir, to_name)?;
+
+        // Enforce POSIX type rules up front so callers get a precise error:
+        // a directory may only replace an empty directory, a non-directory
+        // may only replace a non-directory
+        let from_metadata = from_entry
+            .symlink_metadata()
+            .map_err(|_| anyhow!("Source not found: {:?}", from_full_path))?;
+        if let Ok(to_metadata) = to_entry.symlink_metadata() {
+            match (from_metadata.is_dir(), to_metadata.is_dir()) {
+                (true, true) => {
+                    let has_entries = !to_entry
+                        .read_dir()
+                        .context(format!("Failed to read directory: {:?}", to_full_path))?
+                        .is_empty();
+                    if has_entries && from_metadata.ino() != to_metadata.ino() {
+                        return Err(anyhow!(
+                            "Directory not empty: target {:?}",
+                            to_full_path
+                        ));
+                    }
+                }
+                (true, false) => {
+                    return Err(anyhow!("Not a directory: target {:?}", to_full_path));
+                }
+                (false, true) => {
+                    return Err(anyhow!("Is a directory: target {:?}", to_full_path));
+                }
+                (false, false) => {}
+            }
         }
 
-        let from_full_path = from_dir_path.join(from_name);
-        let to_full_path = to_dir_path.join(to_name);
-
-        // Validate both paths are within export root
-        self.validate_path(&from_full_path)?;
-        self.validate_path(&to_full_path)?;
-
         // Rename/move the file or directory
-        fs::rename(&from_full_path, &to_full_path)
+        from_entry
+            .rename(&to_entry)
             .context(format!("Failed to rename {:?} to {:?}", from_full_path, to_full_path))?;
 
+        // Handles follow their objects (with subtree checking they go stale,
+        // their path hashes no longer matching)
+        self.handle_manager.rename_path(&from_full_path, &to_full_path);
+
         debug!("RENAME: {:?} -> {:?}", from_full_path, to_full_path);
 
         Ok(())
     }
 
     fn symlink(&self, dir_handle: &FileHandle, name: &str, target: &str) -> Result<FileHandle> {
-        let dir_path = self.resolve_handle(dir_handle)?;
+        let dir = self.resolve_object(dir_handle)?;
 
-        // Security: prevent path traversal in symlink name
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid symlink name: {}", name));
-        }
+        let symlink_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
 
-        let symlink_path = dir_path.join(name);
-
-        // Validate symlink path is within export root
-        self.validate_path(&symlink_path)?;
-
-        // Check if file/symlink already exists
-        if symlink_path.exists() {
+        // Check if file/symlink already exists (a dangling symlink counts)
+        if entry.symlink_metadata().is_ok() {
             return Err(anyhow!("File or symlink already exists: {:?}", symlink_path));
         }
 
         // Create symbolic link
-        #[cfg(unix)]
-        std::os::unix::fs::symlink(target, &symlink_path)
+        entry
+            .symlink(target)
             .context(format!("Failed to create symlink {:?} -> {}", symlink_path, target))?;
 
-        #[cfg(not(unix))]
-        return Err(anyhow!("Symbolic links are only supported on Unix systems"));
-
         debug!("SYMLINK: {:?} -> {}", symlink_path, target);
 
         // Create handle for the new symlink
@@ -586,83 +1075,74 @@ impl Filesystem for LocalFilesystem {
     }
 
     fn readlink(&self, handle: &FileHandle) -> Result<String> {
-        let path = self.resolve_handle(handle)?;
+        let object = self.resolve_handle(handle)?;
 
         // Verify the path is a symlink
-        let metadata = fs::symlink_metadata(&path)
-            .context(format!("Failed to get metadata for {:?}", path))?;
-
-        if !metadata.file_type().is_symlink() {
-            return Err(anyhow!("Not a symbolic link: {:?}", path));
+        if !object.metadata.file_type().is_symlink() {
+            return Err(anyhow!("Not a symbolic link: {:?}", object.path));
         }
 
         // Read the symlink target
-        let target = fs::read_link(&path)
-            .context(format!("Failed to read symlink {:?}", path))?;
+        let target = object
+            .entry
+            .read_link()
+            .context(format!("Failed to read symlink {:?}", object.path))?;
 
         let target_str = target.to_string_lossy().to_string();
 
-        debug!("READLINK: {:?} -> {}", path, target_str);
+        debug!("READLINK: {:?} -> {}", object.path, target_str);
 
         Ok(target_str)
     }
 
     fn link(&self, file_handle: &FileHandle, dir_handle: &FileHandle, name: &str) -> Result<FileHandle> {
-        let file_path = self.resolve_handle(file_handle)?;
-        let dir_path = self.resolve_handle(dir_handle)?;
-
-        // Security: prevent path traversal in link name
-        if name.contains('/') || name.contains("..") {
-            return Err(anyhow!("Invalid link name: {}", name));
-        }
-
-        let link_path = dir_path.join(name);
+        let file = self.resolve_handle(file_handle)?;
+        let dir = self.resolve_object(dir_handle)?;
 
-        // Validate link path is within export root
-        self.validate_path(&link_path)?;
+        let link_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
 
-        // Check if target already exists
-        if link_path.exists() {
+        // Check if target already exists (a dangling symlink counts)
+        if entry.symlink_metadata().is_ok() {
             return Err(anyhow!("File already exists: {:?}", link_path));
         }
 
-        // Get source file metadata to check if it's a directory
-        let metadata = fs::metadata(&file_path)
-            .context(format!("Failed to get metadata for {:?}", file_path))?;
-
         // Cannot create hard link to a directory (POSIX restriction)
-        if metadata.is_dir() {
-            return Err(anyhow!("Cannot create hard link to directory: {:?}", file_path));
+        if file.metadata.is_dir() {
+            return Err(anyhow!("Cannot create hard link to directory: {:?}", file.path));
         }
 
         // Create hard link
-        fs::hard_link(&file_path, &link_path)
-            .context(format!("Failed to create hard link {:?} -> {:?}", link_path, file_path))?;
+        file.entry
+            .hard_link(&entry)
+            .context(format!("Failed to create hard link {:?} -> {:?}", link_path, file.path))?;
 
-        debug!("LINK: {:?} -> {:?}", link_path, file_path);
+        debug!("LINK: {:?} -> {:?}", link_path, file.path);
 
         // Return the same file handle (hard links share the same inode)
         Ok(file_handle.clone())
     }
 
     fn commit(&self, handle: &FileHandle, offset: u64, count: u32) -> Result<()> {
-        let path = self.resolve_handle(handle)?;
+        let object = self.resolve_object(handle)?;
+        let path = &object.path;
 
         // Open file for syncing
-        let file = fs::OpenOptions::new()
-            .write(true)
-            .open(&path)
+        let file = object
+            .entry
+            .open(libc::O_WRONLY)
             .context(format!("Failed to open file for commit: {:?}", path))?;
 
-        // Sync data to disk
-        // Note: For a more sophisticated implementation, we could:
-        // 1. Only sync the specified range (offset, count) if the OS supports it
-        // 2. Use sync_data() instead of sync_all() to skip metadata sync
-        // 3. Track UNSTABLE writes and only sync those
-        //
-        // For now, we sync all data in the file for simplicity
-        file.sync_all()
-            .context(format!("Failed to sync file: {:?}", path))?;
+        // count == 0 commits the whole file, data and metadata. A ranged
+        // commit skips unrelated metadata, but fdatasync has no range: it
+        // writes back all of the file's data, not just the range asked for
+        if count == 0 {
+            file.sync_all()
+                .context(format!("Failed to sync file: {:?}", path))?;
+        } else {
+            file.sync_data()
+                .context(format!("Failed to sync file: {:?}", path))?;
+        }
 
         debug!(
             "COMMIT: {:?} (offset={}, count={})",
@@ -680,62 +1160,29 @@ impl Filesystem for LocalFilesystem {
         mode: u32,
         rdev: (u32, u32),
     ) -> Result<FileHandle> {
-        let dir_path = self.resolve_handle(dir_handle)?;
-        let file_path = dir_path.join(name);
+        let dir = self.resolve_object(dir_handle)?;
+        let file_path = dir.path.join(name);
+        let entry = self.dir_entry(&dir, name)?;
 
         debug!(
             "MKNOD: {:?}/{} type={:?} mode={:o} rdev=({}, {})",
-            dir_path, name, file_type, mode, rdev.0, rdev.1
+            dir.path, name, file_type, mode, rdev.0, rdev.1
         );
 
-        // On Unix systems, we can create special files using libc functions
-        // For portability, we'll use std::os::unix::fs
-        #[cfg(unix)]
-        {
-            use std::os::unix::fs::DirBuilderExt;
-            use std::os::unix::io::AsRawFd;
-
-            match file_type {
-                FileType::NamedPipe => {
-                    // Create FIFO using mkfifo
-                    use std::ffi::CString;
-                    let c_path = CString::new(file_path.to_str().unwrap())?;
-                    let result = unsafe { libc::mkfifo(c_path.as_ptr(), mode) };
-                    if result != 0 {
-                        return Err(anyhow::anyhow!("Failed to create FIFO: {}", std::io::Error::last_os_error()));
-                    }
-                }
-                FileType::Socket => {
-                    // Unix domain sockets are typically created by bind(), not mknod
-                    // For now, we'll create a placeholder file
-                    // A real implementation would need socket creation logic
-                    return Err(anyhow::anyhow!("Socket creation via MKNOD not fully supported"));
-                }
-                FileType::CharDevice | FileType::BlockDevice => {
-                    // Create device file using mknod
-                    use std::ffi::CString;
-                    let c_path = CString::new(file_path.to_str().unwrap())?;
-                    let dev = libc::makedev(rdev.0, rdev.1);
-                    let mode_with_type = mode | match file_type {
-                        FileType::CharDevice => libc::S_IFCHR,
-                        FileType::BlockDevice => libc::S_IFBLK,
-                        _ => 0,
-                    };
-                    let result = unsafe { libc::mknod(c_path.as_ptr(), mode_with_type, dev) };
-                    if result != 0 {
-                        return Err(anyhow::anyhow!("Failed to create device: {}", std::io::Error::last_os_error()));
-                    }
-                }
-                _ => {
-                    return Err(anyhow::anyhow!("Invalid file type for MKNOD: {:?}", file_type));
-                }
+        // A FIFO or socket node has no device number, and a socket no
+        // listener until a process binds to it
+        let (node_type, dev) = match file_type {
+            FileType::NamedPipe => (libc::S_IFIFO, 0),
+            FileType::CharDevice => (libc::S_IFCHR, libc::makedev(rdev.0, rdev.1)),
+            FileType::BlockDevice => (libc::S_IFBLK, libc::makedev(rdev.0, rdev.1)),
+            FileType::Socket => (libc::S_IFSOCK, 0),
+            _ => {
+                return Err(anyhow::anyhow!("Invalid file type for MKNOD: {:?}", file_type));
             }
-        }
-
-        #[cfg(not(unix))]
-        {
-            return Err(anyhow::anyhow!("MKNOD is only supported on Unix systems"));
-        }
+        };
+        entry
+            .mknod(mode | node_type, dev)
+            .context(format!("Failed to create special file: {:?}", file_path))?;
 
         // Create handle for the new special file
         let handle = self.handle_manager.create_handle(file_path.clone());
@@ -746,6 +1193,7 @@ impl Filesystem for LocalFilesystem {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::fsal::BackendConfig;
     use std::fs;
     use std::io::Write;
     use tempfile::TempDir;
@@ -757,6 +1205,348 @@ mod tests {
         (fs, temp_dir)
     }
 
+    #[test]
+    fn test_invalid_root() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let missing = temp_dir.path().join("missing");
+        let err = LocalFilesystem::new(&missing).err().unwrap();
+        assert!(err.to_string().contains("does not exist"), "{}", err);
+
+        let file = temp_dir.path().join("file");
+        fs::write(&file, b"").unwrap();
+        let err = LocalFilesystem::new(&file).err().unwrap();
+        assert!(err.to_string().contains("is not a directory"), "{}", err);
+
+        // Root can read any directory, so only check permissions as a user
+        let unreadable = temp_dir.path().join("unreadable");
+        fs::create_dir(&unreadable).unwrap();
+        fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o000)).unwrap();
+        if fs::read_dir(&unreadable).is_err() {
+            let err = LocalFilesystem::new(&unreadable).err().unwrap();
+            assert!(err.to_string().contains("is not readable"), "{}", err);
+        }
+        fs::set_permissions(&unreadable, fs::Permissions::from_mode(0o755)).unwrap();
+    }
+
+    #[test]
+    fn test_resolve_child() {
+        let (fs, temp_dir) = create_test_fs();
+        let root = fs.root_path.clone();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
+        let dir = root.join("dir");
+
+        assert_eq!(fs.resolve_child(&root, "file").unwrap(), root.join("file"));
+        assert_eq!(fs.resolve_child(&dir, ".").unwrap(), dir);
+        assert_eq!(fs.resolve_child(&dir, "..").unwrap(), root);
+        assert_eq!(fs.resolve_child(&root, "a..b").unwrap(), root.join("a..b"));
+
+        for name in ["../../etc/passwd", "dir/../..", "/etc/passwd", "", "a\0b"] {
+            let err = fs.resolve_child(&root, name).unwrap_err();
+            assert!(err.to_string().contains("Invalid filename"), "{:?}: {}", name, err);
+        }
+        let err = fs.resolve_child(&root, "..").unwrap_err();
+        assert!(err.to_string().contains("outside export root"), "{}", err);
+        let err = fs.resolve_child(root.parent().unwrap(), "etc").unwrap_err();
+        assert!(err.to_string().contains("outside export root"), "{}", err);
+    }
+
+    #[test]
+    fn test_lookup_dot_and_dotdot() {
+        let (fs, temp_dir) = create_test_fs();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
+
+        let root = fs.root_handle();
+        let dir = fs.lookup(&root, "dir").unwrap();
+        assert_eq!(fs.lookup(&dir, ".").unwrap(), dir);
+        assert_eq!(fs.lookup(&dir, "..").unwrap(), root);
+        assert!(fs.lookup(&root, "..").is_err());
+        assert!(fs.lookup(&dir, "../..").is_err());
+    }
+
+    #[test]
+    fn test_lookup_symlink_not_followed() {
+        let (fs, temp_dir) = create_test_fs();
+        let outside = TempDir::new().unwrap();
+        fs::write(outside.path().join("secret"), b"secret").unwrap();
+        std::os::unix::fs::symlink(outside.path(), temp_dir.path().join("escape")).unwrap();
+        std::os::unix::fs::symlink("missing", temp_dir.path().join("dangling")).unwrap();
+
+        // LOOKUP returns the link itself, even when its target is elsewhere
+        let root = fs.root_handle();
+        let link = fs.lookup(&root, "escape").unwrap();
+        assert_eq!(fs.getattr(&link).unwrap().ftype, FileType::SymbolicLink);
+        assert_eq!(fs.readlink(&link).unwrap(), outside.path().to_str().unwrap());
+        let dangling = fs.lookup(&root, "dangling").unwrap();
+        assert_eq!(fs.readlink(&dangling).unwrap(), "missing");
+
+        // ...but nothing is done through it
+        let err = fs.lookup(&link, "secret").unwrap_err();
+        assert!(err.to_string().contains("Is a symbolic link"), "{}", err);
+        assert!(fs.readdir(&link, 0, 4096).is_err());
+        assert!(fs.read(&link, 0, 100).is_err());
+    }
+
+    #[test]
+    fn test_lookup_symlink_followed() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = LocalFilesystem::new(temp_dir.path())
+            .unwrap()
+            .with_follow_symlinks(true);
+        let outside = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
+        fs::write(temp_dir.path().join("dir/file"), b"inside").unwrap();
+        std::os::unix::fs::symlink("dir", temp_dir.path().join("inner")).unwrap();
+        std::os::unix::fs::symlink(outside.path(), temp_dir.path().join("escape")).unwrap();
+
+        // A target inside the export is returned in place of the link
+        let root = fs.root_handle();
+        let inner = fs.lookup(&root, "inner").unwrap();
+        assert_eq!(fs.getattr(&inner).unwrap().ftype, FileType::Directory);
+        let file = fs.lookup(&inner, "file").unwrap();
+        assert_eq!(fs.read(&file, 0, 100).unwrap(), b"inside");
+
+        // One outside of it is refused
+        let err = fs.lookup(&root, "escape").unwrap_err();
+        assert!(err.to_string().contains("outside export root"), "{}", err);
+    }
+
+    #[test]
+    fn test_setattr_symlink_itself() {
+        let (fs, temp_dir) = create_test_fs();
+        fs::write(temp_dir.path().join("file"), b"data").unwrap();
+        std::os::unix::fs::symlink("file", temp_dir.path().join("link")).unwrap();
+        let link = fs.lookup(&fs.root_handle(), "link").unwrap();
+        let target_mtime = fs::metadata(temp_dir.path().join("file")).unwrap().mtime();
+
+        // Times and owner apply to the link, never through it to the target
+        let mtime = SetTime::ClientTime(FileTime { seconds: 1000, nseconds: 0 });
+        fs.setattr_times(&link, None, Some(mtime)).unwrap();
+        assert_eq!(fs.getattr(&link).unwrap().mtime.seconds, 1000);
+        assert_eq!(fs::metadata(temp_dir.path().join("file")).unwrap().mtime(), target_mtime);
+        fs.setattr_owner(&link, None, None).unwrap();
+
+        // A mode, which Linux doesn't keep for symlinks, is refused
+        assert!(fs.setattr_mode(&link, 0o600).is_err());
+    }
+
+    #[test]
+    fn test_read_atime_policies() {
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("file");
+        fs::write(&path, b"hello").unwrap();
+        let read_atime = |atime| {
+            // An access time older than the last modification
+            let file = fs::File::options().write(true).open(&path).unwrap();
+            file.set_times(
+                fs::FileTimes::new()
+                    .set_accessed(UNIX_EPOCH + Duration::from_secs(1000))
+                    .set_modified(UNIX_EPOCH + Duration::from_secs(2000)),
+            )
+            .unwrap();
+
+            let fs = LocalFilesystem::new(temp_dir.path()).unwrap().with_atime(atime);
+            let handle = fs.lookup(&fs.root_handle(), "file").unwrap();
+            assert_eq!(fs.read(&handle, 0, 5).unwrap(), b"hello");
+            let first = fs::metadata(&path).unwrap().atime();
+            fs.read(&handle, 0, 5).unwrap();
+            (first, fs::metadata(&path).unwrap().atime())
+        };
+
+        // noatime leaves it alone
+        assert_eq!(read_atime(AtimePolicy::Noatime), (1000, 1000));
+
+        // Whatever the mount does to the access time, reads leave ctime
+        // alone; clients would take a change for a modification
+        let ctime = || {
+            let metadata = fs::metadata(&path).unwrap();
+            (metadata.ctime(), metadata.ctime_nsec())
+        };
+        let fs = LocalFilesystem::new(temp_dir.path()).unwrap();
+        let handle = fs.lookup(&fs.root_handle(), "file").unwrap();
+        let before = ctime();
+        fs.read(&handle, 0, 5).unwrap();
+        fs.read(&handle, 0, 5).unwrap();
+        assert_eq!(ctime(), before);
+    }
+
+    #[test]
+    fn test_fileid_stable() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("file"), b"data").unwrap();
+        std::os::unix::fs::symlink("file", temp_dir.path().join("link")).unwrap();
+        let ino = fs::metadata(temp_dir.path().join("file")).unwrap().ino();
+
+        for follow_symlinks in [false, true] {
+            let fs = LocalFilesystem::new(temp_dir.path())
+                .unwrap()
+                .with_follow_symlinks(follow_symlinks);
+            let root = fs.root_handle();
+            let fileid = |handle: &FileHandle| fs.getattr(handle).unwrap().fileid;
+
+            // The inode number, whichever way the file is reached
+            let direct = fs.lookup(&root, "file").unwrap();
+            let sub = fs.lookup(&root, "sub").unwrap();
+            let parent = fs.lookup(&sub, "..").unwrap();
+            let via_parent = fs.lookup(&parent, "file").unwrap();
+            assert_eq!(fileid(&direct), ino);
+            assert_eq!(fileid(&via_parent), ino);
+
+            // READDIR reports the same ids as LOOKUP + GETATTR
+            let (entries, _) = fs.readdir(&root, 0, 100).unwrap();
+            for entry in entries {
+                let handle = fs.lookup(&root, &entry.name).unwrap();
+                assert_eq!(entry.fileid, fileid(&handle), "{}", entry.name);
+            }
+        }
+    }
+
+    #[test]
+    fn test_fsid_per_export() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("sub/file"), b"data").unwrap();
+        std::os::unix::fs::symlink("sub/file", temp_dir.path().join("link")).unwrap();
+
+        let fs = LocalFilesystem::new(temp_dir.path()).unwrap();
+        let root = fs.root_handle();
+        let fsid = fs.getattr(&root).unwrap().fsid;
+        let sub = fs.lookup(&root, "sub").unwrap();
+        let file = fs.lookup(&sub, "file").unwrap();
+        let link = fs.lookup(&root, "link").unwrap();
+        for handle in [&sub, &file, &link] {
+            assert_eq!(fs.getattr(handle).unwrap().fsid, fsid);
+        }
+
+        // Another export of the same device is told apart
+        let nested = LocalFilesystem::new(temp_dir.path().join("sub")).unwrap();
+        assert_ne!(nested.getattr(&nested.root_handle()).unwrap().fsid, fsid);
+    }
+
+    #[test]
+    fn test_fileid_across_mounts() {
+        // The export's own device keeps inode numbers as they are
+        assert_eq!(fileid(8, 1234, 8), 1234);
+
+        // Root inodes of two mounts inside the export, and the export's own
+        let ids = [fileid(8, 2, 8), fileid(9, 2, 8), fileid(10, 2, 8)];
+        assert_ne!(ids[0], ids[1]);
+        assert_ne!(ids[1], ids[2]);
+        assert_ne!(ids[0], ids[2]);
+    }
+
+    #[test]
+    fn test_configured_fsid() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        for (dir, fsid) in [(&a, 101), (&b, 202)] {
+            fs::create_dir(dir.path().join("sub")).unwrap();
+            fs::write(dir.path().join("sub/file"), b"data").unwrap();
+
+            let mut config = BackendConfig::local(dir.path());
+            config.fsid = Some(fsid);
+            let fs = config.create_filesystem().unwrap();
+            let root = fs.root_handle();
+            let sub = fs.lookup(&root, "sub").unwrap();
+            let file = fs.lookup(&sub, "file").unwrap();
+            for handle in [&root, &sub, &file] {
+                assert_eq!(fs.getattr(handle).unwrap().fsid, fsid);
+            }
+        }
+    }
+
+    #[test]
+    fn test_subtree_check_escape() {
+        // a/file is looked up, then `a` is swapped for a symlink to a
+        // directory outside the export that has a `file` of its own, as a
+        // client can do with RENAME and SYMLINK
+        let (temp_dir, outside) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        fs::write(outside.path().join("file"), b"outside").unwrap();
+
+        for subtree_check in [false, true] {
+            let root = temp_dir.path().join(format!("export-{}", subtree_check));
+            fs::create_dir_all(root.join("a")).unwrap();
+            fs::write(root.join("a/file"), b"inside").unwrap();
+
+            let mut config = BackendConfig::local(&root);
+            config.subtree_check = subtree_check;
+            let fs = config.create_filesystem().unwrap();
+            let a = fs.lookup(&fs.root_handle(), "a").unwrap();
+            let file = fs.lookup(&a, "file").unwrap();
+            assert_eq!(fs.read(&file, 0, 100).unwrap(), b"inside");
+
+            fs::rename(root.join("a"), root.join("b")).unwrap();
+            std::os::unix::fs::symlink(outside.path(), root.join("a")).unwrap();
+
+            // The handle's path no longer leads to its object, which moved
+            // behind the server's back: the handle is stale and never leads
+            // to the one outside
+            let error = fs.getattr(&file).err().unwrap().to_string();
+            assert!(error.contains("Stale file handle"), "{}", error);
+            assert!(fs.read(&file, 0, 100).is_err());
+            assert!(fs.write(&file, 0, b"escaped").is_err());
+            assert!(fs.setattr_size(&file, 0).is_err());
+            assert_eq!(fs::read(outside.path().join("file")).unwrap(), b"outside");
+        }
+    }
+
+    #[test]
+    fn test_handles_follow_rename() {
+        for subtree_check in [false, true] {
+            let temp_dir = TempDir::new().unwrap();
+            fs::create_dir(temp_dir.path().join("a")).unwrap();
+            fs::write(temp_dir.path().join("a/file"), b"data").unwrap();
+
+            let mut config = BackendConfig::local(temp_dir.path());
+            config.subtree_check = subtree_check;
+            let fs = config.create_filesystem().unwrap();
+            let root = fs.root_handle();
+            let a = fs.lookup(&root, "a").unwrap();
+            let file = fs.lookup(&a, "file").unwrap();
+
+            // Renamed through the server, the directory and what's in it
+            // keep their handles, unless they are tied to their paths
+            fs.rename(&root, "a", &root, "b").unwrap();
+            if subtree_check {
+                assert!(fs.read(&file, 0, 100).is_err());
+            } else {
+                assert_eq!(fs.read(&file, 0, 100).unwrap(), b"data");
+                assert_eq!(fs.lookup(&a, "file").unwrap(), file);
+            }
+        }
+    }
+
+    #[test]
+    fn test_subtree_check_replaced_object() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file"), b"one").unwrap();
+
+        let mut config = BackendConfig::local(temp_dir.path());
+        config.subtree_check = true;
+        let fs = config.create_filesystem().unwrap();
+        let root = fs.root_handle();
+        let old = fs.lookup(&root, "file").unwrap();
+        assert!(fs.getattr(&old).is_ok());
+        assert!(fs.getattr(&root).is_ok());
+
+        // Same name, another file: the old handle is stale, a new lookup
+        // gets a handle to the new file
+        fs::write(temp_dir.path().join("other"), b"two").unwrap();
+        fs::rename(temp_dir.path().join("other"), temp_dir.path().join("file")).unwrap();
+        let error = fs.getattr(&old).err().unwrap().to_string();
+        assert!(error.contains("Stale file handle"), "{}", error);
+
+        let new = fs.lookup(&root, "file").unwrap();
+        assert_ne!(new, old);
+        assert_eq!(fs.read(&new, 0, 100).unwrap(), b"two");
+
+        // Short handles have no room for the fileid
+        let mut config = BackendConfig::local(temp_dir.path());
+        config.subtree_check = true;
+        config.handle_len = 16;
+        assert!(config.create_filesystem().is_err());
+    }
+
     #[test]
     fn test_root_handle() {
         let (fs, _temp_dir) = create_test_fs();
@@ -765,6 +1555,23 @@ mod tests {
         assert_eq!(root.len(), 32, "Root handle should be 32 bytes");
     }
 
+    #[test]
+    fn test_configured_handle_length() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
+        fs::write(temp_dir.path().join("dir/file.txt"), b"data").unwrap();
+
+        let fs = LocalFilesystem::with_handle_len(temp_dir.path(), 64).unwrap();
+        let dir = fs.lookup(&fs.root_handle(), "dir").unwrap();
+        let file = fs.lookup(&dir, "file.txt").unwrap();
+        for handle in [fs.root_handle(), dir, file.clone()] {
+            assert_eq!(handle.len(), 64);
+        }
+        assert_eq!(fs.read(&file, 0, 4).unwrap(), b"data");
+
+        assert!(LocalFilesystem::with_handle_len(temp_dir.path(), 65).is_err());
+    }
+
     #[test]
     fn test_getattr_root() {
         let (fs, _temp_dir) = create_test_fs();
@@ -774,13 +1581,44 @@ mod tests {
         assert_eq!(attr.ftype, FileType::Directory, "Root should be a directory");
     }
 
+    #[test]
+    fn test_statfs() {
+        let (fs, _temp_dir) = create_test_fs();
+
+        let stat = fs.statfs(&fs.root_handle()).unwrap();
+        assert!(stat.tbytes > 0);
+        assert!(stat.fbytes <= stat.tbytes);
+        assert!(stat.abytes <= stat.fbytes);
+        assert!(stat.ffiles <= stat.tfiles);
+
+        let stale = vec![0u8; fs.root_handle().len()];
+        assert!(fs.statfs(&stale).is_err());
+    }
+
+    #[test]
+    fn test_pathconf_matches_host() {
+        let (fs, temp_dir) = create_test_fs();
+        let file = fs.create(&fs.root_handle(), "file.txt", 0o644, CreateMode::Unchecked).unwrap();
+
+        let conf = fs.pathconf(&file).unwrap();
+        let dir = fs::File::open(temp_dir.path()).unwrap();
+        let expected = query_pathconf(&dir, libc::_PC_NAME_MAX).unwrap();
+        assert_eq!(conf.name_max as libc::c_long, expected);
+        assert!(conf.linkmax >= 1);
+        assert!(!conf.case_insensitive);
+        assert!(conf.case_preserving);
+
+        let stale = vec![0u8; file.len()];
+        assert!(fs.pathconf(&stale).is_err());
+    }
+
     #[test]
     fn test_create_and_lookup_file() {
         let (fs, _temp_dir) = create_test_fs();
         let root = fs.root_handle();
 
         // Create a file
-        let file_handle = fs.create(&root, "test.txt", 0o644)
+        let file_handle = fs.create(&root, "test.txt", 0o644, CreateMode::Unchecked)
             .expect("Failed to create file");
 
         // Lookup the file
@@ -801,7 +1639,7 @@ mod tests {
         let root = fs.root_handle();
 
         // Create file
-        let file_handle = fs.create(&root, "data.txt", 0o644)
+        let file_handle = fs.create(&root, "data.txt", 0o644, CreateMode::Unchecked)
             .expect("Failed to create file");
 
         // Write data
@@ -854,7 +1692,7 @@ mod tests {
             .expect("Failed to create dir2");
 
         // Create file in nested directory
-        let file = fs.create(&dir2, "nested.txt", 0o644)
+        let file = fs.create(&dir2, "nested.txt", 0o644, CreateMode::Unchecked)
             .expect("Failed to create nested file");
 
         // Write and read
@@ -872,7 +1710,7 @@ mod tests {
         let root = fs.root_handle();
 
         // Create and remove file
-        fs.create(&root, "temp.txt", 0o644)
+        fs.create(&root, "temp.txt", 0o644, CreateMode::Unchecked)
             .expect("Failed to create file");
 
         fs.remove(&root, "temp.txt")
@@ -906,16 +1744,48 @@ mod tests {
         let root = fs.root_handle();
 
         // Try to create file with path traversal
-        let result = fs.create(&root, "../etc/passwd", 0o644);
+        let result = fs.create(&root, "../etc/passwd", 0o644, CreateMode::Unchecked);
         assert!(result.is_err(), "Should prevent path traversal with ..");
 
-        let result = fs.create(&root, "subdir/../file", 0o644);
+        let result = fs.create(&root, "subdir/../file", 0o644, CreateMode::Unchecked);
         assert!(result.is_err(), "Should prevent .. in filename");
 
-        let result = fs.create(&root, "dir/file", 0o644);
+        let result = fs.create(&root, "dir/file", 0o644, CreateMode::Unchecked);
         assert!(result.is_err(), "Should prevent / in filename");
     }
 
+    #[test]
+    fn test_names_validated_alike() {
+        let (fs, _temp_dir) = create_test_fs();
+        let root = fs.root_handle();
+
+        // A name LOOKUP accepts can be created, renamed and removed too
+        fs.create(&root, "a..b", 0o644, CreateMode::Unchecked).unwrap();
+        assert!(fs.lookup(&root, "a..b").is_ok());
+        fs.rename(&root, "a..b", &root, "..c").unwrap();
+        fs.link(&fs.lookup(&root, "..c").unwrap(), &root, "d..").unwrap();
+        fs.remove(&root, "..c").unwrap();
+        fs.remove(&root, "d..").unwrap();
+        fs.mkdir(&root, "e..f", 0o755).unwrap();
+        fs.rmdir(&root, "e..f").unwrap();
+
+        // ...and one it doesn't take as a plain name is refused by all
+        for name in ["", ".", "..", "x/y", "x\0y"] {
+            let errors = [
+                fs.create(&root, name, 0o644, CreateMode::Unchecked).map(|_| ()),
+                fs.mkdir(&root, name, 0o755).map(|_| ()),
+                fs.symlink(&root, name, "target").map(|_| ()),
+                fs.remove(&root, name),
+                fs.rmdir(&root, name),
+                fs.rename(&root, name, &root, "other"),
+            ];
+            for error in errors {
+                let error = error.unwrap_err().to_string();
+                assert!(error.contains("Invalid filename"), "{:?}: {}", name, error);
+            }
+        }
+    }
+
     #[test]
     fn test_lookup_nonexistent() {
         let (fs, _temp_dir) = create_test_fs();
@@ -931,7 +1801,7 @@ mod tests {
         let root = fs.root_handle();
 
         // Create file
-        fs.create(&root, "file.txt", 0o644)
+        fs.create(&root, "file.txt", 0o644, CreateMode::Unchecked)
             .expect("Failed to create file");
 
         // Lookup multiple times should return same handle
@@ -940,4 +1810,153 @@ mod tests {
 
         assert_eq!(handle1, handle2, "Multiple lookups should return same handle");
     }
+
+    #[test]
+    fn test_exclusive_create_race() {
+        let (fs, temp_dir) = create_test_fs();
+        let fs = Arc::new(fs);
+        let root = fs.root_handle();
+
+        for round in 0..20 {
+            let name = format!("race{}.txt", round);
+            let barrier = Arc::new(std::sync::Barrier::new(8));
+            let racers: Vec<_> = (0..8u8)
+                .map(|i| {
+                    let (fs, root, name, barrier) =
+                        (fs.clone(), root.clone(), name.clone(), barrier.clone());
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        (i, fs.create(&root, &name, 0o644, CreateMode::Exclusive([i + 1; 8])))
+                    })
+                })
+                .collect();
+            let results: Vec<_> = racers.into_iter().map(|t| t.join().unwrap()).collect();
+
+            // Exactly one verifier wins; every other one finds its file
+            let winners: Vec<_> = results.iter().filter(|(_, r)| r.is_ok()).collect();
+            assert_eq!(winners.len(), 1, "round {}: {} winners", round, winners.len());
+            for (_, result) in results.iter().filter(|(_, r)| r.is_err()) {
+                let error = result.as_ref().unwrap_err().to_string();
+                assert!(error.contains("exists"), "{}", error);
+            }
+
+            // The winner's retransmission gets its handle back
+            let (winner, handle) = winners[0];
+            let retry = fs.create(&root, &name, 0o644, CreateMode::Exclusive([winner + 1; 8]));
+            assert_eq!(&retry.unwrap(), handle.as_ref().unwrap());
+        }
+
+        // No staging files left behind
+        let names: Vec<_> = fs::read_dir(temp_dir.path())
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        assert!(names.iter().all(|name| name.starts_with("race")), "{:?}", names);
+    }
+
+    #[test]
+    fn test_create_guarded() {
+        let (fs, _temp_dir) = create_test_fs();
+        let root = fs.root_handle();
+
+        fs.create(&root, "guarded.txt", 0o644, CreateMode::Guarded)
+            .expect("Guarded create of a new name should succeed");
+        let result = fs.create(&root, "guarded.txt", 0o644, CreateMode::Guarded);
+        assert!(result.is_err(), "Guarded create of an existing name should fail");
+        assert!(result.unwrap_err().to_string().contains("exists"));
+    }
+
+    #[test]
+    fn test_create_exclusive_idempotent() {
+        let (fs, _temp_dir) = create_test_fs();
+        let root = fs.root_handle();
+        let verf = [1, 2, 3, 4, 5, 6, 7, 8];
+
+        let first = fs
+            .create(&root, "excl.txt", 0o644, CreateMode::Exclusive(verf))
+            .expect("Exclusive create should succeed");
+
+        // Retransmission with the same verifier returns the same file
+        let again = fs
+            .create(&root, "excl.txt", 0o644, CreateMode::Exclusive(verf))
+            .expect("Repeated exclusive create should be idempotent");
+        assert_eq!(first, again);
+
+        // A different verifier means someone else's file
+        let other = fs.create(&root, "excl.txt", 0o644, CreateMode::Exclusive([9; 8]));
+        assert!(other.is_err(), "Exclusive create with another verifier should fail");
+    }
+
+    #[test]
+    fn test_setattr_size_truncates_and_extends_sparse() {
+        let (fs, _temp_dir) = create_test_fs();
+        let root = fs.root_handle();
+        let file_handle = fs.create(&root, "sparse.bin", 0o644, CreateMode::Unchecked)
+            .expect("Failed to create file");
+        fs.write(&file_handle, 0, b"0123456789").expect("Failed to write");
+
+        // Shrinking drops the tail
+        fs.setattr_size(&file_handle, 4).expect("Failed to truncate");
+        assert_eq!(fs.getattr(&file_handle).unwrap().size, 4);
+        assert_eq!(fs.read(&file_handle, 0, 100).unwrap(), b"0123");
+
+        // Growing leaves a hole: the size changes, allocated blocks don't
+        let size = 256 * 1024 * 1024;
+        fs.setattr_size(&file_handle, size).expect("Failed to extend");
+        let attr = fs.getattr(&file_handle).unwrap();
+        assert_eq!(attr.size, size);
+        assert!(attr.used < 1024 * 1024, "Extended file should be sparse, used {}", attr.used);
+        assert_eq!(fs.read(&file_handle, 4, 4).unwrap(), vec![0u8; 4], "Hole should read as zeros");
+
+        // Setting the current size again still counts as a modification
+        let mtime = SetTime::ClientTime(FileTime { seconds: 1000, nseconds: 0 });
+        fs.setattr_times(&file_handle, None, Some(mtime)).unwrap();
+        fs.setattr_size(&file_handle, size).expect("Same-size setattr should succeed");
+        let attr = fs.getattr(&file_handle).unwrap();
+        assert_eq!(attr.size, size);
+        assert!(attr.mtime.seconds > 1000);
+    }
+
+    #[test]
+    fn test_readahead_reads_match_file() {
+        let temp_dir = TempDir::new().expect("Failed to create temp dir");
+        let fs = LocalFilesystem::new(temp_dir.path())
+            .expect("Failed to create filesystem")
+            .with_readahead(4096);
+        let root = fs.root_handle();
+        let file_handle = fs.create(&root, "seq.bin", 0o644, CreateMode::Unchecked)
+            .expect("Failed to create file");
+        let contents: Vec<u8> = (0..10_000u32).map(|i| (i % 251) as u8).collect();
+        fs.write(&file_handle, 0, &contents).expect("Failed to write");
+
+        // Sequential reads come back intact, short at end of file
+        let mut read = Vec::new();
+        while read.len() < contents.len() {
+            let chunk = fs.read(&file_handle, read.len() as u64, 1000).unwrap();
+            assert!(!chunk.is_empty());
+            read.extend(chunk);
+        }
+        assert_eq!(read, contents);
+
+        // Writes through the server are seen by the next read, prefetched
+        // or not
+        fs.read(&file_handle, 0, 1000).unwrap();
+        fs.read(&file_handle, 1000, 1000).unwrap();
+        fs.write(&file_handle, 2000, b"new").expect("Failed to write");
+        assert_eq!(&fs.read(&file_handle, 2000, 1000).unwrap()[..3], b"new");
+
+        // So are changes made directly to the file
+        fs.read(&file_handle, 3000, 1000).unwrap();
+        fs.read(&file_handle, 4000, 1000).unwrap();
+        let mut file = fs::OpenOptions::new()
+            .write(true)
+            .open(temp_dir.path().join("seq.bin"))
+            .unwrap();
+        file.seek(SeekFrom::Start(5000)).unwrap();
+        file.write_all(b"direct").unwrap();
+        file.seek(SeekFrom::End(0)).unwrap();
+        file.write_all(b"tail").unwrap();
+        assert_eq!(&fs.read(&file_handle, 5000, 1000).unwrap()[..6], b"direct");
+        assert_eq!(fs.read(&file_handle, 10_000, 1000).unwrap(), b"tail");
+    }
 }
@@ -0,0 +1,153 @@
+// Idle Page Cache Dropping
+//
+// An export of a dataset much larger than RAM fills the page cache with
+// files read once and never again, pushing out memory other processes need.
+// Each READ records when its handle was last read; a background thread
+// periodically advises the kernel (posix_fadvise POSIX_FADV_DONTNEED) to
+// drop the cached pages of files idle for longer than a threshold.
+//
+// The trade-off is throughput: a file read again after its pages were
+// dropped comes from disk instead of memory, so the threshold should be
+// well past the gap between a client's repeated reads of a file.
+
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, Weak};
+use std::thread;
+use std::time::{Duration, Instant};
+use tracing::{debug, warn};
+
+use super::super::handle::FileHandle;
+
+/// Shortest pause between sweeps, whatever is configured
+const MIN_INTERVAL: Duration = Duration::from_secs(1);
+
+/// Files read through the server, by handle, with when each was last read
+pub struct IdlePages {
+    idle: Duration,
+    last_read: Mutex<HashMap<FileHandle, (PathBuf, Instant)>>,
+}
+
+impl IdlePages {
+    /// Drop the cached pages of files not read for `idle`, checking every
+    /// `interval`
+    ///
+    /// The sweeping thread stops once the returned tracker is dropped.
+    pub fn start(idle: Duration, interval: Duration) -> Arc<Self> {
+        let pages = Arc::new(Self::new(idle));
+        let weak = Arc::downgrade(&pages);
+        let spawned = thread::Builder::new()
+            .name("page-cache-sweeper".to_string())
+            .spawn(move || sweep_until_dropped(weak, interval.max(MIN_INTERVAL)));
+        if let Err(e) = spawned {
+            warn!("Failed to start the page cache sweeper: {}", e);
+        }
+        pages
+    }
+
+    fn new(idle: Duration) -> Self {
+        Self {
+            idle,
+            last_read: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Note that the file at `path` was just read through `handle`
+    pub fn touch(&self, handle: &FileHandle, path: &Path) {
+        self.last_read
+            .lock()
+            .unwrap()
+            .insert(handle.clone(), (path.to_path_buf(), Instant::now()));
+    }
+
+    /// Drop the pages of every file idle past the threshold, returning how
+    /// many files were advised
+    pub fn sweep(&self) -> usize {
+        self.sweep_with(drop_pages)
+    }
+
+    fn sweep_with(&self, advise: impl Fn(&Path)) -> usize {
+        // Take the idle files out under the lock, advise without it
+        let idle: Vec<PathBuf> = {
+            let mut last_read = self.last_read.lock().unwrap();
+            let mut idle = Vec::new();
+            last_read.retain(|_, (path, read_at)| {
+                let keep = read_at.elapsed() < self.idle;
+                if !keep {
+                    idle.push(path.clone());
+                }
+                keep
+            });
+            idle
+        };
+        for path in &idle {
+            advise(path);
+        }
+        idle.len()
+    }
+}
+
+/// Sweep every `interval` for as long as the tracker is alive
+fn sweep_until_dropped(pages: Weak<IdlePages>, interval: Duration) {
+    loop {
+        thread::sleep(interval);
+        let Some(pages) = pages.upgrade() else {
+            return;
+        };
+        let dropped = pages.sweep();
+        if dropped > 0 {
+            debug!("Dropped cached pages of {} idle file(s)", dropped);
+        }
+    }
+}
+
+/// Advise the kernel the cached pages of `path` won't be needed
+///
+/// Dirty pages are not dropped until written back; a file that has since
+/// gone away, or been replaced by a symlink or a FIFO, is nothing to drop.
+fn drop_pages(path: &Path) {
+    use std::os::unix::fs::OpenOptionsExt;
+    use std::os::unix::io::AsRawFd;
+
+    let Ok(file) = std::fs::OpenOptions::new()
+        .read(true)
+        .custom_flags(libc::O_NOFOLLOW | libc::O_NONBLOCK)
+        .open(path)
+    else {
+        return;
+    };
+    let rc = unsafe { libc::posix_fadvise(file.as_raw_fd(), 0, 0, libc::POSIX_FADV_DONTNEED) };
+    if rc != 0 {
+        debug!(
+            "posix_fadvise(DONTNEED) on {:?} failed: {}",
+            path,
+            std::io::Error::from_raw_os_error(rc)
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::RefCell;
+
+    #[test]
+    fn test_sweep_drops_idle_files() {
+        let pages = IdlePages::new(Duration::from_secs(3600));
+        pages.touch(&vec![1; 16], Path::new("/export/a"));
+
+        // Read recently: kept
+        let advised = RefCell::new(Vec::new());
+        assert_eq!(pages.sweep_with(|path| advised.borrow_mut().push(path.to_path_buf())), 0);
+        assert!(advised.borrow().is_empty());
+
+        // Past the threshold: advised once, then forgotten
+        let pages = IdlePages::new(Duration::ZERO);
+        pages.touch(&vec![1; 16], Path::new("/export/a"));
+        pages.touch(&vec![2; 16], Path::new("/export/b"));
+        assert_eq!(pages.sweep_with(|path| advised.borrow_mut().push(path.to_path_buf())), 2);
+        advised.borrow_mut().sort();
+        assert_eq!(*advised.borrow(), vec![PathBuf::from("/export/a"), PathBuf::from("/export/b")]);
+        assert_eq!(pages.sweep_with(|_| unreachable!()), 0);
+    }
+}
@@ -0,0 +1,245 @@
+// Sequential Readahead
+//
+// Clients stream files in rtpref-sized READs, each of which would open the
+// file and read just that chunk. Once a handle's reads are seen to follow on
+// from one another, the next read also fetches the following `window` bytes
+// and keeps them, so the next READs are answered from memory. A buffer is
+// only used while the file's size, mtime and inode still match those seen
+// when it was filled; writes through the server drop it explicitly.
+
+use anyhow::Result;
+use std::collections::HashMap;
+use std::sync::Mutex;
+use std::time::Instant;
+
+use super::super::handle::FileHandle;
+
+/// Most handles tracked at once; the least recently read is dropped first
+const MAX_STREAMS: usize = 64;
+
+/// Largest window, so the buffers of one export stay under
+/// MAX_STREAMS * MAX_READAHEAD (256 MiB)
+pub const MAX_READAHEAD: u32 = 4 * 1024 * 1024;
+
+/// What identifies one version of a file's contents: inode, size, mtime
+pub type FileVersion = (u64, u64, i64, i64);
+
+/// Read state of one handle
+struct Stream {
+    /// Offset just past the last byte returned
+    next_offset: u64,
+    /// File version the buffer was read from
+    version: FileVersion,
+    /// Offset of the first prefetched byte
+    buffer_offset: u64,
+    /// Prefetched bytes
+    buffer: Vec<u8>,
+    /// Whether the buffer runs to end of file
+    eof: bool,
+    last_used: Instant,
+}
+
+impl Stream {
+    /// The buffered bytes answering a read of `count` at `offset`, if they
+    /// cover it
+    fn take(&self, offset: u64, count: u32) -> Option<&[u8]> {
+        let start = offset.checked_sub(self.buffer_offset)?;
+        let start = usize::try_from(start).ok()?;
+        if start > self.buffer.len() {
+            return None;
+        }
+        let end = start.saturating_add(count as usize);
+        if end <= self.buffer.len() {
+            Some(&self.buffer[start..end])
+        } else if self.eof {
+            Some(&self.buffer[start..])
+        } else {
+            None
+        }
+    }
+}
+
+/// Per-handle readahead buffers for one export
+pub struct Readahead {
+    window: u32,
+    streams: Mutex<HashMap<FileHandle, Stream>>,
+}
+
+impl Readahead {
+    /// Prefetch `window` bytes, at most MAX_READAHEAD, past each
+    /// sequential read
+    pub fn new(window: u32) -> Self {
+        Self {
+            window: window.min(MAX_READAHEAD),
+            streams: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Read `count` bytes at `offset` of the file behind `handle`
+    ///
+    /// `version` identifies the file's current contents; `read_at` reads
+    /// from disk, returning fewer bytes than asked only at end of file.
+    pub fn read(
+        &self,
+        handle: &FileHandle,
+        offset: u64,
+        count: u32,
+        version: FileVersion,
+        read_at: impl FnOnce(u64, u32) -> Result<Vec<u8>>,
+    ) -> Result<Vec<u8>> {
+        let sequential = {
+            let mut streams = self.streams.lock().unwrap();
+            match streams.get_mut(handle) {
+                Some(stream) if stream.version == version => {
+                    if let Some(data) = stream.take(offset, count).map(<[u8]>::to_vec) {
+                        stream.next_offset = offset + data.len() as u64;
+                        stream.last_used = Instant::now();
+                        return Ok(data);
+                    }
+                    stream.next_offset == offset
+                }
+                _ => false,
+            }
+        };
+
+        // Fetch the window along with the read once the pattern is
+        // sequential; the disk is read without holding the lock
+        let fetch = if sequential {
+            count.saturating_add(self.window)
+        } else {
+            count
+        };
+        let mut data = read_at(offset, fetch)?;
+        let eof = data.len() < fetch as usize;
+        let buffer = if data.len() > count as usize {
+            data.split_off(count as usize)
+        } else {
+            Vec::new()
+        };
+
+        let mut streams = self.streams.lock().unwrap();
+        if streams.len() >= MAX_STREAMS && !streams.contains_key(handle) {
+            let oldest = streams
+                .iter()
+                .min_by_key(|(_, stream)| stream.last_used)
+                .map(|(handle, _)| handle.clone());
+            if let Some(oldest) = oldest {
+                streams.remove(&oldest);
+            }
+        }
+        streams.insert(
+            handle.clone(),
+            Stream {
+                next_offset: offset + data.len() as u64,
+                version,
+                buffer_offset: offset + data.len() as u64,
+                buffer,
+                eof,
+                last_used: Instant::now(),
+            },
+        );
+        Ok(data)
+    }
+
+    /// Forget what was read through `handle` (its contents changed)
+    pub fn invalidate(&self, handle: &FileHandle) {
+        self.streams.lock().unwrap().remove(handle);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use anyhow::anyhow;
+    use std::cell::RefCell;
+
+    const VERSION: FileVersion = (1, 1000, 0, 0);
+
+    /// Reads served by a 1000-byte file whose bytes are their offset mod 256,
+    /// recording each disk read
+    fn disk<'a>(reads: &'a RefCell<Vec<(u64, u32)>>) -> impl Fn(u64, u32) -> Result<Vec<u8>> + 'a {
+        move |offset, count| {
+            reads.borrow_mut().push((offset, count));
+            let end = (offset + count as u64).min(1000);
+            Ok((offset..end).map(|i| i as u8).collect())
+        }
+    }
+
+    fn expected(offset: u64, count: u64) -> Vec<u8> {
+        (offset..(offset + count).min(1000)).map(|i| i as u8).collect()
+    }
+
+    #[test]
+    fn test_sequential_reads_prefetch() {
+        let readahead = Readahead::new(300);
+        let fh: FileHandle = vec![1; 16];
+        let reads = RefCell::new(Vec::new());
+
+        // The first read can't tell the pattern; the second one prefetches
+        assert_eq!(readahead.read(&fh, 0, 100, VERSION, disk(&reads)).unwrap(), expected(0, 100));
+        assert_eq!(readahead.read(&fh, 100, 100, VERSION, disk(&reads)).unwrap(), expected(100, 100));
+        assert_eq!(*reads.borrow(), vec![(0, 100), (100, 400)]);
+
+        // Served from the buffer
+        for offset in [200, 300, 400] {
+            let data = readahead.read(&fh, offset, 100, VERSION, |_, _| Err(anyhow!("disk read")));
+            assert_eq!(data.unwrap(), expected(offset, 100));
+        }
+
+        // Past the buffer: prefetch again, up to end of file
+        assert_eq!(readahead.read(&fh, 500, 100, VERSION, disk(&reads)).unwrap(), expected(500, 100));
+        assert_eq!(reads.borrow().last(), Some(&(500, 400)));
+        assert_eq!(readahead.read(&fh, 600, 100, VERSION, disk(&reads)).unwrap(), expected(600, 100));
+        assert_eq!(readahead.read(&fh, 700, 100, VERSION, disk(&reads)).unwrap(), expected(700, 100));
+
+        // This prefetch reaches end of file, which the buffer remembers
+        assert_eq!(readahead.read(&fh, 800, 200, VERSION, disk(&reads)).unwrap(), expected(800, 200));
+        assert_eq!(reads.borrow().last(), Some(&(800, 500)));
+        assert!(readahead.read(&fh, 1000, 100, VERSION, disk(&reads)).unwrap().is_empty());
+        assert_eq!(reads.borrow().len(), 4);
+    }
+
+    #[test]
+    fn test_window_is_bounded() {
+        let readahead = Readahead::new(u32::MAX);
+        let fh: FileHandle = vec![1; 16];
+        let reads = RefCell::new(Vec::new());
+
+        readahead.read(&fh, 0, 100, VERSION, disk(&reads)).unwrap();
+        readahead.read(&fh, 100, 100, VERSION, disk(&reads)).unwrap();
+        assert_eq!(reads.borrow().last(), Some(&(100, 100 + MAX_READAHEAD)));
+    }
+
+    #[test]
+    fn test_random_reads_not_prefetched() {
+        let readahead = Readahead::new(300);
+        let fh: FileHandle = vec![1; 16];
+        let reads = RefCell::new(Vec::new());
+
+        for offset in [500, 0, 300, 800] {
+            readahead.read(&fh, offset, 100, VERSION, disk(&reads)).unwrap();
+        }
+        assert_eq!(*reads.borrow(), vec![(500, 100), (0, 100), (300, 100), (800, 100)]);
+    }
+
+    #[test]
+    fn test_changed_file_drops_buffer() {
+        let readahead = Readahead::new(300);
+        let fh: FileHandle = vec![1; 16];
+        let reads = RefCell::new(Vec::new());
+
+        readahead.read(&fh, 0, 100, VERSION, disk(&reads)).unwrap();
+        readahead.read(&fh, 100, 100, VERSION, disk(&reads)).unwrap();
+
+        // A new mtime means the buffered bytes may be stale
+        let modified = (1, 1000, 5, 0);
+        readahead.read(&fh, 200, 100, modified, disk(&reads)).unwrap();
+        assert_eq!(reads.borrow().last(), Some(&(200, 100)));
+
+        // So does an explicit invalidation
+        readahead.read(&fh, 300, 100, modified, disk(&reads)).unwrap();
+        readahead.invalidate(&fh);
+        readahead.read(&fh, 400, 100, modified, disk(&reads)).unwrap();
+        assert_eq!(*reads.borrow(), vec![(0, 100), (100, 400), (200, 100), (300, 400), (400, 100)]);
+    }
+}
@@ -2,9 +2,25 @@
 //
 // Provides a common interface for filesystem operations, abstracting the
 // underlying storage backend (local filesystem, network filesystem, etc.)
-
+//
+// Backends are synchronous and may block (the local backend uses std::fs).
+// The RPC servers never call them on a runtime thread: every call is answered
+// on tokio's blocking pool (RpcDispatcher::dispatch_blocking), so a large or
+// slow READ/WRITE only occupies a blocking thread while other connections
+// keep being served.
+//
+// Mutations must move timestamps as POSIX does (data changes advance mtime
+// and ctime, attribute changes ctime, entry changes the directory's mtime and
+// ctime) since that is what invalidates client caches. Procedures fetch
+// attributes again after every mutation for their post-op attributes, so a
+// backend only has to keep getattr current; the local backend leaves this to
+// the host filesystem.
+
+pub mod cache;
+pub mod coalesce;
 pub mod handle;
 pub mod local;
+pub mod registry;
 
 // Future backends (uncomment when implemented)
 // #[cfg(feature = "s3")]
@@ -15,10 +31,71 @@ pub mod local;
 // pub mod memory;
 
 use anyhow::Result;
+use serde::Deserialize;
 use std::path::PathBuf;
+use std::time::Duration;
+
+pub use cache::{CacheStats, CachingFilesystem};
+pub use coalesce::CoalescingFilesystem;
+pub use handle::{FileHandle, HandleManager, DEFAULT_HANDLE_LEN, HANDLE_FORMAT_VERSION, MIN_SUBTREE_CHECK_HANDLE_LEN};
+pub use local::{LocalFilesystem, MAX_READAHEAD};
+pub use registry::{register_backend, BackendFactory};
+
+/// Optional operations supported by a backend
+///
+/// The NFS layer consults this bitset before calling the backend, so
+/// operations a backend cannot perform fail with NFS3ERR_NOTSUPP instead of
+/// a generic I/O error.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capabilities(u32);
+
+impl Capabilities {
+    /// Symbolic links (SYMLINK)
+    pub const SYMLINK: Self = Self(1 << 0);
+    /// Hard links (LINK)
+    pub const HARD_LINK: Self = Self(1 << 1);
+    /// Special files: devices, FIFOs, sockets (MKNOD)
+    pub const MKNOD: Self = Self(1 << 2);
+    /// Setting file times to arbitrary values (SETATTR)
+    pub const SET_TIME: Self = Self(1 << 3);
+    /// Same PATHCONF values for every object of the filesystem
+    pub const HOMOGENEOUS: Self = Self(1 << 4);
+
+    /// Every optional operation
+    pub const fn all() -> Self {
+        Self(
+            Self::SYMLINK.0
+                | Self::HARD_LINK.0
+                | Self::MKNOD.0
+                | Self::SET_TIME.0
+                | Self::HOMOGENEOUS.0,
+        )
+    }
+
+    /// Check whether all capabilities in `other` are present
+    pub const fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
 
-pub use handle::{FileHandle, HandleManager};
-pub use local::LocalFilesystem;
+    /// Remove the capabilities in `other`
+    pub const fn without(self, other: Self) -> Self {
+        Self(self.0 & !other.0)
+    }
+}
+
+/// How `create` treats an existing file with the same name
+///
+/// Mirrors the NFSv3 createmode3 values.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CreateMode {
+    /// Create the file, truncating it if it already exists
+    Unchecked,
+    /// Fail if the file already exists
+    Guarded,
+    /// Fail if the file already exists, unless it was created by an earlier
+    /// call with the same verifier (so retransmissions are idempotent)
+    Exclusive([u8; 8]),
+}
 
 /// File attributes
 ///
@@ -42,7 +119,7 @@ pub struct FileAttributes {
     pub used: u64,
     /// Device ID (for special files)
     pub rdev: (u32, u32),
-    /// Filesystem ID
+    /// Filesystem ID, the same for every object in an export
     pub fsid: u64,
     /// File ID (inode number)
     pub fileid: u64,
@@ -73,6 +150,15 @@ pub struct FileTime {
     pub nseconds: u32,
 }
 
+/// New value for a timestamp changed by SETATTR
+#[derive(Debug, Clone, Copy)]
+pub enum SetTime {
+    /// The server's current time
+    ServerTime,
+    /// A time supplied by the client
+    ClientTime(FileTime),
+}
+
 /// Directory entry
 ///
 /// Represents a single entry in a directory listing.
@@ -86,16 +172,90 @@ pub struct DirEntry {
     pub file_type: FileType,
 }
 
+/// Space and inode totals of a filesystem (see statvfs(3))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FsStat {
+    /// Total size in bytes
+    pub tbytes: u64,
+    /// Free space in bytes
+    pub fbytes: u64,
+    /// Free space available to unprivileged users in bytes
+    pub abytes: u64,
+    /// Total number of file slots (inodes)
+    pub tfiles: u64,
+    /// Free file slots
+    pub ffiles: u64,
+    /// Free file slots available to unprivileged users
+    pub afiles: u64,
+}
+
+/// POSIX path configuration of a filesystem object (see pathconf(3))
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct PathConf {
+    /// Maximum number of hard links to an object
+    pub linkmax: u32,
+    /// Maximum length of a file name component
+    pub name_max: u32,
+    /// Names longer than name_max are rejected rather than truncated
+    pub no_trunc: bool,
+    /// Only a privileged user may change file ownership
+    pub chown_restricted: bool,
+    /// Name lookups ignore case
+    pub case_insensitive: bool,
+    /// Names keep the case they were created with
+    pub case_preserving: bool,
+}
+
+impl Default for PathConf {
+    /// Typical Unix values, for backends that cannot query their storage
+    fn default() -> Self {
+        Self {
+            linkmax: 255,
+            name_max: 255,
+            no_trunc: true,
+            chown_restricted: true,
+            case_insensitive: false,
+            case_preserving: true,
+        }
+    }
+}
+
+/// An error carrying `errno`, from which the NFS layer derives the status
+/// it replies with, described by `message`
+pub fn os_error(errno: i32, message: String) -> anyhow::Error {
+    anyhow::Error::new(std::io::Error::from_raw_os_error(errno)).context(message)
+}
+
 /// Filesystem trait
 ///
 /// This trait defines the interface that all filesystem backends must implement.
 /// It provides operations for file/directory access, metadata queries, and I/O.
+/// Failures carry the `std::io::Error` of their cause (see `os_error`), so
+/// the NFS layer can map them to a status.
 pub trait Filesystem: Send + Sync {
+    /// Optional operations this backend supports
+    ///
+    /// Defaults to all of them; backends that cannot create symlinks, hard
+    /// links or special files should override this.
+    fn capabilities(&self) -> Capabilities {
+        Capabilities::all()
+    }
+
     /// Get the root file handle
     ///
     /// This is typically the starting point for all filesystem operations.
     fn root_handle(&self) -> FileHandle;
 
+    /// Whether `handle` was issued by this backend instance (and is still
+    /// valid), so calls can be routed to the export it belongs to
+    fn owns_handle(&self, handle: &FileHandle) -> bool;
+
+    /// Forget whatever is cached about the files, so nothing decided under
+    /// a previous configuration outlives a reload
+    ///
+    /// Backends without caches need not override this.
+    fn drop_caches(&self) {}
+
     /// Look up a name in a directory
     ///
     /// Given a directory handle and a filename, return the file handle
@@ -111,6 +271,9 @@ pub trait Filesystem: Send + Sync {
 
     /// Get file attributes
     ///
+    /// Symbolic links are not followed: a symlink handle reports the link
+    /// itself (FileType::Symlink).
+    ///
     /// # Arguments
     /// * `handle` - File handle
     ///
@@ -118,6 +281,26 @@ pub trait Filesystem: Send + Sync {
     /// File attributes
     fn getattr(&self, handle: &FileHandle) -> Result<FileAttributes>;
 
+    /// Get space and inode usage of the filesystem holding an object
+    ///
+    /// # Arguments
+    /// * `handle` - File handle
+    ///
+    /// # Returns
+    /// Totals of the backing storage
+    fn statfs(&self, handle: &FileHandle) -> Result<FsStat>;
+
+    /// Get the path configuration of the filesystem holding an object
+    ///
+    /// Defaults to typical Unix values; backends that can ask their storage
+    /// should override this.
+    ///
+    /// # Arguments
+    /// * `handle` - File handle
+    fn pathconf(&self, _handle: &FileHandle) -> Result<PathConf> {
+        Ok(PathConf::default())
+    }
+
     /// Read data from a file
     ///
     /// # Arguments
@@ -142,17 +325,24 @@ pub trait Filesystem: Send + Sync {
 
     /// Write data to a file
     ///
+    /// Data is not required to reach stable storage; callers that need
+    /// durability follow up with `commit`. Advances the file's mtime and
+    /// ctime but not its atime.
+    ///
     /// # Arguments
     /// * `handle` - File handle
-    /// * `offset` - Starting offset
+    /// * `offset` - Starting offset (writing past EOF leaves a hole)
     /// * `data` - Data to write
     ///
     /// # Returns
-    /// Number of bytes actually written
+    /// Number of bytes actually written (may be short)
     fn write(&self, handle: &FileHandle, offset: u64, data: &[u8]) -> Result<u32>;
 
     /// Set file size (truncate/extend)
     ///
+    /// Extending should leave a hole rather than write zeros, so clients
+    /// pre-allocating large files don't consume space up front.
+    ///
     /// # Arguments
     /// * `handle` - File handle
     /// * `size` - New size in bytes
@@ -173,16 +363,37 @@ pub trait Filesystem: Send + Sync {
     /// * `gid` - New group ID (None to keep current)
     fn setattr_owner(&self, handle: &FileHandle, uid: Option<u32>, gid: Option<u32>) -> Result<()>;
 
+    /// Set file access and/or modification time
+    ///
+    /// # Arguments
+    /// * `handle` - File handle
+    /// * `atime` - New access time (None to keep current)
+    /// * `mtime` - New modification time (None to keep current)
+    fn setattr_times(
+        &self,
+        handle: &FileHandle,
+        atime: Option<SetTime>,
+        mtime: Option<SetTime>,
+    ) -> Result<()>;
+
     /// Create a file
     ///
     /// # Arguments
     /// * `dir_handle` - Directory handle
     /// * `name` - Name of new file
     /// * `mode` - File permissions
+    /// * `how` - Behaviour when the name already exists
     ///
     /// # Returns
-    /// File handle of created file
-    fn create(&self, dir_handle: &FileHandle, name: &str, mode: u32) -> Result<FileHandle>;
+    /// File handle of created file (or of the existing file for a repeated
+    /// exclusive create with the same verifier)
+    fn create(
+        &self,
+        dir_handle: &FileHandle,
+        name: &str,
+        mode: u32,
+        how: CreateMode,
+    ) -> Result<FileHandle>;
 
     /// Remove a file
     ///
@@ -211,6 +422,10 @@ pub trait Filesystem: Send + Sync {
 
     /// Rename a file or directory
     ///
+    /// The target may live in another directory. An existing target is
+    /// replaced following POSIX rules; a non-empty target directory fails
+    /// with "Directory not empty".
+    ///
     /// # Arguments
     /// * `from_dir_handle` - Source directory handle
     /// * `from_name` - Source name
@@ -287,29 +502,49 @@ pub trait Filesystem: Send + Sync {
     ) -> Result<FileHandle>;
 }
 
-/// Filesystem backend types
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-pub enum BackendType {
-    /// Local filesystem backend
-    Local,
-    /// S3 backend (future)
-    #[allow(dead_code)]
-    S3,
-    /// Ceph backend (future)
-    #[allow(dead_code)]
-    Ceph,
-    /// In-memory backend (testing)
-    #[allow(dead_code)]
-    Memory,
+/// When READ updates a file's access time
+///
+/// Named in configuration by its lowercase name (`atime = "noatime"`).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum AtimePolicy {
+    /// As the host filesystem's mount options say (relatime on most hosts);
+    /// "relatime" is accepted for it too. The server never sets access
+    /// times itself: utimensat would bump ctime as well, and clients take a
+    /// changed ctime for a modification and drop their caches.
+    #[default]
+    #[serde(alias = "relatime")]
+    Strict,
+    /// Never, where the OS allows it (O_NOATIME is limited to the file's
+    /// owner)
+    Noatime,
 }
 
 /// Filesystem backend configuration
 #[derive(Debug, Clone)]
 pub struct BackendConfig {
-    /// Backend type
-    pub backend_type: BackendType,
+    /// Name the backend is registered under ("local", or one added with
+    /// register_backend)
+    pub backend: String,
     /// Root path for local backend
     pub local_root: Option<PathBuf>,
+    /// Length of every file handle issued (bytes, at most 64)
+    pub handle_len: usize,
+    /// Bytes prefetched past sequential reads (local backend, 0 disables)
+    pub readahead: u32,
+    /// Resolve symlinks on LOOKUP (local backend)
+    pub follow_symlinks: bool,
+    /// Drop cached pages of files idle this long (local backend, zero
+    /// disables) and how often to look for them
+    pub drop_cache_idle: Duration,
+    pub drop_cache_interval: Duration,
+    /// When READ updates access times (local backend)
+    pub atime: AtimePolicy,
+    /// fsid reported for every object instead of the backend's own
+    pub fsid: Option<u64>,
+    /// Check each handle still names its object, inside the export (local
+    /// backend)
+    pub subtree_check: bool,
     /// S3 configuration (future)
     #[allow(dead_code)]
     pub s3_config: Option<S3Config>,
@@ -338,36 +573,24 @@ impl BackendConfig {
     /// Create a local filesystem backend configuration
     pub fn local<P: Into<PathBuf>>(root: P) -> Self {
         Self {
-            backend_type: BackendType::Local,
+            backend: "local".to_string(),
             local_root: Some(root.into()),
+            handle_len: DEFAULT_HANDLE_LEN,
+            readahead: 0,
+            follow_symlinks: false,
+            drop_cache_idle: Duration::ZERO,
+            drop_cache_interval: Duration::from_secs(60),
+            atime: AtimePolicy::default(),
+            fsid: None,
+            subtree_check: false,
             s3_config: None,
             ceph_config: None,
         }
     }
 
-    /// Create filesystem instance from configuration
+    /// Create filesystem instance from configuration, with the backend
+    /// registered under `self.backend`
     pub fn create_filesystem(&self) -> Result<Box<dyn Filesystem>> {
-        match self.backend_type {
-            BackendType::Local => {
-                let root = self
-                    .local_root
-                    .as_ref()
-                    .ok_or_else(|| anyhow::anyhow!("Local root path not configured"))?;
-                let fs = LocalFilesystem::new(root)?;
-                Ok(Box::new(fs))
-            }
-            BackendType::S3 => {
-                // TODO: Implement S3 backend
-                Err(anyhow::anyhow!("S3 backend not yet implemented"))
-            }
-            BackendType::Ceph => {
-                // TODO: Implement Ceph backend
-                Err(anyhow::anyhow!("Ceph backend not yet implemented"))
-            }
-            BackendType::Memory => {
-                // TODO: Implement memory backend
-                Err(anyhow::anyhow!("Memory backend not yet implemented"))
-            }
-        }
+        registry::create_backend(&self.backend, self)
     }
 }
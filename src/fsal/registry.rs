@@ -0,0 +1,132 @@
+// Backend Registry
+//
+// Backends are selected by the name configured in `fsal.backend`. The
+// built-in ones are registered on first use; a crate embedding the server
+// registers its own with register_backend() before calling server::run(),
+// and it is then selected the same way, without patching this crate.
+
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::sync::{Arc, OnceLock, RwLock};
+
+use super::{BackendConfig, Filesystem, LocalFilesystem};
+
+/// Builds a backend for one export
+pub trait BackendFactory: Send + Sync {
+    /// Create the backend serving the export described by `config`
+    fn create(&self, config: &BackendConfig) -> Result<Box<dyn Filesystem>>;
+}
+
+impl<F> BackendFactory for F
+where
+    F: Fn(&BackendConfig) -> Result<Box<dyn Filesystem>> + Send + Sync,
+{
+    fn create(&self, config: &BackendConfig) -> Result<Box<dyn Filesystem>> {
+        self(config)
+    }
+}
+
+type Registry = RwLock<BTreeMap<String, Arc<dyn BackendFactory>>>;
+
+fn registry() -> &'static Registry {
+    static REGISTRY: OnceLock<Registry> = OnceLock::new();
+    REGISTRY.get_or_init(|| {
+        let mut backends: BTreeMap<String, Arc<dyn BackendFactory>> = BTreeMap::new();
+        backends.insert("local".to_string(), Arc::new(local_backend));
+        RwLock::new(backends)
+    })
+}
+
+/// Make `factory` the backend selected by `backend = "<name>"`, replacing
+/// any backend registered under that name
+pub fn register_backend(name: &str, factory: impl BackendFactory + 'static) {
+    registry()
+        .write()
+        .unwrap()
+        .insert(name.to_string(), Arc::new(factory));
+}
+
+/// Whether a backend is registered under `name`
+pub fn is_registered(name: &str) -> bool {
+    registry().read().unwrap().contains_key(name)
+}
+
+/// Names of every registered backend, sorted
+pub fn registered_backends() -> Vec<String> {
+    registry().read().unwrap().keys().cloned().collect()
+}
+
+/// Create a backend with the factory registered under `name`
+pub fn create_backend(name: &str, config: &BackendConfig) -> Result<Box<dyn Filesystem>> {
+    // Not holding the lock while the backend starts up
+    let factory = registry().read().unwrap().get(name).cloned();
+    let factory = factory.ok_or_else(|| {
+        anyhow!(
+            "Unknown FSAL backend {:?} (registered: {})",
+            name,
+            registered_backends().join(", ")
+        )
+    })?;
+    factory.create(config)
+}
+
+/// The built-in `local` backend: a directory of the host
+fn local_backend(config: &BackendConfig) -> Result<Box<dyn Filesystem>> {
+    let root = config
+        .local_root
+        .as_ref()
+        .ok_or_else(|| anyhow!("Local root path not configured"))?;
+    let mut fs = LocalFilesystem::with_handle_len(root, config.handle_len)?
+        .with_readahead(config.readahead)
+        .with_follow_symlinks(config.follow_symlinks)
+        .with_idle_page_drop(config.drop_cache_idle, config.drop_cache_interval)
+        .with_atime(config.atime)
+        .with_subtree_check(config.subtree_check)?;
+    if let Some(fsid) = config.fsid {
+        fs = fs.with_fsid(fsid);
+    }
+    Ok(Box::new(fs))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_register_and_select_backend() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(!is_registered("test-dummy"));
+
+        // A stand-in: local, but with 20-byte handles showing who built it
+        register_backend("test-dummy", |config: &BackendConfig| -> Result<Box<dyn Filesystem>> {
+            let fs = LocalFilesystem::with_handle_len(config.local_root.as_ref().unwrap(), 20)?;
+            Ok(Box::new(fs))
+        });
+        assert!(is_registered("test-dummy"));
+        assert!(registered_backends().contains(&"test-dummy".to_string()));
+
+        let mut config = BackendConfig::local(temp_dir.path());
+        config.backend = "test-dummy".to_string();
+        let fs = config.create_filesystem().unwrap();
+        assert_eq!(fs.root_handle().len(), 20);
+    }
+
+    #[test]
+    fn test_builtin_and_unknown_backends() {
+        let temp_dir = TempDir::new().unwrap();
+        assert!(is_registered("local"));
+        // Not implemented, so not offered
+        for name in ["s3", "ceph", "memory"] {
+            assert!(!is_registered(name), "{} has no backend", name);
+        }
+        assert!(create_backend("local", &BackendConfig::local(temp_dir.path())).is_ok());
+
+        let error = create_backend("nfs", &BackendConfig::local(temp_dir.path()))
+            .err()
+            .unwrap()
+            .to_string();
+        assert!(error.contains("Unknown FSAL backend \"nfs\""), "{}", error);
+        assert!(error.contains("local"), "{}", error);
+    }
+}
@@ -0,0 +1,132 @@
+// Health Check Endpoint
+//
+// Readiness for container orchestrators and load balancers, on its own HTTP
+// port. GET /healthz answers 200 once the RPC listeners are bound and every
+// export root can be read, and 503 otherwise; from the moment shutdown
+// begins it answers 503 so traffic is moved elsewhere while requests drain.
+
+use anyhow::Result;
+use std::sync::Arc;
+use tokio::net::TcpListener;
+use tokio::sync::watch;
+use tracing::{debug, info, warn};
+
+use crate::exports::SharedExports;
+use crate::metrics::{read_request, respond};
+use crate::rpc::server::ACCEPT_ERROR_BACKOFF;
+
+/// What the server must have reached to report itself ready
+pub struct Readiness {
+    /// One flag per RPC listener, set once it is bound
+    listeners: Vec<watch::Receiver<bool>>,
+    exports: SharedExports,
+    shutdown: watch::Receiver<bool>,
+}
+
+impl Readiness {
+    pub fn new(
+        listeners: Vec<watch::Receiver<bool>>,
+        exports: SharedExports,
+        shutdown: watch::Receiver<bool>,
+    ) -> Self {
+        Self {
+            listeners,
+            exports,
+            shutdown,
+        }
+    }
+
+    /// Why the server can't take requests, or None when it can
+    ///
+    /// Reads each export's root attributes, so this may block on the
+    /// filesystem.
+    fn check(&self) -> Option<String> {
+        if *self.shutdown.borrow() {
+            return Some("shutting down".to_string());
+        }
+        if !self.listeners.iter().all(|bound| *bound.borrow()) {
+            return Some("RPC listeners not bound yet".to_string());
+        }
+        let exports = self.exports.current();
+        for export in exports.iter() {
+            let filesystem = &export.filesystem;
+            if let Err(e) = filesystem.getattr(&filesystem.root_handle()) {
+                return Some(format!("export {} not accessible: {}", export.config.path, e));
+            }
+        }
+        None
+    }
+}
+
+/// Serve `/healthz` over HTTP on `addr`
+pub async fn serve(addr: String, readiness: Readiness) -> Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Health endpoint listening on http://{}/healthz", addr);
+
+    let readiness = Arc::new(readiness);
+    loop {
+        let (mut socket, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Health endpoint failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let readiness = readiness.clone();
+        tokio::spawn(async move {
+            let answer = async {
+                let request_line = read_request(&mut socket).await?;
+                let mut parts = request_line.split_whitespace();
+                let (status, body) = match (parts.next(), parts.next()) {
+                    (Some("GET"), Some("/healthz")) => {
+                        match tokio::task::spawn_blocking(move || readiness.check()).await? {
+                            None => ("200 OK", "OK\n".to_string()),
+                            Some(reason) => ("503 Service Unavailable", format!("{}\n", reason)),
+                        }
+                    }
+                    _ => ("404 Not Found", "Not found\n".to_string()),
+                };
+                respond(socket, status, "text/plain", &body).await
+            };
+            if let Err(e) = answer.await {
+                debug!("Health check from {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::exports::{Export, ExportTable};
+    use crate::fsal::BackendConfig;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_readiness() {
+        let root = TempDir::new().unwrap();
+        let filesystem = BackendConfig::local(root.path()).create_filesystem().unwrap();
+        let exports = SharedExports::new(ExportTable::new(vec![Export::new(
+            ExportConfig::default(),
+            Arc::from(filesystem),
+        )]));
+        let (bound_tx, bound) = watch::channel(false);
+        let (shutdown_tx, shutdown) = watch::channel(false);
+        let readiness = Readiness::new(vec![bound], exports, shutdown);
+
+        assert!(readiness.check().unwrap().contains("not bound"));
+        bound_tx.send_replace(true);
+        assert_eq!(readiness.check(), None);
+
+        // A root that can no longer be read makes the server unready
+        let path = root.path().to_path_buf();
+        drop(root);
+        assert!(!path.exists());
+        assert!(readiness.check().unwrap().contains("not accessible"));
+
+        shutdown_tx.send_replace(true);
+        assert_eq!(readiness.check().as_deref(), Some("shutting down"));
+    }
+}
@@ -0,0 +1,195 @@
+// Client Host Names
+//
+// Reverse DNS for export rules written as host names or wildcard patterns
+// ("*.corp.example.com"). A name only counts when it resolves back to the
+// client's address, so a client controlling its own PTR record cannot claim
+// someone else's name. Results, failures included, are cached for a while
+// since every MOUNT and NFS call checks them.
+
+use std::collections::HashMap;
+use std::ffi::CStr;
+use std::net::{IpAddr, SocketAddr, ToSocketAddrs};
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+use tracing::debug;
+
+/// How long a resolved name (or the lack of one) is trusted
+const HOSTNAME_TTL: Duration = Duration::from_secs(300);
+
+/// Longest host name getnameinfo() returns, including the terminator
+const MAX_HOSTNAME: usize = 1025;
+
+/// Reverse lookups cached per client address
+pub struct HostnameCache {
+    ttl: Duration,
+    entries: Mutex<HashMap<IpAddr, (Option<String>, Instant)>>,
+}
+
+impl HostnameCache {
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl,
+            entries: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// The verified host name of `client`, or None if it has none
+    pub fn hostname(&self, client: IpAddr) -> Option<String> {
+        self.get_or_resolve(client.to_canonical(), resolve)
+    }
+
+    fn get_or_resolve(
+        &self,
+        client: IpAddr,
+        resolve: impl FnOnce(IpAddr) -> Option<String>,
+    ) -> Option<String> {
+        let cached = self
+            .entries
+            .lock()
+            .unwrap()
+            .get(&client)
+            .filter(|(_, resolved_at)| resolved_at.elapsed() < self.ttl)
+            .map(|(name, _)| name.clone());
+        if let Some(name) = cached {
+            return name;
+        }
+
+        // Resolve without holding the lock; a concurrent miss for the same
+        // client only costs a duplicate lookup
+        let name = resolve(client);
+        debug!("Client {} resolves to {:?}", client, name);
+        let mut entries = self.entries.lock().unwrap();
+        entries.retain(|_, (_, resolved_at)| resolved_at.elapsed() < self.ttl);
+        entries.insert(client, (name.clone(), Instant::now()));
+        name
+    }
+}
+
+/// Process-wide cache used for export access checks
+pub fn cache() -> &'static HostnameCache {
+    static CACHE: OnceLock<HostnameCache> = OnceLock::new();
+    CACHE.get_or_init(|| HostnameCache::new(HOSTNAME_TTL))
+}
+
+/// Whether `hostname` matches `pattern`
+///
+/// Case-insensitive; `*` matches any run of characters (including dots) and
+/// `?` a single character, as in /etc/exports.
+pub fn matches(pattern: &str, hostname: &str) -> bool {
+    let pattern: Vec<char> = pattern.trim_end_matches('.').to_ascii_lowercase().chars().collect();
+    let name: Vec<char> = hostname.trim_end_matches('.').to_ascii_lowercase().chars().collect();
+
+    // Iterative wildcard match, backtracking to the last `*`
+    let (mut p, mut n) = (0, 0);
+    let mut star: Option<(usize, usize)> = None;
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            star = Some((p, n));
+            p += 1;
+        } else if let Some((star_p, star_n)) = star {
+            p = star_p + 1;
+            n = star_n + 1;
+            star = Some((star_p, star_n + 1));
+        } else {
+            return false;
+        }
+    }
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Reverse-resolve `client` and confirm the name maps back to it
+fn resolve(client: IpAddr) -> Option<String> {
+    let name = reverse_lookup(client)?;
+    let confirmed = (name.as_str(), 0)
+        .to_socket_addrs()
+        .ok()?
+        .any(|addr| addr.ip().to_canonical() == client);
+    if !confirmed {
+        debug!("Ignoring name {} for {}: it does not resolve back", name, client);
+    }
+    confirmed.then_some(name)
+}
+
+/// PTR lookup of `client` through the system resolver
+fn reverse_lookup(client: IpAddr) -> Option<String> {
+    let mut addr: libc::sockaddr_storage = unsafe { std::mem::zeroed() };
+    let len = match SocketAddr::new(client, 0) {
+        SocketAddr::V4(v4) => {
+            let sin = unsafe { &mut *(&mut addr as *mut _ as *mut libc::sockaddr_in) };
+            sin.sin_family = libc::AF_INET as libc::sa_family_t;
+            sin.sin_addr.s_addr = u32::from_ne_bytes(v4.ip().octets());
+            std::mem::size_of::<libc::sockaddr_in>()
+        }
+        SocketAddr::V6(v6) => {
+            let sin6 = unsafe { &mut *(&mut addr as *mut _ as *mut libc::sockaddr_in6) };
+            sin6.sin6_family = libc::AF_INET6 as libc::sa_family_t;
+            sin6.sin6_addr.s6_addr = v6.ip().octets();
+            std::mem::size_of::<libc::sockaddr_in6>()
+        }
+    };
+
+    let mut host = [0 as libc::c_char; MAX_HOSTNAME];
+    let rc = unsafe {
+        libc::getnameinfo(
+            &addr as *const _ as *const libc::sockaddr,
+            len as libc::socklen_t,
+            host.as_mut_ptr(),
+            host.len() as libc::socklen_t,
+            std::ptr::null_mut(),
+            0,
+            libc::NI_NAMEREQD,
+        )
+    };
+    if rc != 0 {
+        return None;
+    }
+    let host = unsafe { CStr::from_ptr(host.as_ptr()) };
+    host.to_str().ok().map(str::to_string)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_matches() {
+        assert!(matches("backup.example.com", "backup.example.com"));
+        assert!(matches("backup.example.com", "Backup.Example.COM."));
+        assert!(!matches("backup.example.com", "backup2.example.com"));
+
+        assert!(matches("*.corp.example.com", "ws1.corp.example.com"));
+        assert!(matches("*.corp.example.com", "a.b.corp.example.com"));
+        assert!(!matches("*.corp.example.com", "corp.example.com"));
+        assert!(!matches("*.corp.example.com", "ws1.corp.example.com.evil.net"));
+
+        assert!(matches("ws?.corp.example.com", "ws7.corp.example.com"));
+        assert!(!matches("ws?.corp.example.com", "ws10.corp.example.com"));
+        assert!(matches("ws*", "ws10.corp.example.com"));
+    }
+
+    #[test]
+    fn test_cache_expires() {
+        let ip: IpAddr = "192.0.2.1".parse().unwrap();
+        let cache = HostnameCache::new(Duration::from_secs(60));
+
+        let name = cache.get_or_resolve(ip, |_| Some("a.example.com".to_string()));
+        assert_eq!(name.as_deref(), Some("a.example.com"));
+        // Cached: the resolver is not asked again
+        let name = cache.get_or_resolve(ip, |_| unreachable!());
+        assert_eq!(name.as_deref(), Some("a.example.com"));
+
+        // Failures are cached too
+        let other: IpAddr = "192.0.2.2".parse().unwrap();
+        assert_eq!(cache.get_or_resolve(other, |_| None), None);
+        assert_eq!(cache.get_or_resolve(other, |_| unreachable!()), None);
+
+        // Past the TTL the name is looked up again
+        let cache = HostnameCache::new(Duration::ZERO);
+        cache.get_or_resolve(ip, |_| Some("a.example.com".to_string()));
+        let name = cache.get_or_resolve(ip, |_| Some("b.example.com".to_string()));
+        assert_eq!(name.as_deref(), Some("b.example.com"));
+    }
+}
@@ -2,12 +2,24 @@
 //
 // This library provides the core components for building an NFSv3 server
 
+pub mod config;
+pub mod exports;
 pub mod fsal;
+pub mod health;
+pub mod hostnames;
+pub mod metrics;
 pub mod mount;
 pub mod nfs;
+pub mod nlm;
+pub mod nsm;
 pub mod portmap;
 pub mod protocol;
 pub mod rpc;
+pub mod server;
+pub mod telemetry;
+
+#[cfg(test)]
+mod test_support;
 
 // Re-export commonly used types
 pub use fsal::{FileHandle, Filesystem, LocalFilesystem};
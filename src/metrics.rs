@@ -0,0 +1,433 @@
+// Prometheus Metrics
+//
+// Call counts, error counts and latency histograms for every RPC answered by
+// the dispatcher, keyed by program and procedure, plus TCP connection gauges
+// and attribute cache counters.
+// Served in the Prometheus text format on a separate HTTP port.
+
+use anyhow::{anyhow, Result};
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tracing::{debug, info, warn};
+
+use crate::fsal::CacheStats;
+use crate::rpc::conn_limit::ConnectionSlots;
+use crate::rpc::server::ACCEPT_ERROR_BACKOFF;
+
+/// Upper bounds of the latency histogram buckets (seconds)
+const LATENCY_BUCKETS: [f64; 10] = [
+    0.0001, 0.0005, 0.001, 0.005, 0.01, 0.05, 0.1, 0.5, 1.0, 5.0,
+];
+
+/// NFSv3 procedure names, indexed by procedure number
+const NFS_PROCEDURES: [&str; 22] = [
+    "NULL", "GETATTR", "SETATTR", "LOOKUP", "ACCESS", "READLINK", "READ", "WRITE", "CREATE",
+    "MKDIR", "SYMLINK", "MKNOD", "REMOVE", "RMDIR", "RENAME", "LINK", "READDIR",
+    "READDIRPLUS", "FSSTAT", "FSINFO", "PATHCONF", "COMMIT",
+];
+
+/// Key of the calls to any other program, or to procedures past those the
+/// served programs have, so clients can't grow the table at will
+const OTHER: (u32, u32) = (u32::MAX, u32::MAX);
+
+/// Every served program's procedure numbers lie below this (NLM's go up
+/// to 23)
+const MAX_PROCEDURES: u32 = 32;
+
+/// Largest HTTP request head read from a client
+const MAX_REQUEST_SIZE: u64 = 8192;
+
+/// How long a client may take to send its request
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Counters of one (program, procedure)
+#[derive(Default)]
+struct ProcedureMetrics {
+    calls: AtomicU64,
+    errors: AtomicU64,
+    /// Calls per latency bucket (not cumulative); the last one is +Inf
+    buckets: [AtomicU64; LATENCY_BUCKETS.len() + 1],
+    latency_sum_micros: AtomicU64,
+}
+
+/// Per-procedure RPC metrics
+#[derive(Default)]
+pub struct Metrics {
+    procedures: Mutex<BTreeMap<(u32, u32), Arc<ProcedureMetrics>>>,
+    attr_cache: Arc<CacheStats>,
+}
+
+impl Metrics {
+    /// Counters for the FSAL attribute caches to update
+    pub fn attr_cache_stats(&self) -> Arc<CacheStats> {
+        self.attr_cache.clone()
+    }
+
+    /// Record one answered call
+    ///
+    /// `call` is the RPC call message and `reply` what was sent back, if
+    /// anything. Messages too short to carry a call header are ignored;
+    /// calls to unknown programs or procedures are counted together as
+    /// "other".
+    pub fn record(&self, call: &[u8], reply: Option<&[u8]>, elapsed: Duration) {
+        let (Some(prog), Some(procedure)) = (word(call, 12), word(call, 20)) else {
+            return;
+        };
+
+        let entry = self
+            .procedures
+            .lock()
+            .unwrap()
+            .entry(metric_key(prog, procedure))
+            .or_default()
+            .clone();
+
+        entry.calls.fetch_add(1, Ordering::Relaxed);
+        if is_error(prog, procedure, reply) {
+            entry.errors.fetch_add(1, Ordering::Relaxed);
+        }
+        let seconds = elapsed.as_secs_f64();
+        let bucket = LATENCY_BUCKETS
+            .iter()
+            .position(|&bound| seconds <= bound)
+            .unwrap_or(LATENCY_BUCKETS.len());
+        entry.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        entry
+            .latency_sum_micros
+            .fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+    }
+
+    /// Render every metric in the Prometheus text exposition format
+    pub fn render(&self, connections: &ConnectionSlots) -> String {
+        let procedures: Vec<_> = self
+            .procedures
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(&key, entry)| (key, entry.clone()))
+            .collect();
+
+        let mut out = String::new();
+        let labels = |(prog, procedure): (u32, u32)| {
+            format!(
+                "program=\"{}\",procedure=\"{}\"",
+                program_name(prog),
+                procedure_name(prog, procedure)
+            )
+        };
+
+        out.push_str("# HELP arcticwolf_rpc_calls_total RPC calls answered\n");
+        out.push_str("# TYPE arcticwolf_rpc_calls_total counter\n");
+        for (key, entry) in &procedures {
+            let calls = entry.calls.load(Ordering::Relaxed);
+            let _ = writeln!(out, "arcticwolf_rpc_calls_total{{{}}} {}", labels(*key), calls);
+        }
+
+        out.push_str(
+            "# HELP arcticwolf_rpc_errors_total RPC calls that failed or returned an error status\n",
+        );
+        out.push_str("# TYPE arcticwolf_rpc_errors_total counter\n");
+        for (key, entry) in &procedures {
+            let errors = entry.errors.load(Ordering::Relaxed);
+            let _ = writeln!(out, "arcticwolf_rpc_errors_total{{{}}} {}", labels(*key), errors);
+        }
+
+        out.push_str("# HELP arcticwolf_rpc_duration_seconds Time taken to answer RPC calls\n");
+        out.push_str("# TYPE arcticwolf_rpc_duration_seconds histogram\n");
+        for (key, entry) in &procedures {
+            let key_labels = labels(*key);
+            let mut cumulative = 0;
+            for (i, count) in entry.buckets.iter().enumerate() {
+                cumulative += count.load(Ordering::Relaxed);
+                let bound = LATENCY_BUCKETS
+                    .get(i)
+                    .map_or("+Inf".to_string(), |bound| bound.to_string());
+                let _ = writeln!(
+                    out,
+                    "arcticwolf_rpc_duration_seconds_bucket{{{},le=\"{}\"}} {}",
+                    key_labels, bound, cumulative
+                );
+            }
+            let sum = entry.latency_sum_micros.load(Ordering::Relaxed) as f64 / 1e6;
+            let _ = writeln!(out, "arcticwolf_rpc_duration_seconds_sum{{{}}} {}", key_labels, sum);
+            let _ = writeln!(
+                out,
+                "arcticwolf_rpc_duration_seconds_count{{{}}} {}",
+                key_labels, cumulative
+            );
+        }
+
+        out.push_str("# HELP arcticwolf_tcp_connections_active Open TCP connections\n");
+        out.push_str("# TYPE arcticwolf_tcp_connections_active gauge\n");
+        let _ = writeln!(out, "arcticwolf_tcp_connections_active {}", connections.active());
+        out.push_str("# HELP arcticwolf_tcp_connections_max Configured TCP connection limit\n");
+        out.push_str("# TYPE arcticwolf_tcp_connections_max gauge\n");
+        let _ = writeln!(out, "arcticwolf_tcp_connections_max {}", connections.max());
+
+        out.push_str("# HELP arcticwolf_attr_cache_hits_total GETATTRs answered from the attribute cache\n");
+        out.push_str("# TYPE arcticwolf_attr_cache_hits_total counter\n");
+        let _ = writeln!(out, "arcticwolf_attr_cache_hits_total {}", self.attr_cache.hits());
+        out.push_str("# HELP arcticwolf_attr_cache_misses_total GETATTRs that went to the backend\n");
+        out.push_str("# TYPE arcticwolf_attr_cache_misses_total counter\n");
+        let _ = writeln!(out, "arcticwolf_attr_cache_misses_total {}", self.attr_cache.misses());
+
+        out
+    }
+}
+
+/// Serve `/metrics` over HTTP on `addr`
+pub async fn serve(addr: String, metrics: Arc<Metrics>, connections: ConnectionSlots) -> Result<()> {
+    let listener = TcpListener::bind(&addr).await?;
+    info!("Metrics endpoint listening on http://{}/metrics", addr);
+
+    loop {
+        let (socket, peer_addr) = match listener.accept().await {
+            Ok(accepted) => accepted,
+            Err(e) => {
+                warn!("Metrics endpoint failed to accept a connection: {}", e);
+                tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                continue;
+            }
+        };
+        let metrics = metrics.clone();
+        let connections = connections.clone();
+        tokio::spawn(async move {
+            if let Err(e) = answer_scrape(socket, &metrics, &connections).await {
+                debug!("Metrics request from {} failed: {}", peer_addr, e);
+            }
+        });
+    }
+}
+
+/// Answer one HTTP request, then close the connection
+async fn answer_scrape(
+    mut socket: TcpStream,
+    metrics: &Metrics,
+    connections: &ConnectionSlots,
+) -> Result<()> {
+    let request_line = read_request(&mut socket).await?;
+    let mut parts = request_line.split_whitespace();
+    let (status, body) = match (parts.next(), parts.next()) {
+        (Some("GET"), Some("/metrics")) => ("200 OK", metrics.render(connections)),
+        _ => ("404 Not Found", "Not found\n".to_string()),
+    };
+    respond(socket, status, "text/plain; version=0.0.4", &body).await
+}
+
+/// Read an HTTP request head from `socket`, returning its request line
+pub(crate) async fn read_request(socket: &mut TcpStream) -> Result<String> {
+    let mut reader = BufReader::new((&mut *socket).take(MAX_REQUEST_SIZE));
+
+    let request_line = tokio::time::timeout(REQUEST_TIMEOUT, async {
+        let mut request_line = String::new();
+        reader.read_line(&mut request_line).await?;
+        // Skip the headers, up to the blank line
+        loop {
+            let mut header = String::new();
+            if reader.read_line(&mut header).await? == 0 || header.trim_end().is_empty() {
+                break;
+            }
+        }
+        Ok::<_, std::io::Error>(request_line)
+    })
+    .await
+    .map_err(|_| anyhow!("Timed out reading request"))??;
+    Ok(request_line)
+}
+
+/// Send an HTTP response on `socket` and close it
+pub(crate) async fn respond(
+    mut socket: TcpStream,
+    status: &str,
+    content_type: &str,
+    body: &str,
+) -> Result<()> {
+    let response = format!(
+        "HTTP/1.1 {}\r\nContent-Type: {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{}",
+        status,
+        content_type,
+        body.len(),
+        body
+    );
+    socket.write_all(response.as_bytes()).await?;
+    socket.shutdown().await?;
+    Ok(())
+}
+
+/// Whether a reply reports failure
+///
+/// No reply at all, a denied or unsuccessful RPC reply, and NFS replies with
+/// a status other than NFS3_OK count as errors.
+fn is_error(prog: u32, procedure: u32, reply: Option<&[u8]>) -> bool {
+    let Some(reply) = reply else {
+        return true;
+    };
+    // reply_stat (MSG_ACCEPTED), then accept_stat after the empty verifier
+    if word(reply, 8) != Some(0) || word(reply, 20) != Some(0) {
+        return true;
+    }
+    prog == crate::nfs::NFS_PROGRAM && procedure != 0 && word(reply, 24) != Some(0)
+}
+
+/// The big-endian word at byte `offset` of `data`
+fn word(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_be_bytes(data.get(offset..offset + 4)?.try_into().ok()?))
+}
+
+/// The table key a call to (prog, procedure) is counted under
+fn metric_key(prog: u32, procedure: u32) -> (u32, u32) {
+    let served = matches!(
+        prog,
+        crate::portmap::PORTMAP_PROGRAM
+            | crate::mount::MOUNT_PROGRAM
+            | crate::nfs::NFS_PROGRAM
+            | crate::nlm::NLM_PROGRAM
+            | crate::nsm::NSM_PROGRAM
+    );
+    if served && procedure < MAX_PROCEDURES {
+        (prog, procedure)
+    } else {
+        OTHER
+    }
+}
+
+fn program_name(prog: u32) -> String {
+    match prog {
+        crate::portmap::PORTMAP_PROGRAM => "portmap".to_string(),
+        crate::mount::MOUNT_PROGRAM => "mount".to_string(),
+        crate::nfs::NFS_PROGRAM => "nfs".to_string(),
+        crate::nlm::NLM_PROGRAM => "nlm".to_string(),
+        crate::nsm::NSM_PROGRAM => "nsm".to_string(),
+        _ => "other".to_string(),
+    }
+}
+
+fn procedure_name(prog: u32, procedure: u32) -> String {
+    match NFS_PROCEDURES.get(procedure as usize) {
+        Some(name) if prog == crate::nfs::NFS_PROGRAM => name.to_string(),
+        _ if (prog, procedure) == OTHER => "other".to_string(),
+        _ => procedure.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Call header for (prog, procedure); the rest of the call isn't read
+    fn call(prog: u32, procedure: u32) -> Vec<u8> {
+        [1u32, 0, 2, prog, 3, procedure]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+
+    /// Accepted reply with an empty verifier, accept_stat and result status
+    fn reply(accept_stat: u32, status: u32) -> Vec<u8> {
+        [1u32, 1, 0, 0, 0, accept_stat, status]
+            .iter()
+            .flat_map(|word| word.to_be_bytes())
+            .collect()
+    }
+
+    #[test]
+    fn test_record_and_render() {
+        let metrics = Metrics::default();
+        let nfs = crate::nfs::NFS_PROGRAM;
+
+        let ok = reply(0, 0);
+        let noent = reply(0, 2);
+        metrics.record(&call(nfs, 6), Some(&ok), Duration::from_micros(300));
+        metrics.record(&call(nfs, 6), Some(&noent), Duration::from_millis(20));
+        metrics.record(&call(nfs, 3), None, Duration::from_secs(10));
+        metrics.record(&call(nfs, 0), Some(&reply(0, 0)[..24]), Duration::ZERO);
+        metrics.record(&[0; 8], None, Duration::ZERO);
+
+        let text = metrics.render(&ConnectionSlots::new(16));
+        let read = "program=\"nfs\",procedure=\"READ\"";
+        assert!(text.contains(&format!("arcticwolf_rpc_calls_total{{{}}} 2\n", read)));
+        assert!(text.contains(&format!("arcticwolf_rpc_errors_total{{{}}} 1\n", read)));
+        assert!(text.contains(&format!(
+            "arcticwolf_rpc_duration_seconds_bucket{{{},le=\"0.0005\"}} 1\n",
+            read
+        )));
+        assert!(text.contains(&format!(
+            "arcticwolf_rpc_duration_seconds_bucket{{{},le=\"+Inf\"}} 2\n",
+            read
+        )));
+        assert!(text.contains(&format!("arcticwolf_rpc_duration_seconds_sum{{{}}} 0.0203\n", read)));
+
+        let lookup = "program=\"nfs\",procedure=\"LOOKUP\"";
+        assert!(text.contains(&format!("arcticwolf_rpc_errors_total{{{}}} 1\n", lookup)));
+        let null = "program=\"nfs\",procedure=\"NULL\"";
+        assert!(text.contains(&format!("arcticwolf_rpc_errors_total{{{}}} 0\n", null)));
+
+        assert!(text.contains("arcticwolf_tcp_connections_active 0\n"));
+        assert!(text.contains("arcticwolf_tcp_connections_max 16\n"));
+        assert!(text.contains("arcticwolf_attr_cache_hits_total 0\n"));
+        assert!(text.contains("arcticwolf_attr_cache_misses_total 0\n"));
+    }
+
+    #[test]
+    fn test_unknown_calls_share_one_key() {
+        let metrics = Metrics::default();
+        for prog in 0..100 {
+            metrics.record(&call(400_000 + prog, 1), None, Duration::ZERO);
+        }
+        for procedure in 32..100 {
+            metrics.record(&call(crate::nfs::NFS_PROGRAM, procedure), None, Duration::ZERO);
+        }
+        metrics.record(&call(crate::nlm::NLM_PROGRAM, 23), None, Duration::ZERO);
+        assert_eq!(metrics.procedures.lock().unwrap().len(), 2);
+
+        let text = metrics.render(&ConnectionSlots::new(16));
+        let other = "program=\"other\",procedure=\"other\"";
+        assert!(text.contains(&format!("arcticwolf_rpc_calls_total{{{}}} 168\n", other)));
+        assert!(text.contains("program=\"nlm\",procedure=\"23\"} 1\n"));
+    }
+
+    #[test]
+    fn test_is_error() {
+        let nfs = crate::nfs::NFS_PROGRAM;
+        assert!(!is_error(nfs, 1, Some(&reply(0, 0))));
+        assert!(is_error(nfs, 1, Some(&reply(0, 70))));
+        assert!(is_error(nfs, 1, Some(&reply(4, 0))), "GARBAGE_ARGS");
+        assert!(is_error(nfs, 1, None));
+
+        // Only NFS results start with a status word
+        let mount = crate::mount::MOUNT_PROGRAM;
+        assert!(!is_error(mount, 5, Some(&reply(0, 1))));
+    }
+
+    #[tokio::test]
+    async fn test_scrape() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let metrics = Metrics::default();
+        metrics.record(&call(crate::nfs::NFS_PROGRAM, 1), Some(&reply(0, 0)), Duration::ZERO);
+
+        for (path, expected) in [("/metrics", "200 OK"), ("/", "404 Not Found")] {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            let (socket, _) = listener.accept().await.unwrap();
+            client
+                .write_all(format!("GET {} HTTP/1.1\r\nHost: x\r\n\r\n", path).as_bytes())
+                .await
+                .unwrap();
+            answer_scrape(socket, &metrics, &ConnectionSlots::new(4))
+                .await
+                .unwrap();
+
+            let mut response = String::new();
+            client.read_to_string(&mut response).await.unwrap();
+            assert!(response.starts_with(&format!("HTTP/1.1 {}\r\n", expected)));
+            if expected == "200 OK" {
+                assert!(response.contains("procedure=\"GETATTR\"} 1\n"));
+            }
+        }
+    }
+}
@@ -0,0 +1,95 @@
+// MOUNT DUMP Procedure Handler
+//
+// Procedure: 2 (DUMP)
+// Purpose: List the current mounts (used by `showmount -a`)
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::{debug, info};
+
+use crate::protocol::v3::mount::MountMessage;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::table::MountTable;
+
+/// Handle MOUNT DUMP procedure
+///
+/// Returns every (hostname, dirpath) pair in the mount table. The hostname is
+/// the address of the peer that sent the MNT call. The table is copied
+/// before encoding, so concurrent MNT/UMNT calls are never blocked on it.
+///
+/// Arguments: void
+/// Returns: mountlist (optional linked list of mountbody)
+pub fn handle(call: &rpc_call_msg, mounts: &MountTable) -> Result<BytesMut> {
+    debug!(
+        "MOUNT DUMP: xid={}, prog={}, vers={}, proc={}",
+        call.xid, call.prog, call.vers, call.proc_
+    );
+
+    let entries: Vec<(String, String)> = mounts
+        .entries()
+        .into_iter()
+        .map(|(client, path)| (client.to_string(), path))
+        .collect();
+    let list = MountMessage::create_mount_list(&entries);
+    let dump_data = MountMessage::serialize_mount_list(&list)?;
+
+    info!("MOUNT DUMP: listing {} mount(s)", entries.len());
+
+    RpcMessage::create_success_reply_with_data(call.xid, dump_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::{procedures, MOUNT_PROGRAM, MOUNT_V3};
+    use crate::test_support::call;
+
+    fn dump_call() -> rpc_call_msg {
+        call(12, MOUNT_PROGRAM, MOUNT_V3, procedures::DUMP)
+    }
+
+    fn read_string(buf: &[u8], offset: &mut usize) -> String {
+        let len = u32::from_be_bytes(buf[*offset..*offset + 4].try_into().unwrap()) as usize;
+        let value = String::from_utf8(buf[*offset + 4..*offset + 4 + len].to_vec()).unwrap();
+        *offset += 4 + ((len + 3) & !3);
+        value
+    }
+
+    /// Decode the mountlist from a reply into (hostname, dirpath) pairs
+    fn parse_mount_list(reply: &[u8]) -> Vec<(String, String)> {
+        let mut offset = 24;
+        let mut mounts = Vec::new();
+        while reply[offset..offset + 4] == [0, 0, 0, 1] {
+            offset += 4;
+            let hostname = read_string(reply, &mut offset);
+            let path = read_string(reply, &mut offset);
+            mounts.push((hostname, path));
+        }
+        assert_eq!(offset + 4, reply.len());
+        mounts
+    }
+
+    #[test]
+    fn test_dump_lists_mounts() {
+        let mounts = MountTable::new();
+        mounts.mount("10.0.0.2".parse().unwrap(), "/srv/export");
+        mounts.mount("10.0.0.1".parse().unwrap(), "/srv/export/sub");
+
+        let reply = handle(&dump_call(), &mounts).unwrap();
+        assert_eq!(
+            parse_mount_list(&reply),
+            vec![
+                ("10.0.0.1".to_string(), "/srv/export/sub".to_string()),
+                ("10.0.0.2".to_string(), "/srv/export".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dump_empty_table() {
+        let reply = handle(&dump_call(), &MountTable::new()).unwrap();
+        assert_eq!(reply.len(), 28);
+        assert!(parse_mount_list(&reply).is_empty());
+    }
+}
@@ -0,0 +1,116 @@
+// MOUNT EXPORT Procedure Handler
+//
+// Procedure: 5 (EXPORT)
+// Purpose: List the exported directories (used by `showmount -e`)
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::{debug, info};
+
+use crate::config::ExportConfig;
+use crate::protocol::v3::mount::MountMessage;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Handle MOUNT EXPORT procedure
+///
+/// Returns every configured export with the client groups allowed to mount
+/// it. An export without a client list is reported with no groups, which
+/// clients show as "everyone".
+///
+/// Arguments: void
+/// Returns: exports (optional linked list of exportnode)
+pub fn handle<'a>(
+    call: &rpc_call_msg,
+    exports: impl IntoIterator<Item = &'a ExportConfig>,
+) -> Result<BytesMut> {
+    debug!(
+        "MOUNT EXPORT: xid={}, prog={}, vers={}, proc={}",
+        call.xid, call.prog, call.vers, call.proc_
+    );
+
+    let entries: Vec<(String, Vec<String>)> = exports
+        .into_iter()
+        .map(|export| (export.path.clone(), export.clients.clone()))
+        .collect();
+    let list = MountMessage::create_export_list(&entries);
+    let export_data = MountMessage::serialize_exports(&list)?;
+
+    info!("MOUNT EXPORT: listing {} export(s)", entries.len());
+
+    RpcMessage::create_success_reply_with_data(call.xid, export_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mount::{procedures, MOUNT_PROGRAM, MOUNT_V3};
+    use crate::test_support::call;
+
+    fn export_call() -> rpc_call_msg {
+        call(11, MOUNT_PROGRAM, MOUNT_V3, procedures::EXPORT)
+    }
+
+    fn read_u32(buf: &[u8], offset: &mut usize) -> u32 {
+        let value = u32::from_be_bytes(buf[*offset..*offset + 4].try_into().unwrap());
+        *offset += 4;
+        value
+    }
+
+    fn read_string(buf: &[u8], offset: &mut usize) -> String {
+        let len = read_u32(buf, offset) as usize;
+        let value = String::from_utf8(buf[*offset..*offset + len].to_vec()).unwrap();
+        *offset += (len + 3) & !3;
+        value
+    }
+
+    /// Decode the exports list from a reply into (path, groups) pairs
+    fn parse_exports(reply: &[u8]) -> Vec<(String, Vec<String>)> {
+        let mut offset = 24;
+        let mut exports = Vec::new();
+        while read_u32(reply, &mut offset) == 1 {
+            let path = read_string(reply, &mut offset);
+            let mut groups = Vec::new();
+            while read_u32(reply, &mut offset) == 1 {
+                groups.push(read_string(reply, &mut offset));
+            }
+            exports.push((path, groups));
+        }
+        assert_eq!(offset, reply.len());
+        exports
+    }
+
+    #[test]
+    fn test_export_lists_paths_and_groups() {
+        let exports = vec![
+            ExportConfig {
+                path: "/srv/nfs".to_string(),
+                clients: vec!["10.0.0.0/24".to_string(), "backup".to_string()],
+                ..ExportConfig::default()
+            },
+            ExportConfig {
+                path: "/srv/public".to_string(),
+                clients: vec![],
+                ..ExportConfig::default()
+            },
+        ];
+
+        let reply = handle(&export_call(), &exports).unwrap();
+        assert_eq!(
+            parse_exports(&reply),
+            vec![
+                (
+                    "/srv/nfs".to_string(),
+                    vec!["10.0.0.0/24".to_string(), "backup".to_string()]
+                ),
+                ("/srv/public".to_string(), vec![]),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_export_empty_list() {
+        let reply = handle(&export_call(), std::iter::empty()).unwrap();
+        assert_eq!(reply.len(), 28);
+        assert!(parse_exports(&reply).is_empty());
+    }
+}
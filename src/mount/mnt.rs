@@ -5,22 +5,36 @@
 
 use anyhow::Result;
 use bytes::BytesMut;
-use tracing::{debug, info};
+use std::net::IpAddr;
+use tracing::{debug, info, warn};
 
-use crate::protocol::v3::mount::MountMessage;
+use crate::exports::{client_allowed, relative_path, ExportTable};
+use crate::fsal::{FileHandle, FileType, Filesystem};
+use crate::protocol::v3::mount::{mountstat3, MountMessage};
 use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
 
+use super::table::MountTable;
+
 /// Handle MOUNT MNT procedure
 ///
 /// This procedure takes a directory path and returns a file handle that can be used
 /// for subsequent NFS operations.
 ///
+/// The path must be an export, or a directory below one; when exports are
+/// nested the longest matching export is used. "/" is an alias for the first
+/// export unless "/" itself is exported. Paths outside every export fail with
+/// MNT3ERR_ACCESS, missing ones with MNT3ERR_NOENT. Clients outside the
+/// export's allow-list get MNT3ERR_ACCESS. Successful mounts are recorded in
+/// `mounts`.
+///
 /// Arguments: dirpath (string)
 /// Returns: mountres3 (file handle + auth flavors on success)
 pub fn handle(
     call: &rpc_call_msg,
     args_data: &[u8],
-    filesystem: &dyn crate::fsal::Filesystem,
+    exports: &ExportTable,
+    client: IpAddr,
+    mounts: &MountTable,
 ) -> Result<BytesMut> {
     debug!(
         "MOUNT MNT: xid={}, prog={}, vers={}, proc={}",
@@ -35,10 +49,31 @@ pub fn handle(
 
     info!("MOUNT MNT request for path: '{}'", dirpath);
 
-    // For root path "/" or empty, return the root file handle
-    // In a production NFS server, we would validate export permissions here
-    // For now, accept any path and return root handle (temporary workaround for path parsing issue)
-    let fhandle_bytes = filesystem.root_handle();
+    let export = match exports.for_path(&dirpath) {
+        Some(export) => Some(export),
+        None if dirpath == "/" => exports.first(),
+        None => None,
+    };
+    let resolved = match export {
+        Some(export) if !client_allowed(client, &export.config) => {
+            warn!("MOUNT MNT: client {} not allowed by export {}", client, export.config.path);
+            Err(mountstat3::MNT3ERR_ACCESS)
+        }
+        Some(export) => {
+            resolve_mount_path(&dirpath, &export.config.path, export.filesystem.as_ref())
+        }
+        None => Err(mountstat3::MNT3ERR_ACCESS),
+    };
+    let fhandle_bytes = match resolved {
+        Ok(handle) => handle,
+        Err(status) => {
+            warn!("MOUNT MNT refused for path '{}': {:?}", dirpath, status);
+            let mount_data = MountMessage::serialize_mount_error(status)?;
+            return RpcMessage::create_success_reply_with_data(call.xid, mount_data);
+        }
+    };
+
+    mounts.mount(client, &dirpath);
 
     info!(
         "Generated file handle ({} bytes) for path '{}'",
@@ -72,3 +107,224 @@ pub fn handle(
     Ok(response)
 }
 
+/// Resolve a mount request path to the handle of a directory in the export
+///
+/// "/" and the export path both name the export root; anything below them is
+/// looked up one component at a time through the FSAL, so it can never
+/// leave the export.
+fn resolve_mount_path(
+    dirpath: &str,
+    export: &str,
+    filesystem: &dyn Filesystem,
+) -> Result<FileHandle, mountstat3> {
+    let relative = if dirpath == "/" {
+        ""
+    } else {
+        relative_path(dirpath, export).ok_or(mountstat3::MNT3ERR_ACCESS)?
+    };
+
+    let mut handle = filesystem.root_handle();
+    for component in relative.split('/').filter(|c| !c.is_empty() && *c != ".") {
+        if component == ".." {
+            return Err(mountstat3::MNT3ERR_ACCESS);
+        }
+        handle = filesystem.lookup(&handle, component).map_err(|e| {
+            debug!("MOUNT MNT: lookup of '{}' failed: {}", component, e);
+            if e.to_string().contains("not found") {
+                mountstat3::MNT3ERR_NOENT
+            } else if e.to_string().contains("outside export") {
+                mountstat3::MNT3ERR_ACCESS
+            } else {
+                mountstat3::MNT3ERR_IO
+            }
+        })?;
+    }
+
+    match filesystem.getattr(&handle) {
+        Ok(attrs) if attrs.ftype == FileType::Directory => Ok(handle),
+        Ok(_) => Err(mountstat3::MNT3ERR_NOTDIR),
+        Err(_) => Err(mountstat3::MNT3ERR_IO),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::exports::Export;
+    use crate::fsal::BackendConfig;
+    use crate::mount::{procedures, MOUNT_PROGRAM, MOUNT_V3};
+    use crate::protocol::v3::mount::dirpath;
+    use crate::test_support::call;
+    use std::sync::Arc;
+    use std::fs;
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    const EXPORT: &str = "/srv/export";
+    const CLIENT: &str = "192.0.2.10";
+
+    /// `filesystem` exported at `path`
+    fn export_at(filesystem: &Arc<dyn Filesystem>, path: &str) -> Export {
+        Export::new(
+            ExportConfig {
+                path: path.to_string(),
+                clients: vec![],
+                ..ExportConfig::default()
+            },
+            filesystem.clone(),
+        )
+    }
+
+    fn mnt_call() -> rpc_call_msg {
+        call(7, MOUNT_PROGRAM, MOUNT_V3, procedures::MNT)
+    }
+
+    /// Send MNT for `path`, returning the mountstat3 and the reply
+    fn mount(filesystem: &Arc<dyn Filesystem>, path: &str) -> (u32, BytesMut) {
+        mount_with_table(filesystem, path, &MountTable::new())
+    }
+
+    fn mount_with_table(
+        filesystem: &Arc<dyn Filesystem>,
+        path: &str,
+        mounts: &MountTable,
+    ) -> (u32, BytesMut) {
+        let exports = ExportTable::new(vec![export_at(filesystem, EXPORT)]);
+        mount_from(&exports, path, mounts)
+    }
+
+    fn mount_from(exports: &ExportTable, path: &str, mounts: &MountTable) -> (u32, BytesMut) {
+        let mut args_buf = Vec::new();
+        dirpath(path.to_string()).pack(&mut args_buf).unwrap();
+        let client = CLIENT.parse().unwrap();
+        let reply = handle(&mnt_call(), &args_buf, exports, client, mounts).unwrap();
+        let status = u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]]);
+        (status, reply)
+    }
+
+    fn local(temp_dir: &TempDir) -> Arc<dyn Filesystem> {
+        Arc::from(
+            BackendConfig::local(temp_dir.path())
+                .create_filesystem()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn test_mnt_export_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = local(&temp_dir);
+        let root = fs.root_handle();
+
+        for path in ["/", EXPORT, "/srv/export/"] {
+            let (status, reply) = mount(&fs, path);
+            assert_eq!(status, mountstat3::MNT3_OK as u32, "Mounting {}", path);
+            // fhandle3: length + bytes
+            assert_eq!(&reply[28..32], &(root.len() as u32).to_be_bytes());
+            assert_eq!(&reply[32..32 + root.len()], &root[..]);
+        }
+    }
+
+    #[test]
+    fn test_mnt_subdirectory() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir_all(temp_dir.path().join("projects/a")).unwrap();
+        let fs = local(&temp_dir);
+        let projects = fs.lookup(&fs.root_handle(), "projects").unwrap();
+        let expected = fs.lookup(&projects, "a").unwrap();
+
+        let (status, reply) = mount(&fs, "/srv/export/projects/a");
+        assert_eq!(status, mountstat3::MNT3_OK as u32);
+        assert_eq!(&reply[32..32 + expected.len()], &expected[..]);
+    }
+
+    #[test]
+    fn test_mnt_rejected_paths() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let fs = local(&temp_dir);
+
+        let cases = [
+            ("/etc", mountstat3::MNT3ERR_ACCESS),
+            ("/srv/exportfoo", mountstat3::MNT3ERR_ACCESS),
+            ("/srv/export/../secret", mountstat3::MNT3ERR_ACCESS),
+            ("/srv/export/missing", mountstat3::MNT3ERR_NOENT),
+            ("/srv/export/file.txt", mountstat3::MNT3ERR_NOTDIR),
+        ];
+        for (path, expected) in cases {
+            let (status, reply) = mount(&fs, path);
+            assert_eq!(status, expected as u32, "Mounting {}", path);
+            assert_eq!(reply.len(), 28, "Failure carries no body");
+        }
+    }
+
+    #[test]
+    fn test_mnt_records_successful_mounts_only() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = local(&temp_dir);
+        let mounts = MountTable::new();
+
+        mount_with_table(&fs, EXPORT, &mounts);
+        mount_with_table(&fs, "/srv/export/missing", &mounts);
+
+        let client: IpAddr = CLIENT.parse().unwrap();
+        assert_eq!(mounts.entries(), vec![(client, EXPORT.to_string())]);
+    }
+
+    #[test]
+    fn test_mnt_longest_matching_export() {
+        let (outer_dir, inner_dir) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        fs::create_dir_all(outer_dir.path().join("home")).unwrap();
+        fs::create_dir_all(inner_dir.path().join("a")).unwrap();
+        let (outer, inner) = (local(&outer_dir), local(&inner_dir));
+        let exports = ExportTable::new(vec![
+            export_at(&outer, "/srv"),
+            export_at(&inner, "/srv/projects"),
+        ]);
+        let mounts = MountTable::new();
+
+        let handle_of = |reply: &BytesMut, len: usize| reply[32..32 + len].to_vec();
+
+        let root = outer.root_handle();
+        let (status, reply) = mount_from(&exports, "/", &mounts);
+        assert_eq!(status, mountstat3::MNT3_OK as u32);
+        assert_eq!(handle_of(&reply, root.len()), root, "/ is the first export");
+
+        let home = outer.lookup(&root, "home").unwrap();
+        let (status, reply) = mount_from(&exports, "/srv/home", &mounts);
+        assert_eq!(status, mountstat3::MNT3_OK as u32);
+        assert_eq!(handle_of(&reply, home.len()), home);
+
+        let a = inner.lookup(&inner.root_handle(), "a").unwrap();
+        let (status, reply) = mount_from(&exports, "/srv/projects/a", &mounts);
+        assert_eq!(status, mountstat3::MNT3_OK as u32);
+        assert_eq!(handle_of(&reply, a.len()), a, "served by the inner export");
+
+        let (status, _) = mount_from(&exports, "/var", &mounts);
+        assert_eq!(status, mountstat3::MNT3ERR_ACCESS as u32);
+    }
+
+    #[test]
+    fn test_mnt_client_allow_list() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = local(&temp_dir);
+        let restricted = |clients: &[&str]| {
+            let mut export = export_at(&fs, EXPORT);
+            export.config.clients = clients.iter().map(|c| c.to_string()).collect();
+            ExportTable::new(vec![export])
+        };
+        let mounts = MountTable::new();
+
+        // CLIENT is 192.0.2.10
+        let (status, _) = mount_from(&restricted(&["192.0.2.0/24"]), EXPORT, &mounts);
+        assert_eq!(status, mountstat3::MNT3_OK as u32);
+
+        let (status, reply) = mount_from(&restricted(&["10.0.0.0/8"]), EXPORT, &mounts);
+        assert_eq!(status, mountstat3::MNT3ERR_ACCESS as u32);
+        assert_eq!(reply.len(), 28);
+
+        let client: IpAddr = CLIENT.parse().unwrap();
+        assert_eq!(mounts.entries(), vec![(client, EXPORT.to_string())]);
+    }
+}
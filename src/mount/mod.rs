@@ -7,15 +7,22 @@
 // Clients must first mount a directory path to obtain a file handle before
 // they can perform NFS operations.
 
+pub mod dump;
+pub mod export;
 pub mod mnt;
 pub mod null;
+pub mod table;
 pub mod umnt;
+pub mod umntall;
 
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
+use std::net::IpAddr;
 use tracing::{debug, warn};
 
-use crate::protocol::v3::rpc::rpc_call_msg;
+use crate::exports::ExportTable;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+pub use table::MountTable;
 
 /// MOUNT program number (RFC 1813)
 pub const MOUNT_PROGRAM: u32 = 100005;
@@ -36,11 +43,14 @@ pub mod procedures {
 /// Dispatch MOUNT procedure call to appropriate handler
 ///
 /// This function routes the RPC call to the correct MOUNT procedure handler
-/// based on the procedure number.
+/// based on the procedure number. `exports` are the served exports, and
+/// `mounts` records which paths each client has mounted.
 pub fn handle_mount_call(
     call: &rpc_call_msg,
     args_data: &[u8],
-    filesystem: &dyn crate::fsal::Filesystem,
+    exports: &ExportTable,
+    client: IpAddr,
+    mounts: &MountTable,
 ) -> Result<BytesMut> {
     debug!(
         "Dispatching MOUNT call: proc={}, prog={}, vers={}",
@@ -61,13 +71,14 @@ pub fn handle_mount_call(
     }
 
     // Verify version 3
+    // Other versions get PROG_MISMATCH pointing them at v3, so the client can
+    // retry on the same connection
     if call.vers != MOUNT_V3 {
-        warn!("Expected MOUNT version {}, got {}", MOUNT_V3, call.vers);
-        return Err(anyhow!(
-            "Unsupported MOUNT version: expected {}, got {}",
-            MOUNT_V3,
-            call.vers
-        ));
+        warn!(
+            "Unsupported MOUNT version: {} (supported: {}-{})",
+            call.vers, MOUNT_V3, MOUNT_V3
+        );
+        return RpcMessage::create_prog_mismatch_reply(call.xid, MOUNT_V3, MOUNT_V3);
     }
 
     // Dispatch to handler based on procedure number
@@ -78,23 +89,23 @@ pub fn handle_mount_call(
         }
         procedures::MNT => {
             debug!("Routing to MOUNT MNT handler");
-            mnt::handle(call, args_data, filesystem)
+            mnt::handle(call, args_data, exports, client, mounts)
         }
         procedures::UMNT => {
             debug!("Routing to MOUNT UMNT handler");
-            umnt::handle(call, args_data)
+            umnt::handle(call, args_data, client, mounts)
         }
         procedures::DUMP => {
-            warn!("MOUNT DUMP not yet implemented");
-            Err(anyhow!("MOUNT DUMP procedure not implemented"))
+            debug!("Routing to MOUNT DUMP handler");
+            dump::handle(call, mounts)
         }
         procedures::UMNTALL => {
-            warn!("MOUNT UMNTALL not yet implemented");
-            Err(anyhow!("MOUNT UMNTALL procedure not implemented"))
+            debug!("Routing to MOUNT UMNTALL handler");
+            umntall::handle(call, client, mounts)
         }
         procedures::EXPORT => {
-            warn!("MOUNT EXPORT not yet implemented");
-            Err(anyhow!("MOUNT EXPORT procedure not implemented"))
+            debug!("Routing to MOUNT EXPORT handler");
+            export::handle(call, exports.configs())
         }
         _ => {
             warn!("Unknown MOUNT procedure: {}", call.proc_);
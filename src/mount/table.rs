@@ -0,0 +1,99 @@
+// MOUNT Table
+//
+// Records which clients have mounted which paths: MNT adds an entry, UMNT
+// and UMNTALL remove them, and DUMP reports them.
+
+use std::collections::BTreeSet;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+
+/// Mounted (client, path) pairs
+///
+/// Clients are identified by IP address only, since a client's MNT and UMNT
+/// calls usually arrive on different connections (and source ports).
+#[derive(Clone)]
+pub struct MountTable {
+    entries: Arc<Mutex<BTreeSet<(IpAddr, String)>>>,
+}
+
+impl MountTable {
+    /// Create an empty mount table
+    pub fn new() -> Self {
+        Self {
+            entries: Arc::new(Mutex::new(BTreeSet::new())),
+        }
+    }
+
+    /// Record that `client` mounted `path` (MNT)
+    pub fn mount(&self, client: IpAddr, path: &str) {
+        let mut entries = self.entries.lock().unwrap();
+        entries.insert((client, path.to_string()));
+    }
+
+    /// Remove the entry for `client` and `path` (UMNT)
+    ///
+    /// Returns true if the entry existed
+    pub fn unmount(&self, client: IpAddr, path: &str) -> bool {
+        let mut entries = self.entries.lock().unwrap();
+        entries.remove(&(client, path.to_string()))
+    }
+
+    /// Remove every entry for `client` (UMNTALL)
+    ///
+    /// Returns the number of entries removed
+    pub fn unmount_all(&self, client: IpAddr) -> usize {
+        let mut entries = self.entries.lock().unwrap();
+        let before = entries.len();
+        entries.retain(|(entry_client, _)| *entry_client != client);
+        before - entries.len()
+    }
+
+    /// Snapshot of all current entries, ordered by client then path
+    ///
+    /// The lock is released before returning, so callers may take their
+    /// time with the result without blocking MNT/UMNT.
+    pub fn entries(&self) -> Vec<(IpAddr, String)> {
+        self.entries.lock().unwrap().iter().cloned().collect()
+    }
+}
+
+impl Default for MountTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_mount_and_unmount() {
+        let table = MountTable::new();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+
+        table.mount(client, "/export");
+        table.mount(client, "/export");
+        assert_eq!(table.entries(), vec![(client, "/export".to_string())]);
+
+        assert!(table.unmount(client, "/export"));
+        // Unmounting again is not an error
+        assert!(!table.unmount(client, "/export"));
+        assert!(table.entries().is_empty());
+    }
+
+    #[test]
+    fn test_unmount_all_only_affects_caller() {
+        let table = MountTable::new();
+        let a: IpAddr = "10.0.0.1".parse().unwrap();
+        let b: IpAddr = "10.0.0.2".parse().unwrap();
+
+        table.mount(a, "/export");
+        table.mount(a, "/export/sub");
+        table.mount(b, "/export");
+
+        assert_eq!(table.unmount_all(a), 2);
+        assert_eq!(table.unmount_all(a), 0);
+        assert_eq!(table.entries(), vec![(b, "/export".to_string())]);
+    }
+}
@@ -5,19 +5,29 @@
 
 use anyhow::Result;
 use bytes::BytesMut;
+use std::net::IpAddr;
 use tracing::{debug, info};
 
 use crate::protocol::v3::mount::MountMessage;
 use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
 
+use super::table::MountTable;
+
 /// Handle MOUNT UMNT procedure
 ///
 /// This procedure unmounts a previously mounted directory path.
 /// It takes a directory path as argument and returns void (just RPC success).
+/// The (client, path) entry is removed from the mount table; a missing entry
+/// is not an error.
 ///
 /// Arguments: dirpath (string)
 /// Returns: void (RPC success reply only)
-pub fn handle(call: &rpc_call_msg, args_data: &[u8]) -> Result<BytesMut> {
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    client: IpAddr,
+    mounts: &MountTable,
+) -> Result<BytesMut> {
     debug!(
         "MOUNT UMNT: xid={}, prog={}, vers={}, proc={}",
         call.xid, call.prog, call.vers, call.proc_
@@ -36,10 +46,11 @@ pub fn handle(call: &rpc_call_msg, args_data: &[u8]) -> Result<BytesMut> {
 
     info!("MOUNT UMNT request for path: '{}'", dirpath);
 
-    // TODO: Remove the mount entry from internal state
-    // For now, we just acknowledge the unmount request
-
-    info!("Unmounted path '{}'", dirpath);
+    if mounts.unmount(client, &dirpath) {
+        info!("Unmounted path '{}' for client {}", dirpath, client);
+    } else {
+        debug!("No mount of '{}' recorded for client {}", dirpath, client);
+    }
 
     // Return simple success reply (void result)
     let reply = RpcMessage::create_null_reply(call.xid);
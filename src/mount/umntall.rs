@@ -0,0 +1,34 @@
+// MOUNT UMNTALL Procedure Handler
+//
+// Procedure: 4 (UMNTALL)
+// Purpose: Remove all mount entries for the calling client
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::net::IpAddr;
+use tracing::{debug, info};
+
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::table::MountTable;
+
+/// Handle MOUNT UMNTALL procedure
+///
+/// Drops every mount table entry recorded for the calling client address.
+/// Succeeds even if the client has nothing mounted.
+///
+/// Arguments: void
+/// Returns: void (RPC success reply only)
+pub fn handle(call: &rpc_call_msg, client: IpAddr, mounts: &MountTable) -> Result<BytesMut> {
+    debug!(
+        "MOUNT UMNTALL: xid={}, prog={}, vers={}, proc={}",
+        call.xid, call.prog, call.vers, call.proc_
+    );
+
+    let removed = mounts.unmount_all(client);
+    info!("Unmounted {} path(s) for client {}", removed, client);
+
+    // Return simple success reply (void result)
+    let reply = RpcMessage::create_null_reply(call.xid);
+    RpcMessage::serialize_reply(&reply)
+}
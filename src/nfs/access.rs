@@ -6,7 +6,8 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::debug;
 
-use crate::fsal::Filesystem;
+use crate::fsal::{FileAttributes, FileType, Filesystem};
+use crate::nfs::{handle_error_to_nfsstat, Credentials, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
@@ -21,11 +22,14 @@ const ACCESS3_EXECUTE: u32 = 0x0020;
 /// Handle NFS ACCESS procedure (procedure 4)
 ///
 /// Determines the access rights that a user has for a file system object.
+/// Only the requested bits the caller's credentials allow are granted (see
+/// `granted_access`).
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized ACCESS3args (file handle + access bits)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (caller credentials, read-only export)
 ///
 /// # Returns
 /// Serialized RPC reply message with granted access rights
@@ -33,6 +37,7 @@ pub fn handle_access(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS ACCESS called (xid={})", xid);
 
@@ -51,8 +56,10 @@ pub fn handle_access(
         Err(e) => {
             debug!("ACCESS failed: {}", e);
             // Return appropriate NFS error
-            let error_status = if e.to_string().contains("not found")
-                || e.to_string().contains("Invalid handle")
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
             {
                 nfsstat3::NFS3ERR_STALE
             } else {
@@ -69,34 +76,8 @@ pub fn handle_access(
         }
     };
 
-    // For simplicity, grant all requested permissions
-    // In a production implementation, this would check actual file permissions
-    // against the user's UID/GID from the RPC credentials
-    let mut granted_access = 0u32;
-
-    // Check each requested access bit
-    if args.access & ACCESS3_READ != 0 {
-        granted_access |= ACCESS3_READ;
-    }
-    if args.access & ACCESS3_LOOKUP != 0 {
-        // LOOKUP is only valid for directories
-        use crate::fsal::FileType;
-        if file_attrs.ftype == FileType::Directory {
-            granted_access |= ACCESS3_LOOKUP;
-        }
-    }
-    if args.access & ACCESS3_MODIFY != 0 {
-        granted_access |= ACCESS3_MODIFY;
-    }
-    if args.access & ACCESS3_EXTEND != 0 {
-        granted_access |= ACCESS3_EXTEND;
-    }
-    if args.access & ACCESS3_DELETE != 0 {
-        granted_access |= ACCESS3_DELETE;
-    }
-    if args.access & ACCESS3_EXECUTE != 0 {
-        granted_access |= ACCESS3_EXECUTE;
-    }
+    let granted_access =
+        granted_access(args.access, &file_attrs, &ctx.credentials, ctx.read_only());
 
     debug!(
         "ACCESS success: requested={:#06x}, granted={:#06x}",
@@ -104,7 +85,7 @@ pub fn handle_access(
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_attrs = NfsMessage::fsal_to_fattr3(&file_attrs);
+    let nfs_attrs = ctx.fattr3(&file_attrs);
 
     // Create successful ACCESS response manually with post_op_attr format
     use xdr_codec::Pack;
@@ -127,13 +108,165 @@ pub fn handle_access(
     RpcMessage::create_success_reply_with_data(xid, res_data)
 }
 
+/// The subset of `requested` access bits `credentials` hold on an object
+///
+/// Follows the mode bits that apply to the caller:
+/// - READ needs read permission
+/// - LOOKUP needs search permission, on directories only
+/// - EXECUTE needs execute permission, on non-directories only
+/// - MODIFY and EXTEND need write permission
+/// - DELETE (removing entries) needs write permission, on directories only
+///
+/// On a read-only export MODIFY, EXTEND and DELETE are never granted.
+fn granted_access(
+    requested: u32,
+    attrs: &FileAttributes,
+    credentials: &Credentials,
+    read_only: bool,
+) -> u32 {
+    let permissions = credentials.permissions(attrs);
+    let is_dir = attrs.ftype == FileType::Directory;
+
+    let mut granted = 0;
+    if permissions & 0o4 != 0 {
+        granted |= ACCESS3_READ;
+    }
+    if permissions & 0o2 != 0 && !read_only {
+        granted |= ACCESS3_MODIFY | ACCESS3_EXTEND;
+        if is_dir {
+            granted |= ACCESS3_DELETE;
+        }
+    }
+    if permissions & 0o1 != 0 {
+        granted |= if is_dir { ACCESS3_LOOKUP } else { ACCESS3_EXECUTE };
+    }
+
+    requested & granted
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::fsal::{BackendConfig, Filesystem};
+    use crate::config::ExportConfig;
+    use crate::fsal::{BackendConfig, FileTime};
+    use crate::nfs::NfsState;
     use std::fs;
     use tempfile::TempDir;
 
+    fn attrs(ftype: FileType, mode: u32) -> FileAttributes {
+        let time = FileTime {
+            seconds: 0,
+            nseconds: 0,
+        };
+        FileAttributes {
+            ftype,
+            mode,
+            nlink: 1,
+            uid: 1000,
+            gid: 100,
+            size: 0,
+            used: 0,
+            rdev: (0, 0),
+            fsid: 0,
+            fileid: 1,
+            atime: time,
+            mtime: time,
+            ctime: time,
+        }
+    }
+
+    fn caller(uid: u32, gid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            gids: Vec::new(),
+            anonymous: false,
+        }
+    }
+
+    const ALL: u32 = ACCESS3_READ
+        | ACCESS3_LOOKUP
+        | ACCESS3_MODIFY
+        | ACCESS3_EXTEND
+        | ACCESS3_DELETE
+        | ACCESS3_EXECUTE;
+
+    #[test]
+    fn test_granted_access_file() {
+        let file = attrs(FileType::RegularFile, 0o754);
+
+        assert_eq!(
+            granted_access(ALL, &file, &caller(1000, 1000), false),
+            ACCESS3_READ | ACCESS3_MODIFY | ACCESS3_EXTEND | ACCESS3_EXECUTE
+        );
+        assert_eq!(
+            granted_access(ALL, &file, &caller(1001, 100), false),
+            ACCESS3_READ | ACCESS3_EXECUTE
+        );
+        assert_eq!(granted_access(ALL, &file, &caller(1002, 1002), false), ACCESS3_READ);
+
+        // Only requested bits are returned
+        assert_eq!(
+            granted_access(ACCESS3_MODIFY, &file, &caller(1000, 1000), false),
+            ACCESS3_MODIFY
+        );
+        assert_eq!(granted_access(ACCESS3_MODIFY, &file, &caller(1002, 1002), false), 0);
+    }
+
+    #[test]
+    fn test_granted_access_directory() {
+        let dir = attrs(FileType::Directory, 0o750);
+
+        assert_eq!(
+            granted_access(ALL, &dir, &caller(1000, 1000), false),
+            ACCESS3_READ | ACCESS3_LOOKUP | ACCESS3_MODIFY | ACCESS3_EXTEND | ACCESS3_DELETE
+        );
+        assert_eq!(
+            granted_access(ALL, &dir, &caller(1001, 100), false),
+            ACCESS3_READ | ACCESS3_LOOKUP
+        );
+        assert_eq!(granted_access(ALL, &dir, &caller(1002, 1002), false), 0);
+    }
+
+    #[test]
+    fn test_granted_access_read_only_export() {
+        let dir = attrs(FileType::Directory, 0o777);
+        assert_eq!(
+            granted_access(ALL, &dir, &caller(0, 0), true),
+            ACCESS3_READ | ACCESS3_LOOKUP
+        );
+    }
+
+    #[test]
+    fn test_access_reply_reports_granted_bits() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let fs = BackendConfig::local(temp_dir.path()).create_filesystem().unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "file.txt").unwrap();
+
+        use crate::protocol::v3::nfs::ACCESS3args;
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        ACCESS3args {
+            object: crate::protocol::v3::nfs::fhandle3(file_handle),
+            access: ACCESS3_READ | ACCESS3_MODIFY,
+        }
+        .pack(&mut args_buf)
+        .unwrap();
+
+        let export = ExportConfig {
+            read_only: true,
+            ..ExportConfig::default()
+        };
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state).with_export(&export);
+        let reply = handle_access(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+
+        // status, attributes_follow, fattr3 (84 bytes), access
+        assert_eq!(&reply[24..28], &0u32.to_be_bytes());
+        assert_eq!(&reply[32 + 84..], &ACCESS3_READ.to_be_bytes());
+    }
+
     #[test]
     fn test_access_file() {
         // Create temp filesystem with a test file
@@ -161,7 +294,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call ACCESS
-        let result = handle_access(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_access(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "ACCESS should succeed for existing file");
 
@@ -192,7 +327,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call ACCESS
-        let result = handle_access(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_access(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "ACCESS should succeed for directory");
     }
@@ -217,7 +354,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call ACCESS
-        let result = handle_access(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_access(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "ACCESS should return error response (not panic)");
     }
@@ -11,22 +11,34 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
+use crate::fsal::{FileAttributes, Filesystem};
+use crate::nfs::{build_wcc_data, handle_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS COMMIT procedure (21)
 ///
-/// Commits data written with UNSTABLE writes to stable storage.
+/// Commits data written with UNSTABLE writes to stable storage. A non-zero
+/// `count` spares the file's metadata, but the backend may still write back
+/// all of the file's data rather than just that range. The returned verifier
+/// is the current one, not the one earlier WRITEs returned: it changes on
+/// restart and when a failed writeback rotates it, telling clients to
+/// resend their unstable data.
 ///
 /// # Arguments
 /// * `xid` - RPC transaction ID
 /// * `args_data` - Serialized COMMIT3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (write serialization, write verifier)
 ///
 /// # Returns
 /// Serialized COMMIT3res wrapped in RPC reply
-pub fn handle_commit(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_commit(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS COMMIT: xid={}", xid);
 
     // Parse arguments
@@ -42,35 +54,52 @@ pub fn handle_commit(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
     // Get file attributes before operation (for wcc_data)
     let file_before = filesystem.getattr(&args.file.0).ok();
 
-    // Perform commit operation
-    match filesystem.commit(&args.file.0, args.offset, args.count) {
+    // Taken before committing, so a concurrent UNSTABLE write keeps the
+    // file marked for the shutdown flush
+    let dirty_generation = ctx.state.dirty_files.generation(&args.file.0);
+
+    // Perform commit operation (serialized per file when configured)
+    let commit_result = ctx.state.write_serializer.run(&args.file.0, || {
+        filesystem.commit(&args.file.0, args.offset, args.count)
+    });
+    match commit_result {
         Ok(()) => {
             debug!("COMMIT OK");
+            if let Some(generation) = dirty_generation {
+                ctx.state.dirty_files.clear(&args.file.0, generation);
+            }
 
             // Get file attributes after operation
             let file_after = match filesystem.getattr(&args.file.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get file attributes after commit: {}", e);
                     None
                 }
             };
 
-            // Create write verifier (8 bytes)
-            // In a production implementation, this should be:
-            // - Unique per server boot
-            // - Persistent across commits
-            // - Changed only when server reboots
-            // For now, we use a constant value
-            let writeverf: [u8; 8] = [0; 8];
+            // Write verifier (8 bytes), shared with WRITE so clients can
+            // detect a reboot between UNSTABLE writes and this COMMIT
+            let writeverf = ctx.state.write_verifier.current();
 
-            create_commit_response(xid, nfsstat3::NFS3_OK, file_after, Some(writeverf))
+            create_commit_response(
+                xid,
+                nfsstat3::NFS3_OK,
+                file_before.as_ref(),
+                file_after,
+                Some(writeverf),
+            )
         }
         Err(e) => {
             warn!("COMMIT failed: {}", e);
             let status = map_error_to_status(&e);
-            let file_attr = file_before.map(|attr| NfsMessage::fsal_to_fattr3(&attr));
-            create_commit_response(xid, status, file_attr, None)
+            // Unstable data acknowledged earlier may be gone; a new
+            // verifier makes clients resend it rather than trust it
+            if status == nfsstat3::NFS3ERR_IO {
+                ctx.state.write_verifier.rotate();
+            }
+            let file_attr = file_before.as_ref().map(|attr| ctx.fattr3(attr));
+            create_commit_response(xid, status, file_before.as_ref(), file_attr, None)
         }
     }
 }
@@ -94,6 +123,7 @@ pub fn handle_commit(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 fn create_commit_response(
     xid: u32,
     status: nfsstat3,
+    file_before: Option<&FileAttributes>,
     file_attr: Option<crate::protocol::v3::nfs::fattr3>,
     writeverf: Option<[u8; 8]>,
 ) -> Result<BytesMut> {
@@ -105,19 +135,7 @@ fn create_commit_response(
     (status as i32).pack(&mut buf)?;
 
     // 2. wcc_data (file weak cache consistency)
-    // pre_op_attr (we don't track this, so set to false)
-    false.pack(&mut buf)?;
-
-    // post_op_attr (file attributes)
-    match &file_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    buf.extend(build_wcc_data(file_before.map(NfsMessage::fsal_to_wcc_attr), file_attr)?);
 
     // 3. For success case, add write verifier
     if status == nfsstat3::NFS3_OK {
@@ -135,9 +153,14 @@ fn create_commit_response(
 
 /// Map filesystem errors to NFS status codes
 fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
+    if let Some(status) = handle_error_to_nfsstat(error) {
+        return status;
+    }
     let error_msg = error.to_string().to_lowercase();
 
-    if error_msg.contains("not found") || error_msg.contains("no such file") {
+    if error_msg.contains("stale file handle") {
+        nfsstat3::NFS3ERR_STALE // 70 - Stale file handle
+    } else if error_msg.contains("not found") || error_msg.contains("no such file") {
         nfsstat3::NFS3ERR_NOENT // 2 - No such file or directory
     } else if error_msg.contains("permission denied") || error_msg.contains("access denied") {
         nfsstat3::NFS3ERR_ACCES // 13 - Permission denied
@@ -149,3 +172,112 @@ fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
         nfsstat3::NFS3ERR_IO // 5 - I/O error
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use crate::nfs::{Credentials, NfsState};
+    use crate::nfs::write::handle_write;
+    use crate::protocol::v3::nfs::{fhandle3, stable_how, COMMIT3args, WRITE3args};
+    use std::fs;
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    fn write_unstable(file: Vec<u8>, data: &[u8]) -> Vec<u8> {
+        let mut args_buf = Vec::new();
+        WRITE3args {
+            file: fhandle3(file),
+            offset: 0,
+            count: data.len() as u32,
+            stable: stable_how::UNSTABLE,
+            data: data.to_vec(),
+        }
+        .pack(&mut args_buf)
+        .unwrap();
+        args_buf
+    }
+
+    fn commit_args(file: Vec<u8>, offset: u64, count: u32) -> Vec<u8> {
+        let mut args_buf = Vec::new();
+        COMMIT3args {
+            file: fhandle3(file),
+            offset,
+            count,
+        }
+        .pack(&mut args_buf)
+        .unwrap();
+        args_buf
+    }
+
+    #[test]
+    fn test_commit_range_and_whole_file() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("data.bin"), vec![7u8; 8192]).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let handle = fs.lookup(&fs.root_handle(), "data.bin").unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        for (offset, count) in [(4096, 4096), (0, 0)] {
+            let reply =
+                handle_commit(1, &commit_args(handle.clone(), offset, count), fs.as_ref(), &ctx)
+                    .unwrap();
+            assert_eq!(&reply[24..28], &[0u8; 4], "COMMIT should succeed");
+
+            // file_wcc: pre_op_attr (4 + 24) + post_op_attr (4 + 84), then
+            // the verifier, the same one WRITE hands out
+            assert_eq!(&reply[28..32], &[0, 0, 0, 1]);
+            assert_eq!(&reply[56..60], &[0, 0, 0, 1]);
+            assert_eq!(&reply[144..152], &state.write_verifier.current());
+            assert_eq!(reply.len(), 152);
+        }
+    }
+
+    #[test]
+    fn test_commit_after_verifier_rotation() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("data.bin"), b"").unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let handle = fs.lookup(&fs.root_handle(), "data.bin").unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        // WRITE3resok: file_wcc (4 + 24 + 4 + 84), count, committed, verf
+        let reply = handle_write(1, &write_unstable(handle.clone(), b"data"), fs.as_ref(), &ctx)
+            .unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4], "WRITE should succeed");
+        let write_verf = reply[152..160].to_vec();
+
+        // The client must see a verifier other than the WRITE's, so it
+        // knows to resend the unstable data
+        state.write_verifier.rotate();
+        let reply = handle_commit(2, &commit_args(handle, 0, 0), fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4], "COMMIT should succeed");
+        assert_ne!(&reply[144..152], &write_verf[..]);
+        assert_eq!(&reply[144..152], &state.write_verifier.current());
+    }
+
+    #[test]
+    fn test_commit_stale_handle() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let reply =
+            handle_commit(1, &commit_args(vec![0xDE, 0xAD], 0, 0), fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_STALE as u32).to_be_bytes());
+    }
+}
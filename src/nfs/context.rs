@@ -0,0 +1,179 @@
+// NFS Request Context
+//
+// Shared NFS server state and the per-call context handed to procedure
+// handlers that need more than the filesystem (client address, limits, ...).
+
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{ExportConfig, NfsConfig};
+use crate::exports::client_allowed;
+use crate::fsal::FileAttributes;
+use crate::protocol::v3::nfs::{fattr3, NfsMessage};
+
+use super::credentials::Credentials;
+use super::dirty::DirtyFiles;
+use super::drc::DuplicateRequestCache;
+use super::inflight::{InflightBudget, InflightGuard};
+use super::throttle::ReaddirThrottle;
+use super::verifier::WriteVerifier;
+use super::write_serializer::WriteSerializer;
+
+/// Largest READ or WRITE payload offered over UDP, as knfsd does, so a
+/// reply always fits one datagram
+pub const MAX_UDP_TRANSFER: u32 = 32 * 1024;
+
+/// Long-lived NFS server state shared by all connections
+pub struct NfsState {
+    /// NFS protocol options
+    pub config: NfsConfig,
+    /// Per-client READDIR/READDIRPLUS entry budget
+    pub readdir_throttle: ReaddirThrottle,
+    /// Optional per-file serialization of modifying operations
+    pub write_serializer: WriteSerializer,
+    /// Write verifier returned by WRITE and COMMIT
+    pub write_verifier: WriteVerifier,
+    /// Budget for call and reply bytes in flight
+    pub inflight: Arc<InflightBudget>,
+    /// Replies to recent non-idempotent calls, replayed on retransmit
+    pub reply_cache: DuplicateRequestCache,
+    /// Files with UNSTABLE writes awaiting COMMIT, flushed on shutdown
+    pub dirty_files: DirtyFiles,
+}
+
+impl NfsState {
+    /// Build the NFS server state from configuration
+    pub fn new(config: NfsConfig) -> Self {
+        let readdir_throttle = ReaddirThrottle::new(
+            config.readdir_max_entries_per_client,
+            Duration::from_secs(config.readdir_window_secs),
+        );
+
+        let write_serializer = WriteSerializer::new(config.serialize_file_writes);
+        let inflight = Arc::new(InflightBudget::new(config.max_inflight_bytes));
+        let reply_cache = DuplicateRequestCache::new(
+            config.reply_cache_size,
+            Duration::from_secs(config.reply_cache_ttl_secs),
+        );
+
+        Self {
+            config,
+            readdir_throttle,
+            write_serializer,
+            write_verifier: WriteVerifier::new(),
+            inflight,
+            reply_cache,
+            dirty_files: DirtyFiles::new(),
+        }
+    }
+
+    /// Apply the configured owner/group override to attributes sent to a client
+    ///
+    /// This only changes what clients see; permission checks and on-disk
+    /// ownership keep using the real values.
+    fn apply_owner_override(&self, attrs: &mut fattr3) {
+        if let Some(uid) = self.config.override_uid {
+            attrs.uid = uid;
+        }
+        if let Some(gid) = self.config.override_gid {
+            attrs.gid = gid;
+        }
+    }
+}
+
+impl Default for NfsState {
+    fn default() -> Self {
+        Self::new(NfsConfig::default())
+    }
+}
+
+/// Per-call context for NFS procedure handlers
+pub struct NfsContext<'a> {
+    /// Address of the client that sent the call
+    pub client_addr: SocketAddr,
+    /// Shared NFS server state
+    pub state: &'a NfsState,
+    /// Identity of the caller (anonymous unless AUTH_SYS was used)
+    pub credentials: Credentials,
+    /// Export the call operates on, if known
+    pub export: Option<&'a ExportConfig>,
+    /// Whether the call came over UDP, where the reply must fit one datagram
+    pub udp: bool,
+    /// The call's in-flight reservation, held until its reply is sent
+    pub reservation: Option<&'a InflightGuard>,
+}
+
+impl<'a> NfsContext<'a> {
+    pub fn new(client_addr: SocketAddr, state: &'a NfsState) -> Self {
+        Self {
+            client_addr,
+            state,
+            credentials: Credentials::anonymous(),
+            export: None,
+            udp: false,
+            reservation: None,
+        }
+    }
+
+    /// Run the call with the caller's credentials
+    pub fn with_credentials(mut self, credentials: Credentials) -> Self {
+        self.credentials = credentials;
+        self
+    }
+
+    /// Run the call against `export`
+    pub fn with_export(mut self, export: &'a ExportConfig) -> Self {
+        self.export = Some(export);
+        self
+    }
+
+    /// Answer the call as received over UDP (or not)
+    pub fn with_udp(mut self, udp: bool) -> Self {
+        self.udp = udp;
+        self
+    }
+
+    /// Count the reply against the call's in-flight `reservation`
+    pub fn with_reservation(mut self, reservation: &'a InflightGuard) -> Self {
+        self.reservation = Some(reservation);
+        self
+    }
+
+    /// Reserve `bytes` of reply payload against the in-flight budget
+    ///
+    /// False when the budget has no room for them besides the call's own
+    /// bytes. Calls without a reservation aren't budgeted.
+    pub fn reserve_reply(&self, bytes: u64) -> bool {
+        self.reservation.is_none_or(|reservation| reservation.try_grow(bytes))
+    }
+
+    /// `limit` (rtmax or wtmax), lowered to `MAX_UDP_TRANSFER` for calls
+    /// over UDP
+    pub fn transfer_limit(&self, limit: u32) -> u32 {
+        if self.udp {
+            limit.min(MAX_UDP_TRANSFER)
+        } else {
+            limit
+        }
+    }
+
+    /// Whether the export the call operates on is read-only
+    pub fn read_only(&self) -> bool {
+        self.export.is_some_and(|export| export.read_only)
+    }
+
+    /// Whether the calling client is on the export's allow-list
+    pub fn client_allowed(&self) -> bool {
+        self.export
+            .is_none_or(|export| client_allowed(self.client_addr.ip(), export))
+    }
+
+    /// Attributes as sent to the client in any reply: converted to fattr3,
+    /// with the configured owner/group override applied
+    pub fn fattr3(&self, attrs: &FileAttributes) -> fattr3 {
+        let mut fattr = NfsMessage::fsal_to_fattr3(attrs);
+        self.state.apply_owner_override(&mut fattr);
+        fattr
+    }
+}
@@ -0,0 +1,81 @@
+// Directory Cookies
+//
+// READDIR and READDIRPLUS share one cookie scheme: a cookie is the position
+// in the directory listing right after the entry it was returned with, so a
+// client resuming from it picks up with the next entry.
+//
+// Positions shift when the directory changes, so each listing also carries a
+// verifier derived from the directory's mtime. A continuation presenting a
+// verifier from before a change is rejected with BAD_COOKIE and the client
+// restarts the listing instead of skipping or repeating entries.
+
+use crate::fsal::FileAttributes;
+use crate::protocol::v3::nfs::{cookie3, cookieverf3};
+
+/// Cookie for the entry at `position` in a listing that resumed at `cookie`
+pub fn entry_cookie(cookie: cookie3, position: usize) -> cookie3 {
+    cookie + position as cookie3 + 1
+}
+
+/// Cookie verifier for the directory's current state
+///
+/// Built from the directory mtime: seconds (low 32 bits) then nanoseconds.
+pub fn cookie_verifier(dir_attrs: &FileAttributes) -> cookieverf3 {
+    let mut verf = [0u8; 8];
+    verf[..4].copy_from_slice(&(dir_attrs.mtime.seconds as u32).to_be_bytes());
+    verf[4..].copy_from_slice(&dir_attrs.mtime.nseconds.to_be_bytes());
+    cookieverf3(verf)
+}
+
+/// Whether a listing resuming at `cookie` with `verf` may continue
+///
+/// The initial call (cookie 0) has no verifier to check; continuations must
+/// present the verifier of the directory as it is now.
+pub fn verifier_matches(cookie: cookie3, verf: &cookieverf3, dir_attrs: &FileAttributes) -> bool {
+    cookie == 0 || verf.0 == cookie_verifier(dir_attrs).0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_entry_cookie_resumes_after_entry() {
+        // First page: entries 0, 1, 2 get cookies 1, 2, 3
+        assert_eq!(entry_cookie(0, 0), 1);
+        assert_eq!(entry_cookie(0, 2), 3);
+        // Resuming from cookie 3, the first entry is the fourth one
+        assert_eq!(entry_cookie(3, 0), 4);
+    }
+
+    #[test]
+    fn test_verifier_tracks_mtime() {
+        use crate::fsal::{FileTime, FileType};
+
+        let time = |seconds, nseconds| FileTime { seconds, nseconds };
+        let mut attrs = FileAttributes {
+            ftype: FileType::Directory,
+            mode: 0o755,
+            nlink: 2,
+            uid: 0,
+            gid: 0,
+            size: 4096,
+            used: 4096,
+            rdev: (0, 0),
+            fsid: 1,
+            fileid: 2,
+            atime: time(100, 0),
+            mtime: time(100, 5),
+            ctime: time(100, 5),
+        };
+        let verf = cookie_verifier(&attrs);
+        assert_eq!(verf.0, [0, 0, 0, 100, 0, 0, 0, 5]);
+        assert!(verifier_matches(3, &verf, &attrs));
+
+        // The directory changed: continuations with the old verifier are stale,
+        // a fresh listing is not
+        attrs.mtime = time(100, 6);
+        assert!(!verifier_matches(3, &verf, &attrs));
+        assert!(verifier_matches(0, &verf, &attrs));
+    }
+}
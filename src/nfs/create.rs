@@ -6,18 +6,23 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::debug;
 
-use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
+use crate::fsal::{CreateMode, Filesystem};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{createhow3, nfsstat3, set_mode3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS CREATE procedure (procedure 8)
 ///
-/// Creates a new regular file.
+/// Creates a new regular file, honoring the UNCHECKED, GUARDED and
+/// EXCLUSIVE creation modes. AUTH_SYS callers need write permission on the
+/// directory, and the new file is given their uid/gid where the backend
+/// allows it.
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized CREATE3args (dir handle + filename + how)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (caller credentials)
 ///
 /// # Returns
 /// Serialized RPC reply message with new file handle
@@ -25,6 +30,7 @@ pub fn handle_create(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS CREATE called (xid={})", xid);
     debug!(
@@ -44,73 +50,91 @@ pub fn handle_create(
     );
 
     // Get directory attributes before create (for wcc_data)
-    let _before_dir_attrs = filesystem.getattr(&args.where_dir.0).ok();
+    let before_dir_attrs = filesystem.getattr(&args.where_dir.0).ok();
+
+    // Failures carry the directory's wcc_data too, as far as it can be read
+    let create_error = |status: nfsstat3| -> Result<BytesMut> {
+        let after_dir_attrs =
+            filesystem.getattr(&args.where_dir.0).ok().map(|attrs| ctx.fattr3(&attrs));
+        let res_data = NfsMessage::create_create_error_response(
+            status,
+            before_dir_attrs.as_ref().map(NfsMessage::fsal_to_wcc_attr),
+            after_dir_attrs.as_ref(),
+        )?;
+        RpcMessage::create_success_reply_with_data(xid, res_data)
+    };
 
-    // Create the file based on mode
-    let file_handle = match &args.how {
-        crate::protocol::v3::nfs::createhow3::UNCHECKED(attrs)
-        | crate::protocol::v3::nfs::createhow3::GUARDED(attrs) => {
-            // For UNCHECKED: create or truncate existing file
-            // For GUARDED: fail if file exists (checked by filesystem layer)
+    let credentials = &ctx.credentials;
+    if before_dir_attrs
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("CREATE denied for uid {}", credentials.uid);
+        return create_error(nfsstat3::NFS3ERR_ACCES);
+    }
 
+    // Map createhow3 to the FSAL create mode
+    // UNCHECKED creates or truncates, GUARDED fails if the name exists and
+    // EXCLUSIVE fails unless the existing file carries the same verifier
+    let (mode, how) = match &args.how {
+        createhow3::UNCHECKED(attrs) | createhow3::GUARDED(attrs) => {
             let mode = match &attrs.mode {
-                crate::protocol::v3::nfs::set_mode3::SET_MODE(m) => *m,
+                set_mode3::SET_MODE(m) => *m,
                 _ => 0o644, // Default mode
             };
-
-            // Create the file
-            match filesystem.create(&args.where_dir.0, &filename, mode) {
-                Ok(handle) => handle,
-                Err(e) => {
-                    debug!("CREATE failed: {}", e);
-                    let error_status = if e.to_string().contains("exists") {
-                        nfsstat3::NFS3ERR_EXIST
-                    } else if e.to_string().contains("not found") {
-                        nfsstat3::NFS3ERR_NOENT
-                    } else if e.to_string().contains("Not a directory") {
-                        nfsstat3::NFS3ERR_NOTDIR
-                    } else if e.to_string().contains("Permission denied") {
-                        nfsstat3::NFS3ERR_ACCES
-                    } else if e.to_string().contains("No space") {
-                        nfsstat3::NFS3ERR_NOSPC
-                    } else if e.to_string().contains("Read-only") {
-                        nfsstat3::NFS3ERR_ROFS
-                    } else {
-                        nfsstat3::NFS3ERR_IO
-                    };
-                    let res_data = NfsMessage::create_create_error_response(error_status)?;
-                    return RpcMessage::create_success_reply_with_data(xid, res_data);
-                }
-            }
+            let how = if matches!(args.how, createhow3::GUARDED(_)) {
+                CreateMode::Guarded
+            } else {
+                CreateMode::Unchecked
+            };
+            (mode, how)
         }
-        crate::protocol::v3::nfs::createhow3::EXCLUSIVE(_verf) => {
-            // EXCLUSIVE mode: create file with verifier stored in mtime/atime
-            // This is for safe concurrent creation
-            // For simplicity, we'll treat it like GUARDED for now
-            match filesystem.create(&args.where_dir.0, &filename, 0o644) {
-                Ok(handle) => handle,
-                Err(e) => {
-                    debug!("CREATE (EXCLUSIVE) failed: {}", e);
-                    let error_status = if e.to_string().contains("exists") {
-                        nfsstat3::NFS3ERR_EXIST
-                    } else {
-                        nfsstat3::NFS3ERR_IO
-                    };
-                    let res_data = NfsMessage::create_create_error_response(error_status)?;
-                    return RpcMessage::create_success_reply_with_data(xid, res_data);
-                }
-            }
+        createhow3::EXCLUSIVE(verf) => (0o644, CreateMode::Exclusive(verf.0)),
+    };
+
+    // Create the file
+    let file_handle = match filesystem.create(&args.where_dir.0, filename, mode, how) {
+        Ok(handle) => handle,
+        Err(e) => {
+            debug!("CREATE ({:?}) failed: {}", how, e);
+            let error_status = if let Some(status) = io_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("Stale file handle") {
+                nfsstat3::NFS3ERR_STALE
+            } else if e.to_string().contains("exists") {
+                nfsstat3::NFS3ERR_EXIST
+            } else if e.to_string().contains("not found") {
+                nfsstat3::NFS3ERR_NOENT
+            } else if e.to_string().contains("Not a directory") {
+                nfsstat3::NFS3ERR_NOTDIR
+            } else if e.to_string().contains("Permission denied") {
+                nfsstat3::NFS3ERR_ACCES
+            } else if e.to_string().contains("No space") {
+                nfsstat3::NFS3ERR_NOSPC
+            } else if e.to_string().contains("Read-only") {
+                nfsstat3::NFS3ERR_ROFS
+            } else {
+                nfsstat3::NFS3ERR_IO
+            };
+            return create_error(error_status);
         }
     };
 
+    // The new file belongs to the caller; a backend that cannot change
+    // ownership (e.g. an unprivileged server) keeps its own
+    if let Err(e) =
+        filesystem.setattr_owner(&file_handle, Some(credentials.uid), Some(credentials.gid))
+    {
+        debug!("CREATE: could not give new file to caller: {}", e);
+    }
+
     // Get file attributes
     let file_attrs = match filesystem.getattr(&file_handle) {
         Ok(attrs) => attrs,
         Err(e) => {
             debug!("CREATE: failed to get file attributes: {}", e);
             let error_status = nfsstat3::NFS3ERR_IO;
-            let res_data = NfsMessage::create_create_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return create_error(error_status);
         }
     };
 
@@ -120,16 +144,15 @@ pub fn handle_create(
         Err(e) => {
             debug!("CREATE: failed to get dir attributes: {}", e);
             let error_status = nfsstat3::NFS3ERR_IO;
-            let res_data = NfsMessage::create_create_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return create_error(error_status);
         }
     };
 
     debug!("CREATE success: new file handle {} bytes", file_handle.len());
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_file_attrs = NfsMessage::fsal_to_fattr3(&file_attrs);
-    let nfs_dir_attrs = NfsMessage::fsal_to_fattr3(&dir_attrs);
+    let nfs_file_attrs = ctx.fattr3(&file_attrs);
+    let nfs_dir_attrs = ctx.fattr3(&dir_attrs);
 
     // Create CREATE response
     use xdr_codec::Pack;
@@ -155,12 +178,10 @@ pub fn handle_create(
     nfs_file_attrs.pack(&mut buf)?;
 
     // dir_wcc: wcc_data (directory weak cache consistency)
-    // pre_op_attr
-    false.pack(&mut buf)?; // pre_op_attr = FALSE
-
-    // post_op_attr
-    true.pack(&mut buf)?; // attributes_follow = TRUE
-    nfs_dir_attrs.pack(&mut buf)?;
+    buf.extend(build_wcc_data(
+        before_dir_attrs.as_ref().map(NfsMessage::fsal_to_wcc_attr),
+        Some(nfs_dir_attrs),
+    )?);
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -172,9 +193,26 @@ pub fn handle_create(
 mod tests {
     use super::*;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::{Credentials, NfsState};
     use std::fs;
     use tempfile::TempDir;
 
+    fn create(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        create_as(xid, args_buf, fs, Credentials::root())
+    }
+
+    fn create_as(
+        xid: u32,
+        args_buf: &[u8],
+        fs: &dyn Filesystem,
+        credentials: Credentials,
+    ) -> Result<BytesMut> {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(credentials);
+        handle_create(xid, args_buf, fs, &ctx)
+    }
+
     #[test]
     fn test_create_file() {
         // Create temp filesystem
@@ -209,7 +247,7 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call CREATE
-        let result = handle_create(12345, &args_buf, fs.as_ref());
+        let result = create(12345, &args_buf, fs.as_ref());
 
         assert!(result.is_ok(), "CREATE should succeed");
 
@@ -255,8 +293,122 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call CREATE - should succeed (UNCHECKED allows overwriting)
-        let result = handle_create(12345, &args_buf, fs.as_ref());
+        let result = create(12345, &args_buf, fs.as_ref());
 
         assert!(result.is_ok(), "CREATE UNCHECKED should succeed even if file exists");
     }
+
+    fn create_args(dir: Vec<u8>, name: &str, how: createhow3) -> Vec<u8> {
+        use crate::protocol::v3::nfs::{fhandle3, filename3, CREATE3args};
+        use xdr_codec::Pack;
+
+        let args = CREATE3args {
+            where_dir: fhandle3(dir),
+            name: filename3(name.to_string()),
+            how,
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    fn reply_status(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_create_guarded_existing() {
+        use crate::protocol::v3::nfs::{
+            sattr3, set_atime, set_gid3, set_mtime, set_size3, set_uid3,
+        };
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        fs::write(temp_dir.path().join("existing.txt"), b"keep me").unwrap();
+
+        let attrs = sattr3 {
+            mode: set_mode3::SET_MODE(0o644),
+            uid: set_uid3::default,
+            gid: set_gid3::default,
+            size: set_size3::default,
+            atime: set_atime::default,
+            mtime: set_mtime::default,
+        };
+        let args_buf = create_args(fs.root_handle(), "existing.txt", createhow3::GUARDED(attrs));
+        let reply = create(1, &args_buf, fs.as_ref()).unwrap();
+
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_EXIST as u32);
+        assert_eq!(
+            fs::read(temp_dir.path().join("existing.txt")).unwrap(),
+            b"keep me",
+            "GUARDED must not touch the existing file"
+        );
+    }
+
+    #[test]
+    fn test_create_exclusive_retransmission() {
+        use crate::protocol::v3::nfs::createverf3;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let root = fs.root_handle();
+
+        let verf = createverf3([0xAA, 0xBB, 0xCC, 0xDD, 1, 2, 3, 4]);
+        let args_buf = create_args(root.clone(), "excl.txt", createhow3::EXCLUSIVE(verf));
+
+        let first = create(1, &args_buf, fs.as_ref()).unwrap();
+        assert_eq!(reply_status(&first), nfsstat3::NFS3_OK as u32);
+
+        // Same verifier: idempotent, same handle in the reply
+        let again = create(2, &args_buf, fs.as_ref()).unwrap();
+        assert_eq!(reply_status(&again), nfsstat3::NFS3_OK as u32);
+        let handle_len = u32::from_be_bytes([first[32], first[33], first[34], first[35]]) as usize;
+        assert_eq!(&first[32..36 + handle_len], &again[32..36 + handle_len]);
+
+        // Different verifier: EXIST
+        let other = createverf3([9; 8]);
+        let args_buf = create_args(root, "excl.txt", createhow3::EXCLUSIVE(other));
+        let reply = create(3, &args_buf, fs.as_ref()).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_EXIST as u32);
+    }
+
+    #[test]
+    fn test_create_requires_directory_write_permission() {
+        use crate::protocol::v3::nfs::{
+            sattr3, set_atime, set_gid3, set_mtime, set_size3, set_uid3,
+        };
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let root = fs.root_handle();
+        let dir = fs.getattr(&root).unwrap();
+
+        let attrs = sattr3 {
+            mode: set_mode3::SET_MODE(0o644),
+            uid: set_uid3::default,
+            gid: set_gid3::default,
+            size: set_size3::default,
+            atime: set_atime::default,
+            mtime: set_mtime::default,
+        };
+        let args_buf = create_args(root, "denied.txt", createhow3::UNCHECKED(attrs));
+
+        // Neither owner nor in the group of the (0755 or tighter) directory
+        let stranger = Credentials {
+            uid: dir.uid.wrapping_add(1),
+            gid: dir.gid.wrapping_add(1),
+            gids: vec![],
+            anonymous: false,
+        };
+        let reply = create_as(1, &args_buf, fs.as_ref(), stranger).unwrap();
+
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_ACCES as u32);
+        assert!(!temp_dir.path().join("denied.txt").exists());
+    }
 }
@@ -0,0 +1,323 @@
+// Caller Credentials
+//
+// Identity of the caller of an NFS procedure, taken from the RPC credential.
+// AUTH_SYS supplies uid/gid/gids; every other flavor maps to the anonymous
+// identity. Against an export, anonymous callers then act as the export's
+// anonymous uid/gid, and the squash policy may map AUTH_SYS identities to it
+// as well.
+
+use tracing::warn;
+
+use crate::config::{ExportConfig, SquashPolicy};
+use crate::fsal::{FileAttributes, FileType};
+use crate::protocol::v3::rpc::{auth_flavor, opaque_auth, RpcMessage};
+
+/// uid used for anonymous callers ("nobody")
+pub const ANONYMOUS_UID: u32 = 65534;
+
+/// gid used for anonymous callers ("nogroup")
+pub const ANONYMOUS_GID: u32 = 65534;
+
+/// Identity of the caller
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Credentials {
+    pub uid: u32,
+    pub gid: u32,
+    /// Supplementary groups
+    pub gids: Vec<u32>,
+    /// True unless the caller presented AUTH_SYS credentials; cleared once
+    /// the identity is mapped onto an export's anonymous uid/gid
+    pub anonymous: bool,
+}
+
+impl Credentials {
+    /// The anonymous identity (AUTH_NONE and unsupported flavors)
+    pub fn anonymous() -> Self {
+        Self {
+            uid: ANONYMOUS_UID,
+            gid: ANONYMOUS_GID,
+            gids: Vec::new(),
+            anonymous: true,
+        }
+    }
+
+    /// Root, for tests that are not about permissions
+    #[cfg(test)]
+    pub fn root() -> Self {
+        Self {
+            uid: 0,
+            gid: 0,
+            gids: Vec::new(),
+            anonymous: false,
+        }
+    }
+
+    /// Resolve the credentials carried by an RPC call
+    ///
+    /// An AUTH_SYS body that fails to decode is treated as anonymous.
+    pub fn from_auth(cred: &opaque_auth) -> Self {
+        match cred.flavor {
+            auth_flavor::AUTH_SYS => match RpcMessage::deserialize_auth_sys(&cred.body) {
+                Ok(params) => Self {
+                    uid: params.uid,
+                    gid: params.gid,
+                    gids: params.gids,
+                    anonymous: false,
+                },
+                Err(e) => {
+                    warn!("Failed to decode AUTH_SYS credential, using anonymous: {}", e);
+                    Self::anonymous()
+                }
+            },
+            _ => Self::anonymous(),
+        }
+    }
+
+    /// Whether the caller's primary or supplementary groups include `gid`
+    pub fn in_group(&self, gid: u32) -> bool {
+        self.gid == gid || self.gids.contains(&gid)
+    }
+
+    /// Permission bits (read 0o4, write 0o2, execute/search 0o1) the caller
+    /// has on a file or directory
+    ///
+    /// Takes the owner, group or other class that applies to the caller.
+    /// Root may read and write anything, search any directory and execute
+    /// files with at least one execute bit set. Anonymous callers are
+    /// checked like anyone else, as nobody/nogroup unless an export maps
+    /// them to its anonuid/anongid.
+    pub fn permissions(&self, attrs: &FileAttributes) -> u32 {
+        if self.uid == 0 {
+            let executable = attrs.ftype == FileType::Directory || attrs.mode & 0o111 != 0;
+            return 0o6 | u32::from(executable);
+        }
+        let shift = if self.uid == attrs.uid {
+            6
+        } else if self.in_group(attrs.gid) {
+            3
+        } else {
+            0
+        };
+        (attrs.mode >> shift) & 0o7
+    }
+
+    /// Whether the caller may modify the contents of a file or directory
+    ///
+    /// Checks the owner, group or other write bit that applies to the caller;
+    /// root may always write.
+    pub fn may_write(&self, attrs: &FileAttributes) -> bool {
+        self.permissions(attrs) & 0o2 != 0
+    }
+
+    /// Whether the caller may change a file's mode, owner or times
+    ///
+    /// Only the owner and root may.
+    pub fn owns(&self, attrs: &FileAttributes) -> bool {
+        self.uid == 0 || self.uid == attrs.uid
+    }
+}
+
+/// Apply an export's squash policy to the caller's credentials
+///
+/// Under root_squash, uid 0 and gid 0 are mapped to the export's
+/// anonuid/anongid independently and gid 0 is dropped from the
+/// supplementary groups, so a non-root caller keeps its own uid.
+/// Squashed and anonymous (AUTH_NONE) callers act as the export's
+/// anonuid/anongid with no supplementary groups, and are subject to
+/// permission checks rather than getting the server's own access.
+pub fn squash_credentials(creds: &Credentials, export: &ExportConfig) -> Credentials {
+    if creds.anonymous || export.squash == SquashPolicy::AllSquash {
+        return Credentials {
+            uid: export.anonuid,
+            gid: export.anongid,
+            gids: Vec::new(),
+            anonymous: false,
+        };
+    }
+
+    match export.squash {
+        SquashPolicy::RootSquash => Credentials {
+            uid: if creds.uid == 0 { export.anonuid } else { creds.uid },
+            gid: if creds.gid == 0 { export.anongid } else { creds.gid },
+            gids: creds.gids.iter().copied().filter(|&gid| gid != 0).collect(),
+            anonymous: false,
+        },
+        _ => creds.clone(),
+    }
+}
+
+impl Default for Credentials {
+    fn default() -> Self {
+        Self::anonymous()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::FileTime;
+    use crate::protocol::v3::rpc::auth_sys_params;
+    use xdr_codec::Pack;
+
+    fn attrs(mode: u32, uid: u32, gid: u32) -> FileAttributes {
+        let time = FileTime {
+            seconds: 0,
+            nseconds: 0,
+        };
+        FileAttributes {
+            ftype: FileType::RegularFile,
+            mode,
+            nlink: 1,
+            uid,
+            gid,
+            size: 0,
+            used: 0,
+            rdev: (0, 0),
+            fsid: 0,
+            fileid: 1,
+            atime: time,
+            mtime: time,
+            ctime: time,
+        }
+    }
+
+    fn auth_sys(uid: u32, gid: u32, gids: Vec<u32>) -> Credentials {
+        Credentials {
+            uid,
+            gid,
+            gids,
+            anonymous: false,
+        }
+    }
+
+    #[test]
+    fn test_from_auth_sys() {
+        let params = auth_sys_params {
+            stamp: 1,
+            machinename: "client".to_string(),
+            uid: 1000,
+            gid: 100,
+            gids: vec![100, 27],
+        };
+        let mut body = Vec::new();
+        params.pack(&mut body).unwrap();
+
+        let creds = Credentials::from_auth(&opaque_auth {
+            flavor: auth_flavor::AUTH_SYS,
+            body,
+        });
+        assert_eq!(creds, auth_sys(1000, 100, vec![100, 27]));
+    }
+
+    #[test]
+    fn test_auth_none_and_garbage_are_anonymous() {
+        let none = Credentials::from_auth(&opaque_auth {
+            flavor: auth_flavor::AUTH_NONE,
+            body: vec![],
+        });
+        assert_eq!(none, Credentials::anonymous());
+
+        let garbage = Credentials::from_auth(&opaque_auth {
+            flavor: auth_flavor::AUTH_SYS,
+            body: vec![0, 0, 0],
+        });
+        assert_eq!(garbage, Credentials::anonymous());
+    }
+
+    #[test]
+    fn test_squash_credentials() {
+        let mut export = ExportConfig {
+            anonuid: 2000,
+            anongid: 3000,
+            ..ExportConfig::default()
+        };
+        let root = auth_sys(0, 0, vec![10]);
+        let user = auth_sys(1000, 100, vec![27]);
+        let squashed = auth_sys(2000, 3000, vec![]);
+
+        // root_squash is the default
+        assert_eq!(squash_credentials(&root, &export), auth_sys(2000, 3000, vec![10]));
+        assert_eq!(squash_credentials(&user, &export), user);
+
+        // uid and gid are squashed independently, and gid 0 is stripped
+        assert_eq!(
+            squash_credentials(&auth_sys(1000, 0, vec![0, 27]), &export),
+            auth_sys(1000, 3000, vec![27])
+        );
+        assert_eq!(
+            squash_credentials(&auth_sys(0, 100, vec![27]), &export),
+            auth_sys(2000, 100, vec![27])
+        );
+
+        export.squash = SquashPolicy::NoRootSquash;
+        assert_eq!(squash_credentials(&root, &export), root);
+
+        export.squash = SquashPolicy::AllSquash;
+        assert_eq!(squash_credentials(&user, &export), squashed);
+    }
+
+    #[test]
+    fn test_anonymous_acts_as_export_identity() {
+        let export = ExportConfig {
+            squash: SquashPolicy::NoRootSquash,
+            anonuid: 2000,
+            anongid: 3000,
+            ..ExportConfig::default()
+        };
+
+        // Even without any squashing, AUTH_NONE never gets the server's access
+        let creds = squash_credentials(&Credentials::anonymous(), &export);
+        assert_eq!(creds, auth_sys(2000, 3000, vec![]));
+        assert!(!creds.may_write(&attrs(0o644, 0, 0)));
+        assert!(creds.may_write(&attrs(0o644, 2000, 0)));
+
+        // Defaults to nobody/nogroup
+        let creds = squash_credentials(&Credentials::anonymous(), &ExportConfig::default());
+        assert_eq!(creds, auth_sys(ANONYMOUS_UID, ANONYMOUS_GID, vec![]));
+
+        // Unmapped, AUTH_NONE is nobody too rather than the server itself
+        let anonymous = Credentials::anonymous();
+        assert!(!anonymous.may_write(&attrs(0o644, 0, 0)));
+        assert!(!anonymous.owns(&attrs(0o644, 0, 0)));
+        assert_eq!(anonymous.permissions(&attrs(0o604, 0, 0)), 0o4);
+    }
+
+    #[test]
+    fn test_may_write() {
+        let file = attrs(0o640, 1000, 100);
+
+        assert!(auth_sys(1000, 1000, vec![]).may_write(&file));
+        assert!(auth_sys(0, 0, vec![]).may_write(&file));
+        // Group has read only, other has nothing
+        assert!(!auth_sys(1001, 1001, vec![100]).may_write(&file));
+        assert!(!auth_sys(1002, 1002, vec![]).may_write(&file));
+
+        let shared = attrs(0o664, 1000, 100);
+        assert!(auth_sys(1001, 1001, vec![100]).may_write(&shared));
+        assert!(!auth_sys(1002, 1002, vec![]).may_write(&shared));
+    }
+
+    #[test]
+    fn test_permissions() {
+        let file = attrs(0o754, 1000, 100);
+        assert_eq!(auth_sys(1000, 1000, vec![]).permissions(&file), 0o7);
+        assert_eq!(auth_sys(1001, 1001, vec![100]).permissions(&file), 0o5);
+        assert_eq!(auth_sys(1002, 1002, vec![]).permissions(&file), 0o4);
+
+        // Root executes only what someone may execute
+        assert_eq!(auth_sys(0, 0, vec![]).permissions(&file), 0o7);
+        assert_eq!(auth_sys(0, 0, vec![]).permissions(&attrs(0o600, 1000, 100)), 0o6);
+        let mut dir = attrs(0o700, 1000, 100);
+        dir.ftype = FileType::Directory;
+        assert_eq!(auth_sys(0, 0, vec![]).permissions(&dir), 0o7);
+        assert_eq!(auth_sys(1001, 1001, vec![]).permissions(&dir), 0);
+    }
+
+    #[test]
+    fn test_owns() {
+        let file = attrs(0o666, 1000, 100);
+        assert!(auth_sys(1000, 100, vec![]).owns(&file));
+        assert!(auth_sys(0, 0, vec![]).owns(&file));
+        assert!(!auth_sys(1001, 100, vec![]).owns(&file));
+    }
+}
@@ -2,12 +2,16 @@
 //
 // Routes incoming NFS RPC calls to the appropriate procedure handler
 
-use anyhow::{anyhow, Result};
+use anyhow::Result;
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
-use crate::protocol::v3::rpc::rpc_call_msg;
+use crate::fsal::{Capabilities, Filesystem};
+use crate::protocol::v3::nfs::nfsstat3;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::drc::{DrcKey, DrcLookup, InProgress};
+use super::{NfsContext, NFS_V3};
 
 use super::{access, commit, create, fsinfo, fsstat, getattr, link, lookup, mkdir, mknod, null, pathconf, read, readdir, readdirplus, readlink, remove, rename, rmdir, setattr, symlink, write};
 
@@ -17,6 +21,7 @@ use super::{access, commit, create, fsinfo, fsstat, getattr, link, lookup, mkdir
 /// * `call` - Parsed RPC call message
 /// * `args_data` - Procedure arguments data
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (client address, shared NFS state)
 ///
 /// # Returns
 /// Serialized RPC reply message
@@ -24,6 +29,7 @@ pub fn dispatch(
     call: &rpc_call_msg,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     let procedure = call.proc_;
     let xid = call.xid;
@@ -34,11 +40,91 @@ pub fn dispatch(
     );
 
     // Verify NFS version
-    if call.vers != 3 {
-        warn!("Unsupported NFS version: {}", call.vers);
-        return Err(anyhow!("NFS version {} not supported", call.vers));
+    // NFSv2 and NFSv4 probes get PROG_MISMATCH pointing them at v3, so the
+    // client can retry on the same connection
+    if call.vers != NFS_V3 {
+        warn!(
+            "Unsupported NFS version: {} (supported: {}-{})",
+            call.vers, NFS_V3, NFS_V3
+        );
+        return RpcMessage::create_prog_mismatch_reply(xid, NFS_V3, NFS_V3);
+    }
+
+    // A handle the export never issued (made up, or kept from an earlier
+    // server instance) is stale, whether or not the client ever mounted;
+    // it never reaches a handler
+    if procedure != 0 {
+        if let Some(handle) = super::file_handle_arg(args_data) {
+            if !filesystem.owns_handle(&handle.to_vec()) {
+                debug!("NFS procedure {} with unknown file handle: stale", procedure);
+                return create_failure_response(xid, nfsstat3::NFS3ERR_STALE, procedure);
+            }
+        }
+    }
+
+    // Operations the backend cannot perform fail with NOTSUPP up front
+    let capabilities = filesystem.capabilities();
+    if let Some(missing) =
+        required_capability(procedure).filter(|&required| !capabilities.contains(required))
+    {
+        debug!(
+            "NFS procedure {} not supported by backend ({:?} missing)",
+            procedure, missing
+        );
+        return create_unsupported_response(xid, procedure);
+    }
+
+    // Clients outside the export's allow-list may only ping (NULL)
+    if procedure != 0 && !ctx.client_allowed() {
+        warn!(
+            "NFS procedure {} from {} rejected: client not allowed by export",
+            procedure, ctx.client_addr
+        );
+        return create_failure_response(xid, nfsstat3::NFS3ERR_ACCES, procedure);
+    }
+
+    // Nothing on a read-only export may be modified
+    if ctx.read_only() && is_modifying(procedure) {
+        debug!("NFS procedure {} rejected: export is read-only", procedure);
+        return create_failure_response(xid, nfsstat3::NFS3ERR_ROFS, procedure);
+    }
+
+    // Retransmits of non-idempotent calls get the original reply replayed
+    // rather than being executed again, and are dropped while it still runs
+    let drc_key = is_reply_cached(procedure)
+        .then(|| DrcKey::new(ctx.client_addr, xid, call.prog, procedure, args_data));
+    if let Some(key) = &drc_key {
+        match ctx.state.reply_cache.begin(key) {
+            DrcLookup::New => {}
+            DrcLookup::InProgress => {
+                debug!("NFS procedure {} xid={} dropped: original still in progress", procedure, xid);
+                return Err(InProgress.into());
+            }
+            DrcLookup::Replay(reply) => {
+                debug!("NFS procedure {} xid={} replayed from reply cache", procedure, xid);
+                return Ok(reply);
+            }
+        }
+    }
+
+    let reply = dispatch_procedure(procedure, xid, args_data, filesystem, ctx);
+    if let Some(key) = drc_key {
+        match &reply {
+            Ok(reply) => ctx.state.reply_cache.insert(key, reply),
+            Err(_) => ctx.state.reply_cache.abandon(&key),
+        }
     }
+    reply
+}
 
+/// Run the handler for a procedure
+fn dispatch_procedure(
+    procedure: u32,
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     // Dispatch based on procedure number
     match procedure {
         0 => {
@@ -47,87 +133,87 @@ pub fn dispatch(
         }
         1 => {
             // GETATTR - get file attributes
-            getattr::handle_getattr(xid, args_data, filesystem)
+            getattr::handle_getattr(xid, args_data, filesystem, ctx)
         }
         2 => {
             // SETATTR - set file attributes
-            setattr::handle_setattr(xid, args_data, filesystem)
+            setattr::handle_setattr(xid, args_data, filesystem, ctx)
         }
         3 => {
             // LOOKUP - lookup filename
-            lookup::handle_lookup(xid, args_data, filesystem)
+            lookup::handle_lookup(xid, args_data, filesystem, ctx)
         }
         4 => {
             // ACCESS - check file access permissions
-            access::handle_access(xid, args_data, filesystem)
+            access::handle_access(xid, args_data, filesystem, ctx)
         }
         5 => {
             // READLINK - read symbolic link
-            readlink::handle_readlink(xid, args_data, filesystem)
+            readlink::handle_readlink(xid, args_data, filesystem, ctx)
         }
         6 => {
             // READ - read from file
-            read::handle_read(xid, args_data, filesystem)
+            read::handle_read(xid, args_data, filesystem, ctx)
         }
         16 => {
             // READDIR - read directory entries
-            readdir::handle_readdir(xid, args_data, filesystem)
+            readdir::handle_readdir(xid, args_data, filesystem, ctx)
         }
         18 => {
             // FSSTAT - get filesystem statistics
-            fsstat::handle_fsstat(xid, args_data, filesystem)
+            fsstat::handle_fsstat(xid, args_data, filesystem, ctx)
         }
         19 => {
             // FSINFO - get filesystem information
-            fsinfo::handle_fsinfo(xid, args_data, filesystem)
+            fsinfo::handle_fsinfo(xid, args_data, filesystem, ctx)
         }
         20 => {
             // PATHCONF - get filesystem path configuration
-            pathconf::handle_pathconf(xid, args_data, filesystem)
+            pathconf::handle_pathconf(xid, args_data, filesystem, ctx)
         }
         17 => {
             // READDIRPLUS - read directory entries with attributes
-            readdirplus::handle_readdirplus(xid, args_data, filesystem)
+            readdirplus::handle_readdirplus(xid, args_data, filesystem, ctx)
         }
         7 => {
             // WRITE - write to file
-            write::handle_write(xid, args_data, filesystem)
+            write::handle_write(xid, args_data, filesystem, ctx)
         }
         8 => {
             // CREATE - create file
-            create::handle_create(xid, args_data, filesystem)
+            create::handle_create(xid, args_data, filesystem, ctx)
         }
         9 => {
             // MKDIR - create directory
-            mkdir::handle_mkdir(xid, args_data, filesystem)
+            mkdir::handle_mkdir(xid, args_data, filesystem, ctx)
         }
         10 => {
             // SYMLINK - create symbolic link
-            symlink::handle_symlink(xid, args_data, filesystem)
+            symlink::handle_symlink(xid, args_data, filesystem, ctx)
         }
         11 => {
             // MKNOD - create special file
-            mknod::handle_mknod(xid, args_data, filesystem)
+            mknod::handle_mknod(xid, args_data, filesystem, ctx)
         }
         12 => {
             // REMOVE - remove file
-            remove::handle_remove(xid, args_data, filesystem)
+            remove::handle_remove(xid, args_data, filesystem, ctx)
         }
         13 => {
             // RMDIR - remove directory
-            rmdir::handle_rmdir(xid, args_data, filesystem)
+            rmdir::handle_rmdir(xid, args_data, filesystem, ctx)
         }
         14 => {
             // RENAME - rename file or directory
-            rename::handle_rename(xid, args_data, filesystem)
+            rename::handle_rename(xid, args_data, filesystem, ctx)
         }
         15 => {
             // LINK - create hard link
-            link::handle_link(xid, args_data, filesystem)
+            link::handle_link(xid, args_data, filesystem, ctx)
         }
         21 => {
             // COMMIT - commit cached writes to stable storage
-            commit::handle_commit(xid, args_data, filesystem)
+            commit::handle_commit(xid, args_data, filesystem, ctx)
         }
         _ => {
             warn!("Unknown NFS procedure: {}", procedure);
@@ -136,12 +222,413 @@ pub fn dispatch(
     }
 }
 
+/// Whether replies to a procedure go through the duplicate request cache
+///
+/// Every non-idempotent procedure: a retransmitted WRITE would be applied
+/// twice, a CREATE/MKDIR would fail with EXIST and a REMOVE with NOENT.
+/// SETATTR: a guarded retransmit would otherwise fail with NOT_SYNC against
+/// the ctime its own first attempt changed.
+fn is_reply_cached(procedure: u32) -> bool {
+    matches!(procedure, 2 | 7..=15)
+}
+
+/// Whether a procedure modifies the exported filesystem
+///
+/// COMMIT is included: on a read-only export there is nothing to commit.
+fn is_modifying(procedure: u32) -> bool {
+    matches!(procedure, 2 | 7..=15 | 21)
+}
+
+/// Backend capability an NFS procedure depends on, if any
+fn required_capability(procedure: u32) -> Option<Capabilities> {
+    match procedure {
+        10 => Some(Capabilities::SYMLINK),
+        11 => Some(Capabilities::MKNOD),
+        15 => Some(Capabilities::HARD_LINK),
+        _ => None,
+    }
+}
+
+/// Create a NFS3ERR_NOTSUPP response for an operation the backend lacks
+///
+/// Unlike `create_notsupp_response`, this includes the (empty) resfail body
+/// of the procedure so clients can decode it.
+fn create_unsupported_response(xid: u32, procedure: u32) -> Result<BytesMut> {
+    create_failure_response(xid, nfsstat3::NFS3ERR_NOTSUPP, procedure)
+}
+
+/// Create an error response with the procedure's resfail body, all of whose
+/// optional attributes are absent:
+/// - GETATTR: nothing
+/// - LOOKUP/ACCESS/READLINK/READ/READDIR/READDIRPLUS/FSSTAT/FSINFO/PATHCONF:
+///   post_op_attr
+/// - SETATTR/WRITE/COMMIT: obj_wcc (pre_op_attr, post_op_attr)
+/// - CREATE/MKDIR/SYMLINK/MKNOD/REMOVE/RMDIR: dir_wcc
+/// - RENAME: fromdir_wcc + todir_wcc
+/// - LINK: file_attributes (post_op_attr) + linkdir_wcc
+fn create_failure_response(xid: u32, status: nfsstat3, procedure: u32) -> Result<BytesMut> {
+    use xdr_codec::Pack;
+
+    let mut buf = Vec::new();
+    (status as i32).pack(&mut buf)?;
+    let empty_attrs = match procedure {
+        1 => 0,
+        3..=6 | 16..=20 => 1,
+        14 => 4,
+        15 => 3,
+        _ => 2,
+    };
+    for _ in 0..empty_attrs {
+        false.pack(&mut buf)?;
+    }
+    let res_data = BytesMut::from(&buf[..]);
+    RpcMessage::create_success_reply_with_data(xid, res_data)
+}
+
 /// Create a NFS3ERR_NOTSUPP error response
 fn create_notsupp_response(xid: u32) -> Result<BytesMut> {
     use xdr_codec::Pack;
 
     let mut buf = Vec::new();
-    (crate::protocol::v3::nfs::nfsstat3::NFS3ERR_NOTSUPP as i32).pack(&mut buf)?;
+    (nfsstat3::NFS3ERR_NOTSUPP as i32).pack(&mut buf)?;
     let res_data = BytesMut::from(&buf[..]);
-    crate::protocol::v3::rpc::RpcMessage::create_success_reply_with_data(xid, res_data)
+    RpcMessage::create_success_reply_with_data(xid, res_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::{BackendConfig, LocalFilesystem};
+    use crate::nfs::{Credentials, NfsState};
+    use crate::protocol::v3::rpc::{auth_flavor, msg_type, opaque_auth};
+    use tempfile::TempDir;
+
+    fn nfs_call(vers: u32) -> rpc_call_msg {
+        rpc_call_msg {
+            xid: 42,
+            mtype: msg_type::CALL,
+            rpcvers: 2,
+            prog: crate::nfs::NFS_PROGRAM,
+            vers,
+            proc_: 0,
+            cred: opaque_auth {
+                flavor: auth_flavor::AUTH_NONE,
+                body: vec![],
+            },
+            verf: opaque_auth {
+                flavor: auth_flavor::AUTH_NONE,
+                body: vec![],
+            },
+        }
+    }
+
+    #[test]
+    fn test_dispatch_version_mismatch() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        for vers in [2, 4] {
+            let reply = dispatch(&nfs_call(vers), &[], fs.as_ref(), &ctx)
+                .expect("version mismatch should produce a reply, not an error");
+
+            // RPC header (24) + mismatch_info (8)
+            assert_eq!(reply.len(), 32);
+            // accept_stat = PROG_MISMATCH (2)
+            assert_eq!(&reply[20..24], &[0u8, 0, 0, 2]);
+            // low = 3, high = 3
+            assert_eq!(&reply[24..32], &[0u8, 0, 0, 3, 0, 0, 0, 3]);
+        }
+    }
+
+    #[test]
+    fn test_dispatch_v3_null() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        let reply = dispatch(&nfs_call(3), &[], fs.as_ref(), &ctx).unwrap();
+
+        // accept_stat = SUCCESS
+        assert_eq!(&reply[20..24], &[0u8; 4]);
+    }
+
+    #[test]
+    fn test_dispatch_unsupported_capabilities() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = LocalFilesystem::new(temp_dir.path())
+            .unwrap()
+            .with_capabilities(
+                Capabilities::all()
+                    .without(Capabilities::SYMLINK)
+                    .without(Capabilities::MKNOD)
+                    .without(Capabilities::HARD_LINK),
+            );
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        // SYMLINK (10), MKNOD (11), LINK (15): rejected before the arguments
+        // are even decoded
+        for (procedure, reply_len) in [(10, 36), (11, 36), (15, 40)] {
+            let mut call = nfs_call(3);
+            call.proc_ = procedure;
+            let reply = dispatch(&call, &[], &fs, &ctx).unwrap();
+
+            assert_eq!(&reply[20..24], &[0u8; 4], "accept_stat should be SUCCESS");
+            // nfsstat3 = NFS3ERR_NOTSUPP (10004)
+            assert_eq!(&reply[24..28], &10004u32.to_be_bytes());
+            assert_eq!(reply.len(), reply_len);
+        }
+    }
+
+    #[test]
+    fn test_unknown_handles_stale() {
+        use crate::protocol::v3::nfs::{fhandle3, GETATTR3args, READ3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        std::fs::write(temp_dir.path().join("gone.txt"), b"data").unwrap();
+        // A server instance before this one issued handles for the same files
+        let previous = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let old_handle = previous.lookup(&previous.root_handle(), "file.txt").unwrap();
+        let gone_handle = previous.lookup(&previous.root_handle(), "gone.txt").unwrap();
+        std::fs::remove_file(temp_dir.path().join("gone.txt")).unwrap();
+        drop(previous);
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+
+        // A fresh server: nothing mounted, no handle looked up yet
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        // Handles never issued, or issued by the previous instance (whether
+        // or not their file is still there), are stale
+        for handle in [vec![0; 32], vec![0xDE, 0xAD, 0xBE, 0xEF], old_handle, gone_handle] {
+            let mut getattr = Vec::new();
+            GETATTR3args {
+                object: fhandle3(handle.clone()),
+            }
+            .pack(&mut getattr)
+            .unwrap();
+            let mut call = nfs_call(3);
+            call.proc_ = 1;
+            let reply = dispatch(&call, &getattr, fs.as_ref(), &ctx).unwrap();
+            assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_STALE as u32).to_be_bytes());
+            assert_eq!(reply.len(), 28, "GETATTR resfail is empty");
+
+            // Same for any other procedure, with its resfail body
+            let mut read = Vec::new();
+            READ3args {
+                file: fhandle3(handle),
+                offset: 0,
+                count: 4,
+            }
+            .pack(&mut read)
+            .unwrap();
+            call.proc_ = 6;
+            let reply = dispatch(&call, &read, fs.as_ref(), &ctx).unwrap();
+            assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_STALE as u32).to_be_bytes());
+            assert_eq!(reply.len(), 32);
+        }
+    }
+
+    #[test]
+    fn test_guarded_setattr_retransmit_replayed() {
+        use crate::protocol::v3::nfs::{
+            fhandle3, nfstime3, sattr3, sattrguard3, set_atime, set_gid3, set_mode3,
+            set_mtime, set_size3, set_uid3, SETATTR3args,
+        };
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        std::fs::write(temp_dir.path().join("guarded.txt"), b"data").unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "guarded.txt").unwrap();
+        let ctime = fs.getattr(&file_handle).unwrap().ctime;
+
+        let args = SETATTR3args {
+            object: fhandle3(file_handle),
+            new_attributes: sattr3 {
+                mode: set_mode3::SET_MODE(0o600),
+                uid: set_uid3::default,
+                gid: set_gid3::default,
+                size: set_size3::default,
+                atime: set_atime::default,
+                mtime: set_mtime::default,
+            },
+            guard: sattrguard3::CHECK(nfstime3 {
+                seconds: ctime.seconds as u32,
+                nseconds: ctime.nseconds,
+            }),
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let mut call = nfs_call(3);
+        call.proc_ = 2;
+
+        let first = dispatch(&call, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&first[24..28], &[0u8; 4], "First attempt should succeed");
+
+        // The change may have moved ctime past the guard; a retransmit (same
+        // xid) still gets the original success
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        std::fs::set_permissions(
+            temp_dir.path().join("guarded.txt"),
+            std::os::unix::fs::PermissionsExt::from_mode(0o600),
+        )
+        .unwrap();
+        let retransmit = dispatch(&call, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(retransmit, first);
+
+        // A new call with the stale guard is evaluated and fails
+        call.xid += 1;
+        let fresh = dispatch(&call, &args_buf, fs.as_ref(), &ctx).unwrap();
+        // nfsstat3 = NFS3ERR_NOT_SYNC (10002)
+        assert_eq!(&fresh[24..28], &10002u32.to_be_bytes());
+    }
+
+    #[test]
+    fn test_read_only_export() {
+        use crate::config::ExportConfig;
+        use crate::protocol::v3::nfs::{
+            fhandle3, filename3, GETATTR3args, LOOKUP3args, READ3args,
+        };
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        std::fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let root = fs.root_handle();
+        let file_handle = fs.lookup(&root, "file.txt").unwrap();
+
+        let export = ExportConfig {
+            read_only: true,
+            ..ExportConfig::default()
+        };
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state).with_export(&export);
+        let call_proc = |procedure: u32, args: &[u8]| {
+            let mut call = nfs_call(3);
+            call.proc_ = procedure;
+            dispatch(&call, args, fs.as_ref(), &ctx).unwrap()
+        };
+
+        // GETATTR, LOOKUP and READ still work
+        let mut getattr = Vec::new();
+        GETATTR3args {
+            object: fhandle3(file_handle.clone()),
+        }
+        .pack(&mut getattr)
+        .unwrap();
+        let mut lookup = Vec::new();
+        LOOKUP3args {
+            what_dir: fhandle3(root),
+            name: filename3("file.txt".to_string()),
+        }
+        .pack(&mut lookup)
+        .unwrap();
+        let mut read = Vec::new();
+        READ3args {
+            file: fhandle3(file_handle),
+            offset: 0,
+            count: 4,
+        }
+        .pack(&mut read)
+        .unwrap();
+        for (procedure, args) in [(1, getattr), (3, lookup), (6, read)] {
+            let reply = call_proc(procedure, &args);
+            assert_eq!(&reply[24..28], &[0u8; 4], "procedure {} should succeed", procedure);
+        }
+
+        // Every modifying procedure fails before its arguments are decoded
+        for (procedure, reply_len) in [
+            (2, 36),
+            (7, 36),
+            (8, 36),
+            (9, 36),
+            (10, 36),
+            (11, 36),
+            (12, 36),
+            (13, 36),
+            (14, 44),
+            (15, 40),
+            (21, 36),
+        ] {
+            let reply = call_proc(procedure, &[]);
+            assert_eq!(
+                &reply[24..28],
+                &(nfsstat3::NFS3ERR_ROFS as u32).to_be_bytes(),
+                "procedure {} should fail with ROFS",
+                procedure
+            );
+            assert_eq!(reply.len(), reply_len);
+        }
+        assert_eq!(std::fs::read(temp_dir.path().join("file.txt")).unwrap(), b"data");
+    }
+
+    #[test]
+    fn test_client_not_allowed() {
+        use crate::config::ExportConfig;
+        use crate::protocol::v3::nfs::{fhandle3, GETATTR3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let mut getattr = Vec::new();
+        GETATTR3args {
+            object: fhandle3(fs.root_handle()),
+        }
+        .pack(&mut getattr)
+        .unwrap();
+
+        let export = ExportConfig {
+            clients: vec!["10.0.0.0/8".to_string()],
+            ..ExportConfig::default()
+        };
+        let state = NfsState::default();
+        let call_from = |client: &str, procedure: u32| {
+            let ctx = NfsContext::new(client.parse().unwrap(), &state).with_export(&export);
+            let mut call = nfs_call(3);
+            call.proc_ = procedure;
+            dispatch(&call, &getattr, fs.as_ref(), &ctx).unwrap()
+        };
+
+        let reply = call_from("10.1.2.3:700", 1);
+        assert_eq!(&reply[24..28], &[0u8; 4], "allowed client");
+
+        let reply = call_from("192.0.2.10:700", 1);
+        assert_eq!(
+            &reply[24..28],
+            &(nfsstat3::NFS3ERR_ACCES as u32).to_be_bytes()
+        );
+        assert_eq!(reply.len(), 28, "GETATTR resfail is empty");
+
+        // NULL still answers, so clients can tell the server is up
+        let reply = call_from("192.0.2.10:700", 0);
+        assert_eq!(&reply[20..24], &[0u8; 4]);
+    }
 }
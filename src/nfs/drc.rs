@@ -0,0 +1,318 @@
+// Duplicate Request Cache
+//
+// Remembers the replies to recent non-idempotent calls, keyed by
+// (client address, xid, program, procedure, checksum of the arguments), so a
+// retransmitted call is answered with the original reply instead of being
+// executed a second time. A retransmit that arrives while the original is
+// still running is dropped; the client retries and gets the cached reply.
+
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap};
+use std::hash::Hasher;
+use std::net::SocketAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use bytes::BytesMut;
+
+/// Identity of an RPC call for duplicate detection
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct DrcKey {
+    pub client: SocketAddr,
+    pub xid: u32,
+    pub program: u32,
+    pub procedure: u32,
+    /// Checksum of the procedure arguments, so a client reusing an xid for
+    /// a different call is not answered with another call's reply
+    pub checksum: u64,
+}
+
+impl DrcKey {
+    pub fn new(client: SocketAddr, xid: u32, program: u32, procedure: u32, args: &[u8]) -> Self {
+        let mut hasher = DefaultHasher::new();
+        hasher.write(args);
+        Self {
+            client,
+            xid,
+            program,
+            procedure,
+            checksum: hasher.finish(),
+        }
+    }
+}
+
+/// What to do with a call, according to the cache
+#[derive(Debug, PartialEq)]
+pub enum DrcLookup {
+    /// First time seen: execute it, then `insert` (or `abandon`) the reply
+    New,
+    /// The original is still executing: drop the retransmit
+    InProgress,
+    /// Already answered: send this reply again
+    Replay(BytesMut),
+}
+
+/// Error for a retransmit dropped because its original is still executing
+#[derive(Debug, thiserror::Error)]
+#[error("retransmitted call is still in progress")]
+pub struct InProgress;
+
+/// Bounded LRU cache of serialized replies
+///
+/// The least recently used entry is evicted first; entries older than the
+/// TTL are never replayed, since a client retransmits within seconds.
+pub struct DuplicateRequestCache {
+    capacity: usize,
+    ttl: Duration,
+    inner: Mutex<DrcInner>,
+}
+
+struct DrcEntry {
+    /// None while the call is executing
+    reply: Option<BytesMut>,
+    inserted: Instant,
+    /// Position in `DrcInner::lru`
+    stamp: u64,
+}
+
+#[derive(Default)]
+struct DrcInner {
+    replies: HashMap<DrcKey, DrcEntry>,
+    /// Keys by last use, least recent first
+    lru: BTreeMap<u64, DrcKey>,
+    next_stamp: u64,
+}
+
+impl DrcInner {
+    /// Mark an entry as most recently used
+    fn touch(&mut self, key: &DrcKey) {
+        let stamp = self.next_stamp;
+        self.next_stamp += 1;
+        if let Some(entry) = self.replies.get_mut(key) {
+            self.lru.remove(&entry.stamp);
+            entry.stamp = stamp;
+            self.lru.insert(stamp, *key);
+        }
+    }
+
+    fn remove(&mut self, key: &DrcKey) {
+        if let Some(entry) = self.replies.remove(key) {
+            self.lru.remove(&entry.stamp);
+        }
+    }
+}
+
+impl DuplicateRequestCache {
+    /// Cache up to `capacity` replies (0 disables the cache), each for `ttl`
+    pub fn new(capacity: usize, ttl: Duration) -> Self {
+        Self {
+            capacity,
+            ttl,
+            inner: Mutex::new(DrcInner::default()),
+        }
+    }
+
+    /// Look up a call before executing it
+    ///
+    /// A call not seen before is recorded as in progress, so retransmits
+    /// arriving before its reply is inserted are not executed again.
+    pub fn begin(&self, key: &DrcKey) -> DrcLookup {
+        if self.capacity == 0 {
+            return DrcLookup::New;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        if let Some(entry) = inner.replies.get(key) {
+            // Expired entries (including calls whose handler never finished)
+            // are treated as new
+            if entry.inserted.elapsed() <= self.ttl {
+                let lookup = match &entry.reply {
+                    Some(reply) => DrcLookup::Replay(reply.clone()),
+                    None => DrcLookup::InProgress,
+                };
+                inner.touch(key);
+                return lookup;
+            }
+        }
+        self.store(&mut inner, *key, None);
+        DrcLookup::New
+    }
+
+    /// Forget a call that failed without a reply, so a retransmit runs again
+    pub fn abandon(&self, key: &DrcKey) {
+        self.inner.lock().unwrap().remove(key);
+    }
+
+    /// Forget every reply, so none sent under a previous configuration is
+    /// replayed after a reload
+    pub fn clear(&self) {
+        let mut inner = self.inner.lock().unwrap();
+        inner.replies.clear();
+        inner.lru.clear();
+    }
+
+    /// Remember the reply sent for a call
+    pub fn insert(&self, key: DrcKey, reply: &BytesMut) {
+        if self.capacity == 0 {
+            return;
+        }
+
+        let mut inner = self.inner.lock().unwrap();
+        self.store(&mut inner, key, Some(reply.clone()));
+    }
+
+    fn store(&self, inner: &mut DrcInner, key: DrcKey, reply: Option<BytesMut>) {
+        inner.remove(&key);
+        let stamp = inner.next_stamp;
+        inner.next_stamp += 1;
+        inner.replies.insert(
+            key,
+            DrcEntry {
+                reply,
+                inserted: Instant::now(),
+                stamp,
+            },
+        );
+        inner.lru.insert(stamp, key);
+
+        while inner.replies.len() > self.capacity {
+            let Some((_, lru_key)) = inner.lru.pop_first() else {
+                break;
+            };
+            inner.replies.remove(&lru_key);
+        }
+    }
+
+    /// Whether a call has an entry (in progress or answered)
+    #[cfg(test)]
+    fn contains(&self, key: &DrcKey) -> bool {
+        self.inner.lock().unwrap().replies.contains_key(key)
+    }
+
+    /// Number of cached replies
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.inner.lock().unwrap().replies.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(xid: u32) -> DrcKey {
+        DrcKey::new("127.0.0.1:700".parse().unwrap(), xid, 100003, 2, b"args")
+    }
+
+    /// Cached reply for a call, if any, leaving no entry behind on a miss
+    fn lookup(cache: &DuplicateRequestCache, key: &DrcKey) -> Option<BytesMut> {
+        if !cache.contains(key) {
+            return None;
+        }
+        match cache.begin(key) {
+            DrcLookup::Replay(reply) => Some(reply),
+            _ => None,
+        }
+    }
+
+    const TTL: Duration = Duration::from_secs(60);
+
+    #[test]
+    fn test_replay_same_call_only() {
+        let cache = DuplicateRequestCache::new(8, TTL);
+        cache.insert(key(1), &BytesMut::from(&b"reply-1"[..]));
+
+        assert_eq!(lookup(&cache, &key(1)).unwrap(), &b"reply-1"[..]);
+        assert!(lookup(&cache, &key(2)).is_none());
+
+        // Same xid from another client, or for another procedure, is a new call
+        let other_client = DrcKey {
+            client: "127.0.0.2:700".parse().unwrap(),
+            ..key(1)
+        };
+        assert!(lookup(&cache, &other_client).is_none());
+        let other_proc = DrcKey {
+            procedure: 7,
+            ..key(1)
+        };
+        assert!(lookup(&cache, &other_proc).is_none());
+
+        // Same xid with different arguments is a new call too
+        let other_args = DrcKey::new(key(1).client, 1, 100003, 2, b"other args");
+        assert!(lookup(&cache, &other_args).is_none());
+    }
+
+    #[test]
+    fn test_retransmit_while_in_progress() {
+        let cache = DuplicateRequestCache::new(8, TTL);
+        assert_eq!(cache.begin(&key(1)), DrcLookup::New);
+        assert_eq!(cache.begin(&key(1)), DrcLookup::InProgress);
+
+        cache.insert(key(1), &BytesMut::from(&b"reply-1"[..]));
+        assert_eq!(
+            cache.begin(&key(1)),
+            DrcLookup::Replay(BytesMut::from(&b"reply-1"[..]))
+        );
+
+        // A call that failed without a reply runs again
+        assert_eq!(cache.begin(&key(2)), DrcLookup::New);
+        cache.abandon(&key(2));
+        assert_eq!(cache.begin(&key(2)), DrcLookup::New);
+    }
+
+    #[test]
+    fn test_oldest_evicted() {
+        let cache = DuplicateRequestCache::new(2, TTL);
+        for xid in 1..=3 {
+            cache.insert(key(xid), &BytesMut::from(&b"reply"[..]));
+        }
+
+        assert_eq!(cache.len(), 2);
+        assert!(lookup(&cache, &key(1)).is_none());
+        assert!(lookup(&cache, &key(2)).is_some());
+        assert!(lookup(&cache, &key(3)).is_some());
+    }
+
+    #[test]
+    fn test_least_recently_used_evicted() {
+        let cache = DuplicateRequestCache::new(2, TTL);
+        cache.insert(key(1), &BytesMut::from(&b"reply-1"[..]));
+        cache.insert(key(2), &BytesMut::from(&b"reply-2"[..]));
+
+        // Replaying 1 makes 2 the least recently used
+        assert!(lookup(&cache, &key(1)).is_some());
+        cache.insert(key(3), &BytesMut::from(&b"reply-3"[..]));
+
+        assert_eq!(cache.len(), 2);
+        assert!(lookup(&cache, &key(1)).is_some());
+        assert!(lookup(&cache, &key(2)).is_none());
+        assert!(lookup(&cache, &key(3)).is_some());
+    }
+
+    #[test]
+    fn test_entries_expire() {
+        let cache = DuplicateRequestCache::new(8, Duration::from_millis(20));
+        cache.insert(key(1), &BytesMut::from(&b"reply"[..]));
+        assert!(lookup(&cache, &key(1)).is_some());
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert_eq!(cache.begin(&key(1)), DrcLookup::New);
+        assert_eq!(cache.len(), 1);
+    }
+
+    #[test]
+    fn test_clear() {
+        let cache = DuplicateRequestCache::new(4, TTL);
+        cache.insert(key(1), &BytesMut::from(&b"reply"[..]));
+        cache.clear();
+        assert_eq!(cache.len(), 0);
+        assert_eq!(cache.begin(&key(1)), DrcLookup::New);
+    }
+
+    #[test]
+    fn test_zero_capacity_disables_cache() {
+        let cache = DuplicateRequestCache::new(0, TTL);
+        cache.insert(key(1), &BytesMut::from(&b"reply"[..]));
+        assert!(lookup(&cache, &key(1)).is_none());
+    }
+}
@@ -6,7 +6,8 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::debug;
 
-use crate::fsal::Filesystem;
+use crate::fsal::{Capabilities, Filesystem};
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
@@ -24,6 +25,7 @@ const FSF3_CANSETTIME: u32 = 0x0010; // Server can set time on server
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized FSINFO3args (fsroot handle)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (advertised transfer sizes)
 ///
 /// # Returns
 /// Serialized RPC reply message with filesystem information
@@ -31,6 +33,7 @@ pub fn handle_fsinfo(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS FSINFO called (xid={})", xid);
 
@@ -48,36 +51,51 @@ pub fn handle_fsinfo(
         Ok(attrs) => attrs,
         Err(e) => {
             debug!("FSINFO failed: {}", e);
-            let error_status = if e.to_string().contains("not found")
-                || e.to_string().contains("Invalid handle")
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
             {
                 nfsstat3::NFS3ERR_STALE
             } else {
                 nfsstat3::NFS3ERR_IO
             };
 
-            let res_data = NfsMessage::create_fsinfo_error_response(error_status)?;
+            let res_data = NfsMessage::create_fsinfo_error_response(error_status, None)?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
 
-    // Define filesystem capabilities and limits
-    // These values are based on RFC 1813 recommendations
-    let rtmax = 1024 * 1024; // 1 MB - max read request
-    let rtpref = 64 * 1024; // 64 KB - preferred read size
+    // Transfer sizes come from the configuration, lowered over UDP so
+    // replies fit a datagram; multiples match the page size
+    let config = &ctx.state.config;
+    let rtmax = ctx.transfer_limit(config.rtmax); // max read request
+    let rtpref = config.rtpref.min(rtmax); // preferred read size
     let rtmult = 4096; // 4 KB - suggested read multiple
-    let wtmax = 1024 * 1024; // 1 MB - max write request
-    let wtpref = 64 * 1024; // 64 KB - preferred write size
+    let wtmax = ctx.transfer_limit(config.wtmax); // max write request (enforced by WRITE)
+    let wtpref = config.wtpref.min(wtmax); // preferred write size
     let wtmult = 4096; // 4 KB - suggested write multiple
-    let dtpref = 8192; // 8 KB - preferred READDIR size
-    let maxfilesize = 0xFFFFFFFFFFFFFFFFu64; // Maximum file size (unlimited)
+    let dtpref = config.dtpref; // preferred READDIR size
+    let maxfilesize = i64::MAX as u64; // largest offset a backend (off_t) can hold
 
     // Time precision - 1 nanosecond
     let time_delta_seconds = 0u32;
     let time_delta_nseconds = 1u32;
 
-    // Filesystem properties
-    let properties = FSF3_LINK | FSF3_SYMLINK | FSF3_HOMOGENEOUS | FSF3_CANSETTIME;
+    // Filesystem properties follow the backend capabilities; LINK/SYMLINK
+    // match what the dispatcher rejects with NFS3ERR_NOTSUPP
+    let capabilities = filesystem.capabilities();
+    let mut properties = 0;
+    for (capability, flag) in [
+        (Capabilities::HARD_LINK, FSF3_LINK),
+        (Capabilities::SYMLINK, FSF3_SYMLINK),
+        (Capabilities::HOMOGENEOUS, FSF3_HOMOGENEOUS),
+        (Capabilities::SET_TIME, FSF3_CANSETTIME),
+    ] {
+        if capabilities.contains(capability) {
+            properties |= flag;
+        }
+    }
 
     debug!(
         "FSINFO success: rtmax={}, wtmax={}, dtpref={}",
@@ -85,7 +103,7 @@ pub fn handle_fsinfo(
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_attrs = NfsMessage::fsal_to_fattr3(&obj_attrs);
+    let nfs_attrs = ctx.fattr3(&obj_attrs);
 
     // Create successful response (manually serialized with proper post_op_attr)
     let res_data = NfsMessage::create_fsinfo_ok(
@@ -111,6 +129,7 @@ pub fn handle_fsinfo(
 mod tests {
     use super::*;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::NfsState;
     use tempfile::TempDir;
 
     #[test]
@@ -135,7 +154,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call FSINFO
-        let result = handle_fsinfo(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_fsinfo(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "FSINFO should succeed");
 
@@ -162,8 +183,101 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call FSINFO
-        let result = handle_fsinfo(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_fsinfo(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "FSINFO should return error response (not panic)");
     }
+
+    #[test]
+    fn test_fsinfo_properties_follow_capabilities() {
+        use crate::fsal::LocalFilesystem;
+        use crate::protocol::v3::nfs::{fhandle3, FSINFO3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        let all = FSF3_LINK | FSF3_SYMLINK | FSF3_HOMOGENEOUS | FSF3_CANSETTIME;
+        let cases = [
+            (Capabilities::all(), all),
+            (Capabilities::all().without(Capabilities::SYMLINK), all & !FSF3_SYMLINK),
+            (Capabilities::all().without(Capabilities::HARD_LINK), all & !FSF3_LINK),
+            (
+                Capabilities::all()
+                    .without(Capabilities::SET_TIME)
+                    .without(Capabilities::HOMOGENEOUS),
+                FSF3_LINK | FSF3_SYMLINK,
+            ),
+        ];
+        for (capabilities, expected_links) in cases {
+            let fs = LocalFilesystem::new(temp_dir.path())
+                .unwrap()
+                .with_capabilities(capabilities);
+
+            let args = FSINFO3args {
+                fsroot: fhandle3(fs.root_handle()),
+            };
+            let mut args_buf = Vec::new();
+            args.pack(&mut args_buf).unwrap();
+
+            let reply = handle_fsinfo(1, &args_buf, &fs, &ctx).unwrap();
+
+            // properties is the last field of FSINFO3resok
+            let tail = &reply[reply.len() - 4..];
+            let properties = u32::from_be_bytes([tail[0], tail[1], tail[2], tail[3]]);
+            assert_eq!(properties, expected_links);
+        }
+    }
+
+    #[test]
+    fn test_fsinfo_transfer_sizes_from_config() {
+        use crate::config::NfsConfig;
+        use crate::protocol::v3::nfs::{fhandle3, FSINFO3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path()).create_filesystem().unwrap();
+        let state = NfsState::new(NfsConfig {
+            rtmax: 256 * 1024,
+            rtpref: 128 * 1024,
+            wtmax: 512 * 1024,
+            wtpref: 1024 * 1024,
+            dtpref: 16 * 1024,
+            ..NfsConfig::default()
+        });
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        let mut args_buf = Vec::new();
+        FSINFO3args {
+            fsroot: fhandle3(fs.root_handle()),
+        }
+        .pack(&mut args_buf)
+        .unwrap();
+        let reply = handle_fsinfo(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+
+        // status, attributes_follow, fattr3 (84 bytes), then the sizes
+        let word = |i: usize| {
+            let at = 32 + 84 + 4 * i;
+            u32::from_be_bytes(reply[at..at + 4].try_into().unwrap())
+        };
+        assert_eq!(word(0), 256 * 1024); // rtmax
+        assert_eq!(word(1), 128 * 1024); // rtpref
+        assert_eq!(word(3), 512 * 1024); // wtmax
+        assert_eq!(word(4), 512 * 1024); // wtpref, capped at wtmax
+        assert_eq!(word(6), 16 * 1024); // dtpref
+
+        // Over UDP every transfer fits a datagram
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state).with_udp(true);
+        let reply = handle_fsinfo(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        let word = |i: usize| {
+            let at = 32 + 84 + 4 * i;
+            u32::from_be_bytes(reply[at..at + 4].try_into().unwrap())
+        };
+        for i in [0, 1, 3, 4] {
+            assert_eq!(word(i), crate::nfs::MAX_UDP_TRANSFER);
+        }
+    }
 }
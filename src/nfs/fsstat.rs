@@ -7,6 +7,7 @@ use bytes::BytesMut;
 use tracing::debug;
 
 use crate::fsal::Filesystem;
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
@@ -18,6 +19,7 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized FSSTAT3args (fsroot handle)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (owner/group override)
 ///
 /// # Returns
 /// Serialized RPC reply message with filesystem statistics
@@ -25,6 +27,7 @@ pub fn handle_fsstat(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS FSSTAT called (xid={})", xid);
 
@@ -38,36 +41,41 @@ pub fn handle_fsstat(
         Ok(attrs) => attrs,
         Err(e) => {
             debug!("FSSTAT failed: {}", e);
-            let error_status = if e.to_string().contains("not found")
-                || e.to_string().contains("Invalid handle")
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
             {
                 nfsstat3::NFS3ERR_STALE
             } else {
                 nfsstat3::NFS3ERR_IO
             };
 
-            let res_data = NfsMessage::create_fsstat_error_response(error_status)?;
+            let res_data = NfsMessage::create_fsstat_error_response(error_status, None)?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
 
-    // Get filesystem statistics
-    // For now, use hardcoded values - in production this would query the actual filesystem
-    let tbytes = 1024 * 1024 * 1024 * 100u64; // 100 GB total
-    let fbytes = 1024 * 1024 * 1024 * 50u64; // 50 GB free
-    let abytes = 1024 * 1024 * 1024 * 50u64; // 50 GB available to non-root
-    let tfiles = 1000000u64; // 1M total inodes
-    let ffiles = 500000u64; // 500k free inodes
-    let afiles = 500000u64; // 500k available inodes to non-root
-    let invarsec = 0u32; // filesystem not expected to change without client intervention
+    // Get filesystem statistics from the backing storage
+    let stat = match filesystem.statfs(&args.fsroot.0) {
+        Ok(stat) => stat,
+        Err(e) => {
+            debug!("FSSTAT statfs failed: {}", e);
+            let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+            let res_data =
+                NfsMessage::create_fsstat_error_response(status, Some(&ctx.fattr3(&obj_attrs)))?;
+            return RpcMessage::create_success_reply_with_data(xid, res_data);
+        }
+    };
+    let invarsec = 0u32; // usage may change at any time
 
     debug!(
         "FSSTAT success: tbytes={}, fbytes={}, tfiles={}",
-        tbytes, fbytes, tfiles
+        stat.tbytes, stat.fbytes, stat.tfiles
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_attrs = NfsMessage::fsal_to_fattr3(&obj_attrs);
+    let nfs_attrs = ctx.fattr3(&obj_attrs);
 
     // Create FSSTAT response manually with post_op_attr format
     use xdr_codec::Pack;
@@ -81,12 +89,12 @@ pub fn handle_fsstat(
     nfs_attrs.pack(&mut buf)?;
 
     // 3. FSSTAT fields
-    tbytes.pack(&mut buf)?;
-    fbytes.pack(&mut buf)?;
-    abytes.pack(&mut buf)?;
-    tfiles.pack(&mut buf)?;
-    ffiles.pack(&mut buf)?;
-    afiles.pack(&mut buf)?;
+    stat.tbytes.pack(&mut buf)?;
+    stat.fbytes.pack(&mut buf)?;
+    stat.abytes.pack(&mut buf)?;
+    stat.tfiles.pack(&mut buf)?;
+    stat.ffiles.pack(&mut buf)?;
+    stat.afiles.pack(&mut buf)?;
     invarsec.pack(&mut buf)?;
 
     let res_data = BytesMut::from(&buf[..]);
@@ -99,6 +107,7 @@ pub fn handle_fsstat(
 mod tests {
     use super::*;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::NfsState;
     use tempfile::TempDir;
 
     #[test]
@@ -123,12 +132,19 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call FSSTAT
-        let result = handle_fsstat(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_fsstat(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "FSSTAT should succeed");
 
         let reply = result.unwrap();
         assert!(!reply.is_empty(), "Reply should contain data");
+
+        // status, attributes_follow, fattr3 (84 bytes), then the totals
+        let totals = &reply[32 + 84..];
+        let tbytes = u64::from_be_bytes(totals[0..8].try_into().unwrap());
+        assert_eq!(tbytes, fs.statfs(&fs.root_handle()).unwrap().tbytes);
     }
 
     #[test]
@@ -150,8 +166,41 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call FSSTAT
-        let result = handle_fsstat(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_fsstat(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "FSSTAT should return error response (not panic)");
     }
+
+    #[test]
+    fn test_fsstat_owner_override() {
+        use crate::config::NfsConfig;
+        use crate::protocol::v3::nfs::{fhandle3, FSSTAT3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let mut args_buf = Vec::new();
+        FSSTAT3args {
+            fsroot: fhandle3(fs.root_handle()),
+        }
+        .pack(&mut args_buf)
+        .unwrap();
+
+        // Every reply carrying attributes shows the override, not just GETATTR
+        let state = NfsState::new(NfsConfig {
+            override_uid: Some(4242),
+            override_gid: Some(4343),
+            ..NfsConfig::default()
+        });
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let reply = handle_fsstat(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+
+        // RPC header (24) + status + attributes_follow + type/mode/nlink -> uid, gid
+        assert_eq!(&reply[44..48], &4242u32.to_be_bytes());
+        assert_eq!(&reply[48..52], &4343u32.to_be_bytes());
+    }
 }
@@ -7,6 +7,7 @@ use bytes::BytesMut;
 use tracing::debug;
 
 use crate::fsal::Filesystem;
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{NfsMessage, nfsstat3};
 use crate::protocol::v3::rpc::RpcMessage;
 
@@ -18,6 +19,7 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized GETATTR3args (contains file handle)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (owner/group override)
 ///
 /// # Returns
 /// Serialized RPC reply message with file attributes
@@ -25,6 +27,7 @@ pub fn handle_getattr(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS GETATTR called (xid={})", xid);
 
@@ -40,7 +43,7 @@ pub fn handle_getattr(
             debug!("GETATTR failed: {}", e);
             // Return NFS error - use STALE for invalid handle, IO for other errors
             use crate::protocol::v3::nfs::nfsstat3;
-            let error_status = nfsstat3::NFS3ERR_STALE; // File handle error
+            let error_status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_STALE);
             let res_data = NfsMessage::create_getattr_error_response(error_status)?;
 
             return RpcMessage::create_success_reply_with_data(xid, res_data);
@@ -53,7 +56,7 @@ pub fn handle_getattr(
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_attrs = NfsMessage::fsal_to_fattr3(&fsal_attrs);
+    let nfs_attrs = ctx.fattr3(&fsal_attrs);
 
     // Create successful response
     let response = NfsMessage::create_getattr_ok(nfs_attrs);
@@ -70,7 +73,9 @@ mod tests {
     use super::*;
     use std::path::PathBuf;
     use tempfile::TempDir;
+    use crate::config::NfsConfig;
     use crate::fsal::{BackendConfig, LocalFilesystem};
+    use crate::nfs::NfsState;
 
     #[test]
     fn test_getattr_root() {
@@ -94,11 +99,43 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call GETATTR
-        let result = handle_getattr(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_getattr(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "GETATTR should succeed for root");
 
         let reply = result.unwrap();
         assert!(!reply.is_empty(), "Reply should contain data");
     }
+
+    #[test]
+    fn test_getattr_owner_override() {
+        let temp_dir = TempDir::new().unwrap();
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        use crate::protocol::v3::nfs::{GETATTR3args, fhandle3};
+        use xdr_codec::Pack;
+
+        let args = GETATTR3args {
+            object: fhandle3(fs.root_handle()),
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+
+        let state = NfsState::new(NfsConfig {
+            override_uid: Some(4242),
+            override_gid: Some(4343),
+            ..NfsConfig::default()
+        });
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let reply = handle_getattr(12345, &args_buf, fs.as_ref(), &ctx).unwrap();
+
+        // RPC header (24) + status (4) + type/mode/nlink (12) -> uid, gid
+        let uid = u32::from_be_bytes([reply[40], reply[41], reply[42], reply[43]]);
+        let gid = u32::from_be_bytes([reply[44], reply[45], reply[46], reply[47]]);
+        assert_eq!(uid, 4242);
+        assert_eq!(gid, 4343);
+    }
 }
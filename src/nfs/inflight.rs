@@ -0,0 +1,321 @@
+// In-Flight Payload Budget
+//
+// Bounds the total RPC message bytes being processed at once. Many
+// concurrent large transfers would otherwise buffer an unbounded amount of
+// data. A call's bytes are reserved before its record is read off the
+// connection; handlers grow the reservation for the payload their reply will
+// carry, and the transport resizes it to the reply before sending it. Once
+// the budget is used up, connections wait before reading their next call,
+// and READs are refused with NFS3ERR_JUKEBOX so clients back off and retry
+// after the backlog drains.
+
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use tokio::sync::Notify;
+
+/// Global budget for payload bytes in flight
+pub struct InflightBudget {
+    /// Maximum bytes in flight (None = unlimited)
+    limit: Option<u64>,
+    current: AtomicU64,
+    /// Woken whenever bytes are released
+    released: Notify,
+}
+
+impl InflightBudget {
+    pub fn new(limit: Option<u64>) -> Self {
+        Self {
+            limit,
+            current: AtomicU64::new(0),
+            released: Notify::new(),
+        }
+    }
+
+    /// Reserve `bytes` of payload for the duration of a transfer
+    ///
+    /// Returns None when the budget is exhausted. A transfer is always admitted
+    /// when nothing else is in flight, so a single request larger than the
+    /// limit cannot be starved forever.
+    pub fn try_acquire(self: &Arc<Self>, bytes: u64) -> Option<InflightGuard> {
+        let Some(limit) = self.limit else {
+            return Some(self.reserve(0));
+        };
+
+        self.current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current == 0 || current + bytes <= limit).then_some(current + bytes)
+            })
+            .ok()?;
+
+        Some(InflightGuard {
+            budget: self.clone(),
+            bytes: AtomicU64::new(bytes),
+        })
+    }
+
+    /// Reserve `bytes`, waiting until the budget has room for them
+    pub async fn acquire(self: &Arc<Self>, bytes: u64) -> InflightGuard {
+        loop {
+            // Registered before checking, so a release in between still wakes us
+            let released = self.released.notified();
+            if let Some(guard) = self.try_acquire(bytes) {
+                return guard;
+            }
+            released.await;
+        }
+    }
+
+    /// Reserve `bytes` already held in memory, regardless of the limit
+    pub fn reserve(self: &Arc<Self>, bytes: u64) -> InflightGuard {
+        let guard = InflightGuard {
+            budget: self.clone(),
+            bytes: AtomicU64::new(0),
+        };
+        if self.limit.is_some() {
+            self.current.fetch_add(bytes, Ordering::AcqRel);
+            guard.bytes.store(bytes, Ordering::Release);
+        }
+        guard
+    }
+
+    /// Payload bytes currently in flight
+    #[cfg(test)]
+    pub(crate) fn in_flight(&self) -> u64 {
+        self.current.load(Ordering::Acquire)
+    }
+}
+
+/// Reservation of in-flight payload bytes, released on drop
+///
+/// One per call, shared with its handler, which is why growing it only
+/// needs `&self`.
+pub struct InflightGuard {
+    budget: Arc<InflightBudget>,
+    bytes: AtomicU64,
+}
+
+impl InflightGuard {
+    /// Grow the reservation by `bytes` if the budget has room for them
+    ///
+    /// Only what others hold counts against the room, so a call alone in
+    /// flight may always grow: its own bytes can't lock it out.
+    pub fn try_grow(&self, bytes: u64) -> bool {
+        let Some(limit) = self.budget.limit else {
+            return true;
+        };
+
+        let own = self.bytes.load(Ordering::Acquire);
+        let grown = self
+            .budget
+            .current
+            .fetch_update(Ordering::AcqRel, Ordering::Acquire, |current| {
+                (current == own || current + bytes <= limit).then_some(current + bytes)
+            })
+            .is_ok();
+        if grown {
+            self.bytes.fetch_add(bytes, Ordering::AcqRel);
+        }
+        grown
+    }
+
+    /// Grow the reservation by `bytes`, waiting until the budget has room
+    pub async fn grow(&self, bytes: u64) {
+        loop {
+            let released = self.budget.released.notified();
+            if self.try_grow(bytes) {
+                return;
+            }
+            released.await;
+        }
+    }
+
+    /// Resize the reservation to the `bytes` of the reply about to be sent,
+    /// in place of the call it answers
+    ///
+    /// A reply within what the call and its handler reserved gives the rest
+    /// back. A larger one lets go of everything before waiting for room, so
+    /// a reply never holds bytes while it waits for others to finish.
+    pub async fn settle(&self, bytes: u64) {
+        if self.budget.limit.is_none() {
+            return;
+        }
+        let own = self.bytes.load(Ordering::Acquire);
+        if bytes <= own {
+            self.release(own - bytes);
+        } else {
+            self.release(own);
+            self.grow(bytes).await;
+        }
+    }
+
+    /// Bytes this reservation holds
+    #[cfg(test)]
+    pub(crate) fn bytes(&self) -> u64 {
+        self.bytes.load(Ordering::Acquire)
+    }
+
+    fn release(&self, bytes: u64) {
+        if bytes > 0 {
+            self.bytes.fetch_sub(bytes, Ordering::AcqRel);
+            self.budget.current.fetch_sub(bytes, Ordering::AcqRel);
+            self.budget.released.notify_waiters();
+        }
+    }
+}
+
+impl Drop for InflightGuard {
+    fn drop(&mut self) {
+        let bytes = *self.bytes.get_mut();
+        self.release(bytes);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread;
+    use std::time::Duration;
+
+    #[test]
+    fn test_unlimited() {
+        let budget = Arc::new(InflightBudget::new(None));
+        let _a = budget.try_acquire(u64::MAX / 2).unwrap();
+        let _b = budget.try_acquire(u64::MAX / 2).unwrap();
+    }
+
+    #[test]
+    fn test_budget_refuses_and_releases() {
+        let budget = Arc::new(InflightBudget::new(Some(100)));
+
+        let a = budget.try_acquire(60).unwrap();
+        assert!(budget.try_acquire(60).is_none(), "Over budget should be refused");
+        let _b = budget.try_acquire(40).unwrap();
+        assert_eq!(budget.in_flight(), 100);
+
+        drop(a);
+        assert_eq!(budget.in_flight(), 40);
+        assert!(budget.try_acquire(60).is_some());
+    }
+
+    #[test]
+    fn test_oversized_admitted_when_idle() {
+        let budget = Arc::new(InflightBudget::new(Some(100)));
+        let big = budget.try_acquire(1000).expect("Idle server should admit any size");
+        assert!(budget.try_acquire(1).is_none());
+        drop(big);
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_stress_peak_stays_under_cap() {
+        const LIMIT: u64 = 1024 * 1024;
+        const CHUNK: u64 = 256 * 1024;
+
+        let budget = Arc::new(InflightBudget::new(Some(LIMIT)));
+        let peak = Arc::new(AtomicU64::new(0));
+
+        let threads: Vec<_> = (0..16)
+            .map(|_| {
+                let budget = budget.clone();
+                let peak = peak.clone();
+                thread::spawn(move || {
+                    for _ in 0..200 {
+                        // Refused transfers would get JUKEBOX and retry later
+                        if let Some(_guard) = budget.try_acquire(CHUNK) {
+                            // Simulate buffering the payload
+                            peak.fetch_max(budget.in_flight(), Ordering::SeqCst);
+                            let buffer = vec![0u8; CHUNK as usize];
+                            thread::yield_now();
+                            drop(buffer);
+                        }
+                    }
+                })
+            })
+            .collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        assert!(peak.load(Ordering::SeqCst) <= LIMIT, "Peak buffered bytes over the cap");
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_acquire_waits_for_release() {
+        let budget = Arc::new(InflightBudget::new(Some(100)));
+        let held = budget.try_acquire(80).unwrap();
+
+        let waiter = tokio::spawn({
+            let budget = budget.clone();
+            async move { budget.acquire(40).await }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!waiter.is_finished(), "Should wait while over budget");
+
+        drop(held);
+        let guard = waiter.await.unwrap();
+        assert_eq!(budget.in_flight(), 40);
+        drop(guard);
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_reserve_ignores_limit() {
+        let budget = Arc::new(InflightBudget::new(Some(100)));
+        let guard = budget.try_acquire(60).unwrap();
+        let _reply = budget.reserve(50);
+        assert_eq!(budget.in_flight(), 110);
+
+        drop(guard);
+        assert_eq!(budget.in_flight(), 50);
+    }
+
+    #[test]
+    fn test_grow_excludes_own_reservation() {
+        let budget = Arc::new(InflightBudget::new(Some(100)));
+
+        // Alone in flight, a call may grow past the limit
+        let call = budget.try_acquire(60).unwrap();
+        assert!(call.try_grow(80));
+        assert_eq!(call.bytes(), 140);
+
+        // But not while another holds part of the budget
+        let other = budget.reserve(10);
+        assert!(!call.try_grow(1));
+        drop(other);
+        assert!(call.try_grow(1));
+
+        drop(call);
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_settle_resizes_to_reply() {
+        let budget = Arc::new(InflightBudget::new(Some(100)));
+
+        // A reply smaller than the reservation gives the rest back
+        let call = budget.try_acquire(60).unwrap();
+        assert!(call.try_grow(30));
+        call.settle(40).await;
+        assert_eq!(budget.in_flight(), 40);
+        drop(call);
+
+        // A larger one waits for room, holding nothing meanwhile
+        let call = budget.try_acquire(20).unwrap();
+        let other = budget.try_acquire(60).unwrap();
+        let settling = tokio::spawn(async move {
+            call.settle(70).await;
+            call
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!settling.is_finished(), "Reply admitted over the cap");
+        assert_eq!(budget.in_flight(), 60);
+
+        drop(other);
+        let call = settling.await.unwrap();
+        assert_eq!(budget.in_flight(), 70);
+        drop(call);
+        assert_eq!(budget.in_flight(), 0);
+    }
+}
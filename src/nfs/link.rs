@@ -6,13 +6,16 @@
 // - Creates a new directory entry (link) pointing to the same inode
 // - Returns updated file attributes (link count increases)
 // - Returns wcc_data for the target directory
+// - Fails with NFS3ERR_XDEV when the file and directory are on different
+//   filesystems (different exports, or different devices within one)
 
 use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
 use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{nfsstat3, wcc_attr, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS LINK procedure (15)
@@ -23,10 +26,16 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - RPC transaction ID
 /// * `args_data` - Serialized LINK3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context with the caller's credentials
 ///
 /// # Returns
 /// Serialized LINK3res wrapped in RPC reply
-pub fn handle_link(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_link(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS LINK: xid={}", xid);
 
     // Parse arguments
@@ -43,7 +52,26 @@ pub fn handle_link(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> R
     let file_before = filesystem.getattr(&args.file.0).ok();
 
     // Get target directory attributes before operation (for wcc_data)
-    let dir_before = filesystem.getattr(&args.link_dir.0).ok();
+    let link_dir_before = filesystem.getattr(&args.link_dir.0).ok();
+    let dir_before = link_dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+
+    // The call was routed to the export that issued the file handle; a
+    // directory handle it did not issue lives in another export
+    if filesystem.owns_handle(&args.file.0) && !filesystem.owns_handle(&args.link_dir.0) {
+        debug!("LINK across exports refused");
+        let file_attr = file_before.map(|attr| ctx.fattr3(&attr));
+        return create_link_response(xid, nfsstat3::NFS3ERR_XDEV, file_attr, None, None);
+    }
+
+    let credentials = &ctx.credentials;
+    if link_dir_before
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("LINK denied for uid {}", credentials.uid);
+        let file_attr = file_before.map(|attr| ctx.fattr3(&attr));
+        return create_link_response(xid, nfsstat3::NFS3ERR_ACCES, file_attr, dir_before, dir_before);
+    }
 
     // Perform link operation
     match filesystem.link(&args.file.0, &args.link_dir.0, &args.name.0) {
@@ -52,7 +80,7 @@ pub fn handle_link(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> R
 
             // Get source file attributes after operation (link count should increase)
             let file_after = match filesystem.getattr(&args.file.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get file attributes after link: {}", e);
                     None
@@ -61,21 +89,20 @@ pub fn handle_link(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> R
 
             // Get target directory attributes after operation
             let dir_after = match filesystem.getattr(&args.link_dir.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get directory attributes after link: {}", e);
                     None
                 }
             };
 
-            create_link_response(xid, nfsstat3::NFS3_OK, file_after, dir_after)
+            create_link_response(xid, nfsstat3::NFS3_OK, file_after, dir_before, dir_after)
         }
         Err(e) => {
             warn!("LINK failed: {}", e);
             let status = map_error_to_status(&e);
-            let file_attr = file_before.map(|attr| NfsMessage::fsal_to_fattr3(&attr));
-            let dir_attr = dir_before.map(|attr| NfsMessage::fsal_to_fattr3(&attr));
-            create_link_response(xid, status, file_attr, dir_attr)
+            let file_attr = file_before.map(|attr| ctx.fattr3(&attr));
+            create_link_response(xid, status, file_attr, dir_before, dir_before)
         }
     }
 }
@@ -101,7 +128,8 @@ fn create_link_response(
     xid: u32,
     status: nfsstat3,
     file_attr: Option<crate::protocol::v3::nfs::fattr3>,
-    dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
+    dir_before: Option<crate::protocol::v3::nfs::fattr3>,
+    dir_after: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
 
@@ -122,29 +150,30 @@ fn create_link_response(
     }
 
     // 3. wcc_data (target directory)
-    // pre_op_attr (we don't track this, so set to false)
-    false.pack(&mut buf)?;
-
-    // post_op_attr (target directory)
-    match &dir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    let dir_before = dir_before.map(|attr| wcc_attr {
+        size: attr.size,
+        mtime: attr.mtime,
+        ctime: attr.ctime,
+    });
+    buf.extend(build_wcc_data(dir_before, dir_after)?);
 
     let res_data = BytesMut::from(&buf[..]);
     RpcMessage::create_success_reply_with_data(xid, res_data)
 }
 
 /// Map filesystem errors to NFS status codes
+///
+/// The whole error chain is matched, so an OS error (e.g. EXDEV) below the
+/// backend's context message is still recognized.
 fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
-    let error_msg = error.to_string().to_lowercase();
+    if let Some(status) = io_error_to_nfsstat(error) {
+        return status;
+    }
+    let error_msg = format!("{:#}", error).to_lowercase();
 
-    if error_msg.contains("not found") || error_msg.contains("no such file") {
+    if error_msg.contains("stale file handle") {
+        nfsstat3::NFS3ERR_STALE
+    } else if error_msg.contains("not found") || error_msg.contains("no such file") {
         nfsstat3::NFS3ERR_NOENT // 2 - No such file or directory
     } else if error_msg.contains("already exists") || error_msg.contains("file exists") {
         nfsstat3::NFS3ERR_EXIST // 17 - File exists
@@ -162,3 +191,99 @@ fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
         nfsstat3::NFS3ERR_IO // 5 - I/O error
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use crate::nfs::{Credentials, NfsState};
+    use crate::protocol::v3::nfs::{fhandle3, filename3, LINK3args};
+    use std::fs;
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    fn link(fs: &dyn Filesystem, file: Vec<u8>, dir: Vec<u8>, name: &str) -> BytesMut {
+        link_as(fs, file, dir, name, Credentials::root())
+    }
+
+    fn link_as(
+        fs: &dyn Filesystem,
+        file: Vec<u8>,
+        dir: Vec<u8>,
+        name: &str,
+        credentials: Credentials,
+    ) -> BytesMut {
+        let args = LINK3args {
+            file: fhandle3(file),
+            link_dir: fhandle3(dir),
+            name: filename3(name.to_string()),
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(credentials);
+        handle_link(1, &args_buf, fs, &ctx).unwrap()
+    }
+
+    fn reply_status(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_link() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let root = fs.root_handle();
+        let file = fs.lookup(&root, "file.txt").unwrap();
+
+        let reply = link(fs.as_ref(), file.clone(), root.clone(), "alias.txt");
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+        assert_eq!(fs::read(temp_dir.path().join("alias.txt")).unwrap(), b"data");
+        assert_eq!(fs.getattr(&file).unwrap().nlink, 2);
+
+        // The name is taken now
+        let reply = link(fs.as_ref(), file, root, "alias.txt");
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_EXIST as u32);
+    }
+
+    #[test]
+    fn test_link_across_exports() {
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        fs::write(a.path().join("file.txt"), b"data").unwrap();
+        let fs_a = BackendConfig::local(a.path()).create_filesystem().unwrap();
+        let fs_b = BackendConfig::local(b.path()).create_filesystem().unwrap();
+        let file = fs_a.lookup(&fs_a.root_handle(), "file.txt").unwrap();
+
+        let reply = link(fs_a.as_ref(), file, fs_b.root_handle(), "alias.txt");
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_XDEV as u32);
+        assert!(!b.path().join("alias.txt").exists());
+    }
+
+    #[test]
+    fn test_link_needs_write_access() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file.txt"), b"data").unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let root = fs.root_handle();
+        let file = fs.lookup(&root, "file.txt").unwrap();
+        let dir = fs.getattr(&root).unwrap();
+
+        // Neither owner nor in the group of the (0755 or tighter) directory
+        let stranger = Credentials {
+            uid: dir.uid.wrapping_add(1),
+            gid: dir.gid.wrapping_add(1),
+            gids: vec![],
+            anonymous: false,
+        };
+        let reply = link_as(fs.as_ref(), file.clone(), root, "alias.txt", stranger);
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_ACCES as u32);
+        assert!(!temp_dir.path().join("alias.txt").exists());
+        assert_eq!(fs.getattr(&file).unwrap().nlink, 1);
+    }
+}
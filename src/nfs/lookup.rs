@@ -7,7 +7,8 @@ use bytes::BytesMut;
 use tracing::debug;
 
 use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{NfsMessage, nfsstat3};
+use crate::nfs::{handle_error_to_nfsstat, io_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{fattr3, NfsMessage, nfsstat3};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS LOOKUP procedure (procedure 3)
@@ -19,6 +20,7 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized LOOKUP3args (directory handle + filename)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (owner/group override)
 ///
 /// # Returns
 /// Serialized RPC reply message with file handle and attributes
@@ -26,6 +28,7 @@ pub fn handle_lookup(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS LOOKUP called (xid={})", xid);
 
@@ -41,23 +44,55 @@ pub fn handle_lookup(
         name
     );
 
+    // The directory's attributes go with every reply, success or failure,
+    // so clients can keep their cache of it valid while walking paths.
+    // Read after the lookup, as the reply describes the directory after it.
+    let dir_attributes = || -> Option<fattr3> {
+        match filesystem.getattr(&args.what_dir.0) {
+            Ok(attrs) => {
+                let attrs = ctx.fattr3(&attrs);
+                Some(attrs)
+            }
+            Err(e) => {
+                debug!("LOOKUP: failed to get directory attributes: {}", e);
+                None
+            }
+        }
+    };
+
     // Look up the file in the directory
     let file_handle = match filesystem.lookup(&args.what_dir.0, name) {
         Ok(handle) => handle,
         Err(e) => {
             debug!("LOOKUP failed: {}", e);
-            // Return appropriate NFS error
-            let error_status = if e.to_string().contains("not found") {
+            // Only the directory having gone away makes its handle stale;
+            // a missing entry (or a dangling symlink's target) is NOENT
+            let dir_status = match filesystem.getattr(&args.what_dir.0) {
+                Ok(_) => None,
+                Err(dir_error) => handle_error_to_nfsstat(&dir_error),
+            };
+            let error_status = if let Some(status) = dir_status {
+                status
+            } else if let Some(status) = io_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("Stale file handle") {
+                nfsstat3::NFS3ERR_STALE
+            } else if e.to_string().contains("not found") {
                 nfsstat3::NFS3ERR_NOENT
             } else if e.to_string().contains("Invalid filename") {
                 nfsstat3::NFS3ERR_INVAL
-            } else if e.to_string().contains("Not a directory") {
+            } else if e.to_string().contains("Not a directory")
+                || e.to_string().contains("Is a symbolic link")
+            {
                 nfsstat3::NFS3ERR_NOTDIR
+            } else if e.to_string().contains("outside export root") {
+                nfsstat3::NFS3ERR_ACCES
             } else {
                 nfsstat3::NFS3ERR_IO
             };
 
-            let res_data = NfsMessage::create_lookup_error_response(error_status)?;
+            let res_data =
+                NfsMessage::create_lookup_error_response(error_status, dir_attributes().as_ref())?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
@@ -67,32 +102,20 @@ pub fn handle_lookup(
         Ok(attrs) => attrs,
         Err(e) => {
             debug!("LOOKUP: failed to get attributes for found file: {}", e);
-            let error_status = nfsstat3::NFS3ERR_IO;
-            let res_data = NfsMessage::create_lookup_error_response(error_status)?;
+            let error_status = io_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+            let res_data =
+                NfsMessage::create_lookup_error_response(error_status, dir_attributes().as_ref())?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
 
-    // Get attributes for the directory (optional but recommended)
-    let dir_attrs = match filesystem.getattr(&args.what_dir.0) {
-        Ok(attrs) => attrs,
-        Err(e) => {
-            debug!("LOOKUP: failed to get directory attributes: {}", e);
-            // We can still return success even if dir attrs fail
-            // For now, use the obj_attrs as a placeholder
-            // TODO: Make dir_attributes optional in response
-            obj_attrs.clone()
-        }
-    };
-
     debug!(
         "LOOKUP success: {} -> handle, type={:?}, size={}",
         name, obj_attrs.ftype, obj_attrs.size
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_obj_attrs = NfsMessage::fsal_to_fattr3(&obj_attrs);
-    let nfs_dir_attrs = NfsMessage::fsal_to_fattr3(&dir_attrs);
+    let nfs_obj_attrs = ctx.fattr3(&obj_attrs);
 
     // Wrap file_handle in fhandle3 (newtype wrapper)
     use crate::protocol::v3::nfs::fhandle3;
@@ -113,8 +136,15 @@ pub fn handle_lookup(
     nfs_obj_attrs.pack(&mut buf)?;
 
     // 4. post_op_attr (dir_attributes)
-    true.pack(&mut buf)?;  // attributes_follow = TRUE
-    nfs_dir_attrs.pack(&mut buf)?;
+    match dir_attributes() {
+        Some(nfs_dir_attrs) => {
+            true.pack(&mut buf)?;  // attributes_follow = TRUE
+            nfs_dir_attrs.pack(&mut buf)?;
+        }
+        None => {
+            false.pack(&mut buf)?;  // attributes_follow = FALSE
+        }
+    }
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -128,6 +158,7 @@ mod tests {
     use std::fs;
     use tempfile::TempDir;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::NfsState;
 
     #[test]
     fn test_lookup_existing_file() {
@@ -155,7 +186,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call LOOKUP
-        let result = handle_lookup(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_lookup(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "LOOKUP should succeed for existing file");
 
@@ -186,8 +219,131 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call LOOKUP
-        let result = handle_lookup(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_lookup(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "LOOKUP should return error response (not panic)");
     }
+
+    #[test]
+    fn test_lookup_rejects_traversal() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path()).create_filesystem().unwrap();
+        let root_handle = fs.root_handle();
+
+        use crate::protocol::v3::nfs::{LOOKUP3args, filename3, fhandle3};
+        use xdr_codec::Pack;
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let status_of = |name: &str| {
+            let args = LOOKUP3args {
+                what_dir: fhandle3(root_handle.clone()),
+                name: filename3(name.to_string()),
+            };
+            let mut args_buf = Vec::new();
+            args.pack(&mut args_buf).unwrap();
+            let reply = handle_lookup(12345, &args_buf, fs.as_ref(), &ctx).unwrap();
+            u32::from_be_bytes(reply[24..28].try_into().unwrap())
+        };
+
+        assert_eq!(status_of("../../etc/passwd"), nfsstat3::NFS3ERR_INVAL as u32);
+        assert_eq!(status_of("/etc/passwd"), nfsstat3::NFS3ERR_INVAL as u32);
+        assert_eq!(status_of(".."), nfsstat3::NFS3ERR_ACCES as u32);
+    }
+
+    #[test]
+    fn test_lookup_returns_dir_attributes() {
+        use crate::protocol::v3::nfs::{filename3, fhandle3, LOOKUP3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        fs::write(temp_dir.path().join("sub/file.txt"), b"hello").unwrap();
+        let fs = BackendConfig::local(temp_dir.path()).create_filesystem().unwrap();
+        let sub = fs.lookup(&fs.root_handle(), "sub").unwrap();
+        let sub_fileid = fs.getattr(&sub).unwrap().fileid;
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let lookup = |name: &str| {
+            let args = LOOKUP3args {
+                what_dir: fhandle3(sub.clone()),
+                name: filename3(name.to_string()),
+            };
+            let mut args_buf = Vec::new();
+            args.pack(&mut args_buf).unwrap();
+            handle_lookup(12345, &args_buf, fs.as_ref(), &ctx).unwrap()
+        };
+        let word = |reply: &[u8], at: usize| {
+            u32::from_be_bytes(reply[at..at + 4].try_into().unwrap())
+        };
+        // fattr3: type (4), mode, nlink, uid, gid (16), size, used (16),
+        // rdev (8), fsid (8), then fileid
+        let fileid = |reply: &[u8], attrs: usize| {
+            u64::from_be_bytes(reply[attrs + 52..attrs + 60].try_into().unwrap())
+        };
+
+        // Found: status, handle (4 + 32), then both post_op_attrs
+        let reply = lookup("file.txt");
+        assert_eq!(word(&reply, 24), nfsstat3::NFS3_OK as u32);
+        let obj_attrs = 28 + 4 + 32;
+        assert_eq!(word(&reply, obj_attrs), 1, "obj_attributes follow");
+        assert_eq!(word(&reply, obj_attrs + 4), 1, "object is a regular file");
+        let dir_attrs = obj_attrs + 4 + 84;
+        assert_eq!(word(&reply, dir_attrs), 1, "dir_attributes follow");
+        assert_eq!(word(&reply, dir_attrs + 4), 2, "dir_attributes describe a directory");
+        assert_eq!(fileid(&reply, dir_attrs + 4), sub_fileid);
+        assert_eq!(reply.len(), dir_attrs + 4 + 84);
+
+        // Not found: status, then the directory's post_op_attr
+        let reply = lookup("missing.txt");
+        assert_eq!(word(&reply, 24), nfsstat3::NFS3ERR_NOENT as u32);
+        assert_eq!(word(&reply, 28), 1, "dir_attributes follow on failure");
+        assert_eq!(fileid(&reply, 32), sub_fileid);
+        assert_eq!(reply.len(), 32 + 84);
+    }
+
+    #[test]
+    fn test_lookup_missing_entry_vs_removed_dir() {
+        use crate::protocol::v3::nfs::{LOOKUP3args, filename3, fhandle3};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("sub")).unwrap();
+        std::os::unix::fs::symlink("missing", temp_dir.path().join("sub/dangling")).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let sub = fs.lookup(&fs.root_handle(), "sub").unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let lookup = |name: &str| {
+            let mut args_buf = Vec::new();
+            LOOKUP3args {
+                what_dir: fhandle3(sub.clone()),
+                name: filename3(name.to_string()),
+            }
+            .pack(&mut args_buf)
+            .unwrap();
+            handle_lookup(1, &args_buf, fs.as_ref(), &ctx).unwrap()
+        };
+
+        // A missing entry is NOENT, with the directory's attributes
+        let reply = lookup("nonexistent.txt");
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_NOENT as u32).to_be_bytes());
+        assert_eq!(&reply[28..32], &1u32.to_be_bytes(), "dir_attributes follow");
+
+        // A dangling symlink is found like any other entry
+        let reply = lookup("dangling");
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+
+        // Only the directory itself going away makes the handle stale
+        fs::remove_file(temp_dir.path().join("sub/dangling")).unwrap();
+        fs::remove_dir(temp_dir.path().join("sub")).unwrap();
+        let reply = lookup("nonexistent.txt");
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_STALE as u32).to_be_bytes());
+    }
 }
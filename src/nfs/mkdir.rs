@@ -6,22 +6,31 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
+use crate::fsal::{FileAttributes, Filesystem};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext, NFS3_MAXNAMLEN};
+use crate::protocol::v3::nfs::{nfsstat3, set_gid3, set_mode3, set_uid3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS MKDIR request
 ///
-/// Creates a new directory in the specified parent directory.
+/// Creates a new directory in the specified parent directory, applying the
+/// requested mode and owner. Names longer than `NFS3_MAXNAMLEN` are rejected
+/// with NFS3ERR_NAMETOOLONG.
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized MKDIR3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context with the caller's credentials
 ///
 /// # Returns
 /// Serialized RPC reply with MKDIR3res
-pub fn handle_mkdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_mkdir(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS MKDIR: xid={}", xid);
 
     // Parse arguments
@@ -36,29 +45,80 @@ pub fn handle_mkdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
     // Get parent directory attributes before operation (for wcc_data)
     let dir_before = filesystem.getattr(&args.where_dir.0).ok();
 
+    // Reject names the server would never store (see PATHCONF name_max)
+    if args.name.0.len() > NFS3_MAXNAMLEN {
+        warn!("MKDIR failed: name of {} bytes is too long", args.name.0.len());
+        let dir_after = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+        return create_mkdir_response(
+            xid,
+            nfsstat3::NFS3ERR_NAMETOOLONG,
+            None,
+            None,
+            dir_before.as_ref(),
+            dir_after,
+        );
+    }
+
+    let credentials = &ctx.credentials;
+    if dir_before
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("MKDIR denied for uid {}", credentials.uid);
+        let dir_after = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+        return create_mkdir_response(
+            xid,
+            nfsstat3::NFS3ERR_ACCES,
+            None,
+            None,
+            dir_before.as_ref(),
+            dir_after,
+        );
+    }
+
     // Extract mode from sattr3, default to 0755
     let mode = match args.attributes.mode {
-        crate::protocol::v3::nfs::set_mode3::SET_MODE(m) => m,
-        crate::protocol::v3::nfs::set_mode3::default => 0o755,
+        set_mode3::SET_MODE(m) => m,
+        set_mode3::default => 0o755,
+    };
+
+    // Owner/group only change when requested; otherwise the new directory
+    // keeps what the backend assigned
+    let uid = match args.attributes.uid {
+        set_uid3::SET_UID(uid) => Some(uid),
+        set_uid3::default => None,
+    };
+    let gid = match args.attributes.gid {
+        set_gid3::SET_GID(gid) => Some(gid),
+        set_gid3::default => None,
     };
 
     // Perform mkdir operation
-    match filesystem.mkdir(&args.where_dir.0, &args.name.0, mode) {
+    let mkdir_result = filesystem
+        .mkdir(&args.where_dir.0, &args.name.0, mode)
+        .and_then(|handle| {
+            if uid.is_some() || gid.is_some() {
+                filesystem.setattr_owner(&handle, uid, gid)?;
+            }
+            Ok(handle)
+        });
+
+    match mkdir_result {
         Ok(new_dir_handle) => {
             debug!("MKDIR OK: created directory '{}'", args.name.0);
 
             // Get new directory attributes
             let new_dir_attr = match filesystem.getattr(&new_dir_handle) {
-                Ok(attr) => NfsMessage::fsal_to_fattr3(&attr),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get new directory attributes: {}", e);
-                    return create_mkdir_response(xid, nfsstat3::NFS3_OK, None, None, None);
+                    None
                 }
             };
 
             // Get parent directory attributes after operation
             let dir_after = match filesystem.getattr(&args.where_dir.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get parent dir attributes after mkdir: {}", e);
                     None
@@ -69,7 +129,8 @@ pub fn handle_mkdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
                 xid,
                 nfsstat3::NFS3_OK,
                 Some(new_dir_handle),
-                Some(new_dir_attr),
+                new_dir_attr,
+                dir_before.as_ref(),
                 dir_after,
             )
         }
@@ -78,7 +139,11 @@ pub fn handle_mkdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 
             // Determine appropriate error code
             let error_string = e.to_string();
-            let status = if error_string.contains("already exists") || error_string.contains("File exists") {
+            let status = if let Some(status) = io_error_to_nfsstat(&e) {
+                status
+            } else if error_string.contains("Stale file handle") {
+                nfsstat3::NFS3ERR_STALE
+            } else if error_string.contains("already exists") || error_string.contains("File exists") {
                 nfsstat3::NFS3ERR_EXIST
             } else if error_string.contains("not found") || error_string.contains("No such") {
                 nfsstat3::NFS3ERR_NOENT
@@ -99,9 +164,9 @@ pub fn handle_mkdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
             };
 
             // Try to get current parent directory attributes for wcc_data
-            let dir_after = filesystem.getattr(&args.where_dir.0).ok().map(|attr| NfsMessage::fsal_to_fattr3(&attr));
+            let dir_after = filesystem.getattr(&args.where_dir.0).ok().map(|attr| ctx.fattr3(&attr));
 
-            create_mkdir_response(xid, status, None, None, dir_after)
+            create_mkdir_response(xid, status, None, None, dir_before.as_ref(), dir_after)
         }
     }
 }
@@ -112,6 +177,7 @@ fn create_mkdir_response(
     status: nfsstat3,
     new_dir_handle: Option<Vec<u8>>,
     new_dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
+    parent_dir_before: Option<&FileAttributes>,
     parent_dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
@@ -152,21 +218,10 @@ fn create_mkdir_response(
     }
 
     // 4. wcc_data (parent directory)
-    // wcc_data = pre_op_attr + post_op_attr
-
-    // 4.1 pre_op_attr (before the operation) - we don't track this, so send FALSE
-    false.pack(&mut buf)?;
-
-    // 4.2 post_op_attr (after the operation)
-    match parent_dir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    buf.extend(build_wcc_data(
+        parent_dir_before.map(NfsMessage::fsal_to_wcc_attr),
+        parent_dir_attr,
+    )?);
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -182,10 +237,32 @@ fn create_mkdir_response(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nfs::{Credentials, NfsState};
     use crate::fsal::local::LocalFilesystem;
     use std::fs;
     use std::path::PathBuf;
 
+
+    fn mkdir(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        handle_mkdir(xid, args_buf, fs, &ctx)
+    }
+
+    fn mkdir_attrs() -> crate::protocol::v3::nfs::sattr3 {
+        use crate::protocol::v3::nfs::{sattr3, set_atime, set_mtime, set_size3};
+
+        sattr3 {
+            mode: set_mode3::SET_MODE(0o755),
+            uid: set_uid3::default,
+            gid: set_gid3::default,
+            size: set_size3::default,
+            atime: set_atime::default,
+            mtime: set_mtime::default,
+        }
+    }
+
     #[test]
     fn test_mkdir() {
         // Create test directory
@@ -212,18 +289,10 @@ mod tests {
         dirname.pack(&mut args_buf).unwrap();
 
         // attributes (sattr3)
-        let sattr = crate::protocol::v3::nfs::sattr3 {
-            mode: Some(0o755),
-            uid: None,
-            gid: None,
-            size: None,
-            atime: crate::protocol::v3::nfs::set_atime::DONT_CHANGE,
-            mtime: crate::protocol::v3::nfs::set_mtime::DONT_CHANGE,
-        };
-        sattr.pack(&mut args_buf).unwrap();
+        mkdir_attrs().pack(&mut args_buf).unwrap();
 
         // Call MKDIR
-        let result = handle_mkdir(12345, &args_buf, &fs);
+        let result = mkdir(12345, &args_buf, &fs);
         assert!(result.is_ok(), "MKDIR should succeed");
 
         // Verify directory was created
@@ -261,23 +330,43 @@ mod tests {
         let dirname = crate::protocol::v3::nfs::filename3("existingdir".to_string());
         dirname.pack(&mut args_buf).unwrap();
 
-        let sattr = crate::protocol::v3::nfs::sattr3 {
-            mode: Some(0o755),
-            uid: None,
-            gid: None,
-            size: None,
-            atime: crate::protocol::v3::nfs::set_atime::DONT_CHANGE,
-            mtime: crate::protocol::v3::nfs::set_mtime::DONT_CHANGE,
-        };
-        sattr.pack(&mut args_buf).unwrap();
+        mkdir_attrs().pack(&mut args_buf).unwrap();
 
         // Call MKDIR - should return error response
-        let result = handle_mkdir(12345, &args_buf, &fs);
+        let result = mkdir(12345, &args_buf, &fs);
         assert!(result.is_ok(), "MKDIR should return response (not crash)");
 
-        // TODO: Parse response and verify status is NFS3ERR_EXIST
+        let reply = result.unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_EXIST as u32).to_be_bytes());
 
         // Cleanup
         fs::remove_dir_all(&test_dir).unwrap();
     }
+
+    #[test]
+    fn test_mkdir_name_too_long() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_mkdir_toolong");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_mkdir_toolong".to_string()).unwrap();
+
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs.root_handle())
+            .pack(&mut args_buf)
+            .unwrap();
+        crate::protocol::v3::nfs::filename3("d".repeat(NFS3_MAXNAMLEN + 1))
+            .pack(&mut args_buf)
+            .unwrap();
+        mkdir_attrs().pack(&mut args_buf).unwrap();
+
+        let reply = mkdir(12345, &args_buf, &fs).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_NAMETOOLONG as u32).to_be_bytes());
+        // wcc_data: pre_op_attr and post_op_attr both present
+        assert_eq!(&reply[28..32], &[0u8, 0, 0, 1]);
+        assert_eq!(fs::read_dir(&test_dir).unwrap().count(), 0);
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
 }
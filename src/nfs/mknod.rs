@@ -12,21 +12,31 @@ use bytes::BytesMut;
 use tracing::{debug, warn};
 
 use crate::fsal::{FileType, Filesystem};
-use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{mknoddata3, nfsstat3, wcc_attr, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS MKNOD procedure (11)
 ///
-/// Creates a special file (device, FIFO, socket).
+/// Creates a special file (device, FIFO, socket) owned by the caller.
+/// Device nodes may only be created by root; other callers get
+/// NFS3ERR_PERM. Types MKNOD cannot create (regular files, directories,
+/// symlinks) get NFS3ERR_BADTYPE.
 ///
 /// # Arguments
 /// * `xid` - RPC transaction ID
 /// * `args_data` - Serialized MKNOD3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (caller credentials)
 ///
 /// # Returns
 /// Serialized MKNOD3res wrapped in RPC reply
-pub fn handle_mknod(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_mknod(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS MKNOD: xid={}", xid);
 
     // Parse arguments
@@ -41,41 +51,91 @@ pub fn handle_mknod(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 
     // Get directory attributes before operation (for wcc_data)
     let dir_before = filesystem.getattr(&args.where_dir.0).ok();
+    let dir_before_attr = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
 
     // Extract file type, mode, and device numbers from union
     let (file_type, mode, rdev) = match &args.what {
-        crate::protocol::v3::nfs::mknoddata3::NF3CHR(dev) => {
+        mknoddata3::NF3CHR(dev) => {
             debug!("  Creating character device: major={}, minor={}", dev.major, dev.minor);
             let mode = extract_mode(&dev.dev_attributes);
             (FileType::CharDevice, mode, (dev.major, dev.minor))
         }
-        crate::protocol::v3::nfs::mknoddata3::NF3BLK(dev) => {
+        mknoddata3::NF3BLK(dev) => {
             debug!("  Creating block device: major={}, minor={}", dev.major, dev.minor);
             let mode = extract_mode(&dev.dev_attributes);
             (FileType::BlockDevice, mode, (dev.major, dev.minor))
         }
-        crate::protocol::v3::nfs::mknoddata3::NF3SOCK(attrs) => {
+        mknoddata3::NF3SOCK(attrs) => {
             debug!("  Creating socket");
             let mode = extract_mode(attrs);
             (FileType::Socket, mode, (0, 0))
         }
-        crate::protocol::v3::nfs::mknoddata3::NF3FIFO(attrs) => {
+        mknoddata3::NF3FIFO(attrs) => {
             debug!("  Creating FIFO (named pipe)");
             let mode = extract_mode(attrs);
             (FileType::NamedPipe, mode, (0, 0))
         }
+        mknoddata3::default => {
+            debug!("MKNOD: type cannot be created with MKNOD");
+            return create_mknod_response(
+                xid,
+                nfsstat3::NFS3ERR_BADTYPE,
+                None,
+                None,
+                dir_before_attr,
+                dir_before_attr,
+            );
+        }
     };
 
+    let credentials = &ctx.credentials;
+    if dir_before
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("MKNOD denied for uid {}", credentials.uid);
+        return create_mknod_response(
+            xid,
+            nfsstat3::NFS3ERR_ACCES,
+            None,
+            None,
+            dir_before_attr,
+            dir_before_attr,
+        );
+    }
+
+    // Device nodes grant raw access to hardware, so only root may create them
+    let is_device = matches!(file_type, FileType::CharDevice | FileType::BlockDevice);
+    if is_device && credentials.uid != 0 {
+        debug!("MKNOD of a device denied for uid {}", credentials.uid);
+        return create_mknod_response(
+            xid,
+            nfsstat3::NFS3ERR_PERM,
+            None,
+            None,
+            dir_before_attr,
+            dir_before_attr,
+        );
+    }
+
     let name = &args.name.0;
 
     // Perform mknod operation
-    match filesystem.mknod(&args.where_dir.0, &name, file_type, mode, rdev) {
+    match filesystem.mknod(&args.where_dir.0, name, file_type, mode, rdev) {
         Ok(handle) => {
             debug!("MKNOD OK: created {:?}", name);
 
+            // The new node belongs to the caller; a backend that cannot
+            // change ownership (e.g. an unprivileged server) keeps its own
+            if let Err(e) =
+                filesystem.setattr_owner(&handle, Some(credentials.uid), Some(credentials.gid))
+            {
+                debug!("MKNOD: could not give new node to caller: {}", e);
+            }
+
             // Get attributes of the created special file
             let obj_attr = match filesystem.getattr(&handle) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get attributes after mknod: {}", e);
                     None
@@ -84,20 +144,30 @@ pub fn handle_mknod(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 
             // Get directory attributes after operation
             let dir_after = match filesystem.getattr(&args.where_dir.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get dir attributes after mknod: {}", e);
                     None
                 }
             };
 
-            create_mknod_response(xid, nfsstat3::NFS3_OK, Some(handle), obj_attr, dir_after)
+            create_mknod_response(
+                xid,
+                nfsstat3::NFS3_OK,
+                Some(handle),
+                obj_attr,
+                dir_before_attr,
+                dir_after,
+            )
         }
         Err(e) => {
             warn!("MKNOD failed: {}", e);
             let status = map_error_to_status(&e);
-            let dir_attr = dir_before.map(|attr| NfsMessage::fsal_to_fattr3(&attr));
-            create_mknod_response(xid, status, None, None, dir_attr)
+            let dir_after = filesystem
+                .getattr(&args.where_dir.0)
+                .ok()
+                .map(|attr| ctx.fattr3(&attr));
+            create_mknod_response(xid, status, None, None, dir_before_attr, dir_after)
         }
     }
 }
@@ -132,7 +202,8 @@ fn create_mknod_response(
     status: nfsstat3,
     obj_handle: Option<Vec<u8>>,
     obj_attr: Option<crate::protocol::v3::nfs::fattr3>,
-    dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
+    dir_before: Option<crate::protocol::v3::nfs::fattr3>,
+    dir_after: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
 
@@ -173,21 +244,12 @@ fn create_mknod_response(
     }
 
     // dir_wcc (for both success and failure)
-    // wcc_data: pre_op_attr + post_op_attr
-
-    // pre_op_attr (we don't track this, so set to false)
-    false.pack(&mut buf)?;
-
-    // post_op_attr (directory attributes)
-    match &dir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    let dir_before = dir_before.map(|attr| wcc_attr {
+        size: attr.size,
+        mtime: attr.mtime,
+        ctime: attr.ctime,
+    });
+    buf.extend(build_wcc_data(dir_before, dir_after)?);
 
     let res_data = BytesMut::from(&buf[..]);
     RpcMessage::create_success_reply_with_data(xid, res_data)
@@ -195,11 +257,18 @@ fn create_mknod_response(
 
 /// Map filesystem errors to NFS status codes
 fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
+    if let Some(status) = io_error_to_nfsstat(error) {
+        return status;
+    }
     let error_msg = error.to_string().to_lowercase();
 
-    if error_msg.contains("not found") || error_msg.contains("no such file") {
+    if error_msg.contains("stale file handle") {
+        nfsstat3::NFS3ERR_STALE
+    } else if error_msg.contains("not found") || error_msg.contains("no such file") {
         nfsstat3::NFS3ERR_NOENT // 2 - No such file or directory
-    } else if error_msg.contains("permission denied") || error_msg.contains("access denied") || error_msg.contains("operation not permitted") {
+    } else if error_msg.contains("operation not permitted") {
+        nfsstat3::NFS3ERR_PERM // 1 - Not owner (EPERM)
+    } else if error_msg.contains("permission denied") || error_msg.contains("access denied") {
         nfsstat3::NFS3ERR_ACCES // 13 - Permission denied
     } else if error_msg.contains("exists") || error_msg.contains("already") {
         nfsstat3::NFS3ERR_EXIST // 17 - File exists
@@ -213,3 +282,129 @@ fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
         nfsstat3::NFS3ERR_IO // 5 - I/O error
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use crate::nfs::{Credentials, NfsState};
+    use crate::protocol::v3::nfs::{
+        devicedata3, fhandle3, filename3, ftype3, sattr3, set_atime, set_gid3, set_mode3,
+        set_mtime, set_size3, set_uid3, MKNOD3args,
+    };
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    fn attrs() -> sattr3 {
+        sattr3 {
+            mode: set_mode3::SET_MODE(0o640),
+            uid: set_uid3::default,
+            gid: set_gid3::default,
+            size: set_size3::default,
+            atime: set_atime::default,
+            mtime: set_mtime::default,
+        }
+    }
+
+    fn mknod_as(
+        fs: &dyn Filesystem,
+        name: &str,
+        what: mknoddata3,
+        credentials: Credentials,
+    ) -> BytesMut {
+        let args = MKNOD3args {
+            where_dir: fhandle3(fs.root_handle()),
+            name: filename3(name.to_string()),
+            what,
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        send_as(fs, &args_buf, credentials)
+    }
+
+    fn send_as(fs: &dyn Filesystem, args_buf: &[u8], credentials: Credentials) -> BytesMut {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(credentials);
+        handle_mknod(1, args_buf, fs, &ctx).unwrap()
+    }
+
+    fn caller(uid: u32) -> Credentials {
+        Credentials {
+            uid,
+            gid: uid,
+            gids: vec![],
+            anonymous: false,
+        }
+    }
+
+    fn reply_status(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_mknod_fifo_and_socket() {
+        use std::os::unix::fs::FileTypeExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let owner = caller(fs.getattr(&fs.root_handle()).unwrap().uid);
+
+        let reply = mknod_as(fs.as_ref(), "pipe", mknoddata3::NF3FIFO(attrs()), owner.clone());
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+        let reply = mknod_as(fs.as_ref(), "sock", mknoddata3::NF3SOCK(attrs()), owner);
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+
+        let file_type = |name| {
+            std::fs::symlink_metadata(temp_dir.path().join(name))
+                .unwrap()
+                .file_type()
+        };
+        assert!(file_type("pipe").is_fifo());
+        assert!(file_type("sock").is_socket());
+    }
+
+    #[test]
+    fn test_mknod_device_requires_root() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let owner = caller(fs.getattr(&fs.root_handle()).unwrap().uid);
+        let device = || {
+            mknoddata3::NF3CHR(devicedata3 {
+                dev_attributes: attrs(),
+                major: 1,
+                minor: 3,
+            })
+        };
+
+        if owner.uid != 0 {
+            let reply = mknod_as(fs.as_ref(), "null", device(), owner);
+            assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_PERM as u32);
+        }
+        // Anonymous callers are nobody, who may not even write the directory
+        let reply = mknod_as(fs.as_ref(), "null", device(), Credentials::anonymous());
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_ACCES as u32);
+        assert!(!temp_dir.path().join("null").exists());
+    }
+
+    #[test]
+    fn test_mknod_regular_file_is_badtype() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+
+        // The generated `default` arm cannot carry its type, so pack by hand
+        let mut args_buf = Vec::new();
+        fhandle3(fs.root_handle()).pack(&mut args_buf).unwrap();
+        filename3("file".to_string()).pack(&mut args_buf).unwrap();
+        (ftype3::NF3REG as i32).pack(&mut args_buf).unwrap();
+
+        let reply = send_as(fs.as_ref(), &args_buf, caller(0));
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_BADTYPE as u32);
+    }
+}
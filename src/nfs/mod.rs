@@ -3,9 +3,18 @@
 // This module implements the NFSv3 protocol procedures.
 // See RFC 1813 for the complete specification.
 
+pub mod context;
+pub mod credentials;
+pub mod dirty;
 pub mod dispatcher;
+pub mod drc;
+pub mod inflight;
+pub mod throttle;
+pub mod verifier;
+pub mod write_serializer;
 mod access;
 mod commit;
+mod cookie;
 mod create;
 mod fsinfo;
 mod fsstat;
@@ -27,4 +36,188 @@ mod setattr;
 mod symlink;
 mod write;
 
+use anyhow::Result;
+use xdr_codec::Pack;
+
+use crate::protocol::v3::nfs::{fattr3, nfsstat3, wcc_attr};
+
+pub use context::{NfsContext, NfsState, MAX_UDP_TRANSFER};
+pub use credentials::Credentials;
 pub use dispatcher::dispatch;
+
+/// NFS program number (RFC 1813)
+pub const NFS_PROGRAM: u32 = 100003;
+
+/// NFS version 3
+pub const NFS_V3: u32 = 3;
+
+/// Longest file name accepted by the server (reported as PATHCONF name_max)
+pub const NFS3_MAXNAMLEN: usize = 255;
+
+/// The file handle at the start of NFSv3 procedure arguments
+///
+/// Every NFSv3 procedure but NULL takes a handle (nfs_fh3, opaque<64>) as
+/// its first argument. Returns None if the arguments don't start with one.
+pub fn file_handle_arg(args_data: &[u8]) -> Option<&[u8]> {
+    let len = u32::from_be_bytes(args_data.get(0..4)?.try_into().ok()?) as usize;
+    if len > crate::fsal::handle::MAX_HANDLE_LEN {
+        return None;
+    }
+    args_data.get(4..4 + len)
+}
+
+/// Pack wcc_data: the object's size, mtime and ctime captured before a
+/// change (pre_op_attr) followed by its attributes after it (post_op_attr)
+///
+/// Clients compare the pre-op times with what they cached to tell whether
+/// anyone else changed the object, so every mutating procedure reports
+/// both halves whenever the attributes could be read.
+pub fn build_wcc_data(pre: Option<wcc_attr>, post: Option<fattr3>) -> Result<Vec<u8>> {
+    let mut buf = Vec::new();
+    match pre {
+        Some(pre) => {
+            true.pack(&mut buf)?; // pre_op_attr: attributes_follow = TRUE
+            pre.pack(&mut buf)?;
+        }
+        None => {
+            false.pack(&mut buf)?; // pre_op_attr: attributes_follow = FALSE
+        }
+    }
+    match post {
+        Some(post) => {
+            true.pack(&mut buf)?; // post_op_attr: attributes_follow = TRUE
+            post.pack(&mut buf)?;
+        }
+        None => {
+            false.pack(&mut buf)?; // post_op_attr: attributes_follow = FALSE
+        }
+    }
+    Ok(buf)
+}
+
+/// NFS status for the OS error behind a backend failure, if there is one
+///
+/// Backends keep the `io::Error` as the source of the errors they return
+/// (`.context(...)`), so the errno tells precisely what went wrong; handlers
+/// fall back to matching the message for errors raised without one.
+pub fn io_error_to_nfsstat(err: &anyhow::Error) -> Option<nfsstat3> {
+    let errno = err
+        .chain()
+        .find_map(|cause| cause.downcast_ref::<std::io::Error>())?
+        .raw_os_error()?;
+    let status = match errno {
+        libc::EPERM => nfsstat3::NFS3ERR_PERM,
+        libc::ENOENT => nfsstat3::NFS3ERR_NOENT,
+        libc::EIO => nfsstat3::NFS3ERR_IO,
+        libc::ENXIO => nfsstat3::NFS3ERR_NXIO,
+        libc::EACCES => nfsstat3::NFS3ERR_ACCES,
+        libc::EEXIST => nfsstat3::NFS3ERR_EXIST,
+        libc::EXDEV => nfsstat3::NFS3ERR_XDEV,
+        libc::ENODEV => nfsstat3::NFS3ERR_NODEV,
+        libc::ENOTDIR => nfsstat3::NFS3ERR_NOTDIR,
+        libc::EISDIR => nfsstat3::NFS3ERR_ISDIR,
+        libc::EINVAL => nfsstat3::NFS3ERR_INVAL,
+        libc::EFBIG => nfsstat3::NFS3ERR_FBIG,
+        libc::ENOSPC => nfsstat3::NFS3ERR_NOSPC,
+        libc::EROFS => nfsstat3::NFS3ERR_ROFS,
+        libc::EMLINK => nfsstat3::NFS3ERR_MLINK,
+        libc::ENAMETOOLONG => nfsstat3::NFS3ERR_NAMETOOLONG,
+        libc::ENOTEMPTY => nfsstat3::NFS3ERR_NOTEMPTY,
+        libc::EDQUOT => nfsstat3::NFS3ERR_DQUOT,
+        libc::ESTALE => nfsstat3::NFS3ERR_STALE,
+        libc::EOPNOTSUPP => nfsstat3::NFS3ERR_NOTSUPP,
+        _ => return None,
+    };
+    Some(status)
+}
+
+/// `io_error_to_nfsstat` for a failure on the object a file handle names,
+/// where the object having gone away makes the handle stale
+pub fn handle_error_to_nfsstat(err: &anyhow::Error) -> Option<nfsstat3> {
+    match io_error_to_nfsstat(err) {
+        Some(nfsstat3::NFS3ERR_NOENT) => Some(nfsstat3::NFS3ERR_STALE),
+        status => status,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::protocol::v3::nfs::{ftype3, nfstime3};
+
+    #[test]
+    fn test_build_wcc_data() {
+        let time = |seconds| nfstime3 { seconds, nseconds: 0 };
+        let pre = wcc_attr {
+            size: 10,
+            mtime: time(1),
+            ctime: time(2),
+        };
+        let post = fattr3 {
+            type_: ftype3::NF3REG,
+            mode: 0o644,
+            nlink: 1,
+            uid: 0,
+            gid: 0,
+            size: 20,
+            used: 0,
+            rdev: 0,
+            fsid: 0,
+            fileid: 7,
+            atime: time(3),
+            mtime: time(4),
+            ctime: time(5),
+        };
+
+        // Both halves: 4 + 24, then 4 + 84
+        let wcc = build_wcc_data(Some(pre.clone()), Some(post.clone())).unwrap();
+        assert_eq!(wcc.len(), 116);
+        assert_eq!(&wcc[0..4], &[0, 0, 0, 1]);
+        assert_eq!(&wcc[4..12], &10u64.to_be_bytes());
+        assert_eq!(&wcc[12..16], &1u32.to_be_bytes());
+        assert_eq!(&wcc[20..24], &2u32.to_be_bytes());
+        assert_eq!(&wcc[28..32], &[0, 0, 0, 1]);
+        assert_eq!(&wcc[100..104], &4u32.to_be_bytes());
+
+        // Missing halves are just the FALSE discriminant
+        assert_eq!(build_wcc_data(None, None).unwrap(), vec![0u8; 8]);
+        assert_eq!(build_wcc_data(Some(pre), None).unwrap().len(), 32);
+        assert_eq!(build_wcc_data(None, Some(post)).unwrap().len(), 92);
+    }
+
+    #[test]
+    fn test_io_error_to_nfsstat() {
+        use anyhow::Context;
+
+        let os_error = |errno| -> anyhow::Error {
+            Err::<(), _>(std::io::Error::from_raw_os_error(errno))
+                .context("Failed to do something")
+                .unwrap_err()
+        };
+        let status = |errno| io_error_to_nfsstat(&os_error(errno)).map(|s| s as i32);
+
+        assert_eq!(status(libc::ENOENT), Some(nfsstat3::NFS3ERR_NOENT as i32));
+        assert_eq!(status(libc::EACCES), Some(nfsstat3::NFS3ERR_ACCES as i32));
+        assert_eq!(status(libc::ENOSPC), Some(nfsstat3::NFS3ERR_NOSPC as i32));
+        assert_eq!(status(libc::EROFS), Some(nfsstat3::NFS3ERR_ROFS as i32));
+        assert_eq!(status(libc::ENOTEMPTY), Some(nfsstat3::NFS3ERR_NOTEMPTY as i32));
+        assert_eq!(status(libc::ENAMETOOLONG), Some(nfsstat3::NFS3ERR_NAMETOOLONG as i32));
+        assert_eq!(status(libc::EINTR), None);
+
+        // Found through any amount of context
+        let nested = Err::<(), _>(os_error(libc::EDQUOT)).context("outer").unwrap_err();
+        assert_eq!(
+            io_error_to_nfsstat(&nested).map(|s| s as i32),
+            Some(nfsstat3::NFS3ERR_DQUOT as i32)
+        );
+
+        // Nothing to go on without an io::Error
+        assert!(io_error_to_nfsstat(&anyhow::anyhow!("No space left")).is_none());
+
+        // A missing object behind a handle is a stale handle
+        assert_eq!(
+            handle_error_to_nfsstat(&os_error(libc::ENOENT)).map(|s| s as i32),
+            Some(nfsstat3::NFS3ERR_STALE as i32)
+        );
+    }
+}
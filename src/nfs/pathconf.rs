@@ -8,7 +8,8 @@ use tracing::debug;
 use xdr_codec::Pack;
 
 use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{fattr3, nfsstat3, NfsMessage};
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{fattr3, nfsstat3};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS PATHCONF request
@@ -17,10 +18,16 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized PATHCONF3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (owner/group override)
 ///
 /// # Returns
 /// Serialized RPC reply with PATHCONF3res
-pub fn handle_pathconf(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_pathconf(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS PATHCONF: xid={}", xid);
 
     // Parse arguments - just a file handle
@@ -33,22 +40,33 @@ pub fn handle_pathconf(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem)
 
     // Get file attributes
     let obj_attrs = match filesystem.getattr(&object.0) {
-        Ok(attr) => NfsMessage::fsal_to_fattr3(&attr),
+        Ok(attr) => ctx.fattr3(&attr),
         Err(e) => {
             debug!("PATHCONF failed: {}", e);
-            return create_pathconf_error(xid, nfsstat3::NFS3ERR_STALE);
+            let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_STALE);
+            return create_pathconf_error(xid, status, None);
         }
     };
 
-    // Create PATHCONF response with typical Unix values
+    // Limits of the filesystem holding the object
+    let conf = match filesystem.pathconf(&object.0) {
+        Ok(conf) => conf,
+        Err(e) => {
+            debug!("PATHCONF failed: {}", e);
+            let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_STALE);
+            return create_pathconf_error(xid, status, Some(&obj_attrs));
+        }
+    };
+
+    // Names longer than NFS3_MAXNAMLEN are rejected whatever the backend allows
     let response = create_pathconf_ok(
         obj_attrs,
-        255,    // linkmax - maximum number of hard links
-        255,    // name_max - maximum filename length
-        true,   // no_trunc - server will reject names longer than name_max
-        true,   // chown_restricted - only privileged user can change file ownership
-        false,  // case_insensitive - filenames are case-sensitive
-        true,   // case_preserving - filenames preserve case
+        conf.linkmax,
+        conf.name_max.min(super::NFS3_MAXNAMLEN as u32),
+        conf.no_trunc,
+        conf.chown_restricted,
+        conf.case_insensitive,
+        conf.case_preserving,
     )?;
 
     debug!("PATHCONF OK: response size: {} bytes", response.len());
@@ -88,15 +106,28 @@ fn create_pathconf_ok(
     Ok(BytesMut::from(&buf[..]))
 }
 
-/// Create PATHCONF error response
-fn create_pathconf_error(xid: u32, status: nfsstat3) -> Result<BytesMut> {
+/// Create PATHCONF error response, with the object's attributes when they
+/// could be read
+fn create_pathconf_error(
+    xid: u32,
+    status: nfsstat3,
+    obj_attributes: Option<&fattr3>,
+) -> Result<BytesMut> {
     let mut buf = Vec::new();
 
     // Status code
     (status as i32).pack(&mut buf)?;
 
-    // post_op_attr with no attributes (bool = false)
-    false.pack(&mut buf)?;  // attributes_follow = FALSE
+    // post_op_attr (obj_attributes)
+    match obj_attributes {
+        Some(attrs) => {
+            true.pack(&mut buf)?;  // attributes_follow = TRUE
+            attrs.pack(&mut buf)?;
+        }
+        None => {
+            false.pack(&mut buf)?;  // attributes_follow = FALSE
+        }
+    }
 
     let res_data = BytesMut::from(&buf[..]);
     RpcMessage::create_success_reply_with_data(xid, res_data)
@@ -4,9 +4,10 @@
 
 use anyhow::Result;
 use bytes::BytesMut;
-use tracing::debug;
+use tracing::{debug, warn};
 
 use crate::fsal::Filesystem;
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
@@ -18,6 +19,7 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized READ3args (file handle + offset + count)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (in-flight payload budget)
 ///
 /// # Returns
 /// Serialized RPC reply message with file data
@@ -25,6 +27,7 @@ pub fn handle_read(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS READ called (xid={})", xid);
 
@@ -38,25 +41,38 @@ pub fn handle_read(
         args.count
     );
 
+    // Never return more than the rtmax advertised in FSINFO; a short reply
+    // tells the client to ask for the rest
+    let rtmax = ctx.transfer_limit(ctx.state.config.rtmax);
+    let count = args.count.min(rtmax);
+    if count < args.count {
+        debug!("READ: count {} clamped to rtmax {}", args.count, rtmax);
+    }
+
+    // The file's attributes go with failures too, when they can be read
+    let file_attributes = || filesystem.getattr(&args.file.0).ok().map(|attrs| ctx.fattr3(&attrs));
+
+    // Reserve the payload against the in-flight budget; when it is exhausted
+    // JUKEBOX makes the client retry once outstanding transfers drain
+    if !ctx.reserve_reply(count as u64) {
+        warn!("READ deferred: in-flight payload budget exhausted (count={})", count);
+        let res_data = NfsMessage::create_read_error_response(
+            nfsstat3::NFS3ERR_JUKEBOX,
+            file_attributes().as_ref(),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
+    }
+
     // Read data from the file
-    let data = match filesystem.read(&args.file.0, args.offset, args.count) {
+    // Reads past end of file return no data rather than an error
+    let data = match filesystem.read(&args.file.0, args.offset, count) {
         Ok(data) => data,
         Err(e) => {
-            debug!("READ failed: {}", e);
-            // Return appropriate NFS error
-            let error_status = if e.to_string().contains("not found")
-                || e.to_string().contains("Invalid handle")
-            {
-                nfsstat3::NFS3ERR_STALE
-            } else if e.to_string().contains("Not a file") {
-                nfsstat3::NFS3ERR_ISDIR
-            } else if e.to_string().contains("Permission denied") {
-                nfsstat3::NFS3ERR_ACCES
-            } else {
-                nfsstat3::NFS3ERR_IO
-            };
-
-            let res_data = NfsMessage::create_read_error_response(error_status)?;
+            debug!("READ failed: {:#}", e);
+            let error_status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+
+            let res_data =
+                NfsMessage::create_read_error_response(error_status, file_attributes().as_ref())?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
@@ -68,14 +84,14 @@ pub fn handle_read(
             debug!("READ: failed to get file attributes: {}", e);
             // Still return error even if we read successfully but can't get attrs
             let error_status = nfsstat3::NFS3ERR_IO;
-            let res_data = NfsMessage::create_read_error_response(error_status)?;
+            let res_data = NfsMessage::create_read_error_response(error_status, None)?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
 
     // Determine if we've reached end of file
     let bytes_read = data.len() as u32;
-    let eof = (args.offset + bytes_read as u64) >= file_attrs.size;
+    let eof = args.offset.saturating_add(bytes_read as u64) >= file_attrs.size;
 
     debug!(
         "READ success: read {} bytes, eof={}",
@@ -83,7 +99,7 @@ pub fn handle_read(
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_attrs = NfsMessage::fsal_to_fattr3(&file_attrs);
+    let nfs_attrs = ctx.fattr3(&file_attrs);
 
     // Create READ response manually with post_op_attr format
     use xdr_codec::Pack;
@@ -124,6 +140,7 @@ pub fn handle_read(
 mod tests {
     use super::*;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::NfsState;
     use std::fs;
     use tempfile::TempDir;
 
@@ -156,7 +173,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call READ
-        let result = handle_read(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_read(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "READ should succeed");
 
@@ -193,7 +212,9 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call READ
-        let result = handle_read(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_read(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "Partial READ should succeed");
     }
@@ -219,8 +240,164 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call READ
-        let result = handle_read(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_read(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "READ should return error response (not panic)");
+        let reply = result.unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_STALE as u32).to_be_bytes());
+    }
+
+    #[test]
+    fn test_read_inflight_budget_exhausted() {
+        use crate::config::NfsConfig;
+        use crate::protocol::v3::nfs::READ3args;
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("busy.txt"), b"0123456789").unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "busy.txt").unwrap();
+
+        let state = NfsState::new(NfsConfig {
+            max_inflight_bytes: Some(16),
+            ..NfsConfig::default()
+        });
+        let args = READ3args {
+            file: crate::protocol::v3::nfs::fhandle3(file_handle),
+            offset: 0,
+            count: 10,
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+
+        // The call's own bytes, as the transport reserves them, over the limit
+        let call = state.inflight.try_acquire(20).unwrap();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_reservation(&call);
+
+        // Another transfer holds part of the budget: this one does not fit
+        let held = state.inflight.reserve(8);
+        let reply = handle_read(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_JUKEBOX as u32).to_be_bytes());
+        assert_eq!(&reply[28..32], &1u32.to_be_bytes(), "file_attributes follow");
+        assert_eq!(call.bytes(), 20);
+
+        // Once it drains the retry goes through, despite the call's own bytes
+        drop(held);
+        let reply = handle_read(2, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+        assert_eq!(call.bytes(), 30, "Payload should stay reserved for the reply");
+        drop(ctx);
+        drop(call);
+        assert_eq!(state.inflight.in_flight(), 0);
+    }
+
+    /// READ3args for `count` bytes of `name` at `offset`
+    fn read_args(fs: &dyn Filesystem, name: &str, offset: u64, count: u32) -> Vec<u8> {
+        use crate::protocol::v3::nfs::READ3args;
+        use xdr_codec::Pack;
+
+        let file_handle = fs.lookup(&fs.root_handle(), name).unwrap();
+        let args = READ3args {
+            file: crate::protocol::v3::nfs::fhandle3(file_handle),
+            offset,
+            count,
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    /// Status, count, eof and data of a READ reply
+    ///
+    /// Layout: RPC header (24) + status (4) + post_op_attr (4 + 84)
+    /// + count (4) + eof (4) + data length (4) + data
+    fn parse_reply(reply: &[u8]) -> (u32, u32, bool, Vec<u8>) {
+        let read_u32 = |at: usize| u32::from_be_bytes(reply[at..at + 4].try_into().unwrap());
+        let len = read_u32(124) as usize;
+        (read_u32(24), read_u32(116), read_u32(120) == 1, reply[128..128 + len].to_vec())
+    }
+
+    #[test]
+    fn test_read_eof() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("eof.txt"), b"0123456789").unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        // Within the file: eof=false
+        let args_buf = read_args(fs.as_ref(), "eof.txt", 2, 4);
+        let reply = handle_read(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(parse_reply(&reply), (0, 4, false, b"2345".to_vec()));
+        assert_eq!(&reply[28..32], &[0, 0, 0, 1], "post-op attributes should follow");
+
+        // Ending exactly at end of file: eof=true
+        let args_buf = read_args(fs.as_ref(), "eof.txt", 6, 4);
+        let reply = handle_read(2, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(parse_reply(&reply), (0, 4, true, b"6789".to_vec()));
+
+        // Crossing end of file: short read, eof=true
+        let args_buf = read_args(fs.as_ref(), "eof.txt", 8, 100);
+        let reply = handle_read(3, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(parse_reply(&reply), (0, 2, true, b"89".to_vec()));
+
+        // Entirely past end of file: no data, eof=true, not an error
+        let args_buf = read_args(fs.as_ref(), "eof.txt", 1000, 10);
+        let reply = handle_read(4, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(parse_reply(&reply), (0, 0, true, Vec::new()));
+    }
+
+    #[test]
+    fn test_read_clamped_to_rtmax() {
+        use crate::config::NfsConfig;
+
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("big.txt"), vec![b'x'; 100]).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let state = NfsState::new(NfsConfig {
+            rtmax: 64,
+            ..NfsConfig::default()
+        });
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        let args_buf = read_args(fs.as_ref(), "big.txt", 0, 100);
+        let reply = handle_read(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        let (status, count, eof, data) = parse_reply(&reply);
+        assert_eq!((status, count, eof), (0, 64, false));
+        assert_eq!(data.len(), 64);
+    }
+
+    #[test]
+    fn test_read_error_status() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::create_dir(temp_dir.path().join("dir")).unwrap();
+        fs::write(temp_dir.path().join("gone.txt"), b"data").unwrap();
+        std::os::unix::fs::symlink("gone.txt", temp_dir.path().join("link")).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let status = |args_buf: &[u8]| {
+            let reply = handle_read(1, args_buf, fs.as_ref(), &ctx).unwrap();
+            u32::from_be_bytes(reply[24..28].try_into().unwrap())
+        };
+
+        assert_eq!(status(&read_args(fs.as_ref(), "dir", 0, 10)), nfsstat3::NFS3ERR_ISDIR as u32);
+        assert_eq!(status(&read_args(fs.as_ref(), "link", 0, 10)), nfsstat3::NFS3ERR_INVAL as u32);
+
+        // A handle whose file was removed is stale
+        let args_buf = read_args(fs.as_ref(), "gone.txt", 0, 10);
+        fs::remove_file(temp_dir.path().join("gone.txt")).unwrap();
+        assert_eq!(status(&args_buf), nfsstat3::NFS3ERR_STALE as u32);
     }
 }
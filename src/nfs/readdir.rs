@@ -7,7 +7,10 @@ use bytes::BytesMut;
 use tracing::{debug, warn};
 
 use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{cookieverf3, entry3, fileid3, nfsstat3, NfsMessage, COOKIEVERFSIZE};
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{entry3, fileid3, nfsstat3, NfsMessage};
+
+use super::cookie;
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS READDIR request
@@ -16,10 +19,16 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized READDIR3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (used for the per-client enumeration budget)
 ///
 /// # Returns
 /// Serialized RPC reply with READDIR3res
-pub fn handle_readdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_readdir(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS READDIR: xid={}", xid);
 
     // Parse arguments
@@ -32,23 +41,56 @@ pub fn handle_readdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
         args.count
     );
 
+    // Throttle clients that exhausted their enumeration budget
+    // JUKEBOX tells the client to back off and retry later
+    let client_ip = ctx.client_addr.ip();
+    if !ctx.state.readdir_throttle.check(client_ip) {
+        warn!("READDIR throttled: client {} exceeded its entry budget", client_ip);
+        let res_data = NfsMessage::create_readdir_error_response(
+            nfsstat3::NFS3ERR_JUKEBOX,
+            filesystem.getattr(&args.dir.0).ok().map(|attrs| ctx.fattr3(&attrs)).as_ref(),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
+    }
+
     // Get directory attributes
-    let dir_attr = match filesystem.getattr(&args.dir.0) {
-        Ok(attr) => NfsMessage::fsal_to_fattr3(&attr),
+    let attrs = match filesystem.getattr(&args.dir.0) {
+        Ok(attr) => attr,
         Err(e) => {
             warn!("READDIR failed: getattr error: {}", e);
-            let res_data = NfsMessage::create_readdir_error_response(nfsstat3::NFS3ERR_IO)?;
+            let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+            let res_data = NfsMessage::create_readdir_error_response(status, None)?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
+    let dir_attr = ctx.fattr3(&attrs);
+
+    // The directory changed since the listing started: cookies no longer
+    // point where they did, so the client has to start over
+    if !cookie::verifier_matches(args.cookie, &args.cookieverf, &attrs) {
+        debug!("READDIR: stale cookie verifier for cookie {}", args.cookie);
+        let res_data = NfsMessage::create_readdir_error_response(
+            nfsstat3::NFS3ERR_BAD_COOKIE,
+            Some(&dir_attr),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
+    }
 
     // Read directory entries
-    let (entries, eof) = match filesystem.readdir(&args.dir.0, args.cookie, args.count) {
-        Ok(result) => result,
-        Err(e) => {
-            warn!("READDIR failed: {}", e);
-            let res_data = NfsMessage::create_readdir_error_response(nfsstat3::NFS3ERR_IO)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+    // A zero count leaves no room for entries: reply with an empty list and
+    // eof=false so the client can retry with a real budget
+    let (entries, eof) = if args.count == 0 {
+        debug!("  count is 0, returning no entries");
+        (Vec::new(), false)
+    } else {
+        match filesystem.readdir(&args.dir.0, args.cookie, args.count) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("READDIR failed: {}", e);
+                let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+                let res_data = NfsMessage::create_readdir_error_response(status, Some(&dir_attr))?;
+                return RpcMessage::create_success_reply_with_data(xid, res_data);
+            }
         }
     };
 
@@ -67,30 +109,50 @@ pub fn handle_readdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
     dir_attr.pack(&mut buf)?;
 
     // 3. cookieverf
-    let cookieverf = cookieverf3([0u8; COOKIEVERFSIZE as usize]);
-    cookieverf.pack(&mut buf)?;
+    cookie::cookie_verifier(&attrs).pack(&mut buf)?;
 
     // 4. dirlist3 (entry list)
     // Serialize each entry with boolean discriminator pattern:
     // For each entry: true + entry3 data (fileid + name + cookie)
     // End of list: false
-    let mut cookie_counter = args.cookie;
-    for dir_entry in entries.iter() {
-        cookie_counter += 1;
+    // Entries are only emitted while the reply stays within the client's
+    // count, leaving room for the list terminator and eof (8 bytes)
+    let mut emitted = 0;
+    for (position, dir_entry) in entries.iter().enumerate() {
+        let mut entry_buf = Vec::new();
 
         // Boolean discriminator: true = entry follows
-        true.pack(&mut buf)?;
+        true.pack(&mut entry_buf)?;
 
         // Serialize entry3 fields directly (without nextentry pointer)
         let fileid = dir_entry.fileid as fileid3;
-        fileid.pack(&mut buf)?;
+        fileid.pack(&mut entry_buf)?;
 
         let name = crate::protocol::v3::nfs::filename3(dir_entry.name.clone());
-        name.pack(&mut buf)?;
+        name.pack(&mut entry_buf)?;
 
-        cookie_counter.pack(&mut buf)?;
+        cookie::entry_cookie(args.cookie, position).pack(&mut entry_buf)?;
+
+        if buf.len() + entry_buf.len() + 8 > args.count as usize {
+            break;
+        }
+        buf.extend_from_slice(&entry_buf);
+        emitted += 1;
     }
 
+    // Not even one entry fits: tell the client to use a bigger count instead
+    // of handing back an empty, non-eof list it would loop on
+    if emitted == 0 && !entries.is_empty() {
+        debug!("READDIR: count {} too small for a single entry", args.count);
+        let res_data = NfsMessage::create_readdir_error_response(
+            nfsstat3::NFS3ERR_TOOSMALL,
+            Some(&dir_attr),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
+    }
+    let eof = eof && emitted == entries.len();
+    ctx.state.readdir_throttle.record(client_ip, emitted as u64);
+
     // End of list: false = no more entries
     false.pack(&mut buf)?;
 
@@ -101,7 +163,7 @@ pub fn handle_readdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
 
     debug!(
         "READDIR OK: {} entries, eof={}, response size: {} bytes",
-        entries.len(),
+        emitted,
         eof,
         res_data.len()
     );
@@ -109,3 +171,244 @@ pub fn handle_readdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
     // Wrap in RPC reply
     RpcMessage::create_success_reply_with_data(xid, res_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::NfsConfig;
+    use crate::fsal::BackendConfig;
+    use crate::nfs::NfsState;
+    use crate::protocol::v3::nfs::{cookieverf3, fhandle3, READDIR3args, COOKIEVERFSIZE};
+    use std::fs;
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    fn readdir_args(dir: Vec<u8>, count: u32) -> Vec<u8> {
+        continuation_args(dir, 0, [0u8; COOKIEVERFSIZE as usize], count)
+    }
+
+    fn continuation_args(dir: Vec<u8>, cookie: u64, verf: [u8; 8], count: u32) -> Vec<u8> {
+        let args = READDIR3args {
+            dir: fhandle3(dir),
+            cookie,
+            cookieverf: cookieverf3(verf),
+            count,
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    #[test]
+    fn test_readdir_basic() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file1.txt"), "content1").unwrap();
+        fs::write(temp_dir.path().join("file2.txt"), "content2").unwrap();
+
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        let args_buf = readdir_args(fs.root_handle(), 4096);
+        let result = handle_readdir(12345, &args_buf, fs.as_ref(), &ctx);
+
+        assert!(result.is_ok(), "READDIR should succeed");
+    }
+
+    #[test]
+    fn test_readdir_zero_count() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("file1.txt"), "content1").unwrap();
+
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        let args_buf = readdir_args(fs.root_handle(), 0);
+        let reply = handle_readdir(12345, &args_buf, fs.as_ref(), &ctx).unwrap();
+
+        // RPC header (24) + status (4) + post_op_attr (4 + 84) + cookieverf (8)
+        // + end of list (4) + eof (4)
+        assert_eq!(reply.len(), 132, "Reply should contain no entries");
+
+        // status = NFS3_OK
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+        // end of list = FALSE, eof = FALSE
+        assert_eq!(&reply[124..132], &[0u8; 8]);
+    }
+
+    #[test]
+    fn test_readdir_throttled() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..5 {
+            fs::write(temp_dir.path().join(format!("file{}.txt", i)), "x").unwrap();
+        }
+
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        let state = NfsState::new(NfsConfig {
+            readdir_max_entries_per_client: Some(5),
+            readdir_window_secs: 60,
+            ..NfsConfig::default()
+        });
+        let greedy = NfsContext::new("10.0.0.1:700".parse().unwrap(), &state);
+        let other = NfsContext::new("10.0.0.2:700".parse().unwrap(), &state);
+
+        // First listing consumes the whole budget
+        let args_buf = readdir_args(fs.root_handle(), 4096);
+        let reply = handle_readdir(1, &args_buf, fs.as_ref(), &greedy).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4], "First listing should succeed");
+
+        // Further listings from the same client are throttled with JUKEBOX
+        let reply = handle_readdir(2, &args_buf, fs.as_ref(), &greedy).unwrap();
+        let status = u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]]);
+        assert_eq!(status, nfsstat3::NFS3ERR_JUKEBOX as u32);
+
+        // Other clients still list normally
+        let reply = handle_readdir(3, &args_buf, fs.as_ref(), &other).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+    }
+
+    #[test]
+    fn test_readdir_too_small() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("aaaa"), "a").unwrap();
+        fs::write(temp_dir.path().join("bbbb"), "b").unwrap();
+
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        // Fixed READDIR3resok overhead: status (4) + post_op_attr (4 + 84)
+        // + cookieverf (8) + end of list (4) + eof (4)
+        let overhead = 108;
+        // entry3 with a 4-byte name: discriminator (4) + fileid (8)
+        // + name (4 + 4) + cookie (8)
+        let entry = 28;
+
+        // One byte short of a single entry
+        let args_buf = readdir_args(fs.root_handle(), overhead + entry - 1);
+        let reply = handle_readdir(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_TOOSMALL as u32).to_be_bytes());
+
+        // Exactly one entry: returned, more remain so eof=false
+        let args_buf = readdir_args(fs.root_handle(), overhead + entry);
+        let reply = handle_readdir(2, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+        assert_eq!(reply.len(), 24 + (overhead + entry) as usize);
+        assert_eq!(&reply[reply.len() - 4..], &[0u8; 4], "eof should be FALSE");
+
+        // Room for both entries: eof=true
+        let args_buf = readdir_args(fs.root_handle(), overhead + 2 * entry);
+        let reply = handle_readdir(3, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(reply.len(), 24 + (overhead + 2 * entry) as usize);
+        assert_eq!(&reply[reply.len() - 4..], &[0u8, 0, 0, 1], "eof should be TRUE");
+    }
+
+    #[test]
+    fn test_readdir_stale_cookie_verifier() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("aaaa"), "a").unwrap();
+        fs::write(temp_dir.path().join("bbbb"), "b").unwrap();
+
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        // First page holds one entry; the verifier follows post_op_attr (28..116)
+        let args_buf = readdir_args(fs.root_handle(), 108 + 28);
+        let reply = handle_readdir(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+        let verf: [u8; 8] = reply[116..124].try_into().unwrap();
+
+        // Continuing with that verifier works while the directory is unchanged
+        let args_buf = continuation_args(fs.root_handle(), 1, verf, 4096);
+        let reply = handle_readdir(2, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+        assert_eq!(&reply[116..124], &verf);
+
+        // Once the directory changes the old verifier is stale
+        std::thread::sleep(std::time::Duration::from_millis(10));
+        fs::write(temp_dir.path().join("cccc"), "c").unwrap();
+        let reply = handle_readdir(3, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_BAD_COOKIE as u32).to_be_bytes());
+
+        // Restarting from cookie 0 hands out a fresh verifier
+        let args_buf = continuation_args(fs.root_handle(), 0, verf, 4096);
+        let reply = handle_readdir(4, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+        assert_ne!(&reply[116..124], &verf);
+    }
+
+    /// Names, last cookie, verifier and eof of a successful READDIR reply
+    fn parse_entries(reply: &[u8]) -> (Vec<String>, u64, [u8; 8], bool) {
+        let read_u32 = |at: usize| u32::from_be_bytes(reply[at..at + 4].try_into().unwrap());
+        let verf = reply[116..124].try_into().unwrap();
+
+        let mut names = Vec::new();
+        let mut last_cookie = 0;
+        let mut at = 124;
+        while read_u32(at) == 1 {
+            let name_len = read_u32(at + 12) as usize;
+            let name = &reply[at + 16..at + 16 + name_len];
+            names.push(String::from_utf8(name.to_vec()).unwrap());
+            at += 16 + name_len.div_ceil(4) * 4;
+            last_cookie = u64::from_be_bytes(reply[at..at + 8].try_into().unwrap());
+            at += 8;
+        }
+        (names, last_cookie, verf, read_u32(at + 4) == 1)
+    }
+
+    #[test]
+    fn test_readdir_pages_within_count() {
+        let temp_dir = TempDir::new().unwrap();
+        for i in 0..50 {
+            fs::write(temp_dir.path().join(format!("file{:02}", i)), "x").unwrap();
+        }
+
+        let config = BackendConfig::local(temp_dir.path());
+        let fs = config.create_filesystem().unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        // Room for the fixed overhead (108) and a handful of 32-byte entries
+        let count = 512;
+        let mut seen = Vec::new();
+        let mut cookie = 0;
+        let mut verf = [0u8; COOKIEVERFSIZE as usize];
+        let mut round_trips = 0;
+        loop {
+            let args_buf = continuation_args(fs.root_handle(), cookie, verf, count);
+            let reply = handle_readdir(round_trips, &args_buf, fs.as_ref(), &ctx).unwrap();
+            assert_eq!(&reply[24..28], &[0u8; 4]);
+            assert!(reply.len() - 24 <= count as usize, "Reply exceeds count");
+            round_trips += 1;
+
+            let (names, last_cookie, next_verf, eof) = parse_entries(&reply);
+            assert!(!names.is_empty(), "Every page should make progress");
+            assert_eq!(last_cookie, cookie + names.len() as u64);
+            seen.extend(names);
+            cookie = last_cookie;
+            verf = next_verf;
+            if eof {
+                break;
+            }
+        }
+        assert!(round_trips > 1, "Listing should need several READDIR calls");
+
+        // Every entry exactly once across the pages
+        seen.sort();
+        let expected: Vec<String> = (0..50).map(|i| format!("file{:02}", i)).collect();
+        assert_eq!(seen, expected);
+    }
+}
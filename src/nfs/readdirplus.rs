@@ -8,9 +8,12 @@ use bytes::BytesMut;
 use tracing::{debug, warn};
 
 use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{cookieverf3, nfsstat3, NfsMessage, COOKIEVERFSIZE};
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
+use super::cookie;
+
 /// Handle NFS READDIRPLUS request
 ///
 /// READDIRPLUS is an enhanced version of READDIR that returns:
@@ -20,10 +23,14 @@ use crate::protocol::v3::rpc::RpcMessage;
 ///
 /// This reduces round-trips compared to READDIR + multiple LOOKUP/GETATTR.
 ///
+/// Two budgets bound the reply: `dircount` caps the directory information
+/// (fileid, name and cookie of each entry) and `maxcount` the whole reply.
+///
 /// # Arguments
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized READDIRPLUS3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (enumeration budget, owner/group override)
 ///
 /// # Returns
 /// Serialized RPC reply with READDIRPLUS3res
@@ -31,6 +38,7 @@ pub fn handle_readdirplus(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS READDIRPLUS: xid={}", xid);
 
@@ -45,24 +53,59 @@ pub fn handle_readdirplus(
         args.maxcount
     );
 
+    // Throttle clients that exhausted their enumeration budget
+    // JUKEBOX tells the client to back off and retry later
+    let client_ip = ctx.client_addr.ip();
+    if !ctx.state.readdir_throttle.check(client_ip) {
+        warn!("READDIRPLUS throttled: client {} exceeded its entry budget", client_ip);
+        let res_data = NfsMessage::create_readdirplus_error_response(
+            nfsstat3::NFS3ERR_JUKEBOX,
+            filesystem.getattr(&args.dir.0).ok().map(|attrs| ctx.fattr3(&attrs)).as_ref(),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
+    }
+
     // Get directory attributes
-    let dir_attr = match filesystem.getattr(&args.dir.0) {
-        Ok(attr) => NfsMessage::fsal_to_fattr3(&attr),
+    let attrs = match filesystem.getattr(&args.dir.0) {
+        Ok(attr) => attr,
         Err(e) => {
             warn!("READDIRPLUS failed: getattr error: {}", e);
-            let res_data = NfsMessage::create_readdirplus_error_response(nfsstat3::NFS3ERR_IO)?;
+            let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+            let res_data = NfsMessage::create_readdirplus_error_response(status, None)?;
             return RpcMessage::create_success_reply_with_data(xid, res_data);
         }
     };
+    let dir_attr = ctx.fattr3(&attrs);
+
+    // Cookies from before a directory change are stale (see cookie.rs)
+    if !cookie::verifier_matches(args.cookie, &args.cookieverf, &attrs) {
+        debug!("READDIRPLUS: stale cookie verifier for cookie {}", args.cookie);
+        let res_data = NfsMessage::create_readdirplus_error_response(
+            nfsstat3::NFS3ERR_BAD_COOKIE,
+            Some(&dir_attr),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
+    }
 
     // Read directory entries
     // Use dircount as the count parameter (RFC 1813 says dircount is for entry names)
-    let (entries, eof) = match filesystem.readdir(&args.dir.0, args.cookie, args.dircount) {
-        Ok(result) => result,
-        Err(e) => {
-            warn!("READDIRPLUS failed: {}", e);
-            let res_data = NfsMessage::create_readdirplus_error_response(nfsstat3::NFS3ERR_IO)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+    // A zero budget leaves no room for entries: reply with an empty list and
+    // eof=false so the client can retry with a real budget
+    let (entries, eof) = if args.dircount == 0 || args.maxcount == 0 {
+        debug!("  dircount/maxcount is 0, returning no entries");
+        (Vec::new(), false)
+    } else {
+        match filesystem.readdir(&args.dir.0, args.cookie, args.dircount) {
+            Ok(result) => result,
+            Err(e) => {
+                warn!("READDIRPLUS failed: {}", e);
+                let status = handle_error_to_nfsstat(&e).unwrap_or(nfsstat3::NFS3ERR_IO);
+                let res_data = NfsMessage::create_readdirplus_error_response(
+                    status,
+                    Some(&dir_attr),
+                )?;
+                return RpcMessage::create_success_reply_with_data(xid, res_data);
+            }
         }
     };
 
@@ -81,29 +124,39 @@ pub fn handle_readdirplus(
     dir_attr.pack(&mut buf)?;
 
     // 3. cookieverf
-    let cookieverf = cookieverf3([0u8; COOKIEVERFSIZE as usize]);
-    cookieverf.pack(&mut buf)?;
+    cookie::cookie_verifier(&attrs).pack(&mut buf)?;
 
     // 4. dirlistplus3 (entry list with attributes and handles)
     // Serialize each entry with boolean discriminator pattern:
     // For each entry: true + entryplus3 data
     // entryplus3 = fileid + name + cookie + post_op_attr + post_op_fh3
     // End of list: false
-    let mut cookie_counter = args.cookie;
-    for dir_entry in entries.iter() {
-        cookie_counter += 1;
+    // Entries are only emitted while the reply stays within maxcount,
+    // leaving room for the list terminator and eof (8 bytes), and their
+    // directory information (fileid + name + cookie) stays within dircount
+    let mut dir_info_len = 0;
+    let mut emitted = 0;
+    for (position, dir_entry) in entries.iter().enumerate() {
+        let mut entry_buf = Vec::new();
 
         // Boolean discriminator: true = entry follows
-        true.pack(&mut buf)?;
+        true.pack(&mut entry_buf)?;
 
         // Serialize entryplus3 fields
         let fileid = dir_entry.fileid;
-        fileid.pack(&mut buf)?;
+        fileid.pack(&mut entry_buf)?;
 
         let name = crate::protocol::v3::nfs::filename3(dir_entry.name.clone());
-        name.pack(&mut buf)?;
+        name.pack(&mut entry_buf)?;
 
-        cookie_counter.pack(&mut buf)?;
+        cookie::entry_cookie(args.cookie, position).pack(&mut entry_buf)?;
+
+        // Directory information counted against dircount: everything so far
+        // except the discriminator
+        let entry_dir_info_len = entry_buf.len() - 4;
+        if dir_info_len + entry_dir_info_len > args.dircount as usize {
+            break;
+        }
 
         // post_op_attr: Get attributes for this entry
         // We need to lookup the file handle first
@@ -113,31 +166,54 @@ pub fn handle_readdirplus(
                 match filesystem.getattr(&entry_handle) {
                     Ok(entry_attr) => {
                         // post_op_attr: true + fattr3
-                        true.pack(&mut buf)?;
-                        let fattr = NfsMessage::fsal_to_fattr3(&entry_attr);
-                        fattr.pack(&mut buf)?;
+                        true.pack(&mut entry_buf)?;
+                        let fattr = ctx.fattr3(&entry_attr);
+                        fattr.pack(&mut entry_buf)?;
 
                         // post_op_fh3: true + fhandle3
-                        true.pack(&mut buf)?;
+                        true.pack(&mut entry_buf)?;
                         let fhandle = crate::protocol::v3::nfs::fhandle3(entry_handle);
-                        fhandle.pack(&mut buf)?;
+                        fhandle.pack(&mut entry_buf)?;
                     }
                     Err(e) => {
                         // Failed to get attributes - return empty post_op_attr and post_op_fh3
                         warn!("READDIRPLUS: failed to get attributes for {}: {}", dir_entry.name, e);
-                        false.pack(&mut buf)?; // post_op_attr: no attributes
-                        false.pack(&mut buf)?; // post_op_fh3: no handle
+                        false.pack(&mut entry_buf)?; // post_op_attr: no attributes
+                        false.pack(&mut entry_buf)?; // post_op_fh3: no handle
                     }
                 }
             }
             Err(e) => {
                 // Failed to lookup - return empty post_op_attr and post_op_fh3
                 warn!("READDIRPLUS: failed to lookup {}: {}", dir_entry.name, e);
-                false.pack(&mut buf)?; // post_op_attr: no attributes
-                false.pack(&mut buf)?; // post_op_fh3: no handle
+                false.pack(&mut entry_buf)?; // post_op_attr: no attributes
+                false.pack(&mut entry_buf)?; // post_op_fh3: no handle
             }
         }
+
+        if buf.len() + entry_buf.len() + 8 > args.maxcount as usize {
+            break;
+        }
+        buf.extend_from_slice(&entry_buf);
+        dir_info_len += entry_dir_info_len;
+        emitted += 1;
+    }
+
+    // Not even one entry fits: tell the client to use bigger budgets
+    // instead of handing back an empty, non-eof list it would loop on
+    if emitted == 0 && !entries.is_empty() {
+        debug!(
+            "READDIRPLUS: dircount {} / maxcount {} too small for a single entry",
+            args.dircount, args.maxcount
+        );
+        let res_data = NfsMessage::create_readdirplus_error_response(
+            nfsstat3::NFS3ERR_TOOSMALL,
+            Some(&dir_attr),
+        )?;
+        return RpcMessage::create_success_reply_with_data(xid, res_data);
     }
+    let eof = eof && emitted == entries.len();
+    ctx.state.readdir_throttle.record(client_ip, emitted as u64);
 
     // End of list: false = no more entries
     false.pack(&mut buf)?;
@@ -149,7 +225,7 @@ pub fn handle_readdirplus(
 
     debug!(
         "READDIRPLUS OK: {} entries, eof={}, response size: {} bytes",
-        entries.len(),
+        emitted,
         eof,
         res_data.len()
     );
@@ -161,7 +237,10 @@ pub fn handle_readdirplus(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::NfsConfig;
     use crate::fsal::local::LocalFilesystem;
+    use crate::nfs::NfsState;
+    use crate::protocol::v3::nfs::{cookieverf3, COOKIEVERFSIZE};
     use std::fs;
     use std::path::PathBuf;
 
@@ -205,7 +284,9 @@ mod tests {
         32768u32.pack(&mut args_buf).unwrap();
 
         // Call handler
-        let result = handle_readdirplus(1, &args_buf, &fs);
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let result = handle_readdirplus(1, &args_buf, &fs, &ctx);
         assert!(result.is_ok());
 
         let response = result.unwrap();
@@ -214,4 +295,221 @@ mod tests {
         // Cleanup
         fs::remove_dir_all(&test_dir).unwrap();
     }
+
+    #[test]
+    fn test_readdirplus_zero_maxcount() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_readdirplus_zero");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        fs::write(test_dir.join("file1.txt"), "content1").unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_readdirplus_zero".to_string()).unwrap();
+        let root_handle = fs.root_handle();
+
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(root_handle)
+            .pack(&mut args_buf)
+            .unwrap();
+        0u64.pack(&mut args_buf).unwrap(); // cookie
+        cookieverf3([0u8; COOKIEVERFSIZE as usize])
+            .pack(&mut args_buf)
+            .unwrap();
+        0u32.pack(&mut args_buf).unwrap(); // dircount
+        0u32.pack(&mut args_buf).unwrap(); // maxcount
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let response = handle_readdirplus(1, &args_buf, &fs, &ctx).unwrap();
+
+        // RPC header (24) + status (4) + post_op_attr (4 + 84) + cookieverf (8)
+        // + end of list (4) + eof (4)
+        assert_eq!(response.len(), 132, "Reply should contain no entries");
+        assert_eq!(&response[24..28], &[0u8; 4]);
+        assert_eq!(&response[124..132], &[0u8; 8]);
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_readdirplus_owner_override() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_readdirplus_owner");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        fs::write(test_dir.join("a"), "content").unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_readdirplus_owner".to_string()).unwrap();
+
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs.root_handle())
+            .pack(&mut args_buf)
+            .unwrap();
+        0u64.pack(&mut args_buf).unwrap(); // cookie
+        cookieverf3([0u8; COOKIEVERFSIZE as usize])
+            .pack(&mut args_buf)
+            .unwrap();
+        8192u32.pack(&mut args_buf).unwrap(); // dircount
+        32768u32.pack(&mut args_buf).unwrap(); // maxcount
+
+        let state = NfsState::new(NfsConfig {
+            override_uid: Some(4242),
+            override_gid: Some(4343),
+            ..NfsConfig::default()
+        });
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let response = handle_readdirplus(1, &args_buf, &fs, &ctx).unwrap();
+
+        let read_u32 = |at: usize| {
+            u32::from_be_bytes([
+                response[at],
+                response[at + 1],
+                response[at + 2],
+                response[at + 3],
+            ])
+        };
+
+        // Directory attributes: RPC header (24) + status (4) + follows (4) + 12
+        assert_eq!(read_u32(44), 4242);
+        assert_eq!(read_u32(48), 4343);
+
+        // First entry starts after cookieverf (116..124): follows (4) + fileid (8)
+        // + name "a" (8) + cookie (8) + attributes_follow (4) + 12
+        assert_eq!(read_u32(124), 1, "Listing should contain an entry");
+        assert_eq!(read_u32(168), 4242);
+        assert_eq!(read_u32(172), 4343);
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_readdirplus_too_small() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_readdirplus_toosmall");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        fs::write(test_dir.join("file1.txt"), "content1").unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_readdirplus_toosmall".to_string()).unwrap();
+
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs.root_handle())
+            .pack(&mut args_buf)
+            .unwrap();
+        0u64.pack(&mut args_buf).unwrap(); // cookie
+        cookieverf3([0u8; COOKIEVERFSIZE as usize])
+            .pack(&mut args_buf)
+            .unwrap();
+        8192u32.pack(&mut args_buf).unwrap(); // dircount
+        // maxcount: room for the fixed reply overhead (108) but not for an
+        // entry with attributes and a handle
+        128u32.pack(&mut args_buf).unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+        let response = handle_readdirplus(1, &args_buf, &fs, &ctx).unwrap();
+
+        assert_eq!(
+            &response[24..28],
+            &(nfsstat3::NFS3ERR_TOOSMALL as u32).to_be_bytes()
+        );
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    /// Build READDIRPLUS3args for the root of `fs`
+    fn readdirplus_args(
+        fs: &LocalFilesystem,
+        cookie: u64,
+        verf: [u8; 8],
+        dircount: u32,
+        maxcount: u32,
+    ) -> Vec<u8> {
+        use xdr_codec::Pack;
+
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs.root_handle())
+            .pack(&mut args_buf)
+            .unwrap();
+        cookie.pack(&mut args_buf).unwrap();
+        cookieverf3(verf).pack(&mut args_buf).unwrap();
+        dircount.pack(&mut args_buf).unwrap();
+        maxcount.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    /// Walk a READDIRPLUS reply, returning (names, last cookie, eof)
+    fn parse_entries(response: &[u8]) -> (Vec<String>, u64, bool) {
+        let read_u32 = |at: usize| u32::from_be_bytes(response[at..at + 4].try_into().unwrap());
+        let read_u64 = |at: usize| u64::from_be_bytes(response[at..at + 8].try_into().unwrap());
+
+        // RPC header (24) + status (4) + post_op_attr (4 + 84) + cookieverf (8)
+        let mut at = 124;
+        let mut names = Vec::new();
+        let mut last_cookie = 0;
+        while read_u32(at) == 1 {
+            at += 4 + 8; // discriminator + fileid
+            let name_len = read_u32(at) as usize;
+            names.push(String::from_utf8(response[at + 4..at + 4 + name_len].to_vec()).unwrap());
+            at += 4 + name_len.div_ceil(4) * 4;
+            last_cookie = read_u64(at);
+            at += 8;
+            if read_u32(at) == 1 {
+                at += 4 + 84; // post_op_attr
+            } else {
+                at += 4;
+            }
+            if read_u32(at) == 1 {
+                let handle_len = read_u32(at + 4) as usize;
+                at += 8 + handle_len.div_ceil(4) * 4; // post_op_fh3
+            } else {
+                at += 4;
+            }
+        }
+        (names, last_cookie, read_u32(at + 4) == 1)
+    }
+
+    #[test]
+    fn test_readdirplus_dircount_budget() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_readdirplus_dircount");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        for i in 0..10 {
+            fs::write(test_dir.join(format!("f{}", i)), "content").unwrap();
+        }
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_readdirplus_dircount".to_string()).unwrap();
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state);
+
+        // Each two-letter name costs fileid (8) + name (4 + 4) + cookie (8)
+        // of directory information, so dircount 72 fits three entries even
+        // though maxcount has room for many more
+        let mut seen = Vec::new();
+        let mut cookie = 0;
+        let mut verf = [0u8; COOKIEVERFSIZE as usize];
+        loop {
+            let args_buf = readdirplus_args(&fs, cookie, verf, 72, 32768);
+            let response = handle_readdirplus(1, &args_buf, &fs, &ctx).unwrap();
+            assert_eq!(&response[24..28], &[0u8; 4]);
+            // Continuations present the verifier (after post_op_attr) back
+            verf = response[116..124].try_into().unwrap();
+
+            let (names, last_cookie, eof) = parse_entries(&response);
+            assert!(!names.is_empty() && names.len() <= 3, "Got {} entries", names.len());
+            assert_eq!(last_cookie, cookie + names.len() as u64);
+            seen.extend(names);
+            cookie = last_cookie;
+            if eof {
+                break;
+            }
+        }
+
+        // Every entry exactly once across the pages
+        seen.sort();
+        let expected: Vec<String> = (0..10).map(|i| format!("f{}", i)).collect();
+        assert_eq!(seen, expected);
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
 }
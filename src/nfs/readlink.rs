@@ -7,19 +7,30 @@ use bytes::BytesMut;
 use tracing::{debug, warn};
 
 use crate::fsal::Filesystem;
+use crate::nfs::{handle_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle READLINK procedure
 ///
+/// Returns the target of a symbolic link. A handle that is not a symlink
+/// gets NFS3ERR_INVAL; post-op attributes are included whenever they are
+/// available, on success and failure alike.
+///
 /// # Arguments
 /// * `xid` - RPC transaction ID
 /// * `args_data` - Serialized READLINK3args
 /// * `filesystem` - Filesystem implementation
+/// * `ctx` - Per-call context (owner/group override)
 ///
 /// # Returns
 /// Serialized READLINK3res response
-pub fn handle_readlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_readlink(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS READLINK: xid={}", xid);
 
     // Parse arguments
@@ -37,7 +48,7 @@ pub fn handle_readlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem)
 
             // Get symlink attributes after operation
             let symlink_attr_after = match filesystem.getattr(&args.symlink.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get symlink attributes after readlink: {}", e);
                     None
@@ -53,7 +64,7 @@ pub fn handle_readlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem)
             let status = map_error_to_status(&e);
 
             // Get symlink attributes for failure case
-            let symlink_attr = symlink_attr_before.map(|attr| NfsMessage::fsal_to_fattr3(&attr));
+            let symlink_attr = symlink_attr_before.map(|attr| ctx.fattr3(&attr));
 
             create_readlink_response(xid, status, symlink_attr, None)
         }
@@ -62,9 +73,16 @@ pub fn handle_readlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem)
 
 /// Map filesystem error to NFS status code
 fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
+    if let Some(status) = handle_error_to_nfsstat(error) {
+        return status;
+    }
     let error_str = format!("{:?}", error);
 
     // Check for specific error patterns
+    if error_str.contains("Stale file handle") {
+        return nfsstat3::NFS3ERR_STALE;
+    }
+
     if error_str.contains("No such file") || error_str.contains("not found") {
         return nfsstat3::NFS3ERR_NOENT;
     }
@@ -146,3 +164,83 @@ fn create_readlink_response(
     let res_data = BytesMut::from(&buf[..]);
     RpcMessage::create_success_reply_with_data(xid, res_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fsal::BackendConfig;
+    use crate::nfs::NfsState;
+    use crate::protocol::v3::nfs::{fhandle3, READLINK3args};
+    use std::fs;
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    fn readlink_args(handle: Vec<u8>) -> Vec<u8> {
+        let mut args_buf = Vec::new();
+        READLINK3args {
+            symlink: fhandle3(handle),
+        }
+        .pack(&mut args_buf)
+        .unwrap();
+        args_buf
+    }
+
+    fn readlink(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        let state = NfsState::default();
+        handle_readlink(xid, args_buf, fs, &NfsContext::new("127.0.0.1:700".parse().unwrap(), &state))
+    }
+
+    fn reply_status(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_readlink_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("target.txt"), b"data").unwrap();
+        std::os::unix::fs::symlink("target.txt", temp_dir.path().join("link")).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let handle = fs.lookup(&fs.root_handle(), "link").unwrap();
+
+        let reply = readlink(1, &readlink_args(handle), fs.as_ref()).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+
+        // post_op_attr (4 + 84), then the nfspath3 (length + data + padding)
+        assert_eq!(&reply[28..32], &[0, 0, 0, 1]);
+        assert_eq!(&reply[116..120], &10u32.to_be_bytes());
+        assert_eq!(&reply[120..130], b"target.txt");
+        assert_eq!(reply.len(), 132);
+    }
+
+    #[test]
+    fn test_readlink_not_a_symlink() {
+        let temp_dir = TempDir::new().unwrap();
+        fs::write(temp_dir.path().join("plain.txt"), b"data").unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let handle = fs.lookup(&fs.root_handle(), "plain.txt").unwrap();
+
+        let reply = readlink(1, &readlink_args(handle), fs.as_ref()).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_INVAL as u32);
+        // Failure still carries the object's attributes
+        assert_eq!(&reply[28..32], &[0, 0, 0, 1]);
+        assert_eq!(reply.len(), 116);
+    }
+
+    #[test]
+    fn test_readlink_stale_handle() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+
+        let reply =
+            readlink(1, &readlink_args(vec![0xDE, 0xAD, 0xBE, 0xEF]), fs.as_ref())
+                .unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_STALE as u32);
+        assert_eq!(&reply[28..32], &[0, 0, 0, 0]);
+    }
+}
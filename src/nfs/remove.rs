@@ -6,23 +6,32 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
+use crate::fsal::{FileAttributes, Filesystem};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS REMOVE request
 ///
 /// Removes a file from a directory. This operation is atomic - either the file
-/// is removed successfully or the directory is unchanged.
+/// is removed successfully or the directory is unchanged. Directories are
+/// rejected with NFS3ERR_ISDIR (clients must use RMDIR), and callers that
+/// may not write the directory with NFS3ERR_ACCES.
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized REMOVE3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context with the caller's credentials
 ///
 /// # Returns
 /// Serialized RPC reply with REMOVE3res
-pub fn handle_remove(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_remove(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS REMOVE: xid={}", xid);
 
     // Parse arguments
@@ -37,6 +46,16 @@ pub fn handle_remove(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
     // Get directory attributes before removal (for wcc_data)
     let dir_before = filesystem.getattr(&args.dir.0).ok();
 
+    let credentials = &ctx.credentials;
+    if dir_before
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("REMOVE denied for uid {}", credentials.uid);
+        let dir_attr = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+        return create_remove_response(xid, nfsstat3::NFS3ERR_ACCES, dir_before.as_ref(), dir_attr);
+    }
+
     // Perform remove operation
     match filesystem.remove(&args.dir.0, &args.name.0) {
         Ok(()) => {
@@ -44,27 +63,32 @@ pub fn handle_remove(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 
             // Get directory attributes after removal
             let dir_after = match filesystem.getattr(&args.dir.0) {
-                Ok(attr) => NfsMessage::fsal_to_fattr3(&attr),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get dir attributes after remove: {}", e);
                     // Continue anyway, removal succeeded
-                    return create_remove_response(xid, nfsstat3::NFS3_OK, None);
+                    None
                 }
             };
 
-            create_remove_response(xid, nfsstat3::NFS3_OK, Some(dir_after))
+            create_remove_response(xid, nfsstat3::NFS3_OK, dir_before.as_ref(), dir_after)
         }
         Err(e) => {
             warn!("REMOVE failed for '{}': {}", args.name.0, e);
 
             // Determine appropriate error code based on error message and IO error kind
             let error_string = e.to_string();
-            let status = if error_string.contains("not found") || error_string.contains("No such") {
+            // Directories are rejected with ISDIR: clients must use RMDIR
+            let status = if let Some(status) = io_error_to_nfsstat(&e) {
+                status
+            } else if error_string.contains("Stale file handle") {
+                nfsstat3::NFS3ERR_STALE
+            } else if error_string.contains("Is a directory") {
+                nfsstat3::NFS3ERR_ISDIR
+            } else if error_string.contains("not found") || error_string.contains("No such") {
                 nfsstat3::NFS3ERR_NOENT
             } else if error_string.contains("permission") || error_string.contains("Permission") {
                 nfsstat3::NFS3ERR_ACCES
-            } else if error_string.contains("directory") || error_string.contains("Is a directory") {
-                nfsstat3::NFS3ERR_ISDIR
             } else {
                 // Try to get std::io::Error from anyhow::Error
                 if let Some(io_err) = e.downcast_ref::<std::io::Error>() {
@@ -79,9 +103,9 @@ pub fn handle_remove(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
             };
 
             // Try to get current directory attributes for wcc_data
-            let dir_after = filesystem.getattr(&args.dir.0).ok().map(|attr| NfsMessage::fsal_to_fattr3(&attr));
+            let dir_after = filesystem.getattr(&args.dir.0).ok().map(|attr| ctx.fattr3(&attr));
 
-            create_remove_response(xid, status, dir_after)
+            create_remove_response(xid, status, dir_before.as_ref(), dir_after)
         }
     }
 }
@@ -90,6 +114,7 @@ pub fn handle_remove(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 fn create_remove_response(
     xid: u32,
     status: nfsstat3,
+    dir_before: Option<&FileAttributes>,
     dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
@@ -100,21 +125,7 @@ fn create_remove_response(
     (status as i32).pack(&mut buf)?;
 
     // 2. wcc_data (dir_wcc)
-    // wcc_data = pre_op_attr + post_op_attr
-
-    // 2.1 pre_op_attr (before the operation) - we don't track this, so send FALSE
-    false.pack(&mut buf)?; // pre_op_attr: attributes_follow = FALSE
-
-    // 2.2 post_op_attr (after the operation)
-    match dir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?; // post_op_attr: attributes_follow = TRUE
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?; // post_op_attr: attributes_follow = FALSE
-        }
-    }
+    buf.extend(build_wcc_data(dir_before.map(NfsMessage::fsal_to_wcc_attr), dir_attr)?);
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -130,10 +141,19 @@ fn create_remove_response(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nfs::{Credentials, NfsState};
     use crate::fsal::local::LocalFilesystem;
     use std::fs;
     use std::path::PathBuf;
 
+
+    fn remove(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        handle_remove(xid, args_buf, fs, &ctx)
+    }
+
     #[test]
     fn test_remove_file() {
         // Create test directory
@@ -167,7 +187,7 @@ mod tests {
         assert!(test_file.exists());
 
         // Call REMOVE
-        let result = handle_remove(12345, &args_buf, &fs);
+        let result = remove(12345, &args_buf, &fs);
         assert!(result.is_ok(), "REMOVE should succeed");
 
         // Verify file was removed
@@ -203,12 +223,39 @@ mod tests {
         filename.pack(&mut args_buf).unwrap();
 
         // Call REMOVE - should fail with NOENT
-        let result = handle_remove(12345, &args_buf, &fs);
+        let result = remove(12345, &args_buf, &fs);
         assert!(result.is_ok(), "REMOVE should return response (not crash)");
 
-        // TODO: Parse response and verify status is NFS3ERR_NOENT
+        let reply = result.unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_NOENT as u32).to_be_bytes());
+        // wcc_data is still populated for the directory
+        assert_eq!(&reply[28..32], &[0u8, 0, 0, 1], "pre_op_attr should follow");
 
         // Cleanup
         fs::remove_dir_all(&test_dir).unwrap();
     }
+
+    #[test]
+    fn test_remove_directory() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_remove_isdir");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("subdir")).unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_remove_isdir".to_string()).unwrap();
+
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs.root_handle())
+            .pack(&mut args_buf)
+            .unwrap();
+        crate::protocol::v3::nfs::filename3("subdir".to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+
+        let reply = remove(12345, &args_buf, &fs).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_ISDIR as u32).to_be_bytes());
+        assert!(test_dir.join("subdir").is_dir(), "Directory must not be removed");
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
 }
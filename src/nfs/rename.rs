@@ -6,22 +6,38 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
+use crate::fsal::{FileAttributes, Filesystem};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS RENAME request
 ///
-/// Renames or moves a file/directory from one location to another.
+/// Renames or moves a file/directory from one location to another, possibly
+/// across directories.
+///
+/// Follows POSIX rules when the target exists: a file replaces an existing
+/// file, a directory may replace an empty directory (NFS3ERR_NOTEMPTY if it
+/// is not empty) but not a file (NFS3ERR_NOTDIR), and a file may not replace
+/// a directory (NFS3ERR_ISDIR).
+///
+/// The reply carries full wcc_data (pre and post attributes) for both the
+/// source and target directories.
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized RENAME3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context with the caller's credentials
 ///
 /// # Returns
 /// Serialized RPC reply with RENAME3res
-pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_rename(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS RENAME: xid={}", xid);
 
     // Parse arguments
@@ -44,11 +60,44 @@ pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
     // Get target directory attributes before operation (for wcc_data)
     // Only if different from source directory
     let todir_before = if args.from_dir.0 == args.to_dir.0 {
-        None  // Same directory, use fromdir_before
+        fromdir_before.clone() // Same directory
     } else {
         filesystem.getattr(&args.to_dir.0).ok()
     };
 
+    // The call was routed to the export that issued the source directory
+    // handle; a target directory handle it did not issue lives in another
+    // export
+    if filesystem.owns_handle(&args.from_dir.0) && !filesystem.owns_handle(&args.to_dir.0) {
+        debug!("RENAME across exports refused");
+        return create_rename_response(
+            xid,
+            nfsstat3::NFS3ERR_XDEV,
+            fromdir_before.as_ref(),
+            fromdir_before.as_ref().map(|attr| ctx.fattr3(attr)),
+            None,
+            None,
+        );
+    }
+
+    // Both directories change, so the caller must be able to write each
+    let credentials = &ctx.credentials;
+    if [&fromdir_before, &todir_before]
+        .into_iter()
+        .flatten()
+        .any(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("RENAME denied for uid {}", credentials.uid);
+        return create_rename_response(
+            xid,
+            nfsstat3::NFS3ERR_ACCES,
+            fromdir_before.as_ref(),
+            fromdir_before.as_ref().map(|attr| ctx.fattr3(attr)),
+            todir_before.as_ref(),
+            todir_before.as_ref().map(|attr| ctx.fattr3(attr)),
+        );
+    }
+
     // Perform rename operation
     match filesystem.rename(
         &args.from_dir.0,
@@ -64,7 +113,7 @@ pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 
             // Get source directory attributes after operation
             let fromdir_after = match filesystem.getattr(&args.from_dir.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get source dir attributes after rename: {}", e);
                     None
@@ -76,7 +125,7 @@ pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
                 fromdir_after.clone()  // Same directory
             } else {
                 match filesystem.getattr(&args.to_dir.0) {
-                    Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                    Ok(attr) => Some(ctx.fattr3(&attr)),
                     Err(e) => {
                         warn!("Failed to get target dir attributes after rename: {}", e);
                         None
@@ -84,14 +133,25 @@ pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
                 }
             };
 
-            create_rename_response(xid, nfsstat3::NFS3_OK, fromdir_after, todir_after)
+            create_rename_response(
+                xid,
+                nfsstat3::NFS3_OK,
+                fromdir_before.as_ref(),
+                fromdir_after,
+                todir_before.as_ref(),
+                todir_after,
+            )
         }
         Err(e) => {
             warn!("RENAME failed for '{}': {}", args.from_name.0, e);
 
             // Determine appropriate error code
             let error_string = e.to_string();
-            let status = if error_string.contains("not found") || error_string.contains("No such") {
+            let status = if let Some(status) = io_error_to_nfsstat(&e) {
+                status
+            } else if error_string.contains("Stale file handle") {
+                nfsstat3::NFS3ERR_STALE
+            } else if error_string.contains("not found") || error_string.contains("No such") {
                 nfsstat3::NFS3ERR_NOENT
             } else if error_string.contains("already exists") || error_string.contains("File exists") {
                 nfsstat3::NFS3ERR_EXIST
@@ -120,14 +180,21 @@ pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
             };
 
             // Try to get current directory attributes for wcc_data
-            let fromdir_after = filesystem.getattr(&args.from_dir.0).ok().map(|attr| NfsMessage::fsal_to_fattr3(&attr));
+            let fromdir_after = filesystem.getattr(&args.from_dir.0).ok().map(|attr| ctx.fattr3(&attr));
             let todir_after = if args.from_dir.0 == args.to_dir.0 {
                 fromdir_after.clone()
             } else {
-                filesystem.getattr(&args.to_dir.0).ok().map(|attr| NfsMessage::fsal_to_fattr3(&attr))
+                filesystem.getattr(&args.to_dir.0).ok().map(|attr| ctx.fattr3(&attr))
             };
 
-            create_rename_response(xid, status, fromdir_after, todir_after)
+            create_rename_response(
+                xid,
+                status,
+                fromdir_before.as_ref(),
+                fromdir_after,
+                todir_before.as_ref(),
+                todir_after,
+            )
         }
     }
 }
@@ -136,7 +203,9 @@ pub fn handle_rename(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 fn create_rename_response(
     xid: u32,
     status: nfsstat3,
+    fromdir_before: Option<&FileAttributes>,
     fromdir_attr: Option<crate::protocol::v3::nfs::fattr3>,
+    todir_before: Option<&FileAttributes>,
     todir_attr: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
@@ -147,38 +216,10 @@ fn create_rename_response(
     (status as i32).pack(&mut buf)?;
 
     // 2. wcc_data for source directory (fromdir_wcc)
-    // wcc_data = pre_op_attr + post_op_attr
-
-    // 2.1 pre_op_attr (before the operation) - we don't track this, so send FALSE
-    false.pack(&mut buf)?;
-
-    // 2.2 post_op_attr (after the operation)
-    match &fromdir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    buf.extend(build_wcc_data(fromdir_before.map(NfsMessage::fsal_to_wcc_attr), fromdir_attr)?);
 
     // 3. wcc_data for target directory (todir_wcc)
-    // wcc_data = pre_op_attr + post_op_attr
-
-    // 3.1 pre_op_attr (before the operation) - we don't track this, so send FALSE
-    false.pack(&mut buf)?;
-
-    // 3.2 post_op_attr (after the operation)
-    match &todir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    buf.extend(build_wcc_data(todir_before.map(NfsMessage::fsal_to_wcc_attr), todir_attr)?);
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -194,11 +235,20 @@ fn create_rename_response(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nfs::{Credentials, NfsState};
     use crate::fsal::local::LocalFilesystem;
     use std::fs;
     use std::io::Write;
     use std::path::PathBuf;
 
+
+    fn rename(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        handle_rename(xid, args_buf, fs, &ctx)
+    }
+
     #[test]
     fn test_rename_file() {
         // Create test directory
@@ -236,7 +286,7 @@ mod tests {
         to_name.pack(&mut args_buf).unwrap();
 
         // Call RENAME
-        let result = handle_rename(12345, &args_buf, &fs);
+        let result = rename(12345, &args_buf, &fs);
         assert!(result.is_ok(), "RENAME should succeed");
 
         // Verify file was renamed
@@ -279,7 +329,7 @@ mod tests {
         to_name.pack(&mut args_buf).unwrap();
 
         // Call RENAME
-        let result = handle_rename(12346, &args_buf, &fs);
+        let result = rename(12346, &args_buf, &fs);
         assert!(result.is_ok(), "RENAME should succeed");
 
         // Verify directory was renamed
@@ -289,4 +339,123 @@ mod tests {
         // Cleanup
         fs::remove_dir_all(&test_dir).unwrap();
     }
+
+    /// Rename `from` to `to` within the root of `test_dir`, returning the status
+    fn rename_status(test_dir: &str, from: &str, to: &str) -> u32 {
+        use xdr_codec::Pack;
+
+        let fs = LocalFilesystem::new(test_dir.to_string()).unwrap();
+        let fhandle = crate::protocol::v3::nfs::fhandle3(fs.root_handle());
+
+        let mut args_buf = Vec::new();
+        fhandle.pack(&mut args_buf).unwrap();
+        crate::protocol::v3::nfs::filename3(from.to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+        fhandle.pack(&mut args_buf).unwrap();
+        crate::protocol::v3::nfs::filename3(to.to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+
+        let reply = rename(12347, &args_buf, &fs).unwrap();
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_rename_directory_over_existing() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_rename_over");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("src")).unwrap();
+        fs::write(test_dir.join("src/keep.txt"), "data").unwrap();
+        fs::create_dir(test_dir.join("empty")).unwrap();
+        fs::create_dir(test_dir.join("full")).unwrap();
+        fs::write(test_dir.join("full/occupied.txt"), "data").unwrap();
+        fs::write(test_dir.join("file.txt"), "data").unwrap();
+        let root = "/tmp/nfs_test_rename_over";
+
+        // Directory onto a non-empty directory: NOTEMPTY, nothing changes
+        assert_eq!(rename_status(root, "src", "full"), nfsstat3::NFS3ERR_NOTEMPTY as u32);
+        assert!(test_dir.join("full/occupied.txt").exists());
+
+        // Directory onto a file: NOTDIR
+        assert_eq!(rename_status(root, "src", "file.txt"), nfsstat3::NFS3ERR_NOTDIR as u32);
+
+        // File onto a directory: ISDIR
+        assert_eq!(rename_status(root, "file.txt", "empty"), nfsstat3::NFS3ERR_ISDIR as u32);
+        assert!(test_dir.join("file.txt").is_file());
+
+        // Directory onto an empty directory: replaces it
+        assert_eq!(rename_status(root, "src", "empty"), nfsstat3::NFS3_OK as u32);
+        assert!(!test_dir.join("src").exists());
+        assert!(test_dir.join("empty/keep.txt").exists());
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_across_directories_overwrites_file() {
+        use xdr_codec::Pack;
+
+        let test_dir = PathBuf::from("/tmp/nfs_test_rename_cross");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(test_dir.join("a")).unwrap();
+        fs::create_dir_all(test_dir.join("b")).unwrap();
+        fs::write(test_dir.join("a/moved.txt"), "new").unwrap();
+        fs::write(test_dir.join("b/target.txt"), "old").unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_rename_cross".to_string()).unwrap();
+        let root = fs.root_handle();
+        let dir_a = fs.lookup(&root, "a").unwrap();
+        let dir_b = fs.lookup(&root, "b").unwrap();
+
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(dir_a).pack(&mut args_buf).unwrap();
+        crate::protocol::v3::nfs::filename3("moved.txt".to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+        crate::protocol::v3::nfs::fhandle3(dir_b).pack(&mut args_buf).unwrap();
+        crate::protocol::v3::nfs::filename3("target.txt".to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+
+        let reply = rename(12348, &args_buf, &fs).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4], "RENAME should succeed");
+        assert!(!test_dir.join("a/moved.txt").exists());
+        assert_eq!(fs::read_to_string(test_dir.join("b/target.txt")).unwrap(), "new");
+
+        // fromdir_wcc: pre_op_attr (4 + 24) + post_op_attr (4 + 84)
+        assert_eq!(&reply[28..32], &[0, 0, 0, 1], "fromdir pre_op_attr should follow");
+        assert_eq!(&reply[56..60], &[0, 0, 0, 1], "fromdir post_op_attr should follow");
+        // todir_wcc follows with the same layout
+        assert_eq!(&reply[144..148], &[0, 0, 0, 1], "todir pre_op_attr should follow");
+        assert_eq!(&reply[172..176], &[0, 0, 0, 1], "todir post_op_attr should follow");
+        assert_eq!(reply.len(), 24 + 4 + 2 * (28 + 88));
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
+
+    #[test]
+    fn test_rename_across_exports() {
+        use xdr_codec::Pack;
+
+        let (a, b) = (tempfile::TempDir::new().unwrap(), tempfile::TempDir::new().unwrap());
+        fs::write(a.path().join("file.txt"), "data").unwrap();
+        let fs_a = LocalFilesystem::new(a.path()).unwrap();
+        let fs_b = LocalFilesystem::new(b.path()).unwrap();
+
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs_a.root_handle()).pack(&mut args_buf).unwrap();
+        crate::protocol::v3::nfs::filename3("file.txt".to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+        crate::protocol::v3::nfs::fhandle3(fs_b.root_handle()).pack(&mut args_buf).unwrap();
+        crate::protocol::v3::nfs::filename3("file.txt".to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+
+        let reply = rename(12349, &args_buf, &fs_a).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_XDEV as u32).to_be_bytes());
+        assert!(a.path().join("file.txt").exists());
+        assert!(!b.path().join("file.txt").exists());
+    }
 }
@@ -6,7 +6,8 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
+use crate::fsal::{FileAttributes, Filesystem};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
@@ -19,10 +20,16 @@ use crate::protocol::v3::rpc::RpcMessage;
 /// * `xid` - Transaction ID from RPC call
 /// * `args_data` - Serialized RMDIR3args
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context with the caller's credentials
 ///
 /// # Returns
 /// Serialized RPC reply with RMDIR3res
-pub fn handle_rmdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_rmdir(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS RMDIR: xid={}", xid);
 
     // Parse arguments
@@ -37,6 +44,16 @@ pub fn handle_rmdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
     // Get parent directory attributes before removal (for wcc_data)
     let dir_before = filesystem.getattr(&args.dir.0).ok();
 
+    let credentials = &ctx.credentials;
+    if dir_before
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("RMDIR denied for uid {}", credentials.uid);
+        let dir_attr = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+        return create_rmdir_response(xid, nfsstat3::NFS3ERR_ACCES, dir_before.as_ref(), dir_attr);
+    }
+
     // Perform rmdir operation
     match filesystem.rmdir(&args.dir.0, &args.name.0) {
         Ok(()) => {
@@ -44,22 +61,26 @@ pub fn handle_rmdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 
             // Get parent directory attributes after removal
             let dir_after = match filesystem.getattr(&args.dir.0) {
-                Ok(attr) => NfsMessage::fsal_to_fattr3(&attr),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get parent dir attributes after rmdir: {}", e);
                     // Continue anyway, removal succeeded
-                    return create_rmdir_response(xid, nfsstat3::NFS3_OK, None);
+                    None
                 }
             };
 
-            create_rmdir_response(xid, nfsstat3::NFS3_OK, Some(dir_after))
+            create_rmdir_response(xid, nfsstat3::NFS3_OK, dir_before.as_ref(), dir_after)
         }
         Err(e) => {
             warn!("RMDIR failed for '{}': {}", args.name.0, e);
 
             // Determine appropriate error code
             let error_string = e.to_string();
-            let status = if error_string.contains("not found") || error_string.contains("No such") {
+            let status = if let Some(status) = io_error_to_nfsstat(&e) {
+                status
+            } else if error_string.contains("Stale file handle") {
+                nfsstat3::NFS3ERR_STALE
+            } else if error_string.contains("not found") || error_string.contains("No such") {
                 nfsstat3::NFS3ERR_NOENT
             } else if error_string.contains("permission") || error_string.contains("Permission") {
                 nfsstat3::NFS3ERR_ACCES
@@ -81,9 +102,9 @@ pub fn handle_rmdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
             };
 
             // Try to get current parent directory attributes for wcc_data
-            let dir_after = filesystem.getattr(&args.dir.0).ok().map(|attr| NfsMessage::fsal_to_fattr3(&attr));
+            let dir_after = filesystem.getattr(&args.dir.0).ok().map(|attr| ctx.fattr3(&attr));
 
-            create_rmdir_response(xid, status, dir_after)
+            create_rmdir_response(xid, status, dir_before.as_ref(), dir_after)
         }
     }
 }
@@ -92,6 +113,7 @@ pub fn handle_rmdir(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) ->
 fn create_rmdir_response(
     xid: u32,
     status: nfsstat3,
+    dir_before: Option<&FileAttributes>,
     dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
@@ -102,21 +124,7 @@ fn create_rmdir_response(
     (status as i32).pack(&mut buf)?;
 
     // 2. wcc_data (parent directory)
-    // wcc_data = pre_op_attr + post_op_attr
-
-    // 2.1 pre_op_attr (before the operation) - we don't track this, so send FALSE
-    false.pack(&mut buf)?; // pre_op_attr: attributes_follow = FALSE
-
-    // 2.2 post_op_attr (after the operation)
-    match dir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?; // post_op_attr: attributes_follow = TRUE
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?; // post_op_attr: attributes_follow = FALSE
-        }
-    }
+    buf.extend(build_wcc_data(dir_before.map(NfsMessage::fsal_to_wcc_attr), dir_attr)?);
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -132,10 +140,19 @@ fn create_rmdir_response(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::nfs::{Credentials, NfsState};
     use crate::fsal::local::LocalFilesystem;
     use std::fs;
     use std::path::PathBuf;
 
+
+    fn rmdir(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        handle_rmdir(xid, args_buf, fs, &ctx)
+    }
+
     #[test]
     fn test_rmdir() {
         // Create test directory
@@ -169,7 +186,7 @@ mod tests {
         assert!(target_dir.exists());
 
         // Call RMDIR
-        let result = handle_rmdir(12345, &args_buf, &fs);
+        let result = rmdir(12345, &args_buf, &fs);
         assert!(result.is_ok(), "RMDIR should succeed");
 
         // Verify directory was removed
@@ -203,10 +220,11 @@ mod tests {
         dirname.pack(&mut args_buf).unwrap();
 
         // Call RMDIR - should fail with NOENT
-        let result = handle_rmdir(12345, &args_buf, &fs);
+        let result = rmdir(12345, &args_buf, &fs);
         assert!(result.is_ok(), "RMDIR should return response (not crash)");
 
-        // TODO: Parse response and verify status is NFS3ERR_NOENT
+        let reply = result.unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_NOENT as u32).to_be_bytes());
 
         // Cleanup
         fs::remove_dir_all(&test_dir).unwrap();
@@ -241,15 +259,43 @@ mod tests {
         dirname.pack(&mut args_buf).unwrap();
 
         // Call RMDIR - should fail with NOTEMPTY
-        let result = handle_rmdir(12345, &args_buf, &fs);
+        let result = rmdir(12345, &args_buf, &fs);
         assert!(result.is_ok(), "RMDIR should return response (not crash)");
 
         // Verify directory still exists
         assert!(target_dir.exists(), "Directory should still exist");
 
-        // TODO: Parse response and verify status is NFS3ERR_NOTEMPTY
+        let reply = result.unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_NOTEMPTY as u32).to_be_bytes());
+        // Parent wcc_data: pre_op_attr follows
+        assert_eq!(&reply[28..32], &[0u8, 0, 0, 1]);
 
         // Cleanup
         fs::remove_dir_all(&test_dir).unwrap();
     }
+
+    #[test]
+    fn test_rmdir_file() {
+        let test_dir = PathBuf::from("/tmp/nfs_test_rmdir_file");
+        let _ = fs::remove_dir_all(&test_dir);
+        fs::create_dir_all(&test_dir).unwrap();
+        fs::write(test_dir.join("regular.txt"), "data").unwrap();
+
+        let fs = LocalFilesystem::new("/tmp/nfs_test_rmdir_file".to_string()).unwrap();
+
+        use xdr_codec::Pack;
+        let mut args_buf = Vec::new();
+        crate::protocol::v3::nfs::fhandle3(fs.root_handle())
+            .pack(&mut args_buf)
+            .unwrap();
+        crate::protocol::v3::nfs::filename3("regular.txt".to_string())
+            .pack(&mut args_buf)
+            .unwrap();
+
+        let reply = rmdir(12345, &args_buf, &fs).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_NOTDIR as u32).to_be_bytes());
+        assert!(test_dir.join("regular.txt").is_file(), "File must not be removed");
+
+        fs::remove_dir_all(&test_dir).unwrap();
+    }
 }
@@ -6,19 +6,29 @@ use anyhow::Result;
 use bytes::BytesMut;
 use tracing::debug;
 
-use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
+use crate::fsal::{FileAttributes, FileTime, Filesystem, SetTime};
+use crate::nfs::{build_wcc_data, handle_error_to_nfsstat, Credentials, NfsContext};
+use crate::protocol::v3::nfs::{
+    nfsstat3, nfstime3, sattr3, set_atime, set_gid3, set_mode3, set_mtime, set_size3, set_uid3,
+    NfsMessage, SETATTR3args,
+};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS SETATTR procedure (procedure 2)
 ///
 /// Sets file attributes such as mode, uid, gid, size, atime, mtime.
-/// Most commonly used to truncate files before writing.
+/// Most commonly used to truncate files before writing. Timestamps may be
+/// set to the server's current time or to a time supplied by the client.
+/// A guard ctime that no longer matches fails with NFS3ERR_NOT_SYNC.
+/// AUTH_SYS callers must own the file to change its mode, owner or times
+/// (NFS3ERR_PERM) and need write permission to change its size
+/// (NFS3ERR_ACCES).
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized SETATTR3args (file handle + new_attributes + guard)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (write serialization, caller credentials)
 ///
 /// # Returns
 /// Serialized RPC reply message with status and attributes
@@ -26,6 +36,7 @@ pub fn handle_setattr(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS SETATTR called (xid={})", xid);
 
@@ -37,9 +48,40 @@ pub fn handle_setattr(
         args.object.0.len(),
     );
 
+    // Size changes modify file data, so they are ordered with WRITE/COMMIT
+    // on the same file when per-file serialization is configured
+    if let set_size3::SET_SIZE(_) = args.new_attributes.size {
+        ctx.state
+            .write_serializer
+            .run(&args.object.0, || apply_setattr(xid, &args, filesystem, ctx))
+    } else {
+        apply_setattr(xid, &args, filesystem, ctx)
+    }
+}
+
+/// Apply the requested attribute changes and build the SETATTR reply
+fn apply_setattr(
+    xid: u32,
+    args: &SETATTR3args,
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
+    let credentials = &ctx.credentials;
+
     // Get file attributes before setattr (for wcc_data)
     let before_attrs = filesystem.getattr(&args.object.0).ok();
 
+    // Failures carry the object's wcc_data too, as far as it can be read
+    let setattr_error = |status: nfsstat3| -> Result<BytesMut> {
+        let after_attrs = filesystem.getattr(&args.object.0).ok().map(|attrs| ctx.fattr3(&attrs));
+        let res_data = NfsMessage::create_setattr_error_response(
+            status,
+            before_attrs.as_ref().map(NfsMessage::fsal_to_wcc_attr),
+            after_attrs.as_ref(),
+        )?;
+        RpcMessage::create_success_reply_with_data(xid, res_data)
+    };
+
     // Check guard if requested (guard is a union: CHECK with ctime or DONT_CHECK)
     if let crate::protocol::v3::nfs::sattrguard3::CHECK(guard_ctime) = &args.guard {
         if let Some(ref before) = before_attrs {
@@ -50,8 +92,7 @@ pub fn handle_setattr(
                 || before_ctime.nseconds != guard_ctime.nseconds {
                 debug!("SETATTR: guard check failed - file was modified");
                 let error_status = nfsstat3::NFS3ERR_NOT_SYNC;
-                let res_data = NfsMessage::create_setattr_error_response(error_status)?;
-                return RpcMessage::create_success_reply_with_data(xid, res_data);
+                return setattr_error(error_status);
             }
         }
     }
@@ -59,13 +100,25 @@ pub fn handle_setattr(
     // Apply attribute changes
     let new_attrs = &args.new_attributes;
 
+    if let Some(error_status) = before_attrs
+        .as_ref()
+        .and_then(|before| check_permission(credentials, before, new_attrs))
+    {
+        debug!("SETATTR denied for uid {}: {:?}", credentials.uid, error_status);
+        return setattr_error(error_status);
+    }
+
     // Handle size change (truncate/extend)
     if let crate::protocol::v3::nfs::set_size3::SET_SIZE(new_size) = &new_attrs.size {
         debug!("SETATTR: setting size to {}", new_size);
 
         if let Err(e) = filesystem.setattr_size(&args.object.0, *new_size) {
             debug!("SETATTR: failed to set size: {}", e);
-            let error_status = if e.to_string().contains("not found") {
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
+            {
                 nfsstat3::NFS3ERR_STALE
             } else if e.to_string().contains("Permission denied") {
                 nfsstat3::NFS3ERR_ACCES
@@ -74,8 +127,7 @@ pub fn handle_setattr(
             } else {
                 nfsstat3::NFS3ERR_IO
             };
-            let res_data = NfsMessage::create_setattr_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return setattr_error(error_status);
         }
     }
 
@@ -85,15 +137,18 @@ pub fn handle_setattr(
 
         if let Err(e) = filesystem.setattr_mode(&args.object.0, *mode) {
             debug!("SETATTR: failed to set mode: {}", e);
-            let error_status = if e.to_string().contains("not found") {
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
+            {
                 nfsstat3::NFS3ERR_STALE
             } else if e.to_string().contains("Permission denied") {
                 nfsstat3::NFS3ERR_ACCES
             } else {
                 nfsstat3::NFS3ERR_IO
             };
-            let res_data = NfsMessage::create_setattr_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return setattr_error(error_status);
         }
     }
 
@@ -112,21 +167,54 @@ pub fn handle_setattr(
 
         if let Err(e) = filesystem.setattr_owner(&args.object.0, uid, gid) {
             debug!("SETATTR: failed to set owner: {}", e);
-            let error_status = if e.to_string().contains("not found") {
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
+            {
                 nfsstat3::NFS3ERR_STALE
             } else if e.to_string().contains("Permission denied") {
                 nfsstat3::NFS3ERR_ACCES
+            } else if e.to_string().contains("Operation not permitted") {
+                nfsstat3::NFS3ERR_PERM
             } else {
                 nfsstat3::NFS3ERR_IO
             };
-            let res_data = NfsMessage::create_setattr_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return setattr_error(error_status);
         }
     }
 
     // Handle atime/mtime changes
-    // For simplicity, we'll skip time changes for now as they require more complex handling
-    // (SET_TO_SERVER_TIME vs SET_TO_CLIENT_TIME)
+    let atime = match &new_attrs.atime {
+        set_atime::SET_TO_SERVER_TIME => Some(SetTime::ServerTime),
+        set_atime::SET_TO_CLIENT_TIME(t) => Some(SetTime::ClientTime(client_time(t))),
+        _ => None,
+    };
+    let mtime = match &new_attrs.mtime {
+        set_mtime::SET_TO_SERVER_TIME => Some(SetTime::ServerTime),
+        set_mtime::SET_TO_CLIENT_TIME(t) => Some(SetTime::ClientTime(client_time(t))),
+        _ => None,
+    };
+
+    if atime.is_some() || mtime.is_some() {
+        debug!("SETATTR: setting atime={:?}, mtime={:?}", atime, mtime);
+
+        if let Err(e) = filesystem.setattr_times(&args.object.0, atime, mtime) {
+            debug!("SETATTR: failed to set times: {}", e);
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
+            {
+                nfsstat3::NFS3ERR_STALE
+            } else if e.to_string().contains("Permission denied") {
+                nfsstat3::NFS3ERR_ACCES
+            } else {
+                nfsstat3::NFS3ERR_IO
+            };
+            return setattr_error(error_status);
+        }
+    }
 
     // Get file attributes after setattr
     let after_attrs = match filesystem.getattr(&args.object.0) {
@@ -134,15 +222,14 @@ pub fn handle_setattr(
         Err(e) => {
             debug!("SETATTR: failed to get attributes after setattr: {}", e);
             let error_status = nfsstat3::NFS3ERR_IO;
-            let res_data = NfsMessage::create_setattr_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return setattr_error(error_status);
         }
     };
 
     debug!("SETATTR success");
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_after_attrs = NfsMessage::fsal_to_fattr3(&after_attrs);
+    let nfs_after_attrs = ctx.fattr3(&after_attrs);
 
     // Create SETATTR response with wcc_data
     use xdr_codec::Pack;
@@ -152,12 +239,10 @@ pub fn handle_setattr(
     (nfsstat3::NFS3_OK as i32).pack(&mut buf)?;
 
     // 2. obj_wcc: wcc_data
-    // pre_op_attr (optional)
-    false.pack(&mut buf)?; // pre_op_attr = FALSE
-
-    // post_op_attr (after attributes)
-    true.pack(&mut buf)?; // attributes_follow = TRUE
-    nfs_after_attrs.pack(&mut buf)?;
+    buf.extend(build_wcc_data(
+        before_attrs.as_ref().map(NfsMessage::fsal_to_wcc_attr),
+        Some(nfs_after_attrs),
+    )?);
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -165,10 +250,55 @@ pub fn handle_setattr(
     RpcMessage::create_success_reply_with_data(xid, res_data)
 }
 
+/// Check that the caller may make the requested changes to a file
+///
+/// Returns the error to report, or None if the changes are allowed.
+fn check_permission(
+    credentials: &Credentials,
+    before: &FileAttributes,
+    new_attrs: &sattr3,
+) -> Option<nfsstat3> {
+    // Giving a file away, or to a group the caller is not in, needs root
+    let chown = matches!(new_attrs.uid, set_uid3::SET_UID(uid) if uid != before.uid);
+    let chgrp = matches!(new_attrs.gid, set_gid3::SET_GID(gid)
+        if gid != before.gid && !credentials.in_group(gid));
+    if credentials.uid != 0 && (chown || chgrp) {
+        return Some(nfsstat3::NFS3ERR_PERM);
+    }
+
+    // Mode, group and client-supplied times are the owner's to change
+    let owner_only = matches!(new_attrs.mode, set_mode3::SET_MODE(_))
+        || matches!(new_attrs.gid, set_gid3::SET_GID(_))
+        || matches!(new_attrs.atime, set_atime::SET_TO_CLIENT_TIME(_))
+        || matches!(new_attrs.mtime, set_mtime::SET_TO_CLIENT_TIME(_));
+    if owner_only && !credentials.owns(before) {
+        return Some(nfsstat3::NFS3ERR_PERM);
+    }
+
+    // Truncating, or touching with the server time, only needs write access
+    let write_only = matches!(new_attrs.size, set_size3::SET_SIZE(_))
+        || matches!(new_attrs.atime, set_atime::SET_TO_SERVER_TIME)
+        || matches!(new_attrs.mtime, set_mtime::SET_TO_SERVER_TIME);
+    if write_only && !credentials.owns(before) && !credentials.may_write(before) {
+        return Some(nfsstat3::NFS3ERR_ACCES);
+    }
+
+    None
+}
+
+/// Convert a client-supplied nfstime3 to an FSAL time
+fn client_time(time: &nfstime3) -> FileTime {
+    FileTime {
+        seconds: time.seconds as u64,
+        nseconds: time.nseconds,
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::NfsState;
     use std::fs;
     use tempfile::TempDir;
 
@@ -190,7 +320,7 @@ mod tests {
         // Serialize SETATTR3args to truncate to 5 bytes
         use crate::protocol::v3::nfs::{
             fhandle3, sattrguard3, sattr3, set_atime, set_gid3, set_mode3,
-            set_mtime, set_size3, set_uid3, time_how, SETATTR3args,
+            set_mtime, set_uid3,
         };
         use xdr_codec::Pack;
 
@@ -204,20 +334,17 @@ mod tests {
                 atime: set_atime::default,
                 mtime: set_mtime::default,
             },
-            guard: sattrguard3 {
-                check: false,
-                obj_ctime: crate::protocol::v3::nfs::nfstime3 {
-                    seconds: 0,
-                    nseconds: 0,
-                },
-            },
+            guard: sattrguard3::default,
         };
 
         let mut args_buf = Vec::new();
         args.pack(&mut args_buf).unwrap();
 
         // Call SETATTR
-        let result = handle_setattr(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let result = handle_setattr(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "SETATTR should succeed");
 
@@ -244,7 +371,7 @@ mod tests {
         // Serialize SETATTR3args to set mode to 0644
         use crate::protocol::v3::nfs::{
             fhandle3, sattrguard3, sattr3, set_atime, set_gid3, set_mode3,
-            set_mtime, set_size3, set_uid3, time_how, SETATTR3args,
+            set_mtime, set_uid3,
         };
         use xdr_codec::Pack;
 
@@ -258,21 +385,158 @@ mod tests {
                 atime: set_atime::default,
                 mtime: set_mtime::default,
             },
-            guard: sattrguard3 {
-                check: false,
-                obj_ctime: crate::protocol::v3::nfs::nfstime3 {
-                    seconds: 0,
-                    nseconds: 0,
-                },
-            },
+            guard: sattrguard3::default,
         };
 
         let mut args_buf = Vec::new();
         args.pack(&mut args_buf).unwrap();
 
         // Call SETATTR
-        let result = handle_setattr(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let result = handle_setattr(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "SETATTR should succeed");
     }
+
+    fn times_args(file_handle: Vec<u8>, atime: set_atime, mtime: set_mtime) -> Vec<u8> {
+        use crate::protocol::v3::nfs::{
+            fhandle3, sattr3, sattrguard3, set_gid3, set_mode3, set_uid3,
+        };
+        use xdr_codec::Pack;
+
+        let args = SETATTR3args {
+            object: fhandle3(file_handle),
+            new_attributes: sattr3 {
+                mode: set_mode3::default,
+                uid: set_uid3::default,
+                gid: set_gid3::default,
+                size: set_size3::default,
+                atime,
+                mtime,
+            },
+            guard: sattrguard3::default,
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    #[test]
+    fn test_setattr_client_time() {
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let test_file = temp_dir.path().join("times.txt");
+        fs::write(&test_file, b"test").unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "times.txt").unwrap();
+
+        let when = || nfstime3 {
+            seconds: 1_000_000_000,
+            nseconds: 500,
+        };
+        let args_buf = times_args(
+            file_handle,
+            set_atime::SET_TO_CLIENT_TIME(when()),
+            set_mtime::SET_TO_CLIENT_TIME(when()),
+        );
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let reply = handle_setattr(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4], "SETATTR should succeed");
+        // obj_wcc carries pre_op_attr (4 + 24) before post_op_attr
+        assert_eq!(&reply[28..32], &[0, 0, 0, 1]);
+        assert_eq!(&reply[56..60], &[0, 0, 0, 1]);
+
+        let expected = UNIX_EPOCH + Duration::new(1_000_000_000, 500);
+        let metadata = fs::metadata(&test_file).unwrap();
+        assert_eq!(metadata.modified().unwrap(), expected);
+        assert_eq!(metadata.accessed().unwrap(), expected);
+    }
+
+    #[test]
+    fn test_setattr_server_time() {
+        use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let test_file = temp_dir.path().join("times.txt");
+        fs::write(&test_file, b"test").unwrap();
+        let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+        fs::File::options()
+            .write(true)
+            .open(&test_file)
+            .unwrap()
+            .set_times(fs::FileTimes::new().set_accessed(old).set_modified(old))
+            .unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "times.txt").unwrap();
+
+        // Only mtime moves to the server's clock; atime is left alone
+        let args_buf = times_args(file_handle, set_atime::default, set_mtime::SET_TO_SERVER_TIME);
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let before = SystemTime::now();
+        let reply = handle_setattr(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4], "SETATTR should succeed");
+
+        let metadata = fs::metadata(&test_file).unwrap();
+        assert!(metadata.modified().unwrap() >= before - Duration::from_secs(1));
+        assert_eq!(metadata.accessed().unwrap(), old);
+    }
+
+    #[test]
+    fn test_setattr_requires_ownership() {
+        use crate::nfs::Credentials;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let test_file = temp_dir.path().join("shared.txt");
+        fs::write(&test_file, b"test").unwrap();
+        fs::set_permissions(&test_file, fs::Permissions::from_mode(0o666)).unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "shared.txt").unwrap();
+        let owner = fs.getattr(&file_handle).unwrap().uid;
+
+        let state = NfsState::default();
+        let other_user = || {
+            NfsContext::new("127.0.0.1:700".parse().unwrap(), &state).with_credentials(
+                Credentials {
+                    uid: owner.wrapping_add(1),
+                    gid: 4242,
+                    gids: vec![],
+                    anonymous: false,
+                },
+            )
+        };
+
+        // Setting explicit times is the owner's privilege
+        let args_buf = times_args(
+            file_handle.clone(),
+            set_atime::SET_TO_CLIENT_TIME(nfstime3 {
+                seconds: 1,
+                nseconds: 0,
+            }),
+            set_mtime::default,
+        );
+        let reply = handle_setattr(1, &args_buf, fs.as_ref(), &other_user()).unwrap();
+        assert_eq!(&reply[24..28], &(nfsstat3::NFS3ERR_PERM as u32).to_be_bytes());
+
+        // Touching with the server time only needs write access
+        let args_buf = times_args(file_handle, set_atime::SET_TO_SERVER_TIME, set_mtime::default);
+        let reply = handle_setattr(2, &args_buf, fs.as_ref(), &other_user()).unwrap();
+        assert_eq!(&reply[24..28], &[0u8; 4]);
+    }
 }
+
@@ -6,20 +6,31 @@ use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::fsal::Filesystem;
+use crate::fsal::{FileAttributes, Filesystem};
+use crate::nfs::{build_wcc_data, io_error_to_nfsstat, NfsContext, NFS3_MAXNAMLEN};
 use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle SYMLINK procedure
 ///
+/// Creates a symbolic link storing the target path verbatim; it is never
+/// resolved by the server. Names longer than `NFS3_MAXNAMLEN` are rejected
+/// with NFS3ERR_NAMETOOLONG.
+///
 /// # Arguments
 /// * `xid` - RPC transaction ID
 /// * `args_data` - Serialized SYMLINK3args
 /// * `filesystem` - Filesystem implementation
+/// * `ctx` - Per-call context with the caller's credentials
 ///
 /// # Returns
 /// Serialized SYMLINK3res response
-pub fn handle_symlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -> Result<BytesMut> {
+pub fn handle_symlink(
+    xid: u32,
+    args_data: &[u8],
+    filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
+) -> Result<BytesMut> {
     debug!("NFS SYMLINK: xid={}", xid);
 
     // Parse arguments
@@ -35,6 +46,37 @@ pub fn handle_symlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
     // Get parent directory attributes before operation (for wcc_data)
     let dir_before = filesystem.getattr(&args.where_dir.0).ok();
 
+    // Reject names the server would never store (see PATHCONF name_max)
+    if args.name.0.len() > NFS3_MAXNAMLEN {
+        warn!("SYMLINK failed: name of {} bytes is too long", args.name.0.len());
+        let dir_attr = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+        return create_symlink_response(
+            xid,
+            nfsstat3::NFS3ERR_NAMETOOLONG,
+            None,
+            None,
+            dir_before.as_ref(),
+            dir_attr,
+        );
+    }
+
+    let credentials = &ctx.credentials;
+    if dir_before
+        .as_ref()
+        .is_some_and(|attrs| !credentials.may_write(attrs))
+    {
+        debug!("SYMLINK denied for uid {}", credentials.uid);
+        let dir_attr = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
+        return create_symlink_response(
+            xid,
+            nfsstat3::NFS3ERR_ACCES,
+            None,
+            None,
+            dir_before.as_ref(),
+            dir_attr,
+        );
+    }
+
     // Perform symlink operation
     match filesystem.symlink(&args.where_dir.0, &args.name.0, &args.symlink.symlink_data.0) {
         Ok(new_symlink_handle) => {
@@ -42,7 +84,7 @@ pub fn handle_symlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
 
             // Get new symlink attributes
             let symlink_attr = match filesystem.getattr(&new_symlink_handle) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get symlink attributes: {}", e);
                     None
@@ -51,7 +93,7 @@ pub fn handle_symlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
 
             // Get parent directory attributes after operation
             let dir_after = match filesystem.getattr(&args.where_dir.0) {
-                Ok(attr) => Some(NfsMessage::fsal_to_fattr3(&attr)),
+                Ok(attr) => Some(ctx.fattr3(&attr)),
                 Err(e) => {
                     warn!("Failed to get directory attributes after symlink: {}", e);
                     None
@@ -63,6 +105,7 @@ pub fn handle_symlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
                 nfsstat3::NFS3_OK,
                 Some(new_symlink_handle),
                 symlink_attr,
+                dir_before.as_ref(),
                 dir_after,
             )
         }
@@ -73,18 +116,24 @@ pub fn handle_symlink(xid: u32, args_data: &[u8], filesystem: &dyn Filesystem) -
             let status = map_error_to_status(&e);
 
             // Get parent directory attributes for failure case
-            let dir_attr = dir_before.map(|attr| NfsMessage::fsal_to_fattr3(&attr));
+            let dir_attr = dir_before.as_ref().map(|attr| ctx.fattr3(attr));
 
-            create_symlink_response(xid, status, None, None, dir_attr)
+            create_symlink_response(xid, status, None, None, dir_before.as_ref(), dir_attr)
         }
     }
 }
 
 /// Map filesystem error to NFS status code
 fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
+    if let Some(status) = io_error_to_nfsstat(error) {
+        return status;
+    }
     let error_str = format!("{:?}", error);
 
     // Check for specific error patterns
+    if error_str.contains("Stale file handle") {
+        return nfsstat3::NFS3ERR_STALE;
+    }
     if error_str.contains("No such file") || error_str.contains("not found") {
         return nfsstat3::NFS3ERR_NOENT;
     }
@@ -135,12 +184,14 @@ fn map_error_to_status(error: &anyhow::Error) -> nfsstat3 {
 /// * `status` - NFS status code
 /// * `symlink_handle` - New symlink file handle (post_op_fh3)
 /// * `symlink_attr` - New symlink attributes (post_op_attr)
-/// * `dir_attr` - Parent directory attributes (wcc_data)
+/// * `dir_before` - Parent directory attributes before the operation
+/// * `dir_attr` - Parent directory attributes after the operation
 fn create_symlink_response(
     xid: u32,
     status: nfsstat3,
     symlink_handle: Option<Vec<u8>>,
     symlink_attr: Option<crate::protocol::v3::nfs::fattr3>,
+    dir_before: Option<&FileAttributes>,
     dir_attr: Option<crate::protocol::v3::nfs::fattr3>,
 ) -> Result<BytesMut> {
     use xdr_codec::Pack;
@@ -181,20 +232,119 @@ fn create_symlink_response(
     }
 
     // 3. wcc_data (parent directory)
-    // pre_op_attr (we don't track this, so set to false)
-    false.pack(&mut buf)?;
-
-    // post_op_attr (parent directory)
-    match &dir_attr {
-        Some(attr) => {
-            true.pack(&mut buf)?;
-            attr.pack(&mut buf)?;
-        }
-        None => {
-            false.pack(&mut buf)?;
-        }
-    }
+    buf.extend(build_wcc_data(dir_before.map(NfsMessage::fsal_to_wcc_attr), dir_attr)?);
 
     let res_data = BytesMut::from(&buf[..]);
     RpcMessage::create_success_reply_with_data(xid, res_data)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nfs::{Credentials, NfsState};
+    use crate::fsal::{BackendConfig, FileType};
+    use crate::protocol::v3::nfs::{
+        fhandle3, filename3, ftype3, nfspath3, sattr3, set_atime, set_gid3, set_mode3,
+        set_mtime, set_size3, set_uid3, symlinkdata3, SYMLINK3args,
+    };
+    use std::fs;
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+
+    fn symlink(xid: u32, args_buf: &[u8], fs: &dyn Filesystem) -> Result<BytesMut> {
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        handle_symlink(xid, args_buf, fs, &ctx)
+    }
+
+    fn symlink_args(dir: Vec<u8>, name: &str, target: &str) -> Vec<u8> {
+        let args = SYMLINK3args {
+            where_dir: fhandle3(dir),
+            name: filename3(name.to_string()),
+            symlink: symlinkdata3 {
+                symlink_attributes: sattr3 {
+                    mode: set_mode3::default,
+                    uid: set_uid3::default,
+                    gid: set_gid3::default,
+                    size: set_size3::default,
+                    atime: set_atime::default,
+                    mtime: set_mtime::default,
+                },
+                symlink_data: nfspath3(target.to_string()),
+            },
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    fn reply_status(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_symlink_roundtrip() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let root = fs.root_handle();
+
+        // Stored verbatim, even when it dangles or points outside the export
+        let target = "../outside/some target";
+        let reply = symlink(1, &symlink_args(root, "link", target), fs.as_ref()).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+
+        // post_op_fh3 (4 + 4 + 32), then post_op_attr whose fattr3 starts
+        // with the type
+        assert_eq!(&reply[28..32], &[0, 0, 0, 1]);
+        assert_eq!(&reply[32..36], &32u32.to_be_bytes());
+        let handle = reply[36..68].to_vec();
+        assert_eq!(&reply[68..72], &[0, 0, 0, 1]);
+        assert_eq!(&reply[72..76], &(ftype3::NF3LNK as u32).to_be_bytes());
+        // dir_wcc with both pre_op_attr (4 + 24) and post_op_attr (4 + 84)
+        assert_eq!(&reply[156..160], &[0, 0, 0, 1]);
+        assert_eq!(&reply[184..188], &[0, 0, 0, 1]);
+
+        // Later GETATTR/READLINK see the link itself
+        assert_eq!(fs.getattr(&handle).unwrap().ftype, FileType::SymbolicLink);
+        assert_eq!(fs.readlink(&handle).unwrap(), target);
+        assert_eq!(
+            fs::read_link(temp_dir.path().join("link")).unwrap().to_str(),
+            Some(target)
+        );
+    }
+
+    #[test]
+    fn test_symlink_existing_name() {
+        let temp_dir = TempDir::new().unwrap();
+        std::os::unix::fs::symlink("missing", temp_dir.path().join("dangling")).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+
+        let reply = symlink(
+            1,
+            &symlink_args(fs.root_handle(), "dangling", "elsewhere"),
+            fs.as_ref(),
+        )
+        .unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_EXIST as u32);
+    }
+
+    #[test]
+    fn test_symlink_name_too_long() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+
+        let name = "n".repeat(NFS3_MAXNAMLEN + 1);
+        let reply =
+            symlink(1, &symlink_args(fs.root_handle(), &name, "target"), fs.as_ref())
+                .unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_NAMETOOLONG as u32);
+    }
+}
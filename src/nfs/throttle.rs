@@ -0,0 +1,119 @@
+// READDIR Enumeration Throttle
+//
+// Limits how many directory entries a single client may enumerate within a
+// time window. Paginated READDIR on directories with millions of entries can
+// otherwise be used to tie up the server.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+/// Entries enumerated by one client in the current window
+struct ClientWindow {
+    started: Instant,
+    entries: u64,
+}
+
+/// Per-client READDIR/READDIRPLUS entry budget
+pub struct ReaddirThrottle {
+    /// Maximum entries per client per window (None = unlimited)
+    max_entries: Option<u64>,
+    window: Duration,
+    clients: Mutex<HashMap<IpAddr, ClientWindow>>,
+}
+
+impl ReaddirThrottle {
+    /// Create a throttle allowing `max_entries` per client per `window`
+    pub fn new(max_entries: Option<u64>, window: Duration) -> Self {
+        Self {
+            max_entries,
+            window,
+            clients: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Create a throttle that never limits
+    pub fn unlimited() -> Self {
+        Self::new(None, Duration::ZERO)
+    }
+
+    /// Check whether a client still has budget left in its current window
+    pub fn check(&self, client: IpAddr) -> bool {
+        let Some(max_entries) = self.max_entries else {
+            return true;
+        };
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+
+        // Expired windows carry no state, drop them to keep the map bounded
+        clients.retain(|_, w| now.duration_since(w.started) < self.window);
+
+        clients
+            .get(&client)
+            .is_none_or(|w| w.entries < max_entries)
+    }
+
+    /// Charge `entries` returned to a client against its budget
+    pub fn record(&self, client: IpAddr, entries: u64) {
+        if self.max_entries.is_none() {
+            return;
+        }
+
+        let now = Instant::now();
+        let mut clients = self.clients.lock().unwrap();
+        let w = clients.entry(client).or_insert(ClientWindow {
+            started: now,
+            entries: 0,
+        });
+
+        if now.duration_since(w.started) >= self.window {
+            w.started = now;
+            w.entries = 0;
+        }
+        w.entries += entries;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_unlimited_never_throttles() {
+        let throttle = ReaddirThrottle::unlimited();
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+
+        throttle.record(client, u64::MAX / 2);
+        assert!(throttle.check(client));
+    }
+
+    #[test]
+    fn test_throttle_per_client() {
+        let throttle = ReaddirThrottle::new(Some(100), Duration::from_secs(60));
+        let greedy: IpAddr = "10.0.0.1".parse().unwrap();
+        let normal: IpAddr = "10.0.0.2".parse().unwrap();
+
+        assert!(throttle.check(greedy));
+        throttle.record(greedy, 60);
+        assert!(throttle.check(greedy));
+        throttle.record(greedy, 60);
+        assert!(!throttle.check(greedy), "Client over budget should be throttled");
+
+        // Other clients are unaffected
+        assert!(throttle.check(normal));
+    }
+
+    #[test]
+    fn test_throttle_window_expires() {
+        let throttle = ReaddirThrottle::new(Some(10), Duration::from_millis(20));
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+
+        throttle.record(client, 10);
+        assert!(!throttle.check(client));
+
+        std::thread::sleep(Duration::from_millis(30));
+        assert!(throttle.check(client), "Budget should reset after the window");
+    }
+}
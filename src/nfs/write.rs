@@ -4,20 +4,26 @@
 
 use anyhow::Result;
 use bytes::BytesMut;
-use tracing::debug;
+use tracing::{debug, warn};
 
+use crate::config::OversizedWritePolicy;
 use crate::fsal::Filesystem;
-use crate::protocol::v3::nfs::{nfsstat3, NfsMessage};
+use crate::nfs::{build_wcc_data, handle_error_to_nfsstat, NfsContext};
+use crate::protocol::v3::nfs::{nfsstat3, stable_how, NfsMessage};
 use crate::protocol::v3::rpc::RpcMessage;
 
 /// Handle NFS WRITE procedure (procedure 7)
 ///
-/// Writes data to a file at a specified offset.
+/// Writes data to a file at a specified offset. Writing past EOF extends the
+/// file, leaving a zero-filled hole. The reply carries the stability level
+/// that was honored and the per-boot write verifier.
 ///
 /// # Arguments
 /// * `xid` - Transaction ID from the request
 /// * `args_data` - Serialized WRITE3args (file handle + offset + count + stable + data)
 /// * `filesystem` - Filesystem instance
+/// * `ctx` - Per-call context (wtmax, oversized write policy, write serialization,
+///   write verifier)
 ///
 /// # Returns
 /// Serialized RPC reply message with write status
@@ -25,6 +31,7 @@ pub fn handle_write(
     xid: u32,
     args_data: &[u8],
     filesystem: &dyn Filesystem,
+    ctx: &NfsContext,
 ) -> Result<BytesMut> {
     debug!("NFS WRITE called (xid={})", xid);
 
@@ -42,30 +49,104 @@ pub fn handle_write(
     // Get file attributes before write (for wcc_data)
     let before_attrs = filesystem.getattr(&args.file.0).ok();
 
-    // Write data to the file
-    let bytes_written = match filesystem.write(&args.file.0, args.offset, &args.data) {
-        Ok(count) => count,
+    // Failures carry the file's wcc_data too, as far as it can be read
+    let write_error = |status: nfsstat3| -> Result<BytesMut> {
+        let after_attrs = filesystem.getattr(&args.file.0).ok().map(|attrs| ctx.fattr3(&attrs));
+        let res_data = NfsMessage::create_write_error_response(
+            status,
+            before_attrs.as_ref().map(NfsMessage::fsal_to_wcc_attr),
+            after_attrs.as_ref(),
+        )?;
+        RpcMessage::create_success_reply_with_data(xid, res_data)
+    };
+
+    // Check count against the advertised wtmax
+    // Oversized writes are either performed in full (up to the hard limit) or
+    // rejected with INVAL, never silently truncated
+    let config = &ctx.state.config;
+    if args.count > config.wtmax {
+        let accepted = config.oversized_writes == OversizedWritePolicy::Accept
+            && args.count <= config.write_hard_limit;
+        if !accepted {
+            warn!(
+                "WRITE rejected: count {} exceeds wtmax {} (policy={:?}, hard limit={})",
+                args.count, config.wtmax, config.oversized_writes, config.write_hard_limit
+            );
+            return write_error(nfsstat3::NFS3ERR_INVAL);
+        }
+        debug!(
+            "WRITE: accepting count {} above wtmax {}",
+            args.count, config.wtmax
+        );
+    }
+
+    // The data must carry exactly count bytes
+    if args.data.len() != args.count as usize {
+        warn!(
+            "WRITE rejected: count {} does not match data length {}",
+            args.count,
+            args.data.len()
+        );
+        return write_error(nfsstat3::NFS3ERR_INVAL);
+    }
+
+    // AUTH_SYS callers need write permission on the file
+    if before_attrs
+        .as_ref()
+        .is_some_and(|attrs| !ctx.credentials.may_write(attrs))
+    {
+        debug!("WRITE denied for uid {}", ctx.credentials.uid);
+        return write_error(nfsstat3::NFS3ERR_ACCES);
+    }
+
+    // Write data to the file (serialized per file when configured)
+    // UNSTABLE data is left for a later COMMIT; DATA_SYNC and FILE_SYNC are
+    // both honored with a full flush (data + metadata), reported as FILE_SYNC
+    let write_result = ctx.state.write_serializer.run(&args.file.0, || {
+        let written = filesystem.write(&args.file.0, args.offset, &args.data)?;
+        let committed = match args.stable {
+            stable_how::UNSTABLE => {
+                // Remembered so a shutdown flushes it if no COMMIT comes
+                ctx.state.dirty_files.mark(&args.file.0);
+                stable_how::UNSTABLE
+            }
+            stable_how::DATA_SYNC | stable_how::FILE_SYNC => {
+                filesystem.commit(&args.file.0, args.offset, written)?;
+                stable_how::FILE_SYNC
+            }
+        };
+        Ok::<_, anyhow::Error>((written, committed))
+    });
+    let (bytes_written, committed) = match write_result {
+        Ok(result) => result,
         Err(e) => {
             debug!("WRITE failed: {}", e);
             // Return appropriate NFS error
-            let error_status = if e.to_string().contains("not found")
-                || e.to_string().contains("Invalid handle")
+            let error_status = if let Some(status) = handle_error_to_nfsstat(&e) {
+                status
+            } else if e.to_string().contains("not found")
+                || e.to_string().contains("Stale file handle")
             {
                 nfsstat3::NFS3ERR_STALE
             } else if e.to_string().contains("Not a file") {
                 nfsstat3::NFS3ERR_ISDIR
-            } else if e.to_string().contains("Permission denied") {
+            } else if e.to_string().contains("Is a symbolic link") {
+                nfsstat3::NFS3ERR_INVAL
+            } else if e.to_string().contains("Permission denied")
+                || e.to_string().contains("outside export root")
+            {
                 nfsstat3::NFS3ERR_ACCES
             } else if e.to_string().contains("No space") {
                 nfsstat3::NFS3ERR_NOSPC
-            } else if e.to_string().contains("Read-only") {
+            } else if e.to_string().contains("Read-only")
+                || e.to_string().contains("read-only")
+            {
                 nfsstat3::NFS3ERR_ROFS
             } else {
                 nfsstat3::NFS3ERR_IO
             };
 
-            let res_data = NfsMessage::create_write_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return write_error(error_status);
         }
     };
 
@@ -76,18 +157,17 @@ pub fn handle_write(
             debug!("WRITE: failed to get file attributes after write: {}", e);
             // Still return error even if write succeeded but can't get attrs
             let error_status = nfsstat3::NFS3ERR_IO;
-            let res_data = NfsMessage::create_write_error_response(error_status)?;
-            return RpcMessage::create_success_reply_with_data(xid, res_data);
+            return write_error(error_status);
         }
     };
 
     debug!(
-        "WRITE success: wrote {} bytes (requested {})",
-        bytes_written, args.count
+        "WRITE success: wrote {} bytes (requested {}), committed={:?}",
+        bytes_written, args.count, committed
     );
 
     // Convert FSAL attributes to NFS fattr3
-    let nfs_after_attrs = NfsMessage::fsal_to_fattr3(&after_attrs);
+    let nfs_after_attrs = ctx.fattr3(&after_attrs);
 
     // Create WRITE response manually with post_op_attr format
     use xdr_codec::Pack;
@@ -97,27 +177,21 @@ pub fn handle_write(
     (nfsstat3::NFS3_OK as i32).pack(&mut buf)?;
 
     // 2. file_wcc: wcc_data (weak cache consistency data)
-    // For simplicity, we only provide post_op_attr (after)
-    // pre_op_attr (before) is optional and set to FALSE
-    false.pack(&mut buf)?; // pre_op_attr = FALSE (no before attributes)
+    buf.extend(build_wcc_data(
+        before_attrs.as_ref().map(NfsMessage::fsal_to_wcc_attr),
+        Some(nfs_after_attrs),
+    )?);
 
-    // post_op_attr (after attributes)
-    true.pack(&mut buf)?; // attributes_follow = TRUE
-    nfs_after_attrs.pack(&mut buf)?;
-
-    // 3. count (bytes written)
+    // 3. count (bytes written, may be short)
     bytes_written.pack(&mut buf)?;
 
-    // 4. committed (stable_how) - return same as requested
-    // For simplicity, always return FILE_SYNC (2) to indicate data is committed
-    let committed = 2i32; // FILE_SYNC
-    committed.pack(&mut buf)?;
+    // 4. committed (stable_how) - the stability level actually honored
+    (committed as i32).pack(&mut buf)?;
 
     // 5. writeverf3 (write verifier) - 8 bytes
-    // This is used to detect server reboots between unstable writes and COMMIT
-    // For now, use a constant verifier (in production, use server boot time)
-    let verf: [u8; 8] = [0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07];
-    buf.extend_from_slice(&verf);
+    // Constant for this server instance, so clients can detect reboots
+    // between UNSTABLE writes and COMMIT
+    buf.extend_from_slice(&ctx.state.write_verifier.current());
 
     let res_data = BytesMut::from(&buf[..]);
 
@@ -128,9 +202,30 @@ pub fn handle_write(
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::config::NfsConfig;
     use crate::fsal::{BackendConfig, Filesystem};
+    use crate::nfs::{Credentials, NfsState};
+    use crate::protocol::v3::nfs::{fhandle3, WRITE3args};
     use std::fs;
     use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    fn write_args(file_handle: Vec<u8>, offset: u64, data: &[u8]) -> Vec<u8> {
+        let args = WRITE3args {
+            file: fhandle3(file_handle),
+            offset,
+            count: data.len() as u32,
+            stable: stable_how::FILE_SYNC,
+            data: data.to_vec(),
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    fn reply_status(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
 
     #[test]
     fn test_write_file() {
@@ -148,8 +243,6 @@ mod tests {
         let file_handle = fs.lookup(&root_handle, "writetest.txt").unwrap();
 
         // Serialize WRITE3args
-        use crate::protocol::v3::nfs::{fhandle3, stable_how, WRITE3args};
-        use xdr_codec::Pack;
 
         let test_data = b"Hello, NFS World!";
         let args = WRITE3args {
@@ -164,7 +257,10 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call WRITE
-        let result = handle_write(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let result = handle_write(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "WRITE should succeed");
 
@@ -189,8 +285,6 @@ mod tests {
         let file_handle = fs.lookup(&root_handle, "offset.txt").unwrap();
 
         // Write at offset 5
-        use crate::protocol::v3::nfs::{fhandle3, stable_how, WRITE3args};
-        use xdr_codec::Pack;
 
         let test_data = b"ABCDE";
         let args = WRITE3args {
@@ -205,7 +299,10 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call WRITE
-        let result = handle_write(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let result = handle_write(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "WRITE with offset should succeed");
 
@@ -222,8 +319,6 @@ mod tests {
         let fs = config.create_filesystem().unwrap();
 
         // Use invalid file handle
-        use crate::protocol::v3::nfs::{fhandle3, stable_how, WRITE3args};
-        use xdr_codec::Pack;
 
         let test_data = b"test";
         let args = WRITE3args {
@@ -238,8 +333,211 @@ mod tests {
         args.pack(&mut args_buf).unwrap();
 
         // Call WRITE
-        let result = handle_write(12345, &args_buf, fs.as_ref());
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+        let result = handle_write(12345, &args_buf, fs.as_ref(), &ctx);
 
         assert!(result.is_ok(), "WRITE should return error response (not panic)");
     }
+
+    fn oversized_write_state(policy: OversizedWritePolicy) -> NfsState {
+        NfsState::new(NfsConfig {
+            wtmax: 16,
+            oversized_writes: policy,
+            write_hard_limit: 32,
+            ..NfsConfig::default()
+        })
+    }
+
+    #[test]
+    fn test_write_above_wtmax_rejected() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        fs::write(temp_dir.path().join("big.txt"), b"").unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "big.txt").unwrap();
+
+        let state = oversized_write_state(OversizedWritePolicy::Reject);
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        let args_buf = write_args(file_handle, 0, &[b'x'; 24]);
+        let reply = handle_write(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_INVAL as u32);
+        assert_eq!(fs::read(temp_dir.path().join("big.txt")).unwrap().len(), 0);
+
+        // file_wcc is filled in on failure too: pre_op_attr (24 bytes of
+        // wcc_attr) then post_op_attr
+        assert_eq!(&reply[28..32], &1u32.to_be_bytes(), "pre_op_attr follows");
+        assert_eq!(&reply[56..60], &1u32.to_be_bytes(), "post_op_attr follows");
+    }
+
+    #[test]
+    fn test_write_above_wtmax_accepted() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        fs::write(temp_dir.path().join("big.txt"), b"").unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "big.txt").unwrap();
+
+        let state = oversized_write_state(OversizedWritePolicy::Accept);
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        // Within the hard limit: written in full
+        let args_buf = write_args(file_handle.clone(), 0, &[b'x'; 24]);
+        let reply = handle_write(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+        assert_eq!(fs::read(temp_dir.path().join("big.txt")).unwrap().len(), 24);
+
+        // count follows status (4) + wcc_data (4 + 24 + 4 + 84)
+        let count = u32::from_be_bytes([reply[144], reply[145], reply[146], reply[147]]);
+        assert_eq!(count, 24, "Reported count should be the bytes actually written");
+
+        // Above the hard limit: rejected
+        let args_buf = write_args(file_handle, 0, &[b'x'; 40]);
+        let reply = handle_write(2, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3ERR_INVAL as u32);
+    }
+
+    fn write_args_stable(
+        file_handle: Vec<u8>,
+        offset: u64,
+        data: &[u8],
+        stable: stable_how,
+    ) -> Vec<u8> {
+        let args = WRITE3args {
+            file: fhandle3(file_handle),
+            offset,
+            count: data.len() as u32,
+            stable,
+            data: data.to_vec(),
+        };
+        let mut args_buf = Vec::new();
+        args.pack(&mut args_buf).unwrap();
+        args_buf
+    }
+
+    #[test]
+    fn test_write_past_eof_leaves_hole() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        fs::write(temp_dir.path().join("hole.txt"), b"").unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "hole.txt").unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        let args_buf = write_args(file_handle, 100, b"tail");
+        let reply = handle_write(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+        assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+
+        let content = fs::read(temp_dir.path().join("hole.txt")).unwrap();
+        assert_eq!(content.len(), 104);
+        assert!(content[..100].iter().all(|&b| b == 0), "Hole should read as zeros");
+        assert_eq!(&content[100..], b"tail");
+    }
+
+    #[test]
+    fn test_write_advances_mtime_and_ctime() {
+        use crate::fsal::{CacheStats, CachingFilesystem};
+        use std::os::unix::fs::MetadataExt;
+        use std::sync::Arc;
+        use std::time::{Duration, UNIX_EPOCH};
+
+        let temp_dir = TempDir::new().unwrap();
+        let test_file = temp_dir.path().join("times.txt");
+        fs::write(&test_file, b"test").unwrap();
+        let old = UNIX_EPOCH + Duration::from_secs(1_000_000_000);
+
+        // The post-op attributes must be fresh with or without the
+        // attribute cache in front of the backend
+        let plain = BackendConfig::local(temp_dir.path()).create_filesystem().unwrap();
+        let cached: Box<dyn Filesystem> = Box::new(CachingFilesystem::new(
+            BackendConfig::local(temp_dir.path()).create_filesystem().unwrap(),
+            Duration::from_secs(60),
+            100,
+            Arc::new(CacheStats::default()),
+        ));
+        for fs in [plain, cached] {
+            fs::File::options()
+                .write(true)
+                .open(&test_file)
+                .unwrap()
+                .set_times(fs::FileTimes::new().set_accessed(old).set_modified(old))
+                .unwrap();
+            let file_handle = fs.lookup(&fs.root_handle(), "times.txt").unwrap();
+            let before = fs.getattr(&file_handle).unwrap();
+            // Let the coarse kernel clock used for ctime tick over
+            std::thread::sleep(Duration::from_millis(20));
+
+            let state = NfsState::default();
+            let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+            let args_buf = write_args(file_handle, 0, b"more");
+            let reply = handle_write(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+            assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+
+            // pre_op_attr carries the size, mtime and ctime from before
+            assert_eq!(&reply[28..32], &[0, 0, 0, 1]);
+            assert_eq!(&reply[40..44], &1_000_000_000u32.to_be_bytes());
+
+            // post_op_attr follows at 56..60; atime, mtime and ctime are
+            // the last 24 bytes of the fattr3 at 60..144
+            assert_eq!(&reply[56..60], &[0, 0, 0, 1]);
+            let time = |at: usize| {
+                let seconds = u32::from_be_bytes(reply[at..at + 4].try_into().unwrap()) as u64;
+                let nseconds = u32::from_be_bytes(reply[at + 4..at + 8].try_into().unwrap());
+                (seconds, nseconds)
+            };
+            let (atime, mtime, ctime) = (time(120), time(128), time(136));
+            assert_eq!(atime, (1_000_000_000, 0), "WRITE must not touch atime");
+            assert!(mtime.0 > 1_000_000_000, "WRITE must advance mtime");
+            assert!(
+                ctime > (before.ctime.seconds, before.ctime.nseconds),
+                "WRITE must advance ctime"
+            );
+
+            let metadata = fs::metadata(&test_file).unwrap();
+            assert_eq!(mtime, (metadata.mtime() as u64, metadata.mtime_nsec() as u32));
+            assert_eq!(ctime, (metadata.ctime() as u64, metadata.ctime_nsec() as u32));
+        }
+    }
+
+    #[test]
+    fn test_write_stable_levels_and_verifier() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        fs::write(temp_dir.path().join("stable.txt"), b"").unwrap();
+        let file_handle = fs.lookup(&fs.root_handle(), "stable.txt").unwrap();
+
+        let state = NfsState::default();
+        let ctx = NfsContext::new("127.0.0.1:700".parse().unwrap(), &state)
+            .with_credentials(Credentials::root());
+
+        // committed follows count (144..148), then the 8-byte verifier
+        let cases = [
+            (stable_how::UNSTABLE, stable_how::UNSTABLE),
+            (stable_how::DATA_SYNC, stable_how::FILE_SYNC),
+            (stable_how::FILE_SYNC, stable_how::FILE_SYNC),
+        ];
+        for (requested, honored) in cases {
+            let args_buf = write_args_stable(file_handle.clone(), 0, b"data", requested);
+            let reply = handle_write(1, &args_buf, fs.as_ref(), &ctx).unwrap();
+            assert_eq!(reply_status(&reply), nfsstat3::NFS3_OK as u32);
+
+            let committed = u32::from_be_bytes([reply[148], reply[149], reply[150], reply[151]]);
+            assert_eq!(committed, honored as u32, "Requested {:?}", requested);
+            assert_eq!(&reply[152..160], &state.write_verifier.current());
+        }
+    }
 }
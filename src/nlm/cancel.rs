@@ -0,0 +1,29 @@
+// NLM CANCEL Procedure Handler
+//
+// Procedure: 3 (CANCEL)
+// Purpose: Cancel a blocked lock request
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::protocol::v3::nlm::{nlm4_stats, NlmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Handle NLM CANCEL procedure
+///
+/// Blocked requests are never queued (LOCK answers NLM4_BLOCKED and forgets
+/// them), so there is nothing to cancel and the call always succeeds.
+///
+/// Arguments: nlm4_cancargs
+/// Returns: nlm4_res
+pub fn handle(call: &rpc_call_msg, args_data: &[u8]) -> Result<BytesMut> {
+    let args = NlmMessage::deserialize_cancargs(args_data)?;
+    debug!(
+        "NLM CANCEL: caller={}, svid={}, offset={}, len={}",
+        args.alock.caller_name, args.alock.svid, args.alock.l_offset, args.alock.l_len
+    );
+
+    let res = NlmMessage::serialize_res(&args.cookie, nlm4_stats::NLM4_GRANTED)?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
@@ -0,0 +1,30 @@
+// NLM GRANTED Procedure Handler
+//
+// Procedure: 5 (GRANTED)
+// Purpose: Callback telling a client that a lock it waited for was granted
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::protocol::v3::nlm::{nlm4_stats, NlmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Handle NLM GRANTED procedure
+///
+/// GRANTED is sent by a lock manager to the client whose blocked request it
+/// granted. This server never waits for locks of its own, so no request can
+/// match and the callback is refused with NLM4_DENIED, as a client would.
+///
+/// Arguments: nlm4_testargs
+/// Returns: nlm4_res
+pub fn handle(call: &rpc_call_msg, args_data: &[u8]) -> Result<BytesMut> {
+    let args = NlmMessage::deserialize_testargs(args_data)?;
+    debug!(
+        "NLM GRANTED: caller={}, svid={}, offset={}, len={}",
+        args.alock.caller_name, args.alock.svid, args.alock.l_offset, args.alock.l_len
+    );
+
+    let res = NlmMessage::serialize_res(&args.cookie, nlm4_stats::NLM4_DENIED)?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
@@ -0,0 +1,79 @@
+// NLM LOCK Procedure Handler
+//
+// Procedure: 2 (LOCK)
+// Purpose: Acquire a byte-range lock
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::{debug, info};
+
+use crate::exports::ExportTable;
+use crate::nsm::StatusMonitor;
+use crate::protocol::v3::nlm::{nlm4_stats, NlmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::requested_lock;
+use super::table::LockTable;
+
+/// Handle NLM LOCK procedure
+///
+/// Grants the lock unless another owner holds a conflicting one. A blocking
+/// request that conflicts is answered with NLM4_BLOCKED; the request is not
+/// queued, so the client retries rather than waiting for a GRANTED callback.
+///
+/// The caller's host is monitored once it holds a lock, so it is told to
+/// reclaim its locks after this server restarts. During the grace period
+/// that follows a restart only reclaims are granted; other requests get
+/// NLM4_DENIED_GRACE_PERIOD and are retried by the client.
+///
+/// Arguments: nlm4_lockargs
+/// Returns: nlm4_res
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    exports: &ExportTable,
+    locks: &LockTable,
+    monitor: &StatusMonitor,
+) -> Result<BytesMut> {
+    let args = NlmMessage::deserialize_lockargs(args_data)?;
+    debug!(
+        "NLM LOCK: caller={}, svid={}, offset={}, len={}, exclusive={}, block={}, reclaim={}",
+        args.alock.caller_name,
+        args.alock.svid,
+        args.alock.l_offset,
+        args.alock.l_len,
+        args.exclusive,
+        args.block,
+        args.reclaim
+    );
+
+    let fh = &args.alock.fh.0;
+    let stat = if exports.for_handle(fh).is_none() {
+        nlm4_stats::NLM4_STALE_FH
+    } else if !args.reclaim && monitor.in_grace_period() {
+        debug!("NLM LOCK: not a reclaim, denied during the grace period");
+        nlm4_stats::NLM4_DENIED_GRACE_PERIOD
+    } else {
+        match locks.lock(fh, requested_lock(&args.alock, args.exclusive)) {
+            Ok(()) => {
+                monitor.monitor(&args.alock.caller_name);
+                info!(
+                    "NLM lock granted to {} (svid={}) at {}+{}",
+                    args.alock.caller_name, args.alock.svid, args.alock.l_offset, args.alock.l_len
+                );
+                nlm4_stats::NLM4_GRANTED
+            }
+            Err(held) => {
+                debug!("NLM LOCK: conflicts with lock held by svid={}", held.owner.svid);
+                if args.block {
+                    nlm4_stats::NLM4_BLOCKED
+                } else {
+                    nlm4_stats::NLM4_DENIED
+                }
+            }
+        }
+    };
+
+    let res = NlmMessage::serialize_res(&args.cookie, stat)?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
@@ -0,0 +1,288 @@
+// NLM Protocol Handlers
+//
+// Program: 100021 (NLM - Network Lock Manager)
+// Versions: 1, 3, 4 (NLMv4 is the one paired with NFSv3)
+//
+// NLM provides advisory byte-range locking for NFSv3 clients. Clients ping
+// the lock manager with NULL before attempting lock operations. Locking is
+// served for NLMv4 only; earlier versions carry 32-bit offsets and are only
+// answered for NULL.
+
+pub mod cancel;
+pub mod granted;
+pub mod lock;
+pub mod null;
+pub mod table;
+pub mod test;
+pub mod unlock;
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::{debug, warn};
+
+use crate::exports::ExportTable;
+use crate::nsm::StatusMonitor;
+use crate::protocol::v3::nlm::{netobj, nlm4_holder, nlm4_lock};
+use crate::protocol::v3::rpc::{accept_stat, rpc_call_msg, RpcMessage};
+
+pub use table::{Lock, LockOwner, LockTable};
+
+/// NLM program number
+pub const NLM_PROGRAM: u32 = 100021;
+
+/// Lowest NLM version answered
+pub const NLM_V1: u32 = 1;
+
+/// NLM version 4 (used with NFSv3)
+pub const NLM_V4: u32 = 4;
+
+/// NLM procedure numbers
+pub mod procedures {
+    pub const NULL: u32 = 0;
+    pub const TEST: u32 = 1;
+    pub const LOCK: u32 = 2;
+    pub const CANCEL: u32 = 3;
+    pub const UNLOCK: u32 = 4;
+    pub const GRANTED: u32 = 5;
+}
+
+/// Dispatch NLM procedure call to appropriate handler
+pub fn handle_nlm_call(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    exports: &ExportTable,
+    locks: &LockTable,
+    monitor: &StatusMonitor,
+) -> Result<BytesMut> {
+    debug!(
+        "Dispatching NLM call: proc={}, prog={}, vers={}",
+        call.proc_, call.prog, call.vers
+    );
+
+    // Verify version
+    if !(NLM_V1..=NLM_V4).contains(&call.vers) {
+        warn!(
+            "Unsupported NLM version: {} (supported: {}-{})",
+            call.vers, NLM_V1, NLM_V4
+        );
+        return RpcMessage::create_prog_mismatch_reply(call.xid, NLM_V1, NLM_V4);
+    }
+
+    // Dispatch to handler based on procedure number
+    match call.proc_ {
+        procedures::NULL => {
+            debug!("Routing to NLM NULL handler");
+            null::handle(call)
+        }
+        procedures::TEST if call.vers == NLM_V4 => {
+            debug!("Routing to NLM TEST handler");
+            test::handle(call, args_data, exports, locks)
+        }
+        procedures::LOCK if call.vers == NLM_V4 => {
+            debug!("Routing to NLM LOCK handler");
+            lock::handle(call, args_data, exports, locks, monitor)
+        }
+        procedures::CANCEL if call.vers == NLM_V4 => {
+            debug!("Routing to NLM CANCEL handler");
+            cancel::handle(call, args_data)
+        }
+        procedures::UNLOCK if call.vers == NLM_V4 => {
+            debug!("Routing to NLM UNLOCK handler");
+            unlock::handle(call, args_data, exports, locks)
+        }
+        procedures::GRANTED if call.vers == NLM_V4 => {
+            debug!("Routing to NLM GRANTED handler");
+            granted::handle(call, args_data)
+        }
+        _ => {
+            warn!(
+                "NLM procedure {} not implemented for version {}",
+                call.proc_, call.vers
+            );
+            RpcMessage::create_error_reply(call.xid, accept_stat::PROC_UNAVAIL)
+        }
+    }
+}
+
+/// Owner of the lock described by `alock`
+pub fn lock_owner(alock: &nlm4_lock) -> LockOwner {
+    LockOwner {
+        caller_name: alock.caller_name.clone(),
+        svid: alock.svid,
+        oh: alock.oh.0.clone(),
+    }
+}
+
+/// The lock `alock` asks for
+pub fn requested_lock(alock: &nlm4_lock, exclusive: bool) -> Lock {
+    Lock {
+        owner: lock_owner(alock),
+        exclusive,
+        offset: alock.l_offset,
+        len: alock.l_len,
+    }
+}
+
+/// Describe a held lock to a client whose request it blocks
+pub fn holder(lock: &Lock) -> nlm4_holder {
+    nlm4_holder {
+        exclusive: lock.exclusive,
+        svid: lock.owner.svid,
+        oh: netobj(lock.owner.oh.clone()),
+        l_offset: lock.offset,
+        l_len: lock.len,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::exports::Export;
+    use crate::fsal::BackendConfig;
+    use crate::protocol::v3::nlm::{nlm4_lockargs, nlm4_stats, nlm4_testargs, nlm4_unlockargs};
+    use crate::test_support::{call, packed};
+    use std::sync::Arc;
+    use tempfile::TempDir;
+
+    fn nlm_call(vers: u32, procedure: u32) -> rpc_call_msg {
+        call(42, NLM_PROGRAM, vers, procedure)
+    }
+
+    fn alock(fh: &[u8], svid: i32, l_offset: u64, l_len: u64) -> nlm4_lock {
+        nlm4_lock {
+            caller_name: "client".to_string(),
+            fh: netobj(fh.to_vec()),
+            oh: netobj(svid.to_be_bytes().to_vec()),
+            svid,
+            l_offset,
+            l_len,
+        }
+    }
+
+    /// nlm4_stats following an empty cookie in an accepted reply
+    fn stat(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[28], reply[29], reply[30], reply[31]])
+    }
+
+    #[test]
+    fn test_lock_test_unlock() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let fh = fs.root_handle();
+        let exports = ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]);
+        let locks = LockTable::new();
+        let nlm = |procedure, args: Vec<u8>| {
+            handle_nlm_call(
+                &nlm_call(NLM_V4, procedure),
+                &args,
+                &exports,
+                &locks,
+                &StatusMonitor::new(),
+            )
+            .unwrap()
+        };
+        let lockargs = |svid, block| nlm4_lockargs {
+            cookie: netobj(vec![]),
+            block,
+            exclusive: true,
+            alock: alock(&fh, svid, 0, 100),
+            reclaim: false,
+            state: 1,
+        };
+        let testargs = |svid| nlm4_testargs {
+            cookie: netobj(vec![]),
+            exclusive: false,
+            alock: alock(&fh, svid, 50, 1),
+        };
+
+        let reply = nlm(procedures::LOCK, packed(&lockargs(1, false)));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_GRANTED as u32);
+
+        let reply = nlm(procedures::LOCK, packed(&lockargs(2, false)));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_DENIED as u32);
+        let reply = nlm(procedures::LOCK, packed(&lockargs(2, true)));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_BLOCKED as u32);
+
+        // The denied TEST reply names the holder: exclusive, svid 1, 0+100
+        let reply = nlm(procedures::TEST, packed(&testargs(2)));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_DENIED as u32);
+        assert_eq!(&reply[32..36], &1u32.to_be_bytes());
+        assert_eq!(&reply[36..40], &1i32.to_be_bytes());
+        assert_eq!(&reply[reply.len() - 8..], &100u64.to_be_bytes());
+
+        let unlock = nlm4_unlockargs {
+            cookie: netobj(vec![]),
+            alock: alock(&fh, 1, 0, 0),
+        };
+        let reply = nlm(procedures::UNLOCK, packed(&unlock));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_GRANTED as u32);
+        let reply = nlm(procedures::TEST, packed(&testargs(2)));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_GRANTED as u32);
+        assert_eq!(reply.len(), 32);
+
+        // Handles no export issued are stale
+        let stale = nlm4_testargs {
+            alock: alock(&[0xDE, 0xAD], 2, 0, 0),
+            ..testargs(2)
+        };
+        let reply = nlm(procedures::TEST, packed(&stale));
+        assert_eq!(stat(&reply), nlm4_stats::NLM4_STALE_FH as u32);
+    }
+
+    #[test]
+    fn test_grace_period_admits_only_reclaims() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let fh = fs.root_handle();
+        let exports = ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]);
+
+        // A host held locks before the restart
+        let state_dir = TempDir::new().unwrap();
+        std::fs::write(state_dir.path().join("monitor"), "client\n").unwrap();
+        let (monitor, _) =
+            StatusMonitor::open(state_dir.path(), std::time::Duration::from_secs(60)).unwrap();
+
+        let locks = LockTable::new();
+        let lock = |svid, reclaim| {
+            let args = nlm4_lockargs {
+                cookie: netobj(vec![]),
+                block: false,
+                exclusive: true,
+                alock: alock(&fh, svid, svid as u64 * 100, 100),
+                reclaim,
+                state: 1,
+            };
+            let reply = handle_nlm_call(
+                &nlm_call(NLM_V4, procedures::LOCK),
+                &packed(&args),
+                &exports,
+                &locks,
+                &monitor,
+            )
+            .unwrap();
+            stat(&reply)
+        };
+        assert_eq!(lock(1, false), nlm4_stats::NLM4_DENIED_GRACE_PERIOD as u32);
+        assert_eq!(lock(2, true), nlm4_stats::NLM4_GRANTED as u32);
+    }
+
+    #[test]
+    fn test_locking_requires_v4() {
+        let exports = ExportTable::new(vec![]);
+        let reply = handle_nlm_call(
+            &nlm_call(3, procedures::LOCK),
+            &[],
+            &exports,
+            &LockTable::new(),
+            &StatusMonitor::new(),
+        )
+        .unwrap();
+        // accept_stat PROC_UNAVAIL
+        assert_eq!(&reply[20..24], &3u32.to_be_bytes());
+    }
+}
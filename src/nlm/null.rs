@@ -0,0 +1,26 @@
+// NLM NULL Procedure Handler
+//
+// Procedure: 0 (NULL)
+// Purpose: Test connectivity, clients probe the lock manager before locking
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Handle NLM NULL procedure
+///
+/// Takes no arguments and returns no data, only an RPC success reply.
+pub fn handle(call: &rpc_call_msg) -> Result<BytesMut> {
+    debug!(
+        "NLM NULL: xid={}, prog={}, vers={}, proc={}",
+        call.xid, call.prog, call.vers, call.proc_
+    );
+
+    // Create successful reply
+    let reply = RpcMessage::create_null_reply(call.xid);
+
+    // Serialize reply
+    RpcMessage::serialize_reply(&reply)
+}
@@ -0,0 +1,258 @@
+// NLM Lock Table
+//
+// Byte-range advisory locks granted by LOCK, keyed by NFS file handle. A lock
+// conflicts with another owner's overlapping lock unless both are shared.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+/// Who holds a lock: the client host and the process/owner handle it reported
+///
+/// The same process on different hosts (or with different owner handles) is a
+/// different owner.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct LockOwner {
+    pub caller_name: String,
+    pub svid: i32,
+    pub oh: Vec<u8>,
+}
+
+/// A byte-range lock; `len` 0 extends to end of file
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Lock {
+    pub owner: LockOwner,
+    pub exclusive: bool,
+    pub offset: u64,
+    pub len: u64,
+}
+
+impl Lock {
+    /// First byte past the range (u64::MAX for locks to end of file)
+    fn end(&self) -> u64 {
+        range_end(self.offset, self.len)
+    }
+
+    /// The same lock restricted to [start, end)
+    fn with_range(&self, start: u64, end: u64) -> Lock {
+        Lock {
+            offset: start,
+            len: if end == u64::MAX { 0 } else { end - start },
+            ..self.clone()
+        }
+    }
+
+    fn overlaps(&self, start: u64, end: u64) -> bool {
+        self.offset < end && start < self.end()
+    }
+
+    /// Whether `self` prevents `other` from being granted
+    fn conflicts_with(&self, other: &Lock) -> bool {
+        self.owner != other.owner
+            && (self.exclusive || other.exclusive)
+            && self.overlaps(other.offset, other.end())
+    }
+}
+
+/// Exclusive end of the range starting at `offset`, `len` 0 meaning to EOF
+fn range_end(offset: u64, len: u64) -> u64 {
+    if len == 0 {
+        u64::MAX
+    } else {
+        offset.saturating_add(len)
+    }
+}
+
+/// Locks held on each file, shared by all connections and transports
+#[derive(Clone)]
+pub struct LockTable {
+    files: Arc<Mutex<HashMap<Vec<u8>, Vec<Lock>>>>,
+}
+
+impl LockTable {
+    /// Create an empty lock table
+    pub fn new() -> Self {
+        Self {
+            files: Arc::new(Mutex::new(HashMap::new())),
+        }
+    }
+
+    /// A lock held on `fh` that would prevent `lock` from being granted (TEST)
+    pub fn test(&self, fh: &[u8], lock: &Lock) -> Option<Lock> {
+        let files = self.files.lock().unwrap();
+        files
+            .get(fh)
+            .and_then(|locks| locks.iter().find(|held| held.conflicts_with(lock)))
+            .cloned()
+    }
+
+    /// Grant `lock` on `fh` (LOCK)
+    ///
+    /// The owner's own locks in the range are replaced, so a shared lock can
+    /// be upgraded to exclusive and back. Returns the conflicting lock if
+    /// another owner holds one.
+    pub fn lock(&self, fh: &[u8], lock: Lock) -> Result<(), Lock> {
+        let mut files = self.files.lock().unwrap();
+        let locks = files.entry(fh.to_vec()).or_default();
+        if let Some(held) = locks.iter().find(|held| held.conflicts_with(&lock)) {
+            return Err(held.clone());
+        }
+
+        release(locks, &lock.owner, lock.offset, lock.end());
+        locks.push(lock);
+        Ok(())
+    }
+
+    /// Release `owner`'s locks on `fh` within `offset`/`len` (UNLOCK)
+    ///
+    /// Locks extending past the range keep their remaining parts. Unlocking a
+    /// range that is not locked is not an error.
+    pub fn unlock(&self, fh: &[u8], owner: &LockOwner, offset: u64, len: u64) {
+        let mut files = self.files.lock().unwrap();
+        if let Some(locks) = files.get_mut(fh) {
+            release(locks, owner, offset, range_end(offset, len));
+            if locks.is_empty() {
+                files.remove(fh);
+            }
+        }
+    }
+
+    /// Release every lock held by owners on `caller_name` (the host rebooted)
+    ///
+    /// Returns the number of locks released
+    pub fn release_host(&self, caller_name: &str) -> usize {
+        let mut files = self.files.lock().unwrap();
+        let mut released = 0;
+        files.retain(|_, locks| {
+            let before = locks.len();
+            locks.retain(|lock| lock.owner.caller_name != caller_name);
+            released += before - locks.len();
+            !locks.is_empty()
+        });
+        released
+    }
+}
+
+impl Default for LockTable {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Remove [start, end) from `owner`'s locks in `locks`, splitting as needed
+fn release(locks: &mut Vec<Lock>, owner: &LockOwner, start: u64, end: u64) {
+    let mut kept = Vec::with_capacity(locks.len());
+    for lock in locks.drain(..) {
+        if lock.owner != *owner || !lock.overlaps(start, end) {
+            kept.push(lock);
+            continue;
+        }
+        if lock.offset < start {
+            kept.push(lock.with_range(lock.offset, start));
+        }
+        if end < lock.end() {
+            kept.push(lock.with_range(end, lock.end()));
+        }
+    }
+    *locks = kept;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FH: &[u8] = &[1, 2, 3, 4];
+
+    fn owner(svid: i32) -> LockOwner {
+        LockOwner {
+            caller_name: "client".to_string(),
+            svid,
+            oh: svid.to_be_bytes().to_vec(),
+        }
+    }
+
+    fn lock(svid: i32, exclusive: bool, offset: u64, len: u64) -> Lock {
+        Lock {
+            owner: owner(svid),
+            exclusive,
+            offset,
+            len,
+        }
+    }
+
+    #[test]
+    fn test_conflicts() {
+        let table = LockTable::new();
+        table.lock(FH, lock(1, true, 0, 100)).unwrap();
+
+        // Overlapping ranges of another owner conflict; adjacent ones don't
+        assert_eq!(table.lock(FH, lock(2, false, 99, 1)), Err(lock(1, true, 0, 100)));
+        assert!(table.test(FH, &lock(2, true, 50, 0)).is_some());
+        assert!(table.test(FH, &lock(2, true, 100, 0)).is_none());
+        table.lock(FH, lock(2, true, 100, 10)).unwrap();
+
+        // Other files are unaffected
+        assert!(table.test(&[9], &lock(2, true, 0, 0)).is_none());
+    }
+
+    #[test]
+    fn test_shared_locks() {
+        let table = LockTable::new();
+        table.lock(FH, lock(1, false, 0, 0)).unwrap();
+        table.lock(FH, lock(2, false, 10, 10)).unwrap();
+
+        assert!(table.test(FH, &lock(3, false, 0, 0)).is_none());
+        assert_eq!(table.test(FH, &lock(3, true, 15, 1)), Some(lock(1, false, 0, 0)));
+    }
+
+    #[test]
+    fn test_own_locks_never_conflict() {
+        let table = LockTable::new();
+        table.lock(FH, lock(1, false, 0, 100)).unwrap();
+
+        // Upgrading part of the range replaces that part
+        table.lock(FH, lock(1, true, 40, 20)).unwrap();
+        assert!(table.test(FH, &lock(2, false, 0, 40)).is_none());
+        assert!(table.test(FH, &lock(2, false, 40, 1)).is_some());
+        assert!(table.test(FH, &lock(2, false, 60, 40)).is_none());
+        assert!(table.test(FH, &lock(2, true, 0, 1)).is_some());
+    }
+
+    #[test]
+    fn test_unlock_splits_range() {
+        let table = LockTable::new();
+        table.lock(FH, lock(1, true, 0, 0)).unwrap();
+
+        table.unlock(FH, &owner(1), 10, 10);
+        assert!(table.test(FH, &lock(2, true, 10, 10)).is_none());
+        assert!(table.test(FH, &lock(2, true, 9, 1)).is_some());
+        assert!(table.test(FH, &lock(2, true, 20, 1)).is_some());
+        assert!(table.test(FH, &lock(2, true, 1 << 40, 1)).is_some());
+
+        // Another owner's unlock releases nothing
+        table.unlock(FH, &owner(2), 0, 0);
+        assert!(table.test(FH, &lock(2, true, 0, 1)).is_some());
+
+        table.unlock(FH, &owner(1), 0, 0);
+        assert!(table.test(FH, &lock(2, true, 0, 0)).is_none());
+        assert!(table.files.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_release_host() {
+        let table = LockTable::new();
+        table.lock(FH, lock(1, true, 0, 10)).unwrap();
+        table.lock(&[9], lock(2, true, 0, 0)).unwrap();
+        let other_host = Lock {
+            owner: LockOwner {
+                caller_name: "other".to_string(),
+                ..owner(3)
+            },
+            ..lock(3, true, 20, 10)
+        };
+        table.lock(FH, other_host.clone()).unwrap();
+
+        assert_eq!(table.release_host("client"), 2);
+        assert!(table.test(FH, &lock(2, true, 0, 20)).is_none());
+        assert_eq!(table.test(FH, &lock(2, true, 0, 0)), Some(other_host));
+    }
+}
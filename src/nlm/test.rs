@@ -0,0 +1,58 @@
+// NLM TEST Procedure Handler
+//
+// Procedure: 1 (TEST)
+// Purpose: Check whether a lock could be granted, without taking it
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::exports::ExportTable;
+use crate::protocol::v3::nlm::{nlm4_stats, NlmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::table::LockTable;
+use super::{holder, requested_lock};
+
+/// Handle NLM TEST procedure
+///
+/// Reports NLM4_DENIED with the holder of the first conflicting lock, or
+/// NLM4_GRANTED if the lock is currently available. Nothing is recorded.
+///
+/// Arguments: nlm4_testargs
+/// Returns: nlm4_testres
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    exports: &ExportTable,
+    locks: &LockTable,
+) -> Result<BytesMut> {
+    let args = NlmMessage::deserialize_testargs(args_data)?;
+    debug!(
+        "NLM TEST: caller={}, svid={}, offset={}, len={}, exclusive={}",
+        args.alock.caller_name,
+        args.alock.svid,
+        args.alock.l_offset,
+        args.alock.l_len,
+        args.exclusive
+    );
+
+    let fh = &args.alock.fh.0;
+    let res = if exports.for_handle(fh).is_none() {
+        NlmMessage::serialize_testres(&args.cookie, nlm4_stats::NLM4_STALE_FH, None)?
+    } else {
+        match locks.test(fh, &requested_lock(&args.alock, args.exclusive)) {
+            Some(held) => {
+                debug!("NLM TEST: conflicts with lock held by svid={}", held.owner.svid);
+                NlmMessage::serialize_testres(
+                    &args.cookie,
+                    nlm4_stats::NLM4_DENIED,
+                    Some(&holder(&held)),
+                )?
+            }
+            None => NlmMessage::serialize_testres(&args.cookie, nlm4_stats::NLM4_GRANTED, None)?,
+        }
+    };
+
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
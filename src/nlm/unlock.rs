@@ -0,0 +1,55 @@
+// NLM UNLOCK Procedure Handler
+//
+// Procedure: 4 (UNLOCK)
+// Purpose: Release a byte-range lock
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::{debug, info};
+
+use crate::exports::ExportTable;
+use crate::protocol::v3::nlm::{nlm4_stats, NlmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::lock_owner;
+use super::table::LockTable;
+
+/// Handle NLM UNLOCK procedure
+///
+/// Releases the caller's locks within the range. Unlocking a range that is
+/// not locked still succeeds.
+///
+/// Arguments: nlm4_unlockargs
+/// Returns: nlm4_res
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    exports: &ExportTable,
+    locks: &LockTable,
+) -> Result<BytesMut> {
+    let args = NlmMessage::deserialize_unlockargs(args_data)?;
+    debug!(
+        "NLM UNLOCK: caller={}, svid={}, offset={}, len={}",
+        args.alock.caller_name, args.alock.svid, args.alock.l_offset, args.alock.l_len
+    );
+
+    let fh = &args.alock.fh.0;
+    let stat = if exports.for_handle(fh).is_none() {
+        nlm4_stats::NLM4_STALE_FH
+    } else {
+        locks.unlock(
+            fh,
+            &lock_owner(&args.alock),
+            args.alock.l_offset,
+            args.alock.l_len,
+        );
+        info!(
+            "NLM lock released by {} (svid={}) at {}+{}",
+            args.alock.caller_name, args.alock.svid, args.alock.l_offset, args.alock.l_len
+        );
+        nlm4_stats::NLM4_GRANTED
+    };
+
+    let res = NlmMessage::serialize_res(&args.cookie, stat)?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
@@ -0,0 +1,227 @@
+// NSM Protocol Handlers
+//
+// Program: 100024 (NSM - Network Status Monitor, "statd")
+// Version: 1
+//
+// NSM lets lock managers learn about peer reboots so stale locks can be
+// reclaimed or released. Clients ping it with NULL during lock setup.
+//
+// Hosts holding NLM locks (or named in SM_MON) are monitored; after a restart
+// they are sent SM_NOTIFY so they reclaim their locks, and an SM_NOTIFY from a
+// rebooted client releases the locks it held.
+
+pub mod mon;
+pub mod monitor;
+pub mod notify;
+pub mod null;
+pub mod reboot;
+pub mod stat;
+pub mod unmon;
+pub mod unmon_all;
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::net::IpAddr;
+use tracing::{debug, warn};
+
+use crate::nlm::LockTable;
+use crate::protocol::v3::rpc::{accept_stat, rpc_call_msg, RpcMessage};
+
+pub use monitor::StatusMonitor;
+
+/// NSM program number
+pub const NSM_PROGRAM: u32 = 100024;
+
+/// NSM version 1
+pub const NSM_V1: u32 = 1;
+
+/// NSM procedure numbers
+pub mod procedures {
+    pub const NULL: u32 = 0;
+    pub const STAT: u32 = 1;
+    pub const MON: u32 = 2;
+    pub const UNMON: u32 = 3;
+    pub const UNMON_ALL: u32 = 4;
+    pub const NOTIFY: u32 = 6;
+}
+
+/// Dispatch NSM procedure call to appropriate handler
+pub fn handle_nsm_call(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    client: IpAddr,
+    monitor: &StatusMonitor,
+    locks: &LockTable,
+) -> Result<BytesMut> {
+    debug!(
+        "Dispatching NSM call: proc={}, prog={}, vers={}",
+        call.proc_, call.prog, call.vers
+    );
+
+    // Verify version
+    if call.vers != NSM_V1 {
+        warn!("Unsupported NSM version: {} (supported: {})", call.vers, NSM_V1);
+        return RpcMessage::create_prog_mismatch_reply(call.xid, NSM_V1, NSM_V1);
+    }
+
+    // Dispatch to handler based on procedure number
+    match call.proc_ {
+        procedures::NULL => {
+            debug!("Routing to NSM NULL handler");
+            null::handle(call)
+        }
+        procedures::STAT => {
+            debug!("Routing to NSM STAT handler");
+            stat::handle(call, args_data, monitor)
+        }
+        procedures::MON => {
+            debug!("Routing to NSM MON handler");
+            mon::handle(call, args_data, client, monitor)
+        }
+        procedures::UNMON => {
+            debug!("Routing to NSM UNMON handler");
+            unmon::handle(call, args_data, client, monitor)
+        }
+        procedures::UNMON_ALL => {
+            debug!("Routing to NSM UNMON_ALL handler");
+            unmon_all::handle(call, args_data, client, monitor)
+        }
+        procedures::NOTIFY => {
+            debug!("Routing to NSM NOTIFY handler");
+            notify::handle(call, args_data, client, locks)
+        }
+        _ => {
+            warn!("NSM procedure {} not implemented", call.proc_);
+            RpcMessage::create_error_reply(call.xid, accept_stat::PROC_UNAVAIL)
+        }
+    }
+}
+
+/// Whether `client` is this host, the only caller SM_MON, SM_UNMON and
+/// SM_UNMON_ALL are taken from
+fn local_caller(client: IpAddr) -> bool {
+    client.to_canonical().is_loopback()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::nlm::{Lock, LockOwner};
+    use crate::protocol::v3::nsm::{mon, mon_id, my_id, stat_chge};
+    use crate::test_support::{call, packed};
+
+    fn nsm_call(procedure: u32) -> rpc_call_msg {
+        call(7, NSM_PROGRAM, NSM_V1, procedure)
+    }
+
+    const LOCALHOST: IpAddr = IpAddr::V4(std::net::Ipv4Addr::LOCALHOST);
+    const REMOTE: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(192, 0, 2, 1));
+
+    #[test]
+    fn test_mon_reports_state() {
+        let monitor = StatusMonitor::new();
+        let args = mon {
+            mon_id: mon_id {
+                mon_name: "client-a".to_string(),
+                my_id: my_id {
+                    my_name: "localhost".to_string(),
+                    my_prog: 100021,
+                    my_vers: 4,
+                    my_proc: 16,
+                },
+            },
+            priv_data: [0; 16],
+        };
+        let reply = handle_nsm_call(
+            &nsm_call(procedures::MON),
+            &packed(&args),
+            LOCALHOST,
+            &monitor,
+            &LockTable::new(),
+        )
+        .unwrap();
+        // sm_stat_res: STAT_SUCC, state 1
+        assert_eq!(&reply[24..28], &0u32.to_be_bytes());
+        assert_eq!(&reply[28..32], &1u32.to_be_bytes());
+        assert!(!monitor.monitor("client-a"), "already monitored");
+
+        // Other hosts can't change what is monitored
+        let reply = handle_nsm_call(
+            &nsm_call(procedures::MON),
+            &packed(&mon {
+                mon_id: mon_id {
+                    mon_name: "client-b".to_string(),
+                    ..args.mon_id.clone()
+                },
+                ..args.clone()
+            }),
+            REMOTE,
+            &monitor,
+            &LockTable::new(),
+        )
+        .unwrap();
+        assert_eq!(&reply[24..28], &1u32.to_be_bytes(), "STAT_FAIL");
+        assert!(!monitor.unmonitor("client-b"), "never monitored");
+        for procedure in [procedures::UNMON, procedures::UNMON_ALL] {
+            let args = match procedure {
+                procedures::UNMON => packed(&args.mon_id),
+                _ => packed(&args.mon_id.my_id),
+            };
+            let call = nsm_call(procedure);
+            handle_nsm_call(&call, &args, REMOTE, &monitor, &LockTable::new()).unwrap();
+        }
+        assert!(!monitor.monitor("client-a"), "still monitored");
+    }
+
+    #[test]
+    fn test_notify_releases_locks() {
+        let locks = LockTable::new();
+        let owner = LockOwner {
+            caller_name: "client-a".to_string(),
+            svid: 1,
+            oh: vec![1],
+        };
+        let held = Lock {
+            owner,
+            exclusive: true,
+            offset: 0,
+            len: 0,
+        };
+        locks.lock(&[1], held.clone()).unwrap();
+
+        let args = stat_chge {
+            mon_name: "client-a".to_string(),
+            state: 3,
+        };
+        // Only the host itself or the local statd is believed
+        let reply = handle_nsm_call(
+            &nsm_call(procedures::NOTIFY),
+            &packed(&args),
+            REMOTE,
+            &StatusMonitor::new(),
+            &locks,
+        )
+        .unwrap();
+        assert_eq!(reply.len(), 24);
+        assert!(locks.test(&[1], &held).is_none(), "still held by its owner");
+
+        let reply = handle_nsm_call(
+            &nsm_call(procedures::NOTIFY),
+            &packed(&args),
+            LOCALHOST,
+            &StatusMonitor::new(),
+            &locks,
+        )
+        .unwrap();
+        assert_eq!(reply.len(), 24);
+
+        let other_host = Lock {
+            owner: LockOwner {
+                caller_name: "client-b".to_string(),
+                ..held.owner.clone()
+            },
+            ..held
+        };
+        assert!(locks.test(&[1], &other_host).is_none());
+    }
+}
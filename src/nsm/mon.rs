@@ -0,0 +1,52 @@
+// NSM MON Procedure Handler
+//
+// Procedure: 2 (SM_MON)
+// Purpose: Start monitoring a host
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::net::IpAddr;
+use tracing::{debug, info, warn};
+
+use crate::protocol::v3::nsm::{res, NsmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::local_caller;
+use super::monitor::StatusMonitor;
+
+/// Handle NSM MON procedure
+///
+/// Records `mon_name` so it is sent an SM_NOTIFY after this server restarts.
+/// The callback in `my_id` is not called: the only state change this server
+/// acts on is a peer reboot, which NOTIFY handles directly.
+///
+/// Only the local lock manager may ask, as with rpc.statd; other callers get
+/// STAT_FAIL and nothing is recorded.
+///
+/// Arguments: mon
+/// Returns: sm_stat_res
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    client: IpAddr,
+    monitor: &StatusMonitor,
+) -> Result<BytesMut> {
+    let args = NsmMessage::deserialize_mon(args_data)?;
+    debug!(
+        "NSM MON: mon_name={}, my_name={}, my_prog={}",
+        args.mon_id.mon_name, args.mon_id.my_id.my_name, args.mon_id.my_id.my_prog
+    );
+
+    if !local_caller(client) {
+        warn!("NSM MON of {} refused: {} is not local", args.mon_id.mon_name, client);
+        let res = NsmMessage::serialize_sm_stat_res(res::STAT_FAIL, monitor.state())?;
+        return RpcMessage::create_success_reply_with_data(call.xid, res);
+    }
+
+    if monitor.monitor(&args.mon_id.mon_name) {
+        info!("Monitoring host {}", args.mon_id.mon_name);
+    }
+
+    let res = NsmMessage::serialize_sm_stat_res(res::STAT_SUCC, monitor.state())?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
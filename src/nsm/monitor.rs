@@ -0,0 +1,250 @@
+// NSM Monitor State
+//
+// The local state number and the hosts being monitored (clients holding
+// locks, or that asked with SM_MON). With a state directory both survive a
+// restart, so the previously monitored hosts can be told to reclaim their
+// locks; otherwise they only live in memory. A host monitored before a
+// restart stays on record until it has been told.
+
+use anyhow::{Context, Result};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tracing::warn;
+
+/// File holding the state number, in the state directory
+const STATE_FILE: &str = "state";
+
+/// File listing monitored hosts, one per line, in the state directory
+const MONITOR_FILE: &str = "monitor";
+
+struct Monitored {
+    state: u32,
+    hosts: BTreeSet<String>,
+    /// Hosts monitored before the restart that have not been notified yet
+    unnotified: BTreeSet<String>,
+}
+
+/// State number and monitored hosts, shared by all connections
+#[derive(Clone)]
+pub struct StatusMonitor {
+    inner: Arc<Mutex<Monitored>>,
+    state_dir: Option<PathBuf>,
+    /// End of the grace period after a restart, if there is one
+    grace_until: Option<Instant>,
+}
+
+impl StatusMonitor {
+    /// Create an in-memory monitor in state 1 (up, first boot)
+    pub fn new() -> Self {
+        Self {
+            inner: Arc::new(Mutex::new(Monitored {
+                state: 1,
+                hosts: BTreeSet::new(),
+                unnotified: BTreeSet::new(),
+            })),
+            state_dir: None,
+            grace_until: None,
+        }
+    }
+
+    /// Load the monitor kept in `state_dir` after a restart
+    ///
+    /// The state number advances to the next odd value (odd means up) and is
+    /// saved before returning. Also returns the hosts monitored before the
+    /// restart, which need an SM_NOTIFY; each stays on record (and is
+    /// returned again after another restart) until `notified` says it was
+    /// told. Until then it is not monitored for this run, unless it takes a
+    /// lock again. When there are such hosts, a grace period of `grace`
+    /// starts, in which only reclaimed locks are granted.
+    pub fn open(state_dir: &Path, grace: Duration) -> Result<(Self, Vec<String>)> {
+        fs::create_dir_all(state_dir)
+            .with_context(|| format!("Failed to create NSM state directory {}", state_dir.display()))?;
+
+        let previous = match fs::read_to_string(state_dir.join(STATE_FILE)) {
+            Ok(contents) => contents.trim().parse::<u32>().with_context(|| {
+                format!("Invalid NSM state number in {}", state_dir.display())
+            })?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => 0,
+            Err(e) => return Err(e.into()),
+        };
+        let rebooted_peers: Vec<String> = match fs::read_to_string(state_dir.join(MONITOR_FILE)) {
+            Ok(contents) => contents
+                .lines()
+                .map(str::trim)
+                .filter(|host| !host.is_empty())
+                .map(str::to_string)
+                .collect(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Vec::new(),
+            Err(e) => return Err(e.into()),
+        };
+
+        let monitored = Monitored {
+            state: next_state(previous),
+            hosts: BTreeSet::new(),
+            unnotified: rebooted_peers.iter().cloned().collect(),
+        };
+        fs::write(state_dir.join(STATE_FILE), format!("{}\n", monitored.state))
+            .with_context(|| format!("Failed to save NSM state in {}", state_dir.display()))?;
+
+        let monitor = Self {
+            inner: Arc::new(Mutex::new(monitored)),
+            state_dir: Some(state_dir.to_path_buf()),
+            grace_until: (!rebooted_peers.is_empty()).then(|| Instant::now() + grace),
+        };
+        monitor.save_hosts(&monitor.inner.lock().unwrap());
+        Ok((monitor, rebooted_peers))
+    }
+
+    /// Current state number
+    pub fn state(&self) -> u32 {
+        self.inner.lock().unwrap().state
+    }
+
+    /// Whether the grace period after a restart is still running
+    pub fn in_grace_period(&self) -> bool {
+        self.grace_until.is_some_and(|until| Instant::now() < until)
+    }
+
+    /// Record that `host`, monitored before the restart, has been sent its
+    /// SM_NOTIFY
+    pub fn notified(&self, host: &str) {
+        let mut monitored = self.inner.lock().unwrap();
+        if monitored.unnotified.remove(host) {
+            self.save_hosts(&monitored);
+        }
+    }
+
+    /// Start monitoring `host` (SM_MON)
+    ///
+    /// Returns true if it was not monitored already.
+    pub fn monitor(&self, host: &str) -> bool {
+        let mut monitored = self.inner.lock().unwrap();
+        let added = monitored.hosts.insert(host.to_string());
+        if added {
+            self.save_hosts(&monitored);
+        }
+        added
+    }
+
+    /// Stop monitoring `host` (SM_UNMON)
+    ///
+    /// Returns true if it was monitored.
+    pub fn unmonitor(&self, host: &str) -> bool {
+        let mut monitored = self.inner.lock().unwrap();
+        let removed = monitored.hosts.remove(host);
+        if removed {
+            self.save_hosts(&monitored);
+        }
+        removed
+    }
+
+    /// Stop monitoring every host (SM_UNMON_ALL)
+    ///
+    /// Returns the number of hosts that were monitored.
+    pub fn unmonitor_all(&self) -> usize {
+        let mut monitored = self.inner.lock().unwrap();
+        let count = monitored.hosts.len();
+        monitored.hosts.clear();
+        self.save_hosts(&monitored);
+        count
+    }
+
+    /// Write the monitored hosts, and those still to be notified, to the
+    /// state directory, if there is one
+    ///
+    /// A failure only costs the next restart's notifications, so it is
+    /// logged rather than failing the call.
+    fn save_hosts(&self, monitored: &Monitored) {
+        let Some(state_dir) = &self.state_dir else {
+            return;
+        };
+        let contents: String = monitored
+            .hosts
+            .union(&monitored.unnotified)
+            .map(|host| format!("{}\n", host))
+            .collect();
+        if let Err(e) = fs::write(state_dir.join(MONITOR_FILE), contents) {
+            warn!(
+                "Failed to save monitored hosts in {}: {}",
+                state_dir.display(),
+                e
+            );
+        }
+    }
+}
+
+impl Default for StatusMonitor {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The state number after a restart from `previous`: the next odd value
+fn next_state(previous: u32) -> u32 {
+    if previous % 2 == 1 {
+        previous.wrapping_add(2)
+    } else {
+        previous.wrapping_add(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_next_state() {
+        assert_eq!(next_state(0), 1);
+        assert_eq!(next_state(1), 3);
+        assert_eq!(next_state(4), 5);
+    }
+
+    #[test]
+    fn test_in_memory() {
+        let monitor = StatusMonitor::new();
+        assert_eq!(monitor.state(), 1);
+        assert!(monitor.monitor("client-a"));
+        assert!(!monitor.monitor("client-a"));
+        assert!(monitor.unmonitor("client-a"));
+        assert!(!monitor.unmonitor("client-a"));
+    }
+
+    #[test]
+    fn test_restart_reports_monitored_hosts() {
+        let temp_dir = TempDir::new().unwrap();
+
+        let grace = Duration::from_secs(60);
+        let (monitor, rebooted_peers) = StatusMonitor::open(temp_dir.path(), grace).unwrap();
+        assert_eq!(monitor.state(), 1);
+        assert!(rebooted_peers.is_empty());
+        assert!(!monitor.in_grace_period(), "nobody to wait for");
+        monitor.monitor("client-a");
+        monitor.monitor("client-b");
+        monitor.monitor("client-c");
+        monitor.unmonitor("client-b");
+
+        let (monitor, rebooted_peers) = StatusMonitor::open(temp_dir.path(), grace).unwrap();
+        assert_eq!(monitor.state(), 3);
+        assert_eq!(rebooted_peers, vec!["client-a", "client-c"]);
+        assert!(monitor.in_grace_period());
+
+        // Only notified hosts are dropped; the other is told after the next
+        // restart, and UNMON_ALL doesn't forget it either
+        monitor.notified("client-a");
+        assert_eq!(monitor.unmonitor_all(), 0);
+        let (monitor, rebooted_peers) = StatusMonitor::open(temp_dir.path(), grace).unwrap();
+        assert_eq!(monitor.state(), 5);
+        assert_eq!(rebooted_peers, vec!["client-c"]);
+
+        monitor.notified("client-c");
+        let (monitor, rebooted_peers) =
+            StatusMonitor::open(temp_dir.path(), Duration::ZERO).unwrap();
+        assert_eq!(monitor.state(), 7);
+        assert!(rebooted_peers.is_empty());
+        assert!(!monitor.in_grace_period());
+    }
+}
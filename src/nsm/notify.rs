@@ -0,0 +1,61 @@
+// NSM NOTIFY Procedure Handler
+//
+// Procedure: 6 (SM_NOTIFY)
+// Purpose: A peer announces that it rebooted
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::net::{IpAddr, ToSocketAddrs};
+use tracing::{debug, info, warn};
+
+use crate::nlm::LockTable;
+use crate::protocol::v3::nsm::NsmMessage;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::local_caller;
+
+/// Handle NSM NOTIFY procedure
+///
+/// A rebooted client has lost its processes, so every lock it held is
+/// released; it reclaims what it still needs with fresh LOCK calls.
+///
+/// Only the host itself (an address `mon_name` resolves to) or a local
+/// statd relaying for it is believed; a notification from anywhere else
+/// would let any host drop another's locks, and is ignored.
+///
+/// Arguments: stat_chge
+/// Returns: void
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    client: IpAddr,
+    locks: &LockTable,
+) -> Result<BytesMut> {
+    let args = NsmMessage::deserialize_stat_chge(args_data)?;
+    debug!("NSM NOTIFY: mon_name={}, state={}", args.mon_name, args.state);
+
+    if !local_caller(client) && !resolves_to(&args.mon_name, client) {
+        warn!(
+            "Ignoring NSM NOTIFY for {} from {}, which is not that host",
+            args.mon_name, client
+        );
+        let reply = RpcMessage::create_null_reply(call.xid);
+        return RpcMessage::serialize_reply(&reply);
+    }
+
+    let released = locks.release_host(&args.mon_name);
+    info!(
+        "Host {} restarted (state {}), released {} lock(s)",
+        args.mon_name, args.state, released
+    );
+
+    let reply = RpcMessage::create_null_reply(call.xid);
+    RpcMessage::serialize_reply(&reply)
+}
+
+/// Whether `host` (a name or address) resolves to `addr`
+fn resolves_to(host: &str, addr: IpAddr) -> bool {
+    (host, 0)
+        .to_socket_addrs()
+        .is_ok_and(|mut addrs| addrs.any(|resolved| resolved.ip().to_canonical() == addr.to_canonical()))
+}
@@ -0,0 +1,26 @@
+// NSM NULL Procedure Handler
+//
+// Procedure: 0 (NULL)
+// Purpose: Test connectivity, clients probe statd during lock setup
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Handle NSM NULL procedure
+///
+/// Takes no arguments and returns no data, only an RPC success reply.
+pub fn handle(call: &rpc_call_msg) -> Result<BytesMut> {
+    debug!(
+        "NSM NULL: xid={}, prog={}, vers={}, proc={}",
+        call.xid, call.prog, call.vers, call.proc_
+    );
+
+    // Create successful reply
+    let reply = RpcMessage::create_null_reply(call.xid);
+
+    // Serialize reply
+    RpcMessage::serialize_reply(&reply)
+}
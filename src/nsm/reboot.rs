@@ -0,0 +1,179 @@
+// NSM Reboot Notification
+//
+// After a restart, tell every host monitored before it (SM_NOTIFY) so their
+// lock managers reclaim the locks this server forgot. Each peer's statd port
+// is looked up with its portmapper; both calls go over UDP.
+
+use anyhow::{anyhow, Context, Result};
+use std::net::SocketAddr;
+use std::time::Duration;
+use tokio::net::UdpSocket;
+use tracing::{debug, info, warn};
+use xdr_codec::Pack;
+
+use crate::portmap::{procedures as portmap_procedures, PORTMAP_PROGRAM, PORTMAP_V2};
+use crate::protocol::v3::nsm::NsmMessage;
+use crate::protocol::v3::portmap::PortmapMessage;
+use crate::protocol::v3::rpc::{
+    accept_stat, auth_flavor, msg_type, opaque_auth, reply_stat, rpc_call_msg, RpcMessage,
+};
+
+use super::{procedures, StatusMonitor, NSM_PROGRAM, NSM_V1};
+
+/// Well-known portmapper port
+const PORTMAP_PORT: u16 = 111;
+
+/// IP protocol number for UDP, as used in portmapper mappings
+const IPPROTO_UDP: u32 = 17;
+
+/// How long to wait for each reply before retransmitting
+const CALL_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Transmissions per call before giving up on a peer
+const CALL_ATTEMPTS: u32 = 3;
+
+/// Send SM_NOTIFY announcing the state of `monitor` to each of `peers`
+///
+/// Peers are notified concurrently; each one reached is recorded in
+/// `monitor`. One that cannot be reached is logged and stays on record, to
+/// be notified after the next restart; until then it finds out when its
+/// lock calls fail.
+pub async fn notify_peers(monitor: StatusMonitor, peers: Vec<String>) {
+    let state = monitor.state();
+    let my_name = local_hostname();
+    info!(
+        "Notifying {} previously monitored host(s) of restart as {} (state {})",
+        peers.len(),
+        my_name,
+        state
+    );
+
+    let mut tasks = tokio::task::JoinSet::new();
+    for peer in peers {
+        let my_name = my_name.clone();
+        let monitor = monitor.clone();
+        tasks.spawn(async move {
+            match notify_peer(&peer, &my_name, state).await {
+                Ok(()) => {
+                    info!("Notified {} of restart", peer);
+                    monitor.notified(&peer);
+                }
+                Err(e) => warn!("Failed to notify {} of restart: {:#}", peer, e),
+            }
+        });
+    }
+    while tasks.join_next().await.is_some() {}
+}
+
+/// Send SM_NOTIFY to the statd on `peer`
+async fn notify_peer(peer: &str, my_name: &str, state: u32) -> Result<()> {
+    let portmapper = tokio::net::lookup_host((peer, PORTMAP_PORT))
+        .await
+        .with_context(|| format!("Failed to resolve {}", peer))?
+        .next()
+        .ok_or_else(|| anyhow!("No address for {}", peer))?;
+    let local: SocketAddr = if portmapper.is_ipv4() {
+        "0.0.0.0:0".parse()?
+    } else {
+        "[::]:0".parse()?
+    };
+    let socket = UdpSocket::bind(local).await?;
+
+    let mut getport = Vec::new();
+    PortmapMessage::create_mapping(NSM_PROGRAM, NSM_V1, IPPROTO_UDP, 0).pack(&mut getport)?;
+    let reply = call(
+        &socket,
+        portmapper,
+        (PORTMAP_PROGRAM, PORTMAP_V2, portmap_procedures::GETPORT),
+        &getport,
+    )
+    .await?;
+    let port = reply
+        .get(..4)
+        .map(|port| u32::from_be_bytes([port[0], port[1], port[2], port[3]]))
+        .ok_or_else(|| anyhow!("Short GETPORT reply from {}", peer))?;
+    if port == 0 || port > u16::MAX as u32 {
+        return Err(anyhow!("No statd registered on {}", peer));
+    }
+    debug!("statd on {} listens on port {}", peer, port);
+
+    let statd = SocketAddr::new(portmapper.ip(), port as u16);
+    let args = NsmMessage::serialize_stat_chge(my_name, state)?;
+    call(&socket, statd, (NSM_PROGRAM, NSM_V1, procedures::NOTIFY), &args).await?;
+    Ok(())
+}
+
+/// Make an AUTH_NONE RPC call to `(prog, vers, proc)` at `addr`
+///
+/// Retransmits after CALL_TIMEOUT, up to CALL_ATTEMPTS times. Returns the
+/// procedure result following the reply header.
+async fn call(
+    socket: &UdpSocket,
+    addr: SocketAddr,
+    (prog, vers, proc_): (u32, u32, u32),
+    args: &[u8],
+) -> Result<Vec<u8>> {
+    let xid = fresh_xid();
+    let none = || opaque_auth {
+        flavor: auth_flavor::AUTH_NONE,
+        body: vec![],
+    };
+    let mut message = RpcMessage::serialize_call(&rpc_call_msg {
+        xid,
+        mtype: msg_type::CALL,
+        rpcvers: 2,
+        prog,
+        vers,
+        proc_,
+        cred: none(),
+        verf: none(),
+    })?;
+    message.extend_from_slice(args);
+
+    let mut buf = vec![0u8; 8192];
+    for _ in 0..CALL_ATTEMPTS {
+        socket.send_to(&message, addr).await?;
+        let deadline = tokio::time::Instant::now() + CALL_TIMEOUT;
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv_from(&mut buf)).await {
+            let (len, from) = received?;
+            if from != addr {
+                continue;
+            }
+            let Ok((reply, header_len)) = RpcMessage::deserialize_reply(&buf[..len]) else {
+                continue;
+            };
+            if reply.xid != xid || reply.mtype != msg_type::REPLY {
+                continue;
+            }
+            if reply.stat != reply_stat::MSG_ACCEPTED || reply.accept_stat != accept_stat::SUCCESS {
+                return Err(anyhow!(
+                    "Call to program {} at {} failed: {:?}",
+                    prog,
+                    addr,
+                    reply.accept_stat
+                ));
+            }
+            return Ok(buf[header_len..len].to_vec());
+        }
+    }
+    Err(anyhow!("No reply from program {} at {}", prog, addr))
+}
+
+/// A transaction id unlikely to collide with earlier runs
+fn fresh_xid() -> u32 {
+    std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .map(|elapsed| elapsed.subsec_nanos() ^ elapsed.as_secs() as u32)
+        .unwrap_or(1)
+}
+
+/// This server's host name, as peers know it
+fn local_hostname() -> String {
+    let mut buf = [0u8; 256];
+    let rc = unsafe { libc::gethostname(buf.as_mut_ptr().cast(), buf.len()) };
+    if rc != 0 {
+        return "localhost".to_string();
+    }
+    let len = buf.iter().position(|&b| b == 0).unwrap_or(buf.len());
+    String::from_utf8_lossy(&buf[..len]).into_owned()
+}
@@ -0,0 +1,25 @@
+// NSM STAT Procedure Handler
+//
+// Procedure: 1 (SM_STAT)
+// Purpose: Report the local state number
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::protocol::v3::nsm::{res, NsmMessage};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::monitor::StatusMonitor;
+
+/// Handle NSM STAT procedure
+///
+/// Arguments: sm_name (the host the caller would like monitored)
+/// Returns: sm_stat_res with this server's state number
+pub fn handle(call: &rpc_call_msg, args_data: &[u8], monitor: &StatusMonitor) -> Result<BytesMut> {
+    let args = NsmMessage::deserialize_sm_name(args_data)?;
+    debug!("NSM STAT: mon_name={}", args.mon_name);
+
+    let res = NsmMessage::serialize_sm_stat_res(res::STAT_SUCC, monitor.state())?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
@@ -0,0 +1,46 @@
+// NSM UNMON Procedure Handler
+//
+// Procedure: 3 (SM_UNMON)
+// Purpose: Stop monitoring a host
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::net::IpAddr;
+use tracing::{debug, info, warn};
+
+use crate::protocol::v3::nsm::NsmMessage;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::local_caller;
+use super::monitor::StatusMonitor;
+
+/// Handle NSM UNMON procedure
+///
+/// Unmonitoring a host that is not monitored is not an error.
+///
+/// Only the local lock manager may ask; other callers are answered with the
+/// state number and nothing changes.
+///
+/// Arguments: mon_id
+/// Returns: sm_stat
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    client: IpAddr,
+    monitor: &StatusMonitor,
+) -> Result<BytesMut> {
+    let args = NsmMessage::deserialize_mon_id(args_data)?;
+    debug!(
+        "NSM UNMON: mon_name={}, my_name={}",
+        args.mon_name, args.my_id.my_name
+    );
+
+    if !local_caller(client) {
+        warn!("NSM UNMON of {} refused: {} is not local", args.mon_name, client);
+    } else if monitor.unmonitor(&args.mon_name) {
+        info!("No longer monitoring host {}", args.mon_name);
+    }
+
+    let res = NsmMessage::serialize_sm_stat(monitor.state())?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
@@ -0,0 +1,44 @@
+// NSM UNMON_ALL Procedure Handler
+//
+// Procedure: 4 (SM_UNMON_ALL)
+// Purpose: Stop monitoring every host
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::net::IpAddr;
+use tracing::{debug, info, warn};
+
+use crate::protocol::v3::nsm::NsmMessage;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+use super::local_caller;
+use super::monitor::StatusMonitor;
+
+/// Handle NSM UNMON_ALL procedure
+///
+/// Monitors are not tracked per caller, so every host is unmonitored.
+///
+/// Only the local lock manager may ask; other callers are answered with the
+/// state number and nothing changes.
+///
+/// Arguments: my_id
+/// Returns: sm_stat
+pub fn handle(
+    call: &rpc_call_msg,
+    args_data: &[u8],
+    client: IpAddr,
+    monitor: &StatusMonitor,
+) -> Result<BytesMut> {
+    let args = NsmMessage::deserialize_my_id(args_data)?;
+    debug!("NSM UNMON_ALL: my_name={}", args.my_name);
+
+    if local_caller(client) {
+        let count = monitor.unmonitor_all();
+        info!("No longer monitoring {} host(s)", count);
+    } else {
+        warn!("NSM UNMON_ALL refused: {} is not local", client);
+    }
+
+    let res = NsmMessage::serialize_sm_stat(monitor.state())?;
+    RpcMessage::create_success_reply_with_data(call.xid, res)
+}
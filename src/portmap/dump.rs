@@ -0,0 +1,85 @@
+// Portmapper DUMP Procedure Handler
+//
+// Procedure: 4 (PMAPPROC_DUMP)
+// Purpose: List all registered services (used by `rpcinfo -p`)
+
+use anyhow::Result;
+use bytes::BytesMut;
+use tracing::debug;
+
+use crate::portmap::registry::Registry;
+use crate::protocol::v3::portmap::PortmapMessage;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Handle Portmapper DUMP procedure
+///
+/// Returns every registered (prog, vers, prot, port) mapping.
+///
+/// Arguments: void
+/// Returns: pmaplist (optional linked list of mappings)
+pub fn handle(call: &rpc_call_msg, registry: &Registry) -> Result<BytesMut> {
+    debug!(
+        "PORTMAP DUMP: xid={}, prog={}, vers={}, proc={}",
+        call.xid, call.prog, call.vers, call.proc_
+    );
+
+    let mappings = registry.dump();
+    debug!("PORTMAP DUMP: {} mapping(s)", mappings.len());
+
+    let list = PortmapMessage::create_pmaplist(mappings);
+    let result_data = PortmapMessage::serialize_pmaplist(&list)?;
+
+    RpcMessage::create_success_reply_with_data(call.xid, result_data)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portmap::{procedures, PORTMAP_PROGRAM, PORTMAP_V2};
+    use crate::test_support::call;
+
+    fn dump_call() -> rpc_call_msg {
+        call(22, PORTMAP_PROGRAM, PORTMAP_V2, procedures::DUMP)
+    }
+
+    /// Decode the pmaplist from a reply into (prog, vers, prot, port) tuples
+    fn parse_pmaplist(reply: &[u8]) -> Vec<(u32, u32, u32, u32)> {
+        let words: Vec<u32> = reply[24..]
+            .chunks(4)
+            .map(|word| u32::from_be_bytes(word.try_into().unwrap()))
+            .collect();
+
+        let mut entries = Vec::new();
+        let mut rest = &words[..];
+        while rest[0] == 1 {
+            entries.push((rest[1], rest[2], rest[3], rest[4]));
+            rest = &rest[5..];
+        }
+        assert_eq!(rest, [0]);
+        entries
+    }
+
+    #[test]
+    fn test_dump_lists_registrations_in_order() {
+        let registry = Registry::new();
+        for (prog, vers) in [(100005, 3), (100000, 2), (100003, 3)] {
+            registry.set(&PortmapMessage::create_mapping(prog, vers, 6, 4000));
+        }
+
+        let reply = handle(&dump_call(), &registry).unwrap();
+        assert_eq!(
+            parse_pmaplist(&reply),
+            vec![
+                (100000, 2, 6, 4000),
+                (100003, 3, 6, 4000),
+                (100005, 3, 6, 4000),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_dump_empty_registry() {
+        let reply = handle(&dump_call(), &Registry::new()).unwrap();
+        assert!(parse_pmaplist(&reply).is_empty());
+    }
+}
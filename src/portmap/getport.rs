@@ -50,3 +50,60 @@ pub fn handle(call: &rpc_call_msg, args_data: &[u8], registry: &Registry) -> Res
 
     Ok(response)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::portmap::{procedures, PORTMAP_PROGRAM, PORTMAP_V2};
+    use crate::protocol::v3::portmap::mapping;
+    use crate::test_support::call;
+    use xdr_codec::Pack;
+
+    const IPPROTO_TCP: u32 = 6;
+
+    fn getport_call() -> rpc_call_msg {
+        call(21, PORTMAP_PROGRAM, PORTMAP_V2, procedures::GETPORT)
+    }
+
+    /// Send GETPORT for (prog, vers, TCP) and return the port in the reply
+    fn getport(registry: &Registry, prog: u32, vers: u32) -> u32 {
+        let mut args_buf = Vec::new();
+        PortmapMessage::create_mapping(prog, vers, IPPROTO_TCP, 0)
+            .pack(&mut args_buf)
+            .unwrap();
+        let reply = handle(&getport_call(), &args_buf, registry).unwrap();
+        assert_eq!(reply.len(), 28);
+        u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+    }
+
+    #[test]
+    fn test_getport_registered_services() {
+        let registry = Registry::new();
+        for prog in [100005, 100003] {
+            registry.set(&mapping {
+                prog,
+                vers: 3,
+                prot: IPPROTO_TCP,
+                port: 2049,
+            });
+        }
+
+        assert_eq!(getport(&registry, 100005, 3), 2049);
+        assert_eq!(getport(&registry, 100003, 3), 2049);
+    }
+
+    #[test]
+    fn test_getport_unregistered_returns_zero() {
+        let registry = Registry::new();
+        registry.set(&mapping {
+            prog: 100003,
+            vers: 3,
+            prot: IPPROTO_TCP,
+            port: 2049,
+        });
+
+        // Unknown program, and a known program at an unregistered version
+        assert_eq!(getport(&registry, 999999, 1), 0);
+        assert_eq!(getport(&registry, 100003, 4), 0);
+    }
+}
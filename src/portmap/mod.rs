@@ -6,6 +6,7 @@
 // The portmapper is a service discovery mechanism for RPC services.
 // Services register themselves (SET) and clients query for service ports (GETPORT).
 
+pub mod dump;
 pub mod getport;
 pub mod null;
 pub mod registry;
@@ -16,7 +17,7 @@ use anyhow::{anyhow, Result};
 use bytes::BytesMut;
 use tracing::{debug, warn};
 
-use crate::protocol::v3::rpc::rpc_call_msg;
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
 pub use registry::Registry;
 
 /// Portmapper program number (RFC 1833)
@@ -60,16 +61,13 @@ pub fn handle_portmap_call(
     }
 
     // Verify version 2
+    // rpcbind v3/v4 probes get PROG_MISMATCH, so the client falls back to v2
     if call.vers != PORTMAP_V2 {
         warn!(
-            "Expected PORTMAP version {}, got {}",
-            PORTMAP_V2, call.vers
+            "Unsupported PORTMAP version: {} (supported: {}-{})",
+            call.vers, PORTMAP_V2, PORTMAP_V2
         );
-        return Err(anyhow!(
-            "Unsupported PORTMAP version: expected {}, got {}",
-            PORTMAP_V2,
-            call.vers
-        ));
+        return RpcMessage::create_prog_mismatch_reply(call.xid, PORTMAP_V2, PORTMAP_V2);
     }
 
     // Dispatch to handler based on procedure number
@@ -91,8 +89,8 @@ pub fn handle_portmap_call(
             getport::handle(call, args_data, registry)
         }
         procedures::DUMP => {
-            warn!("PORTMAP DUMP not yet implemented");
-            Err(anyhow!("PORTMAP DUMP procedure not implemented"))
+            debug!("Routing to PORTMAP DUMP handler");
+            dump::handle(call, registry)
         }
         procedures::CALLIT => {
             warn!("PORTMAP CALLIT not supported");
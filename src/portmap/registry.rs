@@ -90,10 +90,12 @@ impl Registry {
     }
 
     /// Get all registered mappings (PMAPPROC_DUMP)
+    ///
+    /// Sorted by (prog, vers, prot) so listings are stable
     pub fn dump(&self) -> Vec<mapping> {
         let mappings = self.mappings.read().unwrap();
 
-        mappings
+        let mut list: Vec<mapping> = mappings
             .iter()
             .map(|((prog, vers, prot), port)| mapping {
                 prog: *prog,
@@ -101,7 +103,9 @@ impl Registry {
                 prot: *prot,
                 port: *port,
             })
-            .collect()
+            .collect();
+        list.sort_by_key(|map| (map.prog, map.vers, map.prot));
+        list
     }
 }
 
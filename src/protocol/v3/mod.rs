@@ -9,9 +9,13 @@ pub mod rpc;
 pub mod portmap;
 pub mod mount;
 pub mod nfs;
+pub mod nlm;
+pub mod nsm;
 
 // Re-export for convenience
 pub use rpc::RpcMessage;
 pub use portmap::PortmapMessage;
 pub use mount::MountMessage;
 pub use nfs::NfsMessage;
+pub use nlm::NlmMessage;
+pub use nsm::NsmMessage;
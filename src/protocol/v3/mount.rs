@@ -42,8 +42,63 @@ impl MountMessage {
         })
     }
 
+    /// Build the DUMP result list from (hostname, dirpath) pairs, in order
+    pub fn create_mount_list(mounts: &[(String, String)]) -> Option<Box<mountbody>> {
+        mounts.iter().rev().fold(None, |next, (hostname, path)| {
+            Some(Box::new(mountbody {
+                ml_hostname: name(hostname.clone()),
+                ml_directory: dirpath(path.clone()),
+                ml_next: next,
+            }))
+        })
+    }
+
+    /// Serialize a DUMP result list
+    pub fn serialize_mount_list(mounts: &Option<Box<mountbody>>) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        mounts.pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
+    /// Build the EXPORT result list, in the given order
+    ///
+    /// Each export is a (path, allowed client groups) pair; an empty group
+    /// list means every client may mount it.
+    pub fn create_export_list(exports: &[(String, Vec<String>)]) -> Option<Box<exportnode>> {
+        exports.iter().rev().fold(None, |next, (path, groups)| {
+            let ex_groups = groups.iter().rev().fold(None, |gr_next, group| {
+                Some(Box::new(groupnode {
+                    gr_name: name(group.clone()),
+                    gr_next,
+                }))
+            });
+            Some(Box::new(exportnode {
+                ex_dir: dirpath(path.clone()),
+                ex_groups,
+                ex_next: next,
+            }))
+        })
+    }
+
+    /// Serialize an EXPORT result list
+    pub fn serialize_exports(exports: &Option<Box<exportnode>>) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        exports.pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
     /// Create a mount error response (use the default variant)
     pub fn create_mount_error() -> mountres3 {
         mountres3::default
     }
+
+    /// Serialize a failed mountres3 carrying `status`
+    ///
+    /// The generated `default` variant cannot carry the error code, so the
+    /// status is packed directly (the failure arm has no body).
+    pub fn serialize_mount_error(status: mountstat3) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        (status as i32).pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
 }
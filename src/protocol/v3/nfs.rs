@@ -65,16 +65,48 @@ impl NfsMessage {
 
     /// Create a LOOKUP error response
     ///
-    /// LOOKUP error includes directory attributes in the failure case
-    pub fn create_lookup_error_response(status: nfsstat3) -> Result<BytesMut> {
-        // For LOOKUP error, we need status + post_op_attr (dir_attributes)
-        // Since we don't have dir_attributes in error path, we use FALSE for post_op_attr
+    /// LOOKUP error includes directory attributes in the failure case, when
+    /// they could be read
+    pub fn create_lookup_error_response(
+        status: nfsstat3,
+        dir_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
+        // status + post_op_attr (dir_attributes)
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
-        false.pack(&mut buf)?;  // dir_attributes: post_op_attr = FALSE (no attributes)
+        Self::pack_post_op_attr(&mut buf, dir_attributes)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
+    /// Pack post_op_attr: attributes_follow, then the attributes if present
+    fn pack_post_op_attr(buf: &mut Vec<u8>, attrs: Option<&fattr3>) -> Result<()> {
+        match attrs {
+            Some(attrs) => {
+                true.pack(buf)?; // attributes_follow = TRUE
+                attrs.pack(buf)?;
+            }
+            None => {
+                false.pack(buf)?; // attributes_follow = FALSE
+            }
+        }
+        Ok(())
+    }
+
+    /// Pack wcc_data: pre_op_attr (size, mtime, ctime before the change)
+    /// followed by post_op_attr
+    fn pack_wcc_data(buf: &mut Vec<u8>, before: Option<wcc_attr>, after: Option<&fattr3>) -> Result<()> {
+        match before {
+            Some(before) => {
+                true.pack(buf)?; // pre_op_attr: attributes_follow = TRUE
+                before.pack(buf)?;
+            }
+            None => {
+                false.pack(buf)?; // pre_op_attr: attributes_follow = FALSE
+            }
+        }
+        Self::pack_post_op_attr(buf, after)
+    }
+
     /// Deserialize READ request
     pub fn deserialize_read3args(data: &[u8]) -> Result<READ3args> {
         let mut cursor = Cursor::new(data);
@@ -104,12 +136,16 @@ impl NfsMessage {
         })
     }
 
-    /// Create a READ error response
-    pub fn create_read_error_response(status: nfsstat3) -> Result<BytesMut> {
+    /// Create a READ error response, with the file's attributes when they
+    /// could be read
+    pub fn create_read_error_response(
+        status: nfsstat3,
+        file_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
         // For READ error, we need status + post_op_attr (file_attributes)
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
-        false.pack(&mut buf)?;  // file_attributes: post_op_attr = FALSE (no attributes)
+        Self::pack_post_op_attr(&mut buf, file_attributes)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -141,14 +177,17 @@ impl NfsMessage {
         Ok(args)
     }
 
-    /// Create a WRITE error response
-    pub fn create_write_error_response(status: nfsstat3) -> Result<BytesMut> {
-        // For WRITE error, we need status + wcc_data (file_wcc)
-        // wcc_data = pre_op_attr + post_op_attr
+    /// Create a WRITE error response, with the file's wcc_data as far as
+    /// its attributes could be read
+    pub fn create_write_error_response(
+        status: nfsstat3,
+        before: Option<wcc_attr>,
+        after: Option<&fattr3>,
+    ) -> Result<BytesMut> {
+        // status + wcc_data (file_wcc)
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
-        false.pack(&mut buf)?;  // pre_op_attr = FALSE (no before attributes)
-        false.pack(&mut buf)?;  // post_op_attr = FALSE (no after attributes)
+        Self::pack_wcc_data(&mut buf, before, after)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -161,14 +200,17 @@ impl NfsMessage {
         Ok(args)
     }
 
-    /// Create a SETATTR error response
-    pub fn create_setattr_error_response(status: nfsstat3) -> Result<BytesMut> {
-        // For SETATTR error, we need status + wcc_data (obj_wcc)
-        // wcc_data = pre_op_attr + post_op_attr
+    /// Create a SETATTR error response, with the file's wcc_data as far as
+    /// its attributes could be read
+    pub fn create_setattr_error_response(
+        status: nfsstat3,
+        before: Option<wcc_attr>,
+        after: Option<&fattr3>,
+    ) -> Result<BytesMut> {
+        // status + wcc_data (obj_wcc)
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
-        false.pack(&mut buf)?;  // pre_op_attr = FALSE
-        false.pack(&mut buf)?;  // post_op_attr = FALSE
+        Self::pack_wcc_data(&mut buf, before, after)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -181,14 +223,17 @@ impl NfsMessage {
         Ok(args)
     }
 
-    /// Create a CREATE error response
-    pub fn create_create_error_response(status: nfsstat3) -> Result<BytesMut> {
-        // For CREATE error, we need status + dir_wcc
-        // dir_wcc = pre_op_attr + post_op_attr
+    /// Create a CREATE error response, with the directory's wcc_data as far as
+    /// its attributes could be read
+    pub fn create_create_error_response(
+        status: nfsstat3,
+        before: Option<wcc_attr>,
+        after: Option<&fattr3>,
+    ) -> Result<BytesMut> {
+        // status + wcc_data (dir_wcc)
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
-        false.pack(&mut buf)?;  // pre_op_attr = FALSE
-        false.pack(&mut buf)?;  // post_op_attr = FALSE
+        Self::pack_wcc_data(&mut buf, before, after)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -217,9 +262,14 @@ impl NfsMessage {
     }
 
     /// Create an ACCESS error response
-    pub fn create_access_error_response(status: nfsstat3) -> Result<BytesMut> {
+    pub fn create_access_error_response(
+        status: nfsstat3,
+        obj_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
+        // status + post_op_attr (obj_attributes)
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
+        Self::pack_post_op_attr(&mut buf, obj_attributes)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -264,10 +314,13 @@ impl NfsMessage {
     }
 
     /// Create an FSSTAT error response
-    pub fn create_fsstat_error_response(status: nfsstat3) -> Result<BytesMut> {
+    pub fn create_fsstat_error_response(
+        status: nfsstat3,
+        obj_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
-        false.pack(&mut buf)?;  // obj_attributes: post_op_attr = FALSE (no attributes)
+        Self::pack_post_op_attr(&mut buf, obj_attributes)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -341,15 +394,18 @@ impl NfsMessage {
 
     /// Create an FSINFO error response with manual post_op_attr serialization
     ///
-    /// Error responses have post_op_attr with discriminator FALSE (no attributes)
-    pub fn create_fsinfo_error_response(status: nfsstat3) -> Result<BytesMut> {
+    /// The root's attributes are included when they could be read
+    pub fn create_fsinfo_error_response(
+        status: nfsstat3,
+        obj_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
         let mut buf = Vec::new();
 
         // 1. nfsstat3 status (error code)
         (status as i32).pack(&mut buf)?;
 
-        // 2. post_op_attr: discriminator (bool FALSE = 0, no fattr3 follows)
-        false.pack(&mut buf)?;
+        // 2. post_op_attr (obj_attributes)
+        Self::pack_post_op_attr(&mut buf, obj_attributes)?;
 
         Ok(BytesMut::from(&buf[..]))
     }
@@ -398,6 +454,16 @@ impl NfsMessage {
         }
     }
 
+    /// The size, mtime and ctime of FSAL attributes, as sent in pre_op_attr
+    pub fn fsal_to_wcc_attr(attrs: &fsal::FileAttributes) -> wcc_attr {
+        let attrs = Self::fsal_to_fattr3(attrs);
+        wcc_attr {
+            size: attrs.size,
+            mtime: attrs.mtime,
+            ctime: attrs.ctime,
+        }
+    }
+
     /// Deserialize READDIR request
     pub fn deserialize_readdir3args(data: &[u8]) -> Result<READDIR3args> {
         let mut cursor = Cursor::new(data);
@@ -426,10 +492,15 @@ impl NfsMessage {
         })
     }
 
-    /// Create a READDIR error response
-    pub fn create_readdir_error_response(status: nfsstat3) -> Result<BytesMut> {
+    /// Create a READDIR error response, with the directory's attributes
+    /// when they could be read
+    pub fn create_readdir_error_response(
+        status: nfsstat3,
+        dir_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
+        Self::pack_post_op_attr(&mut buf, dir_attributes)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
@@ -440,10 +511,15 @@ impl NfsMessage {
         Ok(args)
     }
 
-    /// Create a READDIRPLUS error response
-    pub fn create_readdirplus_error_response(status: nfsstat3) -> Result<BytesMut> {
+    /// Create a READDIRPLUS error response, with the directory's attributes
+    /// when they could be read
+    pub fn create_readdirplus_error_response(
+        status: nfsstat3,
+        dir_attributes: Option<&fattr3>,
+    ) -> Result<BytesMut> {
         let mut buf = Vec::new();
         (status as i32).pack(&mut buf)?;
+        Self::pack_post_op_attr(&mut buf, dir_attributes)?;
         Ok(BytesMut::from(&buf[..]))
     }
 
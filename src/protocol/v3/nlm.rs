@@ -0,0 +1,79 @@
+// NLM Protocol Middleware
+//
+// Wraps xdrgen-generated NLMv4 types and provides serialization helpers
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::io::Cursor;
+use xdr_codec::{Pack, Unpack};
+
+// Include xdrgen-generated NLM types
+#[allow(dead_code, non_camel_case_types, non_snake_case, non_upper_case_globals, clippy::all)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/nlm_generated.rs"));
+}
+
+// Re-export generated types
+pub use generated::*;
+
+/// Wrapper for NLM messages providing serialization helpers
+pub struct NlmMessage;
+
+impl NlmMessage {
+    /// Deserialize TEST (and GRANTED) arguments
+    pub fn deserialize_testargs(data: &[u8]) -> Result<nlm4_testargs> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = nlm4_testargs::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize LOCK arguments
+    pub fn deserialize_lockargs(data: &[u8]) -> Result<nlm4_lockargs> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = nlm4_lockargs::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize CANCEL arguments
+    pub fn deserialize_cancargs(data: &[u8]) -> Result<nlm4_cancargs> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = nlm4_cancargs::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize UNLOCK arguments
+    pub fn deserialize_unlockargs(data: &[u8]) -> Result<nlm4_unlockargs> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = nlm4_unlockargs::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Serialize an nlm4_res (LOCK, CANCEL, UNLOCK and GRANTED result)
+    pub fn serialize_res(cookie: &netobj, stat: nlm4_stats) -> Result<BytesMut> {
+        let res = nlm4_res {
+            cookie: cookie.clone(),
+            stat,
+        };
+        let mut buf = Vec::new();
+        res.pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
+    /// Serialize an nlm4_testres
+    ///
+    /// The holder of the conflicting lock follows the status only when it is
+    /// NLM4_DENIED; every other status has no body.
+    pub fn serialize_testres(
+        cookie: &netobj,
+        stat: nlm4_stats,
+        holder: Option<&nlm4_holder>,
+    ) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        cookie.pack(&mut buf)?;
+        (stat as i32).pack(&mut buf)?;
+        if let (nlm4_stats::NLM4_DENIED, Some(holder)) = (stat, holder) {
+            holder.pack(&mut buf)?;
+        }
+        Ok(BytesMut::from(&buf[..]))
+    }
+}
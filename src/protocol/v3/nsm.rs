@@ -0,0 +1,89 @@
+// NSM Protocol Middleware
+//
+// Wraps xdrgen-generated NSM types and provides serialization helpers
+
+use anyhow::Result;
+use bytes::BytesMut;
+use std::io::Cursor;
+use xdr_codec::{Pack, Unpack};
+
+// Include xdrgen-generated NSM types
+#[allow(dead_code, non_camel_case_types, non_snake_case, non_upper_case_globals, clippy::all)]
+mod generated {
+    include!(concat!(env!("OUT_DIR"), "/nsm_generated.rs"));
+}
+
+// Re-export generated types
+pub use generated::*;
+
+/// Wrapper for NSM messages providing serialization helpers
+pub struct NsmMessage;
+
+impl NsmMessage {
+    /// Deserialize STAT arguments
+    pub fn deserialize_sm_name(data: &[u8]) -> Result<sm_name> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = sm_name::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize MON arguments
+    pub fn deserialize_mon(data: &[u8]) -> Result<mon> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = mon::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize UNMON arguments
+    pub fn deserialize_mon_id(data: &[u8]) -> Result<mon_id> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = mon_id::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize UNMON_ALL arguments
+    pub fn deserialize_my_id(data: &[u8]) -> Result<my_id> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = my_id::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Deserialize NOTIFY arguments
+    pub fn deserialize_stat_chge(data: &[u8]) -> Result<stat_chge> {
+        let mut cursor = Cursor::new(data);
+        let (args, _bytes_read) = stat_chge::unpack(&mut cursor)?;
+        Ok(args)
+    }
+
+    /// Serialize a STAT/MON result
+    pub fn serialize_sm_stat_res(res_stat: res, state: u32) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        sm_stat_res {
+            res_stat,
+            state: state as i32,
+        }
+        .pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
+    /// Serialize an UNMON/UNMON_ALL result
+    pub fn serialize_sm_stat(state: u32) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        sm_stat {
+            state: state as i32,
+        }
+        .pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
+    /// Serialize NOTIFY arguments, sent to peers after a restart
+    pub fn serialize_stat_chge(mon_name: &str, state: u32) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        stat_chge {
+            mon_name: mon_name.to_string(),
+            state: state as i32,
+        }
+        .pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+}
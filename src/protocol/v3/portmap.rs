@@ -42,6 +42,21 @@ impl PortmapMessage {
         Ok(BytesMut::from(&buf[..]))
     }
 
+    /// Build a DUMP result list from mappings, in order
+    pub fn create_pmaplist(mappings: Vec<mapping>) -> Option<Box<pmaplist>> {
+        mappings
+            .into_iter()
+            .rev()
+            .fold(None, |next, map| Some(Box::new(pmaplist { map, next })))
+    }
+
+    /// Serialize a DUMP result list
+    pub fn serialize_pmaplist(list: &Option<Box<pmaplist>>) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        list.pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
     /// Create a mapping entry
     pub fn create_mapping(prog: u32, vers: u32, prot: u32, port: u32) -> mapping {
         mapping {
@@ -27,6 +27,33 @@ impl RpcMessage {
         Ok(msg)
     }
 
+    /// Deserialize AUTH_SYS credential body (flavor 1)
+    ///
+    /// Decodes stamp, machinename, uid, gid and the supplementary gids
+    /// (at most 16).
+    pub fn deserialize_auth_sys(body: &[u8]) -> Result<auth_sys_params> {
+        let mut cursor = Cursor::new(body);
+        let (params, _bytes_read) = auth_sys_params::unpack(&mut cursor)?;
+        Ok(params)
+    }
+
+    /// Serialize RPC call to bytes (for calls this server makes)
+    pub fn serialize_call(call: &rpc_call_msg) -> Result<BytesMut> {
+        let mut buf = Vec::new();
+        call.pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
+    }
+
+    /// Deserialize an accepted RPC reply header
+    ///
+    /// Returns the header and its length; the procedure result follows it.
+    /// Denied replies have a different layout and fail to decode.
+    pub fn deserialize_reply(data: &[u8]) -> Result<(rpc_reply_msg, usize)> {
+        let mut cursor = Cursor::new(data);
+        let (msg, bytes_read) = rpc_reply_msg::unpack(&mut cursor)?;
+        Ok((msg, bytes_read))
+    }
+
     /// Serialize RPC reply to bytes
     pub fn serialize_reply(reply: &rpc_reply_msg) -> Result<BytesMut> {
         let mut buf = Vec::new();
@@ -64,8 +91,35 @@ impl RpcMessage {
         Ok(response)
     }
 
+    /// Create an accepted RPC reply carrying an error `stat` and no body
+    ///
+    /// Suitable for PROG_UNAVAIL, PROC_UNAVAIL, GARBAGE_ARGS and SYSTEM_ERR;
+    /// PROG_MISMATCH needs the supported range, see
+    /// `create_prog_mismatch_reply`.
+    pub fn create_error_reply(xid: u32, stat: accept_stat) -> Result<BytesMut> {
+        let rpc_reply = rpc_reply_msg {
+            xid,
+            mtype: msg_type::REPLY,
+            stat: reply_stat::MSG_ACCEPTED,
+            verf: opaque_auth {
+                flavor: auth_flavor::AUTH_NONE,
+                body: vec![],
+            },
+            accept_stat: stat,
+        };
+        Self::serialize_reply(&rpc_reply)
+    }
+
     /// Create an RPC error reply for unsupported programs
     pub fn create_prog_unavail_reply(xid: u32) -> Result<BytesMut> {
+        Self::create_error_reply(xid, accept_stat::PROG_UNAVAIL)
+    }
+
+    /// Create an RPC error reply for unsupported program versions
+    ///
+    /// The reply carries the lowest and highest supported versions
+    /// (mismatch_info) so the client can retry with a version we serve.
+    pub fn create_prog_mismatch_reply(xid: u32, low: u32, high: u32) -> Result<BytesMut> {
         let rpc_reply = rpc_reply_msg {
             xid,
             mtype: msg_type::REPLY,
@@ -74,8 +128,12 @@ impl RpcMessage {
                 flavor: auth_flavor::AUTH_NONE,
                 body: vec![],
             },
-            accept_stat: accept_stat::PROG_UNAVAIL,
+            accept_stat: accept_stat::PROG_MISMATCH,
         };
-        Self::serialize_reply(&rpc_reply)
+
+        let mut buf = Vec::new();
+        rpc_reply.pack(&mut buf)?;
+        mismatch_info { low, high }.pack(&mut buf)?;
+        Ok(BytesMut::from(&buf[..]))
     }
 }
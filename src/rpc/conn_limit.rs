@@ -0,0 +1,181 @@
+// Connection Limits
+//
+// Caps the number of simultaneous TCP connections from a single source IP, so
+// one host cannot exhaust the server by opening thousands of connections, and
+// the total across all clients.
+
+use std::collections::HashMap;
+use std::net::IpAddr;
+use std::sync::{Arc, Mutex};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+
+/// Server-wide cap on simultaneous connections; cheap to clone
+#[derive(Clone)]
+pub struct ConnectionSlots {
+    semaphore: Arc<Semaphore>,
+    max: usize,
+}
+
+impl ConnectionSlots {
+    /// Allow up to `max` simultaneous connections
+    pub fn new(max: usize) -> Self {
+        let max = max.min(Semaphore::MAX_PERMITS);
+        Self {
+            semaphore: Arc::new(Semaphore::new(max)),
+            max,
+        }
+    }
+
+    /// Take a free slot, or None if all are in use
+    ///
+    /// The slot is released when the returned permit is dropped.
+    pub fn try_acquire(&self) -> Option<OwnedSemaphorePermit> {
+        self.semaphore.clone().try_acquire_owned().ok()
+    }
+
+    /// Wait for a slot to become free
+    pub async fn acquire(&self) -> OwnedSemaphorePermit {
+        self.semaphore
+            .clone()
+            .acquire_owned()
+            .await
+            .expect("connection semaphore is never closed")
+    }
+
+    /// Number of connections currently holding a slot
+    pub fn active(&self) -> usize {
+        self.max - self.semaphore.available_permits()
+    }
+
+    /// Total number of slots
+    pub fn max(&self) -> usize {
+        self.max
+    }
+}
+
+/// Active connection counts per source IP
+pub struct ConnectionLimiter {
+    max_per_ip: u32,
+    active: Mutex<HashMap<IpAddr, u32>>,
+}
+
+impl ConnectionLimiter {
+    /// Create a limiter allowing `max_per_ip` connections per source IP
+    pub fn new(max_per_ip: u32) -> Self {
+        Self {
+            max_per_ip,
+            active: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Register a new connection from `ip`
+    ///
+    /// Returns None if the IP is already at its cap. Otherwise the returned
+    /// guard holds the slot until it is dropped (i.e. the connection closes).
+    pub fn try_acquire(self: &Arc<Self>, ip: IpAddr) -> Option<ConnectionGuard> {
+        let mut active = self.active.lock().unwrap();
+        let count = active.entry(ip).or_insert(0);
+        if *count >= self.max_per_ip {
+            if *count == 0 {
+                active.remove(&ip);
+            }
+            return None;
+        }
+        *count += 1;
+
+        Some(ConnectionGuard {
+            limiter: self.clone(),
+            ip,
+        })
+    }
+
+    /// Number of active connections from `ip`
+    #[cfg(test)]
+    fn active(&self, ip: IpAddr) -> u32 {
+        self.active.lock().unwrap().get(&ip).copied().unwrap_or(0)
+    }
+
+    fn release(&self, ip: IpAddr) {
+        let mut active = self.active.lock().unwrap();
+        if let Some(count) = active.get_mut(&ip) {
+            *count -= 1;
+            if *count == 0 {
+                active.remove(&ip);
+            }
+        }
+    }
+}
+
+/// Slot for one accepted connection, released on drop
+pub struct ConnectionGuard {
+    limiter: Arc<ConnectionLimiter>,
+    ip: IpAddr,
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        self.limiter.release(self.ip);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_connection_slots() {
+        let slots = ConnectionSlots::new(2);
+        let first = slots.try_acquire().unwrap();
+        let _second = slots.try_acquire().unwrap();
+        assert_eq!(slots.active(), 2);
+        assert!(slots.try_acquire().is_none());
+
+        // A waiter gets the slot as soon as a connection closes
+        let waiter = tokio::spawn({
+            let slots = slots.clone();
+            async move { slots.acquire().await }
+        });
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        drop(first);
+        let _third = waiter.await.unwrap();
+        assert_eq!(slots.active(), 2);
+    }
+
+    #[test]
+    fn test_cap_per_ip() {
+        let limiter = Arc::new(ConnectionLimiter::new(2));
+        let greedy: IpAddr = "10.0.0.1".parse().unwrap();
+        let other: IpAddr = "10.0.0.2".parse().unwrap();
+
+        let first = limiter.try_acquire(greedy).unwrap();
+        let _second = limiter.try_acquire(greedy).unwrap();
+        assert!(limiter.try_acquire(greedy).is_none(), "Excess connection should be refused");
+
+        // Other IPs are unaffected
+        let _other = limiter.try_acquire(other).unwrap();
+        assert_eq!(limiter.active(other), 1);
+
+        // Closing a connection frees a slot
+        drop(first);
+        assert_eq!(limiter.active(greedy), 1);
+        assert!(limiter.try_acquire(greedy).is_some());
+    }
+
+    #[test]
+    fn test_counts_drop_to_zero() {
+        let limiter = Arc::new(ConnectionLimiter::new(32));
+        let client: IpAddr = "10.0.0.1".parse().unwrap();
+
+        let guards: Vec<_> = (0..32)
+            .map(|_| limiter.try_acquire(client).unwrap())
+            .collect();
+        assert_eq!(limiter.active(client), 32);
+        assert!(limiter.try_acquire(client).is_none());
+
+        drop(guards);
+        assert_eq!(limiter.active(client), 0);
+        assert!(limiter.active.lock().unwrap().is_empty(), "Idle IPs should be dropped");
+    }
+}
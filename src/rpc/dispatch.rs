@@ -0,0 +1,724 @@
+// Transport-agnostic RPC dispatch
+//
+// Decodes a complete RPC call message and routes it to the program handler.
+// Shared by the TCP (record marking) and UDP (one datagram per message)
+// servers, so both see the same exports, mount and lock tables and NFS state.
+
+use anyhow::{anyhow, Result};
+use bytes::BytesMut;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Instant;
+use tracing::{debug, error, field, info_span, warn, Span};
+
+use crate::exports::{ExportTable, SharedExports};
+use crate::metrics::Metrics;
+use crate::mount::MountTable;
+use crate::nfs::credentials::squash_credentials;
+use crate::nfs::inflight::{InflightBudget, InflightGuard};
+use crate::nlm::LockTable;
+use crate::nsm::StatusMonitor;
+use crate::nfs::{Credentials, NfsContext, NfsState};
+use crate::portmap::Registry;
+use crate::protocol::v3::rpc::{accept_stat, auth_flavor, rpc_call_msg, RpcMessage};
+
+/// Server state needed to answer RPC calls, cheap to clone per connection
+#[derive(Clone)]
+pub struct RpcDispatcher {
+    registry: Registry,
+    nfs_state: Arc<NfsState>,
+    /// Served exports, each with its backend; replaced on reload
+    exports: SharedExports,
+    /// Paths mounted by each client, shared by all connections and transports
+    mounts: MountTable,
+    /// NLM byte-range locks, shared by all connections and transports
+    locks: LockTable,
+    /// NSM state number and monitored hosts
+    monitor: StatusMonitor,
+    /// Per-procedure call statistics
+    metrics: Arc<Metrics>,
+    /// Log raw calls and replies (see `rpc::trace`)
+    trace_xdr: bool,
+    /// Whether calls come over UDP, so replies must fit one datagram
+    udp: bool,
+}
+
+impl RpcDispatcher {
+    pub fn new(
+        registry: Registry,
+        nfs_state: Arc<NfsState>,
+        exports: ExportTable,
+    ) -> Self {
+        Self {
+            registry,
+            nfs_state,
+            exports: SharedExports::new(exports),
+            mounts: MountTable::new(),
+            locks: LockTable::new(),
+            monitor: StatusMonitor::new(),
+            metrics: Arc::new(Metrics::default()),
+            trace_xdr: false,
+            udp: false,
+        }
+    }
+
+    /// Answer NSM calls from `monitor` (restored from its state directory)
+    pub fn with_status_monitor(mut self, monitor: StatusMonitor) -> Self {
+        self.monitor = monitor;
+        self
+    }
+
+    /// Record call statistics in `metrics`
+    pub fn with_metrics(mut self, metrics: Arc<Metrics>) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Log every call and reply byte for byte, decoded where possible, at
+    /// debug level
+    pub fn with_xdr_trace(mut self, enabled: bool) -> Self {
+        self.trace_xdr = enabled;
+        self
+    }
+
+    /// Answer calls as received over UDP, offering transfer sizes whose
+    /// replies fit one datagram
+    pub fn for_udp(mut self) -> Self {
+        self.udp = true;
+        self
+    }
+
+    /// Budget a call's bytes are reserved against until its reply is sent
+    pub fn inflight(&self) -> Arc<InflightBudget> {
+        self.nfs_state.inflight.clone()
+    }
+
+    /// The export table calls are answered from, for swapping in a new one
+    pub fn exports(&self) -> SharedExports {
+        self.exports.clone()
+    }
+
+    /// Answer a complete RPC message from `peer_addr`
+    ///
+    /// Calls that fail are answered with an error reply so the client doesn't
+    /// wait for a timeout. Returns None when there is nothing to reply to.
+    ///
+    /// Everything logged while answering belongs to an `rpc_call` span
+    /// carrying the call header and peer; its outcome and elapsed time are
+    /// recorded on the span once the reply is ready. This span is also what
+    /// gets exported to OpenTelemetry when enabled.
+    pub fn dispatch(&self, data: &[u8], peer_addr: SocketAddr) -> Option<BytesMut> {
+        self.dispatch_reserved(data, peer_addr, &self.nfs_state.inflight.reserve(0))
+    }
+
+    /// `dispatch` a call whose bytes the transport holds in `reservation`;
+    /// handlers grow it for the payload of their reply
+    pub fn dispatch_reserved(
+        &self,
+        data: &[u8],
+        peer_addr: SocketAddr,
+        reservation: &InflightGuard,
+    ) -> Option<BytesMut> {
+        let word = |offset: usize| {
+            let bytes = data.get(offset..offset + 4)?;
+            Some(u32::from_be_bytes(bytes.try_into().ok()?))
+        };
+        let span = info_span!(
+            "rpc_call",
+            xid = word(0),
+            prog = word(12),
+            vers = word(16),
+            procedure = word(20),
+            export = field::Empty,
+            client = %peer_addr,
+            untrusted_machinename = field::Empty,
+            outcome = field::Empty,
+            elapsed_us = field::Empty,
+        );
+        let _enter = span.enter();
+        if self.trace_xdr {
+            super::trace::call(data);
+        }
+
+        let started = Instant::now();
+        let reply = self.answer(data, peer_addr, reservation);
+        let elapsed = started.elapsed();
+
+        let outcome = reply_outcome(word(12), word(20), reply.as_deref());
+        span.record("outcome", outcome.as_str());
+        span.record("elapsed_us", elapsed.as_micros() as u64);
+        debug!("RPC call finished: {} in {:?}", outcome, elapsed);
+        if let (true, Some(reply)) = (self.trace_xdr, &reply) {
+            super::trace::reply(reply);
+        }
+
+        self.metrics.record(data, reply.as_deref(), elapsed);
+        reply
+    }
+
+    /// `dispatch_reserved` on tokio's blocking thread pool
+    ///
+    /// Handlers do blocking filesystem I/O; running them here keeps a slow
+    /// disk from stalling the runtime threads other connections are served
+    /// on. `data` and `reservation` are handed back along with the reply so
+    /// callers can reuse their buffer and hold the reservation until the
+    /// reply is sent.
+    pub async fn dispatch_blocking<D>(
+        &self,
+        data: D,
+        reservation: InflightGuard,
+        peer_addr: SocketAddr,
+    ) -> (D, InflightGuard, Option<BytesMut>)
+    where
+        D: AsRef<[u8]> + Send + 'static,
+    {
+        let dispatcher = self.clone();
+        let parent = Span::current();
+        let answered = tokio::task::spawn_blocking(move || {
+            let reply = parent.in_scope(|| {
+                dispatcher.dispatch_reserved(data.as_ref(), peer_addr, &reservation)
+            });
+            (data, reservation, reply)
+        })
+        .await;
+        match answered {
+            Ok(answered) => answered,
+            // A panicking handler takes the connection down, as it would
+            // have on the runtime thread
+            Err(e) if e.is_panic() => std::panic::resume_unwind(e.into_panic()),
+            Err(e) => panic!("RPC handler for {} did not run: {}", peer_addr, e),
+        }
+    }
+
+    fn answer(
+        &self,
+        data: &[u8],
+        peer_addr: SocketAddr,
+        reservation: &InflightGuard,
+    ) -> Option<BytesMut> {
+        debug!("Complete RPC message received ({} bytes)", data.len());
+
+        // The whole call sees one export table, even if a reload lands
+        // while it runs
+        let exports = self.exports.current();
+        let e = match handle_rpc_message(
+            data,
+            peer_addr,
+            &self.registry,
+            &self.nfs_state,
+            &exports,
+            &self.mounts,
+            &self.locks,
+            &self.monitor,
+            self.udp,
+            reservation,
+        ) {
+            Ok(response) => return Some(response),
+            // The original call's reply answers the client
+            Err(e) if e.is::<crate::nfs::drc::InProgress>() => return None,
+            Err(e) => e,
+        };
+        error!("Failed to handle RPC message: {}", e);
+
+        // Without an XID there is nothing to reply to
+        if data.len() < 4 {
+            error!("Buffer too short to extract XID");
+            return None;
+        }
+        let xid = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let stat = error_accept_stat(&e);
+        match RpcMessage::create_error_reply(xid, stat) {
+            Ok(error_response) => {
+                warn!("Sending {:?} error response for xid={}", stat, xid);
+                Some(error_response)
+            }
+            Err(serialize_err) => {
+                error!("Failed to create error response: {}", serialize_err);
+                None
+            }
+        }
+    }
+}
+
+/// Handle a complete RPC message
+#[allow(clippy::too_many_arguments)]
+fn handle_rpc_message(
+    data: &[u8],
+    peer_addr: SocketAddr,
+    registry: &Registry,
+    nfs_state: &NfsState,
+    exports: &ExportTable,
+    mounts: &MountTable,
+    locks: &LockTable,
+    monitor: &StatusMonitor,
+    udp: bool,
+    reservation: &InflightGuard,
+) -> Result<BytesMut> {
+    // Debug: dump complete RPC message
+    debug!(
+        "Complete RPC message ({} bytes): {:02x?}",
+        data.len(),
+        &data[..data.len().min(100)]
+    );
+
+    // Deserialize RPC call header
+    let call = RpcMessage::deserialize_call(data)?;
+
+    // The machinename in AUTH_SYS is self-reported by the client and only
+    // useful for correlating logs; it must never be used for access decisions
+    let span = Span::current();
+    if let Some(machinename) = client_machinename(&call) {
+        span.record("untrusted_machinename", machinename.as_str());
+    }
+
+    debug!(
+        "RPC call: xid={}, prog={}, vers={}, proc={}, flavor={:?}",
+        call.xid, call.prog, call.vers, call.proc_, call.cred.flavor
+    );
+
+    // Calculate where procedure arguments start (after RPC call header)
+    // RPC call header: xid(4) + mtype(4) + rpcvers(4) + prog(4) + vers(4) + proc(4) = 24 bytes
+    // Then: opaque_auth cred + opaque_auth verf (variable length)
+    // opaque_auth = flavor(4) + length(4) + body(length bytes, padded to 4-byte boundary)
+
+    let mut offset = 24; // After fixed RPC header fields
+
+    // Parse credential (opaque_auth)
+    if data.len() < offset + 8 {
+        return Err(anyhow!("RPC message too short for credential header"));
+    }
+    let cred_length = u32::from_be_bytes([
+        data[offset + 4],
+        data[offset + 5],
+        data[offset + 6],
+        data[offset + 7],
+    ]) as usize;
+    let cred_padded = (cred_length + 3) & !3; // Round up to multiple of 4
+    offset += 8 + cred_padded; // flavor(4) + length(4) + body(padded)
+
+    debug!("Credential length: {} bytes (padded: {}), offset now: {}", cred_length, cred_padded, offset);
+
+    // Parse verifier (opaque_auth)
+    if data.len() < offset + 8 {
+        return Err(anyhow!("RPC message too short for verifier header"));
+    }
+    let verf_length = u32::from_be_bytes([
+        data[offset + 4],
+        data[offset + 5],
+        data[offset + 6],
+        data[offset + 7],
+    ]) as usize;
+    let verf_padded = (verf_length + 3) & !3; // Round up to multiple of 4
+    offset += 8 + verf_padded; // flavor(4) + length(4) + body(padded)
+
+    debug!("Verifier length: {} bytes (padded: {}), offset now: {}", verf_length, verf_padded, offset);
+
+    // Now offset points to the procedure arguments
+    let args_offset = offset;
+    let args_data = if data.len() > args_offset {
+        &data[args_offset..]
+    } else {
+        &[]
+    };
+
+    // Route to appropriate handler based on program number
+    match call.prog {
+        crate::portmap::PORTMAP_PROGRAM => {
+            // Portmapper protocol (program 100000)
+            debug!("Routing to PORTMAP protocol handler");
+            crate::portmap::handle_portmap_call(&call, args_data, registry)
+        }
+        crate::mount::MOUNT_PROGRAM => {
+            // MOUNT protocol (program 100005)
+            debug!("Routing to MOUNT protocol handler");
+            crate::mount::handle_mount_call(&call, args_data, exports, peer_addr.ip(), mounts)
+        }
+        crate::nfs::NFS_PROGRAM => {
+            // NFS protocol (program 100003)
+            debug!("Routing to NFS protocol handler");
+            // The call belongs to the export that issued its file handle;
+            // calls without one (NULL) or with a stale one go to the first
+            // export, which answers NFS3ERR_STALE without a handler running
+            let export = crate::nfs::file_handle_arg(args_data)
+                .and_then(|handle| exports.for_handle(handle))
+                .or_else(|| exports.first())
+                .ok_or_else(|| anyhow!("No exports configured"))?;
+            span.record("export", export.config.path.as_str());
+
+            // Squashing (and mapping AUTH_NONE onto the export's anonymous
+            // identity) happens here, before any handler can act on behalf
+            // of the caller
+            let credentials = Credentials::from_auth(&call.cred);
+            let ctx = NfsContext::new(peer_addr, nfs_state)
+                .with_credentials(squash_credentials(&credentials, &export.config))
+                .with_export(&export.config)
+                .with_udp(udp)
+                .with_reservation(reservation);
+            crate::nfs::dispatch(&call, args_data, export.filesystem.as_ref(), &ctx)
+        }
+        crate::nlm::NLM_PROGRAM => {
+            // NLM protocol (program 100021)
+            debug!("Routing to NLM protocol handler");
+            crate::nlm::handle_nlm_call(&call, args_data, exports, locks, monitor)
+        }
+        crate::nsm::NSM_PROGRAM => {
+            // NSM protocol (program 100024)
+            debug!("Routing to NSM protocol handler");
+            crate::nsm::handle_nsm_call(&call, args_data, peer_addr.ip(), monitor, locks)
+        }
+        _ => {
+            // Answer rather than fail, so the connection stays usable
+            warn!("Unknown program number: {}", call.prog);
+            RpcMessage::create_prog_unavail_reply(call.xid)
+        }
+    }
+}
+
+/// Short description of how a call was answered, for the `rpc_call` span
+///
+/// "no reply", "denied", the accept_stat of an unsuccessful call, and for
+/// NFS procedures other than NULL the nfsstat3 of the result.
+fn reply_outcome(prog: Option<u32>, procedure: Option<u32>, reply: Option<&[u8]>) -> String {
+    let Some(reply) = reply else {
+        return "no reply".to_string();
+    };
+    let word = |offset: usize| {
+        let bytes = reply.get(offset..offset + 4)?;
+        Some(u32::from_be_bytes(bytes.try_into().ok()?))
+    };
+
+    // reply_stat (MSG_ACCEPTED), then accept_stat after the empty verifier
+    if word(8) != Some(0) {
+        return "denied".to_string();
+    }
+    match word(20) {
+        Some(0) => {}
+        Some(stat) => return format!("accept_stat={}", stat),
+        None => return "malformed reply".to_string(),
+    }
+    match (prog, procedure, word(24)) {
+        (Some(crate::nfs::NFS_PROGRAM), Some(procedure), Some(status)) if procedure != 0 => {
+            format!("nfsstat3={}", status)
+        }
+        _ => "success".to_string(),
+    }
+}
+
+/// accept_stat to report for a call that could not be handled
+///
+/// Undecodable XDR (in the call header or the procedure arguments) and
+/// truncated messages are the client's fault (GARBAGE_ARGS); anything else is
+/// a server-side failure (SYSTEM_ERR).
+fn error_accept_stat(e: &anyhow::Error) -> accept_stat {
+    let garbage = e.chain().any(|cause| cause.is::<xdr_codec::Error>())
+        || e.to_string().contains("too short");
+    if garbage {
+        accept_stat::GARBAGE_ARGS
+    } else {
+        accept_stat::SYSTEM_ERR
+    }
+}
+
+/// Extract the client-reported machinename from an AUTH_SYS credential
+///
+/// Returns None for other flavors (e.g. AUTH_NONE) or an undecodable body.
+fn client_machinename(call: &rpc_call_msg) -> Option<String> {
+    match call.cred.flavor {
+        auth_flavor::AUTH_SYS => match RpcMessage::deserialize_auth_sys(&call.cred.body) {
+            Ok(params) => Some(params.machinename),
+            Err(e) => {
+                warn!("Failed to decode AUTH_SYS credential: {}", e);
+                None
+            }
+        },
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::exports::Export;
+    use crate::fsal::BackendConfig;
+    use crate::protocol::v3::rpc::{msg_type, opaque_auth};
+    use tempfile::TempDir;
+    use xdr_codec::Pack;
+
+    /// Encode a call to (prog, vers, proc) with an AUTH_SYS credential
+    fn call_bytes(prog: u32, vers: u32, procedure: u32) -> Vec<u8> {
+        let call = rpc_call_msg {
+            xid: 77,
+            mtype: msg_type::CALL,
+            rpcvers: 2,
+            prog,
+            vers,
+            proc_: procedure,
+            // Opaque AUTH_SYS body of an odd length, to exercise padding
+            cred: opaque_auth {
+                flavor: auth_flavor::AUTH_SYS,
+                body: vec![0; 21],
+            },
+            verf: opaque_auth {
+                flavor: auth_flavor::AUTH_NONE,
+                body: vec![],
+            },
+        };
+        let mut buf = Vec::new();
+        call.pack(&mut buf).unwrap();
+        buf
+    }
+
+    /// A single default export backed by `temp_dir`
+    fn exports(temp_dir: &TempDir) -> ExportTable {
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))])
+    }
+
+    /// A reservation against no limit, for calls answered outside a transport
+    fn unbudgeted() -> InflightGuard {
+        Arc::new(InflightBudget::new(None)).reserve(0)
+    }
+
+    fn send(data: &[u8]) -> BytesMut {
+        let temp_dir = TempDir::new().unwrap();
+        handle_rpc_message(
+            data,
+            "127.0.0.1:900".parse().unwrap(),
+            &Registry::new(),
+            &NfsState::default(),
+            &exports(&temp_dir),
+            &MountTable::new(),
+            &LockTable::new(),
+            &StatusMonitor::new(),
+            false,
+            &unbudgeted(),
+        )
+        .unwrap()
+    }
+
+    /// accept_stat of an accepted reply with an empty verifier
+    fn accept_stat(reply: &[u8]) -> u32 {
+        u32::from_be_bytes([reply[20], reply[21], reply[22], reply[23]])
+    }
+
+    #[test]
+    fn test_routes_known_programs() {
+        for (prog, vers) in [
+            (crate::portmap::PORTMAP_PROGRAM, crate::portmap::PORTMAP_V2),
+            (crate::mount::MOUNT_PROGRAM, crate::mount::MOUNT_V3),
+            (crate::nfs::NFS_PROGRAM, crate::nfs::NFS_V3),
+        ] {
+            let reply = send(&call_bytes(prog, vers, 0));
+            assert_eq!(&reply[0..4], &77u32.to_be_bytes());
+            assert_eq!(accept_stat(&reply), 0, "NULL to program {}", prog);
+        }
+    }
+
+    #[test]
+    fn test_unknown_program_gets_prog_unavail() {
+        let reply = send(&call_bytes(999999, 1, 0));
+        assert_eq!(&reply[0..4], &77u32.to_be_bytes());
+        assert_eq!(accept_stat(&reply), 1, "PROG_UNAVAIL");
+    }
+
+    #[test]
+    fn test_unsupported_versions_get_prog_mismatch() {
+        for (prog, vers, supported) in [
+            (crate::portmap::PORTMAP_PROGRAM, 4, crate::portmap::PORTMAP_V2),
+            (crate::mount::MOUNT_PROGRAM, 1, crate::mount::MOUNT_V3),
+            (crate::nfs::NFS_PROGRAM, 4, crate::nfs::NFS_V3),
+        ] {
+            let reply = send(&call_bytes(prog, vers, 0));
+            assert_eq!(accept_stat(&reply), 2, "PROG_MISMATCH for program {}", prog);
+            // mismatch_info: low, high
+            assert_eq!(&reply[24..28], &supported.to_be_bytes());
+            assert_eq!(&reply[28..32], &supported.to_be_bytes());
+        }
+    }
+
+    #[test]
+    fn test_error_accept_stat() {
+        // Truncated MOUNT arguments fail to decode
+        let data = call_bytes(crate::mount::MOUNT_PROGRAM, crate::mount::MOUNT_V3, 1);
+        let temp_dir = TempDir::new().unwrap();
+        let err = handle_rpc_message(
+            &data,
+            "127.0.0.1:900".parse().unwrap(),
+            &Registry::new(),
+            &NfsState::default(),
+            &exports(&temp_dir),
+            &MountTable::new(),
+            &LockTable::new(),
+            &StatusMonitor::new(),
+            false,
+            &unbudgeted(),
+        )
+        .unwrap_err();
+        assert_eq!(error_accept_stat(&err), accept_stat::GARBAGE_ARGS);
+
+        assert_eq!(
+            error_accept_stat(&anyhow!("RPC message too short for verifier header")),
+            accept_stat::GARBAGE_ARGS
+        );
+        assert_eq!(
+            error_accept_stat(&anyhow!("Failed to open backing file")),
+            accept_stat::SYSTEM_ERR
+        );
+    }
+
+    #[test]
+    fn test_mount_args_follow_credential() {
+        // MNT of "/" must decode its dirpath after the padded credential
+        let mut data = call_bytes(crate::mount::MOUNT_PROGRAM, crate::mount::MOUNT_V3, 1);
+        data.extend_from_slice(&1u32.to_be_bytes());
+        data.extend_from_slice(b"/\0\0\0");
+
+        let reply = send(&data);
+        assert_eq!(accept_stat(&reply), 0);
+        assert_eq!(&reply[24..28], &0u32.to_be_bytes(), "MNT3_OK");
+    }
+
+    #[test]
+    fn test_dispatch_replies_to_failed_calls() {
+        let temp_dir = TempDir::new().unwrap();
+        let dispatcher = RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(NfsState::default()),
+            exports(&temp_dir),
+        );
+        let peer = "127.0.0.1:900".parse().unwrap();
+
+        // Truncated MOUNT arguments get GARBAGE_ARGS rather than silence
+        let data = call_bytes(crate::mount::MOUNT_PROGRAM, crate::mount::MOUNT_V3, 1);
+        let reply = dispatcher.dispatch(&data, peer).unwrap();
+        assert_eq!(&reply[0..4], &77u32.to_be_bytes());
+        assert_eq!(accept_stat(&reply), accept_stat::GARBAGE_ARGS as u32);
+
+        // Nothing to answer without an XID
+        assert!(dispatcher.dispatch(&[0, 0], peer).is_none());
+    }
+
+    #[test]
+    fn test_nfs_calls_routed_by_file_handle() {
+        use crate::protocol::v3::nfs::{fhandle3, nfsstat3, GETATTR3args};
+
+        let (a, b) = (TempDir::new().unwrap(), TempDir::new().unwrap());
+        std::fs::write(b.path().join("only-in-b.txt"), b"data").unwrap();
+        let export = |path: &str, dir: &TempDir| {
+            let fs = BackendConfig::local(dir.path())
+                .create_filesystem()
+                .unwrap();
+            let config = ExportConfig {
+                path: path.to_string(),
+                ..ExportConfig::default()
+            };
+            Export::new(config, Arc::from(fs))
+        };
+        let exports = ExportTable::new(vec![export("/a", &a), export("/b", &b)]);
+        let fs_b = &exports.iter().nth(1).unwrap().filesystem;
+        let file = fs_b.lookup(&fs_b.root_handle(), "only-in-b.txt").unwrap();
+
+        let getattr = |handle: Vec<u8>| {
+            let mut data = call_bytes(crate::nfs::NFS_PROGRAM, crate::nfs::NFS_V3, 1);
+            GETATTR3args {
+                object: fhandle3(handle),
+            }
+            .pack(&mut data)
+            .unwrap();
+            let reply = handle_rpc_message(
+                &data,
+                "127.0.0.1:900".parse().unwrap(),
+                &Registry::new(),
+                &NfsState::default(),
+                &exports,
+                &MountTable::new(),
+                &LockTable::new(),
+                &StatusMonitor::new(),
+                false,
+                &unbudgeted(),
+            )
+            .unwrap();
+            u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+        };
+
+        assert_eq!(getattr(file), 0, "served by the second export");
+        assert_eq!(getattr(vec![0; 32]), nfsstat3::NFS3ERR_STALE as u32);
+    }
+
+    #[test]
+    fn test_reload_applies_root_squash() {
+        use crate::config::SquashPolicy;
+        use crate::protocol::v3::nfs::{fhandle3, stable_how, WRITE3args};
+        use crate::protocol::v3::nfs::nfsstat3;
+        use std::os::unix::fs::PermissionsExt;
+
+        let temp_dir = TempDir::new().unwrap();
+        let path = temp_dir.path().join("private");
+        std::fs::write(&path, b"data").unwrap();
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o644)).unwrap();
+        let config = ExportConfig {
+            squash: SquashPolicy::NoRootSquash,
+            ..ExportConfig::default()
+        };
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let file = fs.lookup(&fs.root_handle(), "private").unwrap();
+        let table = ExportTable::new(vec![Export::new(config.clone(), Arc::from(fs))]);
+        let dispatcher = RpcDispatcher::new(Registry::new(), Arc::new(NfsState::default()), table);
+
+        // WRITE by root (AUTH_SYS uid 0) to its 0644 file, a new xid each time
+        let write = |xid: u32| {
+            let mut data = call_bytes(crate::nfs::NFS_PROGRAM, crate::nfs::NFS_V3, 7);
+            data[0..4].copy_from_slice(&xid.to_be_bytes());
+            WRITE3args {
+                file: fhandle3(file.clone()),
+                offset: 0,
+                count: 4,
+                stable: stable_how::FILE_SYNC,
+                data: b"DATA".to_vec(),
+            }
+            .pack(&mut data)
+            .unwrap();
+            let reply = dispatcher.dispatch(&data, "127.0.0.1:900".parse().unwrap()).unwrap();
+            u32::from_be_bytes([reply[24], reply[25], reply[26], reply[27]])
+        };
+        assert_eq!(write(1), nfsstat3::NFS3_OK as u32);
+
+        // Once root is squashed it is the anonymous user, who may not write
+        let squashed = ExportConfig {
+            squash: SquashPolicy::RootSquash,
+            ..config
+        };
+        let exports = dispatcher.exports();
+        let table = exports.current().reconfigure(&[squashed], |_| unreachable!()).unwrap();
+        exports.reload(table, &dispatcher.nfs_state.reply_cache);
+        assert_eq!(write(2), nfsstat3::NFS3ERR_ACCES as u32);
+    }
+
+    #[test]
+    fn test_reply_outcome() {
+        let nfs = Some(crate::nfs::NFS_PROGRAM);
+        let reply = |words: &[u32]| -> Vec<u8> {
+            words.iter().flat_map(|word| word.to_be_bytes()).collect()
+        };
+
+        assert_eq!(reply_outcome(nfs, Some(1), None), "no reply");
+        assert_eq!(reply_outcome(nfs, Some(1), Some(&reply(&[1, 1, 1, 0, 0, 0]))), "denied");
+        assert_eq!(
+            reply_outcome(nfs, Some(1), Some(&reply(&[1, 1, 0, 0, 0, 4]))),
+            "accept_stat=4"
+        );
+        assert_eq!(
+            reply_outcome(nfs, Some(1), Some(&reply(&[1, 1, 0, 0, 0, 0, 70]))),
+            "nfsstat3=70"
+        );
+        assert_eq!(reply_outcome(nfs, Some(0), Some(&reply(&[1, 1, 0, 0, 0, 0]))), "success");
+
+        let mount = Some(crate::mount::MOUNT_PROGRAM);
+        assert_eq!(reply_outcome(mount, Some(5), Some(&reply(&[1, 1, 0, 0, 0, 0, 1]))), "success");
+    }
+}
@@ -1,5 +1,11 @@
 // RPC server module
 //
-// Provides TCP server with RPC record marking protocol
+// Provides TCP server with RPC record marking protocol, and a UDP server;
+// both hand complete messages to the shared dispatcher
 
+pub mod conn_limit;
+pub mod dispatch;
 pub mod server;
+pub mod shutdown;
+pub mod trace;
+pub mod udp;
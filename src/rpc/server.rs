@@ -3,65 +3,361 @@
 // Implements Sun RPC over TCP with record marking protocol (RFC 5531)
 
 use anyhow::{anyhow, Result};
-use bytes::{Buf, BufMut, BytesMut};
-use std::sync::Arc;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use bytes::BytesMut;
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use socket2::{SockRef, TcpKeepalive};
+use std::io::ErrorKind;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader};
 use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
 use tracing::{debug, error, info, warn};
 
-use crate::fsal::Filesystem;
-use crate::portmap::Registry;
-use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+use crate::config::ServerConfig;
+use crate::nfs::inflight::{InflightBudget, InflightGuard};
+
+use super::conn_limit::{ConnectionLimiter, ConnectionSlots};
+use super::dispatch::RpcDispatcher;
+use super::shutdown;
+
+/// Bytes read from a connection at once; record marks and small calls
+/// arriving together are then parsed without a syscall each
+const READ_BUFFER_SIZE: usize = 64 * 1024;
+
+/// Pause after a failed accept, so a lasting condition (out of file
+/// descriptors) doesn't spin the accept loop
+pub(crate) const ACCEPT_ERROR_BACKOFF: Duration = Duration::from_millis(100);
+
+/// Longest a record's later fragment waits for in-flight budget when no
+/// idle timeout is configured
+const FRAGMENT_BUDGET_WAIT: Duration = Duration::from_secs(30);
 
 /// RPC server handling TCP connections with record marking
 pub struct RpcServer {
-    addr: String,
-    registry: Registry,
-    filesystem: Arc<dyn Filesystem>,
+    /// Addresses listened on, all served alike
+    addrs: Vec<SocketAddr>,
+    dispatcher: RpcDispatcher,
+    connection_limiter: Arc<ConnectionLimiter>,
+    /// Server-wide connection cap
+    connection_slots: ConnectionSlots,
+    /// Largest RPC message (all fragments together) accepted from a client
+    max_message_size: usize,
+    /// Largest record fragment sent in a reply
+    max_fragment_size: usize,
+    /// Close connections that send nothing for this long
+    idle_timeout: Option<Duration>,
+    /// Options set on every accepted connection
+    socket_options: SocketOptions,
+    /// How long shutdown waits for in-flight requests
+    shutdown_grace_period: Duration,
+    /// Set once every listener is bound
+    bound: watch::Sender<bool>,
+    /// Addresses actually bound, port 0 resolved; empty until then
+    local_addrs: Mutex<Vec<SocketAddr>>,
 }
 
 impl RpcServer {
-    pub fn new(addr: String, registry: Registry, filesystem: Arc<dyn Filesystem>) -> Self {
+    pub fn new(addrs: Vec<SocketAddr>, dispatcher: RpcDispatcher, config: &ServerConfig) -> Self {
         Self {
-            addr,
-            registry,
-            filesystem,
+            addrs,
+            dispatcher,
+            connection_limiter: Arc::new(ConnectionLimiter::new(config.max_connections_per_ip)),
+            connection_slots: ConnectionSlots::new(config.max_connections),
+            max_message_size: config.max_message_size,
+            max_fragment_size: config.max_fragment_size,
+            idle_timeout: (config.idle_timeout_secs > 0)
+                .then(|| Duration::from_secs(config.idle_timeout_secs)),
+            socket_options: SocketOptions::from_config(config),
+            shutdown_grace_period: Duration::from_secs(config.shutdown_grace_period_secs),
+            bound: watch::channel(false).0,
+            local_addrs: Mutex::new(Vec::new()),
         }
     }
 
-    pub async fn run(&self) -> Result<()> {
-        let listener = TcpListener::bind(&self.addr).await?;
-        info!("RPC server listening on {}", self.addr);
+    /// Turns true once the server is accepting connections
+    pub fn bound(&self) -> watch::Receiver<bool> {
+        self.bound.subscribe()
+    }
+
+    /// Addresses the listeners are bound to, in the order configured;
+    /// empty until `bound` turns true
+    pub fn local_addrs(&self) -> Vec<SocketAddr> {
+        self.local_addrs.lock().unwrap().clone()
+    }
+
+    /// Server-wide connection slots, for reporting connection counts
+    pub fn connection_slots(&self) -> ConnectionSlots {
+        self.connection_slots.clone()
+    }
+
+    /// Serve connections until shutdown is requested on `shutdown`
+    ///
+    /// Connections on every address share the connection limits. On
+    /// shutdown the listeners are closed, every connection finishes the
+    /// request it is handling and closes, and connections still busy after
+    /// the grace period are aborted.
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+        let mut listeners = Vec::with_capacity(self.addrs.len());
+        let mut local_addrs = Vec::with_capacity(self.addrs.len());
+        for addr in &self.addrs {
+            let listener = TcpListener::bind(addr)
+                .await
+                .map_err(|e| anyhow!("Failed to listen on {}: {}", addr, e))?;
+            let local_addr = listener.local_addr()?;
+            info!("RPC server listening on {}", local_addr);
+            listeners.push(listener);
+            local_addrs.push(local_addr);
+        }
+        *self.local_addrs.lock().unwrap() = local_addrs;
+        self.bound.send_replace(true);
 
+        let mut connections = JoinSet::new();
+        let mut next_listener = 0;
         loop {
-            let (socket, peer_addr) = listener.accept().await?;
-            info!("New connection from {}", peer_addr);
+            let (socket, peer_addr) = tokio::select! {
+                accepted = accept_any(&listeners, &mut next_listener) => match accepted {
+                    Ok(accepted) => accepted,
+                    // EMFILE, ENFILE, a connection reset while queued: none
+                    // stops the server; new connections wait until it passes
+                    Err(e) => {
+                        warn!("Failed to accept connection: {}", e);
+                        tokio::time::sleep(ACCEPT_ERROR_BACKOFF).await;
+                        continue;
+                    }
+                },
+                // Reap finished connections so the set doesn't grow
+                Some(_) = connections.join_next() => continue,
+                _ = shutdown::requested(&mut shutdown) => break,
+            };
+
+            // Enforce the per-IP connection cap; dropping the socket closes it
+            let Some(connection_guard) = self.connection_limiter.try_acquire(peer_addr.ip())
+            else {
+                warn!(
+                    "Refusing connection from {}: per-IP connection limit reached",
+                    peer_addr
+                );
+                continue;
+            };
 
-            let registry = self.registry.clone();
-            let filesystem = self.filesystem.clone();
-            tokio::spawn(async move {
-                if let Err(e) = handle_connection(socket, registry, filesystem).await {
+            // At the server-wide cap, stop accepting until a connection
+            // closes; further clients queue in the listen backlog
+            let connection_slot = match self.connection_slots.try_acquire() {
+                Some(slot) => slot,
+                None => {
+                    warn!(
+                        "Connection limit reached ({} active), holding {} until a slot frees",
+                        self.connection_slots.active(),
+                        peer_addr
+                    );
+                    tokio::select! {
+                        slot = self.connection_slots.acquire() => slot,
+                        _ = shutdown::requested(&mut shutdown) => break,
+                    }
+                }
+            };
+            if let Err(e) = configure_socket(&socket, &self.socket_options) {
+                warn!("Failed to set socket options for {}: {}", peer_addr, e);
+            }
+            info!(
+                "New connection from {} ({} active)",
+                peer_addr,
+                self.connection_slots.active()
+            );
+
+            let dispatcher = self.dispatcher.clone();
+            let max_message_size = self.max_message_size;
+            let max_fragment_size = self.max_fragment_size;
+            let idle_timeout = self.idle_timeout;
+            let shutdown = shutdown.clone();
+            connections.spawn(async move {
+                // Hold the connection slots until the connection ends
+                let _connection_guard = connection_guard;
+                let _connection_slot = connection_slot;
+                if let Err(e) = handle_connection(
+                    socket,
+                    peer_addr,
+                    dispatcher,
+                    max_message_size,
+                    max_fragment_size,
+                    idle_timeout,
+                    shutdown,
+                )
+                .await
+                {
                     error!("Connection error from {}: {}", peer_addr, e);
                 }
             });
         }
+
+        drop(listeners);
+        info!(
+            "TCP server stopped accepting; draining {} connection(s)",
+            connections.len()
+        );
+        shutdown::drain(&mut connections, self.shutdown_grace_period).await;
+        Ok(())
     }
 }
 
+/// Accept a connection on whichever listener has one
+///
+/// Listeners are polled starting after the one that last accepted, so a
+/// busy address can't starve the others.
+async fn accept_any(
+    listeners: &[TcpListener],
+    next: &mut usize,
+) -> std::io::Result<(TcpStream, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        for i in 0..listeners.len() {
+            let index = (*next + i) % listeners.len();
+            if let Poll::Ready(accepted) = listeners[index].poll_accept(cx) {
+                *next = index + 1;
+                return Poll::Ready(accepted);
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Socket options of accepted connections
+#[derive(Debug, Clone, Copy, Default)]
+struct SocketOptions {
+    /// Set TCP_NODELAY, so small replies go out at once
+    nodelay: bool,
+    /// Probe after this long of silence, and again at this interval
+    keepalive: Option<Duration>,
+    /// SO_SNDBUF, if not left to the kernel
+    send_buffer: Option<usize>,
+    /// SO_RCVBUF, if not left to the kernel
+    recv_buffer: Option<usize>,
+}
+
+impl SocketOptions {
+    fn from_config(config: &ServerConfig) -> Self {
+        let nonzero = |size: usize| (size > 0).then_some(size);
+        Self {
+            nodelay: config.tcp_nodelay,
+            keepalive: (config.keepalive_secs > 0)
+                .then(|| Duration::from_secs(config.keepalive_secs)),
+            send_buffer: nonzero(config.send_buffer_size),
+            recv_buffer: nonzero(config.recv_buffer_size),
+        }
+    }
+}
+
+/// Apply the connection socket options
+fn configure_socket(socket: &TcpStream, options: &SocketOptions) -> std::io::Result<()> {
+    socket.set_nodelay(options.nodelay)?;
+    let sock = SockRef::from(socket);
+    if let Some(keepalive) = options.keepalive {
+        let params = TcpKeepalive::new()
+            .with_time(keepalive)
+            .with_interval(keepalive);
+        sock.set_tcp_keepalive(&params)?;
+    }
+    // A fixed size turns off the kernel's autotuning of that buffer; the
+    // kernel may grant a different size (Linux doubles it, capped by
+    // net.core.wmem_max / rmem_max)
+    if let Some(size) = options.send_buffer {
+        sock.set_send_buffer_size(size)?;
+        debug!("SO_SNDBUF {} requested, {} granted", size, sock.send_buffer_size()?);
+    }
+    if let Some(size) = options.recv_buffer {
+        sock.set_recv_buffer_size(size)?;
+        debug!("SO_RCVBUF {} requested, {} granted", size, sock.recv_buffer_size()?);
+    }
+    Ok(())
+}
+
 /// Handle a single TCP connection
 async fn handle_connection(
     mut socket: TcpStream,
-    registry: Registry,
-    filesystem: Arc<dyn Filesystem>,
+    peer_addr: SocketAddr,
+    dispatcher: RpcDispatcher,
+    max_message_size: usize,
+    max_fragment_size: usize,
+    idle_timeout: Option<Duration>,
+    mut shutdown: watch::Receiver<bool>,
 ) -> Result<()> {
     let mut buffer = BytesMut::with_capacity(8192);
+    let (reader, mut writer) = socket.split();
+    let mut reader = BufReader::with_capacity(READ_BUFFER_SIZE, reader);
+    let budget = dispatcher.inflight();
+
+    loop {
+        // Read a complete record; malformed or oversized records drop the
+        // connection, since the stream can't be resynchronized. On shutdown
+        // the connection closes between requests
+        let reservation = tokio::select! {
+            reservation = read_record(&mut reader, &mut buffer, max_message_size, idle_timeout, &budget) => reservation?,
+            _ = shutdown::requested(&mut shutdown) => {
+                debug!("Closing connection from {} for shutdown", peer_addr);
+                break;
+            }
+        };
+        let Some(reservation) = reservation else {
+            debug!("Connection from {} closed", peer_addr);
+            break;
+        };
+
+        let (message, reservation, reply) =
+            dispatcher.dispatch_blocking(buffer, reservation, peer_addr).await;
+        buffer = message;
+        let Some(response) = reply else {
+            continue;
+        };
+
+        // Send response with record marking; the reply stays reserved, in
+        // place of the call, until it is written
+        reservation.settle(response.len() as u64).await;
+        write_record_marked(&mut writer, &response, max_fragment_size).await?;
+        drop(reservation);
+
+        debug!("Sent response ({} bytes)", response.len());
+    }
+
+    Ok(())
+}
+
+/// Read one record-marked RPC message into `buffer`
+///
+/// Fragments are accumulated until the one with the last-fragment bit set.
+/// The message's bytes are reserved against `budget` before they are read,
+/// each fragment waiting until the budget has room for it. Records held
+/// part-way could wait on each other forever, so a later fragment that finds
+/// no room within `idle_timeout` (or `FRAGMENT_BUDGET_WAIT`) drops the
+/// connection; the client retransmits on a new one. Returns the reservation,
+/// to be held until the reply is sent, or None if the peer closed the
+/// connection, or sent nothing for `idle_timeout`, before a new record
+/// started. Fails if the message would exceed `max_message_size`, on an empty
+/// fragment that isn't the last one, or if the peer stalls for `idle_timeout`
+/// mid-record.
+async fn read_record<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buffer: &mut BytesMut,
+    max_message_size: usize,
+    idle_timeout: Option<Duration>,
+    budget: &Arc<InflightBudget>,
+) -> Result<Option<InflightGuard>> {
+    buffer.clear();
+    let mut reservation: Option<InflightGuard> = None;
 
     loop {
         // Read record marking fragment header (4 bytes)
         let mut header = [0u8; 4];
-        if socket.read_exact(&mut header).await.is_err() {
-            debug!("Connection closed by peer");
-            break;
+        if let Err(e) = read_exact_within(reader, &mut header, idle_timeout).await {
+            if buffer.is_empty() {
+                if e.kind() == ErrorKind::TimedOut {
+                    debug!("Closing connection idle for {:?}", idle_timeout.unwrap_or_default());
+                }
+                return Ok(None);
+            }
+            return Err(anyhow!("Connection closed mid-record: {}", e));
         }
 
         // Parse record marking header
@@ -76,152 +372,577 @@ async fn handle_connection(
             is_last, fragment_len
         );
 
+        if fragment_len == 0 && !is_last {
+            return Err(anyhow!("Empty non-last record fragment"));
+        }
+        if buffer.len() + fragment_len > max_message_size {
+            return Err(anyhow!(
+                "RPC message exceeds {} bytes (fragment of {} after {} bytes)",
+                max_message_size,
+                fragment_len,
+                buffer.len()
+            ));
+        }
+
+        match reservation.as_ref() {
+            Some(reservation) => {
+                let wait = idle_timeout.unwrap_or(FRAGMENT_BUDGET_WAIT);
+                tokio::time::timeout(wait, reservation.grow(fragment_len as u64))
+                    .await
+                    .map_err(|_| {
+                        anyhow!("No in-flight budget for the rest of the record within {:?}", wait)
+                    })?;
+            }
+            None => reservation = Some(budget.acquire(fragment_len as u64).await),
+        }
+
         // Read fragment data
-        let mut fragment = vec![0u8; fragment_len];
-        socket.read_exact(&mut fragment).await?;
-        buffer.put_slice(&fragment);
+        let start = buffer.len();
+        buffer.resize(start + fragment_len, 0);
+        read_exact_within(reader, &mut buffer[start..], idle_timeout).await?;
 
-        // If this is the last fragment, process the complete RPC message
         if is_last {
-            debug!("Complete RPC message received ({} bytes)", buffer.len());
-
-            let response = match handle_rpc_message(&buffer, &registry, filesystem.as_ref()) {
-                Ok(response) => response,
-                Err(e) => {
-                    error!("Failed to handle RPC message: {}", e);
-
-                    // Try to parse XID from buffer to send proper error response
-                    if buffer.len() >= 4 {
-                        let xid = u32::from_be_bytes([buffer[0], buffer[1], buffer[2], buffer[3]]);
-
-                        // Send PROG_UNAVAIL error response
-                        match RpcMessage::create_prog_unavail_reply(xid) {
-                            Ok(error_response) => {
-                                warn!("Sending PROG_UNAVAIL error response for xid={}", xid);
-                                error_response
-                            }
-                            Err(serialize_err) => {
-                                error!("Failed to create error response: {}", serialize_err);
-                                continue; // Skip this message and wait for next one
-                            }
-                        }
-                    } else {
-                        error!("Buffer too short to extract XID");
-                        continue; // Skip this message and wait for next one
-                    }
-                }
-            };
+            return Ok(reservation);
+        }
+    }
+}
+
+/// `read_exact`, failing with `TimedOut` if it takes longer than `timeout`
+async fn read_exact_within<R: AsyncRead + Unpin>(
+    reader: &mut R,
+    buf: &mut [u8],
+    timeout: Option<Duration>,
+) -> std::io::Result<()> {
+    let Some(timeout) = timeout else {
+        return reader.read_exact(buf).await.map(|_| ());
+    };
+    match tokio::time::timeout(timeout, reader.read_exact(buf)).await {
+        Ok(result) => result.map(|_| ()),
+        Err(_) => Err(std::io::Error::new(
+            ErrorKind::TimedOut,
+            format!("no data for {:?}", timeout),
+        )),
+    }
+}
+
+/// Write `data` as one record, split into fragments of at most
+/// `max_fragment` bytes with the last-fragment bit set on the final one
+///
+/// IMPORTANT: Record marks and payload are sent in a single write() to avoid
+/// TCP fragmentation causing client parsing issues.
+async fn write_record_marked<W: AsyncWrite + Unpin>(
+    socket: &mut W,
+    data: &[u8],
+    max_fragment: usize,
+) -> Result<()> {
+    // Fragment length is limited to 31 bits by the record mark
+    let max_fragment = max_fragment.clamp(1, 0x7FFFFFFF);
+    let fragments = data.len().div_ceil(max_fragment).max(1);
+
+    let mut record = Vec::with_capacity(4 * fragments + data.len());
+    let mut chunks = data.chunks(max_fragment).peekable();
+    if chunks.peek().is_none() {
+        // An empty reply is still one (last) fragment
+        record.extend_from_slice(&0x80000000u32.to_be_bytes());
+    }
+    while let Some(chunk) = chunks.next() {
+        let mut mark = chunk.len() as u32;
+        if chunks.peek().is_none() {
+            mark |= 0x80000000; // Set last fragment bit
+        }
+        record.extend_from_slice(&mark.to_be_bytes());
+        record.extend_from_slice(chunk);
+    }
+
+    socket.write_all(&record).await?;
+    socket.flush().await?;
+    Ok(())
+}
 
-            // Send response with record marking
-            // IMPORTANT: Record mark and payload must be sent in a single write()
-            // to avoid TCP fragmentation causing client parsing issues
-            let response_len = response.len() as u32;
-            let record_header = response_len | 0x80000000; // Set last fragment bit
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::exports::{Export, ExportTable};
+    use crate::fsal::BackendConfig;
+    use crate::nfs::NfsState;
+    use crate::portmap::Registry;
+    use tempfile::TempDir;
+
+    /// `read_record` without an in-flight limit; true if a record was read
+    async fn read_message<R: AsyncRead + Unpin>(
+        reader: &mut R,
+        buffer: &mut BytesMut,
+        max_message_size: usize,
+        idle_timeout: Option<Duration>,
+    ) -> Result<bool> {
+        let budget = Arc::new(InflightBudget::new(None));
+        let reservation = read_record(reader, buffer, max_message_size, idle_timeout, &budget).await?;
+        Ok(reservation.is_some())
+    }
 
-            // Combine record mark + payload into single buffer
-            let mut full_response = Vec::with_capacity(4 + response.len());
-            full_response.extend_from_slice(&record_header.to_be_bytes());
-            full_response.extend_from_slice(&response);
+    /// Encode `payload` as record fragments of `sizes` bytes, the last one
+    /// flagged as such
+    fn record(payload: &[u8], sizes: &[usize]) -> Vec<u8> {
+        let mut out = Vec::new();
+        let mut offset = 0;
+        for (i, &size) in sizes.iter().enumerate() {
+            let last = if i + 1 == sizes.len() { 0x80000000 } else { 0 };
+            out.extend_from_slice(&(size as u32 | last).to_be_bytes());
+            out.extend_from_slice(&payload[offset..offset + size]);
+            offset += size;
+        }
+        out
+    }
 
-            socket.write_all(&full_response).await?;
-            socket.flush().await?;
+    #[tokio::test]
+    async fn test_read_record_joins_fragments() {
+        let payload: Vec<u8> = (0..100).collect();
+        let mut stream = record(&payload, &[10, 0x30, 42]);
+        stream.extend_from_slice(&record(b"next", &[4]));
+
+        let mut reader = stream.as_slice();
+        let mut buffer = BytesMut::new();
+        assert!(read_message(&mut reader, &mut buffer, 1024, None).await.unwrap());
+        assert_eq!(&buffer[..], &payload[..]);
+        assert!(read_message(&mut reader, &mut buffer, 1024, None).await.unwrap());
+        assert_eq!(&buffer[..], b"next");
+
+        // Clean end of stream between records
+        assert!(!read_message(&mut reader, &mut buffer, 1024, None).await.unwrap());
+    }
 
-            debug!("Sent response ({} bytes)", response.len());
+    #[tokio::test]
+    async fn test_read_record_rejects_oversized_message() {
+        let payload = vec![0u8; 100];
+        let mut buffer = BytesMut::new();
+
+        // A single fragment over the limit is refused before it is read
+        let stream = record(&payload, &[100]);
+        assert!(read_message(&mut stream.as_slice(), &mut buffer, 64, None).await.is_err());
+
+        // As are fragments that only add up to more than the limit
+        let stream = record(&payload, &[50, 50]);
+        assert!(read_message(&mut stream.as_slice(), &mut buffer, 64, None).await.is_err());
+        assert!(read_message(&mut stream.as_slice(), &mut buffer, 100, None).await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_read_record_rejects_empty_fragment() {
+        let mut stream = 0u32.to_be_bytes().to_vec();
+        stream.extend_from_slice(&record(b"call", &[4]));
+
+        let mut buffer = BytesMut::new();
+        assert!(read_message(&mut stream.as_slice(), &mut buffer, 1024, None).await.is_err());
+
+        // An empty last fragment just ends the record
+        let stream = record(b"call", &[4, 0]);
+        assert!(read_message(&mut stream.as_slice(), &mut buffer, 1024, None).await.unwrap());
+        assert_eq!(&buffer[..], b"call");
+    }
+
+    #[tokio::test]
+    async fn test_read_record_waits_for_budget() {
+        let budget = Arc::new(InflightBudget::new(Some(16)));
+        let held = budget.try_acquire(16).unwrap();
+        let stream = record(b"12345678", &[8]);
+
+        let reading = tokio::spawn({
+            let budget = budget.clone();
+            async move {
+                let mut buffer = BytesMut::new();
+                read_record(&mut stream.as_slice(), &mut buffer, 1024, None, &budget).await
+            }
+        });
+        tokio::time::sleep(Duration::from_millis(20)).await;
+        assert!(!reading.is_finished(), "Record read before the budget had room");
+
+        // The call stays reserved until the caller drops it
+        drop(held);
+        let reservation = reading.await.unwrap().unwrap().unwrap();
+        assert_eq!(budget.in_flight(), 8);
+        drop(reservation);
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_record_truncated() {
+        let stream = record(b"call", &[2, 2]);
+        let mut buffer = BytesMut::new();
+        assert!(read_message(&mut &stream[..8], &mut buffer, 1024, None).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_read_record_idle_timeout() {
+        let idle = Some(Duration::from_millis(50));
+        let mut buffer = BytesMut::new();
+
+        // A silent peer is treated like one that hung up
+        let (_client, mut server) = tokio::io::duplex(64);
+        assert!(!read_message(&mut server, &mut buffer, 1024, idle).await.unwrap());
+
+        // Each fragment resets the timer, but stalling mid-record is an error
+        let (mut client, mut server) = tokio::io::duplex(64);
+        client.write_all(&record(b"call", &[2, 2])[..6]).await.unwrap();
+        assert!(read_message(&mut server, &mut buffer, 1024, idle).await.is_err());
+    }
 
-            // Clear buffer for next message
-            buffer.clear();
+    #[tokio::test]
+    async fn test_write_record_marked() {
+        let payload: Vec<u8> = (0..100).collect();
+
+        let mut out = Vec::new();
+        write_record_marked(&mut out, &payload, 32).await.unwrap();
+        assert_eq!(out.len(), 4 * 4 + payload.len());
+        assert_eq!(&out[0..4], &32u32.to_be_bytes());
+        assert_eq!(&out[36..40], &32u32.to_be_bytes());
+        assert_eq!(&out[72..76], &32u32.to_be_bytes());
+        assert_eq!(&out[108..112], &(4u32 | 0x80000000).to_be_bytes());
+
+        // Reads back as the same message
+        let mut buffer = BytesMut::new();
+        assert!(read_message(&mut out.as_slice(), &mut buffer, 1024, None).await.unwrap());
+        assert_eq!(&buffer[..], &payload[..]);
+
+        // Small replies stay a single fragment
+        let mut out = Vec::new();
+        write_record_marked(&mut out, &payload, 32 * 1024).await.unwrap();
+        assert_eq!(&out[0..4], &(100u32 | 0x80000000).to_be_bytes());
+
+        // An empty reply is one empty last fragment
+        let mut out = Vec::new();
+        write_record_marked(&mut out, &[], 32).await.unwrap();
+        assert_eq!(out, 0x80000000u32.to_be_bytes());
+    }
+
+    fn test_dispatcher(temp_dir: &TempDir) -> RpcDispatcher {
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(NfsState::default()),
+            ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]),
+        )
+    }
+
+    /// MOUNT NULL with AUTH_NONE, as one record
+    fn mount_null(xid: u32) -> Vec<u8> {
+        let mut call = Vec::new();
+        for word in [xid, 0, 2, crate::mount::MOUNT_PROGRAM, crate::mount::MOUNT_V3, 0] {
+            call.extend_from_slice(&word.to_be_bytes());
         }
+        call.extend_from_slice(&[0; 16]);
+        record(&call, &[call.len()])
     }
 
-    Ok(())
-}
+    #[tokio::test]
+    async fn test_configure_socket() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let _client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, _) = listener.accept().await.unwrap();
+
+        let sock = SockRef::from(&socket);
+        let default_send_buffer = sock.send_buffer_size().unwrap();
+        let options = SocketOptions {
+            nodelay: true,
+            keepalive: Some(Duration::from_secs(30)),
+            ..Default::default()
+        };
+        configure_socket(&socket, &options).unwrap();
+        assert!(socket.nodelay().unwrap());
+        assert!(sock.keepalive().unwrap());
+        assert_eq!(sock.keepalive_time().unwrap(), Duration::from_secs(30));
+        assert_eq!(sock.send_buffer_size().unwrap(), default_send_buffer);
+
+        configure_socket(&socket, &SocketOptions::default()).unwrap();
+        assert!(!socket.nodelay().unwrap());
+
+        // The kernel may round or double the sizes, never grant less
+        // than a small request
+        let options = SocketOptions {
+            send_buffer: Some(96 * 1024),
+            recv_buffer: Some(96 * 1024),
+            ..Default::default()
+        };
+        configure_socket(&socket, &options).unwrap();
+        assert!(sock.send_buffer_size().unwrap() >= 96 * 1024);
+        assert!(sock.recv_buffer_size().unwrap() >= 96 * 1024);
+    }
 
-/// Handle a complete RPC message
-fn handle_rpc_message(
-    data: &[u8],
-    registry: &Registry,
-    filesystem: &dyn Filesystem,
-) -> Result<BytesMut> {
-    // Debug: dump complete RPC message
-    debug!(
-        "Complete RPC message ({} bytes): {:02x?}",
-        data.len(),
-        &data[..data.len().min(100)]
-    );
-
-    // Deserialize RPC call header
-    let call = RpcMessage::deserialize_call(data)?;
-
-    debug!(
-        "RPC call: xid={}, prog={}, vers={}, proc={}",
-        call.xid, call.prog, call.vers, call.proc_
-    );
-
-    // Calculate where procedure arguments start (after RPC call header)
-    // RPC call header: xid(4) + mtype(4) + rpcvers(4) + prog(4) + vers(4) + proc(4) = 24 bytes
-    // Then: opaque_auth cred + opaque_auth verf (variable length)
-    // opaque_auth = flavor(4) + length(4) + body(length bytes, padded to 4-byte boundary)
-
-    let mut offset = 24; // After fixed RPC header fields
-
-    // Parse credential (opaque_auth)
-    if data.len() < offset + 8 {
-        return Err(anyhow!("RPC message too short for credential header"));
-    }
-    let cred_length = u32::from_be_bytes([
-        data[offset + 4],
-        data[offset + 5],
-        data[offset + 6],
-        data[offset + 7],
-    ]) as usize;
-    let cred_padded = (cred_length + 3) & !3; // Round up to multiple of 4
-    offset += 8 + cred_padded; // flavor(4) + length(4) + body(padded)
-
-    debug!("Credential length: {} bytes (padded: {}), offset now: {}", cred_length, cred_padded, offset);
-
-    // Parse verifier (opaque_auth)
-    if data.len() < offset + 8 {
-        return Err(anyhow!("RPC message too short for verifier header"));
-    }
-    let verf_length = u32::from_be_bytes([
-        data[offset + 4],
-        data[offset + 5],
-        data[offset + 6],
-        data[offset + 7],
-    ]) as usize;
-    let verf_padded = (verf_length + 3) & !3; // Round up to multiple of 4
-    offset += 8 + verf_padded; // flavor(4) + length(4) + body(padded)
-
-    debug!("Verifier length: {} bytes (padded: {}), offset now: {}", verf_length, verf_padded, offset);
-
-    // Now offset points to the procedure arguments
-    let args_offset = offset;
-    let args_data = if data.len() > args_offset {
-        &data[args_offset..]
-    } else {
-        &[]
-    };
+    #[tokio::test]
+    async fn test_calls_sharing_a_segment() {
+        let temp_dir = TempDir::new().unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, peer_addr) = listener.accept().await.unwrap();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn(handle_connection(
+            socket,
+            peer_addr,
+            test_dispatcher(&temp_dir),
+            1024,
+            1024,
+            None,
+            shutdown_rx,
+        ));
+
+        // Both calls go out in one write, so they arrive in one read
+        client.set_nodelay(true).unwrap();
+        let mut calls = mount_null(7);
+        calls.extend_from_slice(&mount_null(8));
+        client.write_all(&calls).await.unwrap();
+
+        let mut reply = BytesMut::new();
+        assert!(read_message(&mut client, &mut reply, 1024, None).await.unwrap());
+        assert_eq!(&reply[0..4], &7u32.to_be_bytes());
+        assert!(read_message(&mut client, &mut reply, 1024, None).await.unwrap());
+        assert_eq!(&reply[0..4], &8u32.to_be_bytes());
+    }
+
+    #[tokio::test]
+    async fn test_connection_closes_on_shutdown() {
+        let temp_dir = TempDir::new().unwrap();
+        let dispatcher = test_dispatcher(&temp_dir);
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, peer_addr) = listener.accept().await.unwrap();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let connection = tokio::spawn(handle_connection(
+            socket,
+            peer_addr,
+            dispatcher,
+            1024,
+            1024,
+            None,
+            shutdown_rx,
+        ));
+
+        // MOUNT NULL, AUTH_NONE, is answered as usual
+        client.write_all(&mount_null(7)).await.unwrap();
+        let mut reply = BytesMut::new();
+        assert!(read_message(&mut client, &mut reply, 1024, None).await.unwrap());
+        assert_eq!(&reply[0..4], &7u32.to_be_bytes());
+
+        // The idle connection is closed once shutdown is requested
+        shutdown_tx.send_replace(true);
+        connection.await.unwrap().unwrap();
+        assert!(!read_message(&mut client, &mut reply, 1024, None).await.unwrap());
+    }
 
-    // Route to appropriate handler based on program number
-    match call.prog {
-        100000 => {
-            // Portmapper protocol (program 100000)
-            debug!("Routing to PORTMAP protocol handler");
-            crate::portmap::handle_portmap_call(&call, args_data, registry)
+    /// NFS READ of `count` bytes at `offset`, AUTH_NONE, as one record
+    fn nfs_read(xid: u32, file: &[u8], offset: u64, count: u32) -> Vec<u8> {
+        use crate::protocol::v3::nfs::{fhandle3, READ3args};
+        use xdr_codec::Pack;
+
+        let mut call = Vec::new();
+        for word in [xid, 0, 2, crate::nfs::NFS_PROGRAM, crate::nfs::NFS_V3, 6] {
+            call.extend_from_slice(&word.to_be_bytes());
         }
-        100005 => {
-            // MOUNT protocol (program 100005)
-            debug!("Routing to MOUNT protocol handler");
-            crate::mount::handle_mount_call(&call, args_data, filesystem)
+        call.extend_from_slice(&[0; 16]);
+        READ3args {
+            file: fhandle3(file.to_vec()),
+            offset,
+            count,
         }
-        100003 => {
-            // NFS protocol (program 100003)
-            debug!("Routing to NFS protocol handler");
-            crate::nfs::dispatch(&call, args_data, filesystem)
+        .pack(&mut call)
+        .unwrap();
+        record(&call, &[call.len()])
+    }
+
+    #[tokio::test(flavor = "multi_thread", worker_threads = 4)]
+    async fn test_inflight_peak_stays_under_cap() {
+        use crate::config::{NfsConfig, MESSAGE_HEADER_ROOM};
+        use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+
+        const COUNT: u32 = 64 * 1024;
+        const LIMIT: u64 = 3 * (COUNT as u64 + MESSAGE_HEADER_ROOM as u64);
+        const CLIENTS: usize = 8;
+        const READS: u64 = 16;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("big"), vec![7u8; 1024 * 1024]).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let file = fs.lookup(&fs.root_handle(), "big").unwrap();
+        let state = NfsState::new(NfsConfig {
+            rtmax: COUNT,
+            max_inflight_bytes: Some(LIMIT),
+            ..NfsConfig::default()
+        });
+        let dispatcher = RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(state),
+            ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]),
+        );
+        let budget = dispatcher.inflight();
+
+        // Sample the bytes in flight while the clients run
+        let done = Arc::new(AtomicBool::new(false));
+        let peak = Arc::new(AtomicU64::new(0));
+        let sampler = std::thread::spawn({
+            let (budget, done, peak) = (budget.clone(), done.clone(), peak.clone());
+            move || {
+                while !done.load(Ordering::Acquire) {
+                    peak.fetch_max(budget.in_flight(), Ordering::AcqRel);
+                    std::hint::spin_loop();
+                }
+            }
+        });
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let addr = listener.local_addr().unwrap();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn({
+            let dispatcher = dispatcher.clone();
+            async move {
+                loop {
+                    let (socket, peer_addr) = listener.accept().await.unwrap();
+                    tokio::spawn(handle_connection(
+                        socket,
+                        peer_addr,
+                        dispatcher.clone(),
+                        1024 * 1024,
+                        1024 * 1024,
+                        None,
+                        shutdown_rx.clone(),
+                    ));
+                }
+            }
+        });
+
+        // Every client keeps all its READs outstanding, retrying those
+        // deferred with JUKEBOX, until each has been answered with data
+        let clients: Vec<_> = (0..CLIENTS as u32)
+            .map(|client| {
+                let file = file.clone();
+                tokio::spawn(async move {
+                    let mut stream = TcpStream::connect(addr).await.unwrap();
+                    let xid = |read: u64| client * 1000 + read as u32;
+                    for read in 0..READS {
+                        let call = nfs_read(xid(read), &file, read * COUNT as u64, COUNT);
+                        stream.write_all(&call).await.unwrap();
+                    }
+                    let (mut answered, mut deferred) = (0, 0);
+                    let mut reply = BytesMut::new();
+                    while answered < READS {
+                        assert!(read_message(&mut stream, &mut reply, 2 * 1024 * 1024, None)
+                            .await
+                            .unwrap());
+                        let read = (u32::from_be_bytes(reply[0..4].try_into().unwrap()) % 1000) as u64;
+                        match u32::from_be_bytes(reply[24..28].try_into().unwrap()) {
+                            0 => answered += 1,
+                            10008 => {
+                                deferred += 1;
+                                let call = nfs_read(xid(read), &file, read * COUNT as u64, COUNT);
+                                stream.write_all(&call).await.unwrap();
+                            }
+                            status => panic!("READ failed with {}", status),
+                        }
+                    }
+                    deferred
+                })
+            })
+            .collect();
+        let mut deferred = 0;
+        for client in clients {
+            deferred += client.await.unwrap();
         }
-        _ => {
-            warn!("Unknown program number: {}", call.prog);
-            Err(anyhow!("Unknown program number: {}", call.prog))
+        done.store(true, Ordering::Release);
+        sampler.join().unwrap();
+
+        let peak = peak.load(Ordering::Acquire);
+        assert!(peak <= LIMIT, "Peak of {} bytes in flight over the {} byte cap", peak, LIMIT);
+        assert!(deferred > 0, "Clients should have been backed off");
+        assert_eq!(budget.in_flight(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_idle_server_admits_largest_read() {
+        use crate::config::NfsConfig;
+
+        const COUNT: u32 = 64 * 1024;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("big"), vec![7u8; COUNT as usize]).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let file = fs.lookup(&fs.root_handle(), "big").unwrap();
+
+        // A budget the call's own bytes and rtmax of payload together exceed
+        let state = NfsState::new(NfsConfig {
+            rtmax: COUNT,
+            max_inflight_bytes: Some(COUNT as u64),
+            ..NfsConfig::default()
+        });
+        let dispatcher = RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(state),
+            ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]),
+        );
+
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut client = TcpStream::connect(listener.local_addr().unwrap())
+            .await
+            .unwrap();
+        let (socket, peer_addr) = listener.accept().await.unwrap();
+        let (_shutdown_tx, shutdown_rx) = watch::channel(false);
+        tokio::spawn(handle_connection(
+            socket,
+            peer_addr,
+            dispatcher,
+            1024 * 1024,
+            1024 * 1024,
+            None,
+            shutdown_rx,
+        ));
+
+        client.write_all(&nfs_read(9, &file, 0, COUNT)).await.unwrap();
+        let mut reply = BytesMut::new();
+        assert!(read_message(&mut client, &mut reply, 1024 * 1024, None).await.unwrap());
+        assert_eq!(&reply[24..28], &0u32.to_be_bytes(), "NFS3_OK rather than JUKEBOX");
+    }
+
+    #[tokio::test]
+    async fn test_serves_every_address() {
+        let temp_dir = TempDir::new().unwrap();
+
+        // Two ephemeral ports, picked by the kernel as the server binds
+        let server = Arc::new(RpcServer::new(
+            vec!["127.0.0.1:0".parse().unwrap(); 2],
+            test_dispatcher(&temp_dir),
+            &ServerConfig::default(),
+        ));
+        let mut bound = server.bound();
+        let (shutdown_tx, shutdown_rx) = watch::channel(false);
+        let running = tokio::spawn({
+            let server = server.clone();
+            async move { server.run(shutdown_rx).await }
+        });
+        bound.wait_for(|bound| *bound).await.unwrap();
+        let addrs = server.local_addrs();
+        assert_eq!(addrs.len(), 2);
+        assert_ne!(addrs[0], addrs[1]);
+
+        // Calls on either address are answered by the same dispatcher
+        for (xid, addr) in addrs.iter().enumerate() {
+            let mut client = TcpStream::connect(addr).await.unwrap();
+            client.write_all(&mount_null(xid as u32)).await.unwrap();
+            let mut reply = BytesMut::new();
+            assert!(read_message(&mut client, &mut reply, 1024, None).await.unwrap());
+            assert_eq!(&reply[0..4], &(xid as u32).to_be_bytes());
         }
+
+        shutdown_tx.send_replace(true);
+        running.await.unwrap().unwrap();
     }
 }
@@ -0,0 +1,68 @@
+// Graceful Shutdown
+//
+// The servers watch a shared flag that main sets on SIGINT/SIGTERM: they stop
+// taking new work, then give in-flight requests a grace period to finish.
+
+use std::time::Duration;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::warn;
+
+/// Resolve once shutdown has been requested on `shutdown`
+///
+/// Never resolves if the sending side is dropped without requesting it.
+pub async fn requested(shutdown: &mut watch::Receiver<bool>) {
+    if shutdown.wait_for(|&stop| stop).await.is_err() {
+        std::future::pending::<()>().await;
+    }
+}
+
+/// Wait up to `grace` for every task in `tasks` to finish
+///
+/// Tasks still running afterwards are aborted. Returns whether all of them
+/// finished in time.
+pub async fn drain(tasks: &mut JoinSet<()>, grace: Duration) -> bool {
+    let finished = tokio::time::timeout(grace, async {
+        while tasks.join_next().await.is_some() {}
+    })
+    .await
+    .is_ok();
+
+    if !finished {
+        warn!(
+            "Aborting {} request(s) still running after {:?}",
+            tasks.len(),
+            grace
+        );
+        tasks.abort_all();
+    }
+    finished
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_requested() {
+        let (tx, mut rx) = watch::channel(false);
+        let waiter = tokio::spawn(async move { requested(&mut rx).await });
+
+        tokio::task::yield_now().await;
+        assert!(!waiter.is_finished());
+
+        tx.send_replace(true);
+        waiter.await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_drain() {
+        let mut tasks = JoinSet::new();
+        tasks.spawn(tokio::time::sleep(Duration::from_millis(10)));
+        assert!(drain(&mut tasks, Duration::from_secs(5)).await);
+        assert!(tasks.is_empty());
+
+        tasks.spawn(tokio::time::sleep(Duration::from_secs(60)));
+        assert!(!drain(&mut tasks, Duration::from_millis(10)).await);
+    }
+}
@@ -0,0 +1,177 @@
+// XDR Tracing
+//
+// With `logging.trace_xdr` on, every call and its reply are logged under
+// this module's target (arcticwolf::rpc::trace) at debug level: the raw
+// bytes as a hex dump, the decoded call header and, for NFSv3, the decoded
+// procedure arguments. Meant for tracking down a client that encodes a
+// field differently than the server expects.
+//
+// Nothing is decoded, allocated or formatted unless debug is enabled for
+// this target.
+
+use std::fmt;
+use std::io::Cursor;
+use tracing::{debug, Level};
+use xdr_codec::Unpack;
+
+use crate::nfs::{NFS_PROGRAM, NFS_V3};
+use crate::protocol::v3::nfs::{
+    ACCESS3args, COMMIT3args, CREATE3args, FSINFO3args, FSSTAT3args, GETATTR3args, LINK3args,
+    LOOKUP3args, MKDIR3args, MKNOD3args, PATHCONF3args, READ3args, READDIR3args,
+    READDIRPLUS3args, READLINK3args, REMOVE3args, RENAME3args, RMDIR3args, SETATTR3args,
+    SYMLINK3args, WRITE3args,
+};
+use crate::protocol::v3::rpc::{rpc_call_msg, RpcMessage};
+
+/// Bytes of a message dumped; the rest is only counted
+const MAX_DUMP: usize = 4096;
+
+/// Characters of decoded arguments logged (WRITE data would be huge)
+const MAX_DECODED: usize = 4096;
+
+/// Log a call message received
+pub fn call(data: &[u8]) {
+    if !tracing::enabled!(Level::DEBUG) {
+        return;
+    }
+    debug!("XDR call ({} bytes):\n{}", data.len(), HexDump(data));
+    let call = match RpcMessage::deserialize_call(data) {
+        Ok(call) => call,
+        Err(e) => {
+            debug!("XDR call header undecodable: {}", e);
+            return;
+        }
+    };
+    debug!("XDR call header: {:?}", call);
+    if call.prog == NFS_PROGRAM && call.vers == NFS_V3 {
+        let args = data.get(args_offset(&call)..).unwrap_or_default();
+        if let Some(decoded) = nfs_args(call.proc_, args) {
+            debug!("XDR NFSv3 procedure {} arguments: {}", call.proc_, decoded);
+        }
+    }
+}
+
+/// Log a reply about to be sent
+pub fn reply(data: &[u8]) {
+    if !tracing::enabled!(Level::DEBUG) {
+        return;
+    }
+    debug!("XDR reply ({} bytes):\n{}", data.len(), HexDump(data));
+}
+
+/// Where the procedure arguments of `call` start: after the fixed header
+/// and both opaque_auth, each padded to a multiple of 4
+fn args_offset(call: &rpc_call_msg) -> usize {
+    let padded = |len: usize| (len + 3) & !3;
+    24 + 8 + padded(call.cred.body.len()) + 8 + padded(call.verf.body.len())
+}
+
+/// The arguments of NFSv3 `procedure`, decoded and formatted with Debug;
+/// None for NULL and unknown procedures
+fn nfs_args(procedure: u32, args: &[u8]) -> Option<String> {
+    fn decoded<'a, T: Unpack<Cursor<&'a [u8]>> + fmt::Debug>(args: &'a [u8]) -> String {
+        let mut decoded = match T::unpack(&mut Cursor::new(args)) {
+            Ok((args, _)) => format!("{:?}", args),
+            Err(e) => format!("undecodable ({})", e),
+        };
+        if decoded.len() > MAX_DECODED {
+            let mut end = MAX_DECODED;
+            while !decoded.is_char_boundary(end) {
+                end -= 1;
+            }
+            decoded.truncate(end);
+            decoded.push_str("...");
+        }
+        decoded
+    }
+
+    Some(match procedure {
+        1 => decoded::<GETATTR3args>(args),
+        2 => decoded::<SETATTR3args>(args),
+        3 => decoded::<LOOKUP3args>(args),
+        4 => decoded::<ACCESS3args>(args),
+        5 => decoded::<READLINK3args>(args),
+        6 => decoded::<READ3args>(args),
+        7 => decoded::<WRITE3args>(args),
+        8 => decoded::<CREATE3args>(args),
+        9 => decoded::<MKDIR3args>(args),
+        10 => decoded::<SYMLINK3args>(args),
+        11 => decoded::<MKNOD3args>(args),
+        12 => decoded::<REMOVE3args>(args),
+        13 => decoded::<RMDIR3args>(args),
+        14 => decoded::<RENAME3args>(args),
+        15 => decoded::<LINK3args>(args),
+        16 => decoded::<READDIR3args>(args),
+        17 => decoded::<READDIRPLUS3args>(args),
+        18 => decoded::<FSSTAT3args>(args),
+        19 => decoded::<FSINFO3args>(args),
+        20 => decoded::<PATHCONF3args>(args),
+        21 => decoded::<COMMIT3args>(args),
+        _ => return None,
+    })
+}
+
+/// Bytes shown 16 to a line, offset first and printable ASCII last,
+/// formatted only when logged
+struct HexDump<'a>(&'a [u8]);
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shown = &self.0[..self.0.len().min(MAX_DUMP)];
+        for (line, chunk) in shown.chunks(16).enumerate() {
+            write!(f, "  {:04x}: ", line * 16)?;
+            for i in 0..16 {
+                match chunk.get(i) {
+                    Some(byte) => write!(f, "{:02x} ", byte)?,
+                    None => f.write_str("   ")?,
+                }
+            }
+            f.write_str(" |")?;
+            for &byte in chunk {
+                let c = if byte.is_ascii_graphic() || byte == b' ' { byte as char } else { '.' };
+                write!(f, "{}", c)?;
+            }
+            f.write_str("|")?;
+            if (line + 1) * 16 < shown.len() {
+                f.write_str("\n")?;
+            }
+        }
+        if self.0.len() > shown.len() {
+            write!(f, "\n  ... {} more bytes", self.0.len() - shown.len())?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hex_dump() {
+        let dump = HexDump(b"\x00\x00\x00\x01NFS\x7f0123456789abcdef").to_string();
+        assert_eq!(
+            dump,
+            "  0000: 00 00 00 01 4e 46 53 7f 30 31 32 33 34 35 36 37  |....NFS.01234567|\n  \
+             0010: 38 39 61 62 63 64 65 66                          |89abcdef|"
+        );
+
+        let long = vec![0u8; MAX_DUMP + 10];
+        let dump = HexDump(&long).to_string();
+        assert_eq!(dump.lines().count(), MAX_DUMP / 16 + 1);
+        assert!(dump.ends_with("... 10 more bytes"));
+    }
+
+    #[test]
+    fn test_nfs_args_decoded() {
+        // GETATTR3args: a 4-byte handle
+        let args = [0, 0, 0, 4, 1, 2, 3, 4];
+        let decoded = nfs_args(1, &args).unwrap();
+        assert!(decoded.contains("GETATTR3args"), "{}", decoded);
+        assert!(decoded.contains("[1, 2, 3, 4]"), "{}", decoded);
+
+        assert!(nfs_args(6, &args[..2]).unwrap().starts_with("undecodable"));
+        assert_eq!(nfs_args(0, &[]), None);
+        assert_eq!(nfs_args(22, &args), None);
+    }
+}
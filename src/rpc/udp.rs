@@ -0,0 +1,293 @@
+// RPC UDP Server
+//
+// Implements Sun RPC over UDP: every datagram carries exactly one complete
+// RPC message (no record marking) and the reply goes back to its source.
+
+use anyhow::{anyhow, Result};
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::task::Poll;
+use std::time::Duration;
+use tokio::io::ReadBuf;
+use tokio::net::UdpSocket;
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tracing::{debug, error, info, warn};
+
+use crate::config::ServerConfig;
+use crate::nfs::inflight::InflightGuard;
+use crate::protocol::v3::rpc::{accept_stat, RpcMessage};
+
+use super::conn_limit::{ConnectionLimiter, ConnectionSlots};
+use super::dispatch::RpcDispatcher;
+use super::shutdown;
+
+/// Largest datagram payload that fits in an IPv4 UDP packet
+const MAX_DATAGRAM_SIZE: usize = 65507;
+
+/// RPC server answering calls sent as UDP datagrams
+pub struct UdpRpcServer {
+    /// Addresses received on, all served alike
+    addrs: Vec<SocketAddr>,
+    dispatcher: RpcDispatcher,
+    /// Calls being answered per source IP, capped like TCP connections
+    call_limiter: Arc<ConnectionLimiter>,
+    /// Server-wide cap on calls being answered
+    call_slots: ConnectionSlots,
+    /// How long shutdown waits for in-flight requests
+    shutdown_grace_period: Duration,
+    /// Set once every socket is bound
+    bound: watch::Sender<bool>,
+}
+
+impl UdpRpcServer {
+    pub fn new(addrs: Vec<SocketAddr>, dispatcher: RpcDispatcher, config: &ServerConfig) -> Self {
+        Self {
+            addrs,
+            dispatcher: dispatcher.for_udp(),
+            call_limiter: Arc::new(ConnectionLimiter::new(config.max_connections_per_ip)),
+            call_slots: ConnectionSlots::new(config.max_connections),
+            shutdown_grace_period: Duration::from_secs(config.shutdown_grace_period_secs),
+            bound: watch::channel(false).0,
+        }
+    }
+
+    /// Turns true once the server is receiving datagrams
+    pub fn bound(&self) -> watch::Receiver<bool> {
+        self.bound.subscribe()
+    }
+
+    /// Serve datagrams until shutdown is requested on `shutdown`, then give
+    /// calls being handled the grace period to send their replies
+    pub async fn run(&self, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+        let mut sockets = Vec::with_capacity(self.addrs.len());
+        for addr in &self.addrs {
+            let socket = UdpSocket::bind(addr)
+                .await
+                .map_err(|e| anyhow!("Failed to listen on {} (UDP): {}", addr, e))?;
+            info!("RPC server listening on {} (UDP)", addr);
+            sockets.push(Arc::new(socket));
+        }
+        self.bound.send_replace(true);
+
+        let budget = self.dispatcher.inflight();
+        let mut requests = JoinSet::new();
+        let mut datagram = vec![0u8; MAX_DATAGRAM_SIZE];
+        let mut next_socket = 0;
+        loop {
+            let (socket, len, peer_addr) = tokio::select! {
+                received = recv_any(&sockets, &mut datagram, &mut next_socket) => match received {
+                    Ok(received) => received,
+                    // E.g. ECONNREFUSED from an ICMP error about an earlier
+                    // reply; the socket itself is fine
+                    Err(e) => {
+                        warn!("Failed to receive datagram: {}", e);
+                        continue;
+                    }
+                },
+                // Reap finished requests so the set doesn't grow
+                Some(_) = requests.join_next() => continue,
+                _ = shutdown::requested(&mut shutdown) => break,
+            };
+            debug!("UDP datagram from {} ({} bytes)", peer_addr, len);
+
+            // Each call being answered counts as a connection would: past the
+            // per-IP limit the datagram is dropped and the client retransmits,
+            // past the server-wide one no more datagrams are received
+            let Some(client_slot) = self.call_limiter.try_acquire(peer_addr.ip()) else {
+                debug!("Dropping datagram from {}: per-IP limit reached", peer_addr);
+                continue;
+            };
+            let slot = tokio::select! {
+                slot = self.call_slots.acquire() => slot,
+                _ = shutdown::requested(&mut shutdown) => break,
+            };
+
+            // The call is reserved before it is copied out of the receive
+            // buffer, and held until its reply is sent; while the budget is
+            // exhausted no more datagrams are received
+            let reservation = tokio::select! {
+                reservation = budget.acquire(len as u64) => reservation,
+                _ = shutdown::requested(&mut shutdown) => break,
+            };
+
+            // Handlers may block on the filesystem; don't hold up other
+            // callers. The reply leaves from the address the call came to.
+            let data = datagram[..len].to_vec();
+            let socket = sockets[socket].clone();
+            let dispatcher = self.dispatcher.clone();
+            requests.spawn(async move {
+                if let Err(e) =
+                    handle_datagram(&socket, data, peer_addr, &dispatcher, reservation).await
+                {
+                    error!("UDP reply to {} failed: {}", peer_addr, e);
+                }
+                drop((slot, client_slot));
+            });
+        }
+
+        info!(
+            "UDP server stopped receiving; draining {} request(s)",
+            requests.len()
+        );
+        shutdown::drain(&mut requests, self.shutdown_grace_period).await;
+        Ok(())
+    }
+}
+
+/// Receive a datagram on whichever socket has one, returning the index of
+/// that socket, the datagram's length and its source
+///
+/// Sockets are polled starting after the one that last received, so a
+/// busy address can't starve the others.
+async fn recv_any(
+    sockets: &[Arc<UdpSocket>],
+    datagram: &mut [u8],
+    next: &mut usize,
+) -> std::io::Result<(usize, usize, SocketAddr)> {
+    std::future::poll_fn(|cx| {
+        for i in 0..sockets.len() {
+            let index = (*next + i) % sockets.len();
+            let mut buf = ReadBuf::new(&mut *datagram);
+            if let Poll::Ready(received) = sockets[index].poll_recv_from(cx, &mut buf) {
+                *next = index + 1;
+                let len = buf.filled().len();
+                return Poll::Ready(received.map(|peer_addr| (index, len, peer_addr)));
+            }
+        }
+        Poll::Pending
+    })
+    .await
+}
+
+/// Answer one datagram
+async fn handle_datagram(
+    socket: &UdpSocket,
+    data: Vec<u8>,
+    peer_addr: SocketAddr,
+    dispatcher: &RpcDispatcher,
+    reservation: InflightGuard,
+) -> Result<()> {
+    let (_, reservation, reply) = dispatcher.dispatch_blocking(data, reservation, peer_addr).await;
+    let Some(response) = reply else {
+        return Ok(());
+    };
+    reservation.settle(response.len() as u64).await;
+
+    // Replies can't be fragmented over UDP; rather than leave the client
+    // retransmitting a call it never gets an answer to, fail it (clients
+    // needing more use TCP)
+    if response.len() > MAX_DATAGRAM_SIZE {
+        warn!(
+            "{} byte reply to {} too large for a UDP datagram; sending SYSTEM_ERR",
+            response.len(),
+            peer_addr
+        );
+        let xid = u32::from_be_bytes(response[0..4].try_into()?);
+        let error = RpcMessage::create_error_reply(xid, accept_stat::SYSTEM_ERR)?;
+        socket.send_to(&error, peer_addr).await?;
+        return Ok(());
+    }
+
+    socket.send_to(&response, peer_addr).await?;
+    debug!("Sent UDP response ({} bytes)", response.len());
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::ExportConfig;
+    use crate::exports::{Export, ExportTable};
+    use crate::fsal::BackendConfig;
+    use crate::nfs::NfsState;
+    use crate::portmap::Registry;
+    use tempfile::TempDir;
+
+    #[tokio::test]
+    async fn test_null_call_over_udp() {
+        let temp_dir = TempDir::new().unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let dispatcher = RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(NfsState::default()),
+            ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]),
+        );
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        // MOUNT NULL, AUTH_NONE
+        let mut call = Vec::new();
+        for word in [42u32, 0, 2, crate::mount::MOUNT_PROGRAM, crate::mount::MOUNT_V3, 0] {
+            call.extend_from_slice(&word.to_be_bytes());
+        }
+        call.extend_from_slice(&[0; 16]);
+
+        let reservation = dispatcher.inflight().reserve(call.len() as u64);
+        handle_datagram(&server, call, client_addr, &dispatcher, reservation)
+            .await
+            .unwrap();
+
+        let mut reply = [0u8; 512];
+        let (len, from) = client.recv_from(&mut reply).await.unwrap();
+        assert_eq!(from, server.local_addr().unwrap());
+        assert_eq!(len, 24, "no record mark, just the accepted reply");
+        assert_eq!(&reply[0..4], &42u32.to_be_bytes());
+        assert_eq!(&reply[20..24], &0u32.to_be_bytes(), "SUCCESS");
+    }
+
+    #[tokio::test]
+    async fn test_read_over_udp_fits_datagram() {
+        use crate::protocol::v3::nfs::{fhandle3, READ3args};
+        use xdr_codec::Pack;
+
+        let temp_dir = TempDir::new().unwrap();
+        std::fs::write(temp_dir.path().join("big"), vec![7u8; 256 * 1024]).unwrap();
+        let fs = BackendConfig::local(temp_dir.path())
+            .create_filesystem()
+            .unwrap();
+        let file = fs.lookup(&fs.root_handle(), "big").unwrap();
+        let dispatcher = RpcDispatcher::new(
+            Registry::new(),
+            Arc::new(NfsState::default()),
+            ExportTable::new(vec![Export::new(ExportConfig::default(), Arc::from(fs))]),
+        )
+        .for_udp();
+
+        let server = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client = UdpSocket::bind("127.0.0.1:0").await.unwrap();
+        let client_addr = client.local_addr().unwrap();
+
+        // NFS READ of the default 1 MiB rtmax, AUTH_NONE
+        let mut call = Vec::new();
+        for word in [43u32, 0, 2, crate::nfs::NFS_PROGRAM, 3, 6] {
+            call.extend_from_slice(&word.to_be_bytes());
+        }
+        call.extend_from_slice(&[0; 16]);
+        READ3args {
+            file: fhandle3(file),
+            offset: 0,
+            count: 1024 * 1024,
+        }
+        .pack(&mut call)
+        .unwrap();
+
+        let reservation = dispatcher.inflight().reserve(call.len() as u64);
+        handle_datagram(&server, call, client_addr, &dispatcher, reservation)
+            .await
+            .unwrap();
+
+        let mut reply = vec![0u8; MAX_DATAGRAM_SIZE];
+        let (len, _) = client.recv_from(&mut reply).await.unwrap();
+        assert_eq!(&reply[0..4], &43u32.to_be_bytes());
+        assert_eq!(&reply[24..28], &0u32.to_be_bytes(), "NFS3_OK");
+        // Status, post_op_attr (4 + 84), count, eof, then the data
+        let count = u32::from_be_bytes(reply[116..120].try_into().unwrap());
+        assert_eq!(count, crate::nfs::MAX_UDP_TRANSFER);
+        assert!(len < MAX_DATAGRAM_SIZE);
+    }
+}
@@ -0,0 +1,450 @@
+// Server startup
+//
+// Builds the exports, RPC services and side endpoints from the
+// configuration, then serves until told to shut down. The binary is a thin
+// wrapper around `run`; embedders register their own backends first.
+
+use anyhow::{anyhow, Context, Result};
+use std::path::Path;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::config::{Config, ExportConfig};
+use crate::exports::{Export, ExportTable, SharedExports};
+use crate::fsal::BackendConfig;
+use crate::nfs::NfsState;
+use crate::protocol::v3::portmap::mapping;
+use crate::{fsal, health, metrics, nlm, nsm, portmap, rpc, telemetry};
+
+/// Register all RPC services in the portmapper registry
+///
+/// This makes services discoverable via PMAPPROC_GETPORT queries.
+fn register_services(registry: &portmap::Registry, port: u32) {
+    const IPPROTO_TCP: u32 = 6;
+    const IPPROTO_UDP: u32 = 17;
+
+    // Every program is served on both transports
+    for (prot, transport) in [(IPPROTO_TCP, "TCP"), (IPPROTO_UDP, "UDP")] {
+        // Register Portmapper itself (program 100000)
+        registry.set(&mapping {
+            prog: 100000,  // PORTMAP
+            vers: 2,       // Version 2
+            prot,
+            port,
+        });
+        tracing::info!("Registered Portmapper v2 ({}) on port {}", transport, port);
+
+        // Register MOUNT protocol (program 100005)
+        registry.set(&mapping {
+            prog: 100005,  // MOUNT
+            vers: 3,       // MOUNTv3
+            prot,
+            port,
+        });
+        tracing::info!("Registered MOUNT v3 ({}) on port {}", transport, port);
+
+        // Register NFS protocol (program 100003)
+        registry.set(&mapping {
+            prog: 100003,  // NFS
+            vers: 3,       // NFSv3
+            prot,
+            port,
+        });
+        tracing::info!("Registered NFS v3 ({}) on port {}", transport, port);
+
+        // Register NLM (program 100021), every version answered
+        for vers in nlm::NLM_V1..=nlm::NLM_V4 {
+            registry.set(&mapping {
+                prog: nlm::NLM_PROGRAM,
+                vers,
+                prot,
+                port,
+            });
+        }
+        tracing::info!(
+            "Registered NLM v{}-v{} ({}) on port {}",
+            nlm::NLM_V1,
+            nlm::NLM_V4,
+            transport,
+            port
+        );
+
+        // Register NSM (program 100024)
+        registry.set(&mapping {
+            prog: nsm::NSM_PROGRAM,
+            vers: nsm::NSM_V1,
+            prot,
+            port,
+        });
+        tracing::info!("Registered NSM v1 ({}) on port {}", transport, port);
+    }
+}
+
+/// Wait for SIGINT (Ctrl-C) or SIGTERM
+async fn shutdown_signal() -> Result<()> {
+    let mut sigterm =
+        tokio::signal::unix::signal(tokio::signal::unix::SignalKind::terminate())?;
+    tokio::select! {
+        result = tokio::signal::ctrl_c() => result?,
+        _ = sigterm.recv() => {}
+    }
+    Ok(())
+}
+
+/// Create the backend serving `export`, as configured in `config.fsal`
+fn open_export(
+    config: &Config,
+    export: &ExportConfig,
+    metrics: &metrics::Metrics,
+) -> Result<Arc<dyn fsal::Filesystem>> {
+    let mut fsal_config = BackendConfig::local(&export.path);
+    fsal_config.backend = config.fsal.backend.clone();
+    fsal_config.handle_len = config.nfs.file_handle_len;
+    fsal_config.readahead = config.fsal.readahead;
+    fsal_config.follow_symlinks = config.fsal.follow_symlinks;
+    fsal_config.drop_cache_idle = Duration::from_secs(config.fsal.drop_cache_idle_secs);
+    fsal_config.drop_cache_interval = Duration::from_secs(config.fsal.drop_cache_interval_secs);
+    fsal_config.atime = export.atime;
+    fsal_config.fsid = export.fsid;
+    fsal_config.subtree_check = export.subtree_check;
+    let mut backend = fsal_config
+        .create_filesystem()
+        .with_context(|| format!("Failed to initialize export {}", export.path))?;
+    if config.fsal.write_coalesce_bytes > 0 {
+        backend = Box::new(fsal::CoalescingFilesystem::new(
+            backend,
+            config.fsal.write_coalesce_bytes,
+        ));
+    }
+    if config.fsal.attr_cache_ttl_ms == 0 {
+        return Ok(Arc::from(backend));
+    }
+    Ok(Arc::new(fsal::CachingFilesystem::new(
+        backend,
+        Duration::from_millis(config.fsal.attr_cache_ttl_ms),
+        config.fsal.attr_cache_entries,
+        metrics.attr_cache_stats(),
+    )))
+}
+
+/// Re-read the configuration file on every SIGHUP and apply its exports and
+/// log level
+///
+/// Calls already running finish with the exports they started with. A file
+/// that fails to load, or an export that fails to open, leaves the running
+/// configuration untouched. Everything else, the bind address included,
+/// needs a restart to change.
+///
+/// Cached replies and attributes are dropped on every applied reload: they
+/// may reflect options (squashing, client lists, read-only) that no longer
+/// hold.
+async fn reload_on_sighup(
+    path: Option<String>,
+    mut running: Config,
+    exports: SharedExports,
+    nfs_state: Arc<NfsState>,
+    log_level: telemetry::LogLevel,
+    metrics: Arc<metrics::Metrics>,
+) -> Result<()> {
+    let mut sighup = tokio::signal::unix::signal(tokio::signal::unix::SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        let Some(path) = &path else {
+            tracing::warn!("SIGHUP received, but the server was started without a configuration file");
+            continue;
+        };
+        tracing::info!("SIGHUP received, reloading {}", path);
+        let config = match Config::load(path) {
+            Ok(config) => config,
+            Err(e) => {
+                tracing::error!("Keeping the running configuration: {:#}", e);
+                continue;
+            }
+        };
+
+        // New exports are opened with the FSAL options the server started with
+        let table = exports
+            .current()
+            .reconfigure(&config.exports, |export| open_export(&running, export, &metrics));
+        let table = match table {
+            Ok(table) => table,
+            Err(e) => {
+                tracing::error!("Keeping the running configuration: {:#}", e);
+                continue;
+            }
+        };
+        if let Err(e) = log_level.set(config.logging.effective_level()) {
+            tracing::error!("Keeping the running configuration: {:#}", e);
+            continue;
+        }
+        exports.reload(table, &nfs_state.reply_cache);
+        for export in &config.exports {
+            tracing::info!(
+                "Exporting {}{}",
+                export.path,
+                if export.read_only { " (read-only)" } else { "" }
+            );
+        }
+        tracing::info!("Logging at level {}", config.logging.effective_level());
+
+        if config.bind_addrs() != running.bind_addrs() {
+            tracing::warn!(
+                "Listen addresses changed from {} to {}; restart the server to apply them",
+                join_addrs(&running.bind_addrs()),
+                join_addrs(&config.bind_addrs())
+            );
+        }
+        running.exports = config.exports;
+        running.logging = config.logging;
+    }
+    Ok(())
+}
+
+/// Flush files with uncommitted (UNSTABLE) writes to stable storage
+///
+/// Bounded by `timeout`; a failure or timeout is reported loudly and turned
+/// into an error so the process exits non-zero.
+async fn flush_uncommitted_writes(
+    exports: ExportTable,
+    nfs_state: Arc<NfsState>,
+    timeout: Duration,
+) -> Result<()> {
+    tracing::info!(
+        "Flushing {} file(s) with uncommitted writes",
+        nfs_state.dirty_files.pending()
+    );
+
+    let flush = tokio::task::spawn_blocking(move || {
+        nfs_state.dirty_files.flush_all(&exports)
+    });
+    match tokio::time::timeout(timeout, flush).await {
+        Ok(Ok(Ok(flushed))) => {
+            tracing::info!("Flushed {} file(s)", flushed);
+            Ok(())
+        }
+        Ok(Ok(Err(e))) => {
+            tracing::error!("Shutdown flush failed, uncommitted data may be lost: {}", e);
+            Err(e)
+        }
+        Ok(Err(e)) => Err(anyhow!("Shutdown flush task failed: {}", e)),
+        Err(_) => {
+            tracing::error!(
+                "Shutdown flush timed out after {:?}, uncommitted data may be lost",
+                timeout
+            );
+            Err(anyhow!("Timed out flushing uncommitted writes"))
+        }
+    }
+}
+
+/// Socket addresses as a comma-separated list
+fn join_addrs(addrs: &[std::net::SocketAddr]) -> String {
+    addrs.iter().map(|addr| addr.to_string()).collect::<Vec<_>>().join(",")
+}
+
+/// Log what the server is about to serve, as configured, in one line
+fn log_startup_summary(config: &Config) {
+    let exports: Vec<String> = config
+        .exports
+        .iter()
+        .map(|export| format!("{}({})", export.path, export.options()))
+        .collect();
+    tracing::info!(
+        bind = %join_addrs(&config.bind_addrs()),
+        transports = "tcp,udp",
+        backend = %config.fsal.backend,
+        export_count = exports.len(),
+        exports = %exports.join(" "),
+        handle_format = fsal::HANDLE_FORMAT_VERSION,
+        handle_len = config.nfs.file_handle_len,
+        "Arctic Wolf NFS server starting"
+    );
+}
+
+/// Run the server until SIGINT or SIGTERM, configured from the file at
+/// `config_path`, or with the defaults without one
+///
+/// Backends registered with `fsal::register_backend` beforehand can be
+/// selected by `fsal.backend`.
+pub async fn run(config_path: Option<String>) -> Result<()> {
+    let config = match &config_path {
+        Some(path) => Config::load(path)?,
+        None => Config::default(),
+    };
+    let bind_addrs = config.bind_addrs();
+
+    // Initialize tracing (and OpenTelemetry export when configured)
+    let (tracer_provider, log_level) =
+        telemetry::init(&config.telemetry, config.logging.effective_level())?;
+    match &config_path {
+        Some(path) => tracing::info!("Loaded configuration from {}", path),
+        None => tracing::info!("No configuration file given, using defaults"),
+    }
+    tracing::info!("Logging at level {}", config.logging.effective_level());
+    if let Some(endpoint) = &config.telemetry.otlp_endpoint {
+        tracing::info!("Exporting traces to {}", endpoint);
+    }
+
+    // Initialize FSAL (File System Abstraction Layer)
+    // Every configured export gets its own backend rooted at its path
+    tracing::info!("FSAL backend: {}", config.fsal.backend);
+
+    if config.fsal.attr_cache_ttl_ms > 0 {
+        tracing::info!(
+            "Attribute cache: {} ms, {} entries per export",
+            config.fsal.attr_cache_ttl_ms, config.fsal.attr_cache_entries
+        );
+    }
+
+    if config.fsal.readahead > 0 {
+        tracing::info!("Readahead: {} bytes", config.fsal.readahead);
+    }
+
+    if config.fsal.follow_symlinks {
+        tracing::info!("Following symlinks within the exports");
+    }
+
+    if config.fsal.write_coalesce_bytes > 0 {
+        tracing::info!(
+            "Coalescing contiguous writes up to {} bytes per file",
+            config.fsal.write_coalesce_bytes
+        );
+    }
+
+    if config.fsal.drop_cache_idle_secs > 0 {
+        tracing::info!(
+            "Dropping page cache of files idle for {}s (checked every {}s)",
+            config.fsal.drop_cache_idle_secs, config.fsal.drop_cache_interval_secs
+        );
+    }
+
+    let metrics = Arc::new(metrics::Metrics::default());
+    let mut exports = Vec::with_capacity(config.exports.len());
+    for export in &config.exports {
+        let filesystem = open_export(&config, export, &metrics)?;
+
+        tracing::info!(
+            "Export path: {}{} (root handle: {} bytes)",
+            export.path,
+            if export.read_only { ", read-only" } else { "" },
+            filesystem.root_handle().len()
+        );
+        exports.push(Export::new(export.clone(), filesystem));
+    }
+    let exports = ExportTable::new(exports);
+    log_startup_summary(&config);
+
+    // Create portmapper registry
+    let registry = portmap::Registry::new();
+
+    // Register services in portmapper
+    // Note: Currently all services share the configured port, so GETPORT
+    // always answers with the port the server listens on (the first one,
+    // with several listen addresses)
+    // In production, these would be on different ports (111, 2049, 20048)
+    register_services(&registry, bind_addrs[0].port() as u32);
+
+    // Shared NFS state (limits, throttles)
+    let nfs_state = Arc::new(NfsState::new(config.nfs.clone()));
+
+    // NSM state, restored from its directory when configured so that hosts
+    // which held locks before a restart can be told to reclaim them
+    let (monitor, rebooted_peers) = match &config.nsm.state_dir {
+        Some(state_dir) => {
+            let grace = Duration::from_secs(config.nsm.grace_period_secs);
+            let (monitor, rebooted_peers) = nsm::StatusMonitor::open(Path::new(state_dir), grace)?;
+            tracing::info!("NSM state {} (kept in {})", monitor.state(), state_dir);
+            (monitor, rebooted_peers)
+        }
+        None => (nsm::StatusMonitor::new(), Vec::new()),
+    };
+
+    // Create and run RPC servers with the exports; TCP and UDP share the
+    // dispatcher, and with it the mount and lock tables
+    let dispatcher = rpc::dispatch::RpcDispatcher::new(registry, nfs_state.clone(), exports)
+        .with_status_monitor(monitor.clone())
+        .with_metrics(metrics.clone())
+        .with_xdr_trace(config.logging.trace_xdr);
+    let shared_exports = dispatcher.exports();
+    let server = rpc::server::RpcServer::new(bind_addrs.clone(), dispatcher.clone(), &config.server);
+    let udp_server = rpc::udp::UdpRpcServer::new(bind_addrs, dispatcher, &config.server);
+
+    // SIGHUP re-reads the configuration file. The handler is installed even
+    // without one, since SIGHUP would otherwise terminate the server.
+    let reload = reload_on_sighup(
+        config_path,
+        config.clone(),
+        shared_exports.clone(),
+        nfs_state.clone(),
+        log_level,
+        metrics.clone(),
+    );
+    tokio::spawn(async move {
+        if let Err(e) = reload.await {
+            tracing::error!("Configuration reload disabled: {}", e);
+        }
+    });
+
+    // Prometheus endpoint, on its own port
+    if let Some(listen) = config.metrics.listen.clone() {
+        tracing::info!("Serving metrics on http://{}/metrics", listen);
+        let connections = server.connection_slots();
+        tokio::spawn(async move {
+            if let Err(e) = metrics::serve(listen, metrics, connections).await {
+                tracing::error!("Metrics endpoint failed: {}", e);
+            }
+        });
+    }
+
+    // Clients that held locks before the restart reclaim them once notified
+    if !rebooted_peers.is_empty() {
+        tokio::spawn(nsm::reboot::notify_peers(monitor, rebooted_peers));
+    }
+
+    // On SIGINT/SIGTERM stop accepting connections, let in-flight requests
+    // finish, then make uncommitted writes durable before exiting
+    let (shutdown_tx, shutdown_rx) = tokio::sync::watch::channel(false);
+
+    // Readiness probe, on its own port: ready once both listeners are bound
+    // and the exports are readable, unready again as soon as shutdown begins
+    if let Some(listen) = config.health.listen.clone() {
+        tracing::info!("Serving health checks on http://{}/healthz", listen);
+        let readiness = health::Readiness::new(
+            vec![server.bound(), udp_server.bound()],
+            shared_exports.clone(),
+            shutdown_rx.clone(),
+        );
+        tokio::spawn(async move {
+            if let Err(e) = health::serve(listen, readiness).await {
+                tracing::error!("Health endpoint failed: {}", e);
+            }
+        });
+    }
+    let signal = async {
+        shutdown_signal().await?;
+        tracing::info!(
+            "Shutting down: no longer accepting connections, waiting up to {}s for in-flight requests",
+            config.server.shutdown_grace_period_secs
+        );
+        shutdown_tx.send_replace(true);
+        Ok::<_, anyhow::Error>(())
+    };
+    let result = async {
+        tokio::try_join!(
+            signal,
+            server.run(shutdown_rx.clone()),
+            udp_server.run(shutdown_rx)
+        )?;
+        flush_uncommitted_writes(
+            shared_exports.current(),
+            nfs_state,
+            Duration::from_secs(config.server.shutdown_flush_timeout_secs),
+        )
+        .await
+    }
+    .await;
+
+    // Flush exported spans before exiting
+    telemetry::shutdown(tracer_provider);
+
+    result
+}
@@ -0,0 +1,129 @@
+// Tracing and OpenTelemetry Export
+//
+// Sets up the tracing subscriber. When an OTLP endpoint is configured, the
+// per-request spans are additionally exported as OpenTelemetry traces so NFS
+// latency shows up in the tracing backend next to upstream calls.
+
+use anyhow::{Context, Result};
+use opentelemetry::trace::TracerProvider as _;
+use opentelemetry::KeyValue;
+use opentelemetry_otlp::WithExportConfig;
+use opentelemetry_sdk::trace::TracerProvider;
+use opentelemetry_sdk::{runtime, Resource};
+use tracing_subscriber::filter::LevelFilter;
+use tracing_subscriber::prelude::*;
+use tracing_subscriber::{reload, EnvFilter, Registry};
+
+use crate::config::TelemetryConfig;
+
+/// Install the global tracing subscriber, filtered by `level` (a level or
+/// comma-separated directives such as "info,arcticwolf::nfs=debug") with any
+/// RUST_LOG directives applied on top
+///
+/// Returns the OpenTelemetry tracer provider when OTLP export is enabled; pass
+/// it to `shutdown` before exiting so buffered spans are flushed. The
+/// returned LogLevel changes the filter later on.
+pub fn init(config: &TelemetryConfig, level: &str) -> Result<(Option<TracerProvider>, LogLevel)> {
+    let filter = log_filter(level, std::env::var("RUST_LOG").ok().as_deref())?;
+    let (filter, filter_handle) = reload::Layer::new(filter);
+
+    let provider = match &config.otlp_endpoint {
+        Some(endpoint) => {
+            let exporter = opentelemetry_otlp::SpanExporter::builder()
+                .with_tonic()
+                .with_endpoint(endpoint.clone())
+                .build()
+                .with_context(|| format!("Failed to create OTLP exporter for {}", endpoint))?;
+
+            Some(
+                TracerProvider::builder()
+                    .with_batch_exporter(exporter, runtime::Tokio)
+                    .with_resource(Resource::new(vec![KeyValue::new(
+                        "service.name",
+                        config.service_name.clone(),
+                    )]))
+                    .build(),
+            )
+        }
+        None => None,
+    };
+
+    let otel_layer = provider.as_ref().map(|provider| {
+        tracing_opentelemetry::layer().with_tracer(provider.tracer("arcticwolf"))
+    });
+
+    tracing_subscriber::registry()
+        .with(filter)
+        .with(tracing_subscriber::fmt::layer())
+        .with(otel_layer)
+        .init();
+
+    Ok((provider, LogLevel(filter_handle)))
+}
+
+/// Handle for changing the log filter of the installed subscriber
+#[derive(Clone)]
+pub struct LogLevel(reload::Handle<EnvFilter, Registry>);
+
+impl LogLevel {
+    /// Filter by `level` from now on, RUST_LOG still applied on top
+    pub fn set(&self, level: &str) -> Result<()> {
+        let filter = log_filter(level, std::env::var("RUST_LOG").ok().as_deref())?;
+        self.0
+            .reload(filter)
+            .context("Failed to replace the log filter")
+    }
+}
+
+/// Filter built from the configured directives, overridden per target by
+/// those in `rust_log`
+fn log_filter(level: &str, rust_log: Option<&str>) -> Result<EnvFilter> {
+    let mut filter = EnvFilter::builder()
+        .with_default_directive(LevelFilter::INFO.into())
+        .parse(level)
+        .with_context(|| format!("Invalid log level {:?}", level))?;
+    for directive in rust_log.unwrap_or("").split(',').map(str::trim) {
+        if directive.is_empty() {
+            continue;
+        }
+        let directive = directive
+            .parse()
+            .with_context(|| format!("Invalid RUST_LOG directive {:?}", directive))?;
+        filter = filter.add_directive(directive);
+    }
+    Ok(filter)
+}
+
+/// Flush and stop OpenTelemetry export
+pub fn shutdown(provider: Option<TracerProvider>) {
+    let Some(provider) = provider else {
+        return;
+    };
+    if let Err(e) = provider.shutdown() {
+        eprintln!("Failed to flush OpenTelemetry spans: {}", e);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_log_filter() {
+        let filter = log_filter("info", None).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::INFO));
+
+        // Per-module directives from the configuration
+        let filter = log_filter("warn,arcticwolf::nfs=debug", None).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::DEBUG));
+        assert!(filter.to_string().contains("arcticwolf::nfs=debug"));
+
+        // RUST_LOG overrides a module the configuration also names
+        let filter = log_filter("arcticwolf=debug", Some("arcticwolf=trace, xdr_codec=off")).unwrap();
+        assert_eq!(filter.max_level_hint(), Some(LevelFilter::TRACE));
+        assert!(!filter.to_string().contains("arcticwolf=debug"));
+
+        assert!(log_filter("arcticwolf=loud", None).is_err());
+        assert!(log_filter("info", Some("arcticwolf=loud")).is_err());
+    }
+}
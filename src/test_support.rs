@@ -0,0 +1,32 @@
+// Test Support
+//
+// Fixtures shared by the unit tests of the RPC programs.
+
+use crate::protocol::v3::rpc::{auth_flavor, msg_type, opaque_auth, rpc_call_msg};
+use xdr_codec::Pack;
+
+/// An AUTH_NONE call to `(prog, vers, procedure)`
+pub(crate) fn call(xid: u32, prog: u32, vers: u32, procedure: u32) -> rpc_call_msg {
+    let none = || opaque_auth {
+        flavor: auth_flavor::AUTH_NONE,
+        body: vec![],
+    };
+    rpc_call_msg {
+        xid,
+        mtype: msg_type::CALL,
+        rpcvers: 2,
+        prog,
+        vers,
+        proc_: procedure,
+        cred: none(),
+        verf: none(),
+    }
+}
+
+
+/// XDR encoding of procedure arguments, as they follow the call header
+pub(crate) fn packed(args: &impl Pack<Vec<u8>>) -> Vec<u8> {
+    let mut buf = Vec::new();
+    args.pack(&mut buf).unwrap();
+    buf
+}
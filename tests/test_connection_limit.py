@@ -0,0 +1,96 @@
+#!/usr/bin/env python3
+"""
+Test: Per-IP Connection Limit
+Purpose: Verify the server refuses connections beyond the per-IP cap
+
+This test validates:
+1. Up to max_connections_per_ip (default 32) connections are served
+2. Excess connections from the same IP are closed by the server
+3. Slots are released once connections close
+"""
+
+import socket
+import struct
+import sys
+import time
+
+
+MAX_CONNECTIONS_PER_IP = 32
+
+
+def null_call(sock, xid):
+    """Send an NFS NULL call on an open socket, return True if answered"""
+    message = struct.pack('>IIIIII', xid, 0, 2, 100003, 3, 0)
+    message += struct.pack('>IIII', 0, 0, 0, 0)  # AUTH_NONE cred + verf
+    try:
+        sock.sendall(struct.pack('>I', 0x80000000 | len(message)) + message)
+        header = sock.recv(4)
+        return len(header) == 4
+    except (ConnectionResetError, BrokenPipeError, socket.timeout):
+        return False
+
+
+def connect(host, port):
+    sock = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    sock.settimeout(2.0)
+    sock.connect((host, port))
+    return sock
+
+
+def test_connection_limit():
+    """Test per-IP connection limit"""
+
+    print("Test: Per-IP Connection Limit")
+    print("=" * 60)
+    print()
+
+    host = "localhost"
+    port = 4000
+
+    sockets = []
+    try:
+        print(f"Opening {MAX_CONNECTIONS_PER_IP} connections...")
+        for i in range(MAX_CONNECTIONS_PER_IP):
+            sock = connect(host, port)
+            sockets.append(sock)
+            if not null_call(sock, 1000 + i):
+                print(f"  ✗ Connection {i + 1} was not served")
+                sys.exit(1)
+        print(f"  ✓ All {MAX_CONNECTIONS_PER_IP} connections served")
+
+        print("Opening one more connection...")
+        extra = connect(host, port)
+        if null_call(extra, 2000):
+            print("  ✗ Connection beyond the cap was served")
+            sys.exit(1)
+        extra.close()
+        print("  ✓ Excess connection refused")
+
+        print("Closing one connection and retrying...")
+        sockets.pop().close()
+        time.sleep(0.2)
+        retry = connect(host, port)
+        sockets.append(retry)
+        if not null_call(retry, 3000):
+            print("  ✗ Slot was not released after close")
+            sys.exit(1)
+        print("  ✓ Slot released after close")
+
+        print()
+        print("✅ Connection limit test PASSED")
+
+    except ConnectionRefusedError:
+        print("  ✗ Connection refused - is server running?")
+        sys.exit(1)
+    except Exception as e:
+        print(f"  ✗ Error: {e}")
+        import traceback
+        traceback.print_exc()
+        sys.exit(1)
+    finally:
+        for sock in sockets:
+            sock.close()
+
+
+if __name__ == '__main__':
+    test_connection_limit()
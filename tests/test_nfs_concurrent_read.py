@@ -0,0 +1,182 @@
+#!/usr/bin/env python3
+"""
+Test: Responsiveness during a large READ
+Purpose: Check that one connection streaming a large file doesn't stall
+others
+
+This test validates:
+1. MOUNT and LOOKUP of a large file
+2. A background connection READs the whole file sequentially
+3. Meanwhile NULL calls on a second connection keep being answered quickly
+4. Reports NULL latency while the read runs (median and worst)
+
+Setup: create a large file in the export first, e.g.
+    dd if=/dev/urandom of=/tmp/nfs_exports/test_large_file.bin bs=1M count=512
+"""
+
+import socket
+import statistics
+import struct
+import sys
+import threading
+import time
+
+
+HOST = "localhost"
+PORT = 4000
+LARGE_FILE = "test_large_file.bin"
+READ_SIZE = 1024 * 1024
+
+# Worst NULL round trip tolerated while the read runs
+MAX_NULL_LATENCY = 0.5
+
+
+def pack_string(s):
+    """Pack a string as XDR string"""
+    data = s.encode('utf-8')
+    padding = (4 - (len(data) % 4)) % 4
+    return struct.pack('>I', len(data)) + data + b'\x00' * padding
+
+
+def pack_opaque(data):
+    """Pack variable-length opaque data"""
+    padding = (4 - (len(data) % 4)) % 4
+    return struct.pack('>I', len(data)) + data + b'\x00' * padding
+
+
+def unpack_opaque_flex(data, offset):
+    """Unpack variable-length opaque data (length + data)"""
+    length = struct.unpack('>I', data[offset:offset+4])[0]
+    return data[offset+4:offset+4+length]
+
+
+class Connection:
+    """One TCP connection making sequential RPC calls"""
+
+    def __init__(self):
+        self.sock = socket.create_connection((HOST, PORT), timeout=30.0)
+        self.xid = 600000
+
+    def call(self, prog, vers, proc, args_data=b''):
+        self.xid += 1
+        message = struct.pack('>IIIIII', self.xid, 0, 2, prog, vers, proc)
+        message += struct.pack('>IIII', 0, 0, 0, 0)  # AUTH_NONE cred + verf
+        message += args_data
+        self.sock.sendall(struct.pack('>I', 0x80000000 | len(message)) + message)
+
+        reply = b''
+        last = False
+        while not last:
+            header = self.recv_exact(4)
+            mark = struct.unpack('>I', header)[0]
+            last = bool(mark & 0x80000000)
+            reply += self.recv_exact(mark & 0x7FFFFFFF)
+
+        accept_stat = struct.unpack('>I', reply[20:24])[0]
+        if accept_stat != 0:
+            raise Exception(f"RPC error: accept_stat={accept_stat}")
+        return reply[24:]
+
+    def recv_exact(self, length):
+        data = b''
+        while len(data) < length:
+            chunk = self.sock.recv(length - len(data))
+            if not chunk:
+                raise Exception("Connection closed")
+            data += chunk
+        return data
+
+    def close(self):
+        self.sock.close()
+
+
+def read_whole_file(file_handle, result):
+    """Read the file sequentially, recording bytes and time taken"""
+    conn = Connection()
+    offset = 0
+    started = time.monotonic()
+    try:
+        while True:
+            args = pack_opaque(file_handle) + struct.pack('>QI', offset, READ_SIZE)
+            res = conn.call(100003, 3, 6, args)
+            status = struct.unpack('>I', res[0:4])[0]
+            if status != 0:
+                raise Exception(f"READ failed with status {status}")
+            # status + post_op_attr (4 + 84), then count and eof
+            count, eof = struct.unpack('>II', res[92:100])
+            offset += count
+            if eof or count == 0:
+                break
+    except Exception as e:
+        result['error'] = e
+    result['bytes'] = offset
+    result['seconds'] = time.monotonic() - started
+    conn.close()
+
+
+def test_concurrent_read():
+    """Measure NULL latency on one connection while another READs"""
+
+    print("Test: Responsiveness during a large READ")
+    print("=" * 60)
+    print()
+
+    conn = Connection()
+
+    # Step 1: MOUNT and LOOKUP
+    print(f"Step 1: MOUNT / and LOOKUP {LARGE_FILE}")
+    print("-" * 60)
+    res = conn.call(100005, 3, 1, pack_string("/"))
+    if struct.unpack('>I', res[0:4])[0] != 0:
+        print("  ✗ MOUNT failed")
+        sys.exit(1)
+    root_fhandle = unpack_opaque_flex(res, 4)
+
+    res = conn.call(100003, 3, 3, pack_opaque(root_fhandle) + pack_string(LARGE_FILE))
+    if struct.unpack('>I', res[0:4])[0] != 0:
+        print(f"  ✗ LOOKUP failed; create {LARGE_FILE} in the export first")
+        sys.exit(1)
+    file_handle = unpack_opaque_flex(res, 4)
+    print("  ✓ Got file handle")
+    print()
+
+    # Step 2: stream the file while timing NULL calls
+    print("Step 2: READ the file while pinging with NULL")
+    print("-" * 60)
+    result = {}
+    reader = threading.Thread(target=read_whole_file, args=(file_handle, result))
+    reader.start()
+
+    latencies = []
+    while reader.is_alive():
+        started = time.monotonic()
+        conn.call(100003, 3, 0)
+        latencies.append(time.monotonic() - started)
+        time.sleep(0.01)
+    reader.join()
+    conn.close()
+
+    if 'error' in result:
+        print(f"  ✗ Background READ failed: {result['error']}")
+        sys.exit(1)
+
+    mib = result['bytes'] / (1024 * 1024)
+    print(f"  Read {mib:.1f} MiB in {result['seconds']:.2f}s "
+          f"({mib / max(result['seconds'], 1e-9):.1f} MiB/s)")
+    if not latencies:
+        print("  ✗ The read finished before any NULL call; use a larger file")
+        sys.exit(1)
+    print(f"  NULL calls: {len(latencies)}, "
+          f"median {statistics.median(latencies) * 1000:.2f} ms, "
+          f"worst {max(latencies) * 1000:.2f} ms")
+    print()
+
+    if max(latencies) > MAX_NULL_LATENCY:
+        print(f"  ✗ NULL calls stalled behind the READ (> {MAX_NULL_LATENCY}s)")
+        sys.exit(1)
+
+    print("✓ Other connections stayed responsive during the READ")
+
+
+if __name__ == '__main__':
+    test_concurrent_read()
@@ -337,8 +337,8 @@ def test_nfs_mknod():
     print()
     print("Note:")
     print("  - FIFO (named pipe) creation works for regular users")
-    print("  - Device file creation (NF3CHR, NF3BLK) requires root privileges")
-    print("  - Socket creation via MKNOD is not fully supported")
+    print("  - Device file creation (NF3CHR, NF3BLK) requires a root caller;")
+    print("    others get NFS3ERR_PERM")
 
 
 if __name__ == '__main__':
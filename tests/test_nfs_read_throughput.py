@@ -0,0 +1,182 @@
+#!/usr/bin/env python3
+"""
+Test: Sequential READ throughput
+Purpose: Measure how fast one TCP connection streams a large file, to
+compare socket buffer settings over a high-latency link
+
+This test validates:
+1. MOUNT and LOOKUP of a large file
+2. READs the whole file on one connection, keeping WINDOW READs
+   outstanding as a client with several RPC slots does
+3. Reports bytes read, elapsed time and MiB/s
+
+Setup: create a large file in the export first, e.g.
+    dd if=/dev/urandom of=/tmp/nfs_exports/test_large_file.bin bs=1M count=512
+
+Measuring the effect of send_buffer_size / recv_buffer_size (needs root and
+the sch_netem kernel module):
+    1. Add latency to loopback, 25ms each way (50ms round trip):
+           tc qdisc add dev lo root netem delay 25ms
+    2. Start the server with send_buffer_size = 0 under [server] (kernel
+       autotuning) and run this script three times; note the median MiB/s
+    3. Restart it with e.g. send_buffer_size = 4194304 and run it three
+       times again. Linux caps the value at net.core.wmem_max, so raise that
+       first if it is lower
+    4. Remove the latency again:
+           tc qdisc del dev lo root
+Report the kernel version, net.ipv4.tcp_wmem, net.core.wmem_max and the
+delay along with the numbers; they decide where autotuning tops out.
+"""
+
+import socket
+import struct
+import sys
+import time
+
+
+HOST = "localhost"
+PORT = 4000
+LARGE_FILE = "test_large_file.bin"
+READ_SIZE = 1024 * 1024
+
+# READs outstanding at once
+WINDOW = 8
+
+
+def pack_string(s):
+    """Pack a string as XDR string"""
+    data = s.encode('utf-8')
+    padding = (4 - (len(data) % 4)) % 4
+    return struct.pack('>I', len(data)) + data + b'\x00' * padding
+
+
+def pack_opaque(data):
+    """Pack variable-length opaque data"""
+    padding = (4 - (len(data) % 4)) % 4
+    return struct.pack('>I', len(data)) + data + b'\x00' * padding
+
+
+def unpack_opaque_flex(data, offset):
+    """Unpack variable-length opaque data (length + data)"""
+    length = struct.unpack('>I', data[offset:offset+4])[0]
+    return data[offset+4:offset+4+length]
+
+
+class Connection:
+    """One TCP connection; calls may be sent ahead of their replies"""
+
+    def __init__(self):
+        self.sock = socket.create_connection((HOST, PORT), timeout=60.0)
+        self.xid = 800000
+
+    def send(self, prog, vers, proc, args_data=b''):
+        """Send a call and return its xid"""
+        self.xid += 1
+        message = struct.pack('>IIIIII', self.xid, 0, 2, prog, vers, proc)
+        message += struct.pack('>IIII', 0, 0, 0, 0)  # AUTH_NONE cred + verf
+        message += args_data
+        self.sock.sendall(struct.pack('>I', 0x80000000 | len(message)) + message)
+        return self.xid
+
+    def recv(self):
+        """Read the next reply, returning its xid and results"""
+        reply = bytearray()
+        last = False
+        while not last:
+            mark = struct.unpack('>I', self.recv_exact(4))[0]
+            last = bool(mark & 0x80000000)
+            reply += self.recv_exact(mark & 0x7FFFFFFF)
+
+        accept_stat = struct.unpack('>I', reply[20:24])[0]
+        if accept_stat != 0:
+            raise Exception(f"RPC error: accept_stat={accept_stat}")
+        return struct.unpack('>I', reply[0:4])[0], bytes(reply[24:])
+
+    def call(self, prog, vers, proc, args_data=b''):
+        self.send(prog, vers, proc, args_data)
+        return self.recv()[1]
+
+    def recv_exact(self, length):
+        data = bytearray()
+        while len(data) < length:
+            chunk = self.sock.recv(length - len(data))
+            if not chunk:
+                raise Exception("Connection closed")
+            data += chunk
+        return data
+
+    def close(self):
+        self.sock.close()
+
+
+def test_read_throughput():
+    """Stream the large file with WINDOW READs in flight"""
+
+    print("Test: Sequential READ throughput")
+    print("=" * 60)
+    print()
+
+    conn = Connection()
+
+    # Step 1: MOUNT and LOOKUP
+    print(f"Step 1: MOUNT / and LOOKUP {LARGE_FILE}")
+    print("-" * 60)
+    res = conn.call(100005, 3, 1, pack_string("/"))
+    if struct.unpack('>I', res[0:4])[0] != 0:
+        print("  ✗ MOUNT failed")
+        sys.exit(1)
+    root_fhandle = unpack_opaque_flex(res, 4)
+
+    res = conn.call(100003, 3, 3, pack_opaque(root_fhandle) + pack_string(LARGE_FILE))
+    if struct.unpack('>I', res[0:4])[0] != 0:
+        print(f"  ✗ LOOKUP failed; create {LARGE_FILE} in the export first")
+        sys.exit(1)
+    file_handle = unpack_opaque_flex(res, 4)
+
+    # GETATTR for the size, so READs can be issued ahead of the replies
+    res = conn.call(100003, 3, 1, pack_opaque(file_handle))
+    if struct.unpack('>I', res[0:4])[0] != 0:
+        print("  ✗ GETATTR failed")
+        sys.exit(1)
+    # status, then fattr3: type, mode, nlink, uid, gid, size
+    size = struct.unpack('>Q', res[24:32])[0]
+    print(f"  ✓ Got file handle, {size / (1024 * 1024):.1f} MiB")
+    print()
+
+    # Step 2: READ it all
+    print(f"Step 2: READ in {READ_SIZE // 1024} KiB calls, {WINDOW} outstanding")
+    print("-" * 60)
+    offsets = {}
+    next_offset = 0
+    received = 0
+    started = time.monotonic()
+    while next_offset < size or offsets:
+        while next_offset < size and len(offsets) < WINDOW:
+            args = pack_opaque(file_handle) + struct.pack('>QI', next_offset, READ_SIZE)
+            offsets[conn.send(100003, 3, 6, args)] = next_offset
+            next_offset += READ_SIZE
+
+        xid, res = conn.recv()
+        offset = offsets.pop(xid)
+        status = struct.unpack('>I', res[0:4])[0]
+        if status != 0:
+            print(f"  ✗ READ at {offset} failed with status {status}")
+            sys.exit(1)
+        # status + post_op_attr (4 + 84), then count and eof
+        count = struct.unpack('>I', res[92:96])[0]
+        received += count
+    seconds = time.monotonic() - started
+    conn.close()
+
+    if received != size:
+        print(f"  ✗ Read {received} bytes of {size}")
+        sys.exit(1)
+
+    mib = received / (1024 * 1024)
+    print(f"  Read {mib:.1f} MiB in {seconds:.2f}s ({mib / max(seconds, 1e-9):.1f} MiB/s)")
+    print()
+    print("✓ READ throughput measured")
+
+
+if __name__ == '__main__':
+    test_read_throughput()
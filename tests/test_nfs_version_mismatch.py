@@ -0,0 +1,128 @@
+#!/usr/bin/env python3
+"""
+Test: NFS Version Mismatch
+Purpose: Verify NFSv2/NFSv4 probes get PROG_MISMATCH pointing at v3
+
+This test validates:
+1. NFS NULL with vers=2 returns PROG_MISMATCH (low=3, high=3)
+2. NFS NULL with vers=4 returns PROG_MISMATCH (low=3, high=3)
+3. The connection stays open and a v3 retry succeeds on the same socket
+"""
+
+import socket
+import struct
+import sys
+
+
+PROG_MISMATCH = 2
+
+
+def build_null_call(xid, vers):
+    """Build a record-marked NFS NULL call for the given version"""
+    message = b''
+    message += struct.pack('>I', xid)      # XID
+    message += struct.pack('>I', 0)        # msg_type = CALL (0)
+    message += struct.pack('>I', 2)        # RPC version
+    message += struct.pack('>I', 100003)   # Program (NFS)
+    message += struct.pack('>I', vers)     # Version
+    message += struct.pack('>I', 0)        # Procedure (NULL)
+    # cred (AUTH_NONE)
+    message += struct.pack('>I', 0)        # flavor = AUTH_NONE
+    message += struct.pack('>I', 0)        # length = 0
+    # verf (AUTH_NONE)
+    message += struct.pack('>I', 0)        # flavor = AUTH_NONE
+    message += struct.pack('>I', 0)        # length = 0
+
+    record_header = struct.pack('>I', 0x80000000 | len(message))
+    return record_header + message
+
+
+def recv_reply(sock):
+    """Read one record-marked reply from the socket"""
+    reply_header_bytes = sock.recv(4)
+    if len(reply_header_bytes) != 4:
+        raise Exception("Failed to read response header")
+
+    reply_header = struct.unpack('>I', reply_header_bytes)[0]
+    reply_len = reply_header & 0x7FFFFFFF
+
+    reply_data = b''
+    while len(reply_data) < reply_len:
+        chunk = sock.recv(reply_len - len(reply_data))
+        if not chunk:
+            break
+        reply_data += chunk
+    return reply_data
+
+
+def test_nfs_version_mismatch():
+    """Test NFS version negotiation"""
+
+    print("Test: NFS Version Mismatch")
+    print("=" * 60)
+    print()
+
+    host = "localhost"
+    port = 4000
+
+    try:
+        sock = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+        sock.settimeout(5.0)
+        sock.connect((host, port))
+
+        for xid, vers in [(88801, 2), (88802, 4)]:
+            print(f"Sending NFS NULL with vers={vers}...")
+            sock.sendall(build_null_call(xid, vers))
+            reply_data = recv_reply(sock)
+
+            # xid + msg_type + reply_stat + verf (flavor, len) + accept_stat + low + high
+            if len(reply_data) < 32:
+                print(f"  ✗ Response too short: {len(reply_data)} bytes")
+                sys.exit(1)
+
+            reply_xid, _, reply_stat, _, _, accept_stat, low, high = struct.unpack(
+                '>IIIIIIII', reply_data[:32]
+            )
+            print(f"  accept_stat={accept_stat}, low={low}, high={high}")
+
+            if reply_xid != xid:
+                print(f"  ✗ XID mismatch: expected {xid}, got {reply_xid}")
+                sys.exit(1)
+            if reply_stat != 0 or accept_stat != PROG_MISMATCH:
+                print(f"  ✗ Expected PROG_MISMATCH, got reply_stat={reply_stat} accept_stat={accept_stat}")
+                sys.exit(1)
+            if (low, high) != (3, 3):
+                print(f"  ✗ Expected low=3 high=3, got low={low} high={high}")
+                sys.exit(1)
+            print("  ✓ PROG_MISMATCH (3-3)")
+
+        # Retry with v3 on the same connection
+        print("Retrying with vers=3 on the same connection...")
+        sock.sendall(build_null_call(88803, 3))
+        reply_data = recv_reply(sock)
+        sock.close()
+
+        accept_stat = struct.unpack('>I', reply_data[20:24])[0]
+        if accept_stat != 0:
+            print(f"  ✗ v3 retry failed: accept_stat={accept_stat}")
+            sys.exit(1)
+        print("  ✓ v3 retry succeeded")
+        print()
+
+        print("✅ NFS version mismatch test PASSED")
+
+    except socket.timeout:
+        print("  ✗ Connection timeout")
+        sys.exit(1)
+    except ConnectionRefusedError:
+        print("  ✗ Connection refused - is server running?")
+        sys.exit(1)
+    except Exception as e:
+        print(f"  ✗ Error: {e}")
+        import traceback
+        traceback.print_exc()
+        sys.exit(1)
+
+
+if __name__ == '__main__':
+    test_nfs_version_mismatch()
@@ -0,0 +1,119 @@
+#!/usr/bin/env python3
+"""
+Test: NLM and NSM NULL Procedures
+Purpose: Verify the lock manager and status monitor answer NULL probes
+
+This test validates:
+1. NLM NULL (program 100021, version 4, procedure 0)
+2. NSM NULL (program 100024, version 1, procedure 0)
+3. Both replies are well-formed MSG_ACCEPTED/SUCCESS void replies
+"""
+
+import socket
+import struct
+import sys
+
+
+PROGRAMS = [
+    # (xid, program, version, name)
+    (77701, 100021, 4, "NLM"),
+    (77702, 100024, 1, "NSM"),
+]
+
+
+def null_call(host, port, xid, prog, vers):
+    """Send a NULL call and return the raw reply"""
+    message = b''
+    message += struct.pack('>I', xid)      # XID
+    message += struct.pack('>I', 0)        # msg_type = CALL (0)
+    message += struct.pack('>I', 2)        # RPC version
+    message += struct.pack('>I', prog)     # Program
+    message += struct.pack('>I', vers)     # Version
+    message += struct.pack('>I', 0)        # Procedure (NULL)
+    # cred (AUTH_NONE)
+    message += struct.pack('>I', 0)        # flavor = AUTH_NONE
+    message += struct.pack('>I', 0)        # length = 0
+    # verf (AUTH_NONE)
+    message += struct.pack('>I', 0)        # flavor = AUTH_NONE
+    message += struct.pack('>I', 0)        # length = 0
+
+    record_header = struct.pack('>I', 0x80000000 | len(message))
+
+    sock = socket.socket(socket.AF_INET, socket.SOCK_STREAM)
+    sock.settimeout(5.0)
+    sock.connect((host, port))
+    sock.sendall(record_header + message)
+
+    reply_header_bytes = sock.recv(4)
+    if len(reply_header_bytes) != 4:
+        sock.close()
+        raise Exception("Failed to read response header")
+
+    reply_header = struct.unpack('>I', reply_header_bytes)[0]
+    reply_len = reply_header & 0x7FFFFFFF
+
+    reply_data = b''
+    while len(reply_data) < reply_len:
+        chunk = sock.recv(reply_len - len(reply_data))
+        if not chunk:
+            break
+        reply_data += chunk
+
+    sock.close()
+    return reply_data
+
+
+def test_nlm_nsm_null():
+    """Test NLM and NSM NULL procedures"""
+
+    print("Test: NLM and NSM NULL Procedures")
+    print("=" * 60)
+    print()
+
+    host = "localhost"
+    port = 4000
+
+    try:
+        for xid, prog, vers, name in PROGRAMS:
+            print(f"Sending {name} NULL (program {prog}, version {vers})...")
+            reply_data = null_call(host, port, xid, prog, vers)
+
+            # A void reply is exactly the 24-byte accepted reply header
+            if len(reply_data) != 24:
+                print(f"  ✗ Expected 24-byte void reply, got {len(reply_data)} bytes")
+                sys.exit(1)
+
+            reply_xid, msg_type, reply_stat, _, verf_len, accept_stat = struct.unpack(
+                '>IIIIII', reply_data
+            )
+
+            if reply_xid != xid:
+                print(f"  ✗ XID mismatch: expected {xid}, got {reply_xid}")
+                sys.exit(1)
+            if msg_type != 1 or reply_stat != 0 or verf_len != 0:
+                print(f"  ✗ Malformed reply: msg_type={msg_type} reply_stat={reply_stat}")
+                sys.exit(1)
+            if accept_stat != 0:
+                print(f"  ✗ RPC error: accept_stat={accept_stat}")
+                sys.exit(1)
+
+            print(f"  ✓ {name} NULL returned MSG_ACCEPTED/SUCCESS")
+
+        print()
+        print("✅ NLM/NSM NULL test PASSED")
+
+    except socket.timeout:
+        print("  ✗ Connection timeout")
+        sys.exit(1)
+    except ConnectionRefusedError:
+        print("  ✗ Connection refused - is server running?")
+        sys.exit(1)
+    except Exception as e:
+        print(f"  ✗ Error: {e}")
+        import traceback
+        traceback.print_exc()
+        sys.exit(1)
+
+
+if __name__ == '__main__':
+    test_nlm_nsm_null()
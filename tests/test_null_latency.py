@@ -0,0 +1,100 @@
+#!/usr/bin/env python3
+"""
+Test: NULL round-trip latency
+Purpose: Measure request latency for a ping-pong NULL workload on one TCP
+connection
+
+This test validates:
+1. NFS NULL calls are answered one after another on a single connection
+2. Reports median, p99 and worst round trip
+3. Fails if the median suggests replies are held back (Nagle's algorithm
+   interacting with delayed ACKs adds tens of milliseconds)
+
+Compare runs with `tcp_nodelay = true` and `tcp_nodelay = false` under
+[server] to see the effect of TCP_NODELAY.
+"""
+
+import socket
+import statistics
+import struct
+import sys
+import time
+
+
+HOST = "localhost"
+PORT = 4000
+CALLS = 2000
+
+# Median round trip above which replies are considered delayed
+MAX_MEDIAN_LATENCY = 0.005
+
+
+def null_call(xid):
+    """Record-marked NFS NULL call with AUTH_NONE"""
+    message = struct.pack('>IIIIII', xid, 0, 2, 100003, 3, 0)
+    message += struct.pack('>IIII', 0, 0, 0, 0)  # AUTH_NONE cred + verf
+    return struct.pack('>I', 0x80000000 | len(message)) + message
+
+
+def recv_exact(sock, length):
+    data = b''
+    while len(data) < length:
+        chunk = sock.recv(length - len(data))
+        if not chunk:
+            raise Exception("Connection closed")
+        data += chunk
+    return data
+
+
+def recv_reply(sock):
+    """Read one record-marked reply"""
+    reply = b''
+    last = False
+    while not last:
+        mark = struct.unpack('>I', recv_exact(sock, 4))[0]
+        last = bool(mark & 0x80000000)
+        reply += recv_exact(sock, mark & 0x7FFFFFFF)
+    return reply
+
+
+def test_null_latency():
+    """Time CALLS sequential NULL round trips"""
+
+    print("Test: NULL round-trip latency")
+    print("=" * 60)
+    print()
+
+    # The client side sets TCP_NODELAY too, so only the server's setting
+    # decides whether replies are delayed
+    sock = socket.create_connection((HOST, PORT), timeout=10.0)
+    sock.setsockopt(socket.IPPROTO_TCP, socket.TCP_NODELAY, 1)
+
+    latencies = []
+    for xid in range(700000, 700000 + CALLS):
+        started = time.monotonic()
+        sock.sendall(null_call(xid))
+        reply = recv_reply(sock)
+        latencies.append(time.monotonic() - started)
+        if struct.unpack('>I', reply[0:4])[0] != xid:
+            print(f"  ✗ Reply xid mismatch for call {xid}")
+            sys.exit(1)
+    sock.close()
+
+    latencies.sort()
+    median = statistics.median(latencies)
+    p99 = latencies[int(len(latencies) * 0.99) - 1]
+    print(f"  {CALLS} calls: median {median * 1000:.3f} ms, "
+          f"p99 {p99 * 1000:.3f} ms, worst {latencies[-1] * 1000:.3f} ms")
+    print(f"  {CALLS / sum(latencies):.0f} calls/s")
+    print()
+
+    if median > MAX_MEDIAN_LATENCY:
+        print(f"  ✗ Median round trip above {MAX_MEDIAN_LATENCY * 1000:.0f} ms; "
+              "is tcp_nodelay disabled?")
+        sys.exit(1)
+
+    print("✓ NULL calls answered without delay")
+
+
+if __name__ == '__main__':
+    test_null_latency()
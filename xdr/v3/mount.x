@@ -18,6 +18,9 @@ typedef opaque fhandle3<FHSIZE3>;
 /* Directory path - XDR string type */
 typedef string dirpath<MNTPATHLEN>;
 
+/* Host or group name */
+typedef string name<MNTNAMLEN>;
+
 /* ===== MOUNT Status Codes ===== */
 
 enum mountstat3 {
@@ -51,6 +54,31 @@ union mountres3 switch (mountstat3 fhs_status) {
         void;
 };
 
+/* DUMP (2) - List current mounts
+ * Arguments: void
+ * Results: optional mountbody (linked list)
+ */
+struct mountbody {
+    name ml_hostname;
+    dirpath ml_directory;
+    mountbody *ml_next;
+};
+
+/* EXPORT (5) - List exported directories
+ * Arguments: void
+ * Results: optional exportnode (linked list)
+ */
+struct groupnode {
+    name gr_name;
+    groupnode *gr_next;
+};
+
+struct exportnode {
+    dirpath ex_dir;
+    groupnode *ex_groups;
+    exportnode *ex_next;
+};
+
 /* UMNT (3) - Unmount a directory
  * Arguments: dirpath
  * Results: void
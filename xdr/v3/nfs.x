@@ -98,6 +98,14 @@ struct fattr3 {
     nfstime3 ctime;
 };
 
+/* Attributes a client compares around a change (weak cache consistency);
+ * sent as pre_op_attr, which is packed manually like post_op_attr */
+struct wcc_attr {
+    uint64 size;
+    nfstime3 mtime;
+    nfstime3 ctime;
+};
+
 /* ===== GETATTR Procedure (1) ===== */
 
 struct GETATTR3args {
@@ -172,6 +180,8 @@ union set_size3 switch (set_size3_how set_it) {
 };
 
 union set_atime switch (time_how set_it) {
+    case SET_TO_SERVER_TIME:
+        void;
     case SET_TO_CLIENT_TIME:
         nfstime3 atime;
     default:
@@ -179,6 +189,8 @@ union set_atime switch (time_how set_it) {
 };
 
 union set_mtime switch (time_how set_it) {
+    case SET_TO_SERVER_TIME:
+        void;
     case SET_TO_CLIENT_TIME:
         nfstime3 mtime;
     default:
@@ -517,6 +529,8 @@ union mknoddata3 switch (ftype3 type) {
         sattr3 sock_attributes;
     case NF3FIFO:
         sattr3 pipe_attributes;
+    default:
+        void;               /* other types: NFS3ERR_BADTYPE */
 };
 
 struct MKNOD3args {
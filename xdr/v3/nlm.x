@@ -0,0 +1,120 @@
+/* Network Lock Manager Protocol v4 (RFC 1813, Appendix II) */
+/* Program number: 100021 */
+
+/* ===== Constants ===== */
+
+const LM_MAXSTRLEN = 1024;   /* Maximum caller name length */
+const MAXNETOBJ_SZ = 1024;   /* Maximum netobj size */
+
+const NLM_PROGRAM = 100021;
+const NLM4_VERS = 4;
+
+/* ===== Common Types ===== */
+
+typedef unsigned hyper uint64;
+typedef int int32;
+
+/* Opaque object: cookies, file handles and lock owner handles */
+typedef opaque netobj<MAXNETOBJ_SZ>;
+
+/* ===== NLM Status Codes ===== */
+
+enum nlm4_stats {
+    NLM4_GRANTED             = 0,   /* Lock granted */
+    NLM4_DENIED              = 1,   /* Conflicting lock held */
+    NLM4_DENIED_NOLOCKS      = 2,   /* Out of lock resources */
+    NLM4_BLOCKED             = 3,   /* Blocking request queued */
+    NLM4_DENIED_GRACE_PERIOD = 4,   /* Server in reclaim grace period */
+    NLM4_DEADLCK             = 5,   /* Request would deadlock */
+    NLM4_ROFS                = 6,   /* Read-only filesystem */
+    NLM4_STALE_FH            = 7,   /* Unknown file handle */
+    NLM4_FBIG                = 8,   /* Offset or length too large */
+    NLM4_FAILED              = 9    /* Other failure */
+};
+
+/* ===== Lock Description ===== */
+
+/* A byte range lock; l_len = 0 extends to end of file */
+struct nlm4_lock {
+    string caller_name<LM_MAXSTRLEN>;   /* Client host name */
+    netobj fh;                          /* NFS file handle */
+    netobj oh;                          /* Lock owner handle */
+    int32 svid;                         /* Owner process id */
+    uint64 l_offset;
+    uint64 l_len;
+};
+
+/* Current holder of a conflicting lock */
+struct nlm4_holder {
+    bool exclusive;
+    int32 svid;
+    netobj oh;
+    uint64 l_offset;
+    uint64 l_len;
+};
+
+/* ===== NLM Procedures ===== */
+
+/* TEST (1) - Check whether a lock could be granted
+ * Arguments: nlm4_testargs
+ * Results: nlm4_testres (cookie, nlm4_stats, holder when DENIED)
+ *
+ * NOTE: nlm4_testres is a union whose failure arms carry the status code,
+ * which xdrgen's `default` variant cannot represent. It is packed manually.
+ */
+struct nlm4_testargs {
+    netobj cookie;
+    bool exclusive;
+    nlm4_lock alock;
+};
+
+/* LOCK (2) - Acquire a lock
+ * Arguments: nlm4_lockargs
+ * Results: nlm4_res
+ */
+struct nlm4_lockargs {
+    netobj cookie;
+    bool block;
+    bool exclusive;
+    nlm4_lock alock;
+    bool reclaim;                       /* Reclaiming after a server reboot */
+    int32 state;                        /* Client's NSM state */
+};
+
+/* CANCEL (3) - Cancel a blocked lock request
+ * Arguments: nlm4_cancargs
+ * Results: nlm4_res
+ */
+struct nlm4_cancargs {
+    netobj cookie;
+    bool block;
+    bool exclusive;
+    nlm4_lock alock;
+};
+
+/* UNLOCK (4) - Release a lock
+ * Arguments: nlm4_unlockargs
+ * Results: nlm4_res
+ */
+struct nlm4_unlockargs {
+    netobj cookie;
+    nlm4_lock alock;
+};
+
+/* GRANTED (5) - Callback: a blocked lock has been granted
+ * Arguments: nlm4_testargs
+ * Results: nlm4_res
+ */
+
+/* Result of LOCK, CANCEL, UNLOCK and GRANTED
+ * (RFC 1813 wraps the status in a one-field struct; the encoding is the same)
+ */
+struct nlm4_res {
+    netobj cookie;
+    nlm4_stats stat;
+};
+
+/* NULL (0) - Ping test
+ * Arguments: void
+ * Results: void
+ */
@@ -0,0 +1,86 @@
+/* Network Status Monitor Protocol v1 (X/Open XNFS, "statd") */
+/* Program number: 100024 */
+
+/* ===== Constants ===== */
+
+const SM_MAXSTRLEN = 1024;   /* Maximum host name length */
+const SM_PRIV_SIZE = 16;     /* Size of the caller's private data */
+
+const SM_PROG = 100024;
+const SM_VERS = 1;
+
+/* ===== Status Codes ===== */
+
+enum res {
+    STAT_SUCC = 0,           /* Host is (now) monitored */
+    STAT_FAIL = 1            /* Host cannot be monitored */
+};
+
+/* ===== Common Types ===== */
+
+/* Host to query or monitor */
+struct sm_name {
+    string mon_name<SM_MAXSTRLEN>;
+};
+
+/* RPC procedure to call back when a monitored host changes state */
+struct my_id {
+    string my_name<SM_MAXSTRLEN>;
+    int my_prog;
+    int my_vers;
+    int my_proc;
+};
+
+struct mon_id {
+    string mon_name<SM_MAXSTRLEN>;
+    my_id my_id;
+};
+
+/* ===== NSM Procedures ===== */
+
+/* STAT (1) - Query the local state number
+ * Arguments: sm_name
+ * Results: sm_stat_res
+ */
+struct sm_stat_res {
+    res res_stat;
+    int state;
+};
+
+/* MON (2) - Monitor a host
+ * Arguments: mon
+ * Results: sm_stat_res
+ *
+ * NOTE: the RFC names the private data `priv`, a Rust keyword; only the
+ * field name differs, the encoding is the same.
+ */
+struct mon {
+    mon_id mon_id;
+    opaque priv_data[SM_PRIV_SIZE];
+};
+
+/* UNMON (3) - Stop monitoring a host
+ * Arguments: mon_id
+ * Results: sm_stat
+ *
+ * UNMON_ALL (4) - Stop monitoring every host
+ * Arguments: my_id
+ * Results: sm_stat
+ */
+struct sm_stat {
+    int state;
+};
+
+/* NOTIFY (6) - A host rebooted and is now in `state`
+ * Arguments: stat_chge
+ * Results: void
+ */
+struct stat_chge {
+    string mon_name<SM_MAXSTRLEN>;
+    int state;
+};
+
+/* NULL (0) - Ping test
+ * Arguments: void
+ * Results: void
+ */
@@ -30,6 +30,7 @@ struct auth_sys_params {
     string machinename<255>;
     unsigned int uid;
     unsigned int gid;
+    unsigned int gids<16>;   /* Supplementary groups */
 };
 
 /* Version mismatch info */